@@ -1,20 +1,34 @@
-use crate::filesystem::{DirectoryCommon, File, FileCommon, Filesystem};
-use exhume_apfs::{APFS, ApfsVolumeSuperblock, DirEntry, FsTree, InodeVal, apfs_kind, is_dir_mode};
+use crate::filesystem::{
+    BlockRun, ByteRange, DirectoryCommon, FLAGS_KEY, File, FileCommon, Filesystem, FsFileReadSeek,
+    WalkCheckpoint, WalkOptions, finish_analyzers, namespaced_metadata, sort_children, unix_ftype,
+    visit_content, walk_breadth_first,
+};
+use crate::folder_impl::bsd_flag_names;
+use crate::timefmt::format_timestamp;
+use exhume_apfs::{APFS, ApfsVolumeSuperblock, DirEntry, FsTree, InodeVal, is_dir_mode};
+use serde::Serialize;
 use serde_json::{Value, json};
-use std::collections::{HashSet, VecDeque};
+use std::collections::HashMap;
 use std::error::Error;
 use std::io::{self, Read, Seek, SeekFrom};
 use log::warn;
 use std::path::Path;
 
 const MAX_READ_BYTES: u64 = 512 * 1024 * 1024;
-const PACKED_INODE_MASK: u64 = 0x00ff_ffff_ffff_ffff;
+/// Directory entry dates remembered by `list_dir` for `get_file`.
+const MAX_DATES_ADDED: usize = 65536;
+pub const PACKED_INODE_MASK: u64 = 0x00ff_ffff_ffff_ffff;
+/// Namespace of the inode fields in `File.metadata`.
+pub const METADATA_NAMESPACE: &str = "apfs";
 
 #[derive(Debug, Clone)]
 pub struct ApfsFileRecord {
     pub fs_index: u32,
     pub inode_id: u64,
     pub inode: InodeVal,
+    /// When the file was added to its directory (ns), from the directory entry it was
+    /// reached through.
+    pub date_added: Option<u64>,
 }
 
 #[derive(Debug, Clone)]
@@ -27,12 +41,136 @@ pub struct ApfsDirectoryEntry {
     pub date_added: u64,
 }
 
+/// Volume roles (`apfs_role`), the ones after `installer` being counted in steps of 64.
+const VOLUME_ROLES: [(u16, &str); 16] = [
+    (0x0000, "none"),
+    (0x0001, "system"),
+    (0x0002, "user"),
+    (0x0004, "recovery"),
+    (0x0008, "vm"),
+    (0x0010, "preboot"),
+    (0x0020, "installer"),
+    (0x0040, "data"),
+    (0x0080, "baseband"),
+    (0x00c0, "update"),
+    (0x0100, "xart"),
+    (0x0140, "hardware"),
+    (0x0180, "backup"),
+    (0x01c0, "sidecar"),
+    (0x0240, "enterprise"),
+    (0x02c0, "prelogin"),
+];
+
+/// Name of a volume role, as `diskutil` shows it in lower case.
+pub fn volume_role_name(role: u16) -> &'static str {
+    VOLUME_ROLES
+        .iter()
+        .find(|(value, _)| *value == role)
+        .map_or("unknown", |(_, name)| *name)
+}
+
+/// `apfs_fs_flags` bit set on volumes that are not encrypted.
+const FS_UNENCRYPTED: u64 = 0x1;
+/// Offset of `apfs_last_mod_time` in the volume superblock, followed by `apfs_fs_flags`;
+/// `ApfsVolumeSuperblock` keeps neither.
+const APSB_LAST_MOD_TIME: u64 = 0x100;
+
+/// `apfs_last_mod_time` (nanoseconds) and `apfs_fs_flags` of `volume`, read from its
+/// superblock.
+fn volume_state<T: Read + Seek>(
+    apfs: &mut APFS<T>,
+    volume: &ApfsVolumeSuperblock,
+) -> Result<(u64, u64), Box<dyn Error>> {
+    let position = volume
+        .found_at_block
+        .checked_mul(apfs.block_size_u64())
+        .and_then(|x| x.checked_add(APSB_LAST_MOD_TIME))
+        .ok_or("volume superblock offset overflow")?;
+    let mut buf = [0u8; 16];
+    apfs.body.seek(SeekFrom::Start(position))?;
+    apfs.body.read_exact(&mut buf)?;
+    Ok((
+        u64::from_le_bytes(buf[..8].try_into().unwrap()),
+        u64::from_le_bytes(buf[8..].try_into().unwrap()),
+    ))
+}
+
+/// A volume of the container, as shown to pick one before walking.
+#[derive(Debug, Clone, Serialize)]
+pub struct VolumeInfo {
+    pub fs_index: u32,
+    pub name: String,
+    /// Role name (`system`, `data`, ...), see `volume_role_name`.
+    pub role: &'static str,
+    pub uuid: String,
+    pub encrypted: bool,
+    /// Last modification of the volume (UNIX seconds), written when it was last mounted
+    /// read-write.
+    pub last_mounted: Option<u64>,
+    /// Whether its filesystem tree could be opened, so that it can be walked.
+    pub walkable: bool,
+    /// Path of its root in the records, see `VolumePrefixes`.
+    pub prefix: String,
+}
+
+/// Where the root of each volume goes in the paths of APFS records. Volumes are matched
+/// by role name or by `fs_index`; the others stay under `/volume_N`. Several volumes may
+/// share a prefix, like the System and Data volumes a live macOS merges at `/` through
+/// its firmlinks.
+#[derive(Debug, Clone, Default)]
+pub struct VolumePrefixes {
+    by_role: HashMap<String, String>,
+    by_index: HashMap<u32, String>,
+}
+
+impl VolumePrefixes {
+    /// Parse `ROLE=PREFIX` or `INDEX=PREFIX` mappings, e.g. `data=/` or `2=/Preboot`.
+    /// `macos` stands for the layout of a live macOS, `system=/` and `data=/`.
+    pub fn parse<'a>(specs: impl IntoIterator<Item = &'a str>) -> Result<Self, String> {
+        let mut prefixes = Self::default();
+        for spec in specs {
+            if spec.eq_ignore_ascii_case("macos") {
+                prefixes.by_role.insert("system".into(), "/".into());
+                prefixes.by_role.insert("data".into(), "/".into());
+                continue;
+            }
+            let (volume, prefix) = spec
+                .split_once('=')
+                .ok_or_else(|| format!("expected ROLE=PREFIX or INDEX=PREFIX, got '{}'", spec))?;
+            let prefix = format!("/{}", prefix.trim_matches('/'));
+            if let Ok(fs_index) = volume.parse::<u32>() {
+                prefixes.by_index.insert(fs_index, prefix);
+            } else if VOLUME_ROLES.iter().any(|(_, name)| name.eq_ignore_ascii_case(volume)) {
+                prefixes.by_role.insert(volume.to_ascii_lowercase(), prefix);
+            } else {
+                return Err(format!("unknown volume role '{}'", volume));
+            }
+        }
+        Ok(prefixes)
+    }
+
+    /// Path of the root of `volume`.
+    pub fn prefix(&self, volume: &ApfsVolumeSuperblock) -> String {
+        self.by_index
+            .get(&volume.fs_index)
+            .or_else(|| self.by_role.get(volume_role_name(volume.role)))
+            .cloned()
+            .unwrap_or_else(|| format!("/volume_{}", volume.fs_index))
+    }
+}
+
 pub struct ApfsFs<T: Read + Seek> {
     pub apfs: APFS<T>,
     pub volume: ApfsVolumeSuperblock,
     pub root_inode_id: u64,
     pub valid_volumes: Vec<(ApfsVolumeSuperblock, u64)>, // (volume, root_inode_id)
+    /// Paths of the volume roots in walks and path lookups.
+    pub prefixes: VolumePrefixes,
     cached_trees: std::collections::HashMap<u32, FsTree>,
+    /// `date_added` of the entries listed by `list_dir`, by (fs_index, inode_id).
+    dates_added: std::collections::HashMap<(u32, u64), u64>,
+    /// `volume_state` of the volumes, by fs_index.
+    volume_states: HashMap<u32, (u64, u64)>,
 }
 
 impl<T: Read + Seek> ApfsFs<T> {
@@ -74,6 +212,16 @@ impl<T: Read + Seek> ApfsFs<T> {
             return Err("Could not open any APFS volume with a valid filesystem tree".into());
         }
 
+        let mut volume_states = HashMap::new();
+        for vol in apfs.volumes.clone() {
+            match volume_state(&mut apfs, &vol) {
+                Ok(state) => {
+                    volume_states.insert(vol.fs_index, state);
+                }
+                Err(e) => warn!("APFS volume {}: superblock: {}", vol.fs_index, e),
+            }
+        }
+
         let selected = valid_volumes
             .iter()
             .find(|(v, _)| v.fs_index == 0)
@@ -85,7 +233,10 @@ impl<T: Read + Seek> ApfsFs<T> {
             volume: selected.0,
             root_inode_id: selected.1,
             valid_volumes,
+            prefixes: VolumePrefixes::default(),
             cached_trees: std::collections::HashMap::new(),
+            dates_added: std::collections::HashMap::new(),
+            volume_states,
         })
     }
 
@@ -101,6 +252,85 @@ impl<T: Read + Seek> ApfsFs<T> {
         Ok(())
     }
 
+    /// Every volume of the container, by `fs_index`.
+    pub fn list_volumes(&self) -> Vec<VolumeInfo> {
+        let mut volumes: Vec<VolumeInfo> = self
+            .apfs
+            .volumes
+            .iter()
+            .map(|vol| {
+                let state = self.volume_states.get(&vol.fs_index);
+                VolumeInfo {
+                    fs_index: vol.fs_index,
+                    name: vol.volume_name.clone(),
+                    role: volume_role_name(vol.role),
+                    uuid: format_uuid(&vol.vol_uuid),
+                    encrypted: state.is_some_and(|(_, flags)| flags & FS_UNENCRYPTED == 0),
+                    last_mounted: state
+                        .filter(|(time, _)| *time != 0)
+                        .map(|(time, _)| time / 1_000_000_000),
+                    walkable: self.valid_volumes.iter().any(|(v, _)| v.fs_index == vol.fs_index),
+                    prefix: self.prefixes.prefix(vol),
+                }
+            })
+            .collect();
+        volumes.sort_by_key(|v| v.fs_index);
+        volumes
+    }
+
+    /// Place the volume roots at `prefixes` instead of `/volume_N`.
+    pub fn with_prefixes(mut self, prefixes: VolumePrefixes) -> Self {
+        self.prefixes = prefixes;
+        self
+    }
+
+    /// The record at `components` below the root of volume `fs_index`.
+    fn resolve_in_volume(
+        &mut self,
+        fs_index: u32,
+        components: &[&str],
+    ) -> Result<ApfsFileRecord, Box<dyn Error>> {
+        let root_inode_id = self
+            .valid_volumes
+            .iter()
+            .find(|(v, _)| v.fs_index == fs_index)
+            .map(|(_, id)| *id)
+            .ok_or_else(|| format!("no valid volume with fs_index={}", fs_index))?;
+
+        self.ensure_fstree(fs_index)?;
+
+        let root_inode = {
+            let fst = self.cached_trees.get(&fs_index).unwrap();
+            fst.inode_by_id(&mut self.apfs, root_inode_id)?
+                .ok_or_else(|| format!("root inode {} not found", root_inode_id))?
+        };
+
+        let mut current = ApfsFileRecord { fs_index, inode_id: root_inode_id, inode: root_inode, date_added: None };
+
+        for component in components {
+            let entries = self.list_dir(&current)?;
+            let entry = entries
+                .into_iter()
+                .find(|e| e.name() == *component)
+                .ok_or_else(|| format!("path component not found: {:?}", component))?;
+
+            self.ensure_fstree(fs_index)?;
+            let inode = {
+                let fst = self.cached_trees.get(&fs_index).unwrap();
+                fst.inode_by_id(&mut self.apfs, entry.inode_id)?
+                    .ok_or_else(|| format!("inode {} not found", entry.inode_id))?
+            };
+            current = ApfsFileRecord {
+                fs_index,
+                inode_id: entry.inode_id,
+                inode,
+                date_added: Some(entry.date_added),
+            };
+        }
+
+        Ok(current)
+    }
+
     fn volume_by_index(&self, fs_index: u32) -> Option<ApfsVolumeSuperblock> {
         self.valid_volumes
             .iter()
@@ -137,6 +367,7 @@ impl FileCommon for ApfsFileRecord {
             "mode": self.inode.mode,
             "size": self.size(),
             "inode": self.inode,
+            "date_added": self.date_added.filter(|t| *t != 0).map(|t| t / 1_000_000_000),
         })
     }
 }
@@ -267,6 +498,7 @@ impl<T: Read + Seek> Filesystem for ApfsFs<T> {
                 fs_index,
                 inode_id: inode_query,
                 inode,
+                date_added: self.dates_added.get(&(fs_index, inode_query)).copied(),
             });
         }
         if let Some(inode_id) = fst.inode_id_by_private_id(&mut self.apfs, inode_query)?
@@ -276,6 +508,7 @@ impl<T: Read + Seek> Filesystem for ApfsFs<T> {
                 fs_index,
                 inode_id,
                 inode,
+                date_added: self.dates_added.get(&(fs_index, inode_id)).copied(),
             });
         }
         Err(format!(
@@ -340,6 +573,14 @@ impl<T: Read + Seek> Filesystem for ApfsFs<T> {
         self.ensure_fstree(inode.fs_index)?;
         let fst = self.cached_trees.get(&inode.fs_index).unwrap();
         let entries: Vec<DirEntry> = fst.dir_children(&mut self.apfs, inode.inode_id)?;
+        if self.dates_added.len() + entries.len() > MAX_DATES_ADDED {
+            self.dates_added.clear();
+        }
+        for e in &entries {
+            if let Some(inode_id) = e.inode_id {
+                self.dates_added.insert((inode.fs_index, inode_id), e.date_added);
+            }
+        }
         Ok(entries
             .into_iter()
             .filter_map(|e| {
@@ -364,11 +605,15 @@ impl<T: Read + Seek> Filesystem for ApfsFs<T> {
                 Some(name) => name.to_string_lossy().to_string(),
                 None => absolute_path.to_string(),
             },
-            ftype: apfs_kind(file.inode.mode).to_string(),
+            raw_name: None,
+            ftype: unix_ftype(file.inode.mode as u32).to_string(),
             size: file.size(),
+            // Compressed content is in the resource fork, without a data stream.
+            size_on_disk: file.inode.dstream.as_ref().map(|d| d.alloced_size),
             created: Some(file.inode.create_time / 1_000_000_000),
             modified: Some(file.inode.mod_time / 1_000_000_000),
             accessed: Some(file.inode.access_time / 1_000_000_000),
+            changed: Some(file.inode.change_time / 1_000_000_000),
             permissions: Some(apfs_mode_to_string(file.inode.mode)),
             owner: Some(format!("{}", file.inode.owner)),
             group: Some(format!("{}", file.inode.group)),
@@ -376,7 +621,7 @@ impl<T: Read + Seek> Filesystem for ApfsFs<T> {
                 "[{}] - {} {} {} {} {} {}",
                 file_id,
                 apfs_mode_to_string(file.inode.mode),
-                exhume_apfs::fmt_apfs_ns_utc(file.inode.mod_time),
+                format_timestamp(file.inode.mod_time / 1_000_000_000),
                 file.inode.owner,
                 file.inode.group,
                 file.size(),
@@ -385,7 +630,16 @@ impl<T: Read + Seek> Filesystem for ApfsFs<T> {
             sig_name: None,
             sig_mime: None,
             sig_exts: None,
-            metadata: file.to_json(),
+            detected_type: None,
+            ext_mismatch: None,
+            md5: None,
+            sha1: None,
+            sha256: None,
+            metadata: namespaced_metadata(
+                METADATA_NAMESPACE,
+                file.to_json(),
+                json!({ FLAGS_KEY: bsd_flag_names(file.inode.bsd_flags) }),
+            ),
         }
     }
 
@@ -393,60 +647,122 @@ impl<T: Read + Seek> Filesystem for ApfsFs<T> {
         self.root_inode_id
     }
 
-    fn get_file_by_path(&mut self, path: &str, _file_id: u64) -> Result<Self::FileType, Box<dyn Error>> {
-        let mut components: Vec<&str> = path.split('/').filter(|c| !c.is_empty()).collect();
-        if components.is_empty() {
-            return Err("empty path".into());
+    fn file_block_runs(
+        &mut self,
+        file: &Self::FileType,
+    ) -> Result<Option<Vec<BlockRun>>, Box<dyn Error>> {
+        self.ensure_fstree(file.fs_index)?;
+        let fst = self.cached_trees.get(&file.fs_index).unwrap();
+        let mut ext = fst
+            .file_extents(&mut self.apfs, file.inode_id)
+            .unwrap_or_default();
+        if ext.is_empty() && file.inode.private_id != 0 {
+            ext = fst
+                .file_extents(&mut self.apfs, file.inode.private_id)
+                .unwrap_or_default();
         }
+        let bs = self.apfs.block_size_u64();
+        Ok(Some(
+            ext.iter()
+                .filter(|e| e.phys_block_num != 0)
+                .map(|e| (e.phys_block_num, e.length_bytes.div_ceil(bs)))
+                .collect(),
+        ))
+    }
 
-        // First component is "volume_N" → extract fs_index
-        let vol_component = components.remove(0);
-        let fs_index: u32 = if let Some(n) = vol_component.strip_prefix("volume_") {
-            n.parse().map_err(|_| format!("invalid volume component: {}", vol_component))?
-        } else {
-            return Err(format!("expected volume_N prefix, got: {}", vol_component).into());
-        };
+    fn file_holes(
+        &mut self,
+        file: &Self::FileType,
+    ) -> Result<Option<Vec<ByteRange>>, Box<dyn Error>> {
+        // Compressed content lives in the resource fork, without a data stream.
+        if file.inode.dstream.is_none() {
+            return Ok(None);
+        }
+        self.ensure_fstree(file.fs_index)?;
+        let fst = self.cached_trees.get(&file.fs_index).unwrap();
+        let mut ext = fst.file_extents(&mut self.apfs, file.inode_id)?;
+        if ext.is_empty() && file.inode.private_id != 0 {
+            ext = fst.file_extents(&mut self.apfs, file.inode.private_id)?;
+        }
+        ext.sort_by_key(|e| e.logical_addr);
+        let size = file.size();
+        let mut holes = Vec::new();
+        let mut covered = 0;
+        for e in &ext {
+            if e.logical_addr > covered {
+                holes.push((covered, e.logical_addr - covered));
+            }
+            if e.phys_block_num == 0 {
+                holes.push((e.logical_addr, e.length_bytes));
+            }
+            covered = covered.max(e.logical_addr + e.length_bytes);
+        }
+        if covered < size {
+            holes.push((covered, size - covered));
+        }
+        Ok(Some(
+            holes
+                .into_iter()
+                .filter(|&(offset, _)| offset < size)
+                .map(|(offset, length)| (offset, length.min(size - offset)))
+                .collect(),
+        ))
+    }
 
-        let root_inode_id = self
+    /// Paths start with the prefix of a volume, or with `volume_N` whatever the prefixes.
+    /// Volumes sharing a prefix are tried in turn, longest prefixes first.
+    fn get_file_by_path(&mut self, path: &str, _file_id: u64) -> Result<Self::FileType, Box<dyn Error>> {
+        let components: Vec<&str> = path.split('/').filter(|c| !c.is_empty()).collect();
+        let mut candidates: Vec<(usize, u32)> = self
             .valid_volumes
             .iter()
-            .find(|(v, _)| v.fs_index == fs_index)
-            .map(|(_, id)| *id)
-            .ok_or_else(|| format!("no valid volume with fs_index={}", fs_index))?;
-
-        self.ensure_fstree(fs_index)?;
-
-        let root_inode = {
-            let fst = self.cached_trees.get(&fs_index).unwrap();
-            fst.inode_by_id(&mut self.apfs, root_inode_id)?
-                .ok_or_else(|| format!("root inode {} not found", root_inode_id))?
-        };
-
-        let mut current = ApfsFileRecord { fs_index, inode_id: root_inode_id, inode: root_inode };
-
-        for component in components {
-            let entries = self.list_dir(&current)?;
-            let entry = entries
-                .into_iter()
-                .find(|e| e.name() == component)
-                .ok_or_else(|| format!("path component not found: {:?}", component))?;
-
-            self.ensure_fstree(fs_index)?;
-            let inode = {
-                let fst = self.cached_trees.get(&fs_index).unwrap();
-                fst.inode_by_id(&mut self.apfs, entry.inode_id)?
-                    .ok_or_else(|| format!("inode {} not found", entry.inode_id))?
-            };
-            current = ApfsFileRecord { fs_index, inode_id: entry.inode_id, inode };
+            .filter_map(|(vol, _)| {
+                let prefix = self.prefixes.prefix(vol);
+                let prefix: Vec<&str> = prefix.split('/').filter(|c| !c.is_empty()).collect();
+                components
+                    .starts_with(&prefix)
+                    .then_some((prefix.len(), vol.fs_index))
+            })
+            .collect();
+        if let Some(n) = components.first().and_then(|c| c.strip_prefix("volume_"))
+            && let Ok(fs_index) = n.parse()
+        {
+            candidates.push((1, fs_index));
         }
-
-        Ok(current)
+        candidates.sort_by_key(|&(depth, fs_index)| (std::cmp::Reverse(depth), fs_index));
+        candidates.dedup();
+
+        let mut error: Box<dyn Error> = format!("no volume is mapped at the start of {}", path).into();
+        for (depth, fs_index) in candidates {
+            match self.resolve_in_volume(fs_index, &components[depth..]) {
+                Ok(record) => return Ok(record),
+                Err(e) => error = e,
+            }
+        }
+        Err(error)
     }
 
     fn walk_fs(
         &mut self,
         callback: &mut dyn FnMut(crate::filesystem::WalkEvent),
     ) -> Result<(), Box<dyn Error>> {
+        self.walk_fs_with(WalkOptions::default(), callback)
+    }
+
+    /// APFS walks scan each volume B-Tree in one pass, which cannot be resumed midway.
+    fn walk_fs_with(
+        &mut self,
+        options: WalkOptions,
+        callback: &mut dyn FnMut(crate::filesystem::WalkEvent),
+    ) -> Result<(), Box<dyn Error>> {
+        if options.resume.is_some() || options.checkpoint_every > 0 {
+            return Err("checkpointing is not supported for APFS walks".into());
+        }
+        let mut visitor = options.visitor;
+        let mut analyzers = options.analyzers;
+        let exclude = options.exclude;
+        let sorted = options.sorted;
+        let all_names = options.all_names;
         let vols = self.valid_volumes.clone();
 
         for (vol, root_inode_id) in vols {
@@ -473,50 +789,55 @@ impl<T: Read + Seek> Filesystem for ApfsFs<T> {
                 "Building tree for volume {}...",
                 vol.fs_index
             )));
-            let mut visited = HashSet::<u64>::new();
-            let mut queue = VecDeque::<(u64, String)>::new();
-            let vol_prefix = format!("/volume_{}", vol.fs_index);
-            queue.push_back((root_inode_id, vol_prefix.clone()));
-
-            while let Some((inode_id, path)) = queue.pop_front() {
-                if !visited.insert(inode_id) {
-                    continue;
+            let dates_added: std::collections::HashMap<u64, u64> = drecs
+                .values()
+                .flatten()
+                .filter_map(|de| Some((de.inode_id?, de.date_added)))
+                .collect();
+            let mut state = WalkCheckpoint::default();
+            state.queue.push_back((root_inode_id, self.prefixes.prefix(&vol)));
+            walk_breadth_first(&mut state, "/", exclude, 0, None, 0, &mut |inode_id, path, first| {
+                if !first && !all_names {
+                    return None;
                 }
-
-                let inode = match inodes.get(&inode_id) {
-                    Some(v) => v.clone(),
-                    None => continue,
-                };
-
+                let inode = inodes.get(&inode_id)?.clone();
                 let rec = ApfsFileRecord {
                     fs_index: vol.fs_index,
                     inode_id,
                     inode,
+                    date_added: dates_added.get(&inode_id).copied(),
                 };
                 let packed_id = pack_identifier(vol.fs_index, inode_id);
-                callback(crate::filesystem::WalkEvent::File(
-                    self.record_to_file(&rec, packed_id, &path),
-                ));
-
-                if rec.is_dir()
-                    && let Some(children) = drecs.get(&inode_id)
-                {
-                    for de in children {
-                        let Some(child_inode) = de.inode_id else {
-                            continue;
-                        };
-                        let child_path = if path == vol_prefix {
-                            format!("{}/{}", vol_prefix, de.name)
-                        } else {
-                            format!("{}/{}", path, de.name)
-                        };
-                        queue.push_back((child_inode, child_path));
-                    }
+                let mut file_obj = self.record_to_file(&rec, packed_id, path);
+                let is_dir = rec.is_dir();
+                if !first && is_dir {
+                    return None;
                 }
-            }
+                if !is_dir && (visitor.is_some() || !analyzers.is_empty()) {
+                    let mut reader = FsFileReadSeek::new(self, rec);
+                    visit_content(
+                        visitor.as_mut(),
+                        &mut analyzers,
+                        &mut file_obj,
+                        &mut reader,
+                    );
+                }
+                callback(crate::filesystem::WalkEvent::File(file_obj));
+
+                let children = drecs.get(&inode_id).filter(|_| is_dir);
+                let mut children: Vec<_> = children
+                    .into_iter()
+                    .flatten()
+                    .filter_map(|de| Some((de.inode_id?, de.name.clone())))
+                    .collect();
+                if sorted {
+                    sort_children(&mut children);
+                }
+                Some(children)
+            });
         }
 
-        Ok(())
+        finish_analyzers(&mut analyzers)
     }
 }
 
@@ -639,7 +960,15 @@ fn apfs_mode_to_string(mode: u16) -> String {
     out
 }
 
-fn pack_identifier(fs_index: u32, inode_id: u64) -> u64 {
+/// A UUID in its usual hyphenated form.
+fn format_uuid(b: &[u8; 16]) -> String {
+    let hex = hex::encode(b);
+    format!("{}-{}-{}-{}-{}", &hex[..8], &hex[8..12], &hex[12..16], &hex[16..20], &hex[20..])
+}
+
+/// Pack a volume index and an inode number into one record identifier (volume in the
+/// top byte). Volume 0 packs to the bare inode number, which resolves on the selected volume.
+pub fn pack_identifier(fs_index: u32, inode_id: u64) -> u64 {
     ((fs_index as u64) << 56) | (inode_id & PACKED_INODE_MASK)
 }
 
@@ -0,0 +1,173 @@
+//! Evidence access audit: once installed, every content read performed through the
+//! filesystem dispatch and the block device is appended to a log as one JSON object per
+//! line (operation, record, offset, length, timestamp and the SHA-256 of the bytes
+//! returned, or the error). Lines are buffered and synced to disk every
+//! `SYNC_INTERVAL` reads, at export checkpoints and when the `AuditLog` guard is dropped.
+use log::error;
+use serde::Serialize;
+use sha2::{Digest, Sha256};
+use std::error::Error;
+use std::fs::{File, OpenOptions};
+use std::io::{self, BufWriter, Write};
+use std::sync::{Mutex, OnceLock};
+
+/// Reads appended between two syncs of the log.
+const SYNC_INTERVAL: usize = 4096;
+
+static AUDIT_LOG: OnceLock<Mutex<Log>> = OnceLock::new();
+
+struct Log {
+    writer: BufWriter<File>,
+    /// Lines appended since the last sync.
+    unsynced: usize,
+}
+
+impl Log {
+    fn sync(&mut self) -> io::Result<()> {
+        self.writer.flush()?;
+        self.writer.get_ref().sync_data()?;
+        self.unsynced = 0;
+        Ok(())
+    }
+}
+
+/// Syncs the audit log when dropped, at the end of the run.
+#[must_use = "the audit log is synced when the guard is dropped"]
+pub struct AuditLog(());
+
+impl Drop for AuditLog {
+    fn drop(&mut self) {
+        if let Err(e) = sync() {
+            error!("Could not sync the audit log: {}", e);
+        }
+    }
+}
+
+#[derive(Debug, Clone, Serialize)]
+pub struct AuditEntry<'a> {
+    pub timestamp: String,
+    /// `read_file_content`, `read_file_prefix`, `read_file_slice`, `read_file_slice_into`
+    /// or `read_blocks`.
+    pub operation: &'a str,
+    /// Record identifier, `None` for raw block reads.
+    pub record: Option<u64>,
+    /// Offset in the record content, or in the partition for raw block reads.
+    pub offset: u64,
+    /// Requested length (`None` for whole content reads).
+    pub length: Option<u64>,
+    /// Bytes actually returned.
+    pub returned: u64,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub sha256: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub error: Option<String>,
+}
+
+/// Open `path` for appending and start auditing. Existing content is never truncated, so
+/// successive runs on the same evidence share a single log.
+pub fn install(path: &str) -> io::Result<AuditLog> {
+    let file = OpenOptions::new().create(true).append(true).open(path)?;
+    let log = Log {
+        writer: BufWriter::new(file),
+        unsynced: 0,
+    };
+    AUDIT_LOG
+        .set(Mutex::new(log))
+        .map_err(|_| io::Error::other("the audit log is already installed"))?;
+    Ok(AuditLog(()))
+}
+
+/// Write the buffered lines and sync them to disk. A no-op when auditing is off.
+pub fn sync() -> io::Result<()> {
+    match AUDIT_LOG.get() {
+        Some(log) => log.lock().unwrap_or_else(|e| e.into_inner()).sync(),
+        None => Ok(()),
+    }
+}
+
+pub fn enabled() -> bool {
+    AUDIT_LOG.get().is_some()
+}
+
+/// Append the outcome of a read. A no-op when auditing is off; write failures are logged
+/// rather than aborting the read.
+pub fn record_read(
+    operation: &str,
+    record: Option<u64>,
+    offset: u64,
+    length: Option<u64>,
+    result: &Result<Vec<u8>, Box<dyn Error>>,
+) {
+    if !enabled() {
+        return;
+    }
+    let outcome = match result {
+        Ok(data) => Ok(data.as_slice()),
+        Err(e) => Err(e.to_string()),
+    };
+    append(operation, record, offset, length, outcome);
+}
+
+/// `record_read` for a read into a caller buffer: `buf` is the whole buffer, of which the
+/// first `n` bytes of `Ok(n)` were filled.
+pub fn record_read_into(
+    operation: &str,
+    record: Option<u64>,
+    offset: u64,
+    buf: &[u8],
+    result: &Result<usize, Box<dyn Error>>,
+) {
+    if !enabled() {
+        return;
+    }
+    let outcome = match result {
+        Ok(n) => Ok(&buf[..*n]),
+        Err(e) => Err(e.to_string()),
+    };
+    append(operation, record, offset, Some(buf.len() as u64), outcome);
+}
+
+fn append(
+    operation: &str,
+    record: Option<u64>,
+    offset: u64,
+    length: Option<u64>,
+    outcome: Result<&[u8], String>,
+) {
+    let Some(log) = AUDIT_LOG.get() else {
+        return;
+    };
+    let (returned, sha256, error) = match outcome {
+        Ok(data) => (
+            data.len() as u64,
+            Some(hex::encode(Sha256::digest(data))),
+            None,
+        ),
+        Err(e) => (0, None, Some(e)),
+    };
+    let entry = AuditEntry {
+        timestamp: jiff::Timestamp::now().to_string(),
+        operation,
+        record,
+        offset,
+        length,
+        returned,
+        sha256,
+        error,
+    };
+    let Ok(line) = serde_json::to_string(&entry) else {
+        return;
+    };
+    let mut log = log.lock().unwrap_or_else(|e| e.into_inner());
+    let written = writeln!(log.writer, "{}", line).and_then(|_| {
+        log.unsynced += 1;
+        if log.unsynced >= SYNC_INTERVAL {
+            log.sync()
+        } else {
+            Ok(())
+        }
+    });
+    if let Err(e) = written {
+        error!("Could not write to the audit log: {}", e);
+    }
+}
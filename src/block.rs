@@ -0,0 +1,307 @@
+use crate::audit;
+use std::collections::VecDeque;
+use std::error::Error;
+use std::io::{self, Read, Seek, SeekFrom};
+
+const EXT_SUPERBLOCK_OFFSET: u64 = 1024;
+const EXT_MAGIC: u16 = 0xEF53;
+const EXT_INCOMPAT_64BIT: u32 = 0x80;
+const EXT_BG_BLOCK_UNINIT: u16 = 0x2;
+const EXFAT_SIGNATURE: &[u8] = b"EXFAT   ";
+const EXFAT_ENTRY_BITMAP: u8 = 0x81;
+const EXFAT_CHAIN_END: u32 = 0xFFFF_FFF8;
+/// Small reads (bitmap bytes, descriptors, FAT entries) are served from cached pages.
+const PAGE_SIZE: u64 = 4096;
+const CACHED_PAGES: usize = 16;
+
+/// On-disk allocation bitmap layouts that can be read without the filesystem parser.
+enum BitmapLayout {
+    Ext {
+        block_size: u64,
+        first_data_block: u64,
+        blocks_per_group: u64,
+        desc_size: u64,
+        wide_desc: bool,
+    },
+    Exfat {
+        geometry: ExfatGeometry,
+        cluster_count: u64,
+        bitmap_cluster: u32,
+    },
+    Unknown,
+}
+
+#[derive(Clone, Copy)]
+struct ExfatGeometry {
+    cluster_size: u64,
+    fat_offset: u64,
+    heap_offset: u64,
+}
+
+impl ExfatGeometry {
+    fn cluster_offset(&self, cluster: u32) -> u64 {
+        self.heap_offset + (cluster as u64 - 2) * self.cluster_size
+    }
+}
+
+fn le_u16(b: &[u8], at: usize) -> u16 {
+    u16::from_le_bytes([b[at], b[at + 1]])
+}
+
+fn le_u32(b: &[u8], at: usize) -> u32 {
+    u32::from_le_bytes(b[at..at + 4].try_into().unwrap())
+}
+
+/// Raw block access over a partition stream, independent of the filesystem parser.
+///
+/// Block addresses are counted in `block_size` units from the start of the partition,
+/// the same addressing used by The Sleuth Kit (`blkcat`, `blkstat`).
+pub struct BlockDevice<T: Read + Seek> {
+    stream: T,
+    block_size: u64,
+    len: u64,
+    layout: Option<BitmapLayout>,
+    pages: VecDeque<(u64, Vec<u8>)>,
+}
+
+impl<T: Read + Seek> BlockDevice<T> {
+    pub fn new(mut stream: T, block_size: u64) -> io::Result<Self> {
+        if block_size == 0 {
+            return Err(io::Error::new(
+                io::ErrorKind::InvalidInput,
+                "block size must not be zero",
+            ));
+        }
+        let len = stream.seek(SeekFrom::End(0))?;
+        Ok(Self {
+            stream,
+            block_size,
+            len,
+            layout: None,
+            pages: VecDeque::new(),
+        })
+    }
+
+    pub fn block_size(&self) -> u64 {
+        self.block_size
+    }
+
+    pub fn block_count(&self) -> u64 {
+        self.len / self.block_size
+    }
+
+    fn read_at(&mut self, offset: u64, length: usize) -> io::Result<Vec<u8>> {
+        let page = offset - offset % PAGE_SIZE;
+        if offset + length as u64 <= (page + PAGE_SIZE).min(self.len) {
+            let at = (offset - page) as usize;
+            if let Some((_, data)) = self.pages.iter().find(|(p, _)| *p == page) {
+                return Ok(data[at..at + length].to_vec());
+            }
+            let mut data = vec![0u8; (PAGE_SIZE.min(self.len - page)) as usize];
+            self.stream.seek(SeekFrom::Start(page))?;
+            self.stream.read_exact(&mut data)?;
+            let buf = data[at..at + length].to_vec();
+            if self.pages.len() == CACHED_PAGES {
+                self.pages.pop_front();
+            }
+            self.pages.push_back((page, data));
+            return Ok(buf);
+        }
+        let mut buf = vec![0u8; length];
+        self.stream.seek(SeekFrom::Start(offset))?;
+        self.stream.read_exact(&mut buf)?;
+        Ok(buf)
+    }
+
+    /// Read `count` consecutive blocks starting at block address `block`.
+    pub fn read_blocks(&mut self, block: u64, count: u64) -> Result<Vec<u8>, Box<dyn Error>> {
+        let end = block
+            .checked_add(count)
+            .filter(|end| *end <= self.block_count())
+            .ok_or_else(|| {
+                format!(
+                    "blocks {}..{} are out of range (the partition has {} blocks)",
+                    block,
+                    block.saturating_add(count),
+                    self.block_count()
+                )
+            })?;
+        let offset = block * self.block_size;
+        let length = ((end - block) * self.block_size) as usize;
+        let result = self.read_at(offset, length).map_err(Into::into);
+        audit::record_read("read_blocks", None, offset, Some(length as u64), &result);
+        result
+    }
+
+    /// Read up to `length` bytes at byte `offset` of the partition; shorter at its end.
+    pub fn read_bytes(&mut self, offset: u64, length: usize) -> Result<Vec<u8>, Box<dyn Error>> {
+        let length = (length as u64).min(self.len.saturating_sub(offset)) as usize;
+        let result = self.read_at(offset, length).map_err(Into::into);
+        audit::record_read("read_bytes", None, offset, Some(length as u64), &result);
+        result
+    }
+
+    /// Partition length in bytes.
+    pub fn len(&self) -> u64 {
+        self.len
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.len == 0
+    }
+
+    /// Allocation status of a block read from the on-disk allocation bitmap, for
+    /// the filesystems whose bitmap can be located without the full parser
+    /// (ext2/3/4 and exFAT). Returns `None` when the status cannot be determined.
+    pub fn bitmap_allocation(&mut self, block: u64) -> Result<Option<bool>, Box<dyn Error>> {
+        if self.layout.is_none() {
+            self.layout = Some(self.detect_layout()?);
+        }
+        match self.layout {
+            Some(BitmapLayout::Ext {
+                block_size,
+                first_data_block,
+                blocks_per_group,
+                desc_size,
+                wide_desc,
+            }) => {
+                let byte_offset = block * self.block_size;
+                let fs_block = byte_offset / block_size;
+                if fs_block < first_data_block {
+                    return Ok(Some(true));
+                }
+                let relative = fs_block - first_data_block;
+                let group = relative / blocks_per_group;
+                let index = relative % blocks_per_group;
+
+                let gdt_offset = (first_data_block + 1) * block_size;
+                let desc = self.read_at(gdt_offset + group * desc_size, desc_size as usize)?;
+                if le_u16(&desc, 0x12) & EXT_BG_BLOCK_UNINIT != 0 {
+                    // The bitmap of this group was never written.
+                    return Ok(None);
+                }
+                let mut bitmap_block = le_u32(&desc, 0x00) as u64;
+                if wide_desc {
+                    bitmap_block |= (le_u32(&desc, 0x20) as u64) << 32;
+                }
+                let byte = self.read_at(bitmap_block * block_size + index / 8, 1)?[0];
+                Ok(Some(byte & (1 << (index % 8)) != 0))
+            }
+            Some(BitmapLayout::Exfat {
+                geometry,
+                cluster_count,
+                bitmap_cluster,
+            }) => {
+                let cluster_size = geometry.cluster_size;
+                let heap_offset = geometry.heap_offset;
+                let byte_offset = block * self.block_size;
+                if byte_offset < heap_offset {
+                    // Boot region and FAT: always in use.
+                    return Ok(Some(true));
+                }
+                let index = (byte_offset - heap_offset) / cluster_size;
+                if index >= cluster_count {
+                    return Ok(None);
+                }
+                let bitmap_offset = index / 8;
+                let chain_step = bitmap_offset / cluster_size;
+                let cluster = self.exfat_follow_chain(geometry, bitmap_cluster, chain_step)?;
+                let at = geometry.cluster_offset(cluster) + bitmap_offset % cluster_size;
+                let byte = self.read_at(at, 1)?[0];
+                Ok(Some(byte & (1 << (index % 8)) != 0))
+            }
+            _ => Ok(None),
+        }
+    }
+
+    fn detect_layout(&mut self) -> Result<BitmapLayout, Box<dyn Error>> {
+        if self.len >= EXT_SUPERBLOCK_OFFSET + 1024 {
+            let sb = self.read_at(EXT_SUPERBLOCK_OFFSET, 1024)?;
+            if le_u16(&sb, 0x38) == EXT_MAGIC {
+                let wide_desc = le_u32(&sb, 0x60) & EXT_INCOMPAT_64BIT != 0;
+                let desc_size = if wide_desc {
+                    (le_u16(&sb, 0xFE) as u64).max(32)
+                } else {
+                    32
+                };
+                return Ok(BitmapLayout::Ext {
+                    block_size: 1024u64 << le_u32(&sb, 0x18),
+                    first_data_block: le_u32(&sb, 0x14) as u64,
+                    blocks_per_group: (le_u32(&sb, 0x20) as u64).max(1),
+                    desc_size,
+                    wide_desc: wide_desc && desc_size >= 64,
+                });
+            }
+        }
+
+        if self.len >= 512 {
+            let boot = self.read_at(0, 512)?;
+            if &boot[3..11] == EXFAT_SIGNATURE {
+                let sector_size = 1u64 << boot[0x6C];
+                let geometry = ExfatGeometry {
+                    cluster_size: sector_size << boot[0x6D],
+                    fat_offset: le_u32(&boot, 0x50) as u64 * sector_size,
+                    heap_offset: le_u32(&boot, 0x58) as u64 * sector_size,
+                };
+                return Ok(BitmapLayout::Exfat {
+                    geometry,
+                    cluster_count: le_u32(&boot, 0x5C) as u64,
+                    bitmap_cluster: self.exfat_find_bitmap(geometry, le_u32(&boot, 0x60))?,
+                });
+            }
+        }
+
+        Ok(BitmapLayout::Unknown)
+    }
+
+    fn exfat_next_cluster(
+        &mut self,
+        geometry: ExfatGeometry,
+        cluster: u32,
+    ) -> Result<u32, Box<dyn Error>> {
+        Ok(le_u32(
+            &self.read_at(geometry.fat_offset + cluster as u64 * 4, 4)?,
+            0,
+        ))
+    }
+
+    fn exfat_follow_chain(
+        &mut self,
+        geometry: ExfatGeometry,
+        start: u32,
+        steps: u64,
+    ) -> Result<u32, Box<dyn Error>> {
+        let mut cluster = start;
+        for _ in 0..steps {
+            cluster = self.exfat_next_cluster(geometry, cluster)?;
+            if !(2..EXFAT_CHAIN_END).contains(&cluster) {
+                return Err("exFAT allocation bitmap chain is truncated".into());
+            }
+        }
+        Ok(cluster)
+    }
+
+    /// Walk the root directory for the first allocation bitmap entry.
+    fn exfat_find_bitmap(
+        &mut self,
+        geometry: ExfatGeometry,
+        root_cluster: u32,
+    ) -> Result<u32, Box<dyn Error>> {
+        let mut cluster = root_cluster;
+        while (2..EXFAT_CHAIN_END).contains(&cluster) {
+            let data = self.read_at(
+                geometry.cluster_offset(cluster),
+                geometry.cluster_size as usize,
+            )?;
+            for entry in data.chunks_exact(32) {
+                match entry[0] {
+                    0x00 => return Err("exFAT allocation bitmap entry not found".into()),
+                    EXFAT_ENTRY_BITMAP if entry[1] & 1 == 0 => return Ok(le_u32(entry, 20)),
+                    _ => {}
+                }
+            }
+            cluster = self.exfat_next_cluster(geometry, cluster)?;
+        }
+        Err("exFAT allocation bitmap entry not found".into())
+    }
+}
@@ -0,0 +1,46 @@
+//! Global memory budget (`--memory-limit`), for triage machines where a large walk would
+//! otherwise be killed for running out of memory. Without a budget every structure keeps
+//! its default size. With one, each consumer sizes itself from its share:
+//!
+//! - block caches: 25%;
+//! - visited records of a walk: 15%, the rest spills to disk (see `spill`);
+//! - queued records of a walk: 15%, likewise;
+//! - content buffers between threads (read-ahead, hashing, archive writes): 20%;
+//! - walk event channels and index batches: 5%.
+//!
+//! The remainder covers the records being processed and allocator overhead.
+use std::sync::atomic::{AtomicU64, Ordering};
+
+pub const CACHE_SHARE: u64 = 25;
+pub const SEEN_SHARE: u64 = 15;
+pub const QUEUE_SHARE: u64 = 15;
+pub const BUFFER_SHARE: u64 = 20;
+pub const BATCH_SHARE: u64 = 5;
+
+static LIMIT: AtomicU64 = AtomicU64::new(0);
+
+/// Memory budget in bytes for the rest of the run; 0 removes it.
+pub fn set_limit(bytes: u64) {
+    LIMIT.store(bytes, Ordering::Relaxed);
+}
+
+pub fn limit() -> Option<u64> {
+    match LIMIT.load(Ordering::Relaxed) {
+        0 => None,
+        bytes => Some(bytes),
+    }
+}
+
+/// `percent` of the budget in bytes, `None` without a budget.
+pub fn share(percent: u64) -> Option<u64> {
+    limit().map(|bytes| bytes / 100 * percent)
+}
+
+/// `default` items of `item_bytes` each, reduced to fit in `percent` of the budget but
+/// never below one.
+pub fn items(default: usize, percent: u64, item_bytes: u64) -> usize {
+    match share(percent) {
+        Some(bytes) => default.min((bytes / item_bytes.max(1)) as usize).max(1),
+        None => default,
+    }
+}
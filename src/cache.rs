@@ -0,0 +1,292 @@
+//! Shared LRU cache of partition blocks. Every backend reads its metadata (group
+//! descriptors, MFT records, B-tree nodes, FAT sectors) through the partition stream, so
+//! caching small reads there serves all of them; large content reads bypass it to keep
+//! the hot blocks resident. Below the cache, a read buffer coalesces adjacent small reads
+//! (cache misses, or every read when caching is off) into one read of the evidence.
+use crate::budget;
+use std::collections::{BTreeMap, HashMap};
+use std::io::{self, Read, Seek, SeekFrom};
+use std::sync::atomic::{AtomicU64, Ordering};
+
+/// Cached unit, aligned on the start of the partition.
+pub const CACHE_BLOCK_SIZE: u64 = 4096;
+/// Reads of at least this many bytes go straight to the underlying stream.
+const BYPASS_LENGTH: usize = 16 * CACHE_BLOCK_SIZE as usize;
+/// Default capacity of each partition cache.
+pub const DEFAULT_CACHE_CAPACITY: u64 = 32 * 1024 * 1024;
+/// Default size of the read buffer below each partition cache.
+pub const DEFAULT_READ_BUFFER: u64 = 64 * 1024;
+
+static CAPACITY: AtomicU64 = AtomicU64::new(DEFAULT_CACHE_CAPACITY);
+static READ_BUFFER: AtomicU64 = AtomicU64::new(DEFAULT_READ_BUFFER);
+static HITS: AtomicU64 = AtomicU64::new(0);
+static MISSES: AtomicU64 = AtomicU64::new(0);
+static EVICTIONS: AtomicU64 = AtomicU64::new(0);
+static BYPASSED: AtomicU64 = AtomicU64::new(0);
+
+/// Capacity in bytes of the caches opened from now on; 0 disables caching.
+pub fn set_capacity(bytes: u64) {
+    CAPACITY.store(bytes, Ordering::Relaxed);
+}
+
+/// Capacity of the caches opened from now on, within the share of the memory budget.
+pub fn capacity() -> u64 {
+    let capacity = CAPACITY.load(Ordering::Relaxed);
+    budget::share(budget::CACHE_SHARE).map_or(capacity, |share| capacity.min(share))
+}
+
+/// Size of the read buffer of the partition streams opened from now on; 0 disables it.
+pub fn set_read_buffer(bytes: u64) {
+    READ_BUFFER.store(bytes, Ordering::Relaxed);
+}
+
+pub fn read_buffer() -> u64 {
+    READ_BUFFER.load(Ordering::Relaxed)
+}
+
+/// Process-wide counters of every block cache.
+#[derive(Debug, Clone, Copy, Default, serde::Serialize)]
+pub struct CacheStats {
+    pub hits: u64,
+    pub misses: u64,
+    pub evictions: u64,
+    /// Large reads served by the underlying stream directly.
+    pub bypassed: u64,
+}
+
+impl CacheStats {
+    /// Share of block lookups served from memory, in percent.
+    pub fn hit_rate(&self) -> f64 {
+        let lookups = self.hits + self.misses;
+        if lookups == 0 {
+            0.0
+        } else {
+            self.hits as f64 * 100.0 / lookups as f64
+        }
+    }
+}
+
+pub fn stats() -> CacheStats {
+    CacheStats {
+        hits: HITS.load(Ordering::Relaxed),
+        misses: MISSES.load(Ordering::Relaxed),
+        evictions: EVICTIONS.load(Ordering::Relaxed),
+        bypassed: BYPASSED.load(Ordering::Relaxed),
+    }
+}
+
+/// `Read + Seek` adapter keeping the most recently used blocks of `inner` in memory.
+pub struct BlockCache<R: Read + Seek> {
+    inner: R,
+    len: u64,
+    pos: u64,
+    capacity: u64,
+    /// Block number to (last use, content).
+    blocks: HashMap<u64, (u64, Vec<u8>)>,
+    /// Last use to block number, oldest first.
+    recency: BTreeMap<u64, u64>,
+    tick: u64,
+    cached_bytes: u64,
+}
+
+impl<R: Read + Seek> BlockCache<R> {
+    pub fn new(mut inner: R, capacity: u64) -> io::Result<Self> {
+        let len = inner.seek(SeekFrom::End(0))?;
+        inner.seek(SeekFrom::Start(0))?;
+        Ok(Self {
+            inner,
+            len,
+            pos: 0,
+            capacity,
+            blocks: HashMap::new(),
+            recency: BTreeMap::new(),
+            tick: 0,
+            cached_bytes: 0,
+        })
+    }
+
+    fn read_inner(&mut self, offset: u64, buf: &mut [u8]) -> io::Result<usize> {
+        self.inner.seek(SeekFrom::Start(offset))?;
+        let mut filled = 0;
+        while filled < buf.len() {
+            match self.inner.read(&mut buf[filled..]) {
+                Ok(0) => break,
+                Ok(n) => filled += n,
+                Err(e) if e.kind() == io::ErrorKind::Interrupted => continue,
+                Err(e) => return Err(e),
+            }
+        }
+        Ok(filled)
+    }
+
+    /// Content of block `number`, from memory or read and cached.
+    fn block(&mut self, number: u64) -> io::Result<&[u8]> {
+        self.tick += 1;
+        if let Some((used, _)) = self.blocks.get_mut(&number) {
+            HITS.fetch_add(1, Ordering::Relaxed);
+            self.recency.remove(used);
+            *used = self.tick;
+            self.recency.insert(self.tick, number);
+        } else {
+            MISSES.fetch_add(1, Ordering::Relaxed);
+            let start = number * CACHE_BLOCK_SIZE;
+            let mut data = vec![0u8; CACHE_BLOCK_SIZE.min(self.len - start) as usize];
+            let n = self.read_inner(start, &mut data)?;
+            data.truncate(n);
+            while self.cached_bytes + data.len() as u64 > self.capacity {
+                let Some((_, oldest)) = self.recency.pop_first() else {
+                    break;
+                };
+                if let Some((_, evicted)) = self.blocks.remove(&oldest) {
+                    self.cached_bytes -= evicted.len() as u64;
+                    EVICTIONS.fetch_add(1, Ordering::Relaxed);
+                }
+            }
+            self.cached_bytes += data.len() as u64;
+            self.recency.insert(self.tick, number);
+            self.blocks.insert(number, (self.tick, data));
+        }
+        Ok(&self.blocks[&number].1)
+    }
+}
+
+impl<R: Read + Seek> Read for BlockCache<R> {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        if self.pos >= self.len || buf.is_empty() {
+            return Ok(0);
+        }
+        if buf.len() >= BYPASS_LENGTH {
+            BYPASSED.fetch_add(1, Ordering::Relaxed);
+            let n = self.read_inner(self.pos, buf)?;
+            self.pos += n as u64;
+            return Ok(n);
+        }
+        // Fill the whole buffer when possible: some parsers expect a single read to do it.
+        let mut filled = 0;
+        while filled < buf.len() && self.pos < self.len {
+            let within = (self.pos % CACHE_BLOCK_SIZE) as usize;
+            let block = self.block(self.pos / CACHE_BLOCK_SIZE)?;
+            let n = (buf.len() - filled).min(block.len().saturating_sub(within));
+            if n == 0 {
+                break;
+            }
+            buf[filled..filled + n].copy_from_slice(&block[within..within + n]);
+            filled += n;
+            self.pos += n as u64;
+        }
+        Ok(filled)
+    }
+}
+
+impl<R: Read + Seek> Seek for BlockCache<R> {
+    fn seek(&mut self, pos: SeekFrom) -> io::Result<u64> {
+        let target = match pos {
+            SeekFrom::Start(p) => p as i128,
+            SeekFrom::End(d) => self.len as i128 + d as i128,
+            SeekFrom::Current(d) => self.pos as i128 + d as i128,
+        };
+        if target < 0 {
+            return Err(io::Error::new(
+                io::ErrorKind::InvalidInput,
+                "seek before the start of the stream",
+            ));
+        }
+        self.pos = target as u64;
+        Ok(self.pos)
+    }
+}
+
+/// `Read + Seek` adapter reading `inner` by windows of a fixed size: small reads falling
+/// in the current window are served from memory, so runs of adjacent small reads become
+/// one read of `inner`. Reads at least as large as the window go straight through.
+pub struct ReadBuffer<R: Read + Seek> {
+    inner: R,
+    len: u64,
+    pos: u64,
+    size: usize,
+    window: Vec<u8>,
+    window_start: u64,
+}
+
+impl<R: Read + Seek> ReadBuffer<R> {
+    pub fn new(mut inner: R, size: usize) -> io::Result<Self> {
+        let len = inner.seek(SeekFrom::End(0))?;
+        inner.seek(SeekFrom::Start(0))?;
+        Ok(Self {
+            inner,
+            len,
+            pos: 0,
+            size,
+            window: Vec::with_capacity(size),
+            window_start: 0,
+        })
+    }
+
+    fn read_inner(&mut self, offset: u64, buf: &mut [u8]) -> io::Result<usize> {
+        self.inner.seek(SeekFrom::Start(offset))?;
+        let mut filled = 0;
+        while filled < buf.len() {
+            match self.inner.read(&mut buf[filled..]) {
+                Ok(0) => break,
+                Ok(n) => filled += n,
+                Err(e) if e.kind() == io::ErrorKind::Interrupted => continue,
+                Err(e) => return Err(e),
+            }
+        }
+        Ok(filled)
+    }
+}
+
+impl<R: Read + Seek> Read for ReadBuffer<R> {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        if self.pos >= self.len || buf.is_empty() {
+            return Ok(0);
+        }
+        if buf.len() >= self.size {
+            let n = self.read_inner(self.pos, buf)?;
+            self.pos += n as u64;
+            return Ok(n);
+        }
+        // Like the cache, fill the whole buffer when possible.
+        let mut filled = 0;
+        while filled < buf.len() && self.pos < self.len {
+            let window_end = self.window_start + self.window.len() as u64;
+            if self.pos < self.window_start || self.pos >= window_end {
+                let want = (self.len - self.pos).min(self.size as u64) as usize;
+                let mut window = std::mem::take(&mut self.window);
+                window.resize(want, 0);
+                let n = self.read_inner(self.pos, &mut window)?;
+                window.truncate(n);
+                self.window = window;
+                self.window_start = self.pos;
+                if n == 0 {
+                    break;
+                }
+            }
+            let within = (self.pos - self.window_start) as usize;
+            let n = (buf.len() - filled).min(self.window.len() - within);
+            buf[filled..filled + n].copy_from_slice(&self.window[within..within + n]);
+            filled += n;
+            self.pos += n as u64;
+        }
+        Ok(filled)
+    }
+}
+
+impl<R: Read + Seek> Seek for ReadBuffer<R> {
+    fn seek(&mut self, pos: SeekFrom) -> io::Result<u64> {
+        let target = match pos {
+            SeekFrom::Start(p) => p as i128,
+            SeekFrom::End(d) => self.len as i128 + d as i128,
+            SeekFrom::Current(d) => self.pos as i128 + d as i128,
+        };
+        if target < 0 {
+            return Err(io::Error::new(
+                io::ErrorKind::InvalidInput,
+                "seek before the start of the stream",
+            ));
+        }
+        self.pos = target as u64;
+        Ok(self.pos)
+    }
+}
@@ -0,0 +1,421 @@
+//! Signature carving: unallocated blocks, file slack or the whole partition are scanned
+//! for known headers, and each hit is extended to its footer (or the size recorded in
+//! its header). Every carved file keeps the partition offset it was recovered from.
+use crate::block::BlockDevice;
+use crate::filesystem::{FileCommon, Filesystem, WalkEvent, WalkOptions};
+use crate::hashing::{FileHashes, HashAlgorithm, hash_reader};
+use crate::search::ExcludeSet;
+use regex::bytes::Regex;
+use serde::Serialize;
+use std::error::Error;
+use std::fmt;
+use std::io::{Read, Seek};
+use std::path::Path;
+use std::str::FromStr;
+
+/// Bytes scanned per read; hits straddling two chunks are caught by the overlap.
+const SCAN_CHUNK: usize = 4 * 1024 * 1024;
+/// Bytes read at a time while looking for a footer.
+const FOOTER_STEP: usize = 1024 * 1024;
+/// Blocks whose allocation status is queried at once.
+const ALLOCATION_BATCH: u64 = 65_536;
+/// Progress is reported every this many bytes scanned.
+const STATUS_EVERY: u64 = 1024 * 1024 * 1024;
+
+/// Where to look for content.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize)]
+#[serde(rename_all = "lowercase")]
+pub enum CarveSource {
+    Unallocated,
+    Slack,
+    All,
+}
+
+impl FromStr for CarveSource {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s.to_ascii_lowercase().as_str() {
+            "unallocated" | "unalloc" => Ok(CarveSource::Unallocated),
+            "slack" => Ok(CarveSource::Slack),
+            "all" => Ok(CarveSource::All),
+            other => Err(format!("unknown carving source: {}", other)),
+        }
+    }
+}
+
+impl fmt::Display for CarveSource {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let s = match self {
+            CarveSource::Unallocated => "unallocated",
+            CarveSource::Slack => "slack",
+            CarveSource::All => "all",
+        };
+        write!(f, "{}", s)
+    }
+}
+
+/// How the end of a carved file is found.
+#[derive(Debug, Clone, Copy)]
+enum End {
+    /// First occurrence of the footer after the header, footer included.
+    Footer(&'static [u8]),
+    /// Page size times page count, from the SQLite header.
+    SqliteHeader,
+}
+
+/// A carvable file type.
+#[derive(Debug, Clone, Copy)]
+pub struct CarveType {
+    pub name: &'static str,
+    pub extension: &'static str,
+    header: &'static [u8],
+    end: End,
+    /// Carved files are never larger than this.
+    pub max_size: u64,
+}
+
+pub const CARVE_TYPES: &[CarveType] = &[
+    CarveType {
+        name: "jpg",
+        extension: "jpg",
+        header: b"\xFF\xD8\xFF",
+        end: End::Footer(b"\xFF\xD9"),
+        max_size: 20 * 1024 * 1024,
+    },
+    CarveType {
+        name: "png",
+        extension: "png",
+        header: b"\x89PNG\r\n\x1A\n",
+        end: End::Footer(b"IEND\xAE\x42\x60\x82"),
+        max_size: 20 * 1024 * 1024,
+    },
+    CarveType {
+        name: "gif",
+        extension: "gif",
+        header: b"GIF89a",
+        end: End::Footer(b"\x00\x3B"),
+        max_size: 10 * 1024 * 1024,
+    },
+    CarveType {
+        name: "pdf",
+        extension: "pdf",
+        header: b"%PDF-",
+        end: End::Footer(b"%%EOF"),
+        max_size: 100 * 1024 * 1024,
+    },
+    CarveType {
+        name: "sqlite",
+        extension: "sqlite",
+        header: b"SQLite format 3\x00",
+        end: End::SqliteHeader,
+        max_size: 1024 * 1024 * 1024,
+    },
+];
+
+/// Resolve a comma separated list of type names (`all` for every type).
+pub fn parse_carve_types(list: &str) -> Result<Vec<CarveType>, String> {
+    let mut types: Vec<CarveType> = Vec::new();
+    for name in list.split(',').map(|n| n.trim().to_ascii_lowercase()) {
+        let name = match name.as_str() {
+            "jpeg" => "jpg",
+            "sqlite3" | "db" => "sqlite",
+            other => other,
+        };
+        if name == "all" {
+            return Ok(CARVE_TYPES.to_vec());
+        }
+        let carve_type = CARVE_TYPES.iter().find(|t| t.name == name).ok_or_else(|| {
+            let names: Vec<&str> = CARVE_TYPES.iter().map(|t| t.name).collect();
+            format!(
+                "unknown carving type: {} (expected {} or all)",
+                name,
+                names.join(", ")
+            )
+        })?;
+        if !types.iter().any(|t| t.name == carve_type.name) {
+            types.push(*carve_type);
+        }
+    }
+    Ok(types)
+}
+
+/// A contiguous byte range of the partition to scan.
+#[derive(Debug, Clone, Copy, Serialize)]
+pub struct Region {
+    pub offset: u64,
+    pub length: u64,
+    /// Record owning the slack space, for slack regions.
+    pub record: Option<u64>,
+}
+
+/// Contiguous runs of blocks the filesystem reports as unallocated, as byte regions.
+/// Blocks of unknown status are left out.
+pub fn unallocated_regions<F: Filesystem + ?Sized, T: Read + Seek>(
+    fs: &mut F,
+    device: &mut BlockDevice<T>,
+) -> Result<Vec<Region>, Box<dyn Error>> {
+    let block_size = device.block_size();
+    let block_count = device.block_count();
+    let mut regions: Vec<Region> = Vec::new();
+    let mut known = false;
+    let mut first = 0;
+    while first < block_count {
+        let count = ALLOCATION_BATCH.min(block_count - first);
+        let statuses = fs.block_allocation_range(first, count)?;
+        for (block, status) in (first..first + count).zip(statuses) {
+            let status = match status {
+                Some(status) => Some(status),
+                None => device.bitmap_allocation(block)?,
+            };
+            known |= status.is_some();
+            if status != Some(false) {
+                continue;
+            }
+            let offset = block * block_size;
+            match regions.last_mut() {
+                Some(last) if last.offset + last.length == offset => last.length += block_size,
+                _ => regions.push(Region {
+                    offset,
+                    length: block_size,
+                    record: None,
+                }),
+            }
+        }
+        first += count;
+    }
+    if !known {
+        return Err("the allocation status of this filesystem is unknown; use --source all".into());
+    }
+    Ok(regions)
+}
+
+fn is_regular(ftype: &str) -> bool {
+    ftype.eq_ignore_ascii_case("file") || ftype.eq_ignore_ascii_case("regular")
+}
+
+/// The bytes between the end of each regular file and the end of its last block.
+pub fn slack_regions<F: Filesystem + ?Sized>(
+    fs: &mut F,
+    exclude: Option<&ExcludeSet>,
+) -> Result<Vec<Region>, Box<dyn Error>> {
+    let block_size = fs.block_size();
+    let mut files = Vec::new();
+    fs.walk_fs_with(
+        WalkOptions {
+            exclude,
+            ..Default::default()
+        },
+        &mut |event| {
+            if let WalkEvent::File(file) = event
+                && is_regular(&file.ftype)
+            {
+                files.push(file.identifier);
+            }
+        },
+    )?;
+
+    let mut regions = Vec::new();
+    for identifier in files {
+        let Ok(record) = fs.get_file(identifier) else {
+            continue;
+        };
+        let size = record.size();
+        let Ok(Some(runs)) = fs.file_block_runs(&record) else {
+            continue;
+        };
+        // Logical position of each run, to locate the bytes past the end of the file.
+        let mut logical = 0;
+        for (block, count) in runs {
+            let run_len = count * block_size;
+            let run_end = logical + run_len;
+            if run_end > size {
+                let skip = size.saturating_sub(logical);
+                regions.push(Region {
+                    offset: block * block_size + skip,
+                    length: run_len - skip,
+                    record: Some(identifier),
+                });
+            }
+            logical = run_end;
+        }
+    }
+    Ok(regions)
+}
+
+/// One carved file, with its provenance.
+#[derive(Debug, Clone, Serialize)]
+pub struct CarvedFile {
+    /// Output file name, relative to the output directory.
+    pub file: String,
+    #[serde(rename = "type")]
+    pub kind: &'static str,
+    pub source: CarveSource,
+    /// Offset of the content in the partition.
+    pub offset: u64,
+    /// Offset of the content in the image (partition offset included).
+    pub image_offset: u64,
+    pub length: u64,
+    /// Record owning the slack space the content was found in.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub record: Option<u64>,
+    #[serde(flatten)]
+    pub hashes: FileHashes,
+}
+
+/// Summary written next to the carved files.
+#[derive(Debug, Clone, Serialize)]
+pub struct CarveReport {
+    pub source: CarveSource,
+    pub partition_offset: u64,
+    pub regions: usize,
+    pub bytes_scanned: u64,
+    /// Headers whose end could not be found within the region or the type's size limit.
+    pub unterminated: u64,
+    pub files: Vec<CarvedFile>,
+}
+
+fn header_regex(types: &[CarveType]) -> Result<Regex, Box<dyn Error>> {
+    let alternatives: Vec<String> = types
+        .iter()
+        .map(|t| t.header.iter().map(|b| format!("\\x{:02X}", b)).collect())
+        .collect();
+    Ok(Regex::new(&format!("(?-u)(?:{})", alternatives.join("|")))?)
+}
+
+/// Length of the content starting at `start`, or `None` when its end is not within
+/// `limit` bytes.
+fn carved_length<T: Read + Seek>(
+    device: &mut BlockDevice<T>,
+    carve_type: &CarveType,
+    start: u64,
+    limit: u64,
+) -> Result<Option<u64>, Box<dyn Error>> {
+    match carve_type.end {
+        End::SqliteHeader => {
+            let header = device.read_bytes(start, 100)?;
+            if header.len() < 100 {
+                return Ok(None);
+            }
+            let page_size = match u16::from_be_bytes([header[16], header[17]]) {
+                1 => 65_536,
+                n if n >= 512 && n.is_power_of_two() => n as u64,
+                _ => return Ok(None),
+            };
+            let pages = u32::from_be_bytes(header[28..32].try_into().unwrap()) as u64;
+            let length = page_size * pages;
+            Ok((pages > 0 && length <= limit).then_some(length))
+        }
+        End::Footer(footer) => {
+            let mut scanned = carve_type.header.len() as u64;
+            while scanned < limit {
+                // Back up so a footer split between two reads is still seen.
+                let from = scanned.saturating_sub(footer.len() as u64 - 1);
+                let want = (FOOTER_STEP as u64).min(limit - from) as usize;
+                let data = device.read_bytes(start + from, want)?;
+                if let Some(pos) = data.windows(footer.len()).position(|w| w == footer) {
+                    return Ok(Some(from + (pos + footer.len()) as u64));
+                }
+                if data.len() < want {
+                    break;
+                }
+                scanned = from + data.len() as u64;
+            }
+            Ok(None)
+        }
+    }
+}
+
+/// What to carve and where to write it.
+pub struct CarveOptions<'a> {
+    pub source: CarveSource,
+    pub types: &'a [CarveType],
+    pub out_dir: &'a Path,
+    /// Added to partition offsets to report image offsets.
+    pub partition_offset: u64,
+    pub algorithms: &'a [HashAlgorithm],
+}
+
+/// Carve out of `regions`, writing the files into the output directory.
+pub fn carve<T: Read + Seek>(
+    device: &mut BlockDevice<T>,
+    regions: &[Region],
+    options: &CarveOptions,
+    on_status: &mut dyn FnMut(String),
+) -> Result<CarveReport, Box<dyn Error>> {
+    std::fs::create_dir_all(options.out_dir)?;
+    let types = options.types;
+    let headers = header_regex(types)?;
+    let overlap = types.iter().map(|t| t.header.len()).max().unwrap_or(1) as u64 - 1;
+    let mut report = CarveReport {
+        source: options.source,
+        partition_offset: options.partition_offset,
+        regions: regions.len(),
+        bytes_scanned: regions.iter().map(|r| r.length).sum(),
+        unterminated: 0,
+        files: Vec::new(),
+    };
+    let mut scanned = 0u64;
+
+    for region in regions {
+        let region_end = region.offset + region.length;
+        let mut pos = region.offset;
+        while pos < region_end {
+            let want = (SCAN_CHUNK as u64).min(region_end - pos) as usize;
+            let chunk = device.read_bytes(pos, want)?;
+            if chunk.is_empty() {
+                break;
+            }
+            let chunk_end = pos + chunk.len() as u64;
+            // Headers starting in the overlap are handled with the next chunk.
+            let mut next = if chunk_end >= region_end {
+                region_end
+            } else {
+                (chunk_end - overlap).max(pos + 1)
+            };
+            for hit in headers.find_iter(&chunk) {
+                let start = pos + hit.start() as u64;
+                if start >= next {
+                    break;
+                }
+                let Some(carve_type) = types.iter().find(|t| hit.as_bytes() == t.header) else {
+                    continue;
+                };
+                let limit = carve_type.max_size.min(region_end - start);
+                let Some(length) = carved_length(device, carve_type, start, limit)? else {
+                    report.unterminated += 1;
+                    continue;
+                };
+                let data = device.read_bytes(start, length as usize)?;
+                let name = format!("{}_{:012x}.{}", options.source, start, carve_type.extension);
+                std::fs::write(options.out_dir.join(&name), &data)?;
+                let (hashes, _) = hash_reader(&mut data.as_slice(), options.algorithms)?;
+                report.files.push(CarvedFile {
+                    file: name,
+                    kind: carve_type.name,
+                    source: options.source,
+                    offset: start,
+                    image_offset: options.partition_offset + start,
+                    length,
+                    record: region.record,
+                    hashes,
+                });
+                // Resume the scan right after the carved content.
+                next = start + length;
+                break;
+            }
+            let before = scanned;
+            scanned += next - pos;
+            if scanned / STATUS_EVERY != before / STATUS_EVERY {
+                on_status(format!(
+                    "{} of {} bytes scanned, {} files carved",
+                    scanned,
+                    report.bytes_scanned,
+                    report.files.len()
+                ));
+            }
+            pos = next;
+        }
+    }
+    Ok(report)
+}
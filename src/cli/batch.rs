@@ -0,0 +1,338 @@
+//! Case manifest processing (`batch`): every operation of a manifest is run against every
+//! partition of every evidence it lists, each as a child run of this binary, with one
+//! consolidated log and a summary report.
+//!
+//! ```yaml
+//! name: acme-2024
+//! output: /cases/acme-2024
+//! operations:
+//!   - name: enum
+//!     args: ["--enum", "--output-format", "csv", "--output", "files.csv"]
+//!   - name: triage
+//!     args: ["triage"]
+//! evidence:
+//!   - id: laptop
+//!     path: /evidence/laptop.E01
+//!     format: ewf
+//!     partitions:
+//!       - { offset: 0x100000, size: 0x3a380000, label: system }
+//!   - id: usb
+//!     path: /evidence/usb.dd      # every partition of its partition table
+//! ```
+//!
+//! TOML manifests (`.toml`) take the same keys. Jobs run in
+//! `<output>/<evidence>/<partition>/`, so relative output paths land there, and the
+//! standard output of each job is kept in `<operation>.out` next to them.
+use exhume_body::Body;
+use exhume_filesystem::detected_fs::detect_filesystem;
+use exhume_filesystem::partitions::{detect_sector_size, read_partition_table};
+use log::{error, info, warn};
+use serde::{Deserialize, Serialize};
+use std::error::Error;
+use std::fs::{self, File as StdFile, OpenOptions};
+use std::io::{BufRead, BufReader, Write};
+use std::path::{Path, PathBuf};
+use std::process::{Command, Stdio};
+use std::time::Instant;
+
+#[derive(Debug, Clone, Deserialize)]
+pub struct CaseManifest {
+    pub name: String,
+    /// Base output directory of the case.
+    pub output: PathBuf,
+    /// Operations run on every partition of every evidence.
+    #[serde(default)]
+    pub operations: Vec<Operation>,
+    #[serde(default)]
+    pub evidence: Vec<EvidenceSpec>,
+}
+
+#[derive(Debug, Clone, Deserialize)]
+pub struct EvidenceSpec {
+    pub id: String,
+    /// Image or folder.
+    pub path: String,
+    /// Container format given to the body reader (`auto` by default).
+    pub format: Option<String>,
+    /// Partitions to process. When empty, every partition of the partition table holding
+    /// a supported filesystem (folders are processed as a whole).
+    #[serde(default)]
+    pub partitions: Vec<PartitionSpec>,
+    /// Operations for this evidence only, run after the shared ones.
+    #[serde(default)]
+    pub operations: Vec<Operation>,
+}
+
+#[derive(Debug, Clone, Deserialize)]
+pub struct PartitionSpec {
+    /// Start of the filesystem, in bytes.
+    pub offset: u64,
+    /// Size of the filesystem, in sectors.
+    pub size: u64,
+    pub label: Option<String>,
+}
+
+#[derive(Debug, Clone, Deserialize)]
+pub struct Operation {
+    pub name: String,
+    /// Arguments following the evidence options, e.g. `["--enum", "--hash", "sha256"]`.
+    pub args: Vec<String>,
+}
+
+/// Outcome of one operation on one partition.
+#[derive(Debug, Clone, Serialize)]
+pub struct JobResult {
+    pub evidence: String,
+    pub partition: String,
+    pub operation: String,
+    pub args: Vec<String>,
+    pub directory: String,
+    pub stdout: String,
+    pub success: bool,
+    pub exit_code: Option<i32>,
+    /// ERROR and WARN lines logged by the job.
+    pub errors: u64,
+    pub warnings: u64,
+    pub duration_secs: f64,
+}
+
+#[derive(Debug, Clone, Serialize)]
+pub struct CaseSummary {
+    pub name: String,
+    pub started: String,
+    pub finished: String,
+    pub jobs: u64,
+    pub succeeded: u64,
+    pub failed: u64,
+    /// Evidence or partitions that could not be processed at all.
+    pub problems: Vec<String>,
+    pub results: Vec<JobResult>,
+}
+
+impl CaseManifest {
+    pub fn load(path: &str) -> Result<Self, Box<dyn Error>> {
+        let text = fs::read_to_string(path)
+            .map_err(|e| format!("could not read case manifest {}: {}", path, e))?;
+        let manifest: Self = if path.ends_with(".toml") {
+            toml::from_str(&text).map_err(|e| format!("invalid case manifest {}: {}", path, e))?
+        } else {
+            serde_yaml::from_str(&text)
+                .map_err(|e| format!("invalid case manifest {}: {}", path, e))?
+        };
+        if manifest.evidence.is_empty() {
+            return Err(format!("case manifest {} lists no evidence", path).into());
+        }
+        Ok(manifest)
+    }
+}
+
+/// A partition to run the operations on, `None` for a folder.
+type Target = (String, Option<(u64, u64)>);
+
+/// Partitions of `evidence`: the listed ones, or the detected ones of its partition table.
+fn targets(evidence: &EvidenceSpec) -> Result<Vec<Target>, Box<dyn Error>> {
+    if Path::new(&evidence.path).is_dir() {
+        return Ok(vec![("folder".to_string(), None)]);
+    }
+    if !evidence.partitions.is_empty() {
+        return Ok(evidence
+            .partitions
+            .iter()
+            .map(|p| {
+                let label = p
+                    .label
+                    .clone()
+                    .unwrap_or_else(|| format!("{:#x}", p.offset));
+                (label, Some((p.offset, p.size)))
+            })
+            .collect());
+    }
+    let format = evidence.format.as_deref().unwrap_or("auto");
+    let mut body = Body::new(evidence.path.clone(), format);
+    let sector_size = detect_sector_size(&mut body).unwrap_or(body.get_sector_size() as u64);
+    let (_, entries) = read_partition_table(&mut body, sector_size)?;
+    Ok(entries
+        .iter()
+        .filter(|e| e.type_name != "Extended")
+        .filter(|e| detect_filesystem(&body, e.start_offset, e.size, None).is_ok())
+        .map(|e| {
+            (
+                format!("p{}", e.index),
+                Some((e.start_offset, e.sector_count)),
+            )
+        })
+        .collect())
+}
+
+/// Runs the jobs of a case and appends their output to the consolidated log.
+struct Runner<'a> {
+    executable: PathBuf,
+    /// `--config` / `--case` forwarded to every job.
+    forwarded: &'a [String],
+    log: StdFile,
+}
+
+impl Runner<'_> {
+    fn log_line(&mut self, line: &str) {
+        if let Err(e) = writeln!(self.log, "{}", line) {
+            warn!("Could not write to the case log: {}", e);
+        }
+    }
+
+    fn run(
+        &mut self,
+        evidence: &EvidenceSpec,
+        (label, partition): &Target,
+        operation: &Operation,
+        directory: &Path,
+    ) -> Result<JobResult, Box<dyn Error>> {
+        fs::create_dir_all(directory)?;
+        // Jobs run from their output directory, so relative evidence paths are resolved first.
+        let path = fs::canonicalize(&evidence.path)
+            .map(|p| p.to_string_lossy().into_owned())
+            .unwrap_or_else(|_| evidence.path.clone());
+        let mut args = vec!["-b".to_string(), path];
+        if let Some(format) = &evidence.format {
+            args.extend(["-f".to_string(), format.clone()]);
+        }
+        if let Some((offset, size)) = partition {
+            args.extend(["-o".to_string(), offset.to_string()]);
+            args.extend(["-s".to_string(), size.to_string()]);
+        }
+        args.extend(self.forwarded.iter().cloned());
+        args.extend(operation.args.iter().cloned());
+
+        let stdout_name = format!("{}.out", operation.name);
+        let prefix = format!("[{}/{}/{}]", evidence.id, label, operation.name);
+        self.log_line(&format!("{} exhume_filesystem {}", prefix, args.join(" ")));
+        let started = Instant::now();
+        let mut child = Command::new(&self.executable)
+            .args(&args)
+            .current_dir(directory)
+            .stdout(StdFile::create(directory.join(&stdout_name))?)
+            .stderr(Stdio::piped())
+            .spawn()?;
+        let (mut errors, mut warnings) = (0, 0);
+        if let Some(stderr) = child.stderr.take() {
+            for line in BufReader::new(stderr).lines() {
+                let line = line?;
+                if line.contains(" ERROR ") {
+                    errors += 1;
+                } else if line.contains(" WARN ") {
+                    warnings += 1;
+                }
+                self.log_line(&format!("{} {}", prefix, line));
+            }
+        }
+        let status = child.wait()?;
+        let success = status.success() && errors == 0;
+        self.log_line(&format!(
+            "{} {} ({})",
+            prefix,
+            if success { "succeeded" } else { "failed" },
+            status
+        ));
+        Ok(JobResult {
+            evidence: evidence.id.clone(),
+            partition: label.clone(),
+            operation: operation.name.clone(),
+            args: operation.args.clone(),
+            directory: directory.to_string_lossy().into_owned(),
+            stdout: stdout_name,
+            success,
+            exit_code: status.code(),
+            errors,
+            warnings,
+            duration_secs: started.elapsed().as_secs_f64(),
+        })
+    }
+}
+
+/// Process every operation of `manifest`, writing `case.log` and `summary.json` into its
+/// output directory.
+pub fn run(manifest: &CaseManifest, forwarded: &[String]) -> Result<CaseSummary, Box<dyn Error>> {
+    fs::create_dir_all(&manifest.output)?;
+    let log_path = manifest.output.join("case.log");
+    let log = OpenOptions::new()
+        .create(true)
+        .append(true)
+        .open(&log_path)
+        .map_err(|e| format!("could not open {}: {}", log_path.display(), e))?;
+    let mut runner = Runner {
+        executable: std::env::current_exe()?,
+        forwarded,
+        log,
+    };
+    let mut summary = CaseSummary {
+        name: manifest.name.clone(),
+        started: jiff::Timestamp::now().to_string(),
+        finished: String::new(),
+        jobs: 0,
+        succeeded: 0,
+        failed: 0,
+        problems: Vec::new(),
+        results: Vec::new(),
+    };
+    runner.log_line(&format!(
+        "== case {} started {}",
+        manifest.name, summary.started
+    ));
+
+    for evidence in &manifest.evidence {
+        let targets = match targets(evidence) {
+            Ok(targets) if targets.is_empty() => {
+                Err("no partition with a supported filesystem".into())
+            }
+            result => result,
+        };
+        let targets = match targets {
+            Ok(targets) => targets,
+            Err(e) => {
+                let problem = format!("{}: {}", evidence.id, e);
+                error!("{}", problem);
+                runner.log_line(&format!("[{}] {}", evidence.id, e));
+                summary.problems.push(problem);
+                continue;
+            }
+        };
+        for target in &targets {
+            let directory = manifest.output.join(&evidence.id).join(&target.0);
+            for operation in manifest.operations.iter().chain(&evidence.operations) {
+                info!("Running {} on {}/{}", operation.name, evidence.id, target.0);
+                summary.jobs += 1;
+                match runner.run(evidence, target, operation, &directory) {
+                    Ok(result) => {
+                        if result.success {
+                            summary.succeeded += 1;
+                        } else {
+                            summary.failed += 1;
+                            warn!(
+                                "{} on {}/{} failed, see case.log",
+                                operation.name, evidence.id, target.0
+                            );
+                        }
+                        summary.results.push(result);
+                    }
+                    Err(e) => {
+                        summary.failed += 1;
+                        let problem =
+                            format!("{}/{}/{}: {}", evidence.id, target.0, operation.name, e);
+                        error!("{}", problem);
+                        summary.problems.push(problem);
+                    }
+                }
+            }
+        }
+    }
+
+    summary.finished = jiff::Timestamp::now().to_string();
+    runner.log_line(&format!(
+        "== case {} finished {}: {} jobs, {} succeeded, {} failed",
+        manifest.name, summary.finished, summary.jobs, summary.succeeded, summary.failed
+    ));
+    fs::write(
+        manifest.output.join("summary.json"),
+        serde_json::to_string_pretty(&summary)?,
+    )?;
+    Ok(summary)
+}
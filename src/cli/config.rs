@@ -0,0 +1,124 @@
+//! CLI defaults loaded from `~/.config/exhume/config.toml` (or `--config`).
+//!
+//! ```toml
+//! output_format = "json"
+//! hash = "md5,sha256"
+//! timezone = "Europe/Paris"
+//! exclude = ["/proc/**", "**/node_modules/**"]
+//!
+//! [cases.acme-2024]
+//! output_dir = "/cases/acme-2024/exports"
+//! hash = "sha256"
+//! ```
+//!
+//! Command line flags always win; a `--case` section overrides the top-level values and
+//! adds its `exclude` globs to them.
+use serde::Deserialize;
+use std::collections::BTreeMap;
+use std::path::{Path, PathBuf};
+
+/// Values that can be set at the top level of the file or per case.
+#[derive(Debug, Clone, Default, Deserialize)]
+pub struct Defaults {
+    /// Default `--output-format` of `--enum`.
+    pub output_format: Option<String>,
+    /// Default `--hash` algorithms.
+    pub hash: Option<String>,
+    /// Path globs skipped by walks, on top of `--exclude`.
+    #[serde(default)]
+    pub exclude: Vec<String>,
+    /// Directory receiving relative output paths (exports, checkpoints, dumps, collections).
+    pub output_dir: Option<PathBuf>,
+    pub timezone: Option<String>,
+    pub time_format: Option<String>,
+    /// Default `--fat-time` policy.
+    pub fat_time: Option<String>,
+    /// Default `--name-rendering`.
+    pub name_rendering: Option<String>,
+    pub log_level: Option<String>,
+}
+
+impl Defaults {
+    fn overlay(mut self, case: &Defaults) -> Self {
+        macro_rules! take {
+            ($($field:ident),*) => {
+                $(if case.$field.is_some() {
+                    self.$field = case.$field.clone();
+                })*
+            };
+        }
+        take!(
+            output_format,
+            hash,
+            output_dir,
+            timezone,
+            time_format,
+            fat_time,
+            name_rendering,
+            log_level
+        );
+        self.exclude.extend(case.exclude.iter().cloned());
+        self
+    }
+
+    /// Place a relative output path below `output_dir`, creating that directory.
+    pub fn output_path(&self, path: &str) -> Result<String, String> {
+        match &self.output_dir {
+            Some(dir) if Path::new(path).is_relative() => {
+                std::fs::create_dir_all(dir).map_err(|e| {
+                    format!("could not create output directory {}: {}", dir.display(), e)
+                })?;
+                Ok(dir.join(path).to_string_lossy().into_owned())
+            }
+            _ => Ok(path.to_string()),
+        }
+    }
+}
+
+#[derive(Debug, Clone, Default, Deserialize)]
+pub struct Config {
+    #[serde(flatten)]
+    pub defaults: Defaults,
+    #[serde(default)]
+    pub cases: BTreeMap<String, Defaults>,
+}
+
+/// `$XDG_CONFIG_HOME/exhume/config.toml`, `~/.config/exhume/config.toml`, or
+/// `%APPDATA%\exhume\config.toml` on Windows.
+fn default_config_path() -> Option<PathBuf> {
+    let base = if cfg!(windows) {
+        std::env::var_os("APPDATA").map(PathBuf::from)
+    } else {
+        std::env::var_os("XDG_CONFIG_HOME")
+            .map(PathBuf::from)
+            .or_else(|| std::env::var_os("HOME").map(|h| PathBuf::from(h).join(".config")))
+    };
+    base.map(|b| b.join("exhume").join("config.toml"))
+}
+
+impl Config {
+    /// Load `explicit`, or the default location when it exists.
+    pub fn load(explicit: Option<&str>) -> Result<Self, String> {
+        let path = match explicit {
+            Some(path) => PathBuf::from(path),
+            None => match default_config_path() {
+                Some(path) if path.is_file() => path,
+                _ => return Ok(Self::default()),
+            },
+        };
+        let text = std::fs::read_to_string(&path)
+            .map_err(|e| format!("could not read config file {}: {}", path.display(), e))?;
+        toml::from_str(&text).map_err(|e| format!("invalid config file {}: {}", path.display(), e))
+    }
+
+    /// Effective defaults for `case` (the top-level values when `None`).
+    pub fn resolve(&self, case: Option<&str>) -> Result<Defaults, String> {
+        match case {
+            None => Ok(self.defaults.clone()),
+            Some(name) => match self.cases.get(name) {
+                Some(section) => Ok(self.defaults.clone().overlay(section)),
+                None => Err(format!("case '{}' is not defined in the config file", name)),
+            },
+        }
+    }
+}
@@ -0,0 +1,47 @@
+//! Logger setup: human readable lines on stderr by default, optionally redirected to a
+//! file (`--log-file`) and/or emitted as one JSON object per line (`--log-format json`).
+use log::LevelFilter;
+use serde_json::json;
+use std::fs::OpenOptions;
+use std::io::{self, BufWriter, Write};
+
+/// Install the global logger. Log files are appended to, so resumed runs keep the history
+/// of the interrupted ones.
+pub fn init(level: LevelFilter, file: Option<&str>, json: bool) -> io::Result<()> {
+    let mut builder = env_logger::Builder::new();
+    builder.filter_level(level);
+    if let Some(path) = file {
+        let file = OpenOptions::new().create(true).append(true).open(path)?;
+        builder.target(env_logger::Target::Pipe(Box::new(LineFlush(
+            BufWriter::new(file),
+        ))));
+    }
+    if json {
+        builder.format(|buf, record| {
+            let line = json!({
+                "timestamp": jiff::Timestamp::now().to_string(),
+                "level": record.level().as_str(),
+                "target": record.target(),
+                "message": record.args().to_string(),
+            });
+            writeln!(buf, "{}", line)
+        });
+    }
+    builder.init();
+    Ok(())
+}
+
+/// Flushes after each record so the file is complete if the process is killed.
+struct LineFlush<W: Write>(W);
+
+impl<W: Write> Write for LineFlush<W> {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        let n = self.0.write(buf)?;
+        self.0.flush()?;
+        Ok(n)
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        self.0.flush()
+    }
+}
@@ -0,0 +1,8 @@
+//! Command line front-ends that only make sense for the `exhume_filesystem` binary.
+pub mod batch;
+pub mod config;
+pub mod logging;
+pub mod shell;
+pub mod tsk;
+#[cfg(feature = "tui")]
+pub mod tui;
@@ -0,0 +1,476 @@
+use exhume_filesystem::Filesystem;
+use exhume_filesystem::filesystem::{DirectoryCommon, FileCommon, FsFileReadSeek};
+use exhume_filesystem::hashing::{HashAlgorithm, copy_and_hash, hash_reader, parse_hash_list};
+use exhume_filesystem::hexdump::hexdump;
+use exhume_filesystem::names::{name_matches, split_path};
+use rustyline::completion::{Completer, Pair};
+use rustyline::error::ReadlineError;
+use rustyline::highlight::Highlighter;
+use rustyline::hint::Hinter;
+use rustyline::validate::Validator;
+use rustyline::{Context, Editor, Helper};
+use std::cell::RefCell;
+use std::error::Error;
+use std::fs::File as StdFile;
+use std::io::{self, BufWriter, IsTerminal, Read, Seek, SeekFrom, Write};
+use std::ops::Range;
+use std::rc::Rc;
+
+const HELP: &str = "\
+Commands:
+  ls [-l] [path]            list a directory (default: current directory)
+  cd [path]                 change the current directory ('..' and '/' supported)
+  pwd                       print the current directory
+  stat <path>               print the record metadata
+  cat <path>                print the record content (a hexdump of binary content,
+                            unless redirected)
+  hash <path> [algs]        hash the record content (default: md5,sha1,sha256)
+  dump <path> [dest]        write the record content into a local file
+  help                      show this help
+  exit | quit               leave the shell
+
+Paths are absolute ('/a/b') or relative to the current directory.
+'#<id>' refers to a record by its identifier.
+Quote names with spaces ('a b' or \"a b\") or escape them (a\\ b).";
+
+/// Bytes of content looked at by `cat` to tell text from binary.
+const TEXT_PROBE: u64 = 64 * 1024;
+
+/// A directory on the current navigation path: (record identifier, name).
+type PathEntry = (u64, String);
+
+struct ShellState<F: Filesystem> {
+    fs: F,
+    separator: String,
+    root_id: u64,
+    cwd: Vec<PathEntry>,
+}
+
+impl<F: Filesystem> ShellState<F> {
+    fn new(fs: F) -> Self {
+        let separator = fs.path_separator();
+        let root_id = fs.get_root_file_id();
+        Self {
+            fs,
+            separator,
+            root_id,
+            cwd: Vec::new(),
+        }
+    }
+
+    fn current_id(cwd: &[PathEntry], root_id: u64) -> u64 {
+        cwd.last().map(|(id, _)| *id).unwrap_or(root_id)
+    }
+
+    fn path_string(&self, path: &[PathEntry]) -> String {
+        let names: Vec<&str> = path.iter().map(|(_, n)| n.as_str()).collect();
+        format!("{}{}", self.separator, names.join(&self.separator))
+    }
+
+    /// Resolve a shell path into the chain of records leading to it.
+    fn resolve(&mut self, path: &str) -> Result<Vec<PathEntry>, Box<dyn Error>> {
+        if let Some(id) = path.strip_prefix('#') {
+            let id = clap_num::maybe_hex::<u64>(id).map_err(|e| format!("bad record id: {e}"))?;
+            self.fs.get_file(id)?;
+            return Ok(vec![(id, format!("#{}", id))]);
+        }
+
+        let mut resolved = if path.starts_with(['/', '\\']) {
+            Vec::new()
+        } else {
+            self.cwd.clone()
+        };
+        let separator = self.fs.path_separator();
+        for component in split_path(path, &separator) {
+            match component {
+                "." => {}
+                ".." => {
+                    resolved.pop();
+                }
+                name => {
+                    let dir_id = Self::current_id(&resolved, self.root_id);
+                    let dir = self.fs.get_file(dir_id)?;
+                    if !dir.is_dir() {
+                        return Err(
+                            format!("not a directory: {}", self.path_string(&resolved)).into()
+                        );
+                    }
+                    let entry = self
+                        .fs
+                        .list_dir(&dir)?
+                        .into_iter()
+                        .find(|e| name_matches(e.name(), name))
+                        .ok_or_else(|| format!("no such file or directory: {}", name))?;
+                    resolved.push((entry.file_id(), entry.name().to_string()));
+                }
+            }
+        }
+        Ok(resolved)
+    }
+
+    fn resolve_record(&mut self, path: &str) -> Result<(u64, F::FileType), Box<dyn Error>> {
+        let resolved = self.resolve(path)?;
+        let id = Self::current_id(&resolved, self.root_id);
+        let file = self.fs.get_file(id)?;
+        Ok((id, file))
+    }
+
+    fn ls(&mut self, args: &[&str]) -> Result<(), Box<dyn Error>> {
+        let long = args.contains(&"-l");
+        let target = args
+            .iter()
+            .find(|a| !a.starts_with('-'))
+            .copied()
+            .unwrap_or(".");
+        let resolved = self.resolve(target)?;
+        let dir_id = Self::current_id(&resolved, self.root_id);
+        let dir = self.fs.get_file(dir_id)?;
+        if !dir.is_dir() {
+            return Err(format!("not a directory: {}", target).into());
+        }
+        let base = self.path_string(&resolved);
+        let mut entries = self.fs.list_dir(&dir)?;
+        entries.sort_by(|a, b| a.name().cmp(b.name()));
+        for entry in entries {
+            if !long {
+                println!("[{}] {}", entry.file_id(), entry.name());
+                continue;
+            }
+            match self.fs.get_file(entry.file_id()) {
+                Ok(record) => {
+                    let child_path = if base == self.separator {
+                        format!("{}{}", base, entry.name())
+                    } else {
+                        format!("{}{}{}", base, self.separator, entry.name())
+                    };
+                    let file = self
+                        .fs
+                        .record_to_file(&record, entry.file_id(), &child_path);
+                    println!("{}", exhume_filesystem::export::text_line(&file));
+                }
+                Err(e) => println!("[{}] {} (unreadable: {})", entry.file_id(), entry.name(), e),
+            }
+        }
+        Ok(())
+    }
+
+    fn cd(&mut self, args: &[&str]) -> Result<(), Box<dyn Error>> {
+        let target = args.first().copied().unwrap_or("/");
+        let resolved = self.resolve(target)?;
+        let id = Self::current_id(&resolved, self.root_id);
+        if !self.fs.get_file(id)?.is_dir() {
+            return Err(format!("not a directory: {}", target).into());
+        }
+        self.cwd = resolved;
+        Ok(())
+    }
+
+    fn stat(&mut self, args: &[&str]) -> Result<(), Box<dyn Error>> {
+        let target = args.first().ok_or("usage: stat <path>")?;
+        let (_, file) = self.resolve_record(target)?;
+        println!("{}", file.to_string());
+        Ok(())
+    }
+
+    fn cat(&mut self, args: &[&str]) -> Result<(), Box<dyn Error>> {
+        let target = args.first().ok_or("usage: cat <path>")?;
+        let (_, file) = self.resolve_record(target)?;
+        if file.is_dir() {
+            return Err(format!("is a directory: {}", target).into());
+        }
+        let mut reader = FsFileReadSeek::new(&mut self.fs, file);
+        let mut out = io::stdout().lock();
+        // Binary content would garble the terminal; redirected, it is written as is.
+        if out.is_terminal() {
+            let mut prefix = Vec::new();
+            (&mut reader).take(TEXT_PROBE).read_to_end(&mut prefix)?;
+            reader.seek(SeekFrom::Start(0))?;
+            if !is_text(&prefix) {
+                hexdump(&mut reader, &mut out, 0, None)?;
+                return Ok(());
+            }
+        }
+        io::copy(&mut reader, &mut out)?;
+        writeln!(out)?;
+        Ok(())
+    }
+
+    fn hash(&mut self, args: &[&str]) -> Result<(), Box<dyn Error>> {
+        let target = args.first().ok_or("usage: hash <path> [algs]")?;
+        let algorithms = match args.get(1) {
+            Some(list) => parse_hash_list(list)?,
+            None => vec![
+                HashAlgorithm::Md5,
+                HashAlgorithm::Sha1,
+                HashAlgorithm::Sha256,
+            ],
+        };
+        let (_, file) = self.resolve_record(target)?;
+        if file.is_dir() {
+            return Err(format!("is a directory: {}", target).into());
+        }
+        let mut reader = FsFileReadSeek::new(&mut self.fs, file);
+        let (hashes, size) = hash_reader(&mut reader, &algorithms)?;
+        println!("size: {}", size);
+        for (name, digest) in [
+            ("md5", &hashes.md5),
+            ("sha1", &hashes.sha1),
+            ("sha256", &hashes.sha256),
+        ] {
+            if let Some(digest) = digest {
+                println!("{}: {}", name, digest);
+            }
+        }
+        Ok(())
+    }
+
+    fn dump(&mut self, args: &[&str]) -> Result<(), Box<dyn Error>> {
+        let target = args.first().ok_or("usage: dump <path> [dest]")?;
+        let (id, file) = self.resolve_record(target)?;
+        if file.is_dir() {
+            return Err(format!("is a directory: {}", target).into());
+        }
+        let dest = match args.get(1) {
+            Some(dest) => dest.to_string(),
+            None => format!("file_{}.bin", id),
+        };
+        let mut reader = FsFileReadSeek::new(&mut self.fs, file);
+        let mut out = BufWriter::new(StdFile::create(&dest)?);
+        let (hashes, written) = copy_and_hash(&mut reader, &mut out, &[HashAlgorithm::Sha256])?;
+        out.flush()?;
+        println!(
+            "wrote {} bytes into '{}' (sha256 {})",
+            written,
+            dest,
+            hashes.sha256.unwrap_or_default()
+        );
+        Ok(())
+    }
+
+    /// Complete the last path component of `word` against the directory listing.
+    fn complete_path(&mut self, word: &str) -> Vec<Pair> {
+        let (dir_part, prefix) = match word.rfind(['/', '\\']) {
+            Some(i) => (&word[..=i], &word[i + 1..]),
+            None => ("", word),
+        };
+        let Ok(resolved) = self.resolve(if dir_part.is_empty() { "." } else { dir_part }) else {
+            return Vec::new();
+        };
+        let dir_id = Self::current_id(&resolved, self.root_id);
+        let Ok(dir) = self.fs.get_file(dir_id) else {
+            return Vec::new();
+        };
+        let Ok(entries) = self.fs.list_dir(&dir) else {
+            return Vec::new();
+        };
+
+        let mut candidates: Vec<Pair> = Vec::new();
+        for entry in entries {
+            let name = entry.name();
+            if !name.starts_with(prefix) || name == "." || name == ".." {
+                continue;
+            }
+            let is_dir = self
+                .fs
+                .get_file(entry.file_id())
+                .map(|f| f.is_dir())
+                .unwrap_or(false);
+            let suffix = if is_dir { "/" } else { "" };
+            candidates.push(Pair {
+                display: format!("{}{}", name, suffix),
+                replacement: format!("{}{}{}", dir_part, name, suffix),
+            });
+        }
+        candidates.sort_by(|a, b| a.display.cmp(&b.display));
+        candidates
+    }
+}
+
+/// Whether `prefix` is UTF-8 without NUL bytes; a character cut at its end is accepted.
+fn is_text(prefix: &[u8]) -> bool {
+    !prefix.contains(&0)
+        && match std::str::from_utf8(prefix) {
+            Ok(_) => true,
+            Err(e) => e.error_len().is_none(),
+        }
+}
+
+/// Whether a backslash before `c` escapes it. Other backslashes are kept, so that
+/// Windows paths are typed as usual.
+fn escapable(quote: Option<char>, c: char) -> bool {
+    matches!(c, '"' | '\\') || (quote.is_none() && (c == '\'' || c.is_whitespace()))
+}
+
+/// Split a command line into words, each with the bytes of the line it spans, like a
+/// shell does: single quotes keep everything, double quotes keep all but escaped `"`
+/// and `\`. Also returns the quote left open at the end of the line, if any.
+fn lex(line: &str) -> (Vec<(Range<usize>, String)>, Option<char>) {
+    let mut words = Vec::new();
+    let mut current: Option<(usize, String)> = None;
+    let mut quote = None;
+    let mut chars = line.char_indices().peekable();
+    while let Some((i, c)) = chars.next() {
+        if quote.is_none() && c.is_whitespace() {
+            if let Some((start, word)) = current.take() {
+                words.push((start..i, word));
+            }
+            continue;
+        }
+        let word = &mut current.get_or_insert_with(|| (i, String::new())).1;
+        match (quote, c) {
+            (Some(q), c) if c == q => quote = None,
+            (None, '\'' | '"') => quote = Some(c),
+            (Some('\''), c) => word.push(c),
+            (_, '\\') => match chars.peek() {
+                Some(&(_, next)) if escapable(quote, next) => {
+                    word.push(next);
+                    chars.next();
+                }
+                _ => word.push('\\'),
+            },
+            (_, c) => word.push(c),
+        }
+    }
+    if let Some((start, word)) = current {
+        words.push((start..line.len(), word));
+    }
+    (words, quote)
+}
+
+fn split_words(line: &str) -> Result<Vec<String>, String> {
+    match lex(line) {
+        (_, Some(quote)) => Err(format!("unterminated {} quote", quote)),
+        (words, None) => Ok(words.into_iter().map(|(_, word)| word).collect()),
+    }
+}
+
+/// Escape `word` so that `lex` reads it back as a single word.
+fn escape_word(word: &str) -> String {
+    let mut escaped = String::with_capacity(word.len());
+    let mut chars = word.chars().peekable();
+    while let Some(c) = chars.next() {
+        let escape = match c {
+            '\\' => chars.peek().is_some_and(|&next| escapable(None, next)),
+            c => escapable(None, c),
+        };
+        if escape {
+            escaped.push('\\');
+        }
+        escaped.push(c);
+    }
+    escaped
+}
+
+struct ShellHelper<F: Filesystem> {
+    state: Rc<RefCell<ShellState<F>>>,
+}
+
+impl<F: Filesystem> Completer for ShellHelper<F> {
+    type Candidate = Pair;
+
+    fn complete(
+        &self,
+        line: &str,
+        pos: usize,
+        _ctx: &Context<'_>,
+    ) -> rustyline::Result<(usize, Vec<Pair>)> {
+        let (mut words, _) = lex(&line[..pos]);
+        // The word under the cursor, or a new one after whitespace.
+        let (start, word) = match words.last() {
+            Some((range, _)) if range.end == pos => {
+                let (range, word) = words.pop().unwrap_or_default();
+                (range.start, word)
+            }
+            _ => (pos, String::new()),
+        };
+        // Only complete arguments, not the command itself.
+        if words.is_empty() {
+            return Ok((start, Vec::new()));
+        }
+        let Ok(mut state) = self.state.try_borrow_mut() else {
+            return Ok((start, Vec::new()));
+        };
+        let mut candidates = state.complete_path(&word);
+        for candidate in &mut candidates {
+            candidate.replacement = escape_word(&candidate.replacement);
+        }
+        Ok((start, candidates))
+    }
+}
+
+impl<F: Filesystem> Hinter for ShellHelper<F> {
+    type Hint = String;
+}
+
+impl<F: Filesystem> Highlighter for ShellHelper<F> {}
+
+impl<F: Filesystem> Validator for ShellHelper<F> {}
+
+impl<F: Filesystem> Helper for ShellHelper<F> {}
+
+/// Run the interactive evidence browser until `exit` or end of input.
+pub fn run<F: Filesystem>(fs: F) -> Result<(), Box<dyn Error>> {
+    let state = Rc::new(RefCell::new(ShellState::new(fs)));
+    let mut editor: Editor<ShellHelper<F>, _> = Editor::new()?;
+    editor.set_helper(Some(ShellHelper {
+        state: state.clone(),
+    }));
+
+    println!(
+        "exhume shell on a {} filesystem. Type 'help' for the list of commands.",
+        state.borrow().fs.filesystem_type()
+    );
+
+    loop {
+        let prompt = {
+            let st = state.borrow();
+            format!("exhume:{}> ", st.path_string(&st.cwd))
+        };
+        let line = match editor.readline(&prompt) {
+            Ok(line) => line,
+            Err(ReadlineError::Interrupted) => continue,
+            Err(ReadlineError::Eof) => break,
+            Err(e) => return Err(e.into()),
+        };
+        let line = line.trim();
+        if line.is_empty() {
+            continue;
+        }
+        let _ = editor.add_history_entry(line);
+
+        let words = match split_words(line) {
+            Ok(words) => words,
+            Err(e) => {
+                eprintln!("{}", e);
+                continue;
+            }
+        };
+        let Some((command, args)) = words.split_first() else {
+            continue;
+        };
+        let args: Vec<&str> = args.iter().map(String::as_str).collect();
+        let mut st = state.borrow_mut();
+        let result = match command.as_str() {
+            "ls" | "dir" => st.ls(&args),
+            "cd" => st.cd(&args),
+            "pwd" => {
+                println!("{}", st.path_string(&st.cwd));
+                Ok(())
+            }
+            "stat" => st.stat(&args),
+            "cat" => st.cat(&args),
+            "hash" => st.hash(&args),
+            "dump" => st.dump(&args),
+            "help" | "?" => {
+                println!("{}", HELP);
+                Ok(())
+            }
+            "exit" | "quit" => break,
+            other => Err(format!("unknown command: {} (try 'help')", other).into()),
+        };
+        if let Err(e) = result {
+            eprintln!("{}: {}", command, e);
+        }
+    }
+    Ok(())
+}
@@ -0,0 +1,278 @@
+//! Sleuth Kit style `fls`, `icat`, `istat`, `ffind` and `ifind` front-ends, so existing scripts keep
+//! working on every backend supported by this crate.
+use exhume_filesystem::detected_fs::DetectedFs;
+use exhume_filesystem::export::bodyfile_line;
+use exhume_filesystem::filesystem::{DirectoryCommon, FileCommon, FsFileReadSeek};
+use exhume_filesystem::ntfs_impl::find_object_id;
+use exhume_filesystem::reverse::{find_block_owners, find_names, find_path_id};
+use exhume_filesystem::timefmt::format_timestamp;
+use exhume_filesystem::{File, Filesystem};
+use std::collections::HashSet;
+use std::error::Error;
+use std::io::{self, Read, Seek, Write};
+
+/// Flags of the `fls` subcommand (same letters as TSK).
+#[derive(Default)]
+pub struct FlsOptions {
+    /// `-r`: recurse into directories.
+    pub recursive: bool,
+    /// `-p`: print the full path instead of the name.
+    pub full_path: bool,
+    /// `-l`: long listing with times, size and ownership.
+    pub long: bool,
+    /// `-m <mnt>`: body file output, with paths prefixed by the mount point.
+    pub mactime: Option<String>,
+    /// `-D`: directories only.
+    pub dirs_only: bool,
+    /// `-F`: files only.
+    pub files_only: bool,
+}
+
+/// The TSK one-letter type of a normalized `ftype`.
+fn type_letter(ftype: &str) -> char {
+    match ftype.to_ascii_lowercase().as_str() {
+        "dir" | "directory" => 'd',
+        "file" | "regular" => 'r',
+        "symlink" | "link" => 'l',
+        "char" | "chardev" => 'c',
+        "block" | "blockdev" => 'b',
+        "fifo" => 'p',
+        "socket" => 's',
+        _ => '-',
+    }
+}
+
+fn tsk_time(ts: Option<u64>) -> String {
+    match ts {
+        Some(ts) if ts > 0 => format_timestamp(ts),
+        _ => "0000-00-00 00:00:00 (UTC)".to_string(),
+    }
+}
+
+fn fls_line(file: &File, depth: usize, options: &FlsOptions, separator: &str) -> String {
+    let t = type_letter(&file.ftype);
+    let mut line = String::new();
+    if depth > 0 {
+        line.push_str(&"+".repeat(depth));
+        line.push(' ');
+    }
+    let name = if options.full_path {
+        file.absolute_path.trim_start_matches(separator)
+    } else {
+        file.name.as_str()
+    };
+    line.push_str(&format!("{}/{} {}:\t{}", t, t, file.identifier, name));
+    if options.long {
+        line.push_str(&format!(
+            "\t{}\t{}\t{}\t{}\t{}\t{}\t{}",
+            tsk_time(file.modified),
+            tsk_time(file.accessed),
+            tsk_time(file.changed),
+            tsk_time(file.created),
+            file.size,
+            file.owner.as_deref().unwrap_or("0"),
+            file.group.as_deref().unwrap_or("0"),
+        ));
+    }
+    line
+}
+
+fn join_path(parent: &str, name: &str, separator: &str) -> String {
+    if parent == separator {
+        format!("{}{}", separator, name)
+    } else {
+        format!("{}{}{}", parent, separator, name)
+    }
+}
+
+/// Recursive state of an `fls` run.
+struct Lister<'a, W: Write> {
+    options: &'a FlsOptions,
+    separator: String,
+    seen: HashSet<u64>,
+    out: W,
+}
+
+impl<W: Write> Lister<'_, W> {
+    fn list<F: Filesystem>(
+        &mut self,
+        fs: &mut F,
+        dir: &F::FileType,
+        path: &str,
+        depth: usize,
+    ) -> Result<(), Box<dyn Error>> {
+        for entry in fs.list_dir(dir)? {
+            if entry.name() == "." || entry.name() == ".." {
+                continue;
+            }
+            let child_id = entry.file_id();
+            let child = match fs.get_file(child_id) {
+                Ok(child) => child,
+                Err(_) => continue,
+            };
+            let child_path = join_path(path, entry.name(), &self.separator);
+            let is_dir = child.is_dir();
+            let mut file = fs.record_to_file(&child, child_id, &child_path);
+            file.name = entry.name().to_string();
+
+            let options = self.options;
+            let shown = !(options.dirs_only && !is_dir || options.files_only && is_dir);
+            if shown {
+                match &options.mactime {
+                    Some(mount) => {
+                        file.absolute_path = format!(
+                            "{}{}",
+                            mount.trim_end_matches(self.separator.as_str()),
+                            child_path
+                        );
+                        writeln!(self.out, "{}", bodyfile_line(&file))?;
+                    }
+                    None => writeln!(
+                        self.out,
+                        "{}",
+                        fls_line(&file, depth, options, &self.separator)
+                    )?,
+                }
+            }
+
+            if options.recursive && is_dir && self.seen.insert(child_id) {
+                self.list(fs, &child, &child_path, depth + 1)?;
+            }
+        }
+        Ok(())
+    }
+}
+
+/// List the entries of a directory (the root when `dir_id` is `None`).
+pub fn fls<F: Filesystem>(
+    fs: &mut F,
+    dir_id: Option<u64>,
+    options: &FlsOptions,
+) -> Result<(), Box<dyn Error>> {
+    let separator = fs.path_separator();
+    let dir_id = dir_id.unwrap_or_else(|| fs.get_root_file_id());
+    let dir = fs.get_file(dir_id)?;
+    if !dir.is_dir() {
+        return Err(format!("record {} is not a directory", dir_id).into());
+    }
+    let mut lister = Lister {
+        options,
+        separator: separator.clone(),
+        seen: HashSet::from([dir_id]),
+        out: io::stdout().lock(),
+    };
+    lister.list(fs, &dir, &separator, 0)?;
+    lister.out.flush()?;
+    Ok(())
+}
+
+/// Write the content of a record to STDOUT, the raw entry blocks of directories.
+pub fn icat<F: Filesystem>(fs: &mut F, file_id: u64) -> Result<(), Box<dyn Error>> {
+    let record = fs.get_file(file_id)?;
+    if record.is_dir() {
+        let data = fs.read_directory_data(&record)?.ok_or_else(|| {
+            format!(
+                "{} directories have no data of their own",
+                fs.filesystem_type()
+            )
+        })?;
+        let mut out = io::stdout().lock();
+        out.write_all(&data)?;
+        out.flush()?;
+        return Ok(());
+    }
+    let mut reader = FsFileReadSeek::new(fs, record);
+    let mut out = io::stdout().lock();
+    io::copy(&mut reader, &mut out)?;
+    out.flush()?;
+    Ok(())
+}
+
+/// Write the value of the extended attribute `name` of a record to STDOUT.
+pub fn icat_xattr<F: Filesystem>(
+    fs: &mut F,
+    file_id: u64,
+    name: &str,
+) -> Result<(), Box<dyn Error>> {
+    let record = fs.get_file(file_id)?;
+    let (_, value) = fs
+        .extended_attributes(&record)?
+        .into_iter()
+        .find(|(n, _)| n == name)
+        .ok_or_else(|| format!("record {} has no extended attribute {}", file_id, name))?;
+    let mut out = io::stdout().lock();
+    out.write_all(&value)?;
+    out.flush()?;
+    Ok(())
+}
+
+/// Print the metadata of a record, normalized fields first and the backend
+/// specific details after.
+pub fn istat<F: Filesystem>(fs: &mut F, file_id: u64) -> Result<(), Box<dyn Error>> {
+    let record = fs.get_file(file_id)?;
+    let file = fs.record_to_file(&record, file_id, "");
+    let mut out = io::stdout().lock();
+    writeln!(out, "inode: {}", file.identifier)?;
+    writeln!(out, "Type: {}", file.ftype)?;
+    writeln!(out, "Mode: {}", file.permissions.as_deref().unwrap_or("-"))?;
+    writeln!(
+        out,
+        "uid / gid: {} / {}",
+        file.owner.as_deref().unwrap_or("-"),
+        file.group.as_deref().unwrap_or("-")
+    )?;
+    writeln!(out, "size: {}", file.size)?;
+    writeln!(out)?;
+    writeln!(out, "Times:")?;
+    writeln!(out, "Created:\t{}", tsk_time(file.created))?;
+    writeln!(out, "File Modified:\t{}", tsk_time(file.modified))?;
+    writeln!(out, "Accessed:\t{}", tsk_time(file.accessed))?;
+    writeln!(out, "Changed:\t{}", tsk_time(file.changed))?;
+    writeln!(out)?;
+    writeln!(out, "{}", FileCommon::to_string(&record))?;
+    Ok(())
+}
+
+/// Print every name referencing a record.
+pub fn ffind<F: Filesystem>(fs: &mut F, file_id: u64) -> Result<(), Box<dyn Error>> {
+    let names = find_names(fs, file_id)?;
+    if names.is_empty() {
+        println!("File name not found for inode");
+    }
+    for name in names {
+        println!("{}", name);
+    }
+    Ok(())
+}
+
+/// Print the record owning a block.
+pub fn ifind_block<F: Filesystem>(fs: &mut F, block: u64) -> Result<(), Box<dyn Error>> {
+    let owners = find_block_owners(fs, block)?;
+    if owners.is_empty() {
+        println!("Block {} is not used by any record", block);
+    }
+    for (id, path) in owners {
+        println!("{}\t{}", id, path);
+    }
+    Ok(())
+}
+
+/// Print the record identifier of a path.
+pub fn ifind_path<F: Filesystem>(fs: &mut F, path: &str) -> Result<(), Box<dyn Error>> {
+    println!("{}", find_path_id(fs, path)?);
+    Ok(())
+}
+
+/// Print the record carrying an NTFS object identifier, looked up in `$Extend\$ObjId`.
+pub fn ifind_object_id<T: Read + Seek>(
+    fs: &mut DetectedFs<T>,
+    object_id: &str,
+) -> Result<(), Box<dyn Error>> {
+    let DetectedFs::Ntfs(ntfs) = fs else {
+        return Err("object identifiers are only indexed on NTFS".into());
+    };
+    let entry = find_object_id(ntfs, object_id)?
+        .ok_or_else(|| format!("no record has the object identifier {}", object_id))?;
+    println!("{}", entry.record);
+    Ok(())
+}
@@ -0,0 +1,326 @@
+use exhume_filesystem::Filesystem;
+use exhume_filesystem::export::{ExportFormat, Exporter};
+use exhume_filesystem::filesystem::{DirectoryCommon, FileCommon, FsFileReadSeek};
+use exhume_filesystem::hashing::{HashAlgorithm, copy_and_hash, hash_reader};
+use exhume_filesystem::hexdump::hexdump_line;
+use ratatui::crossterm::event::{self, Event, KeyCode, KeyEventKind};
+use ratatui::layout::{Constraint, Layout};
+use ratatui::style::{Modifier, Style};
+use ratatui::text::Line;
+use ratatui::widgets::{Block, Borders, List, ListItem, ListState, Paragraph, Wrap};
+use ratatui::{DefaultTerminal, Frame};
+use std::error::Error;
+use std::fs::File as StdFile;
+use std::io::{BufWriter, Write};
+
+const PREVIEW_BYTES: usize = 512;
+const KEYS: &str = "↑/↓ move  →/Enter open  ←/Backspace up  d dump  H hash  e export dir  q quit";
+
+struct Entry {
+    id: u64,
+    name: String,
+    is_dir: bool,
+    size: u64,
+}
+
+struct App<F: Filesystem> {
+    fs: F,
+    separator: String,
+    /// Directories from the root to the current one: (record identifier, name).
+    stack: Vec<(u64, String)>,
+    entries: Vec<Entry>,
+    list: ListState,
+    preview: Vec<String>,
+    status: String,
+}
+
+impl<F: Filesystem> App<F> {
+    fn new(fs: F) -> Self {
+        let separator = fs.path_separator();
+        let root = fs.get_root_file_id();
+        let mut app = Self {
+            fs,
+            separator,
+            stack: vec![(root, String::new())],
+            entries: Vec::new(),
+            list: ListState::default(),
+            preview: Vec::new(),
+            status: String::from(KEYS),
+        };
+        app.load_dir();
+        app
+    }
+
+    fn current_path(&self) -> String {
+        let names: Vec<&str> = self.stack[1..].iter().map(|(_, n)| n.as_str()).collect();
+        format!("{}{}", self.separator, names.join(&self.separator))
+    }
+
+    fn entry_path(&self, entry: &Entry) -> String {
+        let base = self.current_path();
+        if base == self.separator {
+            format!("{}{}", base, entry.name)
+        } else {
+            format!("{}{}{}", base, self.separator, entry.name)
+        }
+    }
+
+    fn load_dir(&mut self) {
+        let (dir_id, _) = self.stack[self.stack.len() - 1];
+        self.entries.clear();
+        let listed = self
+            .fs
+            .get_file(dir_id)
+            .and_then(|dir| self.fs.list_dir(&dir));
+        match listed {
+            Ok(children) => {
+                for child in children {
+                    if child.name() == "." || child.name() == ".." {
+                        continue;
+                    }
+                    let (is_dir, size) = match self.fs.get_file(child.file_id()) {
+                        Ok(f) => (f.is_dir(), f.size()),
+                        Err(_) => (false, 0),
+                    };
+                    self.entries.push(Entry {
+                        id: child.file_id(),
+                        name: child.name().to_string(),
+                        is_dir,
+                        size,
+                    });
+                }
+            }
+            Err(e) => self.status = format!("Cannot list {}: {}", self.current_path(), e),
+        }
+        self.entries
+            .sort_by(|a, b| b.is_dir.cmp(&a.is_dir).then_with(|| a.name.cmp(&b.name)));
+        self.list.select(if self.entries.is_empty() {
+            None
+        } else {
+            Some(0)
+        });
+        self.refresh_preview();
+    }
+
+    fn selected(&self) -> Option<&Entry> {
+        self.list.selected().and_then(|i| self.entries.get(i))
+    }
+
+    fn refresh_preview(&mut self) {
+        self.preview.clear();
+        let Some(entry) = self.selected() else {
+            return;
+        };
+        let (id, is_dir) = (entry.id, entry.is_dir);
+        let record = match self.fs.get_file(id) {
+            Ok(r) => r,
+            Err(e) => {
+                self.preview
+                    .push(format!("Cannot read record {}: {}", id, e));
+                return;
+            }
+        };
+        self.preview
+            .extend(FileCommon::to_string(&record).lines().map(str::to_string));
+        if is_dir {
+            return;
+        }
+        self.preview.push(String::new());
+        match self.fs.read_file_prefix(&record, PREVIEW_BYTES) {
+            Ok(prefix) => {
+                for (i, chunk) in prefix.chunks(16).enumerate() {
+                    self.preview.push(hexdump_line((i * 16) as u64, chunk));
+                }
+            }
+            Err(e) => self.preview.push(format!("Cannot read content: {}", e)),
+        }
+    }
+
+    fn move_selection(&mut self, delta: isize) {
+        if self.entries.is_empty() {
+            return;
+        }
+        let current = self.list.selected().unwrap_or(0) as isize;
+        let next = (current + delta).clamp(0, self.entries.len() as isize - 1);
+        self.list.select(Some(next as usize));
+        self.refresh_preview();
+    }
+
+    fn open(&mut self) {
+        if let Some(entry) = self.selected()
+            && entry.is_dir
+        {
+            let step = (entry.id, entry.name.clone());
+            self.stack.push(step);
+            self.load_dir();
+        }
+    }
+
+    fn up(&mut self) {
+        if self.stack.len() > 1 {
+            self.stack.pop();
+            self.load_dir();
+        }
+    }
+
+    fn dump_selected(&mut self) {
+        let Some(entry) = self.selected() else {
+            return;
+        };
+        if entry.is_dir {
+            self.status = "Cannot dump a directory".to_string();
+            return;
+        }
+        let id = entry.id;
+        let dest = format!("file_{}.bin", id);
+        let result = (|| -> Result<(u64, Option<String>), Box<dyn Error>> {
+            let mut reader = FsFileReadSeek::from_id(&mut self.fs, id)?;
+            let mut out = BufWriter::new(StdFile::create(&dest)?);
+            let (hashes, written) = copy_and_hash(&mut reader, &mut out, &[HashAlgorithm::Sha256])?;
+            out.flush()?;
+            Ok((written, hashes.sha256))
+        })();
+        self.status = match result {
+            Ok((written, sha256)) => format!(
+                "Wrote {} bytes into '{}' (sha256 {})",
+                written,
+                dest,
+                sha256.unwrap_or_default()
+            ),
+            Err(e) => format!("Dump failed: {}", e),
+        };
+    }
+
+    fn hash_selected(&mut self) {
+        let Some(entry) = self.selected() else {
+            return;
+        };
+        if entry.is_dir {
+            self.status = "Cannot hash a directory".to_string();
+            return;
+        }
+        let id = entry.id;
+        let result = FsFileReadSeek::from_id(&mut self.fs, id).and_then(|mut reader| {
+            Ok(hash_reader(
+                &mut reader,
+                &[
+                    HashAlgorithm::Md5,
+                    HashAlgorithm::Sha1,
+                    HashAlgorithm::Sha256,
+                ],
+            )?)
+        });
+        match result {
+            Ok((hashes, _)) => {
+                self.preview.push(String::new());
+                self.preview
+                    .push(format!("md5:    {}", hashes.md5.unwrap_or_default()));
+                self.preview
+                    .push(format!("sha1:   {}", hashes.sha1.unwrap_or_default()));
+                self.preview
+                    .push(format!("sha256: {}", hashes.sha256.unwrap_or_default()));
+                self.status = format!("Hashed record {}", id);
+            }
+            Err(e) => self.status = format!("Hash failed: {}", e),
+        }
+    }
+
+    /// Export the records of the current directory as JSON.
+    fn export_dir(&mut self) {
+        let (dir_id, _) = self.stack[self.stack.len() - 1];
+        let dest = format!("export_{}.json", dir_id);
+        let result = (|| -> Result<u64, Box<dyn Error>> {
+            let out = BufWriter::new(StdFile::create(&dest)?);
+            let mut exporter = Exporter::new(out, ExportFormat::Json)?;
+            for entry in &self.entries {
+                let record = self.fs.get_file(entry.id)?;
+                let file = self
+                    .fs
+                    .record_to_file(&record, entry.id, &self.entry_path(entry));
+                exporter.write_file(&file)?;
+            }
+            let count = exporter.count();
+            exporter.finish()?;
+            Ok(count)
+        })();
+        self.status = match result {
+            Ok(count) => format!("Exported {} records into '{}'", count, dest),
+            Err(e) => format!("Export failed: {}", e),
+        };
+    }
+
+    fn draw(&mut self, frame: &mut Frame) {
+        let [main, status] =
+            Layout::vertical([Constraint::Min(3), Constraint::Length(1)]).areas(frame.area());
+        let [left, right] =
+            Layout::horizontal([Constraint::Percentage(40), Constraint::Percentage(60)])
+                .areas(main);
+
+        let items: Vec<ListItem> = self
+            .entries
+            .iter()
+            .map(|e| {
+                let marker = if e.is_dir { "/" } else { "" };
+                ListItem::new(format!("{}{}  [{}] {}", e.name, marker, e.id, e.size))
+            })
+            .collect();
+        let list = List::new(items)
+            .block(
+                Block::default()
+                    .borders(Borders::ALL)
+                    .title(self.current_path()),
+            )
+            .highlight_style(Style::default().add_modifier(Modifier::REVERSED))
+            .highlight_symbol("> ");
+        frame.render_stateful_widget(list, left, &mut self.list);
+
+        let preview: Vec<Line> = self
+            .preview
+            .iter()
+            .map(|l| Line::from(l.as_str()))
+            .collect();
+        let preview = Paragraph::new(preview)
+            .block(Block::default().borders(Borders::ALL).title("Record"))
+            .wrap(Wrap { trim: false });
+        frame.render_widget(preview, right);
+
+        frame.render_widget(Paragraph::new(self.status.as_str()), status);
+    }
+}
+
+fn event_loop<F: Filesystem>(
+    terminal: &mut DefaultTerminal,
+    app: &mut App<F>,
+) -> Result<(), Box<dyn Error>> {
+    loop {
+        terminal.draw(|frame| app.draw(frame))?;
+        let Event::Key(key) = event::read()? else {
+            continue;
+        };
+        if key.kind != KeyEventKind::Press {
+            continue;
+        }
+        match key.code {
+            KeyCode::Char('q') | KeyCode::Esc => return Ok(()),
+            KeyCode::Down | KeyCode::Char('j') => app.move_selection(1),
+            KeyCode::Up | KeyCode::Char('k') => app.move_selection(-1),
+            KeyCode::PageDown => app.move_selection(20),
+            KeyCode::PageUp => app.move_selection(-20),
+            KeyCode::Enter | KeyCode::Right | KeyCode::Char('l') => app.open(),
+            KeyCode::Backspace | KeyCode::Left | KeyCode::Char('h') => app.up(),
+            KeyCode::Char('d') => app.dump_selected(),
+            KeyCode::Char('H') => app.hash_selected(),
+            KeyCode::Char('e') => app.export_dir(),
+            _ => {}
+        }
+    }
+}
+
+/// Run the two-pane evidence browser until the user quits.
+pub fn run<F: Filesystem>(fs: F) -> Result<(), Box<dyn Error>> {
+    let mut app = App::new(fs);
+    let mut terminal = ratatui::init();
+    let result = event_loop(&mut terminal, &mut app);
+    ratatui::restore();
+    result
+}
@@ -0,0 +1,397 @@
+//! Target based collection (KAPE / Velociraptor style): path globs grouped per artifact
+//! are resolved against any backend and the hits are exported with their paths preserved.
+use crate::budget;
+use crate::filesystem::{File, Filesystem, ReadSeek, WalkEvent, WalkOptions};
+use crate::hashing::{FileHashes, HashAlgorithm, MultiHasher, copy_and_hash};
+use crate::search::{ExcludeSet, normalize_path_glob, slash_path};
+use globset::{GlobBuilder, GlobSet, GlobSetBuilder};
+use log::warn;
+use serde::{Deserialize, Serialize};
+use std::error::Error;
+use std::fs::{self, File as StdFile};
+use std::io::{self, BufWriter, Read, Write};
+use std::path::{Path, PathBuf};
+use std::sync::mpsc::{self, SyncSender};
+use std::thread::{self, JoinHandle};
+
+/// Name of the manifest written next to the collected files.
+pub const MANIFEST_NAME: &str = "collection.json";
+
+/// One artifact definition of a targets file.
+///
+/// ```yaml
+/// targets:
+///   - name: RegistryHives
+///     category: registry
+///     paths:
+///       - /Windows/System32/config/{SAM,SECURITY,SOFTWARE,SYSTEM}
+///       - /Users/*/NTUSER.DAT
+///   - name: EventLogs
+///     paths: ['C:\Windows\System32\winevt\Logs\*.evtx']
+/// ```
+///
+/// Paths are globs matched against the whole path from the filesystem root. Both `/` and
+/// `\` are accepted as separators and a leading drive letter is ignored. Matching is case
+/// insensitive unless `case_sensitive` is set.
+#[derive(Debug, Clone, Deserialize)]
+pub struct Target {
+    pub name: String,
+    #[serde(default)]
+    pub category: Option<String>,
+    pub paths: Vec<String>,
+    #[serde(default)]
+    pub case_sensitive: bool,
+}
+
+#[derive(Debug, Clone, Deserialize)]
+struct TargetsFile {
+    targets: Vec<Target>,
+}
+
+/// A set of compiled targets.
+#[derive(Debug, Clone)]
+pub struct TargetSet {
+    targets: Vec<Target>,
+    globs: Vec<GlobSet>,
+}
+
+impl TargetSet {
+    pub fn from_yaml(text: &str) -> Result<Self, Box<dyn Error>> {
+        let file: TargetsFile = serde_yaml::from_str(text)?;
+        let mut globs = Vec::with_capacity(file.targets.len());
+        for target in &file.targets {
+            if target.paths.is_empty() {
+                return Err(format!("target '{}' has no paths", target.name).into());
+            }
+            let mut builder = GlobSetBuilder::new();
+            for pattern in &target.paths {
+                let glob = GlobBuilder::new(&normalize_path_glob(pattern))
+                    .case_insensitive(!target.case_sensitive)
+                    .literal_separator(true)
+                    .build()
+                    .map_err(|e| format!("target '{}': {}", target.name, e))?;
+                builder.add(glob);
+            }
+            globs.push(builder.build()?);
+        }
+        Ok(Self {
+            targets: file.targets,
+            globs,
+        })
+    }
+
+    pub fn load(path: &str) -> Result<Self, Box<dyn Error>> {
+        let text = fs::read_to_string(path)
+            .map_err(|e| format!("could not read targets file '{}': {}", path, e))?;
+        Self::from_yaml(&text)
+    }
+
+    pub fn targets(&self) -> &[Target] {
+        &self.targets
+    }
+
+    /// Names of the targets matching `path` (given with the filesystem `separator`).
+    pub fn matching(&self, path: &str, separator: &str) -> Vec<&str> {
+        let path = slash_path(path, separator);
+        self.targets
+            .iter()
+            .zip(&self.globs)
+            .filter(|(_, glob)| glob.is_match(&path))
+            .map(|(target, _)| target.name.as_str())
+            .collect()
+    }
+}
+
+/// Where collected files are written.
+pub enum CollectSink {
+    /// Recreate the source tree below a directory.
+    Directory(PathBuf),
+    /// Append the files to a tar archive.
+    Tar(Box<tar::Builder<WriteBehind>>),
+}
+
+impl CollectSink {
+    pub fn directory(path: &Path) -> io::Result<Self> {
+        fs::create_dir_all(path)?;
+        Ok(CollectSink::Directory(path.to_path_buf()))
+    }
+
+    pub fn tar(path: &Path) -> io::Result<Self> {
+        let file = StdFile::create(path)?;
+        let builder = tar::Builder::new(WriteBehind::new(file));
+        Ok(CollectSink::Tar(Box::new(builder)))
+    }
+}
+
+/// Bytes buffered before they are handed to the archive writer thread.
+const WRITE_BEHIND_CHUNK: usize = 1024 * 1024;
+/// Chunks queued for the archive writer thread.
+const WRITE_BEHIND_DEPTH: usize = 4;
+
+/// Archive output written on a background thread, so the next extents are read from the
+/// evidence while the previous ones reach the disk.
+pub struct WriteBehind {
+    buffer: Vec<u8>,
+    sender: Option<SyncSender<Vec<u8>>>,
+    writer: Option<JoinHandle<io::Result<StdFile>>>,
+}
+
+impl WriteBehind {
+    fn new(mut file: StdFile) -> Self {
+        let depth = budget::items(
+            WRITE_BEHIND_DEPTH,
+            budget::BUFFER_SHARE,
+            WRITE_BEHIND_CHUNK as u64,
+        );
+        let (sender, receiver) = mpsc::sync_channel::<Vec<u8>>(depth);
+        let writer = thread::spawn(move || {
+            for chunk in receiver {
+                file.write_all(&chunk)?;
+            }
+            Ok(file)
+        });
+        Self {
+            buffer: Vec::with_capacity(WRITE_BEHIND_CHUNK),
+            sender: Some(sender),
+            writer: Some(writer),
+        }
+    }
+
+    /// Hand the buffered bytes to the writer thread.
+    fn send(&mut self) -> io::Result<()> {
+        if self.buffer.is_empty() {
+            return Ok(());
+        }
+        let chunk = std::mem::replace(&mut self.buffer, Vec::with_capacity(WRITE_BEHIND_CHUNK));
+        let sent = self.sender.as_ref().map(|s| s.send(chunk).is_ok());
+        if sent == Some(true) {
+            return Ok(());
+        }
+        // The writer thread only stops early on an error.
+        self.sender = None;
+        match self.join() {
+            Err(e) => Err(e),
+            Ok(_) => Err(io::Error::other("the archive writer has stopped")),
+        }
+    }
+
+    fn join(&mut self) -> io::Result<StdFile> {
+        self.writer
+            .take()
+            .ok_or_else(|| io::Error::other("the archive writer has stopped"))?
+            .join()
+            .map_err(|_| io::Error::other("the archive writer panicked"))?
+    }
+
+    /// Write what is left and wait for the writer thread.
+    fn finish(mut self) -> io::Result<StdFile> {
+        self.send()?;
+        self.sender = None;
+        let mut file = self.join()?;
+        file.flush()?;
+        Ok(file)
+    }
+}
+
+impl Write for WriteBehind {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        self.buffer.extend_from_slice(buf);
+        if self.buffer.len() >= WRITE_BEHIND_CHUNK {
+            self.send()?;
+        }
+        Ok(buf.len())
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        self.send()
+    }
+}
+
+/// One collected file, as listed in the manifest.
+#[derive(Debug, Clone, Serialize)]
+pub struct CollectedFile {
+    pub targets: Vec<String>,
+    pub identifier: u64,
+    pub source_path: String,
+    /// Path relative to the output directory or archive root.
+    pub output_path: String,
+    pub size: u64,
+    pub modified: Option<u64>,
+    #[serde(flatten)]
+    pub hashes: FileHashes,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub error: Option<String>,
+}
+
+/// Relative output path of a source path: one component per path element, with names
+/// that could escape the output directory neutralized.
+fn output_path(path: &str, separator: &str) -> String {
+    path.split(separator)
+        .filter(|c| !c.is_empty())
+        .map(|c| match c {
+            "." | ".." => c.replace('.', "_"),
+            _ => c.replace(['/', '\\', '\0'], "_"),
+        })
+        .collect::<Vec<_>>()
+        .join("/")
+}
+
+/// Hashes everything read through it.
+struct HashingReader<'a, R: ?Sized> {
+    inner: &'a mut R,
+    hasher: MultiHasher,
+    read: u64,
+}
+
+impl<R: Read + ?Sized> Read for HashingReader<'_, R> {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        let n = self.inner.read(buf)?;
+        self.hasher.update(&buf[..n]);
+        self.read += n as u64;
+        Ok(n)
+    }
+}
+
+struct Collector<'a> {
+    targets: &'a TargetSet,
+    separator: String,
+    algorithms: &'a [HashAlgorithm],
+    sink: CollectSink,
+    collected: Vec<CollectedFile>,
+    /// A failed archive write leaves the archive unusable, so collection stops there.
+    fatal: Option<String>,
+}
+
+impl Collector<'_> {
+    fn write(
+        &mut self,
+        relative: &str,
+        file: &File,
+        reader: &mut dyn ReadSeek,
+    ) -> io::Result<FileHashes> {
+        match &mut self.sink {
+            CollectSink::Directory(root) => {
+                let dest = root.join(relative);
+                if let Some(parent) = dest.parent() {
+                    fs::create_dir_all(parent)?;
+                }
+                let mut out = BufWriter::new(StdFile::create(&dest)?);
+                let (hashes, _) = copy_and_hash(reader, &mut out, self.algorithms)?;
+                out.flush()?;
+                Ok(hashes)
+            }
+            CollectSink::Tar(builder) => {
+                let mut header = tar::Header::new_gnu();
+                header.set_size(file.size);
+                header.set_mode(0o644);
+                header.set_mtime(file.modified.unwrap_or(0));
+                let mut hashing = HashingReader {
+                    inner: reader,
+                    hasher: MultiHasher::new(self.algorithms),
+                    read: 0,
+                };
+                let result = builder.append_data(&mut header, relative, &mut hashing);
+                if let Err(e) = result {
+                    self.fatal = Some(e.to_string());
+                    return Err(e);
+                }
+                if hashing.read != file.size {
+                    let e = io::Error::new(
+                        io::ErrorKind::UnexpectedEof,
+                        format!("read {} of {} bytes", hashing.read, file.size),
+                    );
+                    self.fatal = Some(e.to_string());
+                    return Err(e);
+                }
+                Ok(hashing.hasher.finalize())
+            }
+        }
+    }
+
+    fn visit(&mut self, file: &mut File, reader: &mut dyn ReadSeek) {
+        if self.fatal.is_some() {
+            return;
+        }
+        let targets: Vec<String> = self
+            .targets
+            .matching(&file.absolute_path, &self.separator)
+            .into_iter()
+            .map(String::from)
+            .collect();
+        if targets.is_empty() {
+            return;
+        }
+        let relative = output_path(&file.absolute_path, &self.separator);
+        let (hashes, error) = match self.write(&relative, file, reader) {
+            Ok(hashes) => (hashes, None),
+            Err(e) => {
+                warn!("Could not collect {}: {}", file.absolute_path, e);
+                (FileHashes::default(), Some(e.to_string()))
+            }
+        };
+        self.collected.push(CollectedFile {
+            targets,
+            identifier: file.identifier,
+            source_path: file.absolute_path.clone(),
+            output_path: relative,
+            size: file.size,
+            modified: file.modified,
+            hashes,
+            error,
+        });
+    }
+
+    /// Write the manifest and close the sink.
+    fn finish(self) -> Result<Vec<CollectedFile>, Box<dyn Error>> {
+        if let Some(e) = self.fatal {
+            return Err(format!("collection aborted: {}", e).into());
+        }
+        let manifest = serde_json::to_vec_pretty(&self.collected)?;
+        match self.sink {
+            CollectSink::Directory(root) => fs::write(root.join(MANIFEST_NAME), manifest)?,
+            CollectSink::Tar(mut builder) => {
+                let mut header = tar::Header::new_gnu();
+                header.set_size(manifest.len() as u64);
+                header.set_mode(0o644);
+                builder.append_data(&mut header, MANIFEST_NAME, manifest.as_slice())?;
+                builder.into_inner()?.finish()?;
+            }
+        }
+        Ok(self.collected)
+    }
+}
+
+/// Walk `fs` and export every regular file matching one of `targets` into `sink`, hashing
+/// the content with `algorithms` on the way and skipping `exclude`. A `collection.json`
+/// manifest is added last.
+pub fn collect<F: Filesystem + ?Sized>(
+    fs: &mut F,
+    targets: &TargetSet,
+    sink: CollectSink,
+    algorithms: &[HashAlgorithm],
+    exclude: Option<&ExcludeSet>,
+    on_status: &mut dyn FnMut(String),
+) -> Result<Vec<CollectedFile>, Box<dyn Error>> {
+    let mut collector = Collector {
+        targets,
+        separator: fs.path_separator(),
+        algorithms,
+        sink,
+        collected: Vec::new(),
+        fatal: None,
+    };
+    let mut visitor = |file: &mut File, reader: &mut dyn ReadSeek| collector.visit(file, reader);
+    fs.walk_fs_with(
+        WalkOptions {
+            visitor: Some(&mut visitor),
+            exclude,
+            ..Default::default()
+        },
+        &mut |event| {
+            if let WalkEvent::Status(msg) = event {
+                on_status(msg);
+            }
+        },
+    )?;
+    collector.finish()
+}
@@ -0,0 +1,272 @@
+//! Block decompressors of the backends parsing compressed filesystems (ZFS, SquashFS,
+//! UBIFS, CramFS, ...). Each takes one compressed block and the size it decompresses to, and
+//! fails rather than returns a short block; `bounded` takes the largest size instead,
+//! for blocks whose exact size is not recorded.
+use std::error::Error;
+use std::io::{self, Read, Write};
+
+/// Stream compressors, as `bounded` takes them.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Codec {
+    Zlib,
+    /// LZMA "alone" stream, with its 13-byte header.
+    Lzma,
+    Xz,
+    /// LZ4 block, without frame.
+    Lz4,
+    Zstd,
+}
+
+/// Output buffer refusing to grow past `max` bytes, so that a corrupted stream cannot
+/// expand without end.
+struct Capped {
+    out: Vec<u8>,
+    max: usize,
+}
+
+impl Write for Capped {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        if self.out.len() + buf.len() > self.max {
+            return Err(io::Error::other("block decompresses past its largest size"));
+        }
+        self.out.extend_from_slice(buf);
+        Ok(buf.len())
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        Ok(())
+    }
+}
+
+fn read_capped(mut reader: impl Read, max: usize) -> io::Result<Vec<u8>> {
+    let mut capped = Capped {
+        out: Vec::new(),
+        max,
+    };
+    io::copy(&mut reader, &mut capped)?;
+    Ok(capped.out)
+}
+
+/// Decompress a block of at most `max` bytes: the last block of a file, or a SquashFS
+/// metadata or fragment block.
+pub fn bounded(codec: Codec, src: &[u8], max: usize) -> Result<Vec<u8>, Box<dyn Error>> {
+    Ok(match codec {
+        Codec::Zlib => read_capped(flate2::read::ZlibDecoder::new(src), max)?,
+        Codec::Zstd => read_capped(
+            ruzstd::decoding::StreamingDecoder::new(src).map_err(|e| format!("zstd: {}", e))?,
+            max,
+        )?,
+        Codec::Lz4 => {
+            let mut out = vec![0u8; max];
+            let written = lz4_flex::block::decompress_into(src, &mut out)?;
+            out.truncate(written);
+            out
+        }
+        Codec::Xz | Codec::Lzma => {
+            let mut capped = Capped {
+                out: Vec::new(),
+                max,
+            };
+            let mut input = src;
+            match codec {
+                Codec::Xz => lzma_rs::xz_decompress(&mut input, &mut capped)?,
+                _ => lzma_rs::lzma_decompress(&mut input, &mut capped)?,
+            }
+            capped.out
+        }
+    })
+}
+
+/// Check that a decompressor produced the `expected` bytes.
+fn exact(out: Vec<u8>, expected: usize, name: &str) -> Result<Vec<u8>, Box<dyn Error>> {
+    if out.len() != expected {
+        return Err(format!(
+            "{} block decompressed to {} bytes instead of {}",
+            name,
+            out.len(),
+            expected
+        )
+        .into());
+    }
+    Ok(out)
+}
+
+/// zlib stream (RFC 1950), as gzip compression stores it in ZFS, SquashFS and CramFS.
+pub fn zlib(src: &[u8], expected: usize) -> Result<Vec<u8>, Box<dyn Error>> {
+    exact(bounded(Codec::Zlib, src, expected)?, expected, "zlib")
+}
+
+/// Raw deflate stream (RFC 1951), without the zlib header, as UBIFS stores it.
+pub fn deflate(src: &[u8], expected: usize) -> Result<Vec<u8>, Box<dyn Error>> {
+    let out = read_capped(flate2::read::DeflateDecoder::new(src), expected)?;
+    exact(out, expected, "deflate")
+}
+
+/// LZ4 block, without frame nor size prefix.
+pub fn lz4_block(src: &[u8], expected: usize) -> Result<Vec<u8>, Box<dyn Error>> {
+    exact(bounded(Codec::Lz4, src, expected)?, expected, "LZ4")
+}
+
+/// Zstandard frame.
+pub fn zstd(src: &[u8], expected: usize) -> Result<Vec<u8>, Box<dyn Error>> {
+    exact(bounded(Codec::Zstd, src, expected)?, expected, "zstd")
+}
+
+/// LZJB, the original ZFS compressor: groups of 8 items led by a bitmap telling literal
+/// bytes from 2-byte back references (6 bits of length, 10 of distance).
+pub fn lzjb(src: &[u8], expected: usize) -> Result<Vec<u8>, Box<dyn Error>> {
+    const MATCH_BITS: u32 = 6;
+    const MATCH_MIN: usize = 3;
+    const OFFSET_MASK: usize = (1 << (16 - MATCH_BITS)) - 1;
+    let truncated = || "truncated LZJB block";
+    let mut out = Vec::with_capacity(expected);
+    let mut pos = 0;
+    let (mut copymap, mut copymask) = (0u8, 1u16 << 7);
+    while out.len() < expected {
+        copymask <<= 1;
+        if copymask == 1 << 8 {
+            copymask = 1;
+            copymap = *src.get(pos).ok_or_else(truncated)?;
+            pos += 1;
+        }
+        if copymap as u16 & copymask != 0 {
+            let pair = src.get(pos..pos + 2).ok_or_else(truncated)?;
+            pos += 2;
+            let length = (pair[0] >> (8 - MATCH_BITS)) as usize + MATCH_MIN;
+            let distance = ((pair[0] as usize) << 8 | pair[1] as usize) & OFFSET_MASK;
+            let start = out
+                .len()
+                .checked_sub(distance)
+                .ok_or("LZJB back reference before the start of the block")?;
+            for i in 0..length.min(expected - out.len()) {
+                out.push(out[start + i]);
+            }
+        } else {
+            out.push(*src.get(pos).ok_or_else(truncated)?);
+            pos += 1;
+        }
+    }
+    Ok(out)
+}
+
+/// ZLE, the ZFS zero-length encoding: a byte `n` below `zeros_threshold` introduces
+/// `n + 1` literal bytes, above it a run of `n + 1 - zeros_threshold` zeros.
+pub fn zle(src: &[u8], expected: usize, zeros_threshold: usize) -> Result<Vec<u8>, Box<dyn Error>> {
+    let mut out = Vec::with_capacity(expected);
+    let mut pos = 0;
+    while pos < src.len() && out.len() < expected {
+        let length = 1 + src[pos] as usize;
+        pos += 1;
+        if length <= zeros_threshold {
+            let literal = src.get(pos..pos + length).ok_or("truncated ZLE block")?;
+            out.extend_from_slice(literal);
+            pos += length;
+        } else {
+            out.resize(out.len() + length - zeros_threshold, 0);
+        }
+    }
+    out.truncate(expected);
+    exact(out, expected, "ZLE")
+}
+
+/// LZO1X, the default UBIFS compressor: instructions alternating literal runs and back
+/// references, each reference followed by up to 3 literals its low bits count. Lengths
+/// past their field continue as zero bytes worth 255 each and a final byte.
+pub fn lzo1x(src: &[u8], expected: usize) -> Result<Vec<u8>, Box<dyn Error>> {
+    let truncated = || "truncated LZO block";
+    let mut out: Vec<u8> = Vec::with_capacity(expected);
+    let mut pos = 0;
+    let byte = |pos: usize| src.get(pos).map(|b| *b as usize).ok_or_else(truncated);
+    let extended = |pos: &mut usize| -> Result<usize, Box<dyn Error>> {
+        let start = *pos;
+        while byte(*pos)? == 0 {
+            *pos += 1;
+        }
+        let zeros = *pos - start;
+        *pos += 1;
+        Ok(255 * zeros + byte(*pos - 1)?)
+    };
+    let literals = |out: &mut Vec<u8>, pos: &mut usize, count: usize| {
+        let run = src.get(*pos..*pos + count).ok_or_else(truncated)?;
+        if out.len() + count > expected {
+            return Err("LZO block decompresses past its size");
+        }
+        out.extend_from_slice(run);
+        *pos += count;
+        Ok(())
+    };
+
+    // Literals after a reference (1 to 3) or after a run (4) change how short
+    // instructions read.
+    let mut state = 0;
+    if byte(0)? > 17 {
+        let count = byte(0)? - 17;
+        pos = 1;
+        literals(&mut out, &mut pos, count)?;
+        state = count.min(4);
+    }
+    loop {
+        let t = byte(pos)?;
+        pos += 1;
+        let (distance, length, next) = match t {
+            0..16 if state == 0 => {
+                let count = match t {
+                    0 => 18 + extended(&mut pos)?,
+                    _ => t + 3,
+                };
+                literals(&mut out, &mut pos, count)?;
+                state = 4;
+                continue;
+            }
+            0..16 => {
+                let far = if state == 4 { 0x800 } else { 0 };
+                let distance = 1 + far + (t >> 2) + (byte(pos)? << 2);
+                pos += 1;
+                (distance, if state == 4 { 3 } else { 2 }, t & 3)
+            }
+            64.. => {
+                let distance = 1 + ((t >> 2) & 7) + (byte(pos)? << 3);
+                pos += 1;
+                (distance, (t >> 5) + 1, t & 3)
+            }
+            32.. => {
+                let length = match t & 31 {
+                    0 => 33 + extended(&mut pos)?,
+                    length => length + 2,
+                };
+                let word = byte(pos)? | byte(pos + 1)? << 8;
+                pos += 2;
+                (1 + (word >> 2), length, word & 3)
+            }
+            _ => {
+                let length = match t & 7 {
+                    0 => 9 + extended(&mut pos)?,
+                    length => length + 2,
+                };
+                let word = byte(pos)? | byte(pos + 1)? << 8;
+                pos += 2;
+                let distance = ((t & 8) << 11) + (word >> 2);
+                if distance == 0 {
+                    if length != 3 {
+                        return Err("bad LZO end of stream".into());
+                    }
+                    break;
+                }
+                (distance + 0x4000, length, word & 3)
+            }
+        };
+        let start = out
+            .len()
+            .checked_sub(distance)
+            .ok_or("LZO back reference before the start of the block")?;
+        if out.len() + length > expected {
+            return Err("LZO block decompresses past its size".into());
+        }
+        for i in 0..length {
+            out.push(out[start + i]);
+        }
+        literals(&mut out, &mut pos, next)?;
+        state = next;
+    }
+    exact(out, expected, "LZO")
+}
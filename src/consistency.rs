@@ -0,0 +1,1075 @@
+//! Consistency report: checks of on-disk metadata run on the raw partition, independently
+//! of the filesystem parser, so that corrupted or manipulated structures are reported
+//! rather than silently trusted.
+//!
+//! ext4 volumes with the `metadata_csum` feature have the checksums of their superblock,
+//! group descriptors, inodes, extent tree blocks and directory leaf blocks verified.
+//!
+//! Sealed APFS volumes (the signed system volume of macOS) have the content of every
+//! hashed file range compared with the hash recorded in the file-system tree, and the
+//! seal itself reported when the integrity metadata marks it broken. The object
+//! checksums of the B-tree nodes read on the way are verified too. The hashes of the
+//! tree nodes themselves are not.
+use crate::block::BlockDevice;
+use log::warn;
+use serde::Serialize;
+use sha2::{Digest, Sha256, Sha384, Sha512, Sha512_256};
+use std::collections::{BTreeMap, HashMap, HashSet};
+use std::error::Error;
+use std::io::{Read, Seek};
+
+const EXT_SUPERBLOCK_OFFSET: u64 = 1024;
+const EXT_MAGIC: u16 = 0xEF53;
+const EXT_INCOMPAT_64BIT: u32 = 0x80;
+const EXT_INCOMPAT_CSUM_SEED: u32 = 0x2000;
+const EXT_RO_COMPAT_METADATA_CSUM: u32 = 0x400;
+const EXT_BG_INODE_UNINIT: u16 = 0x1;
+const EXT_EXTENTS_FL: u32 = 0x80000;
+const EXT_INLINE_DATA_FL: u32 = 0x1000_0000;
+const EXT_EXTENT_MAGIC: u16 = 0xF30A;
+/// Deeper extent trees than this are treated as corrupted rather than followed.
+const EXT_MAX_EXTENT_DEPTH: u16 = 5;
+/// Offset of `s_checksum`, the last field of the superblock.
+const EXT_SUPERBLOCK_CSUM: usize = 0x3FC;
+/// Fake directory entry holding the checksum of a directory leaf block.
+const EXT_DIRENT_TAIL_SIZE: usize = 12;
+const EXT_DIRENT_TAIL_TYPE: u8 = 0xDE;
+const APFS_NX_MAGIC: &[u8] = b"NXSB";
+const APFS_VOLUME_MAGIC: &[u8] = b"APSB";
+const APFS_MIN_BLOCK_SIZE: u64 = 4096;
+const APFS_MAX_BLOCK_SIZE: u64 = 65536;
+const APFS_MAX_FILE_SYSTEMS: usize = 100;
+const APFS_INCOMPAT_CASE_INSENSITIVE: u64 = 0x1;
+const APFS_INCOMPAT_NORMALIZATION_INSENSITIVE: u64 = 0x8;
+const APFS_INCOMPAT_SEALED_VOLUME: u64 = 0x20;
+const APFS_SEAL_BROKEN: u32 = 0x1;
+const APFS_OBJ_PHYSICAL: u32 = 0x4000_0000;
+const APFS_OBJ_STORAGETYPE_MASK: u32 = 0xC000_0000;
+const APFS_OMAP_VAL_DELETED: u32 = 0x1;
+const APFS_BTNODE_ROOT: u16 = 0x1;
+const APFS_BTNODE_LEAF: u16 = 0x2;
+const APFS_BTNODE_FIXED_KV_SIZE: u16 = 0x4;
+/// Trailing `btree_info_t` of root nodes.
+const APFS_BTREE_INFO_SIZE: usize = 40;
+const APFS_MAX_TREE_DEPTH: usize = 16;
+const APFS_ROOT_DIR_INODE: u64 = 2;
+/// Deeper directory chains are taken as a loop.
+const APFS_MAX_PATH_DEPTH: usize = 1024;
+const APFS_TYPE_INODE: u8 = 3;
+const APFS_TYPE_XATTR: u8 = 4;
+const APFS_TYPE_FILE_EXTENT: u8 = 8;
+const APFS_TYPE_DIR_REC: u8 = 9;
+const APFS_TYPE_FILE_INFO: u8 = 13;
+const APFS_FILE_INFO_DATA_HASH: u8 = 1;
+const APFS_XATTR_DATA_STREAM: u16 = 0x1;
+/// Fixed key and value sizes of the object map and file extent trees.
+const APFS_OMAP_KV: (usize, usize) = (16, 16);
+const APFS_FEXT_KV: (usize, usize) = (16, 16);
+/// Mismatches listed in the report; the total is always counted.
+const MAX_LISTED_ISSUES: usize = 10_000;
+
+/// Castagnoli polynomial, reversed.
+const CRC32C_POLY: u32 = 0x82F6_3B78;
+const CRC32C_TABLE: [u32; 256] = crc32c_table();
+
+const fn crc32c_table() -> [u32; 256] {
+    let mut table = [0u32; 256];
+    let mut i = 0;
+    while i < 256 {
+        let mut crc = i as u32;
+        let mut bit = 0;
+        while bit < 8 {
+            crc = if crc & 1 != 0 {
+                (crc >> 1) ^ CRC32C_POLY
+            } else {
+                crc >> 1
+            };
+            bit += 1;
+        }
+        table[i] = crc;
+        i += 1;
+    }
+    table
+}
+
+/// CRC32C of `data` continuing from `crc`, without the final inversion: ext4 chains the
+/// checksums of several fields this way.
+pub fn crc32c(mut crc: u32, data: &[u8]) -> u32 {
+    for &b in data {
+        crc = CRC32C_TABLE[((crc ^ b as u32) & 0xFF) as usize] ^ (crc >> 8);
+    }
+    crc
+}
+
+fn le_u16(b: &[u8], at: usize) -> u16 {
+    u16::from_le_bytes([b[at], b[at + 1]])
+}
+
+fn le_u32(b: &[u8], at: usize) -> u32 {
+    u32::from_le_bytes(b[at..at + 4].try_into().unwrap())
+}
+
+/// A structure whose stored checksum or hash does not match its content.
+#[derive(Debug, Clone, Serialize)]
+pub struct ConsistencyIssue {
+    /// `superblock`, `group_descriptor`, `inode`, `extent_block` or `directory_block` on
+    /// ext; `object`, `seal` or `file_data` on APFS.
+    pub structure: &'static str,
+    /// Group, inode, block or data stream number, depending on the structure.
+    pub number: u64,
+    /// Byte offset of the structure in the partition.
+    pub offset: u64,
+    /// Inode owning the block, for extent, directory and file data blocks.
+    pub inode: Option<u64>,
+    /// Path of that inode, for APFS file data.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub path: Option<String>,
+    pub stored: String,
+    pub computed: String,
+}
+
+#[derive(Debug, Clone, Default, Serialize)]
+pub struct ConsistencyReport {
+    pub filesystem: String,
+    /// Structures verified, by kind.
+    pub checked: BTreeMap<&'static str, u64>,
+    /// Total number of mismatches, including those beyond the listed issues.
+    pub mismatches: u64,
+    pub issues: Vec<ConsistencyIssue>,
+    /// What could not be verified, and why.
+    pub notes: Vec<String>,
+}
+
+impl ConsistencyReport {
+    fn checked(&mut self, structure: &'static str) {
+        *self.checked.entry(structure).or_default() += 1;
+    }
+
+    fn mismatch(&mut self, issue: ConsistencyIssue) {
+        warn!(
+            "{} {} at offset {:#x}: stored checksum {}, computed {}",
+            issue.structure, issue.number, issue.offset, issue.stored, issue.computed
+        );
+        self.mismatches += 1;
+        if self.issues.len() < MAX_LISTED_ISSUES {
+            self.issues.push(issue);
+        }
+    }
+
+    /// Count a verified structure, recording an issue when the checksums differ.
+    fn compare(
+        &mut self,
+        structure: &'static str,
+        (number, offset, inode): (u64, u64, Option<u64>),
+        stored: u32,
+        computed: u32,
+    ) {
+        self.checked(structure);
+        if stored != computed {
+            self.mismatch(ConsistencyIssue {
+                structure,
+                number,
+                offset,
+                inode,
+                path: None,
+                stored: format!("{:#010x}", stored),
+                computed: format!("{:#010x}", computed),
+            });
+        }
+    }
+}
+
+/// Verify the metadata checksums of the filesystem on `device`.
+pub fn check<T: Read + Seek>(
+    device: &mut BlockDevice<T>,
+) -> Result<ConsistencyReport, Box<dyn Error>> {
+    if device.len() >= EXT_SUPERBLOCK_OFFSET + 1024 {
+        let sb = device.read_bytes(EXT_SUPERBLOCK_OFFSET, 1024)?;
+        if le_u16(&sb, 0x38) == EXT_MAGIC {
+            return ExtChecker::new(device, sb).run();
+        }
+    }
+    if device.len() >= APFS_MIN_BLOCK_SIZE {
+        let nx = device.read_bytes(0, APFS_MIN_BLOCK_SIZE as usize)?;
+        if &nx[0x20..0x24] == APFS_NX_MAGIC {
+            return ApfsChecker::new(device, &nx)?.run();
+        }
+    }
+    Err("consistency checks are only available for ext4 and APFS volumes".into())
+}
+
+struct ExtChecker<'a, T: Read + Seek> {
+    device: &'a mut BlockDevice<T>,
+    sb: Vec<u8>,
+    block_size: u64,
+    desc_size: usize,
+    wide: bool,
+    inode_size: usize,
+    inodes_per_group: u64,
+    seed: u32,
+    report: ConsistencyReport,
+}
+
+impl<'a, T: Read + Seek> ExtChecker<'a, T> {
+    fn new(device: &'a mut BlockDevice<T>, sb: Vec<u8>) -> Self {
+        let incompat = le_u32(&sb, 0x60);
+        let wide = incompat & EXT_INCOMPAT_64BIT != 0 && le_u16(&sb, 0xFE) >= 64;
+        let seed = if incompat & EXT_INCOMPAT_CSUM_SEED != 0 {
+            le_u32(&sb, 0x270)
+        } else {
+            crc32c(!0, &sb[0x68..0x78])
+        };
+        let inode_size = if le_u32(&sb, 0x4C) == 0 {
+            128
+        } else {
+            le_u16(&sb, 0x58) as usize
+        };
+        Self {
+            block_size: 1024u64 << le_u32(&sb, 0x18).min(16),
+            desc_size: if wide { le_u16(&sb, 0xFE) as usize } else { 32 },
+            wide,
+            inode_size,
+            inodes_per_group: le_u32(&sb, 0x28) as u64,
+            seed,
+            device,
+            sb,
+            report: ConsistencyReport {
+                filesystem: "ext".into(),
+                ..Default::default()
+            },
+        }
+    }
+
+    fn run(mut self) -> Result<ConsistencyReport, Box<dyn Error>> {
+        if le_u32(&self.sb, 0x64) & EXT_RO_COMPAT_METADATA_CSUM == 0 {
+            self.report.notes.push(
+                "metadata_csum is not enabled: the volume stores no checksums to verify".into(),
+            );
+            return Ok(self.report);
+        }
+        if self.inode_size < 128 || self.inodes_per_group == 0 {
+            return Err("the ext superblock is corrupted".into());
+        }
+        self.report.compare(
+            "superblock",
+            (0, EXT_SUPERBLOCK_OFFSET, None),
+            le_u32(&self.sb, EXT_SUPERBLOCK_CSUM),
+            crc32c(!0, &self.sb[..EXT_SUPERBLOCK_CSUM]),
+        );
+
+        let first_data_block = le_u32(&self.sb, 0x14) as u64;
+        let blocks_per_group = (le_u32(&self.sb, 0x20) as u64).max(1);
+        let mut blocks = le_u32(&self.sb, 0x04) as u64;
+        if self.wide {
+            blocks |= (le_u32(&self.sb, 0x150) as u64) << 32;
+        }
+        let groups = blocks
+            .saturating_sub(first_data_block)
+            .div_ceil(blocks_per_group);
+        let gdt_offset = (first_data_block + 1) * self.block_size;
+
+        for group in 0..groups {
+            let offset = gdt_offset + group * self.desc_size as u64;
+            let desc = self.device.read_bytes(offset, self.desc_size)?;
+            if desc.len() < self.desc_size {
+                self.report.notes.push(format!(
+                    "group descriptors past group {} are beyond the partition",
+                    group
+                ));
+                break;
+            }
+            let mut crc = crc32c(self.seed, &(group as u32).to_le_bytes());
+            crc = crc32c(crc, &desc[..0x1E]);
+            crc = crc32c(crc, &[0, 0]);
+            crc = crc32c(crc, &desc[0x20..]);
+            self.report.compare(
+                "group_descriptor",
+                (group, offset, None),
+                le_u16(&desc, 0x1E) as u32,
+                crc & 0xFFFF,
+            );
+            if le_u16(&desc, 0x12) & EXT_BG_INODE_UNINIT == 0 {
+                self.check_inode_table(group, &desc)?;
+            }
+        }
+        Ok(self.report)
+    }
+
+    fn check_inode_table(&mut self, group: u64, desc: &[u8]) -> Result<(), Box<dyn Error>> {
+        let mut table = le_u32(desc, 0x08) as u64;
+        let mut unused = le_u16(desc, 0x1C) as u64;
+        if self.wide {
+            table |= (le_u32(desc, 0x28) as u64) << 32;
+            unused |= (le_u16(desc, 0x32) as u64) << 16;
+        }
+        let used = self.inodes_per_group.saturating_sub(unused);
+        let offset = table * self.block_size;
+        let data = self
+            .device
+            .read_bytes(offset, (used * self.inode_size as u64) as usize)?;
+        for (index, raw) in data.chunks_exact(self.inode_size).enumerate() {
+            // Never allocated: no checksum was ever written.
+            if raw.iter().all(|b| *b == 0) {
+                continue;
+            }
+            let number = group * self.inodes_per_group + index as u64 + 1;
+            let at = offset + (index * self.inode_size) as u64;
+            self.check_inode(number, at, raw)?;
+        }
+        Ok(())
+    }
+
+    /// Checksum seed of the blocks owned by an inode.
+    fn inode_seed(&self, number: u64, raw: &[u8]) -> u32 {
+        let crc = crc32c(self.seed, &(number as u32).to_le_bytes());
+        crc32c(crc, &raw[0x64..0x68])
+    }
+
+    fn check_inode(&mut self, number: u64, offset: u64, raw: &[u8]) -> Result<(), Box<dyn Error>> {
+        let seed = self.inode_seed(number, raw);
+        let wide_csum = raw.len() > 128 && le_u16(raw, 0x80) >= 4;
+        let mut crc = crc32c(seed, &raw[..0x7C]);
+        crc = crc32c(crc, &[0, 0]);
+        crc = crc32c(crc, &raw[0x7E..0x80]);
+        let mut stored = le_u16(raw, 0x7C) as u32;
+        if raw.len() > 128 {
+            crc = crc32c(crc, &raw[0x80..0x82]);
+            if wide_csum {
+                crc = crc32c(crc, &[0, 0]);
+                crc = crc32c(crc, &raw[0x84..]);
+                stored |= (le_u16(raw, 0x82) as u32) << 16;
+            } else {
+                crc = crc32c(crc, &raw[0x82..]);
+            }
+        }
+        if !wide_csum {
+            crc &= 0xFFFF;
+        }
+        self.report
+            .compare("inode", (number, offset, None), stored, crc);
+
+        // Only follow the blocks of inodes in use: freed ones may point to reused blocks.
+        let in_use = le_u16(raw, 0x1A) != 0 && le_u32(raw, 0x14) == 0;
+        let flags = le_u32(raw, 0x20);
+        if !in_use || flags & EXT_INLINE_DATA_FL != 0 {
+            return Ok(());
+        }
+        let is_dir = le_u16(raw, 0x00) & 0xF000 == 0x4000;
+        if flags & EXT_EXTENTS_FL != 0 {
+            let mut data_blocks = Vec::new();
+            self.check_extent_node(number, seed, &raw[0x28..0x64], 0, &mut data_blocks, is_dir)?;
+            for block in data_blocks {
+                self.check_directory_block(number, seed, block)?;
+            }
+        } else if is_dir {
+            // Block-mapped directory: the direct blocks only.
+            for slot in 0..12 {
+                let block = le_u32(raw, 0x28 + slot * 4) as u64;
+                if block != 0 {
+                    self.check_directory_block(number, seed, block)?;
+                }
+            }
+        }
+        Ok(())
+    }
+
+    /// Walk an extent node, verifying the checksum of every child block; the data blocks
+    /// of the leaves are collected when `collect` is set.
+    fn check_extent_node(
+        &mut self,
+        inode: u64,
+        seed: u32,
+        node: &[u8],
+        level: u16,
+        data_blocks: &mut Vec<u64>,
+        collect: bool,
+    ) -> Result<(), Box<dyn Error>> {
+        if node.len() < 12 || le_u16(node, 0) != EXT_EXTENT_MAGIC {
+            return Ok(());
+        }
+        let entries = le_u16(node, 2) as usize;
+        let depth = le_u16(node, 6);
+        if depth > EXT_MAX_EXTENT_DEPTH || level > EXT_MAX_EXTENT_DEPTH {
+            return Ok(());
+        }
+        for entry in node[12..].chunks_exact(12).take(entries) {
+            if depth == 0 {
+                if collect {
+                    let mut length = le_u16(entry, 4) as u64;
+                    if length > 32768 {
+                        length -= 32768;
+                    }
+                    let start = le_u32(entry, 8) as u64 | (le_u16(entry, 6) as u64) << 32;
+                    data_blocks.extend(start..start + length);
+                }
+                continue;
+            }
+            let child = le_u32(entry, 4) as u64 | (le_u16(entry, 8) as u64) << 32;
+            let offset = child * self.block_size;
+            let block = self.device.read_bytes(offset, self.block_size as usize)?;
+            if block.len() < 12 || le_u16(&block, 0) != EXT_EXTENT_MAGIC {
+                continue;
+            }
+            let tail = 12 + 12 * le_u16(&block, 4) as usize;
+            if tail + 4 <= block.len() {
+                self.report.compare(
+                    "extent_block",
+                    (child, offset, Some(inode)),
+                    le_u32(&block, tail),
+                    crc32c(seed, &block[..tail]),
+                );
+            }
+            self.check_extent_node(inode, seed, &block, level + 1, data_blocks, collect)?;
+        }
+        Ok(())
+    }
+
+    fn check_directory_block(
+        &mut self,
+        inode: u64,
+        seed: u32,
+        block: u64,
+    ) -> Result<(), Box<dyn Error>> {
+        let offset = block * self.block_size;
+        let data = self.device.read_bytes(offset, self.block_size as usize)?;
+        let Some(at) = data.len().checked_sub(EXT_DIRENT_TAIL_SIZE) else {
+            return Ok(());
+        };
+        let tail = &data[at..];
+        // Hashed directory index nodes carry no leaf tail.
+        if le_u32(tail, 0) != 0
+            || le_u16(tail, 4) as usize != EXT_DIRENT_TAIL_SIZE
+            || tail[6] != 0
+            || tail[7] != EXT_DIRENT_TAIL_TYPE
+        {
+            return Ok(());
+        }
+        self.report.compare(
+            "directory_block",
+            (block, offset, Some(inode)),
+            le_u32(tail, 8),
+            crc32c(seed, &data[..at]),
+        );
+        Ok(())
+    }
+}
+
+fn le_u64(b: &[u8], at: usize) -> u64 {
+    u64::from_le_bytes(b[at..at + 8].try_into().unwrap())
+}
+
+/// APFS object checksum: Fletcher-64 over the object past its checksum field.
+fn fletcher64(data: &[u8]) -> u64 {
+    const MOD: u64 = 0xFFFF_FFFF;
+    let (mut sum1, mut sum2) = (0u64, 0u64);
+    for word in data.chunks_exact(4) {
+        sum1 = (sum1 + u32::from_le_bytes(word.try_into().unwrap()) as u64) % MOD;
+        sum2 = (sum2 + sum1) % MOD;
+    }
+    let low = MOD - (sum1 + sum2) % MOD;
+    let high = MOD - (sum1 + low) % MOD;
+    (high << 32) | low
+}
+
+/// Digest of the integrity metadata hash type (`im_hash_type`).
+fn apfs_digest(hash_type: u32) -> Option<Box<dyn sha2::digest::DynDigest>> {
+    match hash_type {
+        1 => Some(Box::new(Sha256::new())),
+        2 => Some(Box::new(Sha512_256::new())),
+        3 => Some(Box::new(Sha384::new())),
+        4 => Some(Box::new(Sha512::new())),
+        _ => None,
+    }
+}
+
+/// Key and value of entry `index` of a B-tree node, `None` when they fall outside it.
+/// `fixed` gives the key and value sizes of trees with fixed-size entries.
+fn node_entry(node: &[u8], index: usize, fixed: Option<(usize, usize)>) -> Option<(&[u8], &[u8])> {
+    let flags = le_u16(node, 0x20);
+    let toc = 0x38 + le_u16(node, 0x28) as usize;
+    let keys = toc + le_u16(node, 0x2A) as usize;
+    let values = if flags & APFS_BTNODE_ROOT != 0 {
+        node.len().checked_sub(APFS_BTREE_INFO_SIZE)?
+    } else {
+        node.len()
+    };
+    let (key_at, key_len, value_at, value_len) =
+        match fixed.filter(|_| flags & APFS_BTNODE_FIXED_KV_SIZE != 0) {
+            Some((key_len, value_len)) => {
+                let entry = node.get(toc + index * 4..toc + index * 4 + 4)?;
+                // Index nodes hold the child object identifier.
+                let value_len = if flags & APFS_BTNODE_LEAF != 0 {
+                    value_len
+                } else {
+                    8
+                };
+                (
+                    le_u16(entry, 0) as usize,
+                    key_len,
+                    le_u16(entry, 2) as usize,
+                    value_len,
+                )
+            }
+            None => {
+                let entry = node.get(toc + index * 8..toc + index * 8 + 8)?;
+                (
+                    le_u16(entry, 0) as usize,
+                    le_u16(entry, 2) as usize,
+                    le_u16(entry, 4) as usize,
+                    le_u16(entry, 6) as usize,
+                )
+            }
+        };
+    let key = node.get(keys + key_at..keys + key_at + key_len)?;
+    let value_start = values.checked_sub(value_at)?;
+    let value = node.get(value_start..value_start + value_len)?;
+    Some((key, value))
+}
+
+/// Object identifier and record type of a file-system tree key.
+fn apfs_key_header(key: &[u8]) -> (u64, u8) {
+    let header = le_u64(key, 0);
+    (header & 0x0FFF_FFFF_FFFF_FFFF, (header >> 60) as u8)
+}
+
+fn apfs_name(raw: &[u8]) -> String {
+    let end = raw.iter().position(|b| *b == 0).unwrap_or(raw.len());
+    String::from_utf8_lossy(&raw[..end]).into_owned()
+}
+
+/// File extent: logical byte offset, length in bytes and first physical block.
+type ApfsExtent = (u64, u64, u64);
+type ApfsRecord = (Vec<u8>, Vec<u8>);
+/// Partition offset of the first block of a hashed range, and its digest.
+type HashedRange = (u64, Vec<u8>);
+
+/// In-order iterator over the leaf records of a B-tree.
+struct TreeCursor {
+    /// Nodes from the root down, with the next entry to visit in each.
+    stack: Vec<(Vec<u8>, usize)>,
+    fixed: Option<(usize, usize)>,
+    /// Object map tree resolving the children of a virtual tree.
+    omap: Option<u64>,
+}
+
+impl TreeCursor {
+    fn next<T: Read + Seek>(
+        &mut self,
+        checker: &mut ApfsChecker<T>,
+    ) -> Result<Option<ApfsRecord>, Box<dyn Error>> {
+        loop {
+            let depth = self.stack.len();
+            let Some((node, next)) = self.stack.last_mut() else {
+                return Ok(None);
+            };
+            if *next >= le_u32(node, 0x24) as usize {
+                self.stack.pop();
+                continue;
+            }
+            let index = *next;
+            *next += 1;
+            let Some((key, value)) = node_entry(node, index, self.fixed) else {
+                continue;
+            };
+            if le_u16(node, 0x20) & APFS_BTNODE_LEAF != 0 {
+                return Ok(Some((key.to_vec(), value.to_vec())));
+            }
+            if value.len() < 8 || depth >= APFS_MAX_TREE_DEPTH {
+                continue;
+            }
+            let child = le_u64(value, 0);
+            let address = match self.omap {
+                Some(omap) => checker.omap_lookup(omap, child)?,
+                None => Some(child),
+            };
+            if let Some(address) = address {
+                let node = checker.read_object(address)?;
+                self.stack.push((node, 0));
+            }
+        }
+    }
+}
+
+/// File extent tree of a sealed volume, read alongside the file-system tree: both are
+/// sorted by object identifier.
+struct FextReader {
+    cursor: TreeCursor,
+    peeked: Option<ApfsRecord>,
+    stream: Option<u64>,
+    extents: Vec<ApfsExtent>,
+}
+
+impl FextReader {
+    /// Load the extents of `stream`, which never decreases between calls.
+    fn load<T: Read + Seek>(
+        &mut self,
+        checker: &mut ApfsChecker<T>,
+        stream: u64,
+    ) -> Result<(), Box<dyn Error>> {
+        if self.stream == Some(stream) {
+            return Ok(());
+        }
+        self.stream = Some(stream);
+        self.extents.clear();
+        loop {
+            if self.peeked.is_none() {
+                self.peeked = self.cursor.next(checker)?;
+            }
+            let Some((key, value)) = &self.peeked else {
+                return Ok(());
+            };
+            let private_id = le_u64(key, 0);
+            if private_id > stream {
+                return Ok(());
+            }
+            if private_id == stream {
+                self.extents.push((
+                    le_u64(key, 8),
+                    le_u64(value, 0) & 0x00FF_FFFF_FFFF_FFFF,
+                    le_u64(value, 8),
+                ));
+            }
+            self.peeked = None;
+        }
+    }
+}
+
+struct ApfsChecker<'a, T: Read + Seek> {
+    device: &'a mut BlockDevice<T>,
+    block_size: u64,
+    /// Transaction of the checkpoint read: newer object map entries are ignored.
+    xid: u64,
+    nx_omap: u64,
+    fs_oids: Vec<u64>,
+    verified: HashSet<u64>,
+    report: ConsistencyReport,
+}
+
+impl<'a, T: Read + Seek> ApfsChecker<'a, T> {
+    fn new(device: &'a mut BlockDevice<T>, first: &[u8]) -> Result<Self, Box<dyn Error>> {
+        let block_size = le_u32(first, 0x24) as u64;
+        if !block_size.is_power_of_two()
+            || !(APFS_MIN_BLOCK_SIZE..=APFS_MAX_BLOCK_SIZE).contains(&block_size)
+        {
+            return Err(format!("invalid APFS block size {}", block_size).into());
+        }
+        let mut checker = Self {
+            device,
+            block_size,
+            xid: 0,
+            nx_omap: 0,
+            fs_oids: Vec::new(),
+            verified: HashSet::new(),
+            report: ConsistencyReport {
+                filesystem: "apfs".into(),
+                ..Default::default()
+            },
+        };
+        let nx = checker.latest_superblock()?;
+        checker.xid = le_u64(&nx, 0x10);
+        let omap = checker.read_object(le_u64(&nx, 0xA0))?;
+        checker.nx_omap = le_u64(&omap, 0x30);
+        checker.fs_oids = nx[0xB8..0xB8 + 8 * APFS_MAX_FILE_SYSTEMS]
+            .chunks_exact(8)
+            .map(|oid| le_u64(oid, 0))
+            .filter(|oid| *oid != 0)
+            .collect();
+        Ok(checker)
+    }
+
+    /// Newest valid container superblock of the checkpoint descriptor area, falling back
+    /// on the copy in block zero.
+    fn latest_superblock(&mut self) -> Result<Vec<u8>, Box<dyn Error>> {
+        let mut latest = self.read_object(0)?;
+        let desc_blocks = le_u32(&latest, 0x68);
+        // The descriptor area is contiguous unless its top bit is set.
+        if desc_blocks & 0x8000_0000 != 0 {
+            return Ok(latest);
+        }
+        let base = le_u64(&latest, 0x70);
+        for block in base..base.saturating_add(desc_blocks as u64) {
+            let data = self
+                .device
+                .read_bytes(block * self.block_size, self.block_size as usize)?;
+            if data.len() == self.block_size as usize
+                && &data[0x20..0x24] == APFS_NX_MAGIC
+                && fletcher64(&data[8..]) == le_u64(&data, 0)
+                && le_u64(&data, 0x10) > le_u64(&latest, 0x10)
+            {
+                latest = data;
+            }
+        }
+        Ok(latest)
+    }
+
+    /// Read the object at physical block `address`, verifying its checksum the first
+    /// time it is read.
+    fn read_object(&mut self, address: u64) -> Result<Vec<u8>, Box<dyn Error>> {
+        let offset = address
+            .checked_mul(self.block_size)
+            .ok_or("APFS object address out of range")?;
+        let data = self.device.read_bytes(offset, self.block_size as usize)?;
+        if data.len() < self.block_size as usize {
+            return Err(format!("APFS object at block {} is beyond the partition", address).into());
+        }
+        if self.verified.insert(address) {
+            self.report.checked("object");
+            let stored = le_u64(&data, 0);
+            let computed = fletcher64(&data[8..]);
+            if stored != computed {
+                self.report.mismatch(ConsistencyIssue {
+                    structure: "object",
+                    number: address,
+                    offset,
+                    inode: None,
+                    path: None,
+                    stored: format!("{:#018x}", stored),
+                    computed: format!("{:#018x}", computed),
+                });
+            }
+        }
+        Ok(data)
+    }
+
+    /// Physical block of virtual object `oid` in the object map tree rooted at `tree`.
+    fn omap_lookup(&mut self, tree: u64, oid: u64) -> Result<Option<u64>, Box<dyn Error>> {
+        let mut address = tree;
+        for _ in 0..APFS_MAX_TREE_DEPTH {
+            let node = self.read_object(address)?;
+            let count = le_u32(&node, 0x24) as usize;
+            // Entries are sorted by (oid, xid): take the last one not after the target.
+            let mut found = None;
+            for index in 0..count {
+                let Some((key, value)) = node_entry(&node, index, Some(APFS_OMAP_KV)) else {
+                    break;
+                };
+                if (le_u64(key, 0), le_u64(key, 8)) > (oid, self.xid) {
+                    break;
+                }
+                found = Some((le_u64(key, 0), value.to_vec()));
+            }
+            let Some((key_oid, value)) = found else {
+                return Ok(None);
+            };
+            if le_u16(&node, 0x20) & APFS_BTNODE_LEAF == 0 {
+                address = le_u64(&value, 0);
+                continue;
+            }
+            if key_oid != oid || le_u32(&value, 0) & APFS_OMAP_VAL_DELETED != 0 {
+                return Ok(None);
+            }
+            return Ok(Some(le_u64(&value, 8)));
+        }
+        Ok(None)
+    }
+
+    /// Cursor over the tree `oid` of storage `kind`, virtual trees resolved in `omap`.
+    fn cursor(
+        &mut self,
+        oid: u64,
+        kind: u32,
+        omap: u64,
+        fixed: Option<(usize, usize)>,
+    ) -> Result<Option<TreeCursor>, Box<dyn Error>> {
+        let physical = kind & APFS_OBJ_STORAGETYPE_MASK == APFS_OBJ_PHYSICAL;
+        let root = if physical {
+            Some(oid)
+        } else {
+            self.omap_lookup(omap, oid)?
+        };
+        let Some(root) = root else {
+            return Ok(None);
+        };
+        Ok(Some(TreeCursor {
+            stack: vec![(self.read_object(root)?, 0)],
+            fixed,
+            omap: (!physical).then_some(omap),
+        }))
+    }
+
+    fn run(mut self) -> Result<ConsistencyReport, Box<dyn Error>> {
+        for oid in self.fs_oids.clone() {
+            let volume = match self.omap_lookup(self.nx_omap, oid)? {
+                Some(address) => self.read_object(address)?,
+                None => {
+                    self.report.notes.push(format!(
+                        "volume object {} is not in the container object map",
+                        oid
+                    ));
+                    continue;
+                }
+            };
+            if &volume[0x20..0x24] != APFS_VOLUME_MAGIC {
+                self.report
+                    .notes
+                    .push(format!("volume object {} has no volume superblock", oid));
+                continue;
+            }
+            self.check_volume(&volume)?;
+        }
+        Ok(self.report)
+    }
+
+    fn check_volume(&mut self, volume: &[u8]) -> Result<(), Box<dyn Error>> {
+        let fs_index = le_u32(volume, 0x24);
+        let name = apfs_name(&volume[0x2C0..0x3C0]);
+        let incompat = le_u64(volume, 0x38);
+        if incompat & APFS_INCOMPAT_SEALED_VOLUME == 0 {
+            self.report
+                .notes
+                .push(format!("volume {} ({}) is not sealed", fs_index, name));
+            return Ok(());
+        }
+        let omap = self.read_object(le_u64(volume, 0x80))?;
+        let omap = le_u64(&omap, 0x30);
+
+        let Some(meta) = self.omap_lookup(omap, le_u64(volume, 0x400))? else {
+            self.report.notes.push(format!(
+                "volume {} ({}) is sealed but has no integrity metadata",
+                fs_index, name
+            ));
+            return Ok(());
+        };
+        let meta_offset = meta * self.block_size;
+        let meta = self.read_object(meta)?;
+        let broken_xid = le_u64(&meta, 0x30);
+        self.report.checked("seal");
+        if le_u32(&meta, 0x24) & APFS_SEAL_BROKEN != 0 || broken_xid != 0 {
+            self.report.mismatch(ConsistencyIssue {
+                structure: "seal",
+                number: fs_index as u64,
+                offset: meta_offset,
+                inode: None,
+                path: None,
+                stored: "sealed".into(),
+                computed: format!("broken at transaction {}", broken_xid),
+            });
+        }
+        let hash_type = le_u32(&meta, 0x28);
+        if apfs_digest(hash_type).is_none() {
+            self.report.notes.push(format!(
+                "volume {} ({}): unknown hash type {}, file data not verified",
+                fs_index, name, hash_type
+            ));
+            return Ok(());
+        }
+
+        let Some(mut tree) = self.cursor(le_u64(volume, 0x88), le_u32(volume, 0x74), omap, None)?
+        else {
+            self.report.notes.push(format!(
+                "volume {} ({}): file-system tree not found",
+                fs_index, name
+            ));
+            return Ok(());
+        };
+        let mut fext = match le_u64(volume, 0x408) {
+            0 => None,
+            oid => self
+                .cursor(oid, le_u32(volume, 0x410), omap, Some(APFS_FEXT_KV))?
+                .map(|cursor| FextReader {
+                    cursor,
+                    peeked: None,
+                    stream: None,
+                    extents: Vec::new(),
+                }),
+        };
+
+        let first_issue = self.report.issues.len();
+        let mut current = None;
+        let mut extents: Vec<ApfsExtent> = Vec::new();
+        let mut unmapped = 0u64;
+        while let Some((key, value)) = tree.next(self)? {
+            if key.len() < 8 {
+                continue;
+            }
+            let (id, kind) = apfs_key_header(&key);
+            if current != Some(id) {
+                current = Some(id);
+                extents.clear();
+            }
+            match kind {
+                APFS_TYPE_FILE_EXTENT if key.len() >= 16 && value.len() >= 16 => {
+                    extents.push((
+                        le_u64(&key, 8),
+                        le_u64(&value, 0) & 0x00FF_FFFF_FFFF_FFFF,
+                        le_u64(&value, 8),
+                    ));
+                }
+                APFS_TYPE_FILE_INFO if key.len() >= 16 && value.len() >= 3 => {
+                    let info = le_u64(&key, 8);
+                    if (info >> 56) as u8 != APFS_FILE_INFO_DATA_HASH {
+                        continue;
+                    }
+                    let Some(stored) = value.get(3..3 + value[2] as usize) else {
+                        continue;
+                    };
+                    let stream_extents = match fext.as_mut() {
+                        Some(fext) => {
+                            fext.load(self, id)?;
+                            fext.extents.clone()
+                        }
+                        None => extents.clone(),
+                    };
+                    let range = (info & 0x00FF_FFFF_FFFF_FFFF, le_u16(&value, 0) as u64);
+                    match self.hash_range(range, &stream_extents, hash_type)? {
+                        Some((offset, computed)) => {
+                            self.report.checked("file_data");
+                            if !computed.starts_with(stored) || stored.is_empty() {
+                                self.report.mismatch(ConsistencyIssue {
+                                    structure: "file_data",
+                                    number: id,
+                                    offset,
+                                    inode: None,
+                                    path: None,
+                                    stored: hex::encode(stored),
+                                    computed: hex::encode(
+                                        &computed[..stored.len().min(computed.len())],
+                                    ),
+                                });
+                            }
+                        }
+                        None => unmapped += 1,
+                    }
+                }
+                _ => {}
+            }
+        }
+        if unmapped > 0 {
+            self.report.notes.push(format!(
+                "volume {} ({}): {} hashed ranges are not covered by file extents",
+                fs_index, name, unmapped
+            ));
+        }
+        if self.report.issues.len() > first_issue {
+            let hashed_names = incompat
+                & (APFS_INCOMPAT_CASE_INSENSITIVE | APFS_INCOMPAT_NORMALIZATION_INSENSITIVE)
+                != 0;
+            self.resolve_paths(volume, omap, first_issue, hashed_names)?;
+        }
+        Ok(())
+    }
+
+    /// Hash the blocks `(first, count)` of a data stream. Returns the partition offset of
+    /// the first block and the digest, `None` when the extents do not cover the range.
+    fn hash_range(
+        &mut self,
+        (first, count): (u64, u64),
+        extents: &[ApfsExtent],
+        hash_type: u32,
+    ) -> Result<Option<HashedRange>, Box<dyn Error>> {
+        let Some(mut digest) = apfs_digest(hash_type) else {
+            return Ok(None);
+        };
+        let mut position = first * self.block_size;
+        let end = position + count * self.block_size;
+        let mut offset = None;
+        while position < end {
+            let Some(&(logical, length, physical)) = extents
+                .iter()
+                .find(|(logical, length, _)| (*logical..logical + length).contains(&position))
+            else {
+                return Ok(None);
+            };
+            let chunk = (logical + length).min(end) - position;
+            let at = physical * self.block_size + (position - logical);
+            offset.get_or_insert(at);
+            if physical == 0 {
+                // Sparse range.
+                digest.update(&vec![0u8; chunk as usize]);
+            } else {
+                let data = self.device.read_bytes(at, chunk as usize)?;
+                if data.len() as u64 != chunk {
+                    return Ok(None);
+                }
+                digest.update(&data);
+            }
+            position += chunk;
+        }
+        Ok(Some((offset.unwrap_or(0), digest.finalize().into_vec())))
+    }
+
+    /// Fill in the inode and path of the file data issues from `first_issue` on, with a
+    /// second pass over the file-system tree.
+    fn resolve_paths(
+        &mut self,
+        volume: &[u8],
+        omap: u64,
+        first_issue: usize,
+        hashed_names: bool,
+    ) -> Result<(), Box<dyn Error>> {
+        let wanted: HashSet<u64> = self.report.issues[first_issue..]
+            .iter()
+            .filter(|issue| issue.structure == "file_data")
+            .map(|issue| issue.number)
+            .collect();
+        let Some(mut tree) = self.cursor(le_u64(volume, 0x88), le_u32(volume, 0x74), omap, None)?
+        else {
+            return Ok(());
+        };
+        // Data stream to owning inode and, for extended attributes, their name.
+        let mut owners: HashMap<u64, (u64, Option<String>)> = HashMap::new();
+        // Inode to parent directory and name.
+        let mut parents: HashMap<u64, (u64, String)> = HashMap::new();
+        while let Some((key, value)) = tree.next(self)? {
+            if key.len() < 8 {
+                continue;
+            }
+            let (id, kind) = apfs_key_header(&key);
+            match kind {
+                APFS_TYPE_INODE if value.len() >= 16 => {
+                    let private_id = le_u64(&value, 8);
+                    if wanted.contains(&private_id) {
+                        owners.entry(private_id).or_insert((id, None));
+                    }
+                }
+                APFS_TYPE_XATTR if key.len() >= 10 && value.len() >= 12 => {
+                    let stream = le_u64(&value, 4);
+                    if le_u16(&value, 0) & APFS_XATTR_DATA_STREAM != 0 && wanted.contains(&stream) {
+                        let length = le_u16(&key, 8) as usize;
+                        let name = key.get(10..10 + length).map(apfs_name);
+                        owners.insert(stream, (id, name));
+                    }
+                }
+                APFS_TYPE_DIR_REC if value.len() >= 8 => {
+                    let name = if hashed_names {
+                        key.get(12..12 + (le_u32(&key, 8) & 0x3FF) as usize)
+                    } else {
+                        key.get(10..10 + le_u16(&key, 8) as usize)
+                    };
+                    if let Some(name) = name {
+                        parents.insert(le_u64(&value, 0), (id, apfs_name(name)));
+                    }
+                }
+                _ => {}
+            }
+        }
+
+        let root = format!("/volume_{}", le_u32(volume, 0x24));
+        for issue in &mut self.report.issues[first_issue..] {
+            let Some((inode, attribute)) = owners.get(&issue.number) else {
+                continue;
+            };
+            issue.inode = Some(*inode);
+            let mut components = Vec::new();
+            let mut current = *inode;
+            while current != APFS_ROOT_DIR_INODE && components.len() < APFS_MAX_PATH_DEPTH {
+                let Some((parent, name)) = parents.get(&current) else {
+                    break;
+                };
+                components.push(name.as_str());
+                current = *parent;
+            }
+            if current != APFS_ROOT_DIR_INODE {
+                continue;
+            }
+            components.push(&root);
+            components.reverse();
+            let mut path = components.join("/");
+            if let Some(attribute) = attribute {
+                path = format!("{}:{}", path, attribute);
+            }
+            issue.path = Some(path);
+        }
+        Ok(())
+    }
+}
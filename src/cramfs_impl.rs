@@ -0,0 +1,576 @@
+//! CramFS images, the compressed read-only root filesystem of older embedded firmware.
+//! The superblock (at the start, or after 512 bytes of padding for a boot loader) holds
+//! the root inode; directories are runs of 12-byte inodes each followed by its name, and
+//! a regular file or symbolic link is a table of block end pointers followed by its 4 KiB
+//! blocks, zlib-compressed one by one. Images are in the byte order of the host that made
+//! them: both are read.
+//!
+//! CramFS keeps no inode numbers, timestamps nor link counts: records are identified by
+//! the position of their inode in the image, as the kernel numbers them. Empty blocks
+//! are holes; blocks stored uncompressed or at a direct address (the extended block
+//! pointers of execute-in-place images) are read as well.
+use crate::compression;
+use crate::filesystem::{
+    ByteRange, DEVICE_KEY, DirectoryCommon, FLAGS_KEY, File, FileCommon, Filesystem,
+    SYMLINK_TARGET_KEY, namespaced_metadata, unix_ftype, unix_mode_string,
+};
+use crate::names::{escape_name, name_bytes, render_name};
+use serde::Serialize;
+use serde_json::{Value, json};
+use std::collections::{HashMap, VecDeque};
+use std::error::Error;
+use std::io::{Read, Seek, SeekFrom};
+
+/// Namespace of the inode fields in `File.metadata`.
+pub const METADATA_NAMESPACE: &str = "cramfs";
+
+const MAGIC: u32 = 0x28cd_3d45;
+const SIGNATURE: &[u8; 16] = b"Compressed ROMFS";
+/// Where the superblock may be: first, after the boot loader padding.
+const SUPERBLOCK_OFFSETS: [u64; 2] = [0, 512];
+const SUPERBLOCK_SIZE: usize = 76;
+const INODE_SIZE: usize = 12;
+const ROOT_INODE: u64 = 64;
+const BLOCK_SIZE: u64 = 4096;
+const BLOCK_UNCOMPRESSED: u32 = 1 << 31;
+const BLOCK_DIRECT: u32 = 1 << 30;
+const BLOCK_FLAGS: u32 = BLOCK_UNCOMPRESSED | BLOCK_DIRECT;
+/// Direct block pointers are in 4-byte units.
+const DIRECT_SHIFT: u32 = 2;
+const FLAG_EXT_BLOCK_POINTERS: u32 = 0x800;
+/// Largest image: offsets are 26 bits of 4-byte units.
+const MAX_SIZE: u64 = 256 << 20;
+
+/// Superblock flags, named after their meaning.
+const SUPERBLOCK_FLAGS: [(u32, &str); 6] = [
+    (0x001, "fsid_version_2"),
+    (0x002, "sorted_dirs"),
+    (0x100, "holes"),
+    (0x200, "wrong_signature"),
+    (0x400, "shifted_root_offset"),
+    (0x800, "ext_block_pointers"),
+];
+
+/// Byte order of the image.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize)]
+#[serde(rename_all = "lowercase")]
+pub enum Endian {
+    Little,
+    Big,
+}
+
+impl Endian {
+    fn u16(self, bytes: &[u8], at: usize) -> u16 {
+        let raw = bytes[at..at + 2].try_into().unwrap();
+        match self {
+            Self::Little => u16::from_le_bytes(raw),
+            Self::Big => u16::from_be_bytes(raw),
+        }
+    }
+
+    fn u32(self, bytes: &[u8], at: usize) -> u32 {
+        let raw = bytes[at..at + 4].try_into().unwrap();
+        match self {
+            Self::Little => u32::from_le_bytes(raw),
+            Self::Big => u32::from_be_bytes(raw),
+        }
+    }
+}
+
+#[derive(Debug, Clone, Serialize)]
+pub struct Superblock {
+    /// Byte offset of the superblock in the image.
+    pub location: u64,
+    pub endian: Endian,
+    pub size: u32,
+    pub flags: u32,
+    pub crc: u32,
+    pub edition: u32,
+    pub blocks: u32,
+    pub files: u32,
+    pub name: String,
+}
+
+/// An inode, with the name it was found under.
+#[derive(Debug, Clone, Serialize)]
+pub struct CramInode {
+    /// Byte offset of the inode in the image.
+    pub position: u64,
+    pub mode: u16,
+    pub uid: u16,
+    /// Bytes of content, of entries for a directory, or the device number.
+    pub size: u32,
+    pub gid: u8,
+    /// Byte offset of the directory entries or of the block pointers.
+    pub offset: u64,
+    pub name: String,
+    /// Target of a symbolic link, read when the image is opened.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub target: Option<String>,
+}
+
+impl CramInode {
+    /// The inode at the start of `raw`, and the bytes it takes with its name.
+    fn parse(raw: &[u8], position: u64, endian: Endian) -> (Self, usize) {
+        let [w0, w1, w2] = [0, 4, 8].map(|at| endian.u32(raw, at));
+        // Bit fields fill words from the low bits on little-endian hosts, from the high
+        // bits on big-endian ones.
+        let (mode, uid, size, gid, name_length, offset) = match endian {
+            Endian::Little => (
+                w0 as u16,
+                (w0 >> 16) as u16,
+                w1 & 0xff_ffff,
+                (w1 >> 24) as u8,
+                w2 & 0x3f,
+                w2 >> 6,
+            ),
+            Endian::Big => (
+                (w0 >> 16) as u16,
+                w0 as u16,
+                w1 >> 8,
+                w1 as u8,
+                w2 >> 26,
+                w2 & 0x03ff_ffff,
+            ),
+        };
+        let name_length = name_length as usize * 4;
+        let name = raw.get(INODE_SIZE..INODE_SIZE + name_length).unwrap_or(&[]);
+        let end = name.iter().position(|b| *b == 0).unwrap_or(name.len());
+        let inode = Self {
+            position,
+            mode,
+            uid,
+            size,
+            gid,
+            offset: offset as u64 * 4,
+            name: escape_name(&name[..end]),
+            target: None,
+        };
+        (inode, INODE_SIZE + name_length)
+    }
+}
+
+impl FileCommon for CramInode {
+    fn id(&self) -> u64 {
+        self.position
+    }
+    fn size(&self) -> u64 {
+        match unix_ftype(self.mode as u32) {
+            "file" | "symlink" | "dir" => self.size as u64,
+            _ => 0,
+        }
+    }
+    fn is_dir(&self) -> bool {
+        self.mode & 0o170000 == 0o040000
+    }
+    fn to_string(&self) -> String {
+        format!(
+            "CramInode {{ position: {}, mode: {:o}, size: {} }}",
+            self.position, self.mode, self.size
+        )
+    }
+    fn to_json(&self) -> Value {
+        serde_json::to_value(self).unwrap_or(Value::Null)
+    }
+}
+
+#[derive(Debug, Clone)]
+pub struct CramDirEntry {
+    pub position: u64,
+    pub name: String,
+    pub mode: u16,
+}
+
+impl DirectoryCommon for CramDirEntry {
+    fn file_id(&self) -> u64 {
+        self.position
+    }
+    fn name(&self) -> &str {
+        &self.name
+    }
+    fn to_string(&self) -> String {
+        format!(
+            "CramDirEntry {{ position: {}, name: {} }}",
+            self.position, self.name
+        )
+    }
+    fn to_json(&self) -> Value {
+        json!({ "position": self.position, "name": self.name, "mode": self.mode })
+    }
+}
+
+pub struct CramFS<T: Read + Seek> {
+    body: T,
+    superblock: Superblock,
+    /// Every inode, by position: the image is small and without an inode table.
+    inodes: HashMap<u64, CramInode>,
+    /// Entries of each directory, by directory position.
+    children: HashMap<u64, Vec<u64>>,
+}
+
+impl<T: Read + Seek> CramFS<T> {
+    pub fn new(mut body: T) -> Result<Self, Box<dyn Error>> {
+        let mut raw = [0u8; SUPERBLOCK_SIZE];
+        let mut found = None;
+        for location in SUPERBLOCK_OFFSETS {
+            body.seek(SeekFrom::Start(location))?;
+            if body.read_exact(&mut raw).is_err() {
+                break;
+            }
+            let endian = match raw[..4].try_into().unwrap() {
+                magic if u32::from_le_bytes(magic) == MAGIC => Endian::Little,
+                magic if u32::from_be_bytes(magic) == MAGIC => Endian::Big,
+                _ => continue,
+            };
+            found = Some((location, endian));
+            break;
+        }
+        let (location, endian) = found.ok_or("no CramFS superblock")?;
+        if &raw[16..32] != SIGNATURE {
+            return Err("bad CramFS signature".into());
+        }
+        let name = &raw[48..64];
+        let end = name.iter().position(|b| *b == 0).unwrap_or(name.len());
+        let superblock = Superblock {
+            location,
+            endian,
+            size: endian.u32(&raw, 4),
+            flags: endian.u32(&raw, 8),
+            crc: endian.u32(&raw, 32),
+            edition: endian.u32(&raw, 36),
+            blocks: endian.u32(&raw, 40),
+            files: endian.u32(&raw, 44),
+            name: String::from_utf8_lossy(&name[..end]).into_owned(),
+        };
+        if superblock.size as u64 > MAX_SIZE {
+            return Err(format!("CramFS image of {} bytes", superblock.size).into());
+        }
+        let (root, _) =
+            CramInode::parse(&raw[ROOT_INODE as usize..], location + ROOT_INODE, endian);
+        if !root.is_dir() {
+            return Err("CramFS root inode is not a directory".into());
+        }
+        let mut fs = Self {
+            body,
+            superblock,
+            inodes: HashMap::new(),
+            children: HashMap::new(),
+        };
+        fs.load(root)?;
+        Ok(fs)
+    }
+
+    /// Read the directories from the root, breadth first.
+    fn load(&mut self, root: CramInode) -> Result<(), Box<dyn Error>> {
+        let endian = self.superblock.endian;
+        let mut queue = VecDeque::from([root.position]);
+        self.inodes.insert(root.position, root);
+        while let Some(position) = queue.pop_front() {
+            let directory = &self.inodes[&position];
+            let (start, size) = (directory.offset, directory.size as u64);
+            if size == 0 {
+                continue;
+            }
+            let data = self.read_at(start, size as usize)?;
+            let mut entries = Vec::new();
+            let mut at = 0;
+            while at + INODE_SIZE <= data.len() {
+                let (inode, length) = CramInode::parse(&data[at..], start + at as u64, endian);
+                at += length;
+                if self.inodes.contains_key(&inode.position) {
+                    continue;
+                }
+                entries.push(inode.position);
+                if inode.is_dir() {
+                    queue.push_back(inode.position);
+                }
+                self.inodes.insert(inode.position, inode);
+            }
+            self.children.insert(position, entries);
+        }
+        let symlinks: Vec<CramInode> = self
+            .inodes
+            .values()
+            .filter(|inode| unix_ftype(inode.mode as u32) == "symlink")
+            .cloned()
+            .collect();
+        for symlink in symlinks {
+            let target = self.read_content(&symlink, 0, symlink.size as usize)?;
+            if let Some(inode) = self.inodes.get_mut(&symlink.position) {
+                inode.target = Some(escape_name(&target));
+            }
+        }
+        Ok(())
+    }
+
+    fn flag_names(&self) -> Vec<&'static str> {
+        SUPERBLOCK_FLAGS
+            .iter()
+            .filter(|(flag, _)| self.superblock.flags & flag != 0)
+            .map(|(_, name)| *name)
+            .collect()
+    }
+
+    fn read_at(&mut self, offset: u64, length: usize) -> Result<Vec<u8>, Box<dyn Error>> {
+        let mut data = vec![0u8; length];
+        self.body.seek(SeekFrom::Start(offset))?;
+        self.body.read_exact(&mut data)?;
+        Ok(data)
+    }
+
+    /// Where block `index` of `inode` is: start, stored length and whether it is
+    /// compressed. A length of zero is a hole.
+    fn block(&mut self, inode: &CramInode, index: u64) -> Result<(u64, u64, bool), Box<dyn Error>> {
+        let endian = self.superblock.endian;
+        let extended = self.superblock.flags & FLAG_EXT_BLOCK_POINTERS != 0;
+        let pointer_flags = |pointer: u32| if extended { pointer & BLOCK_FLAGS } else { 0 };
+        let pointer = endian.u32(&self.read_at(inode.offset + index * 4, 4)?, 0);
+        let flags = pointer_flags(pointer);
+        let address = (pointer & !flags) as u64;
+        if flags & BLOCK_DIRECT != 0 {
+            let start = address << DIRECT_SHIFT;
+            if flags & BLOCK_UNCOMPRESSED != 0 {
+                let length = (inode.size as u64 - index * BLOCK_SIZE).min(BLOCK_SIZE);
+                return Ok((start, length, false));
+            }
+            let length = endian.u16(&self.read_at(start, 2)?, 0) as u64;
+            return Ok((start + 2, length, true));
+        }
+        let start = if index == 0 {
+            inode.offset + (inode.size as u64).div_ceil(BLOCK_SIZE) * 4
+        } else {
+            let previous = endian.u32(&self.read_at(inode.offset + (index - 1) * 4, 4)?, 0);
+            let previous_flags = pointer_flags(previous);
+            let previous_address = (previous & !previous_flags) as u64;
+            if previous_flags & BLOCK_DIRECT == 0 {
+                previous_address
+            } else if previous_flags & BLOCK_UNCOMPRESSED != 0 {
+                // The block after a direct one starts where that one ends.
+                (previous_address << DIRECT_SHIFT) + BLOCK_SIZE
+            } else {
+                let previous_start = previous_address << DIRECT_SHIFT;
+                previous_start + 2 + endian.u16(&self.read_at(previous_start, 2)?, 0) as u64
+            }
+        };
+        let length = address
+            .checked_sub(start)
+            .ok_or("CramFS block pointers go backwards")?;
+        Ok((start, length, flags & BLOCK_UNCOMPRESSED == 0))
+    }
+
+    fn read_content(
+        &mut self,
+        inode: &CramInode,
+        offset: u64,
+        length: usize,
+    ) -> Result<Vec<u8>, Box<dyn Error>> {
+        if !matches!(unix_ftype(inode.mode as u32), "file" | "symlink") {
+            return Ok(Vec::new());
+        }
+        let size = inode.size as u64;
+        if offset >= size || length == 0 {
+            return Ok(Vec::new());
+        }
+        let end = size.min(offset + length as u64);
+        let mut out = Vec::with_capacity((end - offset) as usize);
+        for index in offset / BLOCK_SIZE..end.div_ceil(BLOCK_SIZE) {
+            let expected = (size - index * BLOCK_SIZE).min(BLOCK_SIZE) as usize;
+            let (start, stored, compressed) = self.block(inode, index)?;
+            let block = match (stored, compressed) {
+                (0, _) => vec![0u8; expected],
+                (_, true) => compression::zlib(&self.read_at(start, stored as usize)?, expected)?,
+                (_, false) => {
+                    let mut block = self.read_at(start, stored.min(BLOCK_SIZE) as usize)?;
+                    block.resize(expected, 0);
+                    block
+                }
+            };
+            let block_start = index * BLOCK_SIZE;
+            let from = offset.max(block_start) - block_start;
+            let to = end.min(block_start + expected as u64) - block_start;
+            out.extend_from_slice(&block[from as usize..to as usize]);
+        }
+        Ok(out)
+    }
+}
+
+impl<T: Read + Seek> Filesystem for CramFS<T> {
+    type FileType = CramInode;
+    type DirectoryType = CramDirEntry;
+
+    fn filesystem_type(&self) -> String {
+        "CramFS".to_string()
+    }
+
+    fn path_separator(&self) -> String {
+        "/".to_string()
+    }
+
+    fn record_count(&mut self) -> u64 {
+        self.inodes.len() as u64
+    }
+
+    fn block_size(&self) -> u64 {
+        BLOCK_SIZE
+    }
+
+    fn get_metadata(&self) -> Result<Value, Box<dyn Error>> {
+        let mut metadata = serde_json::to_value(&self.superblock)?;
+        metadata["flag_names"] = json!(self.flag_names());
+        Ok(metadata)
+    }
+
+    fn get_metadata_pretty(&self) -> Result<String, Box<dyn Error>> {
+        let sb = &self.superblock;
+        Ok(format!(
+            "CramFS '{}' ({:?}-endian, at {})\n\
+             Size: {} bytes, {} blocks, {} files, edition {}, CRC {:#010x}\n\
+             Flags: {}\n",
+            sb.name,
+            sb.endian,
+            sb.location,
+            sb.size,
+            sb.blocks,
+            sb.files,
+            sb.edition,
+            sb.crc,
+            self.flag_names().join(", ")
+        ))
+    }
+
+    fn get_file(&mut self, position: u64) -> Result<Self::FileType, Box<dyn Error>> {
+        self.inodes
+            .get(&position)
+            .cloned()
+            .ok_or_else(|| format!("no CramFS inode at {}", position).into())
+    }
+
+    fn read_file_content(&mut self, inode: &Self::FileType) -> Result<Vec<u8>, Box<dyn Error>> {
+        self.read_content(inode, 0, inode.size as usize)
+    }
+
+    fn read_file_prefix(
+        &mut self,
+        inode: &Self::FileType,
+        length: usize,
+    ) -> Result<Vec<u8>, Box<dyn Error>> {
+        self.read_content(inode, 0, length)
+    }
+
+    fn read_file_slice(
+        &mut self,
+        inode: &Self::FileType,
+        offset: u64,
+        length: usize,
+    ) -> Result<Vec<u8>, Box<dyn Error>> {
+        self.read_content(inode, offset, length)
+    }
+
+    fn list_dir(
+        &mut self,
+        inode: &Self::FileType,
+    ) -> Result<Vec<Self::DirectoryType>, Box<dyn Error>> {
+        if !inode.is_dir() {
+            return Err("not a directory".into());
+        }
+        Ok(self
+            .children
+            .get(&inode.position)
+            .into_iter()
+            .flatten()
+            .map(|position| {
+                let child = &self.inodes[position];
+                CramDirEntry {
+                    position: *position,
+                    name: child.name.clone(),
+                    mode: child.mode,
+                }
+            })
+            .collect())
+    }
+
+    fn record_to_file(&self, inode: &Self::FileType, file_id: u64, absolute_path: &str) -> File {
+        let mode = inode.mode as u32;
+        let file_type = unix_ftype(mode);
+        let mut common = json!({ FLAGS_KEY: Vec::<&str>::new() });
+        if matches!(file_type, "chardev" | "blockdev") {
+            // The size field holds the device number, 8-bit major and minor.
+            common[DEVICE_KEY] = json!({
+                "major": (inode.size >> 8) & 0xff,
+                "minor": inode.size & 0xff,
+            });
+        }
+        if let Some(target) = &inode.target {
+            common[SYMLINK_TARGET_KEY] = json!(render_name(target));
+        }
+        let metadata = namespaced_metadata(METADATA_NAMESPACE, inode.to_json(), common);
+        let name = match absolute_path.rsplit_once('/') {
+            Some((_, name)) if !name.is_empty() => name,
+            _ => absolute_path,
+        };
+        let absolute_path = render_name(absolute_path);
+        let permissions = unix_mode_string(mode);
+        File {
+            id: None,
+            identifier: file_id,
+            absolute_path: absolute_path.to_string(),
+            name: render_name(name).into_owned(),
+            raw_name: name_bytes(name).map(hex::encode),
+            ftype: file_type.to_string(),
+            size: inode.size(),
+            size_on_disk: None,
+            created: None,
+            modified: None,
+            accessed: None,
+            changed: None,
+            permissions: Some(permissions.clone()),
+            owner: Some(inode.uid.to_string()),
+            group: Some(inode.gid.to_string()),
+            display: Some(format!(
+                "[{}] - {} {} {} {:>5} {}",
+                file_id,
+                permissions,
+                inode.uid,
+                inode.gid,
+                inode.size(),
+                absolute_path
+            )),
+            sig_name: None,
+            sig_mime: None,
+            sig_exts: None,
+            detected_type: None,
+            ext_mismatch: None,
+            md5: None,
+            sha1: None,
+            sha256: None,
+            metadata,
+        }
+    }
+
+    fn get_root_file_id(&self) -> u64 {
+        self.superblock.location + ROOT_INODE
+    }
+
+    fn file_holes(
+        &mut self,
+        inode: &Self::FileType,
+    ) -> Result<Option<Vec<ByteRange>>, Box<dyn Error>> {
+        if unix_ftype(inode.mode as u32) != "file" {
+            return Ok(Some(Vec::new()));
+        }
+        let size = inode.size as u64;
+        let mut holes: Vec<ByteRange> = Vec::new();
+        for index in 0..size.div_ceil(BLOCK_SIZE) {
+            if self.block(inode, index)?.1 != 0 {
+                continue;
+            }
+            let start = index * BLOCK_SIZE;
+            let length = BLOCK_SIZE.min(size - start);
+            match holes.last_mut() {
+                Some((offset, len)) if *offset + *len == start => *len += length,
+                _ => holes.push((start, length)),
+            }
+        }
+        Ok(Some(holes))
+    }
+}
@@ -0,0 +1,145 @@
+//! Chain-of-custody manifests for extracted content: where the evidence came from, which
+//! bytes of the image each output was read from, its digests and the tool version, with
+//! an optional detached ed25519 signature.
+use crate::filesystem::Filesystem;
+use crate::hashing::FileHashes;
+use ed25519_dalek::{Signer, SigningKey};
+use serde::Serialize;
+use serde_json::json;
+use sha2::{Digest, Sha256};
+use std::error::Error;
+use std::fs;
+use std::path::Path;
+
+/// Extension appended to the manifest path for the detached signature.
+pub const SIGNATURE_EXTENSION: &str = "sig";
+
+/// The image the extraction was performed on.
+#[derive(Debug, Clone, Serialize)]
+pub struct EvidenceSource {
+    pub path: String,
+    /// Container format given to the body reader (`raw`, `ewf`, `auto`, ...).
+    pub format: String,
+    pub partition_offset: u64,
+    /// Partition size in sectors, as given on the command line.
+    pub partition_sectors: Option<u64>,
+    /// Shadow copy the records were read from (`--snapshot`), rather than the live volume.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub snapshot: Option<String>,
+    pub filesystem: String,
+}
+
+/// Bytes of the image holding part of a record's content.
+#[derive(Debug, Clone, Copy, Serialize)]
+pub struct ByteRun {
+    /// Absolute offset in the image (partition offset included).
+    pub offset: u64,
+    pub length: u64,
+}
+
+/// One extracted record.
+#[derive(Debug, Clone, Serialize)]
+pub struct CustodyEntry {
+    pub identifier: u64,
+    /// Path of the record when it is known (records selected by identifier have none).
+    pub source_path: Option<String>,
+    /// Where the content was written (a file path, or a path inside an archive).
+    pub output: String,
+    pub size: u64,
+    #[serde(flatten)]
+    pub hashes: FileHashes,
+    /// `None` when the backend cannot map the record to blocks (resident data, folders).
+    pub byte_runs: Option<Vec<ByteRun>>,
+    pub extracted_at: String,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub error: Option<String>,
+}
+
+#[derive(Debug, Clone, Serialize)]
+pub struct CustodyManifest {
+    pub tool: String,
+    pub version: String,
+    pub created: String,
+    pub evidence: EvidenceSource,
+    pub entries: Vec<CustodyEntry>,
+}
+
+/// Current time as an RFC 3339 UTC string.
+pub fn now() -> String {
+    jiff::Timestamp::now().to_string()
+}
+
+/// Image byte runs of `record`, clipped to its logical size.
+pub fn byte_runs<F: Filesystem + ?Sized>(
+    fs: &mut F,
+    record: &F::FileType,
+    size: u64,
+    partition_offset: u64,
+) -> Option<Vec<ByteRun>> {
+    let block_size = fs.block_size();
+    let runs = fs.file_block_runs(record).ok()??;
+    let mut remaining = size;
+    let mut out = Vec::with_capacity(runs.len());
+    for (block, count) in runs {
+        if remaining == 0 {
+            break;
+        }
+        let length = (count * block_size).min(remaining);
+        out.push(ByteRun {
+            offset: partition_offset + block * block_size,
+            length,
+        });
+        remaining -= length;
+    }
+    Some(out)
+}
+
+/// Read an ed25519 signing key: a 32 byte seed, raw or hex encoded.
+pub fn load_signing_key(path: &str) -> Result<SigningKey, Box<dyn Error>> {
+    let data =
+        fs::read(path).map_err(|e| format!("could not read signing key '{}': {}", path, e))?;
+    let seed = match hex::decode(String::from_utf8_lossy(&data).trim()) {
+        Ok(seed) => seed,
+        Err(_) => data,
+    };
+    let seed: [u8; 32] = seed.as_slice().try_into().map_err(|_| {
+        format!(
+            "signing key '{}' must hold a 32 byte ed25519 seed (raw or hex)",
+            path
+        )
+    })?;
+    Ok(SigningKey::from_bytes(&seed))
+}
+
+impl CustodyManifest {
+    pub fn new(evidence: EvidenceSource) -> Self {
+        Self {
+            tool: env!("CARGO_PKG_NAME").to_string(),
+            version: env!("CARGO_PKG_VERSION").to_string(),
+            created: now(),
+            evidence,
+            entries: Vec::new(),
+        }
+    }
+
+    /// Write the manifest to `path` and, with a key, the detached signature of its exact
+    /// bytes next to it (`<path>.sig`).
+    pub fn write(&self, path: &Path, key: Option<&SigningKey>) -> Result<(), Box<dyn Error>> {
+        let data = serde_json::to_vec_pretty(self)?;
+        fs::write(path, &data)?;
+        if let Some(key) = key {
+            let signature = key.sign(&data);
+            let detached = json!({
+                "algorithm": "ed25519",
+                "manifest_sha256": hex::encode(Sha256::digest(&data)),
+                "public_key": hex::encode(key.verifying_key().to_bytes()),
+                "signature": hex::encode(signature.to_bytes()),
+            });
+            let mut sig_path = path.as_os_str().to_owned();
+            sig_path.push(".");
+            sig_path.push(SIGNATURE_EXTENSION);
+            fs::write(sig_path, serde_json::to_vec_pretty(&detached)?)?;
+        }
+        Ok(())
+    }
+}
@@ -0,0 +1,106 @@
+//! Duplicate content detection: regular files are grouped by size first, and only the
+//! sizes shared by several files are hashed to confirm identical content.
+use crate::filesystem::{Filesystem, FsFileReadSeek, WalkEvent, WalkOptions};
+use crate::hashing::{FileHashes, HashAlgorithm, hash_reader};
+use crate::search::ExcludeSet;
+use log::warn;
+use serde::Serialize;
+use std::collections::{BTreeMap, HashMap};
+use std::error::Error;
+
+/// One copy of a duplicated content.
+#[derive(Debug, Clone, Serialize)]
+pub struct DuplicateFile {
+    pub identifier: u64,
+    pub path: String,
+}
+
+/// Files sharing the same content.
+#[derive(Debug, Clone, Serialize)]
+pub struct DuplicateCluster {
+    pub size: u64,
+    #[serde(flatten)]
+    pub hashes: FileHashes,
+    pub files: Vec<DuplicateFile>,
+}
+
+impl DuplicateCluster {
+    /// Bytes that would be freed by keeping a single copy.
+    pub fn wasted(&self) -> u64 {
+        self.size * (self.files.len() as u64 - 1)
+    }
+}
+
+fn is_regular(ftype: &str) -> bool {
+    ftype.eq_ignore_ascii_case("file") || ftype.eq_ignore_ascii_case("regular")
+}
+
+/// Find the regular files of at least `min_size` bytes (never empty ones) whose content is
+/// identical according to `algorithms`. Clusters are returned largest waste first.
+pub fn find_duplicates<F: Filesystem + ?Sized>(
+    fs: &mut F,
+    algorithms: &[HashAlgorithm],
+    min_size: u64,
+    exclude: Option<&ExcludeSet>,
+    on_status: &mut dyn FnMut(String),
+) -> Result<Vec<DuplicateCluster>, Box<dyn Error>> {
+    let min_size = min_size.max(1);
+    let mut by_size: BTreeMap<u64, Vec<DuplicateFile>> = BTreeMap::new();
+    fs.walk_fs_with(
+        WalkOptions {
+            exclude,
+            ..Default::default()
+        },
+        &mut |event| match event {
+            WalkEvent::File(file) if file.size >= min_size && is_regular(&file.ftype) => {
+                by_size.entry(file.size).or_default().push(DuplicateFile {
+                    identifier: file.identifier,
+                    path: file.absolute_path,
+                });
+            }
+            WalkEvent::Status(msg) => on_status(msg),
+            _ => {}
+        },
+    )?;
+    by_size.retain(|_, files| files.len() > 1);
+    let candidates: usize = by_size.values().map(Vec::len).sum();
+    on_status(format!(
+        "{} candidate files in {} size groups",
+        candidates,
+        by_size.len()
+    ));
+
+    let mut clusters = Vec::new();
+    for (size, files) in by_size {
+        let mut by_digest: HashMap<FileHashes, Vec<DuplicateFile>> = HashMap::new();
+        for file in files {
+            let digest = FsFileReadSeek::from_id(fs, file.identifier)
+                .and_then(|mut reader| Ok(hash_reader(&mut reader, algorithms)?));
+            match digest {
+                Ok((hashes, read)) if read == size => {
+                    by_digest.entry(hashes).or_default().push(file)
+                }
+                Ok((_, read)) => warn!("Short read on {}: {} of {} bytes", file.path, read, size),
+                Err(e) => warn!("Could not hash {}: {}", file.path, e),
+            }
+        }
+        for (hashes, files) in by_digest {
+            if files.len() > 1 {
+                clusters.push(DuplicateCluster {
+                    size,
+                    hashes,
+                    files,
+                });
+            }
+        }
+    }
+    for cluster in &mut clusters {
+        cluster.files.sort_by(|a, b| a.path.cmp(&b.path));
+    }
+    clusters.sort_by(|a, b| {
+        b.wasted()
+            .cmp(&a.wasted())
+            .then_with(|| a.files[0].path.cmp(&b.files[0].path))
+    });
+    Ok(clusters)
+}
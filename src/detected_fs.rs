@@ -1,14 +1,35 @@
 use crate::apfs_impl::ApfsFs;
-use crate::filesystem::{DirectoryCommon, File, FileCommon, Filesystem};
+use crate::audit;
+use crate::cache::{self, BlockCache, ReadBuffer};
+use crate::cramfs_impl::CramFS;
+use crate::exfat_impl::ExfatFS;
+use crate::f2fs_impl::F2fsFS;
+use crate::filesystem::{
+    BlockRun, ByteRange, DirectoryCommon, ExtendedAttribute, File, FileCommon, Filesystem,
+    WalkOptions,
+};
 use crate::folder_impl::FolderFS;
+use crate::hfs_impl::HfsFS;
+use crate::overlay::OverlayFS;
+use crate::partitions::detect_sector_size;
+use crate::refs_impl::RefsFS;
+use crate::snapshots::ShadowCopyStream;
+use crate::squashfs_impl::SquashFS;
+use crate::throttle::{self, Throttled};
+use crate::tolerant::{self, Tolerant};
+use crate::ubifs_impl::UbifsFS;
+use crate::udf_impl::UdfFS;
+use crate::ufs_impl::UfsFS;
+use crate::yaffs2_impl::{self, Yaffs2FS};
+use crate::zfs_impl::ZfsFS;
 use exhume_apfs::APFS;
 use exhume_body::{Body, BodySlice};
-use exhume_exfat::ExFatFS;
 use exhume_extfs::ExtFS;
 use exhume_ntfs::NTFS;
 use exhume_ntfs::bitlocker::BitLockerStream;
 use log::info;
 use serde_json::Value;
+use std::collections::HashMap;
 use std::error::Error;
 use std::io::{self, Read, Seek, SeekFrom};
 
@@ -17,9 +38,20 @@ pub struct KeyMaterial {
     pub bitlocker_fvek: Option<Vec<u8>>,
 }
 
+#[allow(clippy::large_enum_variant)]
 pub enum ImageStream {
     Raw(BodySlice),
     BitLocker(BitLockerStream<BodySlice>),
+    /// A volume shadow copy of the partition.
+    Shadow(Box<ShadowCopyStream<ImageStream>>),
+    /// Any of the above behind the shared block cache.
+    Cached(Box<BlockCache<ImageStream>>),
+    /// Any of the above under the read-rate limit.
+    Throttled(Box<Throttled<ImageStream>>),
+    /// Any of the above behind a read buffer coalescing small reads.
+    Buffered(Box<ReadBuffer<ImageStream>>),
+    /// Any of the above zero-filling the sectors it cannot read.
+    Tolerant(Box<Tolerant<ImageStream>>),
 }
 
 impl Read for ImageStream {
@@ -27,6 +59,11 @@ impl Read for ImageStream {
         match self {
             ImageStream::Raw(slice) => slice.read(buf),
             ImageStream::BitLocker(bl) => bl.read(buf),
+            ImageStream::Shadow(shadow) => shadow.read(buf),
+            ImageStream::Cached(cached) => cached.read(buf),
+            ImageStream::Throttled(throttled) => throttled.read(buf),
+            ImageStream::Buffered(buffered) => buffered.read(buf),
+            ImageStream::Tolerant(tolerant) => tolerant.read(buf),
         }
     }
 }
@@ -36,6 +73,11 @@ impl Seek for ImageStream {
         match self {
             ImageStream::Raw(slice) => slice.seek(pos),
             ImageStream::BitLocker(bl) => bl.seek(pos),
+            ImageStream::Shadow(shadow) => shadow.seek(pos),
+            ImageStream::Cached(cached) => cached.seek(pos),
+            ImageStream::Throttled(throttled) => throttled.seek(pos),
+            ImageStream::Buffered(buffered) => buffered.seek(pos),
+            ImageStream::Tolerant(tolerant) => tolerant.seek(pos),
         }
     }
 }
@@ -44,17 +86,40 @@ impl Seek for ImageStream {
 pub enum DetectedFs<T: Read + Seek> {
     Ext(ExtFS<T>),
     Ntfs(NTFS<T>),
-    Exfat(ExFatFS<T>),
+    Exfat(ExfatFS<T>),
     Apfs(ApfsFs<T>),
     Folder(FolderFS),
+    Overlay(OverlayFS),
+    Zfs(ZfsFS<T>),
+    Squashfs(SquashFS<T>),
+    Udf(UdfFS<T>),
+    F2fs(F2fsFS<T>),
+    Ufs(UfsFS<T>),
+    Refs(RefsFS<T>),
+    Hfs(HfsFS<T>),
+    Ubifs(UbifsFS<T>),
+    Yaffs2(Yaffs2FS<T>),
+    Cramfs(CramFS<T>),
 }
 
+#[allow(clippy::large_enum_variant)]
 pub enum DetectedFile {
     Ext(exhume_extfs::inode::Inode),
     Ntfs(exhume_ntfs::mft::MFTRecord),
     Exfat(exhume_exfat::exinode::ExInode),
     Apfs(crate::apfs_impl::ApfsFileRecord),
     Folder(crate::folder_impl::FolderFile),
+    Overlay(crate::overlay::OverlayRecord),
+    Zfs(crate::zfs_impl::ZfsFile),
+    Squashfs(crate::squashfs_impl::SquashInode),
+    Udf(crate::udf_impl::UdfFile),
+    F2fs(crate::f2fs_impl::F2fsInode),
+    Ufs(crate::ufs_impl::UfsInode),
+    Refs(crate::refs_impl::RefsFile),
+    Hfs(crate::hfs_impl::HfsFile),
+    Ubifs(crate::ubifs_impl::UbifsInode),
+    Yaffs2(crate::yaffs2_impl::YaffsObject),
+    Cramfs(crate::cramfs_impl::CramInode),
 }
 
 pub enum DetectedDir {
@@ -63,6 +128,17 @@ pub enum DetectedDir {
     Exfat(exhume_exfat::compat::CompatDirEntry),
     Apfs(crate::apfs_impl::ApfsDirectoryEntry),
     Folder(crate::folder_impl::FolderDirectory),
+    Overlay(crate::overlay::OverlayDirectory),
+    Zfs(crate::zfs_impl::ZfsDirEntry),
+    Squashfs(crate::squashfs_impl::SquashDirEntry),
+    Udf(crate::udf_impl::UdfDirEntry),
+    F2fs(crate::f2fs_impl::F2fsDirEntry),
+    Ufs(crate::ufs_impl::UfsDirEntry),
+    Refs(crate::refs_impl::RefsDirEntry),
+    Hfs(crate::hfs_impl::HfsDirEntry),
+    Ubifs(crate::ubifs_impl::UbifsDirEntry),
+    Yaffs2(crate::yaffs2_impl::YaffsDirEntry),
+    Cramfs(crate::cramfs_impl::CramDirEntry),
 }
 
 impl FileCommon for DetectedFile {
@@ -73,6 +149,17 @@ impl FileCommon for DetectedFile {
             DetectedFile::Exfat(inode) => inode.id(),
             DetectedFile::Apfs(inode) => inode.id(),
             DetectedFile::Folder(file) => file.id(),
+            DetectedFile::Overlay(file) => file.id(),
+            DetectedFile::Zfs(inode) => inode.id(),
+            DetectedFile::Squashfs(inode) => inode.id(),
+            DetectedFile::Udf(inode) => inode.id(),
+            DetectedFile::F2fs(inode) => inode.id(),
+            DetectedFile::Ufs(inode) => inode.id(),
+            DetectedFile::Refs(inode) => inode.id(),
+            DetectedFile::Hfs(inode) => inode.id(),
+            DetectedFile::Ubifs(inode) => inode.id(),
+            DetectedFile::Yaffs2(inode) => inode.id(),
+            DetectedFile::Cramfs(inode) => inode.id(),
         }
     }
     fn size(&self) -> u64 {
@@ -82,6 +169,17 @@ impl FileCommon for DetectedFile {
             DetectedFile::Exfat(inode) => inode.size(),
             DetectedFile::Apfs(inode) => inode.size(),
             DetectedFile::Folder(file) => file.size(),
+            DetectedFile::Overlay(file) => file.size(),
+            DetectedFile::Zfs(inode) => inode.size(),
+            DetectedFile::Squashfs(inode) => inode.size(),
+            DetectedFile::Udf(inode) => inode.size(),
+            DetectedFile::F2fs(inode) => inode.size(),
+            DetectedFile::Ufs(inode) => inode.size(),
+            DetectedFile::Refs(inode) => inode.size(),
+            DetectedFile::Hfs(inode) => inode.size(),
+            DetectedFile::Ubifs(inode) => inode.size(),
+            DetectedFile::Yaffs2(inode) => inode.size(),
+            DetectedFile::Cramfs(inode) => inode.size(),
         }
     }
     fn is_dir(&self) -> bool {
@@ -91,6 +189,17 @@ impl FileCommon for DetectedFile {
             DetectedFile::Exfat(inode) => inode.is_dir(),
             DetectedFile::Apfs(inode) => inode.is_dir(),
             DetectedFile::Folder(file) => file.is_dir(),
+            DetectedFile::Overlay(file) => file.is_dir(),
+            DetectedFile::Zfs(inode) => inode.is_dir(),
+            DetectedFile::Squashfs(inode) => inode.is_dir(),
+            DetectedFile::Udf(inode) => inode.is_dir(),
+            DetectedFile::F2fs(inode) => inode.is_dir(),
+            DetectedFile::Ufs(inode) => inode.is_dir(),
+            DetectedFile::Refs(inode) => inode.is_dir(),
+            DetectedFile::Hfs(inode) => inode.is_dir(),
+            DetectedFile::Ubifs(inode) => inode.is_dir(),
+            DetectedFile::Yaffs2(inode) => inode.is_dir(),
+            DetectedFile::Cramfs(inode) => inode.is_dir(),
         }
     }
     fn to_string(&self) -> String {
@@ -100,6 +209,17 @@ impl FileCommon for DetectedFile {
             DetectedFile::Exfat(inode) => FileCommon::to_string(inode),
             DetectedFile::Apfs(inode) => FileCommon::to_string(inode),
             DetectedFile::Folder(file) => FileCommon::to_string(file),
+            DetectedFile::Overlay(file) => FileCommon::to_string(file),
+            DetectedFile::Zfs(inode) => FileCommon::to_string(inode),
+            DetectedFile::Squashfs(inode) => FileCommon::to_string(inode),
+            DetectedFile::Udf(inode) => FileCommon::to_string(inode),
+            DetectedFile::F2fs(inode) => FileCommon::to_string(inode),
+            DetectedFile::Ufs(inode) => FileCommon::to_string(inode),
+            DetectedFile::Refs(inode) => FileCommon::to_string(inode),
+            DetectedFile::Hfs(inode) => FileCommon::to_string(inode),
+            DetectedFile::Ubifs(inode) => FileCommon::to_string(inode),
+            DetectedFile::Yaffs2(inode) => FileCommon::to_string(inode),
+            DetectedFile::Cramfs(inode) => FileCommon::to_string(inode),
         }
     }
     fn to_json(&self) -> Value {
@@ -109,6 +229,17 @@ impl FileCommon for DetectedFile {
             DetectedFile::Exfat(inode) => inode.to_json(),
             DetectedFile::Apfs(inode) => inode.to_json(),
             DetectedFile::Folder(file) => file.to_json(),
+            DetectedFile::Overlay(file) => file.to_json(),
+            DetectedFile::Zfs(inode) => inode.to_json(),
+            DetectedFile::Squashfs(inode) => inode.to_json(),
+            DetectedFile::Udf(inode) => inode.to_json(),
+            DetectedFile::F2fs(inode) => inode.to_json(),
+            DetectedFile::Ufs(inode) => inode.to_json(),
+            DetectedFile::Refs(inode) => inode.to_json(),
+            DetectedFile::Hfs(inode) => inode.to_json(),
+            DetectedFile::Ubifs(inode) => inode.to_json(),
+            DetectedFile::Yaffs2(inode) => inode.to_json(),
+            DetectedFile::Cramfs(inode) => inode.to_json(),
         }
     }
 }
@@ -121,6 +252,17 @@ impl DirectoryCommon for DetectedDir {
             DetectedDir::Exfat(d) => d.file_id(),
             DetectedDir::Apfs(d) => d.file_id(),
             DetectedDir::Folder(d) => d.file_id(),
+            DetectedDir::Overlay(d) => d.file_id(),
+            DetectedDir::Zfs(d) => d.file_id(),
+            DetectedDir::Squashfs(d) => d.file_id(),
+            DetectedDir::Udf(d) => d.file_id(),
+            DetectedDir::F2fs(d) => d.file_id(),
+            DetectedDir::Ufs(d) => d.file_id(),
+            DetectedDir::Refs(d) => d.file_id(),
+            DetectedDir::Hfs(d) => d.file_id(),
+            DetectedDir::Ubifs(d) => d.file_id(),
+            DetectedDir::Yaffs2(d) => d.file_id(),
+            DetectedDir::Cramfs(d) => d.file_id(),
         }
     }
     fn name(&self) -> &str {
@@ -130,6 +272,17 @@ impl DirectoryCommon for DetectedDir {
             DetectedDir::Exfat(d) => d.name(),
             DetectedDir::Apfs(d) => d.name(),
             DetectedDir::Folder(d) => d.name(),
+            DetectedDir::Overlay(d) => d.name(),
+            DetectedDir::Zfs(d) => d.name(),
+            DetectedDir::Squashfs(d) => d.name(),
+            DetectedDir::Udf(d) => d.name(),
+            DetectedDir::F2fs(d) => d.name(),
+            DetectedDir::Ufs(d) => d.name(),
+            DetectedDir::Refs(d) => d.name(),
+            DetectedDir::Hfs(d) => d.name(),
+            DetectedDir::Ubifs(d) => d.name(),
+            DetectedDir::Yaffs2(d) => d.name(),
+            DetectedDir::Cramfs(d) => d.name(),
         }
     }
     fn to_string(&self) -> String {
@@ -139,6 +292,17 @@ impl DirectoryCommon for DetectedDir {
             DetectedDir::Exfat(d) => DirectoryCommon::to_string(d),
             DetectedDir::Apfs(d) => DirectoryCommon::to_string(d),
             DetectedDir::Folder(d) => DirectoryCommon::to_string(d),
+            DetectedDir::Overlay(d) => DirectoryCommon::to_string(d),
+            DetectedDir::Zfs(d) => DirectoryCommon::to_string(d),
+            DetectedDir::Squashfs(d) => DirectoryCommon::to_string(d),
+            DetectedDir::Udf(d) => DirectoryCommon::to_string(d),
+            DetectedDir::F2fs(d) => DirectoryCommon::to_string(d),
+            DetectedDir::Ufs(d) => DirectoryCommon::to_string(d),
+            DetectedDir::Refs(d) => DirectoryCommon::to_string(d),
+            DetectedDir::Hfs(d) => DirectoryCommon::to_string(d),
+            DetectedDir::Ubifs(d) => DirectoryCommon::to_string(d),
+            DetectedDir::Yaffs2(d) => DirectoryCommon::to_string(d),
+            DetectedDir::Cramfs(d) => DirectoryCommon::to_string(d),
         }
     }
     fn to_json(&self) -> Value {
@@ -148,6 +312,17 @@ impl DirectoryCommon for DetectedDir {
             DetectedDir::Exfat(d) => d.to_json(),
             DetectedDir::Apfs(d) => d.to_json(),
             DetectedDir::Folder(d) => d.to_json(),
+            DetectedDir::Overlay(d) => d.to_json(),
+            DetectedDir::Zfs(d) => d.to_json(),
+            DetectedDir::Squashfs(d) => d.to_json(),
+            DetectedDir::Udf(d) => d.to_json(),
+            DetectedDir::F2fs(d) => d.to_json(),
+            DetectedDir::Ufs(d) => d.to_json(),
+            DetectedDir::Refs(d) => d.to_json(),
+            DetectedDir::Hfs(d) => d.to_json(),
+            DetectedDir::Ubifs(d) => d.to_json(),
+            DetectedDir::Yaffs2(d) => d.to_json(),
+            DetectedDir::Cramfs(d) => d.to_json(),
         }
     }
 }
@@ -163,6 +338,17 @@ impl<T: Read + Seek> Filesystem for DetectedFs<T> {
             DetectedFs::Exfat(fs) => fs.filesystem_type(),
             DetectedFs::Apfs(fs) => fs.filesystem_type(),
             DetectedFs::Folder(fs) => fs.filesystem_type(),
+            DetectedFs::Overlay(fs) => fs.filesystem_type(),
+            DetectedFs::Zfs(fs) => fs.filesystem_type(),
+            DetectedFs::Squashfs(fs) => fs.filesystem_type(),
+            DetectedFs::Udf(fs) => fs.filesystem_type(),
+            DetectedFs::F2fs(fs) => fs.filesystem_type(),
+            DetectedFs::Ufs(fs) => fs.filesystem_type(),
+            DetectedFs::Refs(fs) => fs.filesystem_type(),
+            DetectedFs::Hfs(fs) => fs.filesystem_type(),
+            DetectedFs::Ubifs(fs) => fs.filesystem_type(),
+            DetectedFs::Yaffs2(fs) => fs.filesystem_type(),
+            DetectedFs::Cramfs(fs) => fs.filesystem_type(),
         }
     }
     fn path_separator(&self) -> String {
@@ -172,6 +358,17 @@ impl<T: Read + Seek> Filesystem for DetectedFs<T> {
             DetectedFs::Exfat(fs) => fs.path_separator(),
             DetectedFs::Apfs(fs) => fs.path_separator(),
             DetectedFs::Folder(fs) => fs.path_separator(),
+            DetectedFs::Overlay(fs) => fs.path_separator(),
+            DetectedFs::Zfs(fs) => fs.path_separator(),
+            DetectedFs::Squashfs(fs) => fs.path_separator(),
+            DetectedFs::Udf(fs) => fs.path_separator(),
+            DetectedFs::F2fs(fs) => fs.path_separator(),
+            DetectedFs::Ufs(fs) => fs.path_separator(),
+            DetectedFs::Refs(fs) => fs.path_separator(),
+            DetectedFs::Hfs(fs) => fs.path_separator(),
+            DetectedFs::Ubifs(fs) => fs.path_separator(),
+            DetectedFs::Yaffs2(fs) => fs.path_separator(),
+            DetectedFs::Cramfs(fs) => fs.path_separator(),
         }
     }
     fn record_count(&mut self) -> u64 {
@@ -181,6 +378,17 @@ impl<T: Read + Seek> Filesystem for DetectedFs<T> {
             DetectedFs::Exfat(fs) => fs.record_count(),
             DetectedFs::Apfs(fs) => fs.record_count(),
             DetectedFs::Folder(fs) => fs.record_count(),
+            DetectedFs::Overlay(fs) => fs.record_count(),
+            DetectedFs::Zfs(fs) => fs.record_count(),
+            DetectedFs::Squashfs(fs) => fs.record_count(),
+            DetectedFs::Udf(fs) => fs.record_count(),
+            DetectedFs::F2fs(fs) => fs.record_count(),
+            DetectedFs::Ufs(fs) => fs.record_count(),
+            DetectedFs::Refs(fs) => fs.record_count(),
+            DetectedFs::Hfs(fs) => fs.record_count(),
+            DetectedFs::Ubifs(fs) => fs.record_count(),
+            DetectedFs::Yaffs2(fs) => fs.record_count(),
+            DetectedFs::Cramfs(fs) => fs.record_count(),
         }
     }
     fn block_size(&self) -> u64 {
@@ -190,6 +398,17 @@ impl<T: Read + Seek> Filesystem for DetectedFs<T> {
             DetectedFs::Exfat(fs) => fs.block_size(),
             DetectedFs::Apfs(fs) => fs.block_size(),
             DetectedFs::Folder(fs) => fs.block_size(),
+            DetectedFs::Overlay(fs) => fs.block_size(),
+            DetectedFs::Zfs(fs) => fs.block_size(),
+            DetectedFs::Squashfs(fs) => fs.block_size(),
+            DetectedFs::Udf(fs) => fs.block_size(),
+            DetectedFs::F2fs(fs) => fs.block_size(),
+            DetectedFs::Ufs(fs) => fs.block_size(),
+            DetectedFs::Refs(fs) => fs.block_size(),
+            DetectedFs::Hfs(fs) => fs.block_size(),
+            DetectedFs::Ubifs(fs) => fs.block_size(),
+            DetectedFs::Yaffs2(fs) => fs.block_size(),
+            DetectedFs::Cramfs(fs) => fs.block_size(),
         }
     }
     fn get_metadata(&self) -> Result<Value, Box<dyn Error>> {
@@ -199,6 +418,17 @@ impl<T: Read + Seek> Filesystem for DetectedFs<T> {
             DetectedFs::Exfat(fs) => fs.get_metadata(),
             DetectedFs::Apfs(fs) => fs.get_metadata(),
             DetectedFs::Folder(fs) => fs.get_metadata(),
+            DetectedFs::Overlay(fs) => fs.get_metadata(),
+            DetectedFs::Zfs(fs) => fs.get_metadata(),
+            DetectedFs::Squashfs(fs) => fs.get_metadata(),
+            DetectedFs::Udf(fs) => fs.get_metadata(),
+            DetectedFs::F2fs(fs) => fs.get_metadata(),
+            DetectedFs::Ufs(fs) => fs.get_metadata(),
+            DetectedFs::Refs(fs) => fs.get_metadata(),
+            DetectedFs::Hfs(fs) => fs.get_metadata(),
+            DetectedFs::Ubifs(fs) => fs.get_metadata(),
+            DetectedFs::Yaffs2(fs) => fs.get_metadata(),
+            DetectedFs::Cramfs(fs) => fs.get_metadata(),
         }
     }
     fn get_metadata_pretty(&self) -> Result<String, Box<dyn Error>> {
@@ -208,6 +438,17 @@ impl<T: Read + Seek> Filesystem for DetectedFs<T> {
             DetectedFs::Exfat(fs) => fs.get_metadata_pretty(),
             DetectedFs::Apfs(fs) => fs.get_metadata_pretty(),
             DetectedFs::Folder(fs) => fs.get_metadata_pretty(),
+            DetectedFs::Overlay(fs) => fs.get_metadata_pretty(),
+            DetectedFs::Zfs(fs) => fs.get_metadata_pretty(),
+            DetectedFs::Squashfs(fs) => fs.get_metadata_pretty(),
+            DetectedFs::Udf(fs) => fs.get_metadata_pretty(),
+            DetectedFs::F2fs(fs) => fs.get_metadata_pretty(),
+            DetectedFs::Ufs(fs) => fs.get_metadata_pretty(),
+            DetectedFs::Refs(fs) => fs.get_metadata_pretty(),
+            DetectedFs::Hfs(fs) => fs.get_metadata_pretty(),
+            DetectedFs::Ubifs(fs) => fs.get_metadata_pretty(),
+            DetectedFs::Yaffs2(fs) => fs.get_metadata_pretty(),
+            DetectedFs::Cramfs(fs) => fs.get_metadata_pretty(),
         }
     }
     fn get_file(&mut self, file_id: u64) -> Result<Self::FileType, Box<dyn Error>> {
@@ -217,6 +458,17 @@ impl<T: Read + Seek> Filesystem for DetectedFs<T> {
             DetectedFs::Exfat(fs) => fs.get_file(file_id).map(DetectedFile::Exfat),
             DetectedFs::Apfs(fs) => fs.get_file(file_id).map(DetectedFile::Apfs),
             DetectedFs::Folder(fs) => fs.get_file(file_id).map(DetectedFile::Folder),
+            DetectedFs::Overlay(fs) => fs.get_file(file_id).map(DetectedFile::Overlay),
+            DetectedFs::Zfs(fs) => fs.get_file(file_id).map(DetectedFile::Zfs),
+            DetectedFs::Squashfs(fs) => fs.get_file(file_id).map(DetectedFile::Squashfs),
+            DetectedFs::Udf(fs) => fs.get_file(file_id).map(DetectedFile::Udf),
+            DetectedFs::F2fs(fs) => fs.get_file(file_id).map(DetectedFile::F2fs),
+            DetectedFs::Ufs(fs) => fs.get_file(file_id).map(DetectedFile::Ufs),
+            DetectedFs::Refs(fs) => fs.get_file(file_id).map(DetectedFile::Refs),
+            DetectedFs::Hfs(fs) => fs.get_file(file_id).map(DetectedFile::Hfs),
+            DetectedFs::Ubifs(fs) => fs.get_file(file_id).map(DetectedFile::Ubifs),
+            DetectedFs::Yaffs2(fs) => fs.get_file(file_id).map(DetectedFile::Yaffs2),
+            DetectedFs::Cramfs(fs) => fs.get_file(file_id).map(DetectedFile::Cramfs),
         }
     }
     fn get_file_by_path(
@@ -230,24 +482,54 @@ impl<T: Read + Seek> Filesystem for DetectedFs<T> {
             DetectedFs::Exfat(fs) => fs.get_file_by_path(path, file_id).map(DetectedFile::Exfat),
             DetectedFs::Apfs(fs) => fs.get_file_by_path(path, file_id).map(DetectedFile::Apfs),
             DetectedFs::Folder(fs) => fs.get_file_by_path(path, file_id).map(DetectedFile::Folder),
+            DetectedFs::Overlay(fs) => fs
+                .get_file_by_path(path, file_id)
+                .map(DetectedFile::Overlay),
+            DetectedFs::Zfs(fs) => fs.get_file_by_path(path, file_id).map(DetectedFile::Zfs),
+            DetectedFs::Squashfs(fs) => fs
+                .get_file_by_path(path, file_id)
+                .map(DetectedFile::Squashfs),
+            DetectedFs::Udf(fs) => fs.get_file_by_path(path, file_id).map(DetectedFile::Udf),
+            DetectedFs::F2fs(fs) => fs.get_file_by_path(path, file_id).map(DetectedFile::F2fs),
+            DetectedFs::Ufs(fs) => fs.get_file_by_path(path, file_id).map(DetectedFile::Ufs),
+            DetectedFs::Refs(fs) => fs.get_file_by_path(path, file_id).map(DetectedFile::Refs),
+            DetectedFs::Hfs(fs) => fs.get_file_by_path(path, file_id).map(DetectedFile::Hfs),
+            DetectedFs::Ubifs(fs) => fs.get_file_by_path(path, file_id).map(DetectedFile::Ubifs),
+            DetectedFs::Yaffs2(fs) => fs.get_file_by_path(path, file_id).map(DetectedFile::Yaffs2),
+            DetectedFs::Cramfs(fs) => fs.get_file_by_path(path, file_id).map(DetectedFile::Cramfs),
         }
     }
     fn read_file_content(&mut self, record: &Self::FileType) -> Result<Vec<u8>, Box<dyn Error>> {
-        match (self, record) {
+        let result = match (self, record) {
             (DetectedFs::Ext(fs), DetectedFile::Ext(inode)) => fs.read_file_content(inode),
             (DetectedFs::Ntfs(fs), DetectedFile::Ntfs(rec)) => fs.read_file_content(rec),
             (DetectedFs::Exfat(fs), DetectedFile::Exfat(inode)) => fs.read_file_content(inode),
             (DetectedFs::Apfs(fs), DetectedFile::Apfs(inode)) => fs.read_file_content(inode),
             (DetectedFs::Folder(fs), DetectedFile::Folder(file)) => fs.read_file_content(file),
+            (DetectedFs::Overlay(fs), DetectedFile::Overlay(file)) => fs.read_file_content(file),
+            (DetectedFs::Zfs(fs), DetectedFile::Zfs(inode)) => fs.read_file_content(inode),
+            (DetectedFs::Squashfs(fs), DetectedFile::Squashfs(inode)) => {
+                fs.read_file_content(inode)
+            }
+            (DetectedFs::Udf(fs), DetectedFile::Udf(inode)) => fs.read_file_content(inode),
+            (DetectedFs::F2fs(fs), DetectedFile::F2fs(inode)) => fs.read_file_content(inode),
+            (DetectedFs::Ufs(fs), DetectedFile::Ufs(inode)) => fs.read_file_content(inode),
+            (DetectedFs::Refs(fs), DetectedFile::Refs(inode)) => fs.read_file_content(inode),
+            (DetectedFs::Hfs(fs), DetectedFile::Hfs(inode)) => fs.read_file_content(inode),
+            (DetectedFs::Ubifs(fs), DetectedFile::Ubifs(inode)) => fs.read_file_content(inode),
+            (DetectedFs::Yaffs2(fs), DetectedFile::Yaffs2(inode)) => fs.read_file_content(inode),
+            (DetectedFs::Cramfs(fs), DetectedFile::Cramfs(inode)) => fs.read_file_content(inode),
             _ => Err("filesystem / record variant mismatch".into()),
-        }
+        };
+        audit::record_read("read_file_content", Some(record.id()), 0, None, &result);
+        result
     }
     fn read_file_prefix(
         &mut self,
         record: &Self::FileType,
         length: usize,
     ) -> Result<Vec<u8>, Box<dyn Error>> {
-        match (self, record) {
+        let result = match (self, record) {
             (DetectedFs::Ext(fs), DetectedFile::Ext(inode)) => fs.read_file_prefix(inode, length),
             (DetectedFs::Ntfs(fs), DetectedFile::Ntfs(rec)) => fs.read_file_prefix(rec, length),
             (DetectedFs::Exfat(fs), DetectedFile::Exfat(inode)) => {
@@ -258,8 +540,37 @@ impl<T: Read + Seek> Filesystem for DetectedFs<T> {
             (DetectedFs::Folder(fs), DetectedFile::Folder(file)) => {
                 fs.read_file_prefix(file, length)
             }
+            (DetectedFs::Overlay(fs), DetectedFile::Overlay(file)) => {
+                fs.read_file_prefix(file, length)
+            }
+            (DetectedFs::Zfs(fs), DetectedFile::Zfs(inode)) => fs.read_file_prefix(inode, length),
+            (DetectedFs::Squashfs(fs), DetectedFile::Squashfs(inode)) => {
+                fs.read_file_prefix(inode, length)
+            }
+            (DetectedFs::Udf(fs), DetectedFile::Udf(inode)) => fs.read_file_prefix(inode, length),
+            (DetectedFs::F2fs(fs), DetectedFile::F2fs(inode)) => fs.read_file_prefix(inode, length),
+            (DetectedFs::Ufs(fs), DetectedFile::Ufs(inode)) => fs.read_file_prefix(inode, length),
+            (DetectedFs::Refs(fs), DetectedFile::Refs(inode)) => fs.read_file_prefix(inode, length),
+            (DetectedFs::Hfs(fs), DetectedFile::Hfs(inode)) => fs.read_file_prefix(inode, length),
+            (DetectedFs::Ubifs(fs), DetectedFile::Ubifs(inode)) => {
+                fs.read_file_prefix(inode, length)
+            }
+            (DetectedFs::Yaffs2(fs), DetectedFile::Yaffs2(inode)) => {
+                fs.read_file_prefix(inode, length)
+            }
+            (DetectedFs::Cramfs(fs), DetectedFile::Cramfs(inode)) => {
+                fs.read_file_prefix(inode, length)
+            }
             _ => Err("filesystem / record variant mismatch".into()),
-        }
+        };
+        audit::record_read(
+            "read_file_prefix",
+            Some(record.id()),
+            0,
+            Some(length as u64),
+            &result,
+        );
+        result
     }
     fn read_file_slice(
         &mut self,
@@ -267,7 +578,7 @@ impl<T: Read + Seek> Filesystem for DetectedFs<T> {
         offset: u64,
         length: usize,
     ) -> Result<Vec<u8>, Box<dyn Error>> {
-        match (self, record) {
+        let result = match (self, record) {
             (DetectedFs::Ext(fs), DetectedFile::Ext(inode)) => {
                 fs.read_file_slice(inode, offset, length)
             }
@@ -283,8 +594,115 @@ impl<T: Read + Seek> Filesystem for DetectedFs<T> {
             (DetectedFs::Folder(fs), DetectedFile::Folder(file)) => {
                 fs.read_file_slice(file, offset, length)
             }
+            (DetectedFs::Overlay(fs), DetectedFile::Overlay(file)) => {
+                fs.read_file_slice(file, offset, length)
+            }
+            (DetectedFs::Zfs(fs), DetectedFile::Zfs(inode)) => {
+                fs.read_file_slice(inode, offset, length)
+            }
+            (DetectedFs::Squashfs(fs), DetectedFile::Squashfs(inode)) => {
+                fs.read_file_slice(inode, offset, length)
+            }
+            (DetectedFs::Udf(fs), DetectedFile::Udf(inode)) => {
+                fs.read_file_slice(inode, offset, length)
+            }
+            (DetectedFs::F2fs(fs), DetectedFile::F2fs(inode)) => {
+                fs.read_file_slice(inode, offset, length)
+            }
+            (DetectedFs::Ufs(fs), DetectedFile::Ufs(inode)) => {
+                fs.read_file_slice(inode, offset, length)
+            }
+            (DetectedFs::Refs(fs), DetectedFile::Refs(inode)) => {
+                fs.read_file_slice(inode, offset, length)
+            }
+            (DetectedFs::Hfs(fs), DetectedFile::Hfs(inode)) => {
+                fs.read_file_slice(inode, offset, length)
+            }
+            (DetectedFs::Ubifs(fs), DetectedFile::Ubifs(inode)) => {
+                fs.read_file_slice(inode, offset, length)
+            }
+            (DetectedFs::Yaffs2(fs), DetectedFile::Yaffs2(inode)) => {
+                fs.read_file_slice(inode, offset, length)
+            }
+            (DetectedFs::Cramfs(fs), DetectedFile::Cramfs(inode)) => {
+                fs.read_file_slice(inode, offset, length)
+            }
             _ => Err("filesystem / record variant mismatch".into()),
-        }
+        };
+        audit::record_read(
+            "read_file_slice",
+            Some(record.id()),
+            offset,
+            Some(length as u64),
+            &result,
+        );
+        result
+    }
+    fn read_file_slice_into(
+        &mut self,
+        record: &Self::FileType,
+        offset: u64,
+        buf: &mut [u8],
+    ) -> Result<usize, Box<dyn Error>> {
+        let result = match (self, record) {
+            (DetectedFs::Ext(fs), DetectedFile::Ext(inode)) => {
+                fs.read_file_slice_into(inode, offset, buf)
+            }
+            (DetectedFs::Ntfs(fs), DetectedFile::Ntfs(rec)) => {
+                fs.read_file_slice_into(rec, offset, buf)
+            }
+            (DetectedFs::Exfat(fs), DetectedFile::Exfat(inode)) => {
+                fs.read_file_slice_into(inode, offset, buf)
+            }
+            (DetectedFs::Apfs(fs), DetectedFile::Apfs(inode)) => {
+                fs.read_file_slice_into(inode, offset, buf)
+            }
+            (DetectedFs::Folder(fs), DetectedFile::Folder(file)) => {
+                fs.read_file_slice_into(file, offset, buf)
+            }
+            (DetectedFs::Overlay(fs), DetectedFile::Overlay(file)) => {
+                fs.read_file_slice_into(file, offset, buf)
+            }
+            (DetectedFs::Zfs(fs), DetectedFile::Zfs(inode)) => {
+                fs.read_file_slice_into(inode, offset, buf)
+            }
+            (DetectedFs::Squashfs(fs), DetectedFile::Squashfs(inode)) => {
+                fs.read_file_slice_into(inode, offset, buf)
+            }
+            (DetectedFs::Udf(fs), DetectedFile::Udf(inode)) => {
+                fs.read_file_slice_into(inode, offset, buf)
+            }
+            (DetectedFs::F2fs(fs), DetectedFile::F2fs(inode)) => {
+                fs.read_file_slice_into(inode, offset, buf)
+            }
+            (DetectedFs::Ufs(fs), DetectedFile::Ufs(inode)) => {
+                fs.read_file_slice_into(inode, offset, buf)
+            }
+            (DetectedFs::Refs(fs), DetectedFile::Refs(inode)) => {
+                fs.read_file_slice_into(inode, offset, buf)
+            }
+            (DetectedFs::Hfs(fs), DetectedFile::Hfs(inode)) => {
+                fs.read_file_slice_into(inode, offset, buf)
+            }
+            (DetectedFs::Ubifs(fs), DetectedFile::Ubifs(inode)) => {
+                fs.read_file_slice_into(inode, offset, buf)
+            }
+            (DetectedFs::Yaffs2(fs), DetectedFile::Yaffs2(inode)) => {
+                fs.read_file_slice_into(inode, offset, buf)
+            }
+            (DetectedFs::Cramfs(fs), DetectedFile::Cramfs(inode)) => {
+                fs.read_file_slice_into(inode, offset, buf)
+            }
+            _ => Err("filesystem / record variant mismatch".into()),
+        };
+        audit::record_read_into(
+            "read_file_slice_into",
+            Some(record.id()),
+            offset,
+            buf,
+            &result,
+        );
+        result
     }
     fn list_dir(
         &mut self,
@@ -303,6 +721,36 @@ impl<T: Read + Seek> Filesystem for DetectedFs<T> {
                 .map(|v| v.into_iter().map(DetectedDir::Apfs).collect()),
             (DetectedFs::Folder(fs), DetectedFile::Folder(file)) => Filesystem::list_dir(fs, file)
                 .map(|v| v.into_iter().map(DetectedDir::Folder).collect()),
+            (DetectedFs::Overlay(fs), DetectedFile::Overlay(file)) => {
+                Filesystem::list_dir(fs, file)
+                    .map(|v| v.into_iter().map(DetectedDir::Overlay).collect())
+            }
+            (DetectedFs::Zfs(fs), DetectedFile::Zfs(inode)) => Filesystem::list_dir(fs, inode)
+                .map(|v| v.into_iter().map(DetectedDir::Zfs).collect()),
+            (DetectedFs::Squashfs(fs), DetectedFile::Squashfs(inode)) => {
+                Filesystem::list_dir(fs, inode)
+                    .map(|v| v.into_iter().map(DetectedDir::Squashfs).collect())
+            }
+            (DetectedFs::Udf(fs), DetectedFile::Udf(inode)) => Filesystem::list_dir(fs, inode)
+                .map(|v| v.into_iter().map(DetectedDir::Udf).collect()),
+            (DetectedFs::F2fs(fs), DetectedFile::F2fs(inode)) => Filesystem::list_dir(fs, inode)
+                .map(|v| v.into_iter().map(DetectedDir::F2fs).collect()),
+            (DetectedFs::Ufs(fs), DetectedFile::Ufs(inode)) => Filesystem::list_dir(fs, inode)
+                .map(|v| v.into_iter().map(DetectedDir::Ufs).collect()),
+            (DetectedFs::Refs(fs), DetectedFile::Refs(inode)) => Filesystem::list_dir(fs, inode)
+                .map(|v| v.into_iter().map(DetectedDir::Refs).collect()),
+            (DetectedFs::Hfs(fs), DetectedFile::Hfs(inode)) => Filesystem::list_dir(fs, inode)
+                .map(|v| v.into_iter().map(DetectedDir::Hfs).collect()),
+            (DetectedFs::Ubifs(fs), DetectedFile::Ubifs(inode)) => Filesystem::list_dir(fs, inode)
+                .map(|v| v.into_iter().map(DetectedDir::Ubifs).collect()),
+            (DetectedFs::Yaffs2(fs), DetectedFile::Yaffs2(inode)) => {
+                Filesystem::list_dir(fs, inode)
+                    .map(|v| v.into_iter().map(DetectedDir::Yaffs2).collect())
+            }
+            (DetectedFs::Cramfs(fs), DetectedFile::Cramfs(inode)) => {
+                Filesystem::list_dir(fs, inode)
+                    .map(|v| v.into_iter().map(DetectedDir::Cramfs).collect())
+            }
             _ => Err("filesystem / record variant mismatch".into()),
         }
     }
@@ -314,6 +762,17 @@ impl<T: Read + Seek> Filesystem for DetectedFs<T> {
             DetectedFs::Exfat(fs) => fs.get_root_file_id(),
             DetectedFs::Apfs(fs) => fs.get_root_file_id(),
             DetectedFs::Folder(fs) => fs.get_root_file_id(),
+            DetectedFs::Overlay(fs) => fs.get_root_file_id(),
+            DetectedFs::Zfs(fs) => fs.get_root_file_id(),
+            DetectedFs::Squashfs(fs) => fs.get_root_file_id(),
+            DetectedFs::Udf(fs) => fs.get_root_file_id(),
+            DetectedFs::F2fs(fs) => fs.get_root_file_id(),
+            DetectedFs::Ufs(fs) => fs.get_root_file_id(),
+            DetectedFs::Refs(fs) => fs.get_root_file_id(),
+            DetectedFs::Hfs(fs) => fs.get_root_file_id(),
+            DetectedFs::Ubifs(fs) => fs.get_root_file_id(),
+            DetectedFs::Yaffs2(fs) => fs.get_root_file_id(),
+            DetectedFs::Cramfs(fs) => fs.get_root_file_id(),
         }
     }
     fn walk_fs(
@@ -326,6 +785,222 @@ impl<T: Read + Seek> Filesystem for DetectedFs<T> {
             DetectedFs::Exfat(fs) => fs.walk_fs(callback),
             DetectedFs::Apfs(fs) => fs.walk_fs(callback),
             DetectedFs::Folder(fs) => fs.walk_fs(callback),
+            DetectedFs::Overlay(fs) => fs.walk_fs(callback),
+            DetectedFs::Zfs(fs) => fs.walk_fs(callback),
+            DetectedFs::Squashfs(fs) => fs.walk_fs(callback),
+            DetectedFs::Udf(fs) => fs.walk_fs(callback),
+            DetectedFs::F2fs(fs) => fs.walk_fs(callback),
+            DetectedFs::Ufs(fs) => fs.walk_fs(callback),
+            DetectedFs::Refs(fs) => fs.walk_fs(callback),
+            DetectedFs::Hfs(fs) => fs.walk_fs(callback),
+            DetectedFs::Ubifs(fs) => fs.walk_fs(callback),
+            DetectedFs::Yaffs2(fs) => fs.walk_fs(callback),
+            DetectedFs::Cramfs(fs) => fs.walk_fs(callback),
+        }
+    }
+    fn file_block_runs(
+        &mut self,
+        file: &Self::FileType,
+    ) -> Result<Option<Vec<BlockRun>>, Box<dyn Error>> {
+        match (self, file) {
+            (DetectedFs::Ext(fs), DetectedFile::Ext(f)) => fs.file_block_runs(f),
+            (DetectedFs::Ntfs(fs), DetectedFile::Ntfs(f)) => fs.file_block_runs(f),
+            (DetectedFs::Exfat(fs), DetectedFile::Exfat(f)) => fs.file_block_runs(f),
+            (DetectedFs::Apfs(fs), DetectedFile::Apfs(f)) => fs.file_block_runs(f),
+            (DetectedFs::Folder(fs), DetectedFile::Folder(f)) => fs.file_block_runs(f),
+            (DetectedFs::Overlay(fs), DetectedFile::Overlay(f)) => fs.file_block_runs(f),
+            (DetectedFs::Zfs(fs), DetectedFile::Zfs(f)) => fs.file_block_runs(f),
+            (DetectedFs::Squashfs(fs), DetectedFile::Squashfs(f)) => fs.file_block_runs(f),
+            (DetectedFs::Udf(fs), DetectedFile::Udf(f)) => fs.file_block_runs(f),
+            (DetectedFs::F2fs(fs), DetectedFile::F2fs(f)) => fs.file_block_runs(f),
+            (DetectedFs::Ufs(fs), DetectedFile::Ufs(f)) => fs.file_block_runs(f),
+            (DetectedFs::Refs(fs), DetectedFile::Refs(f)) => fs.file_block_runs(f),
+            (DetectedFs::Hfs(fs), DetectedFile::Hfs(f)) => fs.file_block_runs(f),
+            (DetectedFs::Ubifs(fs), DetectedFile::Ubifs(f)) => fs.file_block_runs(f),
+            (DetectedFs::Yaffs2(fs), DetectedFile::Yaffs2(f)) => fs.file_block_runs(f),
+            (DetectedFs::Cramfs(fs), DetectedFile::Cramfs(f)) => fs.file_block_runs(f),
+            _ => unreachable!("filesystem / record variant mismatch"),
+        }
+    }
+    fn read_directory_data(
+        &mut self,
+        dir: &Self::FileType,
+    ) -> Result<Option<Vec<u8>>, Box<dyn Error>> {
+        match (self, dir) {
+            (DetectedFs::Ext(fs), DetectedFile::Ext(d)) => fs.read_directory_data(d),
+            (DetectedFs::Ntfs(fs), DetectedFile::Ntfs(d)) => fs.read_directory_data(d),
+            (DetectedFs::Exfat(fs), DetectedFile::Exfat(d)) => fs.read_directory_data(d),
+            (DetectedFs::Apfs(fs), DetectedFile::Apfs(d)) => fs.read_directory_data(d),
+            (DetectedFs::Folder(fs), DetectedFile::Folder(d)) => fs.read_directory_data(d),
+            (DetectedFs::Overlay(fs), DetectedFile::Overlay(d)) => fs.read_directory_data(d),
+            (DetectedFs::Zfs(fs), DetectedFile::Zfs(d)) => fs.read_directory_data(d),
+            (DetectedFs::Squashfs(fs), DetectedFile::Squashfs(d)) => fs.read_directory_data(d),
+            (DetectedFs::Udf(fs), DetectedFile::Udf(d)) => fs.read_directory_data(d),
+            (DetectedFs::F2fs(fs), DetectedFile::F2fs(d)) => fs.read_directory_data(d),
+            (DetectedFs::Ufs(fs), DetectedFile::Ufs(d)) => fs.read_directory_data(d),
+            (DetectedFs::Refs(fs), DetectedFile::Refs(d)) => fs.read_directory_data(d),
+            (DetectedFs::Hfs(fs), DetectedFile::Hfs(d)) => fs.read_directory_data(d),
+            (DetectedFs::Ubifs(fs), DetectedFile::Ubifs(d)) => fs.read_directory_data(d),
+            (DetectedFs::Yaffs2(fs), DetectedFile::Yaffs2(d)) => fs.read_directory_data(d),
+            (DetectedFs::Cramfs(fs), DetectedFile::Cramfs(d)) => fs.read_directory_data(d),
+            _ => unreachable!("filesystem / record variant mismatch"),
+        }
+    }
+    fn file_holes(
+        &mut self,
+        file: &Self::FileType,
+    ) -> Result<Option<Vec<ByteRange>>, Box<dyn Error>> {
+        match (self, file) {
+            (DetectedFs::Ext(fs), DetectedFile::Ext(f)) => fs.file_holes(f),
+            (DetectedFs::Ntfs(fs), DetectedFile::Ntfs(f)) => fs.file_holes(f),
+            (DetectedFs::Exfat(fs), DetectedFile::Exfat(f)) => fs.file_holes(f),
+            (DetectedFs::Apfs(fs), DetectedFile::Apfs(f)) => fs.file_holes(f),
+            (DetectedFs::Folder(fs), DetectedFile::Folder(f)) => fs.file_holes(f),
+            (DetectedFs::Overlay(fs), DetectedFile::Overlay(f)) => fs.file_holes(f),
+            (DetectedFs::Zfs(fs), DetectedFile::Zfs(f)) => fs.file_holes(f),
+            (DetectedFs::Squashfs(fs), DetectedFile::Squashfs(f)) => fs.file_holes(f),
+            (DetectedFs::Udf(fs), DetectedFile::Udf(f)) => fs.file_holes(f),
+            (DetectedFs::F2fs(fs), DetectedFile::F2fs(f)) => fs.file_holes(f),
+            (DetectedFs::Ufs(fs), DetectedFile::Ufs(f)) => fs.file_holes(f),
+            (DetectedFs::Refs(fs), DetectedFile::Refs(f)) => fs.file_holes(f),
+            (DetectedFs::Hfs(fs), DetectedFile::Hfs(f)) => fs.file_holes(f),
+            (DetectedFs::Ubifs(fs), DetectedFile::Ubifs(f)) => fs.file_holes(f),
+            (DetectedFs::Yaffs2(fs), DetectedFile::Yaffs2(f)) => fs.file_holes(f),
+            (DetectedFs::Cramfs(fs), DetectedFile::Cramfs(f)) => fs.file_holes(f),
+            _ => unreachable!("filesystem / record variant mismatch"),
+        }
+    }
+    fn extended_attributes(
+        &mut self,
+        file: &Self::FileType,
+    ) -> Result<Vec<ExtendedAttribute>, Box<dyn Error>> {
+        match (self, file) {
+            (DetectedFs::Ext(fs), DetectedFile::Ext(f)) => fs.extended_attributes(f),
+            (DetectedFs::Ntfs(fs), DetectedFile::Ntfs(f)) => fs.extended_attributes(f),
+            (DetectedFs::Exfat(fs), DetectedFile::Exfat(f)) => fs.extended_attributes(f),
+            (DetectedFs::Apfs(fs), DetectedFile::Apfs(f)) => fs.extended_attributes(f),
+            (DetectedFs::Folder(fs), DetectedFile::Folder(f)) => fs.extended_attributes(f),
+            (DetectedFs::Overlay(fs), DetectedFile::Overlay(f)) => fs.extended_attributes(f),
+            (DetectedFs::Zfs(fs), DetectedFile::Zfs(f)) => fs.extended_attributes(f),
+            (DetectedFs::Squashfs(fs), DetectedFile::Squashfs(f)) => fs.extended_attributes(f),
+            (DetectedFs::Udf(fs), DetectedFile::Udf(f)) => fs.extended_attributes(f),
+            (DetectedFs::F2fs(fs), DetectedFile::F2fs(f)) => fs.extended_attributes(f),
+            (DetectedFs::Ufs(fs), DetectedFile::Ufs(f)) => fs.extended_attributes(f),
+            (DetectedFs::Refs(fs), DetectedFile::Refs(f)) => fs.extended_attributes(f),
+            (DetectedFs::Hfs(fs), DetectedFile::Hfs(f)) => fs.extended_attributes(f),
+            (DetectedFs::Ubifs(fs), DetectedFile::Ubifs(f)) => fs.extended_attributes(f),
+            (DetectedFs::Yaffs2(fs), DetectedFile::Yaffs2(f)) => fs.extended_attributes(f),
+            (DetectedFs::Cramfs(fs), DetectedFile::Cramfs(f)) => fs.extended_attributes(f),
+            _ => unreachable!("filesystem / record variant mismatch"),
+        }
+    }
+    fn deleted_entry_names(&mut self) -> Result<HashMap<u64, String>, Box<dyn Error>> {
+        match self {
+            DetectedFs::Ext(fs) => fs.deleted_entry_names(),
+            DetectedFs::Ntfs(fs) => fs.deleted_entry_names(),
+            DetectedFs::Exfat(fs) => fs.deleted_entry_names(),
+            DetectedFs::Apfs(fs) => fs.deleted_entry_names(),
+            DetectedFs::Folder(fs) => fs.deleted_entry_names(),
+            DetectedFs::Overlay(fs) => fs.deleted_entry_names(),
+            DetectedFs::Zfs(fs) => fs.deleted_entry_names(),
+            DetectedFs::Squashfs(fs) => fs.deleted_entry_names(),
+            DetectedFs::Udf(fs) => fs.deleted_entry_names(),
+            DetectedFs::F2fs(fs) => fs.deleted_entry_names(),
+            DetectedFs::Ufs(fs) => fs.deleted_entry_names(),
+            DetectedFs::Refs(fs) => fs.deleted_entry_names(),
+            DetectedFs::Hfs(fs) => fs.deleted_entry_names(),
+            DetectedFs::Ubifs(fs) => fs.deleted_entry_names(),
+            DetectedFs::Yaffs2(fs) => fs.deleted_entry_names(),
+            DetectedFs::Cramfs(fs) => fs.deleted_entry_names(),
+        }
+    }
+    fn is_deleted(&self, file: &Self::FileType) -> Option<bool> {
+        match (self, file) {
+            (DetectedFs::Ext(fs), DetectedFile::Ext(f)) => fs.is_deleted(f),
+            (DetectedFs::Ntfs(fs), DetectedFile::Ntfs(f)) => fs.is_deleted(f),
+            (DetectedFs::Exfat(fs), DetectedFile::Exfat(f)) => fs.is_deleted(f),
+            (DetectedFs::Apfs(fs), DetectedFile::Apfs(f)) => fs.is_deleted(f),
+            (DetectedFs::Folder(fs), DetectedFile::Folder(f)) => fs.is_deleted(f),
+            (DetectedFs::Overlay(fs), DetectedFile::Overlay(f)) => fs.is_deleted(f),
+            (DetectedFs::Zfs(fs), DetectedFile::Zfs(f)) => fs.is_deleted(f),
+            (DetectedFs::Squashfs(fs), DetectedFile::Squashfs(f)) => fs.is_deleted(f),
+            (DetectedFs::Udf(fs), DetectedFile::Udf(f)) => fs.is_deleted(f),
+            (DetectedFs::F2fs(fs), DetectedFile::F2fs(f)) => fs.is_deleted(f),
+            (DetectedFs::Ufs(fs), DetectedFile::Ufs(f)) => fs.is_deleted(f),
+            (DetectedFs::Refs(fs), DetectedFile::Refs(f)) => fs.is_deleted(f),
+            (DetectedFs::Hfs(fs), DetectedFile::Hfs(f)) => fs.is_deleted(f),
+            (DetectedFs::Ubifs(fs), DetectedFile::Ubifs(f)) => fs.is_deleted(f),
+            (DetectedFs::Yaffs2(fs), DetectedFile::Yaffs2(f)) => fs.is_deleted(f),
+            (DetectedFs::Cramfs(fs), DetectedFile::Cramfs(f)) => fs.is_deleted(f),
+            _ => unreachable!("filesystem / record variant mismatch"),
+        }
+    }
+    fn block_allocation(&mut self, block: u64) -> Result<Option<bool>, Box<dyn Error>> {
+        match self {
+            DetectedFs::Ext(fs) => fs.block_allocation(block),
+            DetectedFs::Ntfs(fs) => fs.block_allocation(block),
+            DetectedFs::Exfat(fs) => fs.block_allocation(block),
+            DetectedFs::Apfs(fs) => fs.block_allocation(block),
+            DetectedFs::Folder(fs) => fs.block_allocation(block),
+            DetectedFs::Overlay(fs) => fs.block_allocation(block),
+            DetectedFs::Zfs(fs) => fs.block_allocation(block),
+            DetectedFs::Squashfs(fs) => fs.block_allocation(block),
+            DetectedFs::Udf(fs) => fs.block_allocation(block),
+            DetectedFs::F2fs(fs) => fs.block_allocation(block),
+            DetectedFs::Ufs(fs) => fs.block_allocation(block),
+            DetectedFs::Refs(fs) => fs.block_allocation(block),
+            DetectedFs::Hfs(fs) => fs.block_allocation(block),
+            DetectedFs::Ubifs(fs) => fs.block_allocation(block),
+            DetectedFs::Yaffs2(fs) => fs.block_allocation(block),
+            DetectedFs::Cramfs(fs) => fs.block_allocation(block),
+        }
+    }
+    fn block_allocation_range(
+        &mut self,
+        first: u64,
+        count: u64,
+    ) -> Result<Vec<Option<bool>>, Box<dyn Error>> {
+        match self {
+            DetectedFs::Ext(fs) => fs.block_allocation_range(first, count),
+            DetectedFs::Ntfs(fs) => fs.block_allocation_range(first, count),
+            DetectedFs::Exfat(fs) => fs.block_allocation_range(first, count),
+            DetectedFs::Apfs(fs) => fs.block_allocation_range(first, count),
+            DetectedFs::Folder(fs) => fs.block_allocation_range(first, count),
+            DetectedFs::Overlay(fs) => fs.block_allocation_range(first, count),
+            DetectedFs::Zfs(fs) => fs.block_allocation_range(first, count),
+            DetectedFs::Squashfs(fs) => fs.block_allocation_range(first, count),
+            DetectedFs::Udf(fs) => fs.block_allocation_range(first, count),
+            DetectedFs::F2fs(fs) => fs.block_allocation_range(first, count),
+            DetectedFs::Ufs(fs) => fs.block_allocation_range(first, count),
+            DetectedFs::Refs(fs) => fs.block_allocation_range(first, count),
+            DetectedFs::Hfs(fs) => fs.block_allocation_range(first, count),
+            DetectedFs::Ubifs(fs) => fs.block_allocation_range(first, count),
+            DetectedFs::Yaffs2(fs) => fs.block_allocation_range(first, count),
+            DetectedFs::Cramfs(fs) => fs.block_allocation_range(first, count),
+        }
+    }
+    fn walk_fs_with(
+        &mut self,
+        options: WalkOptions,
+        callback: &mut dyn FnMut(crate::filesystem::WalkEvent),
+    ) -> Result<(), Box<dyn Error>> {
+        match self {
+            DetectedFs::Ext(fs) => fs.walk_fs_with(options, callback),
+            DetectedFs::Ntfs(fs) => fs.walk_fs_with(options, callback),
+            DetectedFs::Exfat(fs) => fs.walk_fs_with(options, callback),
+            DetectedFs::Apfs(fs) => fs.walk_fs_with(options, callback),
+            DetectedFs::Folder(fs) => fs.walk_fs_with(options, callback),
+            DetectedFs::Overlay(fs) => fs.walk_fs_with(options, callback),
+            DetectedFs::Zfs(fs) => fs.walk_fs_with(options, callback),
+            DetectedFs::Squashfs(fs) => fs.walk_fs_with(options, callback),
+            DetectedFs::Udf(fs) => fs.walk_fs_with(options, callback),
+            DetectedFs::F2fs(fs) => fs.walk_fs_with(options, callback),
+            DetectedFs::Ufs(fs) => fs.walk_fs_with(options, callback),
+            DetectedFs::Refs(fs) => fs.walk_fs_with(options, callback),
+            DetectedFs::Hfs(fs) => fs.walk_fs_with(options, callback),
+            DetectedFs::Ubifs(fs) => fs.walk_fs_with(options, callback),
+            DetectedFs::Yaffs2(fs) => fs.walk_fs_with(options, callback),
+            DetectedFs::Cramfs(fs) => fs.walk_fs_with(options, callback),
         }
     }
     fn record_to_file(&self, record: &Self::FileType, inode_num: u64, absolute_path: &str) -> File {
@@ -345,11 +1020,232 @@ impl<T: Read + Seek> Filesystem for DetectedFs<T> {
             (DetectedFs::Folder(fs), DetectedFile::Folder(file)) => {
                 fs.record_to_file(file, inode_num, absolute_path)
             }
+            (DetectedFs::Overlay(fs), DetectedFile::Overlay(file)) => {
+                fs.record_to_file(file, inode_num, absolute_path)
+            }
+            (DetectedFs::Zfs(fs), DetectedFile::Zfs(inode)) => {
+                fs.record_to_file(inode, inode_num, absolute_path)
+            }
+            (DetectedFs::Squashfs(fs), DetectedFile::Squashfs(inode)) => {
+                fs.record_to_file(inode, inode_num, absolute_path)
+            }
+            (DetectedFs::Udf(fs), DetectedFile::Udf(inode)) => {
+                fs.record_to_file(inode, inode_num, absolute_path)
+            }
+            (DetectedFs::F2fs(fs), DetectedFile::F2fs(inode)) => {
+                fs.record_to_file(inode, inode_num, absolute_path)
+            }
+            (DetectedFs::Ufs(fs), DetectedFile::Ufs(inode)) => {
+                fs.record_to_file(inode, inode_num, absolute_path)
+            }
+            (DetectedFs::Refs(fs), DetectedFile::Refs(inode)) => {
+                fs.record_to_file(inode, inode_num, absolute_path)
+            }
+            (DetectedFs::Hfs(fs), DetectedFile::Hfs(inode)) => {
+                fs.record_to_file(inode, inode_num, absolute_path)
+            }
+            (DetectedFs::Ubifs(fs), DetectedFile::Ubifs(inode)) => {
+                fs.record_to_file(inode, inode_num, absolute_path)
+            }
+            (DetectedFs::Yaffs2(fs), DetectedFile::Yaffs2(inode)) => {
+                fs.record_to_file(inode, inode_num, absolute_path)
+            }
+            (DetectedFs::Cramfs(fs), DetectedFile::Cramfs(inode)) => {
+                fs.record_to_file(inode, inode_num, absolute_path)
+            }
             _ => unreachable!("filesystem / record variant mismatch"),
         }
     }
 }
 
+/// Put `stream` under the read-rate limit when one is set (see `throttle::set_rate`), then
+/// behind the read buffer (see `cache::set_read_buffer`) and a block cache of the
+/// configured capacity (see `cache::set_capacity`), each unless disabled. Cache hits are
+/// not throttled.
+fn cached(stream: ImageStream) -> io::Result<ImageStream> {
+    let stream = match tolerant::retries() {
+        Some(_) => ImageStream::Tolerant(Box::new(Tolerant::new(stream)?)),
+        None => stream,
+    };
+    let stream = match throttle::rate() {
+        Some(_) => ImageStream::Throttled(Box::new(Throttled::new(stream))),
+        None => stream,
+    };
+    let stream = match cache::read_buffer() {
+        0 => stream,
+        size => ImageStream::Buffered(Box::new(ReadBuffer::new(stream, size as usize)?)),
+    };
+    match cache::capacity() {
+        0 => Ok(stream),
+        capacity => Ok(ImageStream::Cached(Box::new(BlockCache::new(
+            stream, capacity,
+        )?))),
+    }
+}
+
+/// Backend to open a partition with, `Auto` trying each in turn.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub enum FsType {
+    #[default]
+    Auto,
+    Ext,
+    Ntfs,
+    Apfs,
+    Exfat,
+    Zfs,
+    Squashfs,
+    Udf,
+    F2fs,
+    Ufs,
+    Refs,
+    Hfs,
+    Ubifs,
+    Yaffs2,
+    Cramfs,
+}
+
+impl FsType {
+    /// `auto`, `ext`, `ntfs`, `apfs`, `exfat`, `zfs`, `squashfs`, `udf`, `f2fs`, `ufs`, `refs`,
+    /// `hfs`, `ubifs`, `yaffs2` or `cramfs`.
+    pub fn parse(value: &str) -> Result<Self, String> {
+        match value {
+            "auto" => Ok(Self::Auto),
+            "ext" => Ok(Self::Ext),
+            "ntfs" => Ok(Self::Ntfs),
+            "apfs" => Ok(Self::Apfs),
+            "exfat" => Ok(Self::Exfat),
+            "zfs" => Ok(Self::Zfs),
+            "squashfs" => Ok(Self::Squashfs),
+            "udf" => Ok(Self::Udf),
+            "f2fs" => Ok(Self::F2fs),
+            "ufs" => Ok(Self::Ufs),
+            "refs" => Ok(Self::Refs),
+            "hfs" => Ok(Self::Hfs),
+            "ubifs" => Ok(Self::Ubifs),
+            "yaffs2" => Ok(Self::Yaffs2),
+            "cramfs" => Ok(Self::Cramfs),
+            other => Err(format!(
+                "unknown filesystem type '{}' (auto, ext, ntfs, apfs, exfat, zfs, squashfs, udf, f2fs, ufs, refs, hfs, ubifs, yaffs2 or cramfs)",
+                other
+            )),
+        }
+    }
+
+    /// The filesystem whose signature starts `reader`: the OEM identifier of the NTFS and
+    /// exFAT boot sectors, the APFS container superblock magic, the ext superblock magic
+    /// or the magic of another backend (see `SIGNATURES`), else an object header in the
+    /// first written page of a YAFFS2 dump. `Auto` when none matches.
+    pub fn sniff<R: Read + Seek>(reader: &mut R) -> io::Result<Self> {
+        let mut head = [0u8; 2048];
+        reader.seek(SeekFrom::Start(0))?;
+        let mut len = 0;
+        while len < head.len() {
+            match reader.read(&mut head[len..])? {
+                0 => break,
+                n => len += n,
+            }
+        }
+        let head = &head[..len];
+        Ok(
+            match (head.get(3..11), head.get(32..36), head.get(1080..1082)) {
+                (Some(b"NTFS    "), _, _) => Self::Ntfs,
+                (Some(b"EXFAT   "), _, _) => Self::Exfat,
+                (Some(b"-FVE-FS-"), _, _) => {
+                    return Err(io::Error::other(
+                        "the partition is BitLocker-encrypted: open it with detect_filesystem and its FVEK",
+                    ));
+                }
+                (_, Some(b"NXSB"), _) => Self::Apfs,
+                (_, _, Some([0x53, 0xef])) => Self::Ext,
+                _ => {
+                    for (offset, magic, fstype) in SIGNATURES {
+                        if signature_at(reader, head, *offset, magic)? {
+                            return Ok(*fstype);
+                        }
+                    }
+                    match yaffs2_impl::geometry(reader)? {
+                        Some(_) => Self::Yaffs2,
+                        None => Self::Auto,
+                    }
+                }
+            },
+        )
+    }
+}
+
+/// Magic numbers of the other backends: offset, bytes and backend.
+const SIGNATURES: &[(u64, &[u8], FsType)] = &[
+    (0, b"hsqs", FsType::Squashfs),
+    // UDF volume recognition sequence, alone or after an ISO 9660 descriptor.
+    (32769, b"BEA01", FsType::Udf),
+    (34817, b"BEA01", FsType::Udf),
+    // F2FS superblock, little-endian.
+    (1024, &[0x10, 0x20, 0xf5, 0xf2], FsType::F2fs),
+    // UBI erase counter header, or the node magic of a bare UBIFS superblock.
+    (0, b"UBI#", FsType::Ubifs),
+    (0, &[0x31, 0x18, 0x10, 0x06], FsType::Ubifs),
+    // CramFS superblock magic in either byte order, first or after 512 bytes of padding.
+    (0, &[0x45, 0x3d, 0xcd, 0x28], FsType::Cramfs),
+    (0, &[0x28, 0xcd, 0x3d, 0x45], FsType::Cramfs),
+    (512, &[0x45, 0x3d, 0xcd, 0x28], FsType::Cramfs),
+    (512, &[0x28, 0xcd, 0x3d, 0x45], FsType::Cramfs),
+    // UFS2 and UFS1 superblock magic, little-endian.
+    (65536 + 1372, &[0x19, 0x01, 0x54, 0x19], FsType::Ufs),
+    (8192 + 1372, &[0x54, 0x19, 0x01, 0x00], FsType::Ufs),
+    // ReFS boot sector file system name.
+    (3, b"ReFS\0\0\0\0", FsType::Refs),
+    // First uberblock of the first ZFS label, little-endian.
+    (128 << 10, &[0x0c, 0xb1, 0xba, 0, 0, 0, 0, 0], FsType::Zfs),
+    // HFS master directory block signature, big-endian; short, so tried last.
+    (1024, b"BD", FsType::Hfs),
+];
+
+/// Whether `magic` is at `offset`, in `head` when it is that close to the start.
+fn signature_at<R: Read + Seek>(
+    reader: &mut R,
+    head: &[u8],
+    offset: u64,
+    magic: &[u8],
+) -> io::Result<bool> {
+    let end = offset as usize + magic.len();
+    if end <= head.len() {
+        return Ok(&head[offset as usize..end] == magic);
+    }
+    let mut found = vec![0u8; magic.len()];
+    reader.seek(SeekFrom::Start(offset))?;
+    match reader.read_exact(&mut found) {
+        Ok(()) => Ok(found == magic),
+        Err(e) if e.kind() == io::ErrorKind::UnexpectedEof => Ok(false),
+        Err(e) => Err(e),
+    }
+}
+
+impl DetectedFs<ImageStream> {
+    /// Open the filesystem of the partition of the disk image at `path` (raw or EWF)
+    /// starting at byte `offset` and spanning `size` sectors, like `--offset` and
+    /// `--size`. Sectors are the size the partition table implies, else the one recorded
+    /// by the image. Encrypted partitions need `detect_filesystem` and their keys.
+    pub fn from_image(path: &str, offset: u64, size: u64) -> Result<Self, Box<dyn Error>> {
+        let mut body = Body::new(path.to_string(), "auto");
+        let sector_size = detect_sector_size(&mut body).unwrap_or(body.get_sector_size() as u64);
+        detect_filesystem(&body, offset, size * sector_size, None)
+    }
+}
+
+impl<T: Read + Seek> DetectedFs<T> {
+    /// Open the filesystem held by `reader`, a stream over one partition, picking the
+    /// backend from its signature (see `FsType::sniff`).
+    pub fn from_reader(mut reader: T) -> Result<Self, Box<dyn Error>> {
+        match FsType::sniff(&mut reader)? {
+            FsType::Auto => Err("No supported filesystem signature found".into()),
+            fstype => {
+                reader.seek(SeekFrom::Start(0))?;
+                open_reader_as(reader, fstype)
+            }
+        }
+    }
+}
+
 pub fn detect_filesystem(
     body: &Body,
     offset: u64,
@@ -358,14 +1254,14 @@ pub fn detect_filesystem(
 ) -> Result<DetectedFs<ImageStream>, Box<dyn std::error::Error>> {
     let partition = BodySlice::new(body, offset, partition_size)
         .map_err(|e| format!("Could not create BodySlice: {e}"))?;
-    if let Ok(ext_fs) = ExtFS::new(ImageStream::Raw(partition)) {
+    if let Ok(ext_fs) = ExtFS::new(cached(ImageStream::Raw(partition))?) {
         info!("Detected an Extended filesystem.");
         return Ok(DetectedFs::Ext(ext_fs));
     }
 
     let partition = BodySlice::new(body, offset, partition_size)
         .map_err(|e| format!("Could not create BodySlice: {e}"))?;
-    if let Ok(apfs) = APFS::new(ImageStream::Raw(partition))
+    if let Ok(apfs) = APFS::new(cached(ImageStream::Raw(partition))?)
         && let Ok(apfs_fs) = ApfsFs::new(apfs)
     {
         info!("Detected an APFS filesystem/container.");
@@ -374,14 +1270,85 @@ pub fn detect_filesystem(
 
     let partition = BodySlice::new(body, offset, partition_size)
         .map_err(|e| format!("Could not create BodySlice: {e}"))?;
-    if let Ok(exfat) = ExFatFS::new(ImageStream::Raw(partition)) {
+    if let Ok(exfat) = ExfatFS::new(cached(ImageStream::Raw(partition))?) {
         info!("Detected an exFAT filesystem.");
         return Ok(DetectedFs::Exfat(exfat));
     }
 
     let partition = BodySlice::new(body, offset, partition_size)
         .map_err(|e| format!("Could not create BodySlice: {e}"))?;
-    match NTFS::new(ImageStream::Raw(partition)) {
+    if let Ok(zfs) = ZfsFS::new(cached(ImageStream::Raw(partition))?) {
+        info!("Detected a ZFS pool.");
+        return Ok(DetectedFs::Zfs(zfs));
+    }
+
+    let partition = BodySlice::new(body, offset, partition_size)
+        .map_err(|e| format!("Could not create BodySlice: {e}"))?;
+    if let Ok(squashfs) = SquashFS::new(cached(ImageStream::Raw(partition))?) {
+        info!("Detected a SquashFS image.");
+        return Ok(DetectedFs::Squashfs(squashfs));
+    }
+
+    let partition = BodySlice::new(body, offset, partition_size)
+        .map_err(|e| format!("Could not create BodySlice: {e}"))?;
+    if let Ok(udf) = UdfFS::new(cached(ImageStream::Raw(partition))?) {
+        info!("Detected a UDF volume.");
+        return Ok(DetectedFs::Udf(udf));
+    }
+
+    let partition = BodySlice::new(body, offset, partition_size)
+        .map_err(|e| format!("Could not create BodySlice: {e}"))?;
+    if let Ok(f2fs) = F2fsFS::new(cached(ImageStream::Raw(partition))?) {
+        info!("Detected an F2FS volume.");
+        return Ok(DetectedFs::F2fs(f2fs));
+    }
+
+    let partition = BodySlice::new(body, offset, partition_size)
+        .map_err(|e| format!("Could not create BodySlice: {e}"))?;
+    if let Ok(ufs) = UfsFS::new(cached(ImageStream::Raw(partition))?) {
+        info!("Detected a UFS volume.");
+        return Ok(DetectedFs::Ufs(ufs));
+    }
+
+    let partition = BodySlice::new(body, offset, partition_size)
+        .map_err(|e| format!("Could not create BodySlice: {e}"))?;
+    if let Ok(refs) = RefsFS::new(cached(ImageStream::Raw(partition))?) {
+        info!("Detected a ReFS volume.");
+        return Ok(DetectedFs::Refs(refs));
+    }
+
+    let partition = BodySlice::new(body, offset, partition_size)
+        .map_err(|e| format!("Could not create BodySlice: {e}"))?;
+    if let Ok(hfs) = HfsFS::new(cached(ImageStream::Raw(partition))?) {
+        info!("Detected an HFS volume.");
+        return Ok(DetectedFs::Hfs(hfs));
+    }
+
+    let partition = BodySlice::new(body, offset, partition_size)
+        .map_err(|e| format!("Could not create BodySlice: {e}"))?;
+    if let Ok(ubifs) = UbifsFS::new(cached(ImageStream::Raw(partition))?) {
+        info!("Detected a UBIFS volume.");
+        return Ok(DetectedFs::Ubifs(ubifs));
+    }
+
+    let partition = BodySlice::new(body, offset, partition_size)
+        .map_err(|e| format!("Could not create BodySlice: {e}"))?;
+    if let Ok(cramfs) = CramFS::new(cached(ImageStream::Raw(partition))?) {
+        info!("Detected a CramFS image.");
+        return Ok(DetectedFs::Cramfs(cramfs));
+    }
+
+    // YAFFS2 has no magic number: its geometry is guessed from the first written page.
+    let partition = BodySlice::new(body, offset, partition_size)
+        .map_err(|e| format!("Could not create BodySlice: {e}"))?;
+    if let Ok(yaffs2) = Yaffs2FS::new(cached(ImageStream::Raw(partition))?) {
+        info!("Detected a YAFFS2 dump.");
+        return Ok(DetectedFs::Yaffs2(yaffs2));
+    }
+
+    let partition = BodySlice::new(body, offset, partition_size)
+        .map_err(|e| format!("Could not create BodySlice: {e}"))?;
+    match NTFS::new(cached(ImageStream::Raw(partition))?) {
         Ok(ntfs) => {
             info!("Detected an NT filesystem.");
             return Ok(DetectedFs::Ntfs(ntfs));
@@ -394,19 +1361,23 @@ pub fn detect_filesystem(
                         .map_err(|e| format!("Could not create BodySlice for BL: {e}"))?;
 
                     match BitLockerStream::new(partition_for_bl, &fvek, 512) {
-                        Ok(bl_stream) => match NTFS::new(ImageStream::BitLocker(bl_stream)) {
-                            Ok(ntfs) => {
-                                info!("Successfully detected BitLocker-decrypted NT filesystem.");
-                                return Ok(DetectedFs::Ntfs(ntfs));
-                            }
-                            Err(err) => {
-                                return Err(format!(
-                                    "Failed to parse NTFS over BitLocker: {}",
-                                    err
-                                )
-                                .into());
+                        Ok(bl_stream) => {
+                            match NTFS::new(cached(ImageStream::BitLocker(bl_stream))?) {
+                                Ok(ntfs) => {
+                                    info!(
+                                        "Successfully detected BitLocker-decrypted NT filesystem."
+                                    );
+                                    return Ok(DetectedFs::Ntfs(ntfs));
+                                }
+                                Err(err) => {
+                                    return Err(format!(
+                                        "Failed to parse NTFS over BitLocker: {}",
+                                        err
+                                    )
+                                    .into());
+                                }
                             }
-                        },
+                        }
                         Err(err) => {
                             return Err(
                                 format!("Failed to initialize BitLocker stream: {}", err).into()
@@ -431,6 +1402,112 @@ pub fn detect_filesystem(
     Err(format!("No supported filesystem detected at offset {offset}").into())
 }
 
+/// Open the partition with the backend of `fstype` only, for when detection settles on
+/// the wrong one (e.g. an ext volume over the remnant of an NTFS boot sector). The error
+/// of that backend is returned as is; `FsType::Auto` is `detect_filesystem`.
+pub fn open_filesystem_as(
+    body: &Body,
+    offset: u64,
+    partition_size: u64,
+    keys: Option<KeyMaterial>,
+    fstype: FsType,
+) -> Result<DetectedFs<ImageStream>, Box<dyn std::error::Error>> {
+    if fstype == FsType::Auto {
+        return detect_filesystem(body, offset, partition_size, keys);
+    }
+    let stream = open_partition_stream(body, offset, partition_size, keys)?;
+    open_stream_as(stream, fstype)
+}
+
+/// Open `stream` with the backend of `fstype`, which must not be `FsType::Auto`.
+pub fn open_stream_as(
+    stream: ImageStream,
+    fstype: FsType,
+) -> Result<DetectedFs<ImageStream>, Box<dyn std::error::Error>> {
+    open_reader_as(stream, fstype)
+}
+
+/// `open_stream_as` over any stream.
+pub fn open_reader_as<T: Read + Seek>(
+    stream: T,
+    fstype: FsType,
+) -> Result<DetectedFs<T>, Box<dyn std::error::Error>> {
+    let failed = |name: &str, e: &dyn std::fmt::Display| format!("Could not open {}: {}", name, e);
+    Ok(match fstype {
+        FsType::Ext => DetectedFs::Ext(ExtFS::new(stream).map_err(|e| failed("ext", &e))?),
+        FsType::Ntfs => DetectedFs::Ntfs(NTFS::new(stream).map_err(|e| failed("NTFS", &e))?),
+        FsType::Apfs => {
+            let apfs = APFS::new(stream).map_err(|e| failed("APFS", &e))?;
+            DetectedFs::Apfs(ApfsFs::new(apfs).map_err(|e| failed("APFS", &e))?)
+        }
+        FsType::Exfat => DetectedFs::Exfat(ExfatFS::new(stream).map_err(|e| failed("exFAT", &e))?),
+        FsType::Zfs => DetectedFs::Zfs(ZfsFS::new(stream).map_err(|e| failed("ZFS", &e))?),
+        FsType::Squashfs => {
+            DetectedFs::Squashfs(SquashFS::new(stream).map_err(|e| failed("SquashFS", &e))?)
+        }
+        FsType::Udf => DetectedFs::Udf(UdfFS::new(stream).map_err(|e| failed("UDF", &e))?),
+        FsType::F2fs => DetectedFs::F2fs(F2fsFS::new(stream).map_err(|e| failed("F2FS", &e))?),
+        FsType::Ufs => DetectedFs::Ufs(UfsFS::new(stream).map_err(|e| failed("UFS", &e))?),
+        FsType::Refs => DetectedFs::Refs(RefsFS::new(stream).map_err(|e| failed("ReFS", &e))?),
+        FsType::Hfs => DetectedFs::Hfs(HfsFS::new(stream).map_err(|e| failed("HFS", &e))?),
+        FsType::Ubifs => DetectedFs::Ubifs(UbifsFS::new(stream).map_err(|e| failed("UBIFS", &e))?),
+        FsType::Yaffs2 => {
+            DetectedFs::Yaffs2(Yaffs2FS::new(stream).map_err(|e| failed("YAFFS2", &e))?)
+        }
+        FsType::Cramfs => {
+            DetectedFs::Cramfs(CramFS::new(stream).map_err(|e| failed("CramFS", &e))?)
+        }
+        FsType::Auto => return Err("a filesystem type is required".into()),
+    })
+}
+
+/// Open the partition as a raw stream, decrypted with BitLocker when an FVEK is given.
+pub fn open_partition_stream(
+    body: &Body,
+    offset: u64,
+    partition_size: u64,
+    keys: Option<KeyMaterial>,
+) -> Result<ImageStream, Box<dyn std::error::Error>> {
+    let partition = BodySlice::new(body, offset, partition_size)
+        .map_err(|e| format!("Could not create BodySlice: {e}"))?;
+    let stream = match keys.and_then(|k| k.bitlocker_fvek) {
+        Some(fvek) => ImageStream::BitLocker(
+            BitLockerStream::new(partition, &fvek, 512)
+                .map_err(|e| format!("Failed to initialize BitLocker stream: {}", e))?,
+        ),
+        None => ImageStream::Raw(partition),
+    };
+    Ok(cached(stream)?)
+}
+
+/// Replace a partition stream by the shadow copy chosen by `selector` (see
+/// `ShadowCopyStream::open`).
+pub fn select_snapshot(
+    stream: ImageStream,
+    selector: &str,
+) -> Result<ImageStream, Box<dyn std::error::Error>> {
+    Ok(ImageStream::Shadow(Box::new(ShadowCopyStream::open(
+        stream, selector,
+    )?)))
+}
+
+/// Open the filesystem of a shadow copy of the partition. Shadow copies are an NTFS
+/// feature, so only NTFS is detected.
+pub fn detect_snapshot_filesystem(
+    body: &Body,
+    offset: u64,
+    partition_size: u64,
+    keys: Option<KeyMaterial>,
+    selector: &str,
+) -> Result<DetectedFs<ImageStream>, Box<dyn std::error::Error>> {
+    let stream = open_partition_stream(body, offset, partition_size, keys)?;
+    let shadow = select_snapshot(stream, selector)?;
+    let ntfs = NTFS::new(shadow)
+        .map_err(|e| format!("Could not open NTFS in shadow copy {}: {}", selector, e))?;
+    info!("Detected an NT filesystem in shadow copy {}.", selector);
+    Ok(DetectedFs::Ntfs(ntfs))
+}
+
 pub fn detect_filesystem_from_path(
     path: &str,
 ) -> Result<DetectedFs<ImageStream>, Box<dyn std::error::Error>> {
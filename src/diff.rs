@@ -0,0 +1,337 @@
+use crate::export::csv_field;
+use crate::filesystem::{File, Filesystem, ReadSeek, WalkEvent, WalkOptions};
+use crate::hashing::{HashAlgorithm, hash_reader};
+use log::warn;
+use serde::Serialize;
+use std::collections::{BTreeMap, HashMap};
+use std::error::Error;
+use std::fmt;
+
+/// How a path differs between the baseline and the compared filesystem.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize)]
+#[serde(rename_all = "lowercase")]
+pub enum ChangeKind {
+    Added,
+    Removed,
+    Modified,
+    /// Same record (or same content) found under another path.
+    Renamed,
+}
+
+impl fmt::Display for ChangeKind {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let s = match self {
+            ChangeKind::Added => "added",
+            ChangeKind::Removed => "removed",
+            ChangeKind::Modified => "modified",
+            ChangeKind::Renamed => "renamed",
+        };
+        write!(f, "{}", s)
+    }
+}
+
+/// One difference between two filesystems, keyed by absolute path.
+#[derive(Debug, Clone, Serialize)]
+pub struct FileChange {
+    pub path: String,
+    pub change: ChangeKind,
+    /// Path in the baseline of a renamed record.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub previous_path: Option<String>,
+    /// Names of the `File` fields that differ (empty for added/removed paths).
+    pub fields: Vec<String>,
+    pub old: Option<File>,
+    pub new: Option<File>,
+}
+
+/// Walk `fs` and index every record by absolute path, hashing regular file content
+/// with `algorithms` (pass an empty slice to compare metadata only). Records with
+/// several hard links are indexed at each of their paths; content that cannot be read
+/// is reported and left without digests.
+pub fn index_by_path<F: Filesystem + ?Sized>(
+    fs: &mut F,
+    algorithms: &[HashAlgorithm],
+) -> Result<BTreeMap<String, File>, Box<dyn Error>> {
+    let mut index = BTreeMap::new();
+    let mut hash_visitor =
+        |file: &mut File, reader: &mut dyn ReadSeek| match hash_reader(reader, algorithms) {
+            Ok((hashes, _)) => hashes.apply_to(file),
+            Err(e) => warn!("Could not hash {}: {}", file.absolute_path, e),
+        };
+    let options = WalkOptions {
+        visitor: if algorithms.is_empty() {
+            None
+        } else {
+            Some(&mut hash_visitor)
+        },
+        all_names: true,
+        ..Default::default()
+    };
+    fs.walk_fs_with(options, &mut |event| {
+        if let WalkEvent::File(file) = event {
+            index.insert(file.absolute_path.clone(), file);
+        }
+    })?;
+    Ok(index)
+}
+
+/// List the fields whose values differ between two records of the same path.
+/// Digests are only compared when both sides carry them.
+pub fn changed_fields(old: &File, new: &File) -> Vec<String> {
+    let mut fields = Vec::new();
+    let mut check = |name: &str, differs: bool| {
+        if differs {
+            fields.push(name.to_string());
+        }
+    };
+    check("ftype", old.ftype != new.ftype);
+    check("size", old.size != new.size);
+    check("created", old.created != new.created);
+    check("modified", old.modified != new.modified);
+    check("accessed", old.accessed != new.accessed);
+    check("changed", old.changed != new.changed);
+    check("permissions", old.permissions != new.permissions);
+    check("owner", old.owner != new.owner);
+    check("group", old.group != new.group);
+    for (name, a, b) in [
+        ("md5", &old.md5, &new.md5),
+        ("sha1", &old.sha1, &new.sha1),
+        ("sha256", &old.sha256, &new.sha256),
+    ] {
+        if let (Some(a), Some(b)) = (a, b) {
+            check(name, a != b);
+        }
+    }
+    fields
+}
+
+/// Compare two path indexes. `ignore` lists fields that must not count as a
+/// modification (e.g. `accessed`, which changes on every read of a live system).
+pub fn diff_indexes(
+    old: &BTreeMap<String, File>,
+    new: &BTreeMap<String, File>,
+    ignore: &[String],
+) -> Vec<FileChange> {
+    let mut changes = Vec::new();
+    for (path, old_file) in old {
+        match new.get(path) {
+            None => changes.push(FileChange {
+                path: path.clone(),
+                change: ChangeKind::Removed,
+                previous_path: None,
+                fields: Vec::new(),
+                old: Some(old_file.clone()),
+                new: None,
+            }),
+            Some(new_file) => {
+                let fields: Vec<String> = changed_fields(old_file, new_file)
+                    .into_iter()
+                    .filter(|f| !ignore.contains(f))
+                    .collect();
+                if !fields.is_empty() {
+                    changes.push(FileChange {
+                        path: path.clone(),
+                        change: ChangeKind::Modified,
+                        previous_path: None,
+                        fields,
+                        old: Some(old_file.clone()),
+                        new: Some(new_file.clone()),
+                    });
+                }
+            }
+        }
+    }
+    for (path, new_file) in new {
+        if !old.contains_key(path) {
+            changes.push(FileChange {
+                path: path.clone(),
+                change: ChangeKind::Added,
+                previous_path: None,
+                fields: Vec::new(),
+                old: None,
+                new: Some(new_file.clone()),
+            });
+        }
+    }
+    changes.sort_by(|a, b| a.path.cmp(&b.path));
+    changes
+}
+
+/// Content digest shared by two records, when both carry one of the same algorithm.
+fn content_key(file: &File) -> Option<String> {
+    if file.size == 0 {
+        return None;
+    }
+    file.sha256
+        .as_ref()
+        .map(|h| format!("sha256:{}", h))
+        .or_else(|| file.sha1.as_ref().map(|h| format!("sha1:{}", h)))
+        .or_else(|| file.md5.as_ref().map(|h| format!("md5:{}", h)))
+}
+
+/// Pair removed and added paths into renames. A removed and an added record are the same
+/// file moved when they share the record identifier, type and creation time (a reused
+/// identifier gets a new creation time), or when their content digests match.
+pub fn detect_renames(changes: Vec<FileChange>, ignore: &[String]) -> Vec<FileChange> {
+    let mut by_identifier: HashMap<(u64, String, Option<u64>), Vec<usize>> = HashMap::new();
+    let mut by_content: HashMap<String, Vec<usize>> = HashMap::new();
+    for (i, change) in changes.iter().enumerate() {
+        if let (ChangeKind::Removed, Some(old)) = (change.change, &change.old) {
+            by_identifier
+                .entry((old.identifier, old.ftype.clone(), old.created))
+                .or_default()
+                .push(i);
+            if let Some(key) = content_key(old) {
+                by_content.entry(key).or_default().push(i);
+            }
+        }
+    }
+
+    let mut renamed_from: HashMap<usize, usize> = HashMap::new();
+    let mut taken = vec![false; changes.len()];
+    for (i, change) in changes.iter().enumerate() {
+        let (ChangeKind::Added, Some(new)) = (change.change, &change.new) else {
+            continue;
+        };
+        let key = (new.identifier, new.ftype.clone(), new.created);
+        let candidates = by_identifier
+            .get(&key)
+            .into_iter()
+            .chain(content_key(new).and_then(|k| by_content.get(&k)))
+            .flatten();
+        for &removed in candidates {
+            if !taken[removed] {
+                taken[removed] = true;
+                renamed_from.insert(i, removed);
+                break;
+            }
+        }
+    }
+
+    let mut renamed = Vec::new();
+    for (added, removed) in &renamed_from {
+        let old = changes[*removed].old.clone();
+        let new = changes[*added].new.clone();
+        let fields = match (&old, &new) {
+            (Some(o), Some(n)) => changed_fields(o, n)
+                .into_iter()
+                .filter(|f| !ignore.contains(f))
+                .collect(),
+            _ => Vec::new(),
+        };
+        renamed.push(FileChange {
+            path: changes[*added].path.clone(),
+            change: ChangeKind::Renamed,
+            previous_path: Some(changes[*removed].path.clone()),
+            fields,
+            old,
+            new,
+        });
+    }
+    let mut result: Vec<FileChange> = changes
+        .into_iter()
+        .enumerate()
+        .filter(|(i, _)| !taken[*i] && !renamed_from.contains_key(i))
+        .map(|(_, change)| change)
+        .chain(renamed)
+        .collect();
+    result.sort_by(|a, b| a.path.cmp(&b.path));
+    result
+}
+
+/// A change placed in time.
+#[derive(Debug, Clone, Serialize)]
+pub struct TimelineEvent {
+    /// Unix seconds: creation time of created records, modification time of modified and
+    /// renamed ones. `None` for deletions, which no surviving record dates.
+    pub time: Option<u64>,
+    pub change: ChangeKind,
+    pub path: String,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub previous_path: Option<String>,
+    pub identifier: u64,
+    pub size: u64,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub sha256: Option<String>,
+}
+
+/// Order `changes` chronologically; undated deletions come last.
+pub fn timeline(changes: &[FileChange]) -> Vec<TimelineEvent> {
+    let mut events: Vec<TimelineEvent> = changes
+        .iter()
+        .filter_map(|c| {
+            let file = c.new.as_ref().or(c.old.as_ref())?;
+            let time = match c.change {
+                ChangeKind::Added => file.created.or(file.modified),
+                ChangeKind::Modified | ChangeKind::Renamed => file.modified,
+                ChangeKind::Removed => None,
+            };
+            Some(TimelineEvent {
+                time,
+                change: c.change,
+                path: c.path.clone(),
+                previous_path: c.previous_path.clone(),
+                identifier: file.identifier,
+                size: file.size,
+                sha256: file.sha256.clone(),
+            })
+        })
+        .collect();
+    events.sort_by(|a, b| {
+        (a.time.is_none(), a.time, &a.path).cmp(&(b.time.is_none(), b.time, &b.path))
+    });
+    events
+}
+
+pub const TIMELINE_CSV_HEADER: &str = "time,change,path,previous_path,identifier,size,sha256";
+
+pub fn timeline_csv_line(event: &TimelineEvent) -> String {
+    [
+        event.time.map(|t| t.to_string()).unwrap_or_default(),
+        event.change.to_string(),
+        csv_field(&event.path),
+        csv_field(event.previous_path.as_deref().unwrap_or_default()),
+        event.identifier.to_string(),
+        event.size.to_string(),
+        event.sha256.clone().unwrap_or_default(),
+    ]
+    .join(",")
+}
+
+/// Walk both filesystems and report added, removed and modified paths.
+pub fn diff_filesystems<A: Filesystem + ?Sized, B: Filesystem + ?Sized>(
+    old: &mut A,
+    new: &mut B,
+    algorithms: &[HashAlgorithm],
+    ignore: &[String],
+) -> Result<Vec<FileChange>, Box<dyn Error>> {
+    let old_index = index_by_path(old, algorithms)?;
+    let new_index = index_by_path(new, algorithms)?;
+    Ok(diff_indexes(&old_index, &new_index, ignore))
+}
+
+pub const DIFF_CSV_HEADER: &str = "change,path,fields,old_identifier,new_identifier,old_size,new_size,old_modified,new_modified,old_sha256,new_sha256,previous_path";
+
+pub fn diff_csv_line(change: &FileChange) -> String {
+    let side =
+        |f: &Option<File>, pick: &dyn Fn(&File) -> String| f.as_ref().map(pick).unwrap_or_default();
+    [
+        change.change.to_string(),
+        csv_field(&change.path),
+        csv_field(&change.fields.join(";")),
+        side(&change.old, &|f| f.identifier.to_string()),
+        side(&change.new, &|f| f.identifier.to_string()),
+        side(&change.old, &|f| f.size.to_string()),
+        side(&change.new, &|f| f.size.to_string()),
+        side(&change.old, &|f| {
+            f.modified.map(|v| v.to_string()).unwrap_or_default()
+        }),
+        side(&change.new, &|f| {
+            f.modified.map(|v| v.to_string()).unwrap_or_default()
+        }),
+        side(&change.old, &|f| f.sha256.clone().unwrap_or_default()),
+        side(&change.new, &|f| f.sha256.clone().unwrap_or_default()),
+        csv_field(change.previous_path.as_deref().unwrap_or_default()),
+    ]
+    .join(",")
+}
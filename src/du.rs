@@ -0,0 +1,119 @@
+use crate::filesystem::{DirectoryCommon, FileCommon, Filesystem};
+use serde::Serialize;
+use std::collections::HashSet;
+use std::error::Error;
+
+/// Recursive usage of one directory.
+#[derive(Debug, Clone, Serialize)]
+pub struct DirUsage {
+    pub identifier: u64,
+    pub path: String,
+    /// Depth below the starting directory (0 for the starting directory itself).
+    pub depth: usize,
+    pub files: u64,
+    pub dirs: u64,
+    /// Sum of the logical sizes.
+    pub logical: u64,
+    /// Sum of the allocated sizes (blocks actually used on disk).
+    pub allocated: u64,
+}
+
+/// Allocated size of a record: its block runs when the backend knows them,
+/// otherwise the logical size rounded up to whole blocks.
+fn allocated_size<F: Filesystem + ?Sized>(fs: &mut F, record: &F::FileType) -> u64 {
+    let bs = fs.block_size().max(1);
+    match fs.file_block_runs(record) {
+        Ok(Some(runs)) => runs.iter().map(|(_, count)| count * bs).sum(),
+        _ => record.size().div_ceil(bs) * bs,
+    }
+}
+
+struct UsageWalk<'a, F: ?Sized> {
+    fs: &'a mut F,
+    separator: String,
+    max_depth: Option<usize>,
+    seen: HashSet<u64>,
+    out: Vec<DirUsage>,
+}
+
+impl<F: Filesystem + ?Sized> UsageWalk<'_, F> {
+    /// Compute the usage of `dir`, pushing it (and its subdirectories down to `max_depth`)
+    /// in post-order, like `du`.
+    fn visit(
+        &mut self,
+        dir_id: u64,
+        dir: &F::FileType,
+        path: &str,
+        depth: usize,
+    ) -> Result<DirUsage, Box<dyn Error>> {
+        let mut usage = DirUsage {
+            identifier: dir_id,
+            path: path.to_string(),
+            depth,
+            files: 0,
+            dirs: 0,
+            logical: dir.size(),
+            allocated: allocated_size(self.fs, dir),
+        };
+        let entries = self.fs.list_dir(dir).unwrap_or_default();
+        for entry in entries {
+            if entry.name() == "." || entry.name() == ".." {
+                continue;
+            }
+            let child_id = entry.file_id();
+            // Hard links and directory loops are only counted once.
+            if !self.seen.insert(child_id) {
+                continue;
+            }
+            let child = match self.fs.get_file(child_id) {
+                Ok(child) => child,
+                Err(_) => continue,
+            };
+            let child_path = if path == self.separator {
+                format!("{}{}", self.separator, entry.name())
+            } else {
+                format!("{}{}{}", path, self.separator, entry.name())
+            };
+            if child.is_dir() {
+                let sub = self.visit(child_id, &child, &child_path, depth + 1)?;
+                usage.dirs += sub.dirs + 1;
+                usage.files += sub.files;
+                usage.logical += sub.logical;
+                usage.allocated += sub.allocated;
+            } else {
+                usage.files += 1;
+                usage.logical += child.size();
+                usage.allocated += allocated_size(self.fs, &child);
+            }
+        }
+        if self.max_depth.is_none_or(|max| depth <= max) {
+            self.out.push(usage.clone());
+        }
+        Ok(usage)
+    }
+}
+
+/// Recursive logical and allocated sizes of `dir_id` (the root when `None`) and of its
+/// subdirectories down to `max_depth` levels, children listed before their parent.
+pub fn disk_usage<F: Filesystem + ?Sized>(
+    fs: &mut F,
+    dir_id: Option<u64>,
+    path: &str,
+    max_depth: Option<usize>,
+) -> Result<Vec<DirUsage>, Box<dyn Error>> {
+    let dir_id = dir_id.unwrap_or_else(|| fs.get_root_file_id());
+    let dir = fs.get_file(dir_id)?;
+    if !dir.is_dir() {
+        return Err(format!("record {} is not a directory", dir_id).into());
+    }
+    let separator = fs.path_separator();
+    let mut walk = UsageWalk {
+        fs,
+        separator,
+        max_depth,
+        seen: HashSet::from([dir_id]),
+        out: Vec::new(),
+    };
+    walk.visit(dir_id, &dir, path, 0)?;
+    Ok(walk.out)
+}
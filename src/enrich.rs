@@ -0,0 +1,462 @@
+//! Embedded metadata extraction: extractors read the content of a record (EXIF of
+//! images, PE headers, OLE and OOXML document properties) and their results are merged
+//! under `embedded.<extractor>` in `File.metadata`.
+use crate::filesystem::{File, FileAnalyzer, ReadSeek};
+use crate::magic::identify_reader;
+use log::debug;
+use regex::Regex;
+use serde_json::{Map, Value, json};
+use std::error::Error;
+use std::io::{BufReader, Read, SeekFrom};
+use std::sync::LazyLock;
+
+/// Key of the extracted metadata in `File.metadata`.
+pub const EMBEDDED_KEY: &str = "embedded";
+
+/// Seconds between 1601-01-01 (FILETIME epoch) and 1970-01-01.
+const FILETIME_UNIX_DELTA: u64 = 11_644_473_600;
+
+/// Reads metadata embedded in the content of some file types. Extractors are shared by
+/// the threads of parallel walks.
+pub trait Extractor: Send + Sync {
+    /// Key of the results under `embedded` (`exif`, `pe`, ...).
+    fn name(&self) -> &'static str;
+    /// Whether the extractor handles this record, usually from its `detected_type`.
+    fn accepts(&self, file: &File) -> bool;
+    /// Extract from `reader`, positioned at the start of the content. `Ok(None)` when
+    /// the content holds no such metadata.
+    fn extract(&self, reader: &mut dyn ReadSeek) -> Result<Option<Value>, Box<dyn Error>>;
+}
+
+fn detected(file: &File, kinds: &[&str]) -> bool {
+    file.detected_type
+        .as_deref()
+        .is_some_and(|t| kinds.contains(&t))
+}
+
+fn filetime_to_unix(filetime: u64) -> Option<u64> {
+    (filetime / 10_000_000).checked_sub(FILETIME_UNIX_DELTA)
+}
+
+/// EXIF tags of JPEG, TIFF, PNG, WebP and HEIF images, with decimal GPS coordinates.
+pub struct ExifExtractor;
+
+impl ExifExtractor {
+    fn coordinate(exif: &exif::Exif, value: exif::Tag, reference: exif::Tag) -> Option<f64> {
+        let field = exif.get_field(value, exif::In::PRIMARY)?;
+        let exif::Value::Rational(parts) = &field.value else {
+            return None;
+        };
+        let degrees = parts
+            .iter()
+            .zip([1.0, 60.0, 3600.0])
+            .map(|(r, div)| r.to_f64() / div)
+            .sum::<f64>();
+        let negative = exif
+            .get_field(reference, exif::In::PRIMARY)
+            .map(|f| f.display_value().to_string())
+            .is_some_and(|r| r.starts_with('S') || r.starts_with('W'));
+        Some(if negative { -degrees } else { degrees })
+    }
+}
+
+impl Extractor for ExifExtractor {
+    fn name(&self) -> &'static str {
+        "exif"
+    }
+
+    fn accepts(&self, file: &File) -> bool {
+        detected(file, &["jpeg", "tiff", "png", "webp", "heic"])
+    }
+
+    fn extract(&self, reader: &mut dyn ReadSeek) -> Result<Option<Value>, Box<dyn Error>> {
+        let exif = match exif::Reader::new().read_from_container(&mut BufReader::new(reader)) {
+            Ok(exif) => exif,
+            Err(exif::Error::NotFound(_)) => return Ok(None),
+            Err(e) => return Err(e.into()),
+        };
+        let mut tags = Map::new();
+        for field in exif.fields() {
+            if field.ifd_num != exif::In::PRIMARY || field.tag == exif::Tag::MakerNote {
+                continue;
+            }
+            let value = match &field.value {
+                exif::Value::Ascii(parts) => parts
+                    .iter()
+                    .map(|p| {
+                        String::from_utf8_lossy(p)
+                            .trim_end_matches('\0')
+                            .to_string()
+                    })
+                    .collect::<Vec<_>>()
+                    .join(", "),
+                _ => field.display_value().with_unit(&exif).to_string(),
+            };
+            tags.insert(field.tag.to_string(), Value::String(value));
+        }
+        let latitude = Self::coordinate(&exif, exif::Tag::GPSLatitude, exif::Tag::GPSLatitudeRef);
+        let longitude =
+            Self::coordinate(&exif, exif::Tag::GPSLongitude, exif::Tag::GPSLongitudeRef);
+        if let (Some(latitude), Some(longitude)) = (latitude, longitude) {
+            tags.insert(
+                String::from("gps"),
+                json!({"latitude": latitude, "longitude": longitude}),
+            );
+        }
+        Ok((!tags.is_empty()).then_some(Value::Object(tags)))
+    }
+}
+
+/// COFF and optional header fields of PE images.
+pub struct PeExtractor;
+
+/// Bytes of headers read: enough for the DOS stub, NT headers and a large section table.
+const PE_HEADER_LEN: usize = 8192;
+
+fn u16_at(data: &[u8], offset: usize) -> Option<u16> {
+    Some(u16::from_le_bytes(
+        data.get(offset..offset + 2)?.try_into().ok()?,
+    ))
+}
+
+fn u32_at(data: &[u8], offset: usize) -> Option<u32> {
+    Some(u32::from_le_bytes(
+        data.get(offset..offset + 4)?.try_into().ok()?,
+    ))
+}
+
+fn u64_at(data: &[u8], offset: usize) -> Option<u64> {
+    Some(u64::from_le_bytes(
+        data.get(offset..offset + 8)?.try_into().ok()?,
+    ))
+}
+
+fn pe_machine(machine: u16) -> String {
+    match machine {
+        0x014c => String::from("i386"),
+        0x8664 => String::from("amd64"),
+        0x01c0 => String::from("arm"),
+        0x01c4 => String::from("armnt"),
+        0xaa64 => String::from("arm64"),
+        0x0200 => String::from("ia64"),
+        0x0ebc => String::from("efi-bytecode"),
+        other => format!("0x{:04x}", other),
+    }
+}
+
+fn pe_subsystem(subsystem: u16) -> String {
+    match subsystem {
+        1 => String::from("native"),
+        2 => String::from("windows-gui"),
+        3 => String::from("windows-console"),
+        9 => String::from("windows-ce"),
+        10 => String::from("efi-application"),
+        11 => String::from("efi-boot-driver"),
+        12 => String::from("efi-runtime-driver"),
+        14 => String::from("xbox"),
+        16 => String::from("windows-boot"),
+        other => other.to_string(),
+    }
+}
+
+impl Extractor for PeExtractor {
+    fn name(&self) -> &'static str {
+        "pe"
+    }
+
+    fn accepts(&self, file: &File) -> bool {
+        detected(file, &["pe"])
+    }
+
+    fn extract(&self, reader: &mut dyn ReadSeek) -> Result<Option<Value>, Box<dyn Error>> {
+        let mut data = Vec::with_capacity(PE_HEADER_LEN);
+        reader.take(PE_HEADER_LEN as u64).read_to_end(&mut data)?;
+        let Some(nt) = u32_at(&data, 0x3c).map(|o| o as usize) else {
+            return Ok(None);
+        };
+        if data.get(nt..nt + 4) != Some(b"PE\0\0".as_slice()) {
+            return Ok(None);
+        }
+        let coff = nt + 4;
+        let truncated = || String::from("truncated PE headers");
+        let machine = u16_at(&data, coff).ok_or_else(truncated)?;
+        let section_count = u16_at(&data, coff + 2).ok_or_else(truncated)? as usize;
+        let timestamp = u32_at(&data, coff + 4).ok_or_else(truncated)?;
+        let optional_size = u16_at(&data, coff + 16).ok_or_else(truncated)? as usize;
+        let characteristics = u16_at(&data, coff + 18).ok_or_else(truncated)?;
+
+        let optional = coff + 20;
+        let mut info = json!({
+            "machine": pe_machine(machine),
+            "compile_time": timestamp,
+            "dll": characteristics & 0x2000 != 0,
+            "sections_count": section_count,
+        });
+        if let Some(magic) = u16_at(&data, optional) {
+            let pe32_plus = magic == 0x20b;
+            info["format"] = json!(if pe32_plus { "PE32+" } else { "PE32" });
+            if let Some(entry) = u32_at(&data, optional + 16) {
+                info["entry_point"] = json!(format!("0x{:x}", entry));
+            }
+            let image_base = if pe32_plus {
+                u64_at(&data, optional + 24)
+            } else {
+                u32_at(&data, optional + 28).map(u64::from)
+            };
+            if let Some(base) = image_base {
+                info["image_base"] = json!(format!("0x{:x}", base));
+            }
+            if let Some(subsystem) = u16_at(&data, optional + 68) {
+                info["subsystem"] = json!(pe_subsystem(subsystem));
+            }
+        }
+        let sections: Vec<String> = (0..section_count)
+            .map_while(|i| data.get(optional + optional_size + i * 40..)?.get(..8))
+            .map(|name| {
+                String::from_utf8_lossy(name)
+                    .trim_end_matches('\0')
+                    .to_string()
+            })
+            .collect();
+        info["sections"] = json!(sections);
+        Ok(Some(info))
+    }
+}
+
+/// Summary information properties of OLE compound documents (legacy Office, MSI).
+pub struct OleExtractor;
+
+/// Names of the `SummaryInformation` property identifiers.
+const SUMMARY_PROPERTIES: &[(u32, &str)] = &[
+    (2, "title"),
+    (3, "subject"),
+    (4, "author"),
+    (5, "keywords"),
+    (6, "comments"),
+    (7, "template"),
+    (8, "last_author"),
+    (9, "revision"),
+    (11, "last_printed"),
+    (12, "created"),
+    (13, "last_saved"),
+    (14, "pages"),
+    (15, "words"),
+    (16, "characters"),
+    (18, "application"),
+];
+
+/// Decode the first section of an OLE property set stream.
+fn property_set(data: &[u8]) -> Option<Map<String, Value>> {
+    let section = u32_at(data, 44)? as usize;
+    let count = u32_at(data, section + 4)? as usize;
+    let mut properties = Map::new();
+    for i in 0..count.min(256) {
+        let id = u32_at(data, section + 8 + i * 8)?;
+        let offset = section + u32_at(data, section + 12 + i * 8)? as usize;
+        let Some((_, name)) = SUMMARY_PROPERTIES.iter().find(|(pid, _)| *pid == id) else {
+            continue;
+        };
+        let value = match u32_at(data, offset)? & 0xffff {
+            // VT_I2, VT_I4
+            0x02 => json!(u16_at(data, offset + 4)? as i16),
+            0x03 => json!(u32_at(data, offset + 4)? as i32),
+            // VT_LPSTR
+            0x1e => {
+                let len = u32_at(data, offset + 4)? as usize;
+                let bytes = data.get(offset + 8..offset + 8 + len)?;
+                json!(String::from_utf8_lossy(bytes).trim_end_matches('\0'))
+            }
+            // VT_FILETIME: edit time is a duration, the others are dates
+            0x40 => match u64_at(data, offset + 4)? {
+                0 => continue,
+                ft => json!(filetime_to_unix(ft)),
+            },
+            _ => continue,
+        };
+        properties.insert(name.to_string(), value);
+    }
+    Some(properties)
+}
+
+impl Extractor for OleExtractor {
+    fn name(&self) -> &'static str {
+        "ole"
+    }
+
+    fn accepts(&self, file: &File) -> bool {
+        detected(file, &["ole"])
+    }
+
+    fn extract(&self, reader: &mut dyn ReadSeek) -> Result<Option<Value>, Box<dyn Error>> {
+        let mut compound = cfb::CompoundFile::open(reader)?;
+        let mut info = match compound.open_stream("/\u{5}SummaryInformation") {
+            Ok(mut stream) => {
+                let mut data = Vec::new();
+                stream.read_to_end(&mut data)?;
+                property_set(&data).unwrap_or_default()
+            }
+            Err(_) => Map::new(),
+        };
+        let macros = compound.walk().any(|entry| {
+            let name = entry.name();
+            name.eq_ignore_ascii_case("_VBA_PROJECT_CUR")
+                || name.eq_ignore_ascii_case("Macros")
+                || name.eq_ignore_ascii_case("VBA")
+        });
+        info.insert(String::from("macros"), json!(macros));
+        Ok(Some(Value::Object(info)))
+    }
+}
+
+/// Core and application properties of OOXML documents (docx, xlsx, pptx).
+pub struct OoxmlExtractor;
+
+static XML_ELEMENT: LazyLock<Regex> = LazyLock::new(|| {
+    Regex::new(r"<(?:[A-Za-z]+:)?([A-Za-z]+)(?:\s[^>]*)?>([^<]+)</").expect("valid regex")
+});
+
+/// Elements kept from `docProps/app.xml`.
+const APP_PROPERTIES: &[&str] = &[
+    "Application",
+    "AppVersion",
+    "Company",
+    "Manager",
+    "Template",
+];
+
+impl Extractor for OoxmlExtractor {
+    fn name(&self) -> &'static str {
+        "ooxml"
+    }
+
+    fn accepts(&self, file: &File) -> bool {
+        detected(file, &["docx", "xlsx", "pptx"])
+    }
+
+    fn extract(&self, reader: &mut dyn ReadSeek) -> Result<Option<Value>, Box<dyn Error>> {
+        let mut archive = zip::ZipArchive::new(reader)?;
+        let mut info = Map::new();
+        for (part, keep) in [
+            ("docProps/core.xml", None),
+            ("docProps/app.xml", Some(APP_PROPERTIES)),
+        ] {
+            let Ok(mut entry) = archive.by_name(part) else {
+                continue;
+            };
+            let mut xml = String::new();
+            entry.read_to_string(&mut xml)?;
+            for capture in XML_ELEMENT.captures_iter(&xml) {
+                let name = &capture[1];
+                if keep.is_none_or(|keep| keep.contains(&name)) {
+                    info.insert(name.to_string(), json!(capture[2].trim()));
+                }
+            }
+        }
+        let macros = archive.file_names().any(|n| n.ends_with("vbaProject.bin"));
+        info.insert(String::from("macros"), json!(macros));
+        Ok(Some(Value::Object(info)))
+    }
+}
+
+/// The extractors of an enrichment pass.
+#[derive(Default)]
+pub struct Enricher {
+    extractors: Vec<Box<dyn Extractor>>,
+}
+
+impl Enricher {
+    /// Names accepted by `from_names`.
+    pub const BUILTIN: &[&str] = &["exif", "pe", "ole", "ooxml"];
+
+    /// Build from a comma separated list of built-in extractors (`all` for every one).
+    pub fn from_names(list: &str) -> Result<Self, String> {
+        let mut enricher = Self::default();
+        for name in list.split(',').map(|n| n.trim().to_ascii_lowercase()) {
+            let names: Vec<&str> = match name.as_str() {
+                "all" => Self::BUILTIN.to_vec(),
+                "" => continue,
+                other => vec![other],
+            };
+            for name in names {
+                if enricher.extractors.iter().any(|e| e.name() == name) {
+                    continue;
+                }
+                enricher.register(match name {
+                    "exif" => Box::new(ExifExtractor),
+                    "pe" => Box::new(PeExtractor),
+                    "ole" => Box::new(OleExtractor),
+                    "ooxml" => Box::new(OoxmlExtractor),
+                    other => {
+                        return Err(format!(
+                            "unknown extractor: {} (expected {} or all)",
+                            other,
+                            Self::BUILTIN.join(", ")
+                        ));
+                    }
+                });
+            }
+        }
+        Ok(enricher)
+    }
+
+    pub fn register(&mut self, extractor: Box<dyn Extractor>) {
+        self.extractors.push(extractor);
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.extractors.is_empty()
+    }
+
+    /// Run the accepting extractors on `file` and merge their results into its metadata.
+    /// The content type is identified first when it is not known yet.
+    pub fn enrich(&self, file: &mut File, reader: &mut dyn ReadSeek) {
+        if file.detected_type.is_none()
+            && let Ok(Some(signature)) = identify_reader(reader)
+        {
+            signature.apply_to(file);
+        }
+        for extractor in &self.extractors {
+            if !extractor.accepts(file) {
+                continue;
+            }
+            if let Err(e) = reader.seek(SeekFrom::Start(0)) {
+                debug!("Could not rewind {}: {}", file.absolute_path, e);
+                return;
+            }
+            match extractor.extract(reader) {
+                Ok(Some(value)) => {
+                    if !file.metadata.is_object() {
+                        file.metadata = match file.metadata.take() {
+                            Value::Null => json!({}),
+                            other => json!({ "filesystem": other }),
+                        };
+                    }
+                    file.metadata[EMBEDDED_KEY][extractor.name()] = value;
+                }
+                Ok(None) => {}
+                Err(e) => debug!(
+                    "{} extraction failed on {}: {}",
+                    extractor.name(),
+                    file.absolute_path,
+                    e
+                ),
+            }
+        }
+        let _ = reader.seek(SeekFrom::Start(0));
+    }
+}
+
+impl FileAnalyzer for Enricher {
+    fn name(&self) -> &str {
+        "enrich"
+    }
+
+    fn analyze(
+        &mut self,
+        file: &mut File,
+        reader: &mut dyn ReadSeek,
+    ) -> Result<(), Box<dyn Error>> {
+        self.enrich(file, reader);
+        Ok(())
+    }
+}
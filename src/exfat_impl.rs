@@ -1,15 +1,60 @@
-use crate::filesystem::{DirectoryCommon, File, FileCommon, Filesystem};
+use crate::filesystem::{
+    DirectoryCommon, FLAGS_KEY, File, FileCommon, Filesystem, namespaced_metadata,
+};
+use crate::timefmt::local_time_policy;
 use exhume_exfat::compat::CompatDirEntry;
+use exhume_exfat::direntry::EntryType;
 use exhume_exfat::exinode::ExInode;
 use exhume_exfat::{BootSector, ExFatFS};
-use serde_json::Value;
+use jiff::Timestamp;
+use jiff::tz::{Offset, TimeZone};
+use log::warn;
+use serde_json::{Value, json};
 
+use std::collections::{HashMap, HashSet};
 use std::error::Error;
-use std::io::{Read, Seek};
+use std::io::{Read, Seek, SeekFrom};
 use std::path::Path;
 
-/// Minimal attribute string (read-only, hidden, system, dir, archive)
-fn exfat_attr_string(attrs: u16, is_dir: bool) -> String {
+/// FAT entries from this value on mark the end of a chain (or a bad cluster).
+const EXFAT_CHAIN_END: u32 = 0xFFFF_FFF7;
+/// Size of a directory entry.
+const ENTRY_SIZE: u64 = 32;
+/// Namespace of the directory entry fields in `File.metadata`.
+pub const METADATA_NAMESPACE: &str = "exfat";
+/// Key of the decoded timestamps under `exfat` in `File.metadata`.
+pub const TIMESTAMPS_KEY: &str = "timestamps";
+
+/// DOS attribute bits, shared by NTFS and Windows folders.
+const DOS_ATTRIBUTES: [(u32, &str); 14] = [
+    (0x0001, "readonly"),
+    (0x0002, "hidden"),
+    (0x0004, "system"),
+    (0x0010, "directory"),
+    (0x0020, "archive"),
+    (0x0040, "device"),
+    (0x0080, "normal"),
+    (0x0100, "temporary"),
+    (0x0200, "sparse"),
+    (0x0400, "reparse_point"),
+    (0x0800, "compressed"),
+    (0x1000, "offline"),
+    (0x2000, "not_content_indexed"),
+    (0x4000, "encrypted"),
+];
+
+/// Names of the DOS attributes set in `attrs`.
+pub(crate) fn dos_attr_names(attrs: u32) -> Vec<&'static str> {
+    DOS_ATTRIBUTES
+        .iter()
+        .filter(|(bit, _)| attrs & bit != 0)
+        .map(|(_, name)| *name)
+        .collect()
+}
+
+/// Minimal attribute string (read-only, hidden, system, dir, archive), shared with NTFS
+/// and Windows folders whose DOS attribute bits are the same.
+pub(crate) fn dos_attr_string(attrs: u32, is_dir: bool) -> String {
     let mut s = String::new();
     if (attrs & 0x0001) != 0 {
         s.push('R');
@@ -49,6 +94,279 @@ fn make_root_inode(bpb: &BootSector) -> ExInode {
     }
 }
 
+fn cluster_offset(bpb: &BootSector, cluster: u32) -> u64 {
+    ((bpb.cluster_heap_offset as u64) << bpb.bytes_per_sector_shift)
+        + (cluster as u64 - 2) * bpb.bytes_per_cluster()
+}
+
+/// GeneralSecondaryFlags bit of the stream extension entry: the clusters are contiguous
+/// and the FAT does not describe them.
+const NO_FAT_CHAIN: u8 = 0x02;
+
+/// (index in the file, first cluster, cluster count)
+type ClusterRun = (usize, u32, usize);
+
+/// What `ExInode` leaves out of the directory entry set of a record.
+#[derive(Clone, Copy)]
+struct EntrySet {
+    /// File directory entry (type 0x85).
+    file: [u8; ENTRY_SIZE as usize],
+    /// GeneralSecondaryFlags of the stream extension entry.
+    general_flags: u8,
+}
+
+/// Where the last chain walk stopped, so that sequential slices of a file do not walk
+/// its chain from the start again.
+#[derive(Clone, Copy)]
+struct ChainCursor {
+    record: u64,
+    start: u32,
+    index: usize,
+    cluster: u32,
+}
+
+/// An exFAT volume: `exhume_exfat::ExFatFS`, with the directory entry sets of the
+/// directories read so far and where the last cluster chain walk stopped.
+pub struct ExfatFS<T: Read + Seek> {
+    pub fs: ExFatFS<T>,
+    /// Entry sets by record number, for the directories in `loaded`.
+    entry_sets: HashMap<u64, EntrySet>,
+    /// First clusters of the directories whose entry sets were read.
+    loaded: HashSet<u32>,
+    cursor: Option<ChainCursor>,
+}
+
+impl<T: Read + Seek> ExfatFS<T> {
+    pub fn new(io: T) -> Result<Self, Box<dyn Error>> {
+        Ok(Self {
+            fs: ExFatFS::new(io)?,
+            entry_sets: HashMap::new(),
+            loaded: HashSet::new(),
+            cursor: None,
+        })
+    }
+
+    fn fat_entry(&mut self, cluster: u32) -> Result<u32, Box<dyn Error>> {
+        let bpb = &self.fs.bpb;
+        let at = ((bpb.fat_offset as u64) << bpb.bytes_per_sector_shift) + cluster as u64 * 4;
+        let mut entry = [0u8; 4];
+        self.fs.io.seek(SeekFrom::Start(at))?;
+        self.fs.io.read_exact(&mut entry)?;
+        Ok(u32::from_le_bytes(entry))
+    }
+
+    /// Entry set of `inode`, read with the rest of its directory the first time. Record
+    /// numbers hold the first cluster of the directory and the index of the file
+    /// directory entry in its chain, as `ExFatFS` numbers them. The root has none.
+    fn entry_set(&mut self, inode: &ExInode) -> Result<Option<EntrySet>, Box<dyn Error>> {
+        if inode.i_num == root_inode_num(&self.fs.bpb) {
+            return Ok(None);
+        }
+        let directory = (inode.i_num >> 32) as u32;
+        if self.loaded.insert(directory) {
+            let entries = self.fs.read_dir_entries_from_chain(directory)?;
+            let mut i = 0;
+            while i < entries.len() {
+                match entries[i].kind() {
+                    EntryType::End => break,
+                    EntryType::File => {
+                        let end = (i + 1 + entries[i].raw[1] as usize).min(entries.len());
+                        let general_flags = entries[i + 1..end]
+                            .iter()
+                            .find(|e| e.kind() == EntryType::StreamExt)
+                            .map_or(0, |e| e.raw[1]);
+                        let set = EntrySet {
+                            file: entries[i].raw,
+                            general_flags,
+                        };
+                        self.entry_sets
+                            .insert(((directory as u64) << 32) | i as u64, set);
+                        i = end;
+                    }
+                    _ => i += 1,
+                }
+            }
+        }
+        Ok(self.entry_sets.get(&inode.i_num).copied())
+    }
+
+    /// Whether the clusters of `inode` are contiguous rather than a FAT chain.
+    fn no_fat_chain(&mut self, inode: &ExInode) -> Result<bool, Box<dyn Error>> {
+        Ok(self
+            .entry_set(inode)?
+            .is_some_and(|set| set.general_flags & NO_FAT_CHAIN != 0))
+    }
+
+    /// Clusters of a directory: contiguous up to its size when flagged NoFatChain, else
+    /// its FAT chain.
+    fn directory_clusters(&mut self, inode: &ExInode) -> Result<Vec<u32>, Box<dyn Error>> {
+        let heap_end = self.fs.bpb.cluster_count as u64 + 2;
+        let cluster_size = self.fs.bpb.bytes_per_cluster();
+        if self.no_fat_chain(inode)? {
+            let count = inode.size.div_ceil(cluster_size);
+            let end = inode.first_cluster as u64 + count;
+            if inode.first_cluster < 2 || end > heap_end {
+                return Err(format!(
+                    "exFAT: clusters of directory {:#x} are outside the cluster heap",
+                    inode.i_num
+                )
+                .into());
+            }
+            return Ok((inode.first_cluster..end as u32).collect());
+        }
+        let mut clusters = Vec::new();
+        let mut cluster = inode.first_cluster;
+        // A corrupted chain may loop: a directory cannot hold more clusters than the heap.
+        while (2..heap_end).contains(&(cluster as u64)) && (clusters.len() as u64) < heap_end {
+            clusters.push(cluster);
+            cluster = self.fat_entry(cluster)?;
+        }
+        Ok(clusters)
+    }
+
+    /// Clusters holding the `first..=last` clusters of `inode`'s content, as runs.
+    ///
+    /// Files flagged NoFatChain are contiguous and their FAT entries meaningless (free or
+    /// stale); the chain of the others is followed from where the last walk stopped when
+    /// it was on the same file.
+    fn cluster_runs(
+        &mut self,
+        inode: &ExInode,
+        first: usize,
+        last: usize,
+    ) -> Result<Vec<ClusterRun>, Box<dyn Error>> {
+        let heap_end = self.fs.bpb.cluster_count as u64 + 2;
+        let outside = |cluster: u64| {
+            format!(
+                "exFAT: cluster {} of record {:#x} is outside the cluster heap",
+                cluster, inode.i_num
+            )
+        };
+        if self.no_fat_chain(inode)? {
+            let start = inode.first_cluster as u64 + first as u64;
+            let end = inode.first_cluster as u64 + last as u64;
+            if inode.first_cluster < 2 || end >= heap_end {
+                return Err(outside(end).into());
+            }
+            return Ok(vec![(first, start as u32, last - first + 1)]);
+        }
+        let mut cursor = match self.cursor {
+            Some(c)
+                if c.record == inode.i_num
+                    && c.start == inode.first_cluster
+                    && c.index <= first =>
+            {
+                c
+            }
+            _ => ChainCursor {
+                record: inode.i_num,
+                start: inode.first_cluster,
+                index: 0,
+                cluster: inode.first_cluster,
+            },
+        };
+        let mut runs: Vec<ClusterRun> = Vec::new();
+        loop {
+            if !(2..heap_end).contains(&(cursor.cluster as u64)) {
+                return Err(outside(cursor.cluster as u64).into());
+            }
+            if cursor.index >= first {
+                match runs.last_mut() {
+                    Some((_, start, count))
+                        if *start as u64 + *count as u64 == cursor.cluster as u64 =>
+                    {
+                        *count += 1
+                    }
+                    _ => runs.push((cursor.index, cursor.cluster, 1)),
+                }
+            }
+            if cursor.index == last {
+                break;
+            }
+            let next = self.fat_entry(cursor.cluster)?;
+            if !(2..EXFAT_CHAIN_END).contains(&next) {
+                return Err(format!(
+                    "exFAT: cluster chain of record {:#x} ends after {} clusters",
+                    inode.i_num,
+                    cursor.index + 1
+                )
+                .into());
+            }
+            cursor.cluster = next;
+            cursor.index += 1;
+        }
+        self.cursor = Some(cursor);
+        Ok(runs)
+    }
+}
+
+/// A decoded exFAT timestamp.
+#[derive(Debug, Clone)]
+pub struct ExfatTime {
+    /// UNIX seconds, in UTC.
+    pub seconds: i64,
+    /// Sub-second part from the 10ms increment.
+    pub milliseconds: u32,
+    /// UTC offset recorded with the timestamp, in minutes.
+    pub utc_offset: Option<i32>,
+    /// How the stored local time was converted (see `LocalTimePolicy::to_utc`).
+    pub interpretation: String,
+}
+
+/// Refine a timestamp as `ExInode` decodes it (the local time read as UTC, in UNIX
+/// seconds, negative when invalid) with its 10ms increment (0 to 199) and UTC offset
+/// field (bit 7: valid, bits 0-6: signed count of 15 minutes) from the file directory
+/// entry, following the process local time policy.
+pub fn decode_timestamp(local_seconds: i64, increment: u8, utc_offset: u8) -> Option<ExfatTime> {
+    if local_seconds < 0 {
+        return None;
+    }
+    let increment = if increment < 200 { increment as u32 } else { 0 };
+    let local = Timestamp::from_second(local_seconds.checked_add((increment / 100) as i64)?)
+        .ok()?
+        .to_zoned(TimeZone::UTC)
+        .datetime();
+    // Sign-extend the 7-bit count of 15 minutes.
+    let utc_offset = (utc_offset & 0x80 != 0).then(|| (((utc_offset << 1) as i8) >> 1) as i32 * 15);
+    let recorded = utc_offset.and_then(|minutes| Offset::from_seconds(minutes * 60).ok());
+    let (seconds, interpretation) = local_time_policy().to_utc(local, recorded)?;
+    Some(ExfatTime {
+        seconds,
+        milliseconds: (increment % 100) * 10,
+        utc_offset,
+        interpretation,
+    })
+}
+
+fn time_json(time: &ExfatTime) -> Value {
+    json!({
+        "seconds": time.seconds,
+        "milliseconds": time.milliseconds,
+        "utc_offset_minutes": time.utc_offset,
+        "interpretation": time.interpretation,
+    })
+}
+
+impl<T: Read + Seek> ExfatFS<T> {
+    /// Created, modified and accessed times of `inode`, refined by its file directory
+    /// entry when `get_file` read its directory.
+    fn inode_times(&self, inode: &ExInode) -> [Option<ExfatTime>; 3] {
+        let (increments, offsets) = match self.entry_sets.get(&inode.i_num) {
+            Some(set) => (
+                [set.file[20], set.file[21], 0],
+                [set.file[22], set.file[23], set.file[24]],
+            ),
+            None => ([0; 3], [0; 3]),
+        };
+        let seconds = [
+            inode.create_time,
+            inode.last_mod_time,
+            inode.last_access_time,
+        ];
+        std::array::from_fn(|i| decode_timestamp(seconds[i], increments[i], offsets[i]))
+    }
+}
+
 impl FileCommon for ExInode {
     fn id(&self) -> u64 {
         self.i_num
@@ -82,7 +400,7 @@ impl DirectoryCommon for CompatDirEntry {
     }
 }
 
-impl<T: Read + Seek> Filesystem for ExFatFS<T> {
+impl<T: Read + Seek> Filesystem for ExfatFS<T> {
     type FileType = ExInode;
     type DirectoryType = CompatDirEntry;
 
@@ -100,30 +418,39 @@ impl<T: Read + Seek> Filesystem for ExFatFS<T> {
     }
 
     fn block_size(&self) -> u64 {
-        self.bpb.bytes_per_cluster()
+        self.fs.bpb.bytes_per_cluster()
     }
 
     fn get_metadata(&self) -> Result<Value, Box<dyn Error>> {
-        Ok(self.super_info_json())
+        let mut metadata = self.fs.super_info_json();
+        metadata["timestamp_policy"] = json!(local_time_policy().name());
+        Ok(metadata)
     }
 
     fn get_metadata_pretty(&self) -> Result<String, Box<dyn Error>> {
-        Ok(self.bpb.to_string())
+        Ok(self.fs.bpb.to_string())
     }
 
     /// Get a file by its fake inode number. We handle our synthetic root specially.
     fn get_file(&mut self, file_id: u64) -> Result<Self::FileType, Box<dyn Error>> {
-        if file_id == root_inode_num(&self.bpb) {
-            return Ok(make_root_inode(&self.bpb));
+        if file_id == root_inode_num(&self.fs.bpb) {
+            return Ok(make_root_inode(&self.fs.bpb));
         }
-        Ok(self.get_inode(file_id)?)
+        let inode = self.fs.get_inode(file_id)?;
+        // The timestamp refinements of `record_to_file` come from the entry set.
+        if let Err(e) = self.entry_set(&inode) {
+            warn!("exFAT: directory of record {:#x}: {}", file_id, e);
+        }
+        Ok(inode)
     }
 
     fn read_file_content(&mut self, inode: &Self::FileType) -> Result<Vec<u8>, Box<dyn Error>> {
         if inode.is_dir() {
             return Err("exFAT: requested content for a directory".into());
         }
-        Ok(self.read_inode(inode)?)
+        // Through the cluster runs: empty files have no first cluster for `read_inode`.
+        let size = usize::try_from(inode.size()).map_err(|_| "exFAT file too large")?;
+        self.read_file_slice(inode, 0, size)
     }
 
     fn read_file_prefix(
@@ -131,11 +458,8 @@ impl<T: Read + Seek> Filesystem for ExFatFS<T> {
         inode: &Self::FileType,
         length: usize,
     ) -> Result<Vec<u8>, Box<dyn Error>> {
-        let mut data = self.read_file_content(inode)?;
-        if data.len() > length {
-            data.truncate(length);
-        }
-        Ok(data)
+        // Only the first clusters are read, so identifying large files stays cheap.
+        self.read_file_slice(inode, 0, length)
     }
 
     fn read_file_slice(
@@ -144,13 +468,65 @@ impl<T: Read + Seek> Filesystem for ExFatFS<T> {
         offset: u64,
         length: usize,
     ) -> Result<Vec<u8>, Box<dyn Error>> {
-        let data = self.read_file_content(inode)?;
-        let off = offset as usize;
-        if off >= data.len() {
-            return Ok(Vec::new());
+        let available = inode.size().saturating_sub(offset).min(length as u64);
+        let mut data = vec![0u8; available as usize];
+        let n = self.read_file_slice_into(inode, offset, &mut data)?;
+        data.truncate(n);
+        Ok(data)
+    }
+
+    fn read_file_slice_into(
+        &mut self,
+        inode: &Self::FileType,
+        offset: u64,
+        buf: &mut [u8],
+    ) -> Result<usize, Box<dyn Error>> {
+        if inode.is_dir() {
+            return Err("exFAT: requested content for a directory".into());
+        }
+        let size = inode.size();
+        if offset >= size || buf.is_empty() {
+            return Ok(0);
+        }
+        let end = offset.saturating_add(buf.len() as u64).min(size);
+        let cluster_size = self.fs.bpb.bytes_per_cluster();
+        let first = (offset / cluster_size) as usize;
+        let last = ((end - 1) / cluster_size) as usize;
+
+        for (index, cluster, count) in self.cluster_runs(inode, first, last)? {
+            let run_start = index as u64 * cluster_size;
+            let from = offset.max(run_start);
+            let to = end.min(run_start + count as u64 * cluster_size);
+            let at = cluster_offset(&self.fs.bpb, cluster) + (from - run_start);
+            self.fs.io.seek(SeekFrom::Start(at))?;
+            self.fs
+                .io
+                .read_exact(&mut buf[(from - offset) as usize..(to - offset) as usize])?;
+        }
+        Ok((end - offset) as usize)
+    }
+
+    /// The clusters of the directory entry chain, unused and deleted entries included.
+    fn read_directory_data(
+        &mut self,
+        inode: &Self::FileType,
+    ) -> Result<Option<Vec<u8>>, Box<dyn Error>> {
+        if !inode.is_dir() {
+            return Err("not a directory".into());
+        }
+        let cluster_size = self.fs.bpb.bytes_per_cluster() as usize;
+        let clusters = self.directory_clusters(inode)?;
+        let mut data = vec![0u8; clusters.len() * cluster_size];
+        for (cluster, chunk) in clusters
+            .into_iter()
+            .zip(data.chunks_exact_mut(cluster_size))
+        {
+            self.fs
+                .io
+                .seek(SeekFrom::Start(cluster_offset(&self.fs.bpb, cluster)))?;
+            self.fs.io.read_exact(chunk)?;
         }
-        let end = off.saturating_add(length).min(data.len());
-        Ok(data[off..end].to_vec())
+        Ok(Some(data))
     }
 
     fn list_dir(
@@ -160,12 +536,22 @@ impl<T: Read + Seek> Filesystem for ExFatFS<T> {
         if !inode.is_dir() {
             return Err("not a directory".into());
         }
-        Ok(self.list_dir_inode(inode)?)
+        Ok(self.fs.list_dir_inode(inode)?)
     }
 
     fn record_to_file(&self, inode: &Self::FileType, file_id: u64, absolute_path: &str) -> File {
         let is_dir = inode.is_dir();
+        // exFAT has no links nor special files.
         let ftype = if is_dir { "dir" } else { "file" }.to_string();
+        let [created, modified, accessed] = self.inode_times(inode);
+        let mut own = inode.to_json();
+        own[TIMESTAMPS_KEY] = json!({
+            "created": created.as_ref().map(time_json),
+            "modified": modified.as_ref().map(time_json),
+            "accessed": accessed.as_ref().map(time_json),
+        });
+        let common = json!({ FLAGS_KEY: dos_attr_names(inode.attributes as u32) });
+        let metadata = namespaced_metadata(METADATA_NAMESPACE, own, common);
 
         File {
             id: None,
@@ -175,14 +561,22 @@ impl<T: Read + Seek> Filesystem for ExFatFS<T> {
                 Some(n) => n.to_string_lossy().to_string(),
                 None => absolute_path.to_string(),
             },
-            created: Some(inode.create_time as u64),
-            modified: Some(inode.last_mod_time as u64),
-            accessed: Some(inode.last_access_time as u64),
-            permissions: Some(exfat_attr_string(inode.attributes, is_dir)),
+            raw_name: None,
+            created: created.and_then(|t| u64::try_from(t.seconds).ok()),
+            modified: modified.and_then(|t| u64::try_from(t.seconds).ok()),
+            accessed: accessed.and_then(|t| u64::try_from(t.seconds).ok()),
+            changed: None,
+            permissions: Some(dos_attr_string(inode.attributes as u32, is_dir)),
             owner: None,
             group: None,
             ftype,
             size: inode.size(),
+            size_on_disk: Some(if inode.first_cluster == 0 {
+                0
+            } else {
+                let cluster_size = self.fs.bpb.bytes_per_cluster();
+                inode.size().div_ceil(cluster_size) * cluster_size
+            }),
             display: Some(format!(
                 "{:016x} - {:>4} - {:>10} - {}",
                 file_id,
@@ -193,11 +587,16 @@ impl<T: Read + Seek> Filesystem for ExFatFS<T> {
             sig_name: None,
             sig_mime: None,
             sig_exts: None,
-            metadata: inode.to_json(),
+            detected_type: None,
+            ext_mismatch: None,
+            md5: None,
+            sha1: None,
+            sha256: None,
+            metadata,
         }
     }
 
     fn get_root_file_id(&self) -> u64 {
-        root_inode_num(&self.bpb)
+        root_inode_num(&self.fs.bpb)
     }
 }
@@ -0,0 +1,213 @@
+use crate::filesystem::File;
+use crate::timefmt::format_timestamp;
+use std::borrow::Cow;
+use std::fmt;
+use std::io::{self, Write};
+use std::str::FromStr;
+
+/// Output formats supported when exporting enumerated file records.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ExportFormat {
+    /// Human readable listing (backend specific `display` when available).
+    Text,
+    /// A JSON array of `File` records.
+    Json,
+    /// Comma separated values with a header row.
+    Csv,
+    /// The Sleuth Kit 3.x body file format, ready to be consumed by `mactime`.
+    Bodyfile,
+}
+
+impl FromStr for ExportFormat {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s.to_ascii_lowercase().as_str() {
+            "text" | "txt" => Ok(ExportFormat::Text),
+            "json" => Ok(ExportFormat::Json),
+            "csv" => Ok(ExportFormat::Csv),
+            "bodyfile" | "body" => Ok(ExportFormat::Bodyfile),
+            other => Err(format!("unsupported export format: {}", other)),
+        }
+    }
+}
+
+impl fmt::Display for ExportFormat {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let s = match self {
+            ExportFormat::Text => "text",
+            ExportFormat::Json => "json",
+            ExportFormat::Csv => "csv",
+            ExportFormat::Bodyfile => "bodyfile",
+        };
+        write!(f, "{}", s)
+    }
+}
+
+const CSV_HEADER: &str = "identifier,absolute_path,name,raw_name,ftype,size,size_on_disk,created,modified,accessed,changed,permissions,owner,group,md5,sha1,sha256,detected_type,ext_mismatch";
+
+/// Streaming writer turning `File` records into one of the supported formats.
+///
+/// Records are written as soon as they are pushed so that large enumerations
+/// never have to be buffered in memory. Call `finish` to close the document.
+pub struct Exporter<W: Write> {
+    writer: W,
+    format: ExportFormat,
+    count: u64,
+}
+
+impl<W: Write> Exporter<W> {
+    pub fn new(mut writer: W, format: ExportFormat) -> io::Result<Self> {
+        match format {
+            ExportFormat::Json => writeln!(writer, "[")?,
+            ExportFormat::Csv => writeln!(writer, "{}", CSV_HEADER)?,
+            ExportFormat::Text | ExportFormat::Bodyfile => {}
+        }
+        Ok(Self {
+            writer,
+            format,
+            count: 0,
+        })
+    }
+
+    /// Continue an export that was interrupted after `count` records, without
+    /// writing the document preamble again.
+    pub fn resume(writer: W, format: ExportFormat, count: u64) -> Self {
+        Self {
+            writer,
+            format,
+            count,
+        }
+    }
+
+    pub fn format(&self) -> ExportFormat {
+        self.format
+    }
+
+    /// Number of records written so far.
+    pub fn count(&self) -> u64 {
+        self.count
+    }
+
+    pub fn write_file(&mut self, file: &File) -> io::Result<()> {
+        match self.format {
+            ExportFormat::Text => writeln!(self.writer, "{}", text_line(file))?,
+            ExportFormat::Json => {
+                if self.count > 0 {
+                    writeln!(self.writer, ",")?;
+                }
+                let json = serde_json::to_string_pretty(file).map_err(io::Error::other)?;
+                write!(self.writer, "{}", json)?;
+            }
+            ExportFormat::Csv => writeln!(self.writer, "{}", csv_line(file))?,
+            ExportFormat::Bodyfile => writeln!(self.writer, "{}", bodyfile_line(file))?,
+        }
+        self.count += 1;
+        Ok(())
+    }
+
+    pub fn flush(&mut self) -> io::Result<()> {
+        self.writer.flush()
+    }
+
+    /// Terminate the document and flush the underlying writer.
+    pub fn finish(mut self) -> io::Result<W> {
+        if self.format == ExportFormat::Json {
+            if self.count > 0 {
+                writeln!(self.writer)?;
+            }
+            writeln!(self.writer, "]")?;
+        }
+        self.writer.flush()?;
+        Ok(self.writer)
+    }
+}
+
+/// The default one-line text rendering of a record.
+pub fn text_line(file: &File) -> String {
+    if let Some(custom_display) = &file.display {
+        return custom_display.clone();
+    }
+    format!(
+        "[{}] - {} {} {} {} {} {}",
+        file.identifier,
+        file.permissions.as_deref().unwrap_or("??????????"),
+        format_timestamp(file.modified.unwrap_or(0)),
+        file.owner.as_deref().unwrap_or("-"),
+        file.group.as_deref().unwrap_or("-"),
+        file.size,
+        file.absolute_path
+    )
+}
+
+pub(crate) fn csv_field(value: &str) -> String {
+    if value.contains([',', '"', '\n', '\r']) {
+        format!("\"{}\"", value.replace('"', "\"\""))
+    } else {
+        value.to_string()
+    }
+}
+
+fn opt_u64(value: Option<u64>) -> String {
+    value.map(|v| v.to_string()).unwrap_or_default()
+}
+
+pub fn csv_line(file: &File) -> String {
+    [
+        file.identifier.to_string(),
+        csv_field(&file.absolute_path),
+        csv_field(&file.name),
+        file.raw_name.clone().unwrap_or_default(),
+        csv_field(&file.ftype),
+        file.size.to_string(),
+        opt_u64(file.size_on_disk),
+        opt_u64(file.created),
+        opt_u64(file.modified),
+        opt_u64(file.accessed),
+        opt_u64(file.changed),
+        csv_field(file.permissions.as_deref().unwrap_or("")),
+        csv_field(file.owner.as_deref().unwrap_or("")),
+        csv_field(file.group.as_deref().unwrap_or("")),
+        file.md5.clone().unwrap_or_default(),
+        file.sha1.clone().unwrap_or_default(),
+        file.sha256.clone().unwrap_or_default(),
+        csv_field(file.detected_type.as_deref().unwrap_or("")),
+        file.ext_mismatch.map(|m| m.to_string()).unwrap_or_default(),
+    ]
+    .join(",")
+}
+
+/// Body file fields have no quoting: the separator and line breaks are written as
+/// `\xNN`, the escape of names that are not valid UTF-8 (see `names`).
+fn bodyfile_field(value: &str) -> Cow<'_, str> {
+    if !value.contains(['|', '\n', '\r']) {
+        return Cow::Borrowed(value);
+    }
+    let mut escaped = String::with_capacity(value.len() + 8);
+    for c in value.chars() {
+        match c {
+            '|' | '\n' | '\r' => escaped.push_str(&format!("\\x{:02x}", c as u32)),
+            c => escaped.push(c),
+        }
+    }
+    Cow::Owned(escaped)
+}
+
+/// Format a record as a TSK 3.x body file line:
+/// `MD5|name|inode|mode_as_string|UID|GID|size|atime|mtime|ctime|crtime`
+pub fn bodyfile_line(file: &File) -> String {
+    format!(
+        "{}|{}|{}|{}|{}|{}|{}|{}|{}|{}|{}",
+        file.md5.as_deref().unwrap_or("0"),
+        bodyfile_field(&file.absolute_path),
+        file.identifier,
+        file.permissions.as_deref().unwrap_or(""),
+        file.owner.as_deref().unwrap_or("0"),
+        file.group.as_deref().unwrap_or("0"),
+        file.size,
+        file.accessed.unwrap_or(0),
+        file.modified.unwrap_or(0),
+        file.changed.unwrap_or(0),
+        file.created.unwrap_or(0),
+    )
+}
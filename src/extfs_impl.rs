@@ -1,13 +1,18 @@
+use crate::filesystem::{
+    ByteRange, DEVICE_KEY, FLAGS_KEY, File, Filesystem, SYMLINK_TARGET_KEY, namespaced_metadata,
+    unix_ftype,
+};
 use crate::filesystem::{DirectoryCommon, FileCommon};
-use crate::filesystem::{File, Filesystem};
+use crate::names::{escape_name, name_bytes, render_name};
+use crate::timefmt::format_timestamp;
 use exhume_extfs::ExtFS;
 use exhume_extfs::direntry::DirEntry;
 use exhume_extfs::inode::Inode;
-use serde_json::Value;
+use serde_json::{Value, json};
 
+use std::collections::{HashMap, HashSet};
 use std::error::Error;
 use std::io::{Read, Seek};
-use std::path::Path;
 
 impl FileCommon for Inode {
     fn id(&self) -> u64 {
@@ -30,15 +35,238 @@ impl FileCommon for Inode {
     }
 }
 
+/// Namespace of the inode fields in `File.metadata`.
+pub const METADATA_NAMESPACE: &str = "ext";
+
+/// End of `i_crtime_extra`, counted from the end of the 128-byte base inode.
+const CRTIME_EXTRA_END: u16 = 0x18;
+
+/// Creation time of `inode`, when it has one. ext2/ext3 inodes (128 bytes, or larger but
+/// without the extended fields) leave `i_crtime` unset or hold unrelated bytes there, so
+/// it is only trusted when `i_extra_isize` covers it and its extra field is consistent.
+pub fn creation_time(inode: &Inode, inode_size: u16) -> Option<u64> {
+    if inode_size <= 128
+        || inode.i_extra_isize < CRTIME_EXTRA_END
+        || 128 + inode.i_extra_isize > inode_size
+    {
+        return None;
+    }
+    // Low 2 bits: epoch extension of the signed 32-bit seconds; the rest: nanoseconds.
+    if inode.i_crtime_extra >> 2 >= 1_000_000_000 {
+        return None;
+    }
+    let seconds = inode.i_crtime as i32 as i64 + (((inode.i_crtime_extra & 3) as i64) << 32);
+    if seconds <= 0 {
+        return None;
+    }
+    Some(seconds as u64)
+}
+
+/// `i_flags` bits, named after their chattr(1) meaning.
+const INODE_FLAGS: [(u32, &str); 22] = [
+    (0x0000_0001, "secure_deletion"),
+    (0x0000_0002, "undeletable"),
+    (0x0000_0004, "compressed"),
+    (0x0000_0008, "sync"),
+    (0x0000_0010, "immutable"),
+    (0x0000_0020, "append_only"),
+    (0x0000_0040, "nodump"),
+    (0x0000_0080, "noatime"),
+    (0x0000_0800, "encrypted"),
+    (0x0000_1000, "indexed"),
+    (0x0000_2000, "imagic"),
+    (0x0000_4000, "journal_data"),
+    (0x0000_8000, "notail"),
+    (0x0001_0000, "dirsync"),
+    (0x0002_0000, "topdir"),
+    (0x0004_0000, "huge_file"),
+    (0x0008_0000, "extents"),
+    (0x0010_0000, "verity"),
+    (0x0020_0000, "ea_inode"),
+    (0x1000_0000, "inline_data"),
+    (0x2000_0000, "project_inherit"),
+    (0x4000_0000, "casefold"),
+];
+
+/// `i_flags` bit of inodes mapped by an extent tree.
+const EXTENTS_FLAG: u32 = 0x0008_0000;
+const INLINE_DATA_FLAG: u32 = 0x1000_0000;
+/// Targets of fast symlinks are stored in the 60 bytes of `i_block`.
+const FAST_SYMLINK_MAX: u64 = 60;
+const EXTENT_MAGIC: u32 = 0xF30A;
+/// Extents longer than this are unwritten (preallocated), `ee_len - 32768` blocks long.
+const MAX_INIT_EXTENT_LEN: u32 = 32768;
+
+/// Holes of an inode whose extent tree fits in `i_block` (depth 0): the gaps between its
+/// extents and its unwritten extents. `None` for deeper trees and block-mapped inodes,
+/// whose maps are in blocks of their own.
+pub fn inline_extent_holes(inode: &Inode, block_size: u64) -> Option<Vec<ByteRange>> {
+    let words = &inode.i_block;
+    if inode.i_flags & EXTENTS_FLAG == 0 || words[0] & 0xffff != EXTENT_MAGIC {
+        return None;
+    }
+    let (entries, depth) = ((words[0] >> 16) as usize, words[1] >> 16);
+    if depth != 0 || entries > 4 {
+        return None;
+    }
+    let mut extents: Vec<(u64, u32)> = (0..entries)
+        .map(|i| (words[3 + i * 3] as u64, words[4 + i * 3] & 0xffff))
+        .collect();
+    extents.sort_unstable();
+
+    let size = inode.size();
+    let mut holes = Vec::new();
+    let mut covered = 0;
+    for (first, length) in extents {
+        if first > covered {
+            holes.push((covered * block_size, (first - covered) * block_size));
+        }
+        let blocks = if length > MAX_INIT_EXTENT_LEN {
+            let blocks = (length - MAX_INIT_EXTENT_LEN) as u64;
+            holes.push((first * block_size, blocks * block_size));
+            blocks
+        } else {
+            length as u64
+        };
+        covered = covered.max(first + blocks);
+    }
+    if covered * block_size < size {
+        holes.push((covered * block_size, size - covered * block_size));
+    }
+    Some(
+        holes
+            .into_iter()
+            .filter(|&(offset, _)| offset < size)
+            .map(|(offset, length)| (offset, length.min(size - offset)))
+            .collect(),
+    )
+}
+
+/// Blocks allocated to an inode whose extent tree fits in `i_block`, preallocated ones
+/// included. `None` for deeper trees and block-mapped inodes.
+pub fn inline_extent_blocks(inode: &Inode) -> Option<u64> {
+    let words = &inode.i_block;
+    if inode.i_flags & EXTENTS_FLAG == 0 || words[0] & 0xffff != EXTENT_MAGIC {
+        return None;
+    }
+    let (entries, depth) = ((words[0] >> 16) as usize, words[1] >> 16);
+    if depth != 0 || entries > 4 {
+        return None;
+    }
+    Some(
+        (0..entries)
+            .map(|i| match words[4 + i * 3] & 0xffff {
+                length if length > MAX_INIT_EXTENT_LEN => (length - MAX_INIT_EXTENT_LEN) as u64,
+                length => length as u64,
+            })
+            .sum(),
+    )
+}
+
+/// Target of a fast symlink, stored in `i_block` instead of a data block.
+pub fn fast_symlink_target(inode: &Inode) -> Option<String> {
+    let size = inode.size();
+    if unix_ftype(inode.i_mode as u32) != "symlink"
+        || inode.i_flags & (EXTENTS_FLAG | INLINE_DATA_FLAG) != 0
+        || size >= FAST_SYMLINK_MAX
+    {
+        return None;
+    }
+    let bytes: Vec<u8> = inode.i_block.iter().flat_map(|w| w.to_le_bytes()).collect();
+    Some(escape_name(&bytes[..size as usize]))
+}
+
+/// Inode number and raw name of the entries of a directory's content, in the linear
+/// `ext4_dir_entry_2` layout (hash tree blocks start with fake entries covering their
+/// index). Inline directories start with the parent inode number.
+fn raw_dir_entries(data: &[u8], inline: bool) -> Vec<(u64, &[u8])> {
+    let mut entries = Vec::new();
+    let mut pos = if inline { 4 } else { 0 };
+    while pos + 8 <= data.len() {
+        let inode = u32::from_le_bytes(data[pos..pos + 4].try_into().unwrap());
+        let rec_len = u16::from_le_bytes([data[pos + 4], data[pos + 5]]) as usize;
+        let name_len = data[pos + 6] as usize;
+        if rec_len < 8 || pos + 8 + name_len > data.len() {
+            break;
+        }
+        if inode != 0 {
+            entries.push((inode as u64, &data[pos + 8..pos + 8 + name_len]));
+        }
+        pos += rec_len;
+    }
+    entries
+}
+
+/// Inode number and raw name of the deleted entries left in the slack of a directory's
+/// entries: removing an entry adds its record length to the entry before it, whose name
+/// and inode number stay in place until overwritten.
+fn deleted_dir_entries(data: &[u8], inline: bool, inodes_count: u64) -> Vec<(u64, &[u8])> {
+    let mut entries = Vec::new();
+    let mut pos = if inline { 4 } else { 0 };
+    while pos + 8 <= data.len() {
+        let rec_len = u16::from_le_bytes([data[pos + 4], data[pos + 5]]) as usize;
+        let name_len = data[pos + 6] as usize;
+        if rec_len < 8 || pos + 8 + name_len > data.len() {
+            break;
+        }
+        let end = (pos + rec_len).min(data.len());
+        let mut at = pos + (8 + name_len).next_multiple_of(4);
+        while at + 8 <= end {
+            let inode = u32::from_le_bytes(data[at..at + 4].try_into().unwrap());
+            let length = u16::from_le_bytes([data[at + 4], data[at + 5]]) as usize;
+            let name = data.get(at + 8..at + 8 + data[at + 6] as usize);
+            match name {
+                Some(name)
+                    if (1..=inodes_count).contains(&(inode as u64))
+                        && !name.is_empty()
+                        && !name.iter().any(|b| *b == 0 || *b == b'/')
+                        && length.is_multiple_of(4)
+                        && length >= 8 + name.len()
+                        && at + length <= end =>
+                {
+                    entries.push((inode as u64, name));
+                    at += (8 + name.len()).next_multiple_of(4);
+                }
+                _ => at += 4,
+            }
+        }
+        pos += rec_len;
+    }
+    entries
+}
+
+/// Names of the flags set in `i_flags`.
+pub fn inode_flag_names(flags: u32) -> Vec<&'static str> {
+    INODE_FLAGS
+        .iter()
+        .filter(|(bit, _)| flags & bit != 0)
+        .map(|(_, name)| *name)
+        .collect()
+}
+
+/// Major and minor numbers of a device inode, stored in `i_block` in the old (16-bit,
+/// first word) or the new (32-bit, second word) encoding.
+pub fn device_numbers(inode: &Inode) -> (u32, u32) {
+    if inode.i_block[0] != 0 {
+        let dev = inode.i_block[0];
+        ((dev >> 8) & 0xff, dev & 0xff)
+    } else {
+        let dev = inode.i_block[1];
+        ((dev & 0xfff00) >> 8, (dev & 0xff) | ((dev >> 12) & 0xfff00))
+    }
+}
+
 pub fn format_unix_permissions(inode: &Inode) -> String {
-    format!(
+    let mut out = format!(
         "{}{}{}{}{}{}{}{}{}{}",
-        if inode.is_dir() {
-            'd'
-        } else if inode.is_symlink() {
-            'l'
-        } else {
-            '-'
+        match unix_ftype(inode.i_mode as u32) {
+            "dir" => 'd',
+            "symlink" => 'l',
+            "chardev" => 'c',
+            "blockdev" => 'b',
+            "fifo" => 'p',
+            "socket" => 's',
+            _ => '-',
         },
         if inode.mode() & 0o400 != 0 { 'r' } else { '-' },
         if inode.mode() & 0o200 != 0 { 'w' } else { '-' },
@@ -49,7 +277,19 @@ pub fn format_unix_permissions(inode: &Inode) -> String {
         if inode.mode() & 0o004 != 0 { 'r' } else { '-' },
         if inode.mode() & 0o002 != 0 { 'w' } else { '-' },
         if inode.mode() & 0o001 != 0 { 'x' } else { '-' }
-    )
+    );
+    // setuid / setgid / sticky, rendered like ls(1).
+    for (bit, exec, pos, set, unset) in [
+        (0o4000, 0o100, 3, "s", "S"),
+        (0o2000, 0o010, 6, "s", "S"),
+        (0o1000, 0o001, 9, "t", "T"),
+    ] {
+        if inode.mode() & bit != 0 {
+            let ch = if inode.mode() & exec != 0 { set } else { unset };
+            out.replace_range(pos..pos + 1, ch);
+        }
+    }
+    out
 }
 
 impl DirectoryCommon for DirEntry {
@@ -117,6 +357,29 @@ impl<T: Read + Seek> Filesystem for ExtFS<T> {
         2
     }
 
+    fn file_holes(
+        &mut self,
+        inode: &Self::FileType,
+    ) -> Result<Option<Vec<ByteRange>>, Box<dyn Error>> {
+        Ok(inline_extent_holes(inode, self.superblock.block_size()))
+    }
+
+    /// The directory blocks as mapped by the inode, linear and hash tree blocks alike.
+    fn read_directory_data(
+        &mut self,
+        inode: &Self::FileType,
+    ) -> Result<Option<Vec<u8>>, Box<dyn Error>> {
+        if !inode.is_dir() {
+            return Err("not a directory".into());
+        }
+        Ok(Some(self.read_inode(inode)?))
+    }
+
+    fn is_deleted(&self, inode: &Self::FileType) -> Option<bool> {
+        // Freed inodes get a deletion time and no links, but keep their mode.
+        Some(inode.i_mode != 0 && (inode.i_dtime != 0 || inode.i_links_count == 0))
+    }
+
     fn read_file_slice(
         &mut self,
         inode: &Self::FileType,
@@ -126,40 +389,94 @@ impl<T: Read + Seek> Filesystem for ExtFS<T> {
         self.read_inode_slice(inode, offset, length)
     }
 
+    /// Deleted entries in the directories reachable from the root.
+    fn deleted_entry_names(&mut self) -> Result<HashMap<u64, String>, Box<dyn Error>> {
+        let inodes_count = self.superblock.s_inodes_count;
+        let root = self.get_root_file_id();
+        let mut names = HashMap::new();
+        let mut seen = HashSet::from([root]);
+        let mut pending = vec![root];
+        while let Some(id) = pending.pop() {
+            let Ok(directory) = self.get_inode(id) else {
+                continue;
+            };
+            let Ok(data) = self.read_inode(&directory) else {
+                continue;
+            };
+            let inline = directory.i_flags & INLINE_DATA_FLAG != 0;
+            for (inode, name) in deleted_dir_entries(&data, inline, inodes_count) {
+                names.entry(inode).or_insert_with(|| escape_name(name));
+            }
+            for (inode, name) in raw_dir_entries(&data, inline) {
+                if name == b"." || name == b".." || !seen.insert(inode) {
+                    continue;
+                }
+                if self.get_inode(inode).is_ok_and(|i| i.is_dir()) {
+                    pending.push(inode);
+                }
+            }
+        }
+        Ok(names)
+    }
+
     fn list_dir(
         &mut self,
         inode: &Self::FileType,
     ) -> Result<Vec<Self::DirectoryType>, Box<dyn Error>> {
-        self.list_dir(inode)
+        let mut entries = self.list_dir(inode)?;
+        // Names that are not valid UTF-8 come lossily converted: take their bytes from
+        // the directory content to list them in the escaped form.
+        if entries.iter().any(|e| e.name.contains('\u{FFFD}')) {
+            let data = self.read_inode(inode)?;
+            let mut raw = raw_dir_entries(&data, inode.i_flags & INLINE_DATA_FLAG != 0);
+            for entry in entries.iter_mut().filter(|e| e.name.contains('\u{FFFD}')) {
+                if let Some(i) = raw.iter().position(|(id, name)| {
+                    *id == entry.inode as u64 && String::from_utf8_lossy(name) == entry.name
+                }) {
+                    entry.name = escape_name(raw.swap_remove(i).1);
+                }
+            }
+        }
+        for entry in entries.iter_mut().filter(|e| e.name.contains('\\')) {
+            entry.name = escape_name(entry.name.as_bytes());
+        }
+        Ok(entries)
     }
 
     // Record to File object implementation for ExtFS
     fn record_to_file(&self, inode: &Self::FileType, inode_num: u64, absolute_path: &str) -> File {
-        let mut file_type = String::from("other");
-        if inode.is_dir() {
-            file_type = String::from("dir");
-        } else if inode.is_regular_file() {
-            file_type = String::from("file");
-        } else if inode.is_symlink() {
-            file_type = String::from("symlink");
+        let file_type = unix_ftype(inode.i_mode as u32);
+        let mut common = json!({ FLAGS_KEY: inode_flag_names(inode.i_flags) });
+        if matches!(file_type, "chardev" | "blockdev") {
+            let (major, minor) = device_numbers(inode);
+            common[DEVICE_KEY] = json!({ "major": major, "minor": minor });
+        }
+        if let Some(target) = fast_symlink_target(inode) {
+            common[SYMLINK_TARGET_KEY] = json!(render_name(&target));
         }
+        let metadata = namespaced_metadata(METADATA_NAMESPACE, inode.to_json(), common);
+        let name = match absolute_path.rsplit_once('/') {
+            Some((_, name)) if !name.is_empty() => name,
+            _ => absolute_path,
+        };
+        let absolute_path = render_name(absolute_path);
 
         File {
             id: None,
             identifier: inode_num,
             absolute_path: absolute_path.to_string(),
-            name: match Path::new(absolute_path).file_name() {
-                Some(name) => name.to_string_lossy().to_string(),
-                None => absolute_path.to_string(),
-            },
-            created: Some(inode.i_crtime as u64),
+            name: render_name(name).into_owned(),
+            raw_name: name_bytes(name).map(hex::encode),
+            created: creation_time(inode, self.superblock.s_inode_size),
             modified: Some(inode.i_mtime as u64),
             accessed: Some(inode.i_atime as u64),
+            changed: Some(inode.i_ctime as u64),
             permissions: Some(format_unix_permissions(inode)),
             owner: Some(format!("{}", inode.uid())),
             group: Some(format!("{}", inode.gid())),
-            ftype: file_type.clone(),
+            ftype: file_type.to_string(),
             size: inode.size(),
+            size_on_disk: inline_extent_blocks(inode).map(|b| b * self.superblock.block_size()),
             display: Some(format!(
                 "[{}] - {} {} {} {} {:>5} {} {}",
                 inode_num,
@@ -168,13 +485,18 @@ impl<T: Read + Seek> Filesystem for ExtFS<T> {
                 inode.uid(),
                 inode.gid(),
                 inode.size(),
-                inode.i_mtime_h,
+                format_timestamp(inode.i_mtime as u64),
                 absolute_path
             )),
             sig_name: None,
             sig_mime: None,
             sig_exts: None,
-            metadata: inode.to_json(),
+            detected_type: None,
+            ext_mismatch: None,
+            md5: None,
+            sha1: None,
+            sha256: None,
+            metadata,
         }
     }
 }
@@ -0,0 +1,1034 @@
+//! F2FS volumes, as most Android userdata partitions are formatted. The superblock gives
+//! the layout of the metadata areas; the current checkpoint (the valid one of the two
+//! packs, with the highest version) tells which copy of each NAT and SIT block is live,
+//! and holds the journals of the latest NAT and SIT updates in its summaries.
+//!
+//! Records are identified by inode number (node id), mapped to node blocks through the
+//! NAT. Inline data and dentries, direct and indirect node blocks, inline and node
+//! extended attributes are read; compressed files are not, and the content and names of
+//! encrypted files are returned as stored.
+use crate::filesystem::{
+    BlockRun, ByteRange, DEVICE_KEY, DirectoryCommon, EXTENDED_ATTRIBUTES_KEY, ExtendedAttribute,
+    FLAGS_KEY, File, FileCommon, Filesystem, SYMLINK_TARGET_KEY, namespaced_metadata, unix_ftype,
+    unix_mode_string,
+};
+use crate::names::{escape_name, name_bytes, render_name};
+use crate::timefmt::format_timestamp;
+use serde::Serialize;
+use serde_json::{Value, json};
+use std::collections::HashMap;
+use std::error::Error;
+use std::io::{Read, Seek, SeekFrom};
+
+/// Namespace of the inode fields in `File.metadata`.
+pub const METADATA_NAMESPACE: &str = "f2fs";
+
+const MAGIC: u32 = 0xf2f5_2010;
+const SUPERBLOCK_OFFSET: u64 = 1024;
+const BLOCK_SIZE: usize = 4096;
+const NAT_ENTRY_SIZE: usize = 9;
+const NAT_ENTRIES_PER_BLOCK: u32 = (BLOCK_SIZE / NAT_ENTRY_SIZE) as u32;
+const SIT_ENTRY_SIZE: usize = 74;
+const SIT_ENTRIES_PER_BLOCK: u32 = (BLOCK_SIZE / SIT_ENTRY_SIZE) as u32;
+/// Summary entries of a summary block, before its journal.
+const SUMMARY_ENTRIES_SIZE: usize = 512 * 7;
+const JOURNAL_SIZE: usize = 507;
+const NODE_FOOTER: usize = BLOCK_SIZE - 24;
+const INODE_ADDRS: usize = 923;
+const INODE_ADDR_OFFSET: usize = 360;
+const ADDRS_PER_BLOCK: u32 = 1018;
+const NIDS_PER_BLOCK: u32 = 1018;
+const DEFAULT_INLINE_XATTR_ADDRS: usize = 50;
+const NULL_ADDR: u32 = 0;
+const NEW_ADDR: u32 = u32::MAX;
+const COMPRESS_ADDR: u32 = u32::MAX - 1;
+const DENTRY_SIZE: usize = 11;
+const SLOT_LEN: usize = 8;
+const DENTRIES_PER_BLOCK: usize = 214;
+/// Bitmap and reserved bytes before the dentries of a dentry block.
+const DENTRY_BITMAP_SIZE: usize = 27;
+const DENTRY_RESERVED_SIZE: usize = 3;
+const XATTR_MAGIC: u32 = 0xf2f5_2011;
+const XATTR_HEADER_SIZE: usize = 24;
+/// Largest directory read whole.
+const MAX_DIRECTORY: u64 = 64 << 20;
+
+const CP_COMPACT_SUM_FLAG: u32 = 0x0004;
+const CP_LARGE_NAT_BITMAP_FLAG: u32 = 0x0400;
+const FEATURE_FLEXIBLE_INLINE_XATTR: u32 = 0x0040;
+
+const INLINE_XATTR: u8 = 0x01;
+const INLINE_DATA: u8 = 0x02;
+const INLINE_DENTRY: u8 = 0x04;
+const EXTRA_ATTR: u8 = 0x20;
+
+/// `i_flags` bits, named after their meaning.
+const INODE_FLAGS: [(u32, &str); 10] = [
+    (0x0000_0004, "compressed"),
+    (0x0000_0008, "sync"),
+    (0x0000_0010, "immutable"),
+    (0x0000_0020, "append"),
+    (0x0000_0040, "nodump"),
+    (0x0000_0080, "noatime"),
+    (0x0000_0400, "nocompression"),
+    (0x0000_0800, "encrypted"),
+    (0x0000_1000, "index"),
+    (0x4000_0000, "casefold"),
+];
+
+/// `i_advise` bits, named after their meaning.
+const ADVISE_FLAGS: [(u8, &str); 5] = [
+    (0x01, "cold"),
+    (0x02, "lost_pino"),
+    (0x04, "encrypted"),
+    (0x08, "enc_name"),
+    (0x10, "keep_size"),
+];
+
+fn le_u16(bytes: &[u8], at: usize) -> u16 {
+    u16::from_le_bytes(bytes[at..at + 2].try_into().unwrap())
+}
+
+fn le_u32(bytes: &[u8], at: usize) -> u32 {
+    u32::from_le_bytes(bytes[at..at + 4].try_into().unwrap())
+}
+
+fn le_u64(bytes: &[u8], at: usize) -> u64 {
+    u64::from_le_bytes(bytes[at..at + 8].try_into().unwrap())
+}
+
+/// Bit `nr` of a NAT or SIT bitmap, most significant bit first.
+fn test_bit(bitmap: &[u8], nr: usize) -> bool {
+    bitmap
+        .get(nr / 8)
+        .is_some_and(|byte| byte & (0x80 >> (nr % 8)) != 0)
+}
+
+/// Bit `nr` of a dentry bitmap, least significant bit first.
+fn test_bit_le(bitmap: &[u8], nr: usize) -> bool {
+    bitmap
+        .get(nr / 8)
+        .is_some_and(|byte| byte & (1 << (nr % 8)) != 0)
+}
+
+/// CRC-32 of the checkpoint, seeded with the superblock magic and not inverted.
+pub fn f2fs_crc32(bytes: &[u8]) -> u32 {
+    let mut crc = MAGIC;
+    for byte in bytes {
+        crc ^= *byte as u32;
+        for _ in 0..8 {
+            crc = (crc >> 1) ^ (0xedb8_8320 & (crc & 1).wrapping_neg());
+        }
+    }
+    crc
+}
+
+#[derive(Debug, Clone, Serialize)]
+pub struct Superblock {
+    pub version: (u16, u16),
+    pub log_blocks_per_seg: u32,
+    pub block_count: u64,
+    pub segment_count_sit: u32,
+    pub segment_count_nat: u32,
+    pub cp_blkaddr: u32,
+    pub sit_blkaddr: u32,
+    pub nat_blkaddr: u32,
+    pub ssa_blkaddr: u32,
+    pub main_blkaddr: u32,
+    pub root_ino: u32,
+    pub uuid: String,
+    pub volume_name: String,
+    pub cp_payload: u32,
+    pub feature: u32,
+}
+
+impl Superblock {
+    fn parse(bytes: &[u8]) -> Result<Self, Box<dyn Error>> {
+        if bytes.len() < 1672 || le_u32(bytes, 0) != MAGIC {
+            return Err("not an F2FS superblock".into());
+        }
+        if le_u32(bytes, 16) != 12 {
+            return Err(format!("F2FS block size 2^{} is not supported", le_u32(bytes, 16)).into());
+        }
+        let log_blocks_per_seg = le_u32(bytes, 20);
+        if log_blocks_per_seg > 12 {
+            return Err(format!("bad F2FS segment size 2^{}", log_blocks_per_seg).into());
+        }
+        let name: Vec<u16> = bytes[124..1148]
+            .chunks_exact(2)
+            .map(|c| u16::from_le_bytes([c[0], c[1]]))
+            .take_while(|c| *c != 0)
+            .collect();
+        Ok(Self {
+            version: (le_u16(bytes, 4), le_u16(bytes, 6)),
+            log_blocks_per_seg,
+            block_count: le_u64(bytes, 36),
+            segment_count_sit: le_u32(bytes, 56),
+            segment_count_nat: le_u32(bytes, 60),
+            cp_blkaddr: le_u32(bytes, 76),
+            sit_blkaddr: le_u32(bytes, 80),
+            nat_blkaddr: le_u32(bytes, 84),
+            ssa_blkaddr: le_u32(bytes, 88),
+            main_blkaddr: le_u32(bytes, 92),
+            root_ino: le_u32(bytes, 96),
+            uuid: hex::encode(&bytes[108..124]),
+            volume_name: String::from_utf16_lossy(&name),
+            cp_payload: le_u32(bytes, 1664),
+            feature: le_u32(bytes, 2180),
+        })
+    }
+
+    fn blocks_per_seg(&self) -> u32 {
+        1 << self.log_blocks_per_seg
+    }
+}
+
+/// Fields of the current checkpoint.
+#[derive(Debug, Clone, Serialize)]
+pub struct Checkpoint {
+    pub version: u64,
+    pub user_block_count: u64,
+    pub valid_block_count: u64,
+    pub flags: u32,
+    pub valid_node_count: u32,
+    pub valid_inode_count: u32,
+    pub next_free_nid: u32,
+    /// Which copy of each NAT and SIT block is live.
+    #[serde(skip)]
+    pub nat_bitmap: Vec<u8>,
+    #[serde(skip)]
+    pub sit_bitmap: Vec<u8>,
+}
+
+/// An inode node.
+#[derive(Debug, Clone, Serialize)]
+pub struct F2fsInode {
+    pub ino: u32,
+    pub mode: u16,
+    pub advise: u8,
+    pub inline: u8,
+    pub uid: u32,
+    pub gid: u32,
+    pub links: u32,
+    pub size: u64,
+    /// Blocks of the file, its inode included.
+    pub blocks: u64,
+    pub atime: u64,
+    pub ctime: u64,
+    pub mtime: u64,
+    pub crtime: Option<u64>,
+    pub generation: u32,
+    pub xattr_nid: u32,
+    pub flags: u32,
+    pub parent_ino: u32,
+    /// Name of the inode when it was created (truncated to 255 bytes).
+    pub name: String,
+    /// First of the addresses of the inode, after its extra attributes.
+    #[serde(skip)]
+    pub addr_offset: usize,
+    /// Addresses kept in the inode, after `addr_offset`.
+    #[serde(skip)]
+    pub addr_count: usize,
+    #[serde(skip)]
+    pub inline_xattr_addrs: usize,
+    #[serde(skip)]
+    pub raw: Vec<u8>,
+}
+
+impl F2fsInode {
+    /// Bytes of the inline data or dentries.
+    fn inline_area(&self) -> &[u8] {
+        // The first address after the extra attributes is reserved.
+        let start = INODE_ADDR_OFFSET + 4 * (self.addr_offset + 1);
+        let end = INODE_ADDR_OFFSET + 4 * (INODE_ADDRS - self.inline_xattr_addrs);
+        &self.raw[start..end.max(start)]
+    }
+
+    fn inline_xattrs(&self) -> &[u8] {
+        let start = INODE_ADDR_OFFSET + 4 * (INODE_ADDRS - self.inline_xattr_addrs);
+        &self.raw[start..INODE_ADDR_OFFSET + 4 * INODE_ADDRS]
+    }
+
+    fn addr(&self, index: usize) -> u32 {
+        le_u32(
+            &self.raw,
+            INODE_ADDR_OFFSET + 4 * (self.addr_offset + index),
+        )
+    }
+
+    fn nid(&self, index: usize) -> u32 {
+        le_u32(&self.raw, INODE_ADDR_OFFSET + 4 * INODE_ADDRS + 4 * index)
+    }
+}
+
+impl FileCommon for F2fsInode {
+    fn id(&self) -> u64 {
+        self.ino as u64
+    }
+    fn size(&self) -> u64 {
+        self.size
+    }
+    fn is_dir(&self) -> bool {
+        unix_ftype(self.mode as u32) == "dir"
+    }
+    fn to_string(&self) -> String {
+        format!(
+            "F2fsInode {{ ino: {}, mode: {:o}, size: {} }}",
+            self.ino, self.mode, self.size
+        )
+    }
+    fn to_json(&self) -> Value {
+        serde_json::to_value(self).unwrap_or(Value::Null)
+    }
+}
+
+/// A dentry of a directory.
+#[derive(Debug, Clone)]
+pub struct F2fsDirEntry {
+    pub ino: u32,
+    pub hash: u32,
+    pub name: String,
+    pub file_type: u8,
+}
+
+impl DirectoryCommon for F2fsDirEntry {
+    fn file_id(&self) -> u64 {
+        self.ino as u64
+    }
+    fn name(&self) -> &str {
+        &self.name
+    }
+    fn to_string(&self) -> String {
+        format!("F2fsDirEntry {{ ino: {}, name: {} }}", self.ino, self.name)
+    }
+    fn to_json(&self) -> Value {
+        json!({
+            "ino": self.ino,
+            "hash": self.hash,
+            "name": self.name,
+            "file_type": self.file_type,
+        })
+    }
+}
+
+/// Dentries of a dentry block or inline dentry area of `count` slots.
+fn parse_dentries(
+    area: &[u8],
+    count: usize,
+    bitmap_size: usize,
+    reserved: usize,
+    entries: &mut Vec<F2fsDirEntry>,
+) {
+    let dentries = bitmap_size + reserved;
+    let names = dentries + count * DENTRY_SIZE;
+    let mut slot = 0;
+    while slot < count {
+        if !test_bit_le(&area[..bitmap_size], slot) {
+            slot += 1;
+            continue;
+        }
+        let dentry = &area[dentries + slot * DENTRY_SIZE..];
+        let (hash, ino, name_length) = (
+            le_u32(dentry, 0),
+            le_u32(dentry, 4),
+            le_u16(dentry, 8) as usize,
+        );
+        let start = names + slot * SLOT_LEN;
+        let name = &area[start..(start + name_length).min(names + count * SLOT_LEN)];
+        let is_dot = matches!(name, b"." | b"..");
+        if ino != 0 && name_length > 0 && !is_dot {
+            entries.push(F2fsDirEntry {
+                ino,
+                hash,
+                name: escape_name(name),
+                file_type: dentry[10],
+            });
+        }
+        slot += name_length.div_ceil(SLOT_LEN).max(1);
+    }
+}
+
+pub struct F2fsFS<T: Read + Seek> {
+    body: T,
+    superblock: Superblock,
+    checkpoint: Checkpoint,
+    /// NAT entries of the journal (node id to block address), newer than the NAT.
+    nat_journal: HashMap<u32, u32>,
+    /// SIT entries of the journal by segment number, newer than the SIT.
+    sit_journal: HashMap<u32, Vec<u8>>,
+}
+
+impl<T: Read + Seek> F2fsFS<T> {
+    pub fn new(mut body: T) -> Result<Self, Box<dyn Error>> {
+        let mut raw = vec![0u8; 3072];
+        body.seek(SeekFrom::Start(SUPERBLOCK_OFFSET))?;
+        body.read_exact(&mut raw)?;
+        let superblock = match Superblock::parse(&raw) {
+            Ok(superblock) => superblock,
+            // Backup superblock in the second block.
+            Err(_) => {
+                body.seek(SeekFrom::Start(BLOCK_SIZE as u64 + SUPERBLOCK_OFFSET))?;
+                body.read_exact(&mut raw)?;
+                Superblock::parse(&raw)?
+            }
+        };
+        let mut fs = Self {
+            body,
+            checkpoint: Checkpoint {
+                version: 0,
+                user_block_count: 0,
+                valid_block_count: 0,
+                flags: 0,
+                valid_node_count: 0,
+                valid_inode_count: 0,
+                next_free_nid: 0,
+                nat_bitmap: Vec::new(),
+                sit_bitmap: Vec::new(),
+            },
+            superblock,
+            nat_journal: HashMap::new(),
+            sit_journal: HashMap::new(),
+        };
+        let packs = [0, fs.superblock.blocks_per_seg()];
+        let mut current: Option<(u32, Vec<u8>)> = None;
+        for pack in packs {
+            let start = fs.superblock.cp_blkaddr + pack;
+            if let Ok(header) = fs.valid_checkpoint(start)
+                && current
+                    .as_ref()
+                    .is_none_or(|(_, c)| le_u64(&header, 0) > le_u64(c, 0))
+            {
+                current = Some((start, header));
+            }
+        }
+        let (start, header) = current.ok_or("no valid F2FS checkpoint")?;
+        fs.load_checkpoint(start, &header)?;
+        Ok(fs)
+    }
+
+    pub fn superblock(&self) -> &Superblock {
+        &self.superblock
+    }
+
+    pub fn checkpoint(&self) -> &Checkpoint {
+        &self.checkpoint
+    }
+
+    fn read_block(&mut self, block: u32) -> Result<Vec<u8>, Box<dyn Error>> {
+        if block as u64 >= self.superblock.block_count {
+            return Err(format!("F2FS block {} past the end of the volume", block).into());
+        }
+        let mut data = vec![0u8; BLOCK_SIZE];
+        self.body
+            .seek(SeekFrom::Start(block as u64 * BLOCK_SIZE as u64))?;
+        self.body.read_exact(&mut data)?;
+        Ok(data)
+    }
+
+    /// The header of the checkpoint pack at `start`, when its checksum holds and its
+    /// last block repeats its version.
+    fn valid_checkpoint(&mut self, start: u32) -> Result<Vec<u8>, Box<dyn Error>> {
+        let header = self.read_block(start)?;
+        let checksum_offset = le_u32(&header, 164) as usize;
+        if !(168..=BLOCK_SIZE - 4).contains(&checksum_offset)
+            || f2fs_crc32(&header[..checksum_offset]) != le_u32(&header, checksum_offset)
+        {
+            return Err("bad F2FS checkpoint checksum".into());
+        }
+        let total = le_u32(&header, 136);
+        if total < 2 || total > self.superblock.blocks_per_seg() {
+            return Err("bad F2FS checkpoint pack size".into());
+        }
+        let footer = self.read_block(start + total - 1)?;
+        if le_u64(&footer, 0) != le_u64(&header, 0) {
+            return Err("torn F2FS checkpoint".into());
+        }
+        Ok(header)
+    }
+
+    fn load_checkpoint(&mut self, start: u32, header: &[u8]) -> Result<(), Box<dyn Error>> {
+        let flags = le_u32(header, 132);
+        let (sit_size, nat_size) = (le_u32(header, 156) as usize, le_u32(header, 160) as usize);
+        let bitmap = |at: usize, size: usize| {
+            header
+                .get(at..at + size)
+                .map(<[u8]>::to_vec)
+                .ok_or("F2FS checkpoint bitmap past its block")
+        };
+        let (nat_bitmap, sit_bitmap) = if flags & CP_LARGE_NAT_BITMAP_FLAG != 0 {
+            // The checksum comes first.
+            (bitmap(196 + sit_size, nat_size)?, bitmap(196, sit_size)?)
+        } else if self.superblock.cp_payload > 0 {
+            // The SIT bitmap is in the payload blocks after the header.
+            let mut sit = Vec::new();
+            for block in 0..self.superblock.cp_payload {
+                sit.extend(self.read_block(start + 1 + block)?);
+            }
+            sit.truncate(sit_size);
+            (bitmap(192, nat_size)?, sit)
+        } else {
+            (bitmap(192 + sit_size, nat_size)?, bitmap(192, sit_size)?)
+        };
+        self.checkpoint = Checkpoint {
+            version: le_u64(header, 0),
+            user_block_count: le_u64(header, 8),
+            valid_block_count: le_u64(header, 16),
+            flags,
+            valid_node_count: le_u32(header, 144),
+            valid_inode_count: le_u32(header, 148),
+            next_free_nid: le_u32(header, 152),
+            nat_bitmap,
+            sit_bitmap,
+        };
+
+        // NAT journal of the hot data summary, SIT journal of the cold data summary.
+        let summaries = start + le_u32(header, 140);
+        let (nat_journal, sit_journal) = if flags & CP_COMPACT_SUM_FLAG != 0 {
+            let block = self.read_block(summaries)?;
+            (
+                block[..JOURNAL_SIZE].to_vec(),
+                block[JOURNAL_SIZE..2 * JOURNAL_SIZE].to_vec(),
+            )
+        } else {
+            let journal = SUMMARY_ENTRIES_SIZE..SUMMARY_ENTRIES_SIZE + JOURNAL_SIZE;
+            (
+                self.read_block(summaries)?[journal.clone()].to_vec(),
+                self.read_block(summaries + 2)?[journal].to_vec(),
+            )
+        };
+        let count = (le_u16(&nat_journal, 0) as usize).min((JOURNAL_SIZE - 2) / 13);
+        for entry in nat_journal[2..2 + count * 13].chunks_exact(13) {
+            self.nat_journal
+                .insert(le_u32(entry, 0), le_u32(entry, 4 + 5));
+        }
+        let size = 4 + SIT_ENTRY_SIZE;
+        let count = (le_u16(&sit_journal, 0) as usize).min((JOURNAL_SIZE - 2) / size);
+        for entry in sit_journal[2..2 + count * size].chunks_exact(size) {
+            self.sit_journal
+                .insert(le_u32(entry, 0), entry[4..].to_vec());
+        }
+        Ok(())
+    }
+
+    /// Block address of node `nid`, from the journal or the live NAT block.
+    fn node_address(&mut self, nid: u32) -> Result<u32, Box<dyn Error>> {
+        if let Some(address) = self.nat_journal.get(&nid) {
+            return Ok(*address);
+        }
+        let block_off = nid / NAT_ENTRIES_PER_BLOCK;
+        if block_off
+            >= (self.superblock.segment_count_nat / 2) << self.superblock.log_blocks_per_seg
+        {
+            return Err(format!("F2FS node id {} out of range", nid).into());
+        }
+        let blocks_per_seg = self.superblock.blocks_per_seg();
+        let mut block =
+            self.superblock.nat_blkaddr + (block_off << 1) - (block_off & (blocks_per_seg - 1));
+        if test_bit(&self.checkpoint.nat_bitmap, block_off as usize) {
+            block += blocks_per_seg;
+        }
+        let nat = self.read_block(block)?;
+        let at = (nid % NAT_ENTRIES_PER_BLOCK) as usize * NAT_ENTRY_SIZE;
+        Ok(le_u32(&nat, at + 5))
+    }
+
+    /// The node block of `nid`, checked against its footer.
+    fn node(&mut self, nid: u32) -> Result<Vec<u8>, Box<dyn Error>> {
+        let address = self.node_address(nid)?;
+        if address == NULL_ADDR || address == NEW_ADDR {
+            return Err(format!("F2FS node {} is not allocated", nid).into());
+        }
+        let node = self.read_block(address)?;
+        if le_u32(&node, NODE_FOOTER) != nid {
+            return Err(format!("F2FS node block {} is not node {}", address, nid).into());
+        }
+        Ok(node)
+    }
+
+    fn inode(&mut self, ino: u32) -> Result<F2fsInode, Box<dyn Error>> {
+        let raw = self.node(ino)?;
+        if le_u32(&raw, NODE_FOOTER + 4) != ino {
+            return Err(format!("F2FS node {} is not an inode", ino).into());
+        }
+        let inline = raw[3];
+        let extra_size = match inline & EXTRA_ATTR {
+            0 => 0,
+            _ => le_u16(&raw, INODE_ADDR_OFFSET) as usize,
+        };
+        if extra_size % 4 != 0 || extra_size > 200 {
+            return Err(format!("bad F2FS extra inode size {}", extra_size).into());
+        }
+        let inline_xattr_addrs = match inline & INLINE_XATTR {
+            0 => 0,
+            _ if extra_size > 0 && self.superblock.feature & FEATURE_FLEXIBLE_INLINE_XATTR != 0 => {
+                le_u16(&raw, INODE_ADDR_OFFSET + 2) as usize
+            }
+            _ => DEFAULT_INLINE_XATTR_ADDRS,
+        };
+        let addr_offset = extra_size / 4;
+        if addr_offset + inline_xattr_addrs + 1 > INODE_ADDRS {
+            return Err(format!("bad F2FS inline xattr size {}", inline_xattr_addrs).into());
+        }
+        let name_length = (le_u32(&raw, 88) as usize).min(255);
+        Ok(F2fsInode {
+            ino,
+            mode: le_u16(&raw, 0),
+            advise: raw[2],
+            inline,
+            uid: le_u32(&raw, 4),
+            gid: le_u32(&raw, 8),
+            links: le_u32(&raw, 12),
+            size: le_u64(&raw, 16),
+            blocks: le_u64(&raw, 24),
+            atime: le_u64(&raw, 32),
+            ctime: le_u64(&raw, 40),
+            mtime: le_u64(&raw, 48),
+            crtime: (extra_size >= 20).then(|| le_u64(&raw, INODE_ADDR_OFFSET + 12)),
+            generation: le_u32(&raw, 68),
+            xattr_nid: le_u32(&raw, 76),
+            flags: le_u32(&raw, 80),
+            parent_ino: le_u32(&raw, 84),
+            name: escape_name(&raw[92..92 + name_length]),
+            addr_offset,
+            addr_count: INODE_ADDRS - addr_offset - inline_xattr_addrs,
+            inline_xattr_addrs,
+            raw,
+        })
+    }
+
+    /// Address of data block `index` of `inode`, through its direct, indirect and double
+    /// indirect nodes.
+    fn data_address(&mut self, inode: &F2fsInode, index: u64) -> Result<u32, Box<dyn Error>> {
+        let mut index = index;
+        if index < inode.addr_count as u64 {
+            return Ok(inode.addr(index as usize));
+        }
+        index -= inode.addr_count as u64;
+        let (direct, indirect) = (
+            ADDRS_PER_BLOCK as u64,
+            (ADDRS_PER_BLOCK * NIDS_PER_BLOCK) as u64,
+        );
+        // Node ids of the inode, each covering `span` blocks through `levels` nodes.
+        let nodes = [
+            (0, direct, 0),
+            (1, direct, 0),
+            (2, indirect, 1),
+            (3, indirect, 1),
+        ]
+        .into_iter()
+        .chain([(4, indirect * NIDS_PER_BLOCK as u64, 2)]);
+        for (slot, span, levels) in nodes {
+            if index >= span {
+                index -= span;
+                continue;
+            }
+            let mut nid = inode.nid(slot);
+            let mut span = span;
+            for _ in 0..levels {
+                if nid == 0 {
+                    return Ok(NULL_ADDR);
+                }
+                span /= NIDS_PER_BLOCK as u64;
+                let node = self.node(nid)?;
+                nid = le_u32(&node, 4 * (index / span) as usize);
+                index %= span;
+            }
+            if nid == 0 {
+                return Ok(NULL_ADDR);
+            }
+            let node = self.node(nid)?;
+            return Ok(le_u32(&node, 4 * index as usize));
+        }
+        Err(format!("F2FS block {} past the largest file", index).into())
+    }
+
+    fn read_content(
+        &mut self,
+        inode: &F2fsInode,
+        offset: u64,
+        length: usize,
+    ) -> Result<Vec<u8>, Box<dyn Error>> {
+        if offset >= inode.size {
+            return Ok(Vec::new());
+        }
+        let length = length.min((inode.size - offset) as usize);
+        if inode.inline & INLINE_DATA != 0 {
+            let area = inode.inline_area();
+            let start = (offset as usize).min(area.len());
+            let mut out = area[start..(start + length).min(area.len())].to_vec();
+            out.resize(length, 0);
+            return Ok(out);
+        }
+        let mut out = Vec::with_capacity(length);
+        let mut position = offset;
+        let end = offset + length as u64;
+        while position < end {
+            let index = position / BLOCK_SIZE as u64;
+            let start = (position % BLOCK_SIZE as u64) as usize;
+            let count = (BLOCK_SIZE - start).min((end - position) as usize);
+            match self.data_address(inode, index)? {
+                NULL_ADDR | NEW_ADDR => out.resize(out.len() + count, 0),
+                COMPRESS_ADDR => return Err("compressed F2FS files are not supported".into()),
+                address => out.extend_from_slice(&self.read_block(address)?[start..start + count]),
+            }
+            position += count as u64;
+        }
+        Ok(out)
+    }
+
+    /// Validity of block `block` of the main area in the SIT, its journal first.
+    fn block_valid(&mut self, block: u64) -> Result<Option<bool>, Box<dyn Error>> {
+        let main = self.superblock.main_blkaddr as u64;
+        if block < main {
+            return Ok(Some(true));
+        }
+        if block >= self.superblock.block_count {
+            return Ok(None);
+        }
+        let relative = block - main;
+        let segno = (relative >> self.superblock.log_blocks_per_seg) as u32;
+        let offset = (relative & (self.superblock.blocks_per_seg() as u64 - 1)) as usize;
+        let entry = match self.sit_journal.get(&segno) {
+            Some(entry) => entry.clone(),
+            None => {
+                let block_off = segno / SIT_ENTRIES_PER_BLOCK;
+                let mut address = self.superblock.sit_blkaddr + block_off;
+                if test_bit(&self.checkpoint.sit_bitmap, block_off as usize) {
+                    address += (self.superblock.segment_count_sit / 2)
+                        << self.superblock.log_blocks_per_seg;
+                }
+                let at = (segno % SIT_ENTRIES_PER_BLOCK) as usize * SIT_ENTRY_SIZE;
+                self.read_block(address)?[at..at + SIT_ENTRY_SIZE].to_vec()
+            }
+        };
+        // Valid block count and type, then the validity bitmap.
+        Ok(Some(test_bit(&entry[2..66], offset)))
+    }
+
+    /// Extended attributes of the inline area and the xattr node.
+    fn xattrs(&mut self, inode: &F2fsInode) -> Result<Vec<ExtendedAttribute>, Box<dyn Error>> {
+        let mut area = Vec::new();
+        if inode.inline & INLINE_XATTR != 0 {
+            area.extend_from_slice(inode.inline_xattrs());
+        }
+        // The entries run on from the inline area into the node, which holds the
+        // header when there is no inline area.
+        if inode.xattr_nid != 0 {
+            area.extend_from_slice(&self.node(inode.xattr_nid)?[..NODE_FOOTER]);
+        }
+        Ok(parse_xattrs(&area))
+    }
+}
+
+/// Names of the flags set in `i_flags` and `i_advise`.
+pub fn inode_flag_names(flags: u32, advise: u8) -> Vec<&'static str> {
+    let mut names: Vec<&'static str> = INODE_FLAGS
+        .iter()
+        .filter(|(bit, _)| flags & bit != 0)
+        .map(|(_, name)| *name)
+        .collect();
+    for (bit, name) in ADVISE_FLAGS {
+        if advise & bit != 0 && !names.contains(&name) {
+            names.push(name);
+        }
+    }
+    names
+}
+
+impl<T: Read + Seek> Filesystem for F2fsFS<T> {
+    type FileType = F2fsInode;
+    type DirectoryType = F2fsDirEntry;
+
+    fn filesystem_type(&self) -> String {
+        "F2FS".to_string()
+    }
+
+    fn path_separator(&self) -> String {
+        "/".to_string()
+    }
+
+    fn record_count(&mut self) -> u64 {
+        self.checkpoint.valid_inode_count as u64
+    }
+
+    fn block_size(&self) -> u64 {
+        BLOCK_SIZE as u64
+    }
+
+    fn get_metadata(&self) -> Result<Value, Box<dyn Error>> {
+        Ok(json!({
+            "superblock": self.superblock,
+            "checkpoint": self.checkpoint,
+        }))
+    }
+
+    fn get_metadata_pretty(&self) -> Result<String, Box<dyn Error>> {
+        let sb = &self.superblock;
+        let cp = &self.checkpoint;
+        Ok(format!(
+            "F2FS {}.{} volume '{}' ({})\n\
+             Blocks: {} ({} valid, {} for users)\n\
+             Checkpoint: version {}, flags {:#x}\n\
+             Inodes: {}, nodes: {}\n",
+            sb.version.0,
+            sb.version.1,
+            sb.volume_name,
+            sb.uuid,
+            sb.block_count,
+            cp.valid_block_count,
+            cp.user_block_count,
+            cp.version,
+            cp.flags,
+            cp.valid_inode_count,
+            cp.valid_node_count
+        ))
+    }
+
+    fn get_file(&mut self, ino: u64) -> Result<Self::FileType, Box<dyn Error>> {
+        self.inode(u32::try_from(ino)?)
+    }
+
+    fn read_file_content(&mut self, file: &Self::FileType) -> Result<Vec<u8>, Box<dyn Error>> {
+        self.read_content(file, 0, file.size as usize)
+    }
+
+    fn read_file_prefix(
+        &mut self,
+        file: &Self::FileType,
+        length: usize,
+    ) -> Result<Vec<u8>, Box<dyn Error>> {
+        self.read_content(file, 0, length)
+    }
+
+    fn read_file_slice(
+        &mut self,
+        file: &Self::FileType,
+        offset: u64,
+        length: usize,
+    ) -> Result<Vec<u8>, Box<dyn Error>> {
+        self.read_content(file, offset, length)
+    }
+
+    fn list_dir(
+        &mut self,
+        file: &Self::FileType,
+    ) -> Result<Vec<Self::DirectoryType>, Box<dyn Error>> {
+        if !file.is_dir() {
+            return Err("not a directory".into());
+        }
+        let mut entries = Vec::new();
+        if file.inline & INLINE_DENTRY != 0 {
+            let area = file.inline_area();
+            let count = area.len() * 8 / ((DENTRY_SIZE + SLOT_LEN) * 8 + 1);
+            let bitmap = count.div_ceil(8);
+            let reserved = area.len() - ((DENTRY_SIZE + SLOT_LEN) * count + bitmap);
+            parse_dentries(area, count, bitmap, reserved, &mut entries);
+            return Ok(entries);
+        }
+        if file.size > MAX_DIRECTORY {
+            return Err(format!("directory of {} bytes", file.size).into());
+        }
+        for index in 0..file.size.div_ceil(BLOCK_SIZE as u64) {
+            let block = match self.data_address(file, index)? {
+                NULL_ADDR | NEW_ADDR => continue,
+                address => self.read_block(address)?,
+            };
+            parse_dentries(
+                &block,
+                DENTRIES_PER_BLOCK,
+                DENTRY_BITMAP_SIZE,
+                DENTRY_RESERVED_SIZE,
+                &mut entries,
+            );
+        }
+        Ok(entries)
+    }
+
+    fn record_to_file(&self, file: &Self::FileType, file_id: u64, absolute_path: &str) -> File {
+        let mode = file.mode as u32;
+        let file_type = unix_ftype(mode);
+        let mut common = json!({ FLAGS_KEY: inode_flag_names(file.flags, file.advise) });
+        if matches!(file_type, "chardev" | "blockdev") {
+            // Old encoding in the first address, new one in the second.
+            let rdev = match file.addr(0) {
+                0 => file.addr(1),
+                old => old,
+            };
+            common[DEVICE_KEY] = json!({
+                "major": (rdev & 0xfff00) >> 8,
+                "minor": (rdev & 0xff) | ((rdev >> 12) & 0xfff00),
+            });
+        }
+        if file_type == "symlink" && file.inline & INLINE_DATA != 0 {
+            let area = file.inline_area();
+            let target = &area[..(file.size as usize).min(area.len())];
+            common[SYMLINK_TARGET_KEY] = json!(render_name(&escape_name(target)));
+        }
+        // Attributes of the xattr node are only read by `extended_attributes`.
+        if file.inline & INLINE_XATTR != 0 {
+            let attributes: Vec<Value> = parse_xattrs(file.inline_xattrs())
+                .iter()
+                .map(|(name, value)| {
+                    json!({ "name": name, "size": value.len(), "value": hex::encode(value) })
+                })
+                .collect();
+            if !attributes.is_empty() {
+                common[EXTENDED_ATTRIBUTES_KEY] = json!(attributes);
+            }
+        }
+        let metadata = namespaced_metadata(METADATA_NAMESPACE, file.to_json(), common);
+        let name = match absolute_path.rsplit_once('/') {
+            Some((_, name)) if !name.is_empty() => name,
+            _ => absolute_path,
+        };
+        let absolute_path = render_name(absolute_path);
+        let permissions = unix_mode_string(mode);
+        File {
+            id: None,
+            identifier: file_id,
+            absolute_path: absolute_path.to_string(),
+            name: render_name(name).into_owned(),
+            raw_name: name_bytes(name).map(hex::encode),
+            ftype: file_type.to_string(),
+            size: file.size,
+            size_on_disk: Some(file.blocks.saturating_sub(1) * BLOCK_SIZE as u64),
+            created: file.crtime,
+            modified: Some(file.mtime),
+            accessed: Some(file.atime),
+            changed: Some(file.ctime),
+            permissions: Some(permissions.clone()),
+            owner: Some(file.uid.to_string()),
+            group: Some(file.gid.to_string()),
+            display: Some(format!(
+                "[{}] - {} {} {} {} {:>5} {} {}",
+                file_id,
+                permissions,
+                file.links,
+                file.uid,
+                file.gid,
+                file.size,
+                format_timestamp(file.mtime),
+                absolute_path
+            )),
+            sig_name: None,
+            sig_mime: None,
+            sig_exts: None,
+            detected_type: None,
+            ext_mismatch: None,
+            md5: None,
+            sha1: None,
+            sha256: None,
+            metadata,
+        }
+    }
+
+    fn get_root_file_id(&self) -> u64 {
+        self.superblock.root_ino as u64
+    }
+
+    fn block_allocation(&mut self, block: u64) -> Result<Option<bool>, Box<dyn Error>> {
+        self.block_valid(block)
+    }
+
+    fn file_block_runs(
+        &mut self,
+        file: &Self::FileType,
+    ) -> Result<Option<Vec<BlockRun>>, Box<dyn Error>> {
+        if file.inline & (INLINE_DATA | INLINE_DENTRY) != 0 {
+            return Ok(Some(Vec::new()));
+        }
+        let mut runs: Vec<BlockRun> = Vec::new();
+        for index in 0..file.size.div_ceil(BLOCK_SIZE as u64) {
+            let address = match self.data_address(file, index)? {
+                NULL_ADDR | NEW_ADDR | COMPRESS_ADDR => continue,
+                address => address as u64,
+            };
+            match runs.last_mut() {
+                Some((first, count)) if *first + *count == address => *count += 1,
+                _ => runs.push((address, 1)),
+            }
+        }
+        Ok(Some(runs))
+    }
+
+    fn file_holes(
+        &mut self,
+        file: &Self::FileType,
+    ) -> Result<Option<Vec<ByteRange>>, Box<dyn Error>> {
+        if file.inline & (INLINE_DATA | INLINE_DENTRY) != 0 || file.is_dir() {
+            return Ok(Some(Vec::new()));
+        }
+        let mut holes: Vec<ByteRange> = Vec::new();
+        for index in 0..file.size.div_ceil(BLOCK_SIZE as u64) {
+            if !matches!(self.data_address(file, index)?, NULL_ADDR | NEW_ADDR) {
+                continue;
+            }
+            let start = index * BLOCK_SIZE as u64;
+            let length = (BLOCK_SIZE as u64).min(file.size - start);
+            match holes.last_mut() {
+                Some((offset, len)) if *offset + *len == start => *len += length,
+                _ => holes.push((start, length)),
+            }
+        }
+        Ok(Some(holes))
+    }
+
+    fn read_directory_data(
+        &mut self,
+        dir: &Self::FileType,
+    ) -> Result<Option<Vec<u8>>, Box<dyn Error>> {
+        if dir.inline & INLINE_DENTRY != 0 {
+            return Ok(Some(dir.inline_area().to_vec()));
+        }
+        if dir.size > MAX_DIRECTORY {
+            return Err(format!("directory of {} bytes", dir.size).into());
+        }
+        self.read_content(dir, 0, dir.size as usize).map(Some)
+    }
+
+    fn extended_attributes(
+        &mut self,
+        file: &Self::FileType,
+    ) -> Result<Vec<ExtendedAttribute>, Box<dyn Error>> {
+        self.xattrs(file)
+    }
+}
+
+/// Extended attributes of an xattr area: its header, then entries of a name index, name
+/// length, value size, name and value, each padded to 4 bytes.
+fn parse_xattrs(area: &[u8]) -> Vec<ExtendedAttribute> {
+    let mut attributes = Vec::new();
+    if area.len() < XATTR_HEADER_SIZE || le_u32(area, 0) != XATTR_MAGIC {
+        return attributes;
+    }
+    let mut at = XATTR_HEADER_SIZE;
+    while at + 4 <= area.len() && le_u32(area, at) != 0 {
+        let (index, name_length, value_size) = (
+            area[at],
+            area[at + 1] as usize,
+            le_u16(area, at + 2) as usize,
+        );
+        let (Some(name), Some(value)) = (
+            area.get(at + 4..at + 4 + name_length),
+            area.get(at + 4 + name_length..at + 4 + name_length + value_size),
+        ) else {
+            break;
+        };
+        let prefix = match index {
+            1 => "user.",
+            2 => "system.posix_acl_access",
+            3 => "system.posix_acl_default",
+            4 => "trusted.",
+            6 => "security.",
+            7 => "system.",
+            9 => "encryption.",
+            _ => "",
+        };
+        attributes.push((
+            format!("{}{}", prefix, String::from_utf8_lossy(name)),
+            value.to_vec(),
+        ));
+        at += (4 + name_length + value_size).next_multiple_of(4);
+    }
+    attributes
+}
@@ -1,12 +1,25 @@
-use log::{error, info};
+use crate::budget;
+use crate::names::{name_matches, split_path};
+use crate::progress::{Progress, ProgressUnit};
+use crate::search::ExcludeSet;
+use crate::spill::{SeenSet, WalkQueue};
+use crate::tolerant;
+use log::{error, info, warn};
 use serde::{Deserialize, Serialize};
-use serde_json::Value;
+use serde_json::{Value, json};
 use sqlx::FromRow;
+use std::collections::HashMap;
 use std::error::Error;
-use std::fs::File as StdFile;
-use std::io::{self, Read, Seek, SeekFrom, Write};
+use std::fs::{File as StdFile, OpenOptions};
+use std::io::{self, BufWriter, Read, Seek, SeekFrom, Write};
+use std::sync::mpsc::{self, Receiver, SyncSender};
+use std::thread;
 
 const CACHE_SIZE: usize = 64 * 1024; // 64 KiB cache;
+/// Events buffered between a streaming walk and its consumer.
+pub const STREAM_CAPACITY: usize = 1024;
+/// Memory accounted per walk event in flight.
+const EVENT_BYTES: u64 = 2048;
 
 /// A trait for common file record functionality.
 pub trait FileCommon {
@@ -41,12 +54,18 @@ pub struct File {
     pub identifier: u64,       // FS-specific unique ID (inode, MFT record, etc.)
     pub absolute_path: String, // Full path from root
     pub name: String,          // File name
-    pub ftype: String,         // File type (file, dir, symlink, etc.)
+    #[sqlx(default)]
+    pub raw_name: Option<String>, // Hex of the name bytes when `name` is escaped, see `names`
+    pub ftype: String,         // File type, one of `FTYPES`
     pub size: u64,             // Size in bytes
+    #[sqlx(default)]
+    pub size_on_disk: Option<u64>, // Bytes allocated on disk (compressed size when compressed)
     // We are normalizing all timestamps in UNIX Time for all filesystems
     pub created: Option<u64>,
     pub modified: Option<u64>,
     pub accessed: Option<u64>,
+    #[sqlx(default)]
+    pub changed: Option<u64>, // Metadata change time (ctime, NTFS MFT entry modified)
     pub permissions: Option<String>, // Permissions in some normalized form
     pub owner: Option<String>,       // Owner user name or SID/UID
     pub group: Option<String>,       // Group name or GID (Unix)
@@ -54,7 +73,122 @@ pub struct File {
     pub sig_name: Option<String>, // Identified signature name (e.g. "Executable and Linkable Format")
     pub sig_mime: Option<String>, // Identified MIME type (comma separated)
     pub sig_exts: Option<String>, // Identified extensions (comma separated)
-    pub metadata: Value,          // Filesystem-specific extra metadata
+    #[sqlx(default)]
+    pub detected_type: Option<String>, // Short content type from magic bytes (e.g. "pe", "jpeg")
+    #[sqlx(default)]
+    pub ext_mismatch: Option<bool>, // Extension contradicts detected_type (e.g. a ".jpg" PE)
+    #[sqlx(default)]
+    pub md5: Option<String>, // Content digests (hex), only when hashing was requested
+    #[sqlx(default)]
+    pub sha1: Option<String>,
+    #[sqlx(default)]
+    pub sha256: Option<String>,
+    pub metadata: Value, // Namespaced extra metadata, see `COMMON_KEY`
+}
+
+/// Normalized values of `File.ftype`.
+pub const FTYPES: [&str; 8] = [
+    "file", "dir", "symlink", "chardev", "blockdev", "fifo", "socket", "unknown",
+];
+
+/// Namespace of `File.metadata` holding the keys every backend fills the same way, so
+/// that queries work across filesystems: `common.flags` and `common.streams` on every
+/// record, `common.device`, `common.symlink_target`, `common.extended_attributes` and
+/// `common.bad_ranges` where they apply. Everything else is under the namespace of the
+/// backend (`ntfs`, `ext`, `apfs`, `exfat`, `folder`), e.g. `ntfs.timestamps` or
+/// `ext.i_flags`, and the content extractors write under `embedded`.
+pub const COMMON_KEY: &str = "common";
+
+/// Key of the major and minor numbers of device files under `common`.
+pub const DEVICE_KEY: &str = "device";
+
+/// Key of the attribute flags of a record under `common`, as a list of names: ext
+/// `i_flags`, BSD `st_flags` of APFS records and folders, DOS attributes of NTFS, exFAT
+/// and Windows folders. Filterable with `flags == immutable`.
+pub const FLAGS_KEY: &str = "flags";
+
+/// Key of the extended attributes of a record (NTFS $EA entries, xattrs of folders)
+/// under `common`, each with its `name`, `size` and hex `value`.
+pub const EXTENDED_ATTRIBUTES_KEY: &str = "extended_attributes";
+
+/// Key of the target of a symbolic link under `common`.
+pub const SYMLINK_TARGET_KEY: &str = "symlink_target";
+
+/// Key of the named data streams of a record (NTFS alternate data streams) under
+/// `common`, each with its `name` and `size`.
+pub const STREAMS_KEY: &str = "streams";
+
+/// Key of the ranges of the partition that could not be read and were zero-filled while
+/// reading the content of a record (`--read-retries`) under `common`, each with its
+/// `offset` and `length` in bytes. Ranges already zero-filled for another record are
+/// served from the block cache and not repeated.
+pub const BAD_RANGES_KEY: &str = "bad_ranges";
+
+/// `File.metadata` of a record: `own`, the backend keys, under `namespace` and `common`
+/// under `COMMON_KEY`, with the keys every record has defaulted.
+pub fn namespaced_metadata(namespace: &str, own: Value, mut common: Value) -> Value {
+    for key in [FLAGS_KEY, STREAMS_KEY] {
+        if common.get(key).is_none() {
+            common[key] = json!([]);
+        }
+    }
+    json!({ namespace: own, COMMON_KEY: common })
+}
+
+/// Name and raw value of one extended attribute.
+pub type ExtendedAttribute = (String, Vec<u8>);
+
+/// Normalized `File.ftype` of a Unix `st_mode` (ext, APFS, folders).
+pub fn unix_ftype(mode: u32) -> &'static str {
+    match mode & 0o170000 {
+        0o100000 => "file",
+        0o040000 => "dir",
+        0o120000 => "symlink",
+        0o020000 => "chardev",
+        0o060000 => "blockdev",
+        0o010000 => "fifo",
+        0o140000 => "socket",
+        _ => "unknown",
+    }
+}
+
+/// `ls -l` form of a Unix `st_mode` (`drwxr-xr-x`, `-rwsr-x---`, ...).
+pub fn unix_mode_string(mode: u32) -> String {
+    let mut out = String::with_capacity(10);
+    out.push(match unix_ftype(mode) {
+        "file" => '-',
+        "dir" => 'd',
+        "symlink" => 'l',
+        "chardev" => 'c',
+        "blockdev" => 'b',
+        "fifo" => 'p',
+        "socket" => 's',
+        _ => '?',
+    });
+    for (bit, ch) in [
+        (0o400, 'r'),
+        (0o200, 'w'),
+        (0o100, 'x'),
+        (0o040, 'r'),
+        (0o020, 'w'),
+        (0o010, 'x'),
+        (0o004, 'r'),
+        (0o002, 'w'),
+        (0o001, 'x'),
+    ] {
+        out.push(if mode & bit != 0 { ch } else { '-' });
+    }
+    // setuid / setgid / sticky, rendered like ls(1).
+    for (bit, exec, pos, set, unset) in [
+        (0o4000, 0o100, 3, "s", "S"),
+        (0o2000, 0o010, 6, "s", "S"),
+        (0o1000, 0o001, 9, "t", "T"),
+    ] {
+        if mode & bit != 0 {
+            out.replace_range(pos..pos + 1, if mode & exec != 0 { set } else { unset });
+        }
+    }
+    out
 }
 
 /// Dispatched events during `walk_fs`.
@@ -66,6 +200,377 @@ pub enum WalkEvent {
     Status(String),
 }
 
+/// Serializable traversal state of a Breadth-First walk, used to resume
+/// interrupted enumerations of very large images.
+#[derive(Serialize, Deserialize, Debug, Clone, Default)]
+pub struct WalkCheckpoint {
+    /// Records discovered but not yet visited, with their absolute path.
+    pub queue: WalkQueue,
+    /// Records already visited, saved in the `.seen` file next to the checkpoint.
+    #[serde(skip)]
+    pub seen: SeenSet,
+    /// Number of records emitted so far.
+    pub emitted: u64,
+    /// Identifier of the last record emitted before the checkpoint.
+    pub last_record: Option<u64>,
+    /// Where the export of the walk stood at the checkpoint, when it was exported.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub export: Option<ExportPosition>,
+    /// Visited records in the `.seen` file as of this checkpoint.
+    #[serde(default)]
+    seen_len: u64,
+    /// Visited records not in the `.seen` file yet, tracked when the walk checkpoints.
+    #[serde(skip)]
+    unsaved: Vec<u64>,
+    /// `.seen` file the visited records are appended to.
+    #[serde(skip)]
+    seen_path: Option<String>,
+}
+
+/// Format of an export and how long its output was at a checkpoint.
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct ExportPosition {
+    pub format: String,
+    pub output_len: u64,
+}
+
+impl WalkCheckpoint {
+    pub fn load(path: &str) -> Result<Self, Box<dyn Error>> {
+        let data = std::fs::read(path)?;
+        let mut state: Self = serde_json::from_slice(&data)?;
+        if state.seen_len > 0 {
+            let seen_path = format!("{}.seen", path);
+            let mut seen = io::BufReader::new(StdFile::open(&seen_path)?).take(state.seen_len * 8);
+            let mut value = [0u8; 8];
+            for _ in 0..state.seen_len {
+                seen.read_exact(&mut value)?;
+                state.seen.insert(u64::from_le_bytes(value));
+            }
+            state.seen_path = Some(seen_path);
+        }
+        Ok(state)
+    }
+
+    /// Save the checkpoint atomically (write to a temporary file, then rename). The
+    /// visited records go to the `.seen` file next to it: once written there, only the
+    /// records visited since the previous save are appended.
+    pub fn save(&mut self, path: &str) -> Result<(), Box<dyn Error>> {
+        let seen_path = format!("{}.seen", path);
+        let mut seen = OpenOptions::new()
+            .write(true)
+            .create(true)
+            .truncate(false)
+            .open(&seen_path)?;
+        let seen_len = if self.seen_path.as_deref() == Some(seen_path.as_str()) {
+            // Drop what a failed save may have appended after the last checkpoint.
+            seen.set_len(self.seen_len * 8)?;
+            seen.seek(SeekFrom::End(0))?;
+            let mut out = BufWriter::new(&mut seen);
+            for value in &self.unsaved {
+                out.write_all(&value.to_le_bytes())?;
+            }
+            out.flush()?;
+            self.seen_len + self.unsaved.len() as u64
+        } else {
+            seen.set_len(0)?;
+            let mut out = BufWriter::new(&mut seen);
+            let mut failed = None;
+            self.seen.for_each(&mut |value| {
+                if failed.is_none() {
+                    failed = out.write_all(&value.to_le_bytes()).err();
+                }
+            })?;
+            if let Some(e) = failed {
+                return Err(e.into());
+            }
+            out.flush()?;
+            self.seen.len() as u64
+        };
+        seen.sync_data()?;
+        self.seen_path = Some(seen_path);
+        self.seen_len = seen_len;
+        self.unsaved.clear();
+
+        let tmp = format!("{}.tmp", path);
+        // Streamed, as a spilled queue may not fit in memory.
+        let mut out = BufWriter::new(std::fs::File::create(&tmp)?);
+        serde_json::to_writer(&mut out, self)?;
+        out.flush()?;
+        drop(out);
+        std::fs::rename(&tmp, path)?;
+        Ok(())
+    }
+}
+
+/// A contiguous run of physical blocks: `(first_block, block_count)`.
+pub type BlockRun = (u64, u64);
+
+/// A range of the content of a record: `(offset, length)` in bytes.
+pub type ByteRange = (u64, u64);
+
+/// Object-safe `Read + Seek` handle given to content visitors.
+pub trait ReadSeek: Read + Seek {}
+impl<T: Read + Seek + ?Sized> ReadSeek for T {}
+
+/// Called for every non-directory record during a walk, with the `File` about to be
+/// emitted and a reader over its content, so content can be processed in the same pass.
+pub type ContentVisitor<'a> = dyn FnMut(&mut File, &mut dyn ReadSeek) + 'a;
+
+/// Pluggable processing of every non-directory record of a walk and its content, so
+/// several analyses (hashing, classifiers, custom parsers) share a single evidence pass.
+pub trait FileAnalyzer {
+    /// Name used in diagnostics.
+    fn name(&self) -> &str;
+    /// Whether the content of this record should be handed to `analyze`.
+    fn accepts(&self, _file: &File) -> bool {
+        true
+    }
+    /// Process a record. `reader` starts at the beginning of the content; changes made
+    /// to `file` are part of the emitted record.
+    fn analyze(&mut self, file: &mut File, reader: &mut dyn ReadSeek)
+    -> Result<(), Box<dyn Error>>;
+    /// Called once when the walk completes.
+    fn finish(&mut self) -> Result<(), Box<dyn Error>> {
+        Ok(())
+    }
+}
+
+/// Optional behaviours of `Filesystem::walk_fs_with`.
+#[derive(Default)]
+pub struct WalkOptions<'a> {
+    /// Continue from a previously saved traversal state.
+    pub resume: Option<WalkCheckpoint>,
+    /// Emit a checkpoint every N records (0 disables checkpointing).
+    pub checkpoint_every: u64,
+    pub on_checkpoint: Option<&'a mut dyn FnMut(&mut WalkCheckpoint)>,
+    pub visitor: Option<&'a mut ContentVisitor<'a>>,
+    /// Paths skipped with everything below them.
+    pub exclude: Option<&'a ExcludeSet>,
+    /// Run in registration order, after `visitor`.
+    pub analyzers: Vec<&'a mut dyn FileAnalyzer>,
+    /// Emit records with several hard links once per link, each with its own path. NTFS
+    /// walks find the other names in the record, directory tree walks emit the record
+    /// again wherever it is reached.
+    pub all_names: bool,
+    /// Walk the entries of each directory in name order (see `sort_children`), so that
+    /// listings are the same from one run to the next. NTFS walks then follow the
+    /// directory tree instead of $MFT order.
+    pub sorted: bool,
+}
+
+impl<'a> WalkOptions<'a> {
+    /// Register an analyzer on the walk.
+    pub fn with_analyzer(mut self, analyzer: &'a mut dyn FileAnalyzer) -> Self {
+        self.analyzers.push(analyzer);
+        self
+    }
+}
+
+/// Hand a record's content to the visitor and the accepting analyzers of a walk.
+pub(crate) fn visit_content(
+    visitor: Option<&mut &mut ContentVisitor>,
+    analyzers: &mut [&mut dyn FileAnalyzer],
+    file: &mut File,
+    reader: &mut dyn ReadSeek,
+) {
+    tolerant::take_thread_ranges();
+    if let Some(visitor) = visitor {
+        visitor(file, reader);
+    }
+    for analyzer in analyzers.iter_mut() {
+        if !analyzer.accepts(file) {
+            continue;
+        }
+        let result = reader
+            .seek(SeekFrom::Start(0))
+            .map_err(Into::into)
+            .and_then(|_| analyzer.analyze(file, reader));
+        if let Err(e) = result {
+            warn!(
+                "Analyzer {} failed on {}: {}",
+                analyzer.name(),
+                file.absolute_path,
+                e
+            );
+        }
+    }
+    attach_bad_ranges(file);
+}
+
+/// Record under `common` the ranges zero-filled by this thread since the last call.
+pub(crate) fn attach_bad_ranges(file: &mut File) {
+    let ranges = tolerant::take_thread_ranges();
+    if ranges.is_empty() {
+        return;
+    }
+    warn!(
+        "{}: {} unreadable range(s) zero-filled",
+        file.absolute_path,
+        ranges.len()
+    );
+    file.metadata[COMMON_KEY][BAD_RANGES_KEY] = ranges
+        .iter()
+        .map(|&(offset, length)| json!({ "offset": offset, "length": length }))
+        .collect();
+}
+
+/// Let the analyzers of a completed walk wrap up.
+pub(crate) fn finish_analyzers(
+    analyzers: &mut [&mut dyn FileAnalyzer],
+) -> Result<(), Box<dyn Error>> {
+    for analyzer in analyzers.iter_mut() {
+        analyzer
+            .finish()
+            .map_err(|e| format!("analyzer {}: {}", analyzer.name(), e))?;
+    }
+    Ok(())
+}
+
+/// Identifier and name of a directory entry to walk into.
+pub(crate) type ChildEntry = (u64, String);
+
+/// Called by `walk_breadth_first` with a record identifier, its path and whether the
+/// record is reached for the first time.
+pub(crate) type RecordVisitor<'a> = dyn FnMut(u64, &str, bool) -> Option<Vec<ChildEntry>> + 'a;
+
+/// Order the entries of a directory for sorted walks: by the bytes of their UTF-8 names,
+/// which is Unicode code point order (case-sensitive, `B` before `a`, independent of the
+/// locale), then by identifier for entries of the same name.
+pub(crate) fn sort_children(children: &mut [ChildEntry]) {
+    children.sort_unstable_by(|(a_id, a), (b_id, b)| a.cmp(b).then(a_id.cmp(b_id)));
+}
+
+/// Breadth-first traversal shared by the walks of every backend, continuing from the
+/// records queued in `state`.
+///
+/// `visit` handles a record reached at a path and returns its entries (identifier and
+/// name) to queue below it, or `None` when the record cannot be read. Excluded paths are
+/// skipped with everything below them. A record reached again at another path (a hard
+/// link) is handed to `visit` with `first` false and is not walked into nor counted as
+/// emitted. The walk stops early once `max_queued` records are queued (0 walks the whole
+/// tree).
+pub(crate) fn walk_breadth_first(
+    state: &mut WalkCheckpoint,
+    separator: &str,
+    exclude: Option<&ExcludeSet>,
+    checkpoint_every: u64,
+    mut on_checkpoint: Option<&mut dyn FnMut(&mut WalkCheckpoint)>,
+    max_queued: usize,
+    visit: &mut RecordVisitor,
+) {
+    while max_queued == 0 || state.queue.len() < max_queued {
+        let Some((record_id, path)) = state.queue.pop_front() else {
+            break;
+        };
+        if exclude.is_some_and(|x| x.is_excluded(&path, separator)) {
+            continue;
+        }
+        if !state.seen.insert(record_id) {
+            visit(record_id, &path, false);
+            continue;
+        }
+        if checkpoint_every > 0 {
+            state.unsaved.push(record_id);
+        }
+        let Some(children) = visit(record_id, &path, true) else {
+            continue;
+        };
+        for (child_id, name) in children {
+            let child_path = if path.ends_with(separator) {
+                format!("{}{}", path, name)
+            } else {
+                format!("{}{}{}", path, separator, name)
+            };
+            state.queue.push_back((child_id, child_path));
+        }
+
+        state.emitted += 1;
+        state.last_record = Some(record_id);
+        if checkpoint_every > 0
+            && state.emitted.is_multiple_of(checkpoint_every)
+            && let Some(on_checkpoint) = on_checkpoint.as_mut()
+        {
+            on_checkpoint(state);
+        }
+    }
+}
+
+/// The default `Filesystem::walk_fs_with`: a breadth-first walk of the directory tree
+/// with `get_file` and `list_dir`.
+pub(crate) fn walk_tree<F: Filesystem + ?Sized>(
+    fs: &mut F,
+    options: WalkOptions,
+    callback: &mut dyn FnMut(WalkEvent),
+) -> Result<(), Box<dyn Error>> {
+    let WalkOptions {
+        resume,
+        checkpoint_every,
+        on_checkpoint,
+        mut visitor,
+        exclude,
+        mut analyzers,
+        all_names,
+        sorted,
+    } = options;
+
+    let mut state = match resume {
+        Some(state) => {
+            callback(WalkEvent::Status(format!(
+                "Resuming walk after {} records ({} queued)",
+                state.emitted,
+                state.queue.len()
+            )));
+            state
+        }
+        None => {
+            let mut state = WalkCheckpoint::default();
+            state
+                .queue
+                .push_back((fs.get_root_file_id(), fs.path_separator()));
+            state
+        }
+    };
+
+    let separator = fs.path_separator();
+    walk_breadth_first(
+        &mut state,
+        &separator,
+        exclude,
+        checkpoint_every,
+        on_checkpoint,
+        0,
+        &mut |record_id, path, first| {
+            if !first && !all_names {
+                return None;
+            }
+            let record = fs.get_file(record_id).ok()?;
+            if !first && record.is_dir() {
+                return None;
+            }
+            let mut file_obj = fs.record_to_file(&record, record_id, path);
+            let mut children = Vec::new();
+            if record.is_dir() {
+                if let Ok(entries) = fs.list_dir(&record) {
+                    children = entries
+                        .iter()
+                        .map(|e| (e.file_id(), e.name().to_string()))
+                        .collect();
+                }
+                if sorted {
+                    sort_children(&mut children);
+                }
+            } else if visitor.is_some() || !analyzers.is_empty() {
+                let mut reader = FsFileReadSeek::new(fs, record);
+                visit_content(visitor.as_mut(), &mut analyzers, &mut file_obj, &mut reader);
+            }
+            callback(WalkEvent::File(file_obj));
+            Some(children)
+        },
+    );
+
+    finish_analyzers(&mut analyzers)
+}
+
 /// The Filesystem trait
 pub trait Filesystem {
     type FileType: FileCommon;
@@ -83,17 +588,15 @@ pub trait Filesystem {
         path: &str,
         _file_id: u64,
     ) -> Result<Self::FileType, Box<dyn Error>> {
-        let components: Vec<&str> = path
-            .split(['/', '\\'])
-            .filter(|c| !c.is_empty())
-            .collect();
+        let separator = self.path_separator();
+        let components = split_path(path, &separator);
         let root_id = self.get_root_file_id();
         let mut current = self.get_file(root_id)?;
         for component in &components {
             let entries = self.list_dir(&current)?;
             let entry = entries
                 .into_iter()
-                .find(|e| e.name() == *component)
+                .find(|e| name_matches(e.name(), component))
                 .ok_or_else(|| format!("path component not found: {:?}", component))?;
             current = self.get_file(entry.file_id())?;
         }
@@ -111,6 +614,20 @@ pub trait Filesystem {
         offset: u64,
         length: usize,
     ) -> Result<Vec<u8>, Box<dyn Error>>;
+    /// Read the content at `offset` into `buf`, returning the number of bytes read (short
+    /// only at the end of the content). Lets hot loops reuse one buffer; backends able to
+    /// read straight into it override this copying default.
+    fn read_file_slice_into(
+        &mut self,
+        file: &Self::FileType,
+        offset: u64,
+        buf: &mut [u8],
+    ) -> Result<usize, Box<dyn Error>> {
+        let data = self.read_file_slice(file, offset, buf.len())?;
+        let n = data.len().min(buf.len());
+        buf[..n].copy_from_slice(&data[..n]);
+        Ok(n)
+    }
 
     fn list_dir(
         &mut self,
@@ -122,42 +639,107 @@ pub trait Filesystem {
     /// Walk the filesystem and call the callback for each file found.
     /// This default implementation uses Breadth-First Search via `get_file` and `list_dir`.
     fn walk_fs(&mut self, callback: &mut dyn FnMut(WalkEvent)) -> Result<(), Box<dyn Error>> {
-        use std::collections::{HashSet, VecDeque};
-        let mut seen: HashSet<u64> = HashSet::new();
-        let mut queue: VecDeque<(u64, String)> = VecDeque::new();
+        self.walk_fs_with(WalkOptions::default(), callback)
+    }
 
-        let root_id = self.get_root_file_id();
-        queue.push_back((root_id, self.path_separator()));
+    /// Breadth-First walk with optional checkpointing, resuming and content visiting.
+    ///
+    /// Every `checkpoint_every` emitted records the traversal state is handed to
+    /// `on_checkpoint`. Passing a previously saved state as `resume` continues the walk
+    /// right after the last record emitted before that checkpoint.
+    fn walk_fs_with(
+        &mut self,
+        options: WalkOptions,
+        callback: &mut dyn FnMut(WalkEvent),
+    ) -> Result<(), Box<dyn Error>> {
+        walk_tree(self, options, callback)
+    }
 
-        while let Some((record_id, path)) = queue.pop_front() {
-            if !seen.insert(record_id) {
-                continue;
+    /// `walk_fs_with` sending every event over a bounded channel as it is produced. The
+    /// walk waits while the channel is full; once the receiver is gone, the remaining
+    /// events are dropped and an error is returned when the walk completes.
+    fn walk_to_channel(
+        &mut self,
+        options: WalkOptions,
+        sender: &SyncSender<WalkEvent>,
+    ) -> Result<(), Box<dyn Error>> {
+        let mut disconnected = false;
+        self.walk_fs_with(options, &mut |event| {
+            if !disconnected && sender.send(event).is_err() {
+                disconnected = true;
             }
+        })?;
+        if disconnected {
+            return Err("the receiver of the walk events went away".into());
+        }
+        Ok(())
+    }
 
-            let record = match self.get_file(record_id) {
-                Ok(r) => r,
-                Err(_) => continue,
-            };
+    /// Allocation status of a block (cluster) as recorded by the filesystem itself,
+    /// or `None` when the backend cannot tell.
+    fn block_allocation(&mut self, _block: u64) -> Result<Option<bool>, Box<dyn Error>> {
+        Ok(None)
+    }
 
-            let file_obj = self.record_to_file(&record, record_id, &path);
-            let is_dir = record.is_dir();
+    /// Allocation status of `count` consecutive blocks starting at `first`, one entry per
+    /// block. Backends reading a bitmap override this to fetch it once per range.
+    fn block_allocation_range(
+        &mut self,
+        first: u64,
+        count: u64,
+    ) -> Result<Vec<Option<bool>>, Box<dyn Error>> {
+        (first..first + count)
+            .map(|block| self.block_allocation(block))
+            .collect()
+    }
 
-            callback(WalkEvent::File(file_obj));
+    /// Physical block runs holding the content of a record,
+    /// or `None` when the backend cannot map records to blocks.
+    fn file_block_runs(
+        &mut self,
+        _file: &Self::FileType,
+    ) -> Result<Option<Vec<BlockRun>>, Box<dyn Error>> {
+        Ok(None)
+    }
 
-            if is_dir && let Ok(entries) = self.list_dir(&record) {
-                for entry in entries {
-                    let child_id = entry.file_id();
-                    let child_path = if path == self.path_separator() {
-                        format!("{}{}", self.path_separator(), entry.name())
-                    } else {
-                        format!("{}{}{}", path, self.path_separator(), entry.name())
-                    };
-                    queue.push_back((child_id, child_path));
-                }
-            }
-        }
+    /// Ranges of the content of a record that no storage backs (sparse holes, unwritten
+    /// extents) and read as zeros, or `None` when the backend cannot tell.
+    fn file_holes(
+        &mut self,
+        _file: &Self::FileType,
+    ) -> Result<Option<Vec<ByteRange>>, Box<dyn Error>> {
+        Ok(None)
+    }
 
-        Ok(())
+    /// Raw on-disk content of a directory, for slack parsing and manual recovery, or
+    /// `None` when the backend keeps directories in structures shared with other records
+    /// (APFS B-trees, host folders).
+    fn read_directory_data(
+        &mut self,
+        _dir: &Self::FileType,
+    ) -> Result<Option<Vec<u8>>, Box<dyn Error>> {
+        Ok(None)
+    }
+
+    /// Extended attributes of a record, in on-disk order; empty when it has none or the
+    /// backend does not read them.
+    fn extended_attributes(
+        &mut self,
+        _file: &Self::FileType,
+    ) -> Result<Vec<ExtendedAttribute>, Box<dyn Error>> {
+        Ok(Vec::new())
+    }
+
+    /// Whether a record is still described by the filesystem metadata but no longer in
+    /// use (a deleted file), or `None` when the backend cannot tell.
+    fn is_deleted(&self, _file: &Self::FileType) -> Option<bool> {
+        None
+    }
+
+    /// Names of deleted records that their record holds no name for, by record number,
+    /// as the directory entries left behind in their parent directory still give them.
+    fn deleted_entry_names(&mut self) -> Result<HashMap<u64, String>, Box<dyn Error>> {
+        Ok(HashMap::new())
     }
 
     /// Return all files in the filesystem
@@ -171,34 +753,86 @@ pub trait Filesystem {
         Ok(files)
     }
 
+    /// Return the deleted records found by scanning the record table. They are no longer
+    /// reachable from the root, so each is placed under `$OrphanFiles` with its
+    /// recorded name, the name of its deleted directory entry (see
+    /// `deleted_entry_names`), or `record_<id>` when neither is left.
+    fn enumerate_deleted_files(
+        &mut self,
+        on_status: &mut dyn FnMut(String),
+    ) -> Result<Vec<File>, Box<dyn Error>> {
+        let root = self.get_file(self.get_root_file_id())?;
+        if self.is_deleted(&root).is_none() {
+            return Err(format!(
+                "{} does not support deleted record enumeration",
+                self.filesystem_type()
+            )
+            .into());
+        }
+        let separator = self.path_separator();
+        let count = self.record_count();
+        let names = self.deleted_entry_names()?;
+        let mut files = Vec::new();
+        for id in 0..=count {
+            if id > 0 && id % 100_000 == 0 {
+                on_status(format!("{} of {} records scanned", id, count));
+            }
+            let Ok(record) = self.get_file(id) else {
+                continue;
+            };
+            if self.is_deleted(&record) != Some(true) {
+                continue;
+            }
+            let mut file = self.record_to_file(&record, id, "");
+            if file.name.is_empty() {
+                file.name = match names.get(&id) {
+                    Some(name) => name.clone(),
+                    None => format!("record_{}", id),
+                };
+            }
+            file.absolute_path = format!("{0}$OrphanFiles{0}{1}", separator, file.name);
+            files.push(file);
+        }
+        Ok(files)
+    }
+
     fn dump_to_fs(&mut self, file: &Self::FileType) {
-        info!(
-            "Dumping file {} content into 'file_{}.bin'",
-            file.id(),
-            file.id()
-        );
+        let filename = format!("file_{}.bin", file.id());
+        info!("Dumping file {} content into '{}'", file.id(), filename);
 
-        match &self.read_file_content(file) {
-            Ok(data) => {
-                let filename = format!("file_{}.bin", file.id());
-                match StdFile::create(&filename) {
-                    Ok(mut f) => {
-                        if let Err(e) = f.write_all(data) {
-                            error!("Error writing file '{}': {}", filename, e);
-                        } else {
-                            info!(
-                                "Successfully wrote {} bytes into '{}'",
-                                data.len(),
-                                filename
-                            );
-                        }
-                    }
-                    Err(e) => error!("Could not create dump file '{}': {}", filename, e),
-                }
-            }
+        // Streamed with a progress display, as the content may not fit in memory.
+        let mut out = match StdFile::create(&filename) {
+            Ok(f) => BufWriter::new(f),
             Err(e) => {
-                error!("Cannot read content for inode {}: {}", file.id(), e);
+                error!("Could not create dump file '{}': {}", filename, e);
+                return;
             }
+        };
+        let progress = Progress::new(Some(file.size()), ProgressUnit::Bytes);
+        let mut buf = vec![0u8; 1 << 20];
+        let mut written = 0u64;
+        let result = loop {
+            let wanted = buf.len().min(file.size().saturating_sub(written) as usize);
+            let n = match self.read_file_slice_into(file, written, &mut buf[..wanted]) {
+                Ok(0) => break out.flush().map_err(Box::<dyn Error>::from),
+                Ok(n) => n,
+                Err(e) => break Err(e),
+            };
+            if let Err(e) = out.write_all(&buf[..n]) {
+                break Err(e.into());
+            }
+            written += n as u64;
+            progress.inc(n as u64);
+        };
+        progress.finish();
+        match result {
+            Ok(()) => info!("Successfully wrote {} bytes into '{}'", written, filename),
+            Err(e) => error!(
+                "Error dumping file {} into '{}': {}",
+                file.id(),
+                filename,
+                e
+            ),
         }
     }
 
@@ -216,10 +850,42 @@ pub trait Filesystem {
     }
 }
 
+/// Walk `fs` while `consume` processes its events on another thread, through a channel
+/// holding at most `capacity` events: the walk waits whenever the consumer falls behind,
+/// so memory stays bounded on volumes of any size. The capacity shrinks to fit the memory
+/// budget.
+pub fn stream_walk<F, R>(
+    fs: &mut F,
+    options: WalkOptions,
+    capacity: usize,
+    consume: impl FnOnce(Receiver<WalkEvent>) -> R + Send,
+) -> Result<R, Box<dyn Error>>
+where
+    F: Filesystem + ?Sized,
+    R: Send,
+{
+    let capacity = budget::items(capacity, budget::BATCH_SHARE, EVENT_BYTES);
+    let (sender, receiver) = mpsc::sync_channel(capacity);
+    thread::scope(|scope| {
+        let consumer = scope.spawn(move || consume(receiver));
+        // Events sent once the consumer has returned are dropped: its result tells why
+        // it stopped.
+        let walked = fs.walk_fs_with(options, &mut |event| {
+            let _ = sender.send(event);
+        });
+        drop(sender);
+        let result = consumer
+            .join()
+            .map_err(|_| "the consumer of the walk panicked")?;
+        walked?;
+        Ok(result)
+    })
+}
+
 /// Single-thread Read+Seek adapter backed by Filesystem::read_file_slice().
 pub struct FsFileReadSeek<'a, F>
 where
-    F: Filesystem,
+    F: Filesystem + ?Sized,
     F::FileType: FileCommon,
 {
     fs: &'a mut F,
@@ -234,7 +900,7 @@ where
 
 impl<'a, F> FsFileReadSeek<'a, F>
 where
-    F: Filesystem,
+    F: Filesystem + ?Sized,
     F::FileType: FileCommon,
 {
     /// Create an adapter from an already fetched filesystem file record.
@@ -278,20 +944,25 @@ where
             return Ok(());
         }
 
+        // The cache buffer is reused from one refill to the next.
         let want = (self.len - at).min(CACHE_SIZE as u64) as usize;
-        let data = self
+        self.cache.resize(want, 0);
+        let read = self
             .fs
-            .read_file_slice(&self.file, at, want)
-            .map_err(|e| io::Error::new(io::ErrorKind::Other, e.to_string()))?;
+            .read_file_slice_into(&self.file, at, &mut self.cache);
+        let n = read.map_err(|e| {
+            self.cache.clear();
+            io::Error::other(e.to_string())
+        })?;
+        self.cache.truncate(n);
         self.cache_start = at;
-        self.cache = data;
         Ok(())
     }
 }
 
 impl<'a, F> Read for FsFileReadSeek<'a, F>
 where
-    F: Filesystem,
+    F: Filesystem + ?Sized,
     F::FileType: FileCommon,
 {
     fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
@@ -327,7 +998,7 @@ where
 
 impl<'a, F> Seek for FsFileReadSeek<'a, F>
 where
-    F: Filesystem,
+    F: Filesystem + ?Sized,
     F::FileType: FileCommon,
 {
     fn seek(&mut self, pos: SeekFrom) -> io::Result<u64> {
@@ -1,24 +1,274 @@
-use crate::filesystem::{DirectoryCommon, File, FileCommon, Filesystem};
+use crate::exfat_impl::{dos_attr_names, dos_attr_string};
+use crate::filesystem::{
+    DEVICE_KEY, DirectoryCommon, EXTENDED_ATTRIBUTES_KEY, ExtendedAttribute, FLAGS_KEY, File,
+    FileCommon, Filesystem, SYMLINK_TARGET_KEY, namespaced_metadata, unix_ftype,
+};
+use crate::names::{name_bytes, render_path};
+use crate::throttle::Throttled;
+use log::debug;
+use serde::Serialize;
 use serde_json::{Value, json};
+use std::collections::HashMap;
 use std::error::Error;
+use std::ffi::{OsStr, OsString};
 use std::fs::{self, File as StdFile};
-use std::io::{Read, Seek, SeekFrom};
-use std::os::unix::fs::MetadataExt;
-use std::path::{Path, PathBuf};
+use std::io::{self, BufRead, BufReader, BufWriter, Read, Seek, SeekFrom, Write};
+use std::path::{Component, Path, PathBuf};
+use std::sync::{Arc, Mutex};
 use std::time::UNIX_EPOCH;
 
+/// Namespace of the host file details in `File.metadata`.
+pub const METADATA_NAMESPACE: &str = "folder";
+
+/// Key set under `folder` on directories of another device than the root, whose content
+/// was not listed because the walk stays on one filesystem.
+pub const MOUNT_POINT_KEY: &str = "mount_point";
+
+/// Key of the host device and inode (file index on Windows) of a record under `folder`.
+pub const HOST_ID_KEY: &str = "host_id";
+
+/// Key of the POSIX ACLs of a record under `folder`, in `getfacl` short text form.
+pub const ACL_KEY: &str = "acl";
+
+const ACL_ACCESS_XATTR: &str = "system.posix_acl_access";
+const ACL_DEFAULT_XATTR: &str = "system.posix_acl_default";
+const ACL_XATTR_VERSION: u32 = 2;
+
+const QUARANTINE_XATTR: &str = "com.apple.quarantine";
+const SPOTLIGHT_XATTR_PREFIX: &str = "com.apple.metadata:";
+
+/// `st_flags` bits (macOS, BSDs), named as chflags(1) does.
+const BSD_FLAGS: [(u32, &str); 15] = [
+    (0x0000_0001, "nodump"),
+    (0x0000_0002, "uchg"),
+    (0x0000_0004, "uappnd"),
+    (0x0000_0008, "opaque"),
+    (0x0000_0020, "compressed"),
+    (0x0000_0040, "tracked"),
+    (0x0000_0080, "datavault"),
+    (0x0000_8000, "hidden"),
+    (0x0001_0000, "arch"),
+    (0x0002_0000, "schg"),
+    (0x0004_0000, "sappnd"),
+    (0x0008_0000, "restricted"),
+    (0x0010_0000, "sunlnk"),
+    (0x0080_0000, "firmlink"),
+    (0x4000_0000, "dataless"),
+];
+
+/// Names of the flags set in `st_flags`.
+pub fn bsd_flag_names(flags: u32) -> Vec<&'static str> {
+    BSD_FLAGS
+        .iter()
+        .filter(|(bit, _)| flags & bit != 0)
+        .map(|(_, name)| *name)
+        .collect()
+}
+
+/// Linux capabilities, by bit number.
+const CAPABILITIES: [&str; 41] = [
+    "chown",
+    "dac_override",
+    "dac_read_search",
+    "fowner",
+    "fsetid",
+    "kill",
+    "setgid",
+    "setuid",
+    "setpcap",
+    "linux_immutable",
+    "net_bind_service",
+    "net_broadcast",
+    "net_admin",
+    "net_raw",
+    "ipc_lock",
+    "ipc_owner",
+    "sys_module",
+    "sys_rawio",
+    "sys_chroot",
+    "sys_ptrace",
+    "sys_pacct",
+    "sys_admin",
+    "sys_boot",
+    "sys_nice",
+    "sys_resource",
+    "sys_time",
+    "sys_tty_config",
+    "mknod",
+    "lease",
+    "audit_write",
+    "audit_control",
+    "setfcap",
+    "mac_override",
+    "mac_admin",
+    "syslog",
+    "wake_alarm",
+    "block_suspend",
+    "audit_read",
+    "perfmon",
+    "bpf",
+    "checkpoint_restore",
+];
+
+/// Identity and Unix style attributes of a host file, as far as the platform has them.
+struct HostInfo {
+    /// Inode number on Unix, file index on Windows.
+    id: u64,
+    /// Device number on Unix, volume serial number on Windows.
+    device: u64,
+    mode: u32,
+    uid: Option<u32>,
+    gid: Option<u32>,
+    rdev: u64,
+    changed: Option<u64>,
+    /// Windows file attributes.
+    attributes: Option<u32>,
+    /// BSD file flags (macOS, FreeBSD).
+    flags: Option<u32>,
+    /// Bytes allocated: `st_blocks` on Unix, the compressed file size on Windows.
+    size_on_disk: Option<u64>,
+}
+
+#[cfg(unix)]
+fn host_info(_path: &Path, metadata: &fs::Metadata) -> HostInfo {
+    use std::os::unix::fs::MetadataExt;
+    #[cfg(target_os = "macos")]
+    let flags = Some(std::os::macos::fs::MetadataExt::st_flags(metadata));
+    #[cfg(target_os = "freebsd")]
+    let flags = Some(std::os::freebsd::fs::MetadataExt::st_flags(metadata));
+    #[cfg(not(any(target_os = "macos", target_os = "freebsd")))]
+    let flags = None;
+    HostInfo {
+        id: metadata.ino(),
+        device: metadata.dev(),
+        mode: metadata.mode(),
+        uid: Some(metadata.uid()),
+        gid: Some(metadata.gid()),
+        rdev: metadata.rdev(),
+        changed: u64::try_from(metadata.ctime()).ok(),
+        attributes: None,
+        flags,
+        size_on_disk: Some(metadata.blocks() * 512),
+    }
+}
+
+/// Windows has no inode numbers in `std`: the file index and volume serial number come
+/// from `GetFileInformationByHandle`, and the mode is derived from the file type and the
+/// read-only attribute.
+#[cfg(windows)]
+fn host_info(path: &Path, metadata: &fs::Metadata) -> HostInfo {
+    use std::hash::{DefaultHasher, Hash, Hasher};
+    use std::os::windows::fs::{MetadataExt, OpenOptionsExt};
+    use std::os::windows::io::AsRawHandle;
+    use windows_sys::Win32::Storage::FileSystem::{
+        BY_HANDLE_FILE_INFORMATION, FILE_ATTRIBUTE_READONLY, FILE_FLAG_BACKUP_SEMANTICS,
+        FILE_FLAG_OPEN_REPARSE_POINT, GetFileInformationByHandle,
+    };
+
+    // No access right is needed to query a handle; directories need backup semantics,
+    // and links are queried themselves unless `metadata` describes their target.
+    let mut flags = FILE_FLAG_BACKUP_SEMANTICS;
+    if metadata.file_type().is_symlink() {
+        flags |= FILE_FLAG_OPEN_REPARSE_POINT;
+    }
+    let by_handle = fs::OpenOptions::new()
+        .access_mode(0)
+        .custom_flags(flags)
+        .open(path)
+        .ok()
+        .and_then(|file| {
+            // SAFETY: the handle stays open for the call and `info` is plain data.
+            let mut info: BY_HANDLE_FILE_INFORMATION = unsafe { std::mem::zeroed() };
+            let ok = unsafe { GetFileInformationByHandle(file.as_raw_handle(), &mut info) };
+            (ok != 0).then_some(info)
+        });
+    let (id, device) = match by_handle {
+        Some(info) => (
+            ((info.nFileIndexHigh as u64) << 32) | info.nFileIndexLow as u64,
+            info.dwVolumeSerialNumber as u64,
+        ),
+        None => {
+            // Unreadable (e.g. locked) file: identify it by its path for this run.
+            let mut hasher = DefaultHasher::new();
+            path.hash(&mut hasher);
+            (hasher.finish(), 0)
+        }
+    };
+
+    let attributes = metadata.file_attributes();
+    let file_type = metadata.file_type();
+    let mut mode = if file_type.is_symlink() {
+        0o120777
+    } else if file_type.is_dir() {
+        0o040777
+    } else {
+        0o100666
+    };
+    if attributes & FILE_ATTRIBUTE_READONLY != 0 && !file_type.is_symlink() {
+        mode &= !0o222;
+    }
+    HostInfo {
+        id,
+        device,
+        mode,
+        uid: None,
+        gid: None,
+        rdev: 0,
+        changed: None,
+        attributes: Some(attributes),
+        flags: None,
+        size_on_disk: compressed_file_size(path),
+    }
+}
+
+/// Bytes a file takes on its volume, from `GetCompressedFileSizeW`: less than its size
+/// when it is compressed or sparse.
+#[cfg(windows)]
+fn compressed_file_size(path: &Path) -> Option<u64> {
+    use std::os::windows::ffi::OsStrExt;
+    use windows_sys::Win32::Foundation::{GetLastError, NO_ERROR};
+    use windows_sys::Win32::Storage::FileSystem::{GetCompressedFileSizeW, INVALID_FILE_SIZE};
+
+    let wide: Vec<u16> = path.as_os_str().encode_wide().chain([0]).collect();
+    let mut high = 0u32;
+    // SAFETY: `wide` is NUL-terminated and `high` outlives the call.
+    let low = unsafe { GetCompressedFileSizeW(wide.as_ptr(), &mut high) };
+    if low == INVALID_FILE_SIZE && unsafe { GetLastError() } != NO_ERROR {
+        return None;
+    }
+    Some(((high as u64) << 32) | low as u64)
+}
+
 #[derive(Debug, Clone)]
 pub struct FolderFile {
     pub id: u64,
     pub path: PathBuf,
     pub size: u64,
     pub is_dir: bool,
+    /// Birth time: `st_birthtime` on macOS and the BSDs, `statx` on Linux.
     pub created: Option<u64>,
     pub modified: Option<u64>,
     pub accessed: Option<u64>,
+    pub changed: Option<u64>,
+    /// Unix mode; synthesized from the file type and read-only attribute on Windows.
     pub permissions: u32,
-    pub uid: u32,
-    pub gid: u32,
+    /// Owner and group, `None` on Windows.
+    pub uid: Option<u32>,
+    pub gid: Option<u32>,
+    /// Device number of device files.
+    pub rdev: u64,
+    /// Device (Unix) or volume serial number (Windows) holding the file.
+    pub device: u64,
+    /// Inode (Unix) or file index (Windows) of the file on `device`.
+    pub inode: u64,
+    /// Windows file attributes.
+    pub attributes: Option<u32>,
+    /// BSD file flags (`st_flags`) on macOS and FreeBSD.
+    pub flags: Option<u32>,
+    /// Bytes allocated on the host filesystem.
+    pub size_on_disk: Option<u64>,
+    /// Target of a symbolic link, whether or not it was followed.
+    pub link_target: Option<PathBuf>,
 }
 
 impl FileCommon for FolderFile {
@@ -46,9 +296,16 @@ impl FileCommon for FolderFile {
             "created": self.created,
             "modified": self.modified,
             "accessed": self.accessed,
+            "changed": self.changed,
             "permissions": self.permissions,
             "uid": self.uid,
-            "gid": self.gid
+            "gid": self.gid,
+            "rdev": self.rdev,
+            "device": self.device,
+            "inode": self.inode,
+            "attributes": self.attributes,
+            "flags": self.flags,
+            "link_target": self.link_target
         })
     }
 }
@@ -80,28 +337,422 @@ impl DirectoryCommon for FolderDirectory {
     }
 }
 
-use std::collections::HashMap;
+/// Extended attributes of the host file at `path`, of the link itself when `link` is set.
+#[cfg(unix)]
+fn host_xattrs(path: &Path, link: bool) -> io::Result<Vec<ExtendedAttribute>> {
+    if !xattr::SUPPORTED_PLATFORM {
+        return Ok(Vec::new());
+    }
+    let names = if link {
+        xattr::list(path)?
+    } else {
+        xattr::list_deref(path)?
+    };
+    let mut attributes = Vec::new();
+    for name in names {
+        let value = if link {
+            xattr::get(path, &name)?
+        } else {
+            xattr::get_deref(path, &name)?
+        };
+        // Removed since it was listed.
+        if let Some(value) = value {
+            attributes.push((name.to_string_lossy().into_owned(), value));
+        }
+    }
+    Ok(attributes)
+}
+
+#[cfg(not(unix))]
+fn host_xattrs(_path: &Path, _link: bool) -> io::Result<Vec<ExtendedAttribute>> {
+    Ok(Vec::new())
+}
+
+/// Name of a host entry in the escaped form of `names`.
+#[cfg(unix)]
+fn host_name(name: &OsStr) -> String {
+    use std::os::unix::ffi::OsStrExt;
+    crate::names::escape_name(name.as_bytes())
+}
+
+/// Windows names are UTF-16: only unpaired surrogates, replaced, do not convert.
+#[cfg(not(unix))]
+fn host_name(name: &OsStr) -> String {
+    name.to_string_lossy().into_owned()
+}
+
+/// Host name of a path component given in the escaped form, or as rendered with
+/// replacement characters (the first entry of `dir` rendering that way).
+#[cfg(unix)]
+fn host_component(dir: &Path, component: &OsStr) -> OsString {
+    use std::os::unix::ffi::OsStringExt;
+    let Some(text) = component.to_str() else {
+        return component.to_owned();
+    };
+    if let Some(bytes) = name_bytes(text) {
+        return OsString::from_vec(bytes);
+    }
+    if text.contains('\u{FFFD}')
+        && let Ok(entries) = fs::read_dir(dir)
+    {
+        for entry in entries.flatten() {
+            if crate::names::name_matches(&host_name(&entry.file_name()), text) {
+                return entry.file_name();
+            }
+        }
+    }
+    component.to_owned()
+}
+
+#[cfg(not(unix))]
+fn host_component(_dir: &Path, component: &OsStr) -> OsString {
+    component.to_owned()
+}
+
+/// One extended attribute of a host file: SELinux labels, capability sets, ACLs and
+/// quarantine flags all live there.
+#[derive(Debug, Clone, Serialize)]
+pub struct HostXattr {
+    pub name: String,
+    pub size: usize,
+    /// Value, hex encoded.
+    pub value: String,
+    /// Decoded value of the attributes known to carry security information.
+    pub decoded: Option<Value>,
+}
+
+/// `getfacl` short text form of a `system.posix_acl_*` value.
+fn decode_posix_acl(value: &[u8]) -> Option<String> {
+    let version = u32::from_le_bytes(value.get(0..4)?.try_into().unwrap());
+    if version != ACL_XATTR_VERSION || !(value.len() - 4).is_multiple_of(8) {
+        return None;
+    }
+    let mut entries = Vec::new();
+    for entry in value[4..].chunks_exact(8) {
+        let tag = u16::from_le_bytes([entry[0], entry[1]]);
+        let perm = u16::from_le_bytes([entry[2], entry[3]]);
+        let id = u32::from_le_bytes(entry[4..8].try_into().unwrap());
+        let qualifier = match tag {
+            0x01 => "user::".to_string(),
+            0x02 => format!("user:{}:", id),
+            0x04 => "group::".to_string(),
+            0x08 => format!("group:{}:", id),
+            0x10 => "mask::".to_string(),
+            0x20 => "other::".to_string(),
+            _ => return None,
+        };
+        let bit = |mask: u16, c: char| if perm & mask != 0 { c } else { '-' };
+        entries.push(format!(
+            "{}{}{}{}",
+            qualifier,
+            bit(4, 'r'),
+            bit(2, 'w'),
+            bit(1, 'x')
+        ));
+    }
+    Some(entries.join(","))
+}
+
+/// Permitted and inheritable sets of a `security.capability` value (`vfs_cap_data`).
+fn decode_capabilities(value: &[u8]) -> Option<Value> {
+    let u32_at = |at: usize| {
+        value
+            .get(at..at + 4)
+            .map(|b| u32::from_le_bytes(b.try_into().unwrap()))
+    };
+    let magic = u32_at(0)?;
+    let (revision, words) = match magic & 0xff00_0000 {
+        0x0100_0000 => (1, 1),
+        0x0200_0000 => (2, 2),
+        0x0300_0000 => (3, 2),
+        _ => return None,
+    };
+    let (mut permitted, mut inheritable) = (0u64, 0u64);
+    for word in 0..words {
+        permitted |= (u32_at(4 + word * 8)? as u64) << (32 * word);
+        inheritable |= (u32_at(8 + word * 8)? as u64) << (32 * word);
+    }
+    let names = |set: u64| {
+        (0..64)
+            .filter(|bit| set & (1 << bit) != 0)
+            .map(|bit| match CAPABILITIES.get(bit) {
+                Some(name) => format!("cap_{}", name),
+                None => format!("cap_{}", bit),
+            })
+            .collect::<Vec<_>>()
+    };
+    let mut decoded = json!({
+        "revision": revision,
+        "effective": magic & 1 != 0,
+        "permitted": names(permitted),
+        "inheritable": names(inheritable),
+    });
+    if revision == 3 {
+        decoded["root_id"] = json!(u32_at(20)?);
+    }
+    Some(decoded)
+}
+
+/// Flags, download time, downloading application and event of a
+/// `com.apple.quarantine` value (`0083;5f0a1b2c;Safari;<UUID>`).
+fn decode_quarantine(value: &[u8]) -> Option<Value> {
+    let text = std::str::from_utf8(value).ok()?;
+    let mut fields = text.trim_end_matches('\0').split(';');
+    let flags = u32::from_str_radix(fields.next()?, 16).ok()?;
+    let timestamp = fields.next().and_then(|t| u64::from_str_radix(t, 16).ok());
+    Some(json!({
+        "flags": format!("0x{:04x}", flags),
+        "timestamp": timestamp,
+        "agent": fields.next().filter(|a| !a.is_empty()),
+        "event_id": fields.next().filter(|e| !e.is_empty()),
+    }))
+}
+
+/// JSON form of a property list value; dates become Unix seconds and data hex.
+fn plist_to_json(value: plist::Value) -> Value {
+    match value {
+        plist::Value::Array(items) => Value::Array(items.into_iter().map(plist_to_json).collect()),
+        plist::Value::Dictionary(dict) => Value::Object(
+            dict.into_iter()
+                .map(|(key, value)| (key, plist_to_json(value)))
+                .collect(),
+        ),
+        plist::Value::Boolean(b) => json!(b),
+        plist::Value::Data(data) => json!(hex::encode(data)),
+        plist::Value::Date(date) => json!(
+            std::time::SystemTime::from(date)
+                .duration_since(UNIX_EPOCH)
+                .ok()
+                .map(|d| d.as_secs())
+        ),
+        plist::Value::Real(r) => json!(r),
+        plist::Value::Integer(i) => match i.as_signed() {
+            Some(i) => json!(i),
+            None => json!(i.as_unsigned()),
+        },
+        plist::Value::String(s) => json!(s),
+        plist::Value::Uid(uid) => json!(uid.get()),
+        _ => Value::Null,
+    }
+}
+
+fn decode_xattr(name: &str, value: &[u8]) -> Option<Value> {
+    if name.starts_with(SPOTLIGHT_XATTR_PREFIX) {
+        // Spotlight attributes (kMDItemWhereFroms, kMDItemDownloadedDate...) are
+        // binary property lists.
+        return plist::Value::from_reader(io::Cursor::new(value))
+            .ok()
+            .map(plist_to_json);
+    }
+    match name {
+        QUARANTINE_XATTR => decode_quarantine(value),
+        ACL_ACCESS_XATTR | ACL_DEFAULT_XATTR => decode_posix_acl(value).map(Value::from),
+        "security.capability" => decode_capabilities(value),
+        "security.selinux" | "security.apparmor" | "security.SMACK64" => {
+            let text = std::str::from_utf8(value).ok()?;
+            Some(json!(text.trim_end_matches('\0')))
+        }
+        _ => None,
+    }
+}
+
+/// Where an assigned identifier points.
+#[derive(Debug, Clone)]
+struct IdEntry {
+    device: u64,
+    inode: u64,
+    path: PathBuf,
+}
+
+/// Identifiers of the files of a folder. Inode numbers alone collide across devices and
+/// get reused, so every (device, inode) pair met is given its own number, kept for the
+/// life of the map; hard links share one. The map can be saved and loaded again so that
+/// the identifiers of an earlier walk still resolve.
+#[derive(Debug, Default)]
+pub struct FolderIds {
+    by_host: HashMap<(u64, u64), u64>,
+    entries: HashMap<u64, IdEntry>,
+}
+
+impl FolderIds {
+    /// Identifier of the file `(device, inode)`, reached at `path`.
+    pub fn assign(&mut self, device: u64, inode: u64, path: PathBuf) -> u64 {
+        // Identifiers are never dropped, so the next free one is past the count.
+        let next = self.entries.len() as u64 + 1;
+        let id = *self.by_host.entry((device, inode)).or_insert(next);
+        self.entries.insert(
+            id,
+            IdEntry {
+                device,
+                inode,
+                path,
+            },
+        );
+        id
+    }
+
+    pub fn len(&self) -> usize {
+        self.entries.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.entries.is_empty()
+    }
+
+    /// Save the map of the folder `root` atomically (write to a temporary file, then
+    /// rename), as JSON lines: the root, then `[id, device, inode, path]` per file.
+    pub fn save(&self, root: &Path, path: &str) -> Result<(), Box<dyn Error>> {
+        let tmp = format!("{}.tmp", path);
+        let mut out = BufWriter::new(StdFile::create(&tmp)?);
+        serde_json::to_writer(&mut out, &json!({ "root_path": root.to_string_lossy() }))?;
+        out.write_all(b"\n")?;
+        for (id, entry) in &self.entries {
+            let line = json!([id, entry.device, entry.inode, entry.path.to_string_lossy()]);
+            serde_json::to_writer(&mut out, &line)?;
+            out.write_all(b"\n")?;
+        }
+        out.flush()?;
+        drop(out);
+        fs::rename(&tmp, path)?;
+        Ok(())
+    }
+
+    /// Load a map saved by `save` for the folder `root`.
+    pub fn load(root: &Path, path: &str) -> Result<Self, Box<dyn Error>> {
+        let mut lines = BufReader::new(StdFile::open(path)?).lines();
+        let header: Value = match lines.next() {
+            Some(line) => serde_json::from_str(&line?)?,
+            None => return Err(format!("{}: empty identifier map", path).into()),
+        };
+        let saved_root = header["root_path"].as_str().unwrap_or_default();
+        if saved_root != root.to_string_lossy() {
+            return Err(format!(
+                "{}: identifiers of {}, not {}",
+                path,
+                saved_root,
+                root.display()
+            )
+            .into());
+        }
+        let mut ids = Self::default();
+        for line in lines {
+            let (id, device, inode, file): (u64, u64, u64, String) = serde_json::from_str(&line?)?;
+            ids.by_host.insert((device, inode), id);
+            ids.entries.insert(
+                id,
+                IdEntry {
+                    device,
+                    inode,
+                    path: PathBuf::from(file),
+                },
+            );
+        }
+        Ok(ids)
+    }
+}
+
+/// How a folder walk treats symbolic links and mount points.
+#[derive(Debug, Clone, Copy, Default)]
+pub struct FolderOptions {
+    /// Describe (and descend into) the target of symbolic links instead of the links.
+    /// Dangling links are still described as links.
+    pub follow_symlinks: bool,
+    /// Do not list directories on another device than the root (mount points), like
+    /// `find -xdev`.
+    pub one_file_system: bool,
+}
 
 pub struct FolderFS {
     pub root_path: PathBuf,
-    pub path_cache: HashMap<u64, PathBuf>,
+    pub options: FolderOptions,
+    /// Shared with the other handles on the folder (see `with_ids`).
+    ids: Arc<Mutex<FolderIds>>,
+    root_id: u64,
+    /// Device holding the root, `None` when it could not be read.
+    root_device: Option<u64>,
 }
 
 impl FolderFS {
     pub fn new(root_path: PathBuf) -> Self {
+        Self::with_options(root_path, FolderOptions::default())
+    }
+
+    pub fn with_options(root_path: PathBuf, options: FolderOptions) -> Self {
+        Self::with_ids(root_path, options, Arc::default())
+    }
+
+    /// Open the folder with the identifier map of another handle on it, so that records
+    /// discovered through either resolve in both (parallel walks) or with a map loaded
+    /// from an earlier run.
+    pub fn with_ids(
+        root_path: PathBuf,
+        options: FolderOptions,
+        ids: Arc<Mutex<FolderIds>>,
+    ) -> Self {
         let mut fs = Self {
             root_path: root_path.clone(),
-            path_cache: HashMap::new(),
+            options,
+            ids,
+            root_id: 0,
+            root_device: None,
         };
-        // Prime the cache with the root
-        if let Ok(meta) = fs::metadata(&root_path) {
-            fs.path_cache.insert(meta.ino(), root_path);
+        if let Ok((meta, _)) = fs.stat(&root_path) {
+            let host = host_info(&root_path, &meta);
+            fs.root_device = Some(host.device);
+            fs.root_id = fs.identify(&host, root_path);
         }
         fs
     }
 
-    fn get_file_from_path(&self, path: &Path, id: u64) -> Result<FolderFile, Box<dyn Error>> {
+    /// The identifier map of this handle.
+    pub fn ids(&self) -> Arc<Mutex<FolderIds>> {
+        Arc::clone(&self.ids)
+    }
+
+    /// Replace the identifier map with one saved by `FolderIds::save`.
+    pub fn load_ids(&mut self, path: &str) -> Result<(), Box<dyn Error>> {
+        let loaded = FolderIds::load(&self.root_path, path)?;
+        *self.ids.lock().unwrap_or_else(|e| e.into_inner()) = loaded;
+        let (meta, _) = self.stat(&self.root_path)?;
+        let host = host_info(&self.root_path, &meta);
+        self.root_id = self.identify(&host, self.root_path.clone());
+        Ok(())
+    }
+
+    /// Save the identifier map, see `FolderIds::save`.
+    pub fn save_ids(&self, path: &str) -> Result<(), Box<dyn Error>> {
+        let ids = self.ids.lock().unwrap_or_else(|e| e.into_inner());
+        ids.save(&self.root_path, path)
+    }
+
+    fn identify(&self, host: &HostInfo, path: PathBuf) -> u64 {
+        let mut ids = self.ids.lock().unwrap_or_else(|e| e.into_inner());
+        ids.assign(host.device, host.id, path)
+    }
+
+    /// Metadata of `path` under the symlink policy, with the target of the link when
+    /// `path` is one. A linked root is always followed.
+    fn stat(&self, path: &Path) -> io::Result<(fs::Metadata, Option<PathBuf>)> {
         let metadata = fs::symlink_metadata(path)?;
+        if !metadata.file_type().is_symlink() {
+            return Ok((metadata, None));
+        }
+        let target = fs::read_link(path).ok();
+        if (self.options.follow_symlinks || path == self.root_path)
+            && let Ok(followed) = fs::metadata(path)
+        {
+            return Ok((followed, target));
+        }
+        Ok((metadata, target))
+    }
+
+    /// Whether `file` lies on another device than the root and must not be listed.
+    fn outside_root_device(&self, file: &FolderFile) -> bool {
+        self.options.one_file_system && self.root_device.is_some_and(|d| d != file.device)
+    }
+
+    fn get_file_from_path(&self, path: &Path, id: u64) -> Result<FolderFile, Box<dyn Error>> {
+        let (metadata, link_target) = self.stat(path)?;
 
         let created = metadata
             .created()
@@ -119,6 +770,7 @@ impl FolderFS {
             .and_then(|t| t.duration_since(UNIX_EPOCH).ok())
             .map(|d| d.as_secs());
 
+        let host = host_info(path, &metadata);
         Ok(FolderFile {
             id,
             path: path.to_path_buf(),
@@ -127,9 +779,17 @@ impl FolderFS {
             created,
             modified,
             accessed,
-            permissions: metadata.mode(),
-            uid: metadata.uid(),
-            gid: metadata.gid(),
+            changed: host.changed,
+            permissions: host.mode,
+            uid: host.uid,
+            gid: host.gid,
+            rdev: host.rdev,
+            device: host.device,
+            inode: host.id,
+            attributes: host.attributes,
+            flags: host.flags,
+            size_on_disk: host.size_on_disk,
+            link_target,
         })
     }
 }
@@ -156,7 +816,9 @@ impl Filesystem for FolderFS {
 
     fn get_metadata(&self) -> Result<Value, Box<dyn Error>> {
         Ok(json!({
-            "root_path": self.root_path
+            "root_path": self.root_path,
+            "follow_symlinks": self.options.follow_symlinks,
+            "one_file_system": self.options.one_file_system
         }))
     }
 
@@ -165,13 +827,31 @@ impl Filesystem for FolderFS {
     }
 
     fn get_file(&mut self, file_id: u64) -> Result<Self::FileType, Box<dyn Error>> {
-        let path = self.path_cache.get(&file_id).ok_or_else(|| {
-            format!("File ID {} not found in path cache. FolderFS requires traversal to populate cache.", file_id)
-        })?;
-
-        // We need to clone path to use it, or just use it.
-        // get_file_from_path takes &Path.
-        self.get_file_from_path(path, file_id)
+        let entry = self
+            .ids
+            .lock()
+            .unwrap_or_else(|e| e.into_inner())
+            .entries
+            .get(&file_id)
+            .cloned()
+            .ok_or_else(|| {
+                format!(
+                    "File ID {} is unknown: FolderFS assigns identifiers while listing directories.",
+                    file_id
+                )
+            })?;
+        let file = self.get_file_from_path(&entry.path, file_id)?;
+        // The path may have been given to another file since the identifier was assigned
+        // (e.g. in an earlier run). Device numbers can change across reboots, inodes not.
+        if file.inode != entry.inode {
+            return Err(format!(
+                "File ID {} ({}) was replaced since it was listed",
+                file_id,
+                entry.path.display()
+            )
+            .into());
+        }
+        Ok(file)
     }
 
     fn get_file_by_path(
@@ -182,7 +862,16 @@ impl Filesystem for FolderFS {
         // The path from system_files is likely "absolute" relative to the FS root (e.g. "/implant.exe").
         // We need to map this to the host filesystem path by joining with root_path.
         let relative_path = path.trim_start_matches(['/', '\\']);
-        let full_path = self.root_path.join(relative_path);
+        let mut full_path = self.root_path.clone();
+        for component in Path::new(relative_path).components() {
+            match component {
+                Component::Normal(name) => {
+                    let name = host_component(&full_path, name);
+                    full_path.push(name)
+                }
+                other => full_path.push(other),
+            }
+        }
 
         if full_path.exists() {
             self.get_file_from_path(&full_path, file_id)
@@ -203,7 +892,7 @@ impl Filesystem for FolderFS {
     }
 
     fn read_file_content(&mut self, file: &Self::FileType) -> Result<Vec<u8>, Box<dyn Error>> {
-        let mut f = StdFile::open(&file.path)?;
+        let mut f = Throttled::new(StdFile::open(&file.path)?);
         let mut buffer = Vec::new();
         f.read_to_end(&mut buffer)?;
         Ok(buffer)
@@ -214,7 +903,7 @@ impl Filesystem for FolderFS {
         file: &Self::FileType,
         length: usize,
     ) -> Result<Vec<u8>, Box<dyn Error>> {
-        let mut f = StdFile::open(&file.path)?;
+        let mut f = Throttled::new(StdFile::open(&file.path)?);
         let mut buffer = vec![0; length];
         let n = f.read(&mut buffer)?;
         buffer.truncate(n);
@@ -227,68 +916,153 @@ impl Filesystem for FolderFS {
         offset: u64,
         length: usize,
     ) -> Result<Vec<u8>, Box<dyn Error>> {
-        let mut f = StdFile::open(&file.path)?;
-        f.seek(SeekFrom::Start(offset))?;
         let mut buffer = vec![0; length];
-        let n = f.read(&mut buffer)?;
+        let n = self.read_file_slice_into(file, offset, &mut buffer)?;
         buffer.truncate(n);
         Ok(buffer)
     }
 
+    fn read_file_slice_into(
+        &mut self,
+        file: &Self::FileType,
+        offset: u64,
+        buf: &mut [u8],
+    ) -> Result<usize, Box<dyn Error>> {
+        let mut f = Throttled::new(StdFile::open(&file.path)?);
+        f.seek(SeekFrom::Start(offset))?;
+        let mut filled = 0;
+        while filled < buf.len() {
+            match f.read(&mut buf[filled..]) {
+                Ok(0) => break,
+                Ok(n) => filled += n,
+                Err(e) if e.kind() == io::ErrorKind::Interrupted => continue,
+                Err(e) => return Err(e.into()),
+            }
+        }
+        Ok(filled)
+    }
+
     fn list_dir(
         &mut self,
         file: &Self::FileType,
     ) -> Result<Vec<Self::DirectoryType>, Box<dyn Error>> {
         let mut entries = Vec::new();
+        if self.outside_root_device(file) {
+            return Ok(entries);
+        }
         for entry in fs::read_dir(&file.path)? {
             let entry = entry?;
-            let metadata = entry.metadata()?;
-            let ino = metadata.ino();
-            let name = entry.file_name().to_string_lossy().to_string();
             let path = entry.path();
-
-            // Populate cache
-            self.path_cache.insert(ino, path);
-
-            entries.push(FolderDirectory { file_id: ino, name });
+            // Same policy as `get_file`, so that ids match the records they resolve to.
+            let (metadata, _) = self.stat(&path)?;
+            let host = host_info(&path, &metadata);
+            let name = host_name(&entry.file_name());
+            let file_id = self.identify(&host, path);
+            entries.push(FolderDirectory { file_id, name });
         }
         Ok(entries)
     }
 
     fn get_root_file_id(&self) -> u64 {
-        fs::metadata(&self.root_path).map(|m| m.ino()).unwrap_or(0)
+        self.root_id
+    }
+
+    fn extended_attributes(
+        &mut self,
+        file: &Self::FileType,
+    ) -> Result<Vec<ExtendedAttribute>, Box<dyn Error>> {
+        let link = unix_ftype(file.permissions) == "symlink";
+        Ok(host_xattrs(&file.path, link)?)
     }
 
     fn record_to_file(&self, file: &Self::FileType, _file_id: u64, absolute_path: &str) -> File {
         // `file` is `FolderFile` which already has metadata.
         // `absolute_path` is passed from the walker.
+        let ftype = unix_ftype(file.permissions);
+        let mut own = json!({ HOST_ID_KEY: { "device": file.device, "inode": file.inode } });
+        let mut common = json!({});
+        if matches!(ftype, "chardev" | "blockdev") {
+            // glibc encoding of `dev_t`.
+            let major = ((file.rdev >> 32) & 0xffff_f000) | ((file.rdev >> 8) & 0xfff);
+            let minor = ((file.rdev >> 12) & 0xffff_ff00) | (file.rdev & 0xff);
+            common[DEVICE_KEY] = json!({ "major": major, "minor": minor });
+        }
+        if let Some(flags) = file.flags {
+            common[FLAGS_KEY] = json!(bsd_flag_names(flags));
+        } else if let Some(attributes) = file.attributes {
+            common[FLAGS_KEY] = json!(dos_attr_names(attributes));
+        }
+        let separator = self.path_separator();
+        if let Some(target) = &file.link_target {
+            common[SYMLINK_TARGET_KEY] =
+                json!(render_path(&host_name(target.as_os_str()), &separator));
+        }
+        if file.is_dir && self.outside_root_device(file) {
+            own[MOUNT_POINT_KEY] = json!(true);
+        }
+        let link = ftype == "symlink";
+        match host_xattrs(&file.path, link) {
+            Ok(xattrs) if !xattrs.is_empty() => {
+                let acl = |name: &str| {
+                    xattrs
+                        .iter()
+                        .find(|(n, _)| n == name)
+                        .and_then(|(_, value)| decode_posix_acl(value))
+                };
+                let (access, default) = (acl(ACL_ACCESS_XATTR), acl(ACL_DEFAULT_XATTR));
+                if access.is_some() || default.is_some() {
+                    own[ACL_KEY] = json!({ "access": access, "default": default });
+                }
+                let xattrs: Vec<HostXattr> = xattrs
+                    .iter()
+                    .map(|(name, value)| HostXattr {
+                        name: name.clone(),
+                        size: value.len(),
+                        value: hex::encode(value),
+                        decoded: decode_xattr(name, value),
+                    })
+                    .collect();
+                common[EXTENDED_ATTRIBUTES_KEY] = json!(xattrs);
+            }
+            Ok(_) => {}
+            Err(e) => debug!(
+                "Could not read the extended attributes of {}: {}",
+                file.path.display(),
+                e
+            ),
+        }
+        let metadata = namespaced_metadata(METADATA_NAMESPACE, own, common);
+        let name = file.path.file_name().map(host_name).unwrap_or_default();
 
         File {
             id: None, // Database ID not yet assigned
             identifier: file.id,
-            absolute_path: absolute_path.to_string(),
-            name: file
-                .path
-                .file_name()
-                .map(|n| n.to_string_lossy().to_string())
-                .unwrap_or_default(),
-            ftype: if file.is_dir {
-                "Directory".to_string()
-            } else {
-                "File".to_string()
-            },
+            absolute_path: render_path(absolute_path, &separator).into_owned(),
+            name: render_path(&name, &separator).into_owned(),
+            raw_name: name_bytes(&name).map(hex::encode),
+            ftype: ftype.to_string(),
             size: file.size,
+            size_on_disk: file.size_on_disk,
             created: file.created,
             modified: file.modified,
             accessed: file.accessed,
-            permissions: Some(format!("{:o}", file.permissions)),
-            owner: Some(file.uid.to_string()),
-            group: Some(file.gid.to_string()),
+            changed: file.changed,
+            permissions: Some(match file.attributes {
+                Some(attributes) => dos_attr_string(attributes, file.is_dir),
+                None => format!("{:o}", file.permissions),
+            }),
+            owner: file.uid.map(|uid| uid.to_string()),
+            group: file.gid.map(|gid| gid.to_string()),
             display: None,
             sig_name: None,
             sig_mime: None,
             sig_exts: None,
-            metadata: json!({}),
+            detected_type: None,
+            ext_mismatch: None,
+            md5: None,
+            sha1: None,
+            sha256: None,
+            metadata,
         }
     }
 }
@@ -0,0 +1,207 @@
+//! Entry points of the fuzz targets (`fuzz/`, run with `cargo fuzz`), behind the
+//! `fuzzing` feature. Each feeds arbitrary bytes to detection and to a parser of on-disk
+//! structures, laid out where the backend looks for them and with the checksums that
+//! would otherwise reject nearly every mutation fixed up. None may panic, whatever the
+//! input: malformed evidence must end in an error, not an abort.
+use crate::detected_fs::{DetectedFs, FsType, open_reader_as};
+use crate::exfat_impl::decode_timestamp;
+use crate::filesystem::{DirectoryCommon, FileCommon, Filesystem};
+use crate::ntfs_impl::{
+    RawAttribute, apply_fixups, decode_runs, ea_entries, object_id_entries, raw_attributes,
+};
+use crate::partitions::{detect_sector_size, read_partition_table};
+use std::collections::{HashSet, VecDeque};
+use std::io::Cursor;
+
+/// Records visited per input, so that a corrupted table claiming millions of records
+/// does not stall the fuzzer.
+const MAX_RECORDS: usize = 256;
+/// Bytes of content read per record.
+const MAX_READ: usize = 64 << 10;
+const APFS_BLOCK_SIZE: usize = 4096;
+
+/// Visit the first records of `fs` breadth first: convert them, list directories and
+/// read their raw data, read the start of file contents and their holes.
+pub fn visit<F: Filesystem>(fs: &mut F) {
+    let Ok(root) = fs.get_file(fs.get_root_file_id()) else {
+        return;
+    };
+    let mut seen = HashSet::from([root.id()]);
+    let mut queue = VecDeque::from([root]);
+    let mut visited = 0;
+    while let Some(record) = queue.pop_front() {
+        if visited == MAX_RECORDS {
+            break;
+        }
+        visited += 1;
+        let _ = fs.record_to_file(&record, record.id(), "/");
+        if record.is_dir() {
+            let _ = fs.read_directory_data(&record);
+            for entry in fs.list_dir(&record).unwrap_or_default() {
+                if matches!(entry.name(), "." | "..") || !seen.insert(entry.file_id()) {
+                    continue;
+                }
+                if let Ok(child) = fs.get_file(entry.file_id()) {
+                    queue.push_back(child);
+                }
+            }
+        } else {
+            let _ = fs.read_file_slice(&record, 0, MAX_READ);
+            let _ = fs.file_holes(&record);
+        }
+    }
+}
+
+fn open_and_visit(image: Vec<u8>, fstype: FsType) {
+    if let Ok(mut fs) = open_reader_as(Cursor::new(image), fstype) {
+        visit(&mut fs);
+    }
+}
+
+/// A partition image, opened as the backend its signature names.
+pub fn detect(image: &[u8]) {
+    let _ = FsType::sniff(&mut Cursor::new(image));
+    if let Ok(mut fs) = DetectedFs::from_reader(Cursor::new(image)) {
+        visit(&mut fs);
+    }
+}
+
+/// A disk image, read for its partition table with both common sector sizes.
+pub fn partition_table(image: &[u8]) {
+    let mut reader = Cursor::new(image);
+    let _ = detect_sector_size(&mut reader);
+    for sector_size in [512, 4096] {
+        let _ = read_partition_table(&mut reader, sector_size);
+    }
+}
+
+/// An ext superblock and what follows it, placed at byte 1024 with its magic set.
+pub fn ext_superblock(superblock: &[u8]) {
+    let mut image = vec![0u8; 1024];
+    image.extend_from_slice(superblock);
+    if image.len() >= 1082 {
+        image[1080..1082].copy_from_slice(&[0x53, 0xef]);
+    }
+    open_and_visit(image, FsType::Ext);
+}
+
+/// An NTFS MFT record (or INDX block) as read from disk: fixups, attribute walk, then
+/// the mapping pairs, extended attributes and object identifier entries it holds.
+pub fn ntfs_record(record: &[u8]) {
+    let mut record = record.to_vec();
+    let _ = apply_fixups(&mut record);
+    for (_, _, content) in raw_attributes(&record) {
+        match content {
+            RawAttribute::Resident(value) => {
+                let _ = ea_entries(value);
+                object_id_entries(value, &mut Vec::new());
+            }
+            RawAttribute::NonResident(pairs, _, _) => {
+                let _ = decode_runs(pairs);
+            }
+        }
+    }
+    object_id_entries(record.get(0x18..).unwrap_or_default(), &mut Vec::new());
+}
+
+/// Fletcher-64 checksum of an APFS object, stored in its first 8 bytes.
+fn apfs_checksum(block: &[u8]) -> u64 {
+    const MODULUS: u64 = 0xFFFF_FFFF;
+    let (mut low, mut high) = (0u64, 0u64);
+    for word in block[8..].chunks_exact(4) {
+        low = (low + u32::from_le_bytes(word.try_into().unwrap()) as u64) % MODULUS;
+        high = (high + low) % MODULUS;
+    }
+    let c1 = MODULUS - (low + high) % MODULUS;
+    let c2 = MODULUS - (low + c1) % MODULUS;
+    (c2 << 32) | c1
+}
+
+/// An APFS container of 4 KiB blocks (container superblock, object map and B-tree
+/// nodes), each block given a valid checksum and the first the container magic.
+pub fn apfs_container(blocks: &[u8]) {
+    let mut image = blocks.to_vec();
+    image.resize(image.len().div_ceil(APFS_BLOCK_SIZE) * APFS_BLOCK_SIZE, 0);
+    if let Some(superblock) = image.get_mut(..APFS_BLOCK_SIZE) {
+        superblock[32..36].copy_from_slice(b"NXSB");
+    }
+    for block in image.chunks_exact_mut(APFS_BLOCK_SIZE) {
+        let checksum = apfs_checksum(block);
+        block[..8].copy_from_slice(&checksum.to_le_bytes());
+    }
+    open_and_visit(image, FsType::Apfs);
+}
+
+/// exFAT boot region checksum: every byte but VolumeFlags and PercentInUse.
+fn exfat_boot_checksum(region: &[u8]) -> u32 {
+    region
+        .iter()
+        .enumerate()
+        .filter(|(i, _)| !matches!(i, 106 | 107 | 112))
+        .fold(0u32, |sum, (_, &b)| {
+            sum.rotate_right(1).wrapping_add(b as u32)
+        })
+}
+
+/// The entry sets of an exFAT root directory, on a volume of 512-byte sectors and 4 KiB
+/// clusters whose root spans as many chained clusters as needed (up to 64), followed by
+/// free clusters the entries may point to.
+pub fn exfat_root_directory(directory: &[u8]) {
+    const SECTOR: usize = 512;
+    const CLUSTER: usize = 4096;
+    const FAT_OFFSET: usize = 24;
+    const HEAP_OFFSET: usize = 32;
+    const MAX_ROOT_CLUSTERS: usize = 64;
+
+    let directory = &directory[..directory.len().min(MAX_ROOT_CLUSTERS * CLUSTER)];
+    let root_clusters = directory.len().div_ceil(CLUSTER).max(1);
+    let cluster_count = root_clusters + 16;
+    let mut image = vec![0u8; HEAP_OFFSET * SECTOR + cluster_count * CLUSTER];
+
+    let boot = &mut image[..SECTOR];
+    boot[0..3].copy_from_slice(&[0xEB, 0x76, 0x90]);
+    boot[3..11].copy_from_slice(b"EXFAT   ");
+    let sectors = (HEAP_OFFSET + cluster_count * CLUSTER / SECTOR) as u64;
+    boot[72..80].copy_from_slice(&sectors.to_le_bytes());
+    boot[80..84].copy_from_slice(&(FAT_OFFSET as u32).to_le_bytes());
+    boot[84..88].copy_from_slice(&((HEAP_OFFSET - FAT_OFFSET) as u32).to_le_bytes());
+    boot[88..92].copy_from_slice(&(HEAP_OFFSET as u32).to_le_bytes());
+    boot[92..96].copy_from_slice(&(cluster_count as u32).to_le_bytes());
+    boot[96..100].copy_from_slice(&2u32.to_le_bytes());
+    boot[104..106].copy_from_slice(&0x0100u16.to_le_bytes());
+    boot[108] = SECTOR.trailing_zeros() as u8;
+    boot[109] = (CLUSTER / SECTOR).trailing_zeros() as u8;
+    boot[110] = 1;
+    boot[510..512].copy_from_slice(&[0x55, 0xAA]);
+    for sector in 1..9 {
+        image[sector * SECTOR + 510..(sector + 1) * SECTOR].copy_from_slice(&[0x55, 0xAA]);
+    }
+    let checksum = exfat_boot_checksum(&image[..11 * SECTOR]);
+    for word in image[11 * SECTOR..12 * SECTOR].chunks_exact_mut(4) {
+        word.copy_from_slice(&checksum.to_le_bytes());
+    }
+
+    let fat = FAT_OFFSET * SECTOR;
+    image[fat..fat + 8].copy_from_slice(&[0xF8, 0xFF, 0xFF, 0xFF, 0xFF, 0xFF, 0xFF, 0xFF]);
+    for cluster in 2..2 + root_clusters {
+        let next = if cluster + 1 == 2 + root_clusters {
+            u32::MAX
+        } else {
+            cluster as u32 + 1
+        };
+        image[fat + cluster * 4..fat + cluster * 4 + 4].copy_from_slice(&next.to_le_bytes());
+    }
+    let heap = HEAP_OFFSET * SECTOR;
+    image[heap..heap + directory.len()].copy_from_slice(directory);
+
+    // Increments and offsets of the file entries, applied apart from the backend to the
+    // packed fields taken as seconds.
+    for entry in directory.chunks_exact(32).filter(|e| e[0] & 0x7f == 0x05) {
+        for (at, increment, offset) in [(8, Some(20), 22), (12, Some(21), 23), (16, None, 24)] {
+            let packed = u32::from_le_bytes(entry[at..at + 4].try_into().unwrap());
+            let increment = increment.map_or(0, |i| entry[i]);
+            let _ = decode_timestamp(i64::from(packed), increment, entry[offset]);
+        }
+    }
+    open_and_visit(image, FsType::Exfat);
+}
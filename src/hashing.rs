@@ -0,0 +1,376 @@
+use crate::budget;
+use crate::filesystem::{File, ReadSeek};
+use log::warn;
+use md5::Md5;
+use serde::{Deserialize, Serialize};
+use sha1::Sha1;
+use sha2::{Digest, Sha256};
+use std::collections::{BTreeMap, HashMap};
+use std::fmt;
+use std::io::{self, Read, SeekFrom, Write};
+use std::str::FromStr;
+use std::sync::mpsc::{self, Receiver, Sender, SyncSender};
+use std::thread::{self, JoinHandle};
+
+const HASH_CHUNK_SIZE: usize = 1024 * 1024; // 1 MiB per read
+/// Chunks `copy_and_hash` reads ahead of the one being hashed and written.
+const READ_AHEAD_CHUNKS: usize = 4;
+
+/// Digest algorithms that can be computed over file content.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum HashAlgorithm {
+    Md5,
+    Sha1,
+    Sha256,
+}
+
+impl FromStr for HashAlgorithm {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s.trim().to_ascii_lowercase().as_str() {
+            "md5" => Ok(HashAlgorithm::Md5),
+            "sha1" | "sha-1" => Ok(HashAlgorithm::Sha1),
+            "sha256" | "sha-256" => Ok(HashAlgorithm::Sha256),
+            other => Err(format!("unsupported hash algorithm: {}", other)),
+        }
+    }
+}
+
+impl fmt::Display for HashAlgorithm {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let s = match self {
+            HashAlgorithm::Md5 => "md5",
+            HashAlgorithm::Sha1 => "sha1",
+            HashAlgorithm::Sha256 => "sha256",
+        };
+        write!(f, "{}", s)
+    }
+}
+
+/// Parse a comma separated list of algorithms, e.g. `md5,sha256`.
+pub fn parse_hash_list(list: &str) -> Result<Vec<HashAlgorithm>, String> {
+    let mut algorithms = Vec::new();
+    for item in list.split(',').filter(|s| !s.trim().is_empty()) {
+        let alg: HashAlgorithm = item.parse()?;
+        if !algorithms.contains(&alg) {
+            algorithms.push(alg);
+        }
+    }
+    if algorithms.is_empty() {
+        return Err("no hash algorithm specified".to_string());
+    }
+    Ok(algorithms)
+}
+
+/// Hex encoded digests of a content stream.
+#[derive(Serialize, Deserialize, Debug, Clone, Default, PartialEq, Eq, Hash)]
+pub struct FileHashes {
+    pub md5: Option<String>,
+    pub sha1: Option<String>,
+    pub sha256: Option<String>,
+}
+
+impl FileHashes {
+    /// Copy the digests onto a `File` record.
+    pub fn apply_to(&self, file: &mut File) {
+        file.md5 = self.md5.clone();
+        file.sha1 = self.sha1.clone();
+        file.sha256 = self.sha256.clone();
+    }
+}
+
+/// Computes several digests in a single pass over the data.
+#[derive(Default)]
+pub struct MultiHasher {
+    md5: Option<Md5>,
+    sha1: Option<Sha1>,
+    sha256: Option<Sha256>,
+}
+
+impl MultiHasher {
+    pub fn new(algorithms: &[HashAlgorithm]) -> Self {
+        let mut hasher = Self::default();
+        for alg in algorithms {
+            match alg {
+                HashAlgorithm::Md5 => hasher.md5 = Some(Md5::new()),
+                HashAlgorithm::Sha1 => hasher.sha1 = Some(Sha1::new()),
+                HashAlgorithm::Sha256 => hasher.sha256 = Some(Sha256::new()),
+            }
+        }
+        hasher
+    }
+
+    pub fn update(&mut self, data: &[u8]) {
+        if let Some(h) = self.md5.as_mut() {
+            h.update(data);
+        }
+        if let Some(h) = self.sha1.as_mut() {
+            h.update(data);
+        }
+        if let Some(h) = self.sha256.as_mut() {
+            h.update(data);
+        }
+    }
+
+    pub fn finalize(self) -> FileHashes {
+        FileHashes {
+            md5: self.md5.map(|h| hex::encode(h.finalize())),
+            sha1: self.sha1.map(|h| hex::encode(h.finalize())),
+            sha256: self.sha256.map(|h| hex::encode(h.finalize())),
+        }
+    }
+}
+
+/// Hash a content stream until EOF. Returns the digests and the number of bytes read.
+pub fn hash_reader<R: Read + ?Sized>(
+    reader: &mut R,
+    algorithms: &[HashAlgorithm],
+) -> io::Result<(FileHashes, u64)> {
+    copy_and_hash(reader, &mut io::sink(), algorithms)
+}
+
+/// Copy a content stream into `writer` while hashing it (single pass, bounded memory).
+/// Returns the digests and the number of bytes copied.
+///
+/// Content larger than a chunk is read ahead: the next chunks are read from the evidence
+/// while the previous one is hashed and written on another thread, hiding the seek latency
+/// of spinning disks and network shares.
+pub fn copy_and_hash<R: Read + ?Sized, W: Write + Send + ?Sized>(
+    reader: &mut R,
+    writer: &mut W,
+    algorithms: &[HashAlgorithm],
+) -> io::Result<(FileHashes, u64)> {
+    let mut hasher = MultiHasher::new(algorithms);
+    let mut buf = vec![0u8; HASH_CHUNK_SIZE];
+    let first = read_chunk(reader, &mut buf)?;
+    hasher.update(&buf[..first]);
+    writer.write_all(&buf[..first])?;
+    if first < HASH_CHUNK_SIZE {
+        return Ok((hasher.finalize(), first as u64));
+    }
+
+    let depth = budget::items(
+        READ_AHEAD_CHUNKS,
+        budget::BUFFER_SHARE,
+        HASH_CHUNK_SIZE as u64,
+    );
+    let (sender, receiver) = mpsc::sync_channel::<Vec<u8>>(depth);
+    thread::scope(|scope| {
+        let consumer = scope.spawn(move || {
+            let mut total = first as u64;
+            for chunk in receiver {
+                hasher.update(&chunk);
+                writer.write_all(&chunk)?;
+                total += chunk.len() as u64;
+            }
+            Ok::<_, io::Error>((hasher.finalize(), total))
+        });
+        let mut read = Ok(());
+        loop {
+            let mut chunk = vec![0u8; HASH_CHUNK_SIZE];
+            match read_chunk(reader, &mut chunk) {
+                Ok(0) => break,
+                Ok(n) => {
+                    chunk.truncate(n);
+                    // The consumer only goes away on a write error, reported below.
+                    if sender.send(chunk).is_err() {
+                        break;
+                    }
+                }
+                Err(e) => {
+                    read = Err(e);
+                    break;
+                }
+            }
+        }
+        drop(sender);
+        let copied = consumer
+            .join()
+            .map_err(|_| io::Error::other("the hashing thread panicked"))??;
+        read.map(|_| copied)
+    })
+}
+
+/// Fill `buf` from `reader`, short only at the end of the stream.
+fn read_chunk<R: Read + ?Sized>(reader: &mut R, buf: &mut [u8]) -> io::Result<usize> {
+    let mut filled = 0;
+    while filled < buf.len() {
+        match reader.read(&mut buf[filled..]) {
+            Ok(0) => break,
+            Ok(n) => filled += n,
+            Err(e) if e.kind() == io::ErrorKind::Interrupted => continue,
+            Err(e) => return Err(e),
+        }
+    }
+    Ok(filled)
+}
+
+/// Chunks queued per hashing worker; the walk waits when a worker falls this far behind.
+const PIPELINE_DEPTH: usize = 8;
+
+enum HashJob {
+    Chunk(Vec<u8>),
+    Done(u64),
+    Failed(u64, String),
+}
+
+type HashResult = (u64, Result<FileHashes, String>);
+
+fn hash_worker(
+    algorithms: Vec<HashAlgorithm>,
+    jobs: Receiver<HashJob>,
+    results: Sender<HashResult>,
+) {
+    let mut current: Option<MultiHasher> = None;
+    for job in jobs {
+        let result = match job {
+            HashJob::Chunk(data) => {
+                current
+                    .get_or_insert_with(|| MultiHasher::new(&algorithms))
+                    .update(&data);
+                continue;
+            }
+            HashJob::Done(seq) => {
+                let hasher = current
+                    .take()
+                    .unwrap_or_else(|| MultiHasher::new(&algorithms));
+                (seq, Ok(hasher.finalize()))
+            }
+            HashJob::Failed(seq, e) => {
+                current = None;
+                (seq, Err(e))
+            }
+        };
+        if results.send(result).is_err() {
+            return;
+        }
+    }
+}
+
+/// Digests record content on worker threads while the walk goes on: the walk thread only
+/// reads the content (the filesystem handle cannot be shared) and hands it over chunk by
+/// chunk. Records are handed back in submission order once their digests are known.
+pub struct HashPipeline {
+    workers: Vec<SyncSender<HashJob>>,
+    handles: Vec<JoinHandle<()>>,
+    results: Receiver<HashResult>,
+    /// Sequence number of the next submitted record.
+    next: u64,
+    /// Record whose content was fed last and is about to be submitted.
+    fed: Option<u64>,
+    /// Submitted records not handed back yet, and whether they wait for digests.
+    waiting: BTreeMap<u64, (File, bool)>,
+    done: HashMap<u64, Result<FileHashes, String>>,
+}
+
+impl HashPipeline {
+    pub fn new(algorithms: &[HashAlgorithm], threads: usize) -> Self {
+        let (result_tx, results) = mpsc::channel();
+        let mut workers = Vec::new();
+        let mut handles = Vec::new();
+        let threads = threads.max(1);
+        let depth = budget::items(
+            PIPELINE_DEPTH,
+            budget::BUFFER_SHARE,
+            (HASH_CHUNK_SIZE * threads) as u64,
+        );
+        for _ in 0..threads {
+            let (tx, rx) = mpsc::sync_channel(depth);
+            let algorithms = algorithms.to_vec();
+            let result_tx = result_tx.clone();
+            handles.push(thread::spawn(move || {
+                hash_worker(algorithms, rx, result_tx)
+            }));
+            workers.push(tx);
+        }
+        Self {
+            workers,
+            handles,
+            results,
+            next: 0,
+            fed: None,
+            waiting: BTreeMap::new(),
+            done: HashMap::new(),
+        }
+    }
+
+    /// Send the content of the record about to be submitted to a worker.
+    pub fn feed(&mut self, reader: &mut dyn ReadSeek) {
+        let seq = self.next;
+        let worker = &self.workers[(seq % self.workers.len() as u64) as usize];
+        let read = reader.seek(SeekFrom::Start(0)).and_then(|_| {
+            loop {
+                let mut buf = vec![0u8; HASH_CHUNK_SIZE];
+                let filled = read_chunk(reader, &mut buf)?;
+                if filled == 0 {
+                    return Ok(());
+                }
+                buf.truncate(filled);
+                // A worker only goes away when the pipeline is dropped.
+                let _ = worker.send(HashJob::Chunk(buf));
+            }
+        });
+        let _ = worker.send(match read {
+            Ok(()) => HashJob::Done(seq),
+            Err(e) => HashJob::Failed(seq, e.to_string()),
+        });
+        self.fed = Some(seq);
+    }
+
+    /// Queue a record. A record whose content was just fed waits for its digests; the
+    /// records ready in submission order are passed to `emit`.
+    pub fn submit(&mut self, file: File, emit: &mut dyn FnMut(File)) {
+        let seq = self.next;
+        self.next += 1;
+        let hashed = self.fed.take() == Some(seq);
+        self.waiting.insert(seq, (file, hashed));
+        self.release(false, emit);
+    }
+
+    /// Wait for every submitted record and pass them to `emit`.
+    pub fn flush(&mut self, emit: &mut dyn FnMut(File)) {
+        self.release(true, emit);
+    }
+
+    /// Flush and stop the workers.
+    pub fn finish(mut self, emit: &mut dyn FnMut(File)) {
+        self.flush(emit);
+        self.workers.clear();
+        for handle in self.handles.drain(..) {
+            let _ = handle.join();
+        }
+    }
+
+    fn release(&mut self, wait: bool, emit: &mut dyn FnMut(File)) {
+        self.done.extend(self.results.try_iter());
+        while let Some(entry) = self.waiting.first_entry() {
+            let seq = *entry.key();
+            if entry.get().1 && !self.done.contains_key(&seq) {
+                if !wait {
+                    return;
+                }
+                match self.results.recv() {
+                    Ok((seq, result)) => {
+                        self.done.insert(seq, result);
+                    }
+                    // Every worker is gone: the record keeps no digests.
+                    Err(_) => {
+                        self.done.insert(seq, Err("hashing worker stopped".into()));
+                    }
+                }
+                continue;
+            }
+            let (mut file, hashed) = entry.remove();
+            if hashed {
+                match self.done.remove(&seq) {
+                    Some(Ok(hashes)) => hashes.apply_to(&mut file),
+                    Some(Err(e)) => warn!("Could not hash {}: {}", file.absolute_path, e),
+                    None => {}
+                }
+            }
+            emit(file);
+        }
+    }
+}
@@ -0,0 +1,72 @@
+use std::io::{self, Read, Write};
+
+const BYTES_PER_LINE: usize = 16;
+const READ_CHUNK: usize = 64 * 1024;
+
+/// Format one xxd-style line: offset, grouped hex bytes and an ASCII gutter.
+pub fn hexdump_line(offset: u64, data: &[u8]) -> String {
+    let mut line = format!("{:08x}: ", offset);
+    for i in 0..BYTES_PER_LINE {
+        match data.get(i) {
+            Some(b) => line.push_str(&format!("{:02x}", b)),
+            None => line.push_str("  "),
+        }
+        if i % 2 == 1 {
+            line.push(' ');
+        }
+    }
+    line.push(' ');
+    for b in data {
+        line.push(if b.is_ascii_graphic() || *b == b' ' {
+            *b as char
+        } else {
+            '.'
+        });
+    }
+    line
+}
+
+/// Stream `reader` as an xxd-style hexdump into `writer`, with offsets starting at
+/// `base_offset`, stopping after `length` bytes (or at EOF). Returns the byte count dumped.
+pub fn hexdump<R: Read + ?Sized, W: Write + ?Sized>(
+    reader: &mut R,
+    writer: &mut W,
+    base_offset: u64,
+    length: Option<u64>,
+) -> io::Result<u64> {
+    let mut buf = vec![0u8; READ_CHUNK];
+    let mut pending: Vec<u8> = Vec::with_capacity(BYTES_PER_LINE);
+    let mut offset = base_offset;
+    let mut total = 0u64;
+
+    loop {
+        let want = match length {
+            Some(len) => (len - total).min(READ_CHUNK as u64) as usize,
+            None => READ_CHUNK,
+        };
+        if want == 0 {
+            break;
+        }
+        let n = match reader.read(&mut buf[..want]) {
+            Ok(0) => break,
+            Ok(n) => n,
+            Err(e) if e.kind() == io::ErrorKind::Interrupted => continue,
+            Err(e) => return Err(e),
+        };
+        total += n as u64;
+
+        for &b in &buf[..n] {
+            pending.push(b);
+            if pending.len() == BYTES_PER_LINE {
+                writeln!(writer, "{}", hexdump_line(offset, &pending))?;
+                offset += BYTES_PER_LINE as u64;
+                pending.clear();
+            }
+        }
+    }
+
+    if !pending.is_empty() {
+        writeln!(writer, "{}", hexdump_line(offset, &pending))?;
+    }
+    Ok(total)
+}
@@ -0,0 +1,843 @@
+//! Classic HFS volumes (Mac OS before 8.1, floppies, CD-ROMs and removable media of the
+//! time). The master directory block, 1 KiB into the volume, locates the volume bitmap,
+//! the allocation blocks and the two B-trees: the catalog, whose leaf records are the
+//! files and directories keyed by parent id and name, and the extents overflow file,
+//! holding the extents of forks past their first three.
+//!
+//! Records are identified by catalog node id (the root directory is 2) and found through
+//! their thread record when they have one. Names are MacRoman, with `/` shown as `:` as
+//! macOS does. Resource forks are listed under `STREAMS_KEY` as `rsrc` and read, like the
+//! Finder information, as the `com.apple.ResourceFork` and `com.apple.FinderInfo`
+//! extended attributes. Times are local and converted with the process local time policy.
+//! Blocks are 512-byte sectors: allocation blocks start at a sector that need not be a
+//! multiple of their size. HFS wrappers of HFS+ volumes are not supported.
+use crate::filesystem::{
+    BlockRun, ByteRange, DirectoryCommon, ExtendedAttribute, FLAGS_KEY, File, FileCommon,
+    Filesystem, STREAMS_KEY, namespaced_metadata,
+};
+use crate::timefmt::{format_timestamp, local_time_policy};
+use jiff::Timestamp;
+use jiff::tz::TimeZone;
+use serde::Serialize;
+use serde_json::{Value, json};
+use std::collections::HashMap;
+use std::error::Error;
+use std::io::{Read, Seek, SeekFrom};
+
+/// Namespace of the catalog record fields in `File.metadata`.
+pub const METADATA_NAMESPACE: &str = "hfs";
+
+const SECTOR: u64 = 512;
+const MDB_OFFSET: u64 = 1024;
+const HFS_SIGNATURE: u16 = 0x4244;
+const HFS_PLUS_SIGNATURE: u16 = 0x482b;
+const ROOT_DIRECTORY: u32 = 2;
+const EXTENTS_FILE: u32 = 3;
+const CATALOG_FILE: u32 = 4;
+const NODE_SIZE: usize = 512;
+const NODE_INDEX: u8 = 0x00;
+const NODE_HEADER: u8 = 0x01;
+const NODE_LEAF: u8 = 0xff;
+const RECORD_DIRECTORY: u8 = 1;
+const RECORD_FILE: u8 = 2;
+const RECORD_DIRECTORY_THREAD: u8 = 3;
+const RECORD_FILE_THREAD: u8 = 4;
+const FORK_DATA: u8 = 0x00;
+const FORK_RESOURCE: u8 = 0xff;
+/// Largest B-tree file read into memory.
+const MAX_TREE_SIZE: u64 = 64 << 20;
+/// Seconds from 1904-01-01 to 1970-01-01.
+const MAC_EPOCH_OFFSET: i64 = 2_082_844_800;
+const FINDER_INFO_XATTR: &str = "com.apple.FinderInfo";
+const RESOURCE_FORK_XATTR: &str = "com.apple.ResourceFork";
+
+/// Characters of the MacRoman bytes 0x80 to 0xff.
+const MAC_ROMAN: &str = "ÄÅÇÉÑÖÜáàâäãåçéèêëíìîïñóòôöõúùûü†°¢£§•¶ß®©™´¨≠ÆØ∞±≤≥¥µ∂∑∏π∫ªºΩæø\
+                         ¿¡¬√ƒ≈∆«»…\u{a0}ÀÃÕŒœ–—“”‘’÷◊ÿŸ⁄€‹›ﬁﬂ‡·‚„‰ÂÊÁËÈÍÎÏÌÓÔ\u{f8ff}ÒÚÛÙıˆ˜¯˘˙˚¸˝˛ˇ";
+
+const FINDER_FLAGS: [(u16, &str); 10] = [
+    (0x0001, "on_desk"),
+    (0x0040, "shared"),
+    (0x0080, "no_inits"),
+    (0x0100, "inited"),
+    (0x0400, "custom_icon"),
+    (0x0800, "stationery"),
+    (0x1000, "name_locked"),
+    (0x2000, "bundle"),
+    (0x4000, "invisible"),
+    (0x8000, "alias"),
+];
+
+/// First allocation block and count.
+pub type Extent = (u16, u16);
+
+/// Key (or name) and data of a B-tree record.
+type Record<'a> = (&'a [u8], &'a [u8]);
+
+fn be_u16(bytes: &[u8], at: usize) -> u16 {
+    u16::from_be_bytes(bytes[at..at + 2].try_into().unwrap())
+}
+
+fn be_u32(bytes: &[u8], at: usize) -> u32 {
+    u32::from_be_bytes(bytes[at..at + 4].try_into().unwrap())
+}
+
+fn extent_record(bytes: &[u8], at: usize) -> [Extent; 3] {
+    [0, 1, 2].map(|n| (be_u16(bytes, at + 4 * n), be_u16(bytes, at + 4 * n + 2)))
+}
+
+/// MacRoman text, `/` shown as `:`.
+fn mac_roman(bytes: &[u8]) -> String {
+    bytes
+        .iter()
+        .map(|b| match b {
+            b'/' => ':',
+            0..0x80 => *b as char,
+            _ => MAC_ROMAN.chars().nth(*b as usize - 0x80).unwrap_or('?'),
+        })
+        .collect()
+}
+
+/// Pascal string of at most `max` bytes at `at`, empty when out of `bytes`.
+fn pascal(bytes: &[u8], at: usize, max: usize) -> &[u8] {
+    let length = bytes.get(at).map_or(0, |l| (*l as usize).min(max));
+    bytes.get(at + 1..at + 1 + length).unwrap_or_default()
+}
+
+/// UNIX seconds of a local time in seconds since 1904, following the process local time
+/// policy.
+fn mac_time(seconds: u32) -> Option<u64> {
+    if seconds == 0 {
+        return None;
+    }
+    let local = Timestamp::from_second(seconds as i64 - MAC_EPOCH_OFFSET)
+        .ok()?
+        .to_zoned(TimeZone::UTC)
+        .datetime();
+    u64::try_from(local_time_policy().to_utc(local, None)?.0).ok()
+}
+
+/// Names of the Finder flags set in `flags`.
+pub fn finder_flag_names(flags: u16) -> Vec<&'static str> {
+    FINDER_FLAGS
+        .iter()
+        .filter(|(bit, _)| flags & bit != 0)
+        .map(|(_, name)| *name)
+        .collect()
+}
+
+/// Key and data of a B-tree record: the key length byte does not count itself and the
+/// data starts on an even offset.
+fn split_record(record: &[u8]) -> Option<Record<'_>> {
+    let key_length = *record.first()? as usize;
+    let data = (key_length + 2) & !1;
+    Some((record.get(1..1 + key_length)?, record.get(data..)?))
+}
+
+/// Records of a B-tree node, from the offsets stacked at its end.
+fn node_records(node: &[u8]) -> Vec<&[u8]> {
+    let count = be_u16(node, 10) as usize;
+    let offset = |n: usize| {
+        (2 * (n + 1) <= node.len()).then(|| be_u16(node, node.len() - 2 * (n + 1)) as usize)
+    };
+    (0..count)
+        .filter_map(|n| {
+            let (start, end) = (offset(n)?, offset(n + 1)?);
+            node.get(start..end.max(start))
+        })
+        .collect()
+}
+
+#[derive(Debug, Clone, Default, Serialize)]
+pub struct BTreeHeader {
+    pub depth: u16,
+    pub root: u32,
+    pub leaf_records: u32,
+    pub first_leaf: u32,
+    pub last_leaf: u32,
+    pub node_size: u16,
+    pub max_key_length: u16,
+    pub nodes: u32,
+    pub free_nodes: u32,
+}
+
+/// A B-tree file read into memory.
+#[derive(Default)]
+struct BTree {
+    data: Vec<u8>,
+    header: BTreeHeader,
+}
+
+impl BTree {
+    fn new(data: Vec<u8>, name: &str) -> Result<Self, Box<dyn Error>> {
+        let node = data
+            .get(..NODE_SIZE)
+            .ok_or_else(|| format!("empty HFS {} file", name))?;
+        if node[8] != NODE_HEADER {
+            return Err(format!("no HFS {} header node", name).into());
+        }
+        let header = BTreeHeader {
+            depth: be_u16(node, 14),
+            root: be_u32(node, 16),
+            leaf_records: be_u32(node, 20),
+            first_leaf: be_u32(node, 24),
+            last_leaf: be_u32(node, 28),
+            node_size: be_u16(node, 32),
+            max_key_length: be_u16(node, 34),
+            nodes: be_u32(node, 36),
+            free_nodes: be_u32(node, 40),
+        };
+        if header.node_size as usize != NODE_SIZE {
+            return Err(format!("bad HFS {} node size {}", name, header.node_size).into());
+        }
+        Ok(Self { data, header })
+    }
+
+    fn node(&self, n: u32) -> Result<&[u8], Box<dyn Error>> {
+        let start = n as usize * NODE_SIZE;
+        self.data
+            .get(start..start + NODE_SIZE)
+            .ok_or_else(|| format!("HFS B-tree node {} past the end of its file", n).into())
+    }
+
+    /// Leaf records from node `first` on, as long as `more` holds for their key.
+    fn leaf_records(
+        &self,
+        first: u32,
+        mut more: impl FnMut(&[u8]) -> bool,
+    ) -> Result<Vec<Record<'_>>, Box<dyn Error>> {
+        let mut out = Vec::new();
+        let mut n = first;
+        let mut visited = 0;
+        while n != 0 && visited <= self.header.nodes {
+            let node = self.node(n)?;
+            if node[8] != NODE_LEAF {
+                return Err(format!("HFS B-tree node {} is not a leaf", n).into());
+            }
+            for record in node_records(node) {
+                let Some((key, data)) = split_record(record) else {
+                    continue;
+                };
+                if !more(key) {
+                    return Ok(out);
+                }
+                out.push((key, data));
+            }
+            n = be_u32(node, 0);
+            visited += 1;
+        }
+        Ok(out)
+    }
+}
+
+#[derive(Debug, Clone, Serialize)]
+pub struct MasterDirectoryBlock {
+    pub volume_name: String,
+    pub created: u32,
+    pub modified: u32,
+    pub backed_up: u32,
+    pub attributes: u16,
+    pub bitmap_start: u16,
+    pub allocation_blocks: u16,
+    pub allocation_block_size: u32,
+    pub first_allocation_block: u16,
+    pub next_catalog_id: u32,
+    pub free_blocks: u16,
+    pub write_count: u32,
+    pub files: u32,
+    pub directories: u32,
+    /// Directory id of the blessed System Folder, 0 when none.
+    pub blessed_folder: u32,
+    pub extents_size: u32,
+    pub extents_extents: [Extent; 3],
+    pub catalog_size: u32,
+    pub catalog_extents: [Extent; 3],
+}
+
+/// A file or directory record of the catalog.
+#[derive(Debug, Clone, Serialize)]
+pub struct HfsFile {
+    pub id: u32,
+    pub parent: u32,
+    pub name: String,
+    pub is_dir: bool,
+    /// Record flags: locked and thread record bits of files, directory flags.
+    pub flags: u16,
+    pub finder_flags: u16,
+    /// Entries of a directory.
+    pub valence: Option<u16>,
+    pub file_type: Option<String>,
+    pub creator: Option<String>,
+    pub created: Option<u64>,
+    pub modified: Option<u64>,
+    pub backed_up: Option<u64>,
+    pub data_size: u32,
+    pub data_physical_size: u32,
+    pub resource_size: u32,
+    pub resource_physical_size: u32,
+    #[serde(skip)]
+    pub data_extents: [Extent; 3],
+    #[serde(skip)]
+    pub resource_extents: [Extent; 3],
+    /// Finder information: user then extended, 32 bytes.
+    #[serde(skip)]
+    pub finder_info: Vec<u8>,
+}
+
+impl HfsFile {
+    /// The record of `name` in directory `parent` from its catalog `data`, `None` for
+    /// thread records.
+    fn parse(parent: u32, name: &[u8], data: &[u8]) -> Option<Self> {
+        let mut record = Self {
+            id: 0,
+            parent,
+            name: mac_roman(name),
+            is_dir: false,
+            flags: 0,
+            finder_flags: 0,
+            valence: None,
+            file_type: None,
+            creator: None,
+            created: None,
+            modified: None,
+            backed_up: None,
+            data_size: 0,
+            data_physical_size: 0,
+            resource_size: 0,
+            resource_physical_size: 0,
+            data_extents: [(0, 0); 3],
+            resource_extents: [(0, 0); 3],
+            finder_info: Vec::new(),
+        };
+        match *data.first()? {
+            RECORD_DIRECTORY if data.len() >= 70 => {
+                record.is_dir = true;
+                record.flags = be_u16(data, 2);
+                record.valence = Some(be_u16(data, 4));
+                record.id = be_u32(data, 6);
+                record.created = mac_time(be_u32(data, 10));
+                record.modified = mac_time(be_u32(data, 14));
+                record.backed_up = mac_time(be_u32(data, 18));
+                record.finder_flags = be_u16(data, 30);
+                record.finder_info = data[22..54].to_vec();
+            }
+            RECORD_FILE if data.len() >= 102 => {
+                record.flags = data[2] as u16;
+                record.file_type = Some(mac_roman(&data[4..8]));
+                record.creator = Some(mac_roman(&data[8..12]));
+                record.finder_flags = be_u16(data, 12);
+                record.id = be_u32(data, 20);
+                record.data_size = be_u32(data, 26);
+                record.data_physical_size = be_u32(data, 30);
+                record.resource_size = be_u32(data, 36);
+                record.resource_physical_size = be_u32(data, 40);
+                record.created = mac_time(be_u32(data, 44));
+                record.modified = mac_time(be_u32(data, 48));
+                record.backed_up = mac_time(be_u32(data, 52));
+                record.finder_info = [&data[4..20], &data[56..72]].concat();
+                record.data_extents = extent_record(data, 74);
+                record.resource_extents = extent_record(data, 86);
+            }
+            _ => return None,
+        }
+        Some(record)
+    }
+}
+
+impl FileCommon for HfsFile {
+    fn id(&self) -> u64 {
+        self.id as u64
+    }
+    fn size(&self) -> u64 {
+        self.data_size as u64
+    }
+    fn is_dir(&self) -> bool {
+        self.is_dir
+    }
+    fn to_string(&self) -> String {
+        format!(
+            "HfsFile {{ id: {}, name: {}, size: {} }}",
+            self.id, self.name, self.data_size
+        )
+    }
+    fn to_json(&self) -> Value {
+        serde_json::to_value(self).unwrap_or(Value::Null)
+    }
+}
+
+#[derive(Debug, Clone)]
+pub struct HfsDirEntry {
+    pub id: u32,
+    pub name: String,
+    pub is_dir: bool,
+}
+
+impl DirectoryCommon for HfsDirEntry {
+    fn file_id(&self) -> u64 {
+        self.id as u64
+    }
+    fn name(&self) -> &str {
+        &self.name
+    }
+    fn to_string(&self) -> String {
+        format!("HfsDirEntry {{ id: {}, name: {} }}", self.id, self.name)
+    }
+    fn to_json(&self) -> Value {
+        json!({ "id": self.id, "name": self.name, "is_dir": self.is_dir })
+    }
+}
+
+pub struct HfsFS<T: Read + Seek> {
+    body: T,
+    mdb: MasterDirectoryBlock,
+    extents: BTree,
+    catalog: BTree,
+    /// Records seen while listing directories, by id.
+    records: HashMap<u32, HfsFile>,
+}
+
+impl<T: Read + Seek> HfsFS<T> {
+    pub fn new(mut body: T) -> Result<Self, Box<dyn Error>> {
+        let mut block = [0u8; 162];
+        body.seek(SeekFrom::Start(MDB_OFFSET))?;
+        body.read_exact(&mut block)?;
+        if be_u16(&block, 0) != HFS_SIGNATURE {
+            return Err("not an HFS volume".into());
+        }
+        if be_u16(&block, 124) == HFS_PLUS_SIGNATURE {
+            return Err("HFS wrappers of HFS+ volumes are not supported".into());
+        }
+        let mdb = MasterDirectoryBlock {
+            volume_name: mac_roman(pascal(&block, 36, 27)),
+            created: be_u32(&block, 2),
+            modified: be_u32(&block, 6),
+            backed_up: be_u32(&block, 64),
+            attributes: be_u16(&block, 10),
+            bitmap_start: be_u16(&block, 14),
+            allocation_blocks: be_u16(&block, 18),
+            allocation_block_size: be_u32(&block, 20),
+            first_allocation_block: be_u16(&block, 28),
+            next_catalog_id: be_u32(&block, 30),
+            free_blocks: be_u16(&block, 34),
+            write_count: be_u32(&block, 70),
+            files: be_u32(&block, 84),
+            directories: be_u32(&block, 88),
+            blessed_folder: be_u32(&block, 92),
+            extents_size: be_u32(&block, 130),
+            extents_extents: extent_record(&block, 134),
+            catalog_size: be_u32(&block, 146),
+            catalog_extents: extent_record(&block, 150),
+        };
+        let block_size = mdb.allocation_block_size;
+        if block_size == 0 || !(block_size as u64).is_multiple_of(SECTOR) {
+            return Err(format!("bad HFS allocation block size {}", block_size).into());
+        }
+        if mdb.extents_size as u64 > MAX_TREE_SIZE || mdb.catalog_size as u64 > MAX_TREE_SIZE {
+            return Err("HFS B-tree files too large".into());
+        }
+
+        let mut fs = Self {
+            body,
+            extents: BTree::default(),
+            catalog: BTree::default(),
+            mdb,
+            records: HashMap::new(),
+        };
+        // The extents file never overflows; the catalog may, into it.
+        let (size, first) = (fs.mdb.extents_size, fs.mdb.extents_extents);
+        let data = fs.read_fork(&first, size as u64, 0, size as usize)?;
+        fs.extents = BTree::new(data, "extents overflow")?;
+        let (size, first) = (fs.mdb.catalog_size, fs.mdb.catalog_extents);
+        let extents = fs.fork_extents(CATALOG_FILE, FORK_DATA, &first)?;
+        let data = fs.read_fork(&extents, size as u64, 0, size as usize)?;
+        fs.catalog = BTree::new(data, "catalog")?;
+        Ok(fs)
+    }
+
+    pub fn master_directory_block(&self) -> &MasterDirectoryBlock {
+        &self.mdb
+    }
+
+    fn sectors_per_block(&self) -> u64 {
+        self.mdb.allocation_block_size as u64 / SECTOR
+    }
+
+    /// Extents of a fork: its first three, then those of the extents overflow file for
+    /// `id`, in fork order.
+    fn fork_extents(
+        &self,
+        id: u32,
+        fork: u8,
+        first: &[Extent; 3],
+    ) -> Result<Vec<Extent>, Box<dyn Error>> {
+        let mut out: Vec<Extent> = first.iter().copied().filter(|e| e.1 > 0).collect();
+        if out.len() < 3 || id == EXTENTS_FILE || self.extents.header.first_leaf == 0 {
+            return Ok(out);
+        }
+        let mut overflow: Vec<(u16, [Extent; 3])> = self
+            .extents
+            .leaf_records(self.extents.header.first_leaf, |_| true)?
+            .into_iter()
+            .filter(|(key, data)| {
+                key.len() >= 7 && data.len() >= 12 && key[0] == fork && be_u32(key, 1) == id
+            })
+            .map(|(key, data)| (be_u16(key, 5), extent_record(data, 0)))
+            .collect();
+        overflow.sort_unstable_by_key(|(first_block, _)| *first_block);
+        out.extend(overflow.iter().flat_map(|(_, e)| e).filter(|e| e.1 > 0));
+        Ok(out)
+    }
+
+    /// `length` bytes from `offset` of a fork of `size` bytes in `extents`; what they do
+    /// not cover reads as zeros.
+    fn read_fork(
+        &mut self,
+        extents: &[Extent],
+        size: u64,
+        offset: u64,
+        length: usize,
+    ) -> Result<Vec<u8>, Box<dyn Error>> {
+        if offset >= size {
+            return Ok(Vec::new());
+        }
+        let end = size.min(offset + length as u64);
+        let block_size = self.mdb.allocation_block_size as u64;
+        let first_sector = self.mdb.first_allocation_block as u64;
+        let mut out = vec![0u8; (end - offset) as usize];
+        let mut position = 0u64;
+        for (start, count) in extents {
+            let extent_end = position + *count as u64 * block_size;
+            let (from, to) = (offset.max(position), end.min(extent_end));
+            if from < to {
+                let physical =
+                    first_sector * SECTOR + *start as u64 * block_size + (from - position);
+                self.body.seek(SeekFrom::Start(physical))?;
+                let at = (from - offset) as usize;
+                self.body
+                    .read_exact(&mut out[at..at + (to - from) as usize])?;
+            }
+            position = extent_end;
+            if position >= end {
+                break;
+            }
+        }
+        Ok(out)
+    }
+
+    /// Catalog records of directory `parent`: name and data, thread record first.
+    fn children(&self, parent: u32) -> Result<Vec<Record<'_>>, Box<dyn Error>> {
+        let tree = &self.catalog;
+        let mut n = tree.header.root;
+        if n == 0 {
+            return Ok(Vec::new());
+        }
+        // Down to the leftmost leaf that may hold keys of `parent`.
+        for _ in 0..=tree.header.depth {
+            let node = tree.node(n)?;
+            match node[8] {
+                NODE_LEAF => break,
+                NODE_INDEX => {
+                    let mut child = None;
+                    for record in node_records(node) {
+                        let Some((key, data)) = split_record(record) else {
+                            continue;
+                        };
+                        if key.len() < 5 || data.len() < 4 {
+                            continue;
+                        }
+                        if child.is_some() && be_u32(key, 1) >= parent {
+                            break;
+                        }
+                        child = Some(be_u32(data, 0));
+                    }
+                    n = child.ok_or("empty HFS catalog index node")?;
+                }
+                other => return Err(format!("bad HFS catalog node type {:#x}", other).into()),
+            }
+        }
+        Ok(tree
+            .leaf_records(n, |key| key.len() < 5 || be_u32(key, 1) <= parent)?
+            .into_iter()
+            .filter(|(key, _)| key.len() >= 6 && be_u32(key, 1) == parent)
+            .map(|(key, data)| (pascal(key, 5, key.len() - 6), data))
+            .collect())
+    }
+
+    /// The record `id`, through its thread record or, for files without one, a scan of
+    /// the catalog.
+    fn find_record(&self, id: u32) -> Result<HfsFile, Box<dyn Error>> {
+        let thread = self.children(id)?.into_iter().find_map(|(_, data)| {
+            (matches!(
+                data.first(),
+                Some(&RECORD_DIRECTORY_THREAD | &RECORD_FILE_THREAD)
+            ) && data.len() >= 15)
+                .then(|| (be_u32(data, 10), pascal(data, 14, 31)))
+        });
+        if let Some((parent, name)) = thread {
+            return self
+                .children(parent)?
+                .into_iter()
+                .filter(|(key_name, _)| *key_name == name)
+                .find_map(|(key_name, data)| HfsFile::parse(parent, key_name, data))
+                .ok_or_else(|| format!("HFS thread of {} without its record", id).into());
+        }
+        let catalog = &self.catalog;
+        catalog
+            .leaf_records(catalog.header.first_leaf, |_| true)?
+            .into_iter()
+            .filter(|(key, _)| key.len() >= 6)
+            .filter_map(|(key, data)| {
+                HfsFile::parse(be_u32(key, 1), pascal(key, 5, key.len() - 6), data)
+            })
+            .find(|record| record.id == id)
+            .ok_or_else(|| format!("no HFS catalog record {}", id).into())
+    }
+
+    fn fork(&self, file: &HfsFile, fork: u8) -> Result<(Vec<Extent>, u64), Box<dyn Error>> {
+        Ok(match fork {
+            FORK_RESOURCE => (
+                self.fork_extents(file.id, fork, &file.resource_extents)?,
+                file.resource_size as u64,
+            ),
+            _ => (
+                self.fork_extents(file.id, fork, &file.data_extents)?,
+                file.data_size as u64,
+            ),
+        })
+    }
+}
+
+impl<T: Read + Seek> Filesystem for HfsFS<T> {
+    type FileType = HfsFile;
+    type DirectoryType = HfsDirEntry;
+
+    fn filesystem_type(&self) -> String {
+        "HFS".to_string()
+    }
+
+    fn path_separator(&self) -> String {
+        "/".to_string()
+    }
+
+    fn record_count(&mut self) -> u64 {
+        self.mdb.files as u64 + self.mdb.directories as u64
+    }
+
+    fn block_size(&self) -> u64 {
+        SECTOR
+    }
+
+    fn get_metadata(&self) -> Result<Value, Box<dyn Error>> {
+        let mut metadata = serde_json::to_value(&self.mdb)?;
+        metadata["catalog"] = serde_json::to_value(&self.catalog.header)?;
+        metadata["extents_overflow"] = serde_json::to_value(&self.extents.header)?;
+        metadata["timestamp_policy"] = json!(local_time_policy().name());
+        Ok(metadata)
+    }
+
+    fn get_metadata_pretty(&self) -> Result<String, Box<dyn Error>> {
+        let m = &self.mdb;
+        let time = |t: u32| mac_time(t).map_or("-".to_string(), format_timestamp);
+        Ok(format!(
+            "HFS volume \"{}\"\n\
+             Created: {}, modified: {}, backed up: {}\n\
+             Allocation blocks: {} of {} bytes from sector {}, {} free\n\
+             Files: {}, directories: {}, next catalog id: {}\n\
+             Catalog: {} bytes, depth {}, {} leaf records\n",
+            m.volume_name,
+            time(m.created),
+            time(m.modified),
+            time(m.backed_up),
+            m.allocation_blocks,
+            m.allocation_block_size,
+            m.first_allocation_block,
+            m.free_blocks,
+            m.files,
+            m.directories,
+            m.next_catalog_id,
+            m.catalog_size,
+            self.catalog.header.depth,
+            self.catalog.header.leaf_records
+        ))
+    }
+
+    fn get_file(&mut self, id: u64) -> Result<Self::FileType, Box<dyn Error>> {
+        let id = u32::try_from(id).map_err(|_| format!("bad HFS catalog id {}", id))?;
+        if let Some(record) = self.records.get(&id) {
+            return Ok(record.clone());
+        }
+        self.find_record(id)
+    }
+
+    fn read_file_content(&mut self, file: &Self::FileType) -> Result<Vec<u8>, Box<dyn Error>> {
+        self.read_file_slice(file, 0, file.data_size as usize)
+    }
+
+    fn read_file_prefix(
+        &mut self,
+        file: &Self::FileType,
+        length: usize,
+    ) -> Result<Vec<u8>, Box<dyn Error>> {
+        self.read_file_slice(file, 0, length)
+    }
+
+    fn read_file_slice(
+        &mut self,
+        file: &Self::FileType,
+        offset: u64,
+        length: usize,
+    ) -> Result<Vec<u8>, Box<dyn Error>> {
+        if file.is_dir {
+            return Ok(Vec::new());
+        }
+        let (extents, size) = self.fork(file, FORK_DATA)?;
+        self.read_fork(&extents, size, offset, length)
+    }
+
+    fn list_dir(
+        &mut self,
+        file: &Self::FileType,
+    ) -> Result<Vec<Self::DirectoryType>, Box<dyn Error>> {
+        if !file.is_dir {
+            return Err("not a directory".into());
+        }
+        let records: Vec<HfsFile> = self
+            .children(file.id)?
+            .into_iter()
+            .filter_map(|(name, data)| HfsFile::parse(file.id, name, data))
+            .collect();
+        let listing = records
+            .iter()
+            .map(|r| HfsDirEntry {
+                id: r.id,
+                name: r.name.clone(),
+                is_dir: r.is_dir,
+            })
+            .collect();
+        for record in records {
+            self.records.insert(record.id, record);
+        }
+        Ok(listing)
+    }
+
+    fn record_to_file(&self, file: &Self::FileType, file_id: u64, absolute_path: &str) -> File {
+        let mut flags = finder_flag_names(file.finder_flags);
+        if !file.is_dir && file.flags & 0x01 != 0 {
+            flags.insert(0, "locked");
+        }
+        let mut common = json!({ FLAGS_KEY: flags });
+        if file.resource_size > 0 {
+            common[STREAMS_KEY] = json!([{ "name": "rsrc", "size": file.resource_size }]);
+        }
+        let metadata = namespaced_metadata(METADATA_NAMESPACE, file.to_json(), common);
+        let name = match absolute_path.rsplit_once('/') {
+            Some((_, name)) if !name.is_empty() => name,
+            _ => absolute_path,
+        };
+        let kind = match (&file.file_type, &file.creator) {
+            (Some(file_type), Some(creator)) => format!("{}/{}", file_type, creator),
+            _ => "dir".to_string(),
+        };
+        File {
+            id: None,
+            identifier: file_id,
+            absolute_path: absolute_path.to_string(),
+            name: name.to_string(),
+            raw_name: None,
+            ftype: if file.is_dir { "dir" } else { "file" }.to_string(),
+            size: file.data_size as u64,
+            size_on_disk: Some(file.data_physical_size as u64 + file.resource_physical_size as u64),
+            created: file.created,
+            modified: file.modified,
+            accessed: None,
+            changed: None,
+            permissions: None,
+            owner: None,
+            group: None,
+            display: Some(format!(
+                "[{}] - {} {:>5} {:>5} {} {}",
+                file_id,
+                kind,
+                file.data_size,
+                file.resource_size,
+                format_timestamp(file.modified.unwrap_or(0)),
+                absolute_path
+            )),
+            sig_name: None,
+            sig_mime: None,
+            sig_exts: None,
+            detected_type: None,
+            ext_mismatch: None,
+            md5: None,
+            sha1: None,
+            sha256: None,
+            metadata,
+        }
+    }
+
+    fn get_root_file_id(&self) -> u64 {
+        ROOT_DIRECTORY as u64
+    }
+
+    /// From the volume bitmap; the sectors before the allocation blocks (boot blocks,
+    /// master directory block, bitmap) are in use.
+    fn block_allocation(&mut self, sector: u64) -> Result<Option<bool>, Box<dyn Error>> {
+        let first = self.mdb.first_allocation_block as u64;
+        if sector < first {
+            return Ok(Some(true));
+        }
+        let block = (sector - first) / self.sectors_per_block();
+        if block >= self.mdb.allocation_blocks as u64 {
+            return Ok(None);
+        }
+        let mut byte = [0u8; 1];
+        self.body.seek(SeekFrom::Start(
+            self.mdb.bitmap_start as u64 * SECTOR + block / 8,
+        ))?;
+        self.body.read_exact(&mut byte)?;
+        Ok(Some(byte[0] & (0x80 >> (block % 8)) != 0))
+    }
+
+    fn file_block_runs(
+        &mut self,
+        file: &Self::FileType,
+    ) -> Result<Option<Vec<BlockRun>>, Box<dyn Error>> {
+        let (extents, _) = self.fork(file, FORK_DATA)?;
+        let (first, per_block) = (
+            self.mdb.first_allocation_block as u64,
+            self.sectors_per_block(),
+        );
+        let mut runs: Vec<BlockRun> = Vec::new();
+        for (start, count) in extents {
+            let (sector, sectors) = (first + start as u64 * per_block, count as u64 * per_block);
+            match runs.last_mut() {
+                Some((previous, length)) if *previous + *length == sector => *length += sectors,
+                _ => runs.push((sector, sectors)),
+            }
+        }
+        Ok(Some(runs))
+    }
+
+    fn file_holes(
+        &mut self,
+        _file: &Self::FileType,
+    ) -> Result<Option<Vec<ByteRange>>, Box<dyn Error>> {
+        Ok(Some(Vec::new()))
+    }
+
+    fn extended_attributes(
+        &mut self,
+        file: &Self::FileType,
+    ) -> Result<Vec<ExtendedAttribute>, Box<dyn Error>> {
+        let mut attributes = Vec::new();
+        if file.finder_info.iter().any(|b| *b != 0) {
+            attributes.push((FINDER_INFO_XATTR.to_string(), file.finder_info.clone()));
+        }
+        if file.resource_size > 0 {
+            let (extents, size) = self.fork(file, FORK_RESOURCE)?;
+            let resource = self.read_fork(&extents, size, 0, size as usize)?;
+            attributes.push((RESOURCE_FORK_XATTR.to_string(), resource));
+        }
+        Ok(attributes)
+    }
+}
@@ -0,0 +1,369 @@
+//! Persistent SQLite index of the records of a walk: one row per `File` with indexed
+//! time, size and digest columns, plus an FTS5 table over names, paths and metadata, so
+//! repeated questions can be answered without walking the image again.
+//!
+//! Several evidence files, partitions and snapshots can share an index. Record
+//! identifiers are only unique within one of them, so the database id of every record is
+//! allocated in the `identifiers` table for its (evidence, partition, namespace, record)
+//! key; adding a key twice fails instead of overwriting the first record.
+use crate::budget;
+use crate::filesystem::File;
+use crate::query::Query;
+use sqlx::sqlite::{SqliteConnectOptions, SqlitePool, SqlitePoolOptions};
+use sqlx::{QueryBuilder, Sqlite};
+use std::error::Error;
+use std::path::Path;
+use tokio::runtime::Runtime;
+
+/// Records inserted per transaction.
+const BATCH_SIZE: usize = 1000;
+/// Memory accounted per pending record.
+const FILE_BYTES: u64 = 2048;
+
+const SCHEMA: &[&str] = &[
+    "CREATE TABLE index_info (key TEXT PRIMARY KEY, value TEXT NOT NULL)",
+    "CREATE TABLE identifiers (
+        id INTEGER PRIMARY KEY,
+        evidence TEXT NOT NULL,
+        partition TEXT NOT NULL,
+        namespace TEXT NOT NULL,
+        record INTEGER NOT NULL,
+        UNIQUE (evidence, partition, namespace, record)
+    )",
+    "CREATE TABLE files (
+        id INTEGER PRIMARY KEY,
+        identifier INTEGER NOT NULL,
+        absolute_path TEXT NOT NULL,
+        name TEXT NOT NULL,
+        raw_name TEXT,
+        ftype TEXT NOT NULL,
+        size INTEGER NOT NULL,
+        size_on_disk INTEGER,
+        created INTEGER,
+        modified INTEGER,
+        accessed INTEGER,
+        changed INTEGER,
+        permissions TEXT,
+        owner TEXT,
+        \"group\" TEXT,
+        display TEXT,
+        sig_name TEXT,
+        sig_mime TEXT,
+        sig_exts TEXT,
+        detected_type TEXT,
+        ext_mismatch INTEGER,
+        md5 TEXT,
+        sha1 TEXT,
+        sha256 TEXT,
+        metadata TEXT NOT NULL
+    )",
+    "CREATE INDEX files_identifier ON files (identifier)",
+    "CREATE INDEX files_size ON files (size)",
+    "CREATE INDEX files_created ON files (created)",
+    "CREATE INDEX files_modified ON files (modified)",
+    "CREATE INDEX files_accessed ON files (accessed)",
+    "CREATE INDEX files_changed ON files (changed)",
+    "CREATE INDEX files_md5 ON files (md5)",
+    "CREATE INDEX files_sha1 ON files (sha1)",
+    "CREATE INDEX files_sha256 ON files (sha256)",
+    "CREATE VIRTUAL TABLE files_fts USING fts5(
+        name, absolute_path, metadata, content='files', content_rowid='id'
+    )",
+];
+
+/// Where the records added to an index come from, the scope of their identifiers.
+#[derive(Debug, Clone, Default)]
+pub struct IndexSource {
+    /// Path of the evidence.
+    pub evidence: String,
+    /// Partition offset in sectors, empty for folders and whole images.
+    pub partition: String,
+    /// Identifier space within the partition (`snapshot:<id>`), empty for the live
+    /// filesystem.
+    pub namespace: String,
+}
+
+/// Filters of an index search. Every filter given must match.
+#[derive(Debug, Default)]
+pub struct IndexQuery<'a> {
+    /// FTS5 expression over names, paths and metadata (e.g. `invoice* OR dropper`).
+    pub text: Option<&'a str>,
+    /// MD5, SHA-1 or SHA-256 digest (hex).
+    pub hash: Option<&'a str>,
+    /// Raw SQL condition over the `files` columns (e.g. `size > 1000000`).
+    pub sql: Option<&'a str>,
+    /// `--where` expression, evaluated on the rows selected by the other filters.
+    pub filter: Option<&'a Query>,
+    pub limit: Option<usize>,
+}
+
+pub struct FileIndex {
+    runtime: Runtime,
+    pool: SqlitePool,
+    /// Source of the added records, `None` when opened for searching.
+    source: Option<IndexSource>,
+    pending: Vec<File>,
+    count: u64,
+}
+
+impl FileIndex {
+    fn connect(path: &Path, create: bool) -> Result<(Runtime, SqlitePool), Box<dyn Error>> {
+        let runtime = tokio::runtime::Builder::new_current_thread()
+            .enable_all()
+            .build()?;
+        let options = SqliteConnectOptions::new()
+            .filename(path)
+            .create_if_missing(create);
+        let pool = runtime.block_on(
+            SqlitePoolOptions::new()
+                .max_connections(1)
+                .connect_with(options),
+        )?;
+        Ok((runtime, pool))
+    }
+
+    /// Create a new index at `path`, which must not exist yet, for the records of
+    /// `source`.
+    pub fn create(path: &Path, source: IndexSource) -> Result<Self, Box<dyn Error>> {
+        if path.exists() {
+            return Err(format!("index '{}' already exists", path.display()).into());
+        }
+        let (runtime, pool) = Self::connect(path, true)?;
+        runtime.block_on(async {
+            for statement in SCHEMA {
+                sqlx::query(statement).execute(&pool).await?;
+            }
+            Ok::<_, sqlx::Error>(())
+        })?;
+        Ok(Self {
+            runtime,
+            pool,
+            source: Some(source),
+            pending: Vec::new(),
+            count: 0,
+        })
+    }
+
+    /// Open an existing index to add the records of another `source` to it.
+    pub fn append(path: &Path, source: IndexSource) -> Result<Self, Box<dyn Error>> {
+        let mut index = Self::open(path)?;
+        let registry: Option<String> = index.runtime.block_on(
+            sqlx::query_scalar(
+                "SELECT name FROM sqlite_master WHERE type = 'table' AND name = 'identifiers'",
+            )
+            .fetch_optional(&index.pool),
+        )?;
+        if registry.is_none() {
+            return Err(format!(
+                "index '{}' has no identifier registry and cannot be appended to",
+                path.display()
+            )
+            .into());
+        }
+        index.source = Some(source);
+        Ok(index)
+    }
+
+    /// Open an existing index for searching.
+    pub fn open(path: &Path) -> Result<Self, Box<dyn Error>> {
+        if !path.exists() {
+            return Err(format!("index '{}' does not exist", path.display()).into());
+        }
+        let (runtime, pool) = Self::connect(path, false)?;
+        Ok(Self {
+            runtime,
+            pool,
+            source: None,
+            pending: Vec::new(),
+            count: 0,
+        })
+    }
+
+    pub fn add(&mut self, file: File) -> Result<(), Box<dyn Error>> {
+        self.pending.push(file);
+        if self.pending.len() >= budget::items(BATCH_SIZE, budget::BATCH_SHARE, FILE_BYTES) {
+            self.flush()?;
+        }
+        Ok(())
+    }
+
+    /// Number of records added so far.
+    pub fn count(&self) -> u64 {
+        self.count
+    }
+
+    fn flush(&mut self) -> Result<(), Box<dyn Error>> {
+        if self.pending.is_empty() {
+            return Ok(());
+        }
+        let source = self
+            .source
+            .as_ref()
+            .ok_or("the index was opened for searching")?;
+        let files = std::mem::take(&mut self.pending);
+        self.count += files.len() as u64;
+        self.runtime.block_on(async {
+            let mut tx = self.pool.begin().await?;
+            for file in &files {
+                let key = |query| {
+                    sqlx::query_scalar::<_, i64>(query)
+                        .bind(&source.evidence)
+                        .bind(&source.partition)
+                        .bind(&source.namespace)
+                        .bind(file.identifier as i64)
+                };
+                let id = match key(
+                    "INSERT INTO identifiers (evidence, partition, namespace, record)
+                     VALUES (?, ?, ?, ?) RETURNING id",
+                )
+                .fetch_one(&mut *tx)
+                .await
+                {
+                    Ok(id) => id,
+                    Err(sqlx::Error::Database(e)) if e.is_unique_violation() => {
+                        let existing = key(
+                            "SELECT id FROM identifiers
+                             WHERE evidence = ? AND partition = ? AND namespace = ? AND record = ?",
+                        )
+                        .fetch_one(&mut *tx)
+                        .await?;
+                        return Err(format!(
+                            "identifier collision: record {} ({}) of {} is already indexed as id {}",
+                            file.identifier, file.absolute_path, source.evidence, existing
+                        )
+                        .into());
+                    }
+                    Err(e) => return Err(e.into()),
+                };
+                sqlx::query(
+                    "INSERT INTO files (id, identifier, absolute_path, name, raw_name, ftype, size,
+                        size_on_disk, created, modified, accessed, changed, permissions, owner,
+                        \"group\", display, sig_name, sig_mime, sig_exts, detected_type,
+                        ext_mismatch, md5, sha1, sha256, metadata)
+                     VALUES (?, ?, ?, ?, ?, ?, ?, ?, ?, ?, ?, ?, ?, ?, ?, ?, ?, ?, ?, ?, ?, ?, ?, ?, ?)",
+                )
+                .bind(id)
+                .bind(file.identifier as i64)
+                .bind(&file.absolute_path)
+                .bind(&file.name)
+                .bind(&file.raw_name)
+                .bind(&file.ftype)
+                .bind(file.size as i64)
+                .bind(file.size_on_disk.map(|s| s as i64))
+                .bind(file.created.map(|t| t as i64))
+                .bind(file.modified.map(|t| t as i64))
+                .bind(file.accessed.map(|t| t as i64))
+                .bind(file.changed.map(|t| t as i64))
+                .bind(&file.permissions)
+                .bind(&file.owner)
+                .bind(&file.group)
+                .bind(&file.display)
+                .bind(&file.sig_name)
+                .bind(&file.sig_mime)
+                .bind(&file.sig_exts)
+                .bind(&file.detected_type)
+                .bind(file.ext_mismatch)
+                .bind(&file.md5)
+                .bind(&file.sha1)
+                .bind(&file.sha256)
+                .bind(file.metadata.to_string())
+                .execute(&mut *tx)
+                .await?;
+            }
+            tx.commit().await?;
+            Ok::<_, Box<dyn Error>>(())
+        })
+    }
+
+    /// Flush the remaining records, build the full-text index and record where the
+    /// latest records came from (`evidence`, `filesystem`, ...). Returns the number of
+    /// records added.
+    pub fn finish(mut self, info: &[(&str, String)]) -> Result<u64, Box<dyn Error>> {
+        self.flush()?;
+        let updated = jiff::Timestamp::now().to_string();
+        let version = env!("CARGO_PKG_VERSION").to_string();
+        self.runtime.block_on(async {
+            let records: i64 = sqlx::query_scalar("SELECT COUNT(*) FROM files")
+                .fetch_one(&self.pool)
+                .await?;
+            let sources: i64 = sqlx::query_scalar(
+                "SELECT COUNT(*) FROM
+                 (SELECT DISTINCT evidence, partition, namespace FROM identifiers)",
+            )
+            .fetch_one(&self.pool)
+            .await?;
+            let (records, sources) = (records.to_string(), sources.to_string());
+            sqlx::query("INSERT INTO files_fts (files_fts) VALUES ('rebuild')")
+                .execute(&self.pool)
+                .await?;
+            // The first run created the index, appends update it.
+            sqlx::query("INSERT OR IGNORE INTO index_info (key, value) VALUES ('created', ?)")
+                .bind(&updated)
+                .execute(&self.pool)
+                .await?;
+            let defaults = [
+                ("updated", updated),
+                ("version", version),
+                ("records", records),
+                ("sources", sources),
+            ];
+            for (key, value) in info.iter().cloned().chain(defaults) {
+                sqlx::query("INSERT OR REPLACE INTO index_info (key, value) VALUES (?, ?)")
+                    .bind(key)
+                    .bind(value)
+                    .execute(&self.pool)
+                    .await?;
+            }
+            self.pool.close().await;
+            Ok::<_, sqlx::Error>(())
+        })?;
+        Ok(self.count)
+    }
+
+    /// Key/value description of the indexed evidence.
+    pub fn info(&self) -> Result<Vec<(String, String)>, Box<dyn Error>> {
+        Ok(self.runtime.block_on(
+            sqlx::query_as("SELECT key, value FROM index_info ORDER BY key").fetch_all(&self.pool),
+        )?)
+    }
+
+    /// Records matching `query`, ordered by path.
+    pub fn search(&self, query: &IndexQuery) -> Result<Vec<File>, Box<dyn Error>> {
+        let mut sql: QueryBuilder<Sqlite> = QueryBuilder::new("SELECT files.* FROM files");
+        if let Some(text) = query.text {
+            sql.push(" JOIN files_fts ON files_fts.rowid = files.id AND files_fts MATCH ");
+            sql.push_bind(text);
+        }
+        sql.push(" WHERE 1");
+        if let Some(hash) = query.hash {
+            let hash = hash.trim().to_ascii_lowercase();
+            sql.push(" AND (files.md5 = ");
+            sql.push_bind(hash.clone());
+            sql.push(" OR files.sha1 = ");
+            sql.push_bind(hash.clone());
+            sql.push(" OR files.sha256 = ");
+            sql.push_bind(hash);
+            sql.push(")");
+        }
+        if let Some(condition) = query.sql {
+            sql.push(" AND (");
+            sql.push(condition);
+            sql.push(")");
+        }
+        sql.push(" ORDER BY files.absolute_path");
+        if let (Some(limit), None) = (query.limit, query.filter) {
+            sql.push(" LIMIT ");
+            sql.push_bind(limit as i64);
+        }
+        let mut files: Vec<File> = self
+            .runtime
+            .block_on(sql.build_query_as().fetch_all(&self.pool))?;
+        if let Some(filter) = query.filter {
+            files.retain(|f| filter.matches(f));
+            if let Some(limit) = query.limit {
+                files.truncate(limit);
+            }
+        }
+        Ok(files)
+    }
+}
@@ -1,8 +1,58 @@
 pub mod apfs_impl;
+pub mod audit;
+pub mod block;
+pub mod budget;
+pub mod cache;
+pub mod carve;
+pub mod collect;
+pub mod compression;
+pub mod consistency;
+pub mod cramfs_impl;
+pub mod custody;
+pub mod dedupe;
 pub mod detected_fs;
+pub mod diff;
+pub mod du;
+pub mod enrich;
 pub mod exfat_impl;
+pub mod export;
 pub mod extfs_impl;
+pub mod f2fs_impl;
 pub mod filesystem;
 pub mod folder_impl;
+#[cfg(feature = "fuzzing")]
+pub mod fuzzing;
+pub mod hashing;
+pub mod hfs_impl;
+pub mod hexdump;
+pub mod index;
+pub mod magic;
+pub mod names;
 pub mod ntfs_impl;
+pub mod overlay;
+pub mod parallel;
+pub mod partitions;
+pub mod progress;
+pub mod query;
+pub mod recover;
+pub mod refs_impl;
+pub mod reverse;
+pub mod search;
+pub mod selector;
+pub mod snapshots;
+pub mod sparse;
+pub mod spill;
+pub mod squashfs_impl;
+pub mod stats;
+pub mod strings;
+pub mod throttle;
+pub mod timefmt;
+pub mod tolerant;
+pub mod triage;
+pub mod ubifs_impl;
+pub mod udf_impl;
+pub mod ufs_impl;
+pub mod verify;
+pub mod yaffs2_impl;
+pub mod zfs_impl;
 pub use filesystem::{File, Filesystem};
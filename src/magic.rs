@@ -0,0 +1,422 @@
+//! Content type identification from magic bytes, independent of the file name.
+use crate::filesystem::{File, ReadSeek};
+use std::io::{self, Read, SeekFrom};
+
+/// Bytes of content needed by `identify` (the `ustar` marker sits at offset 257).
+pub const MAGIC_PREFIX_LEN: usize = 4096;
+
+/// A recognized content type.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Signature {
+    /// Short identifier stored in `File::detected_type` (`pe`, `jpeg`, ...).
+    pub kind: &'static str,
+    pub name: &'static str,
+    pub mime: &'static str,
+    /// Extensions usually carried by this content, lowercase and without the dot.
+    pub extensions: &'static [&'static str],
+}
+
+impl Signature {
+    /// Whether the extension of `name` contradicts this content type. Names without an
+    /// extension never do.
+    pub fn contradicts(&self, name: &str) -> bool {
+        match name.rsplit_once('.') {
+            Some((stem, ext)) if !stem.is_empty() && !ext.is_empty() => {
+                let ext = ext.to_ascii_lowercase();
+                !self.extensions.contains(&ext.as_str())
+            }
+            _ => false,
+        }
+    }
+
+    /// Copy the identification onto a `File` record, including the extension check.
+    pub fn apply_to(&self, file: &mut File) {
+        file.detected_type = Some(self.kind.to_string());
+        file.sig_name = Some(self.name.to_string());
+        file.sig_mime = Some(self.mime.to_string());
+        file.sig_exts = Some(self.extensions.join(","));
+        file.ext_mismatch = Some(self.contradicts(&file.name));
+    }
+}
+
+macro_rules! sig {
+    ($kind:expr, $name:expr, $mime:expr, [$($ext:expr),*]) => {
+        Signature {
+            kind: $kind,
+            name: $name,
+            mime: $mime,
+            extensions: &[$($ext),*],
+        }
+    };
+}
+
+const PE: Signature = sig!(
+    "pe",
+    "PE32 executable",
+    "application/vnd.microsoft.portable-executable",
+    [
+        "exe", "dll", "sys", "scr", "cpl", "ocx", "drv", "efi", "mui", "com"
+    ]
+);
+const MSDOS: Signature = sig!(
+    "msdos",
+    "MS-DOS executable",
+    "application/x-dosexec",
+    ["exe", "com"]
+);
+const MACHO: Signature = sig!(
+    "macho",
+    "Mach-O executable",
+    "application/x-mach-binary",
+    ["", "dylib", "bundle", "so"]
+);
+const MACHO_UNIVERSAL: Signature = sig!(
+    "macho",
+    "Mach-O universal binary",
+    "application/x-mach-binary",
+    ["", "dylib", "bundle", "so"]
+);
+const JAVA_CLASS: Signature = sig!(
+    "java-class",
+    "Java class file",
+    "application/java-vm",
+    ["class"]
+);
+const TIFF: Signature = sig!(
+    "tiff",
+    "TIFF image",
+    "image/tiff",
+    ["tif", "tiff", "dng", "nef", "cr2", "arw"]
+);
+const ZIP: Signature = sig!(
+    "zip",
+    "Zip archive",
+    "application/zip",
+    [
+        "zip", "jar", "apk", "ipa", "xpi", "odt", "ods", "odp", "epub", "kmz", "nupkg", "whl",
+        // OOXML documents whose first entries do not reveal the application.
+        "docx", "xlsx", "pptx", "vsdx"
+    ]
+);
+const DOCX: Signature = sig!(
+    "docx",
+    "Microsoft Word 2007+",
+    "application/vnd.openxmlformats-officedocument.wordprocessingml.document",
+    ["docx", "docm", "dotx", "dotm"]
+);
+const XLSX: Signature = sig!(
+    "xlsx",
+    "Microsoft Excel 2007+",
+    "application/vnd.openxmlformats-officedocument.spreadsheetml.sheet",
+    ["xlsx", "xlsm", "xltx", "xltm"]
+);
+const PPTX: Signature = sig!(
+    "pptx",
+    "Microsoft PowerPoint 2007+",
+    "application/vnd.openxmlformats-officedocument.presentationml.presentation",
+    ["pptx", "pptm", "potx", "ppsx"]
+);
+
+/// Bytes expected at an offset of the content.
+type Magic = (usize, &'static [u8]);
+
+/// Signatures made of fixed bytes at fixed offsets; every pair must match.
+const MAGIC: &[(&[Magic], Signature)] = &[
+    (
+        &[(0, b"\x7fELF")],
+        sig!(
+            "elf",
+            "ELF executable",
+            "application/x-executable",
+            ["", "so", "o", "ko", "elf", "bin"]
+        ),
+    ),
+    (&[(0, b"\xfe\xed\xfa\xce")], MACHO),
+    (&[(0, b"\xfe\xed\xfa\xcf")], MACHO),
+    (&[(0, b"\xce\xfa\xed\xfe")], MACHO),
+    (&[(0, b"\xcf\xfa\xed\xfe")], MACHO),
+    (
+        &[(0, b"\xff\xd8\xff")],
+        sig!(
+            "jpeg",
+            "JPEG image",
+            "image/jpeg",
+            ["jpg", "jpeg", "jpe", "jfif"]
+        ),
+    ),
+    (
+        &[(0, b"\x89PNG\r\n\x1a\n")],
+        sig!("png", "PNG image", "image/png", ["png"]),
+    ),
+    (
+        &[(0, b"GIF87a")],
+        sig!("gif", "GIF image", "image/gif", ["gif"]),
+    ),
+    (
+        &[(0, b"GIF89a")],
+        sig!("gif", "GIF image", "image/gif", ["gif"]),
+    ),
+    (&[(0, b"II*\x00")], TIFF),
+    (&[(0, b"MM\x00*")], TIFF),
+    (
+        &[(0, b"RIFF"), (8, b"WEBP")],
+        sig!("webp", "WebP image", "image/webp", ["webp"]),
+    ),
+    (
+        &[(0, b"RIFF"), (8, b"WAVE")],
+        sig!("wav", "WAVE audio", "audio/x-wav", ["wav"]),
+    ),
+    (
+        &[(0, b"RIFF"), (8, b"AVI ")],
+        sig!("avi", "AVI video", "video/x-msvideo", ["avi"]),
+    ),
+    (
+        &[(4, b"ftypqt")],
+        sig!("mov", "QuickTime video", "video/quicktime", ["mov", "qt"]),
+    ),
+    (
+        &[(4, b"ftypheic")],
+        sig!("heic", "HEIF image", "image/heic", ["heic", "heif"]),
+    ),
+    (
+        &[(4, b"ftyp")],
+        sig!(
+            "mp4",
+            "ISO media",
+            "video/mp4",
+            ["mp4", "m4a", "m4v", "3gp", "mov", "heic"]
+        ),
+    ),
+    (
+        &[(0, b"\x1a\x45\xdf\xa3")],
+        sig!(
+            "mkv",
+            "Matroska / WebM",
+            "video/x-matroska",
+            ["mkv", "webm", "mka"]
+        ),
+    ),
+    (
+        &[(0, b"ID3")],
+        sig!("mp3", "MP3 audio", "audio/mpeg", ["mp3"]),
+    ),
+    (
+        &[(0, b"fLaC")],
+        sig!("flac", "FLAC audio", "audio/flac", ["flac"]),
+    ),
+    (
+        &[(0, b"OggS")],
+        sig!(
+            "ogg",
+            "Ogg media",
+            "audio/ogg",
+            ["ogg", "oga", "ogv", "opus"]
+        ),
+    ),
+    (
+        &[(0, b"%PDF-")],
+        sig!("pdf", "PDF document", "application/pdf", ["pdf"]),
+    ),
+    (
+        &[(0, b"{\\rtf")],
+        sig!("rtf", "Rich Text Format", "text/rtf", ["rtf", "doc"]),
+    ),
+    (
+        &[(0, b"\xd0\xcf\x11\xe0\xa1\xb1\x1a\xe1")],
+        sig!(
+            "ole",
+            "OLE2 compound document",
+            "application/x-ole-storage",
+            [
+                "doc", "xls", "ppt", "msg", "msi", "dot", "xlt", "pps", "vsd", "pub", "db"
+            ]
+        ),
+    ),
+    (
+        &[(0, b"\x1f\x8b")],
+        sig!(
+            "gzip",
+            "gzip compressed data",
+            "application/gzip",
+            ["gz", "tgz", "svgz"]
+        ),
+    ),
+    (
+        &[(0, b"BZh")],
+        sig!(
+            "bzip2",
+            "bzip2 compressed data",
+            "application/x-bzip2",
+            ["bz2", "tbz2", "tbz"]
+        ),
+    ),
+    (
+        &[(0, b"\xfd7zXZ\x00")],
+        sig!(
+            "xz",
+            "XZ compressed data",
+            "application/x-xz",
+            ["xz", "txz"]
+        ),
+    ),
+    (
+        &[(0, b"\x28\xb5\x2f\xfd")],
+        sig!(
+            "zstd",
+            "Zstandard compressed data",
+            "application/zstd",
+            ["zst", "tzst"]
+        ),
+    ),
+    (
+        &[(0, b"7z\xbc\xaf\x27\x1c")],
+        sig!("7z", "7-zip archive", "application/x-7z-compressed", ["7z"]),
+    ),
+    (
+        &[(0, b"Rar!\x1a\x07")],
+        sig!("rar", "RAR archive", "application/vnd.rar", ["rar"]),
+    ),
+    (
+        &[(257, b"ustar")],
+        sig!("tar", "POSIX tar archive", "application/x-tar", ["tar"]),
+    ),
+    (
+        &[(0, b"SQLite format 3\x00")],
+        sig!(
+            "sqlite",
+            "SQLite 3 database",
+            "application/vnd.sqlite3",
+            ["sqlite", "sqlite3", "db", "db3", "sqlitedb", "storedata"]
+        ),
+    ),
+    (
+        &[(0, b"regf")],
+        sig!(
+            "registry",
+            "Windows registry hive",
+            "application/x-ms-registry",
+            ["", "dat", "hve", "sav", "log1", "log2"]
+        ),
+    ),
+    (
+        &[(0, b"ElfFile\x00")],
+        sig!(
+            "evtx",
+            "Windows event log",
+            "application/x-ms-evtx",
+            ["evtx"]
+        ),
+    ),
+    (
+        &[(0, b"L\x00\x00\x00\x01\x14\x02\x00")],
+        sig!(
+            "lnk",
+            "Windows shortcut",
+            "application/x-ms-shortcut",
+            ["lnk"]
+        ),
+    ),
+    (
+        &[(4, b"SCCA")],
+        sig!(
+            "prefetch",
+            "Windows prefetch",
+            "application/x-ms-prefetch",
+            ["pf"]
+        ),
+    ),
+    (
+        &[(0, b"MAM\x04")],
+        sig!(
+            "prefetch",
+            "Windows prefetch (compressed)",
+            "application/x-ms-prefetch",
+            ["pf"]
+        ),
+    ),
+    (
+        &[(0, b"#!")],
+        sig!(
+            "script",
+            "Script with interpreter",
+            "text/x-script",
+            ["", "sh", "bash", "py", "pl", "rb", "php", "js", "command"]
+        ),
+    ),
+    (
+        &[(0, b"<?xml")],
+        sig!(
+            "xml",
+            "XML document",
+            "text/xml",
+            [
+                "xml", "plist", "svg", "xaml", "config", "manifest", "rss", "xsl"
+            ]
+        ),
+    ),
+    (
+        &[(0, b"bplist00")],
+        sig!(
+            "bplist",
+            "Binary property list",
+            "application/x-bplist",
+            ["plist"]
+        ),
+    ),
+];
+
+fn has_at(data: &[u8], offset: usize, magic: &[u8]) -> bool {
+    data.get(offset..offset + magic.len()) == Some(magic)
+}
+
+fn contains(data: &[u8], needle: &[u8]) -> bool {
+    data.windows(needle.len()).any(|w| w == needle)
+}
+
+/// Identify the content type from the first bytes of a file (`MAGIC_PREFIX_LEN` is enough).
+pub fn identify(data: &[u8]) -> Option<&'static Signature> {
+    if has_at(data, 0, b"MZ") {
+        let pe_offset = data
+            .get(0x3c..0x40)
+            .map(|b| u32::from_le_bytes([b[0], b[1], b[2], b[3]]) as usize);
+        // The PE header may lie beyond the prefix; an MZ stub alone is reported as MS-DOS.
+        return Some(match pe_offset {
+            Some(off) if has_at(data, off, b"PE\x00\x00") => &PE,
+            _ => &MSDOS,
+        });
+    }
+    if has_at(data, 0, b"\xca\xfe\xba\xbe") {
+        // Shared by Java classes (major version >= 45) and fat Mach-O (a few archs).
+        let word = u32::from_be_bytes(data.get(4..8)?.try_into().ok()?);
+        return Some(if word < 45 {
+            &MACHO_UNIVERSAL
+        } else {
+            &JAVA_CLASS
+        });
+    }
+    if has_at(data, 0, b"PK\x03\x04") {
+        return Some(if contains(data, b"word/") {
+            &DOCX
+        } else if contains(data, b"xl/") {
+            &XLSX
+        } else if contains(data, b"ppt/") {
+            &PPTX
+        } else {
+            &ZIP
+        });
+    }
+    MAGIC
+        .iter()
+        .find(|(magic, _)| magic.iter().all(|(off, bytes)| has_at(data, *off, bytes)))
+        .map(|(_, sig)| sig)
+}
+
+/// Identify the content behind `reader` and rewind it for the next consumer.
+pub fn identify_reader(reader: &mut dyn ReadSeek) -> io::Result<Option<&'static Signature>> {
+    let mut prefix = Vec::with_capacity(MAGIC_PREFIX_LEN);
+    (&mut *reader)
+        .take(MAGIC_PREFIX_LEN as u64)
+        .read_to_end(&mut prefix)?;
+    reader.seek(SeekFrom::Start(0))?;
+    Ok(identify(&prefix))
+}
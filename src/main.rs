@@ -1,17 +1,1450 @@
+mod cli;
+
+use cli::batch::CaseManifest;
+use cli::config::{Config, Defaults};
+
+use clap::builder::{PossibleValuesParser, TypedValueParser};
 use clap::*;
 use clap_num::maybe_hex;
 use exhume_body::Body;
-use exhume_filesystem::Filesystem;
-use exhume_filesystem::detected_fs::{DetectedFs, KeyMaterial, detect_filesystem};
+use exhume_filesystem::apfs_impl::VolumePrefixes;
+use exhume_filesystem::audit;
+use exhume_filesystem::block::BlockDevice;
+use exhume_filesystem::budget;
+use exhume_filesystem::cache;
+use exhume_filesystem::carve::{
+    CarveOptions, CarveSource, Region, carve, parse_carve_types, slack_regions, unallocated_regions,
+};
+use exhume_filesystem::collect::{CollectSink, TargetSet, collect};
+use exhume_filesystem::consistency;
+use exhume_filesystem::custody::{
+    CustodyEntry, CustodyManifest, EvidenceSource, byte_runs, load_signing_key, now as custody_now,
+};
+use exhume_filesystem::dedupe::find_duplicates;
+use exhume_filesystem::detected_fs::{
+    DetectedFs, FsType, ImageStream, KeyMaterial, detect_filesystem, detect_snapshot_filesystem,
+    open_filesystem_as, open_partition_stream, select_snapshot,
+};
+use exhume_filesystem::diff::{
+    ChangeKind, DIFF_CSV_HEADER, TIMELINE_CSV_HEADER, detect_renames, diff_csv_line,
+    diff_filesystems, timeline, timeline_csv_line,
+};
+use exhume_filesystem::du::disk_usage;
+use exhume_filesystem::enrich::Enricher;
+use exhume_filesystem::export::{ExportFormat, Exporter};
 use exhume_filesystem::filesystem::DirectoryCommon;
 use exhume_filesystem::filesystem::FileCommon;
-use exhume_filesystem::folder_impl::FolderFS;
-use log::{debug, error, info};
+use exhume_filesystem::filesystem::{
+    ExportPosition, FsFileReadSeek, ReadSeek, STREAM_CAPACITY, WalkCheckpoint, WalkEvent,
+    WalkOptions, stream_walk,
+};
+use exhume_filesystem::folder_impl::{FolderFS, FolderOptions};
+use exhume_filesystem::hashing::{
+    FileHashes, HashAlgorithm, HashPipeline, copy_and_hash, hash_reader, parse_hash_list,
+};
+use exhume_filesystem::hexdump::hexdump;
+use exhume_filesystem::index::{FileIndex, IndexQuery, IndexSource};
+use exhume_filesystem::magic::identify_reader;
+use exhume_filesystem::names::{NameRendering, set_name_rendering};
+use exhume_filesystem::overlay::{OverlayFS, OverlayLayer};
+use exhume_filesystem::parallel::{SharedVisitor, walk_parallel};
+use exhume_filesystem::partitions::{detect_sector_size, read_partition_table};
+use exhume_filesystem::progress::{Progress, ProgressUnit};
+use exhume_filesystem::query::Query;
+use exhume_filesystem::recover::recover_files;
+use exhume_filesystem::reverse::{find_names, find_path_id};
+use exhume_filesystem::search::{
+    ExcludeSet, NamePattern, content_regex, grep_reader, hex_regex, parse_size,
+};
+use exhume_filesystem::selector::{RecordSelector, parse_record_id, parse_record_list};
+use exhume_filesystem::snapshots::list_snapshots;
+use exhume_filesystem::sparse::{SparseWriter, allocated_size};
+use exhume_filesystem::stats::VolumeStats;
+use exhume_filesystem::strings::{StringEncoding, extract_strings};
+use exhume_filesystem::throttle;
+use exhume_filesystem::timefmt::{
+    LocalTimePolicy, TimeDisplay, set_local_time_policy, set_time_display,
+};
+use exhume_filesystem::tolerant;
+use exhume_filesystem::triage::{Severity, Triage, TriageOptions};
+use exhume_filesystem::verify::verify_content;
+use exhume_filesystem::{File, Filesystem};
+use indicatif::HumanBytes;
+use log::{debug, error, info, warn};
 use serde_json::{Value, json};
-use std::path::Path;
+use std::cell::RefCell;
+use std::error::Error;
+use std::fs::{File as StdFile, OpenOptions};
+use std::io::{self, BufWriter, IsTerminal, Read, Seek, SeekFrom, Write};
+use std::path::{Path, PathBuf};
+use std::sync::Arc;
+
+/// Reopen a partially written export, dropping anything written after the checkpoint.
+fn open_for_resume(path: &str, len: u64) -> io::Result<StdFile> {
+    let mut f = OpenOptions::new().write(true).open(path)?;
+    f.set_len(len)?;
+    f.seek(SeekFrom::End(0))?;
+    Ok(f)
+}
+
+/// Stream a record into `file_<N>.bin` (below `dir` when given) while hashing it, then
+/// write the digests and the record metadata into the `file_<N>.bin.json` sidecar. The
+/// holes of sparse records stay holes in the dump. Returns the dump path, the digests
+/// and the number of bytes written.
+fn dump_with_hashes<F: Filesystem>(
+    fs: &mut F,
+    file_id: u64,
+    record: Value,
+    algorithms: &[HashAlgorithm],
+    dir: Option<&Path>,
+) -> Result<(String, FileHashes, u64), String> {
+    let filename = format!("file_{}.bin", file_id);
+    let filename = match dir {
+        Some(dir) => {
+            std::fs::create_dir_all(dir).map_err(|e| {
+                format!("Could not create dump directory '{}': {}", dir.display(), e)
+            })?;
+            dir.join(filename).to_string_lossy().into_owned()
+        }
+        None => filename,
+    };
+    info!("Dumping file {} content into '{}'", file_id, filename);
+
+    let holes = match fs.get_file(file_id).and_then(|file| fs.file_holes(&file)) {
+        Ok(holes) => holes,
+        Err(e) => {
+            warn!("Could not map the holes of record {}: {}", file_id, e);
+            None
+        }
+    };
+    let reader = FsFileReadSeek::from_id(fs, file_id)
+        .map_err(|e| format!("Cannot read content for record {}: {}", file_id, e))?;
+    let progress = Progress::new(Some(reader.len()), ProgressUnit::Bytes);
+    let mut reader = progress.wrap_read(reader);
+
+    let mut out = StdFile::create(&filename)
+        .map(|f| SparseWriter::new(f, holes.clone().unwrap_or_default()))
+        .map_err(|e| format!("Could not create dump file '{}': {}", filename, e))?;
+
+    let result = copy_and_hash(&mut reader, &mut out, algorithms).and_then(|r| {
+        out.finish()?;
+        Ok(r)
+    });
+    progress.finish();
+    let (hashes, written) =
+        result.map_err(|e| format!("Error writing file '{}': {}", filename, e))?;
+    let allocated = holes.as_deref().map(|holes| allocated_size(written, holes));
+    match allocated {
+        Some(allocated) if allocated < written => info!(
+            "Successfully wrote {} bytes into '{}' ({} allocated, the rest left as holes)",
+            written, filename, allocated
+        ),
+        _ => info!("Successfully wrote {} bytes into '{}'", written, filename),
+    }
+
+    let sidecar = format!("{}.json", filename);
+    let content = json!({
+        "record": file_id,
+        "dump": filename,
+        "size": written,
+        "allocated_size": allocated,
+        "hashes": hashes,
+        "metadata": record,
+    });
+    match serde_json::to_vec_pretty(&content) {
+        Ok(data) => match std::fs::write(&sidecar, data) {
+            Ok(_) => info!("Wrote dump metadata into '{}'", sidecar),
+            Err(e) => error!("Could not write sidecar '{}': {}", sidecar, e),
+        },
+        Err(e) => error!("Could not serialize sidecar '{}': {}", sidecar, e),
+    }
+    Ok((filename, hashes, written))
+}
+
+/// What to do with each record selected through `--record`.
+struct RecordActions<'a> {
+    list: bool,
+    dump: bool,
+    print: bool,
+    json: bool,
+    hashes: Option<&'a [HashAlgorithm]>,
+    /// Directory receiving dumps instead of the working directory.
+    dump_dir: Option<&'a Path>,
+    hexdump: bool,
+    strings: Option<(usize, StringEncoding)>,
+    content_offset: u64,
+    length: Option<u64>,
+}
+
+fn process_record<F: Filesystem>(
+    filesystem: &mut F,
+    selector: &RecordSelector,
+    actions: &RecordActions,
+    custody: Option<&mut CustodyManifest>,
+) {
+    let fetched = match selector {
+        RecordSelector::Id(id) => filesystem.get_file(*id),
+        RecordSelector::Path(path) => filesystem.get_file_by_path(path, 0),
+    };
+    let file = match fetched {
+        Ok(file) => file,
+        Err(err) => {
+            error!("Could not fetch the requested file {}: {:?}", selector, err);
+            return;
+        }
+    };
+    let file_id = match selector {
+        RecordSelector::Id(id) => *id,
+        RecordSelector::Path(_) => file.id(),
+    };
+
+    if actions.list {
+        if file.is_dir() {
+            match filesystem.list_dir(&file) {
+                Ok(entries) => {
+                    if actions.json {
+                        let arr: Vec<Value> = entries.iter().map(|de| de.to_json()).collect();
+                        let dir_json = json!(arr);
+                        println!("{}", serde_json::to_string_pretty(&dir_json).unwrap());
+                    } else {
+                        info!("Directory listing for file record {}:", file_id);
+                        for entry in entries {
+                            println!("[{}] - {}", entry.file_id(), entry.name());
+                        }
+                    }
+                }
+                Err(err) => {
+                    error!(
+                        "Failed to list directory for file record {}: {}",
+                        file_id, err
+                    );
+                }
+            }
+        } else {
+            error!(
+                "Requested to list the directory entries for a file but {} is not a directory.",
+                file_id
+            );
+        }
+    } else if actions.json {
+        match serde_json::to_string_pretty(&file.to_json()) {
+            Ok(json_str) => {
+                info!("File record {} metadata:", file_id);
+                println!("{}", json_str)
+            }
+            Err(e) => error!("Error serializing inode {} to JSON: {}", file_id, e),
+        }
+    } else {
+        println!("{}", file.to_string());
+    }
+
+    if let Some(algs) = actions.hashes
+        && !actions.dump
+        && !file.is_dir()
+    {
+        match FsFileReadSeek::from_id(filesystem, file_id)
+            .map_err(|e| e.to_string())
+            .and_then(|mut reader| hash_reader(&mut reader, algs).map_err(|e| e.to_string()))
+        {
+            Ok((hashes, _)) => {
+                for (name, digest) in [
+                    ("md5", &hashes.md5),
+                    ("sha1", &hashes.sha1),
+                    ("sha256", &hashes.sha256),
+                ] {
+                    if let Some(digest) = digest {
+                        println!("{}: {}", name, digest);
+                    }
+                }
+            }
+            Err(e) => error!("Could not hash file record {}: {}", file_id, e),
+        }
+    }
+
+    if actions.dump {
+        match (actions.hashes, actions.dump_dir, custody) {
+            (None, None, None) => filesystem.dump_to_fs(&file),
+            (algs, dir, custody) => {
+                // Custody entries always carry a digest.
+                let algs = match (algs, &custody) {
+                    (Some(algs), _) => algs,
+                    (None, Some(_)) => &[HashAlgorithm::Sha256],
+                    (None, None) => &[],
+                };
+                let dumped = dump_with_hashes(filesystem, file_id, file.to_json(), algs, dir);
+                if let Err(e) = &dumped {
+                    error!("{}", e);
+                }
+                if let Some(manifest) = custody {
+                    let partition_offset = manifest.evidence.partition_offset;
+                    let (output, hashes, size, error) = match dumped {
+                        Ok((output, hashes, size)) => (output, hashes, size, None),
+                        Err(e) => (String::new(), FileHashes::default(), 0, Some(e)),
+                    };
+                    manifest.entries.push(CustodyEntry {
+                        identifier: file_id,
+                        source_path: match selector {
+                            RecordSelector::Path(path) => Some(path.clone()),
+                            RecordSelector::Id(_) => None,
+                        },
+                        output,
+                        size,
+                        hashes,
+                        byte_runs: byte_runs(filesystem, &file, size, partition_offset),
+                        extracted_at: custody_now(),
+                        error,
+                    });
+                }
+            }
+        }
+    }
+
+    if actions.print {
+        match filesystem.read_file_prefix(&file, 8192) {
+            Ok(prefix) => println!("Successfully read prefix of length {}", prefix.len()),
+            Err(e) => println!("Error reading prefix: {}", e),
+        }
+    }
+
+    if actions.hexdump {
+        if file.is_dir() {
+            error!("Cannot hexdump {}: it is a directory.", file_id);
+            return;
+        }
+        let dumped = FsFileReadSeek::from_id(filesystem, file_id).and_then(|mut reader| {
+            reader.seek(SeekFrom::Start(actions.content_offset))?;
+            let mut out = BufWriter::new(io::stdout().lock());
+            Ok(hexdump(
+                &mut reader,
+                &mut out,
+                actions.content_offset,
+                actions.length,
+            )?)
+        });
+        if let Err(e) = dumped {
+            error!("Could not hexdump file record {}: {}", file_id, e);
+        }
+    }
+
+    if let Some((min_len, encoding)) = actions.strings {
+        if file.is_dir() {
+            error!(
+                "Cannot extract strings from {}: it is a directory.",
+                file_id
+            );
+            return;
+        }
+        let extracted = FsFileReadSeek::from_id(filesystem, file_id).and_then(|mut reader| {
+            reader.seek(SeekFrom::Start(actions.content_offset))?;
+            let mut reader: Box<dyn Read> = match actions.length {
+                Some(len) => Box::new(reader.take(len)),
+                None => Box::new(reader),
+            };
+            let mut out = BufWriter::new(io::stdout().lock());
+            let mut write_error = None;
+            extract_strings(
+                &mut reader,
+                actions.content_offset,
+                min_len,
+                encoding,
+                &mut |found| {
+                    let line = if actions.json {
+                        json!({
+                            "offset": found.offset,
+                            "encoding": found.encoding.to_string(),
+                            "text": found.text,
+                        })
+                        .to_string()
+                    } else if encoding == StringEncoding::Both {
+                        format!("{:08x} {:<7} {}", found.offset, found.encoding, found.text)
+                    } else {
+                        format!("{:08x} {}", found.offset, found.text)
+                    };
+                    if write_error.is_none()
+                        && let Err(e) = writeln!(out, "{}", line)
+                    {
+                        write_error = Some(e);
+                    }
+                },
+            )?;
+            match write_error {
+                Some(e) => Err(e.into()),
+                None => Ok(out.flush()?),
+            }
+        });
+        if let Err(e) = extracted {
+            error!(
+                "Could not extract strings from file record {}: {}",
+                file_id, e
+            );
+        }
+    }
+}
+
+/// Sector size `--size` and partition tables count in: `forced`, else the one the
+/// partition table implies, with a warning as it changes what `--size` means, else the
+/// one of the image format (512 for raw images).
+fn sector_size(body: &mut Body, forced: Option<u64>) -> u64 {
+    if let Some(forced) = forced {
+        return forced;
+    }
+    let recorded = body.get_sector_size() as u64;
+    match detect_sector_size(body) {
+        Some(detected) if detected != recorded => {
+            warn!(
+                "The partition table implies {}-byte sectors (image: {}): --size and partition offsets count in {}-byte sectors; pass --sector-size to override.",
+                detected, recorded, detected
+            );
+            detected
+        }
+        _ => recorded,
+    }
+}
+
+/// Open the evidence, stacked under its `--layer`s (the last the uppermost) when it has
+/// any.
+fn open_filesystem(evidence: &Evidence) -> Result<DetectedFs<ImageStream>, Box<dyn Error>> {
+    let base = open_body(evidence)?;
+    if evidence.layers.is_empty() {
+        return Ok(base);
+    }
+    let mut layers = vec![OverlayLayer::from_fs(evidence.path, base)];
+    for path in evidence.layers {
+        layers.push(OverlayLayer::open(path).map_err(|e| format!("layer {}: {}", path, e))?);
+    }
+    Ok(DetectedFs::Overlay(OverlayFS::new(layers)?))
+}
+
+/// Open a folder as a `FolderFS` walked under `evidence.folder`, or the filesystem of a
+/// disk image starting at `offset` and spanning `size` sectors, detected unless
+/// `evidence.fstype` names it. APFS volume roots go at `evidence.apfs_prefixes`, and a
+/// ZFS pool shows `evidence.zfs_dataset` rather than its root dataset, and a UBI image
+/// `evidence.ubi_volume` rather than its first UBIFS volume.
+fn open_body(evidence: &Evidence) -> Result<DetectedFs<ImageStream>, Box<dyn Error>> {
+    let Evidence {
+        path,
+        format,
+        offset,
+        size,
+        snapshot,
+        fstype,
+        ..
+    } = *evidence;
+    if Path::new(path).is_dir() {
+        if snapshot.is_some() {
+            return Err("--snapshot requires a disk image, not a folder".into());
+        }
+        if fstype != FsType::Auto {
+            return Err("--fstype requires a disk image, not a folder".into());
+        }
+        return Ok(DetectedFs::Folder(FolderFS::with_options(
+            PathBuf::from(path),
+            evidence.folder,
+        )));
+    }
+    let (Some(offset), Some(size)) = (offset, size) else {
+        return Err("Offset and Size arguments are required for disk images.".into());
+    };
+
+    let mut body = Body::new(path.to_owned(), format);
+    debug!("Created Body from '{}'", path);
+
+    let partition_size = size * sector_size(&mut body, evidence.sector_size);
+    let keys = evidence.keys.clone();
+    let filesystem = match snapshot {
+        Some(_) if !matches!(fstype, FsType::Auto | FsType::Ntfs) => {
+            return Err("shadow copies (--snapshot) only exist on NTFS".into());
+        }
+        Some(selector) => {
+            detect_snapshot_filesystem(&body, *offset, partition_size, keys, selector)?
+        }
+        None => open_filesystem_as(&body, *offset, partition_size, keys, fstype)?,
+    };
+    Ok(match filesystem {
+        DetectedFs::Apfs(fs) => DetectedFs::Apfs(fs.with_prefixes(evidence.apfs_prefixes.clone())),
+        DetectedFs::Zfs(mut fs) => {
+            if let Some(dataset) = evidence.zfs_dataset {
+                fs.select_dataset(dataset)?;
+            }
+            DetectedFs::Zfs(fs)
+        }
+        DetectedFs::Ubifs(mut fs) => {
+            if let Some(volume) = evidence.ubi_volume {
+                fs.select_volume(volume)?;
+            }
+            DetectedFs::Ubifs(fs)
+        }
+        filesystem => filesystem,
+    })
+}
+
+/// The partition stream, or the shadow copy of it selected with `--snapshot`.
+fn with_snapshot(
+    stream: ImageStream,
+    snapshot: Option<&str>,
+) -> Result<ImageStream, Box<dyn Error>> {
+    match snapshot {
+        Some(selector) => select_snapshot(stream, selector),
+        None => Ok(stream),
+    }
+}
+
+/// Handle the `batch` subcommand: process a case manifest.
+fn run_batch(matches: &ArgMatches, sub: &ArgMatches) -> Result<(), Box<dyn Error>> {
+    let manifest = CaseManifest::load(sub.get_one::<String>("manifest").unwrap())?;
+    let mut forwarded = Vec::new();
+    if let Some(config) = matches.get_one::<String>("config") {
+        let config = std::fs::canonicalize(config)?;
+        forwarded.extend([
+            "--config".to_string(),
+            config.to_string_lossy().into_owned(),
+        ]);
+    }
+    if let Some(case) = matches.get_one::<String>("case") {
+        forwarded.extend(["--case".to_string(), case.clone()]);
+    }
+    let summary = cli::batch::run(&manifest, &forwarded)?;
+    info!(
+        "Case {}: {} jobs, {} succeeded, {} failed; see {}",
+        summary.name,
+        summary.jobs,
+        summary.succeeded,
+        summary.failed,
+        manifest.output.join("summary.json").display()
+    );
+    for problem in &summary.problems {
+        warn!("{}", problem);
+    }
+    Ok(())
+}
+
+/// Handle the `snapshots` subcommand: list the points in time of the partition.
+fn run_snapshots(mut stream: ImageStream, matches: &ArgMatches) -> Result<(), Box<dyn Error>> {
+    let snapshots = list_snapshots(&mut stream)?;
+    if matches.get_flag("json") {
+        println!("{}", serde_json::to_string_pretty(&snapshots)?);
+    } else if snapshots.is_empty() {
+        info!("No snapshots found");
+    } else {
+        for snapshot in &snapshots {
+            println!("{}", snapshot.to_line());
+        }
+    }
+    Ok(())
+}
+
+/// Logs the block cache and read-rate limit counters, and the unreadable ranges of the
+/// evidence, when the run ends, whichever subcommand returned.
+struct CacheReport;
+
+impl Drop for CacheReport {
+    fn drop(&mut self) {
+        let bad_ranges = tolerant::bad_ranges();
+        if !bad_ranges.is_empty() {
+            warn!(
+                "{} unreadable range(s) of the evidence zero-filled, {} in total",
+                bad_ranges.len(),
+                HumanBytes(bad_ranges.iter().map(|&(_, length)| length).sum())
+            );
+        }
+        if let Some(rate) = throttle::rate() {
+            debug!(
+                "Read rate limited to {}/s: {:.1}s spent waiting",
+                HumanBytes(rate),
+                throttle::waited().as_secs_f64()
+            );
+        }
+        let stats = cache::stats();
+        if stats.hits + stats.misses > 0 {
+            debug!(
+                "Block cache: {} hits, {} misses ({:.1}% hit rate), {} evictions, {} large reads bypassed",
+                stats.hits,
+                stats.misses,
+                stats.hit_rate(),
+                stats.evictions,
+                stats.bypassed
+            );
+        }
+    }
+}
+
+/// Where the `--body` filesystem was opened from.
+struct Evidence<'a> {
+    path: &'a str,
+    format: &'a str,
+    offset: Option<&'a u64>,
+    size: Option<&'a u64>,
+    keys: Option<KeyMaterial>,
+    snapshot: Option<&'a str>,
+    folder: FolderOptions,
+    fstype: FsType,
+    /// `--sector-size`.
+    sector_size: Option<u64>,
+    /// `--apfs-prefix`.
+    apfs_prefixes: &'a VolumePrefixes,
+    /// `--zfs-dataset`.
+    zfs_dataset: Option<&'a str>,
+    /// `--ubi-volume`.
+    ubi_volume: Option<&'a str>,
+    /// `--layer`, stacked over the body from the lowest.
+    layers: &'a [String],
+}
+
+/// Handle the `diff` subcommand: compare the `--body` filesystem (baseline) against
+/// the `--against` one (or another point in time of the same evidence) and report
+/// added, removed, modified and renamed paths, or their timeline.
+fn run_diff(
+    baseline: &mut DetectedFs<ImageStream>,
+    evidence: Evidence,
+    matches: &ArgMatches,
+    settings: &Defaults,
+) {
+    let against_snapshot = matches
+        .get_one::<String>("against_snapshot")
+        .map(String::as_str);
+    let opened = match matches.get_one::<String>("against") {
+        Some(against_path) => open_filesystem(&Evidence {
+            path: against_path,
+            format: matches
+                .get_one::<String>("against_format")
+                .map_or("auto", String::as_str),
+            offset: matches.get_one::<u64>("against_offset"),
+            size: matches.get_one::<u64>("against_size"),
+            keys: None,
+            snapshot: against_snapshot,
+            folder: evidence.folder,
+            fstype: FsType::Auto,
+            sector_size: None,
+            apfs_prefixes: evidence.apfs_prefixes,
+            zfs_dataset: None,
+            ubi_volume: None,
+            layers: &[],
+        }),
+        None if against_snapshot == evidence.snapshot => Err(
+            "nothing to compare: give --against, or a --against-snapshot other than the baseline"
+                .into(),
+        ),
+        None => open_filesystem(&Evidence {
+            snapshot: against_snapshot,
+            ..evidence
+        }),
+    };
+    let mut against = match opened {
+        Ok(fs) => fs,
+        Err(e) => {
+            error!("Could not detect the filesystem to compare against: {e:?}");
+            return;
+        }
+    };
+
+    let algorithms = match matches
+        .get_one::<String>("hash")
+        .map(|h| parse_hash_list(h))
+        .transpose()
+    {
+        Ok(algs) => algs.unwrap_or_default(),
+        Err(e) => {
+            error!("Invalid --hash value: {}", e);
+            return;
+        }
+    };
+    let ignore: Vec<String> = matches
+        .get_one::<String>("ignore")
+        .map(|i| i.split(',').map(|f| f.trim().to_string()).collect())
+        .unwrap_or_default();
+
+    info!(
+        "Comparing '{}' against '{}'",
+        baseline.filesystem_type(),
+        against.filesystem_type()
+    );
+    let as_timeline = matches.get_flag("timeline");
+    let changes = match diff_filesystems(baseline, &mut against, &algorithms, &ignore) {
+        Ok(changes) if as_timeline || matches.get_flag("renames") => {
+            detect_renames(changes, &ignore)
+        }
+        Ok(changes) => changes,
+        Err(e) => {
+            error!("Diff failed: {}", e);
+            return;
+        }
+    };
+
+    let output = match matches
+        .get_one::<String>("output")
+        .map(|p| settings.output_path(p))
+        .transpose()
+    {
+        Ok(output) => output,
+        Err(e) => {
+            error!("{}", e);
+            return;
+        }
+    };
+    let mut out: Box<dyn Write> = match output {
+        Some(path) => match StdFile::create(&path) {
+            Ok(f) => Box::new(BufWriter::new(f)),
+            Err(e) => {
+                error!("Could not create output file '{}': {}", path, e);
+                return;
+            }
+        },
+        None => Box::new(io::stdout().lock()),
+    };
+    let events = as_timeline.then(|| timeline(&changes));
+    let result = match (
+        matches.get_one::<String>("output_format").unwrap().as_str(),
+        &events,
+    ) {
+        ("csv", Some(events)) => writeln!(out, "{}", TIMELINE_CSV_HEADER).and_then(|_| {
+            events
+                .iter()
+                .try_for_each(|e| writeln!(out, "{}", timeline_csv_line(e)))
+        }),
+        ("csv", None) => writeln!(out, "{}", DIFF_CSV_HEADER).and_then(|_| {
+            changes
+                .iter()
+                .try_for_each(|c| writeln!(out, "{}", diff_csv_line(c)))
+        }),
+        (_, Some(events)) => serde_json::to_writer_pretty(&mut out, events)
+            .map_err(io::Error::from)
+            .and_then(|_| writeln!(out)),
+        (_, None) => serde_json::to_writer_pretty(&mut out, &changes)
+            .map_err(io::Error::from)
+            .and_then(|_| writeln!(out)),
+    };
+    if let Err(e) = result.and_then(|_| out.flush()) {
+        error!("Could not write the diff report: {}", e);
+        return;
+    }
+
+    let count = |kind: ChangeKind| changes.iter().filter(|c| c.change == kind).count();
+    info!(
+        "{} added, {} removed, {} modified, {} renamed",
+        count(ChangeKind::Added),
+        count(ChangeKind::Removed),
+        count(ChangeKind::Modified),
+        count(ChangeKind::Renamed)
+    );
+}
+
+/// Handle `blk cat` / `blk stat`: raw block content and allocation status.
+fn run_blk(
+    filesystem: &mut DetectedFs<ImageStream>,
+    stream: ImageStream,
+    matches: &ArgMatches,
+) -> Result<(), Box<dyn Error>> {
+    let mut device = BlockDevice::new(stream, filesystem.block_size())?;
+    match matches.subcommand() {
+        Some(("cat", sub)) => {
+            let addr = *sub.get_one::<u64>("addr").unwrap();
+            let count = *sub.get_one::<u64>("count").unwrap();
+            let data = device.read_blocks(addr, count)?;
+            let mut out = io::stdout().lock();
+            if sub.get_flag("hexdump") {
+                hexdump(
+                    &mut data.as_slice(),
+                    &mut out,
+                    addr * device.block_size(),
+                    None,
+                )?;
+            } else {
+                out.write_all(&data)?;
+            }
+            out.flush()?;
+        }
+        Some(("stat", sub)) => {
+            let addr = *sub.get_one::<u64>("addr").unwrap();
+            if addr >= device.block_count() {
+                return Err(format!(
+                    "block {} is out of range (the partition has {} blocks)",
+                    addr,
+                    device.block_count()
+                )
+                .into());
+            }
+            let allocation = match filesystem.block_allocation(addr)? {
+                Some(allocated) => Some(allocated),
+                None => device.bitmap_allocation(addr)?,
+            };
+            println!("Block: {}", addr);
+            println!("Block size: {}", device.block_size());
+            println!("Offset: 0x{:x}", addr * device.block_size());
+            println!(
+                "{}",
+                match allocation {
+                    Some(true) => "Allocated",
+                    Some(false) => "Not Allocated",
+                    None => "Allocation status unknown",
+                }
+            );
+        }
+        _ => unreachable!("blk requires a subcommand"),
+    }
+    Ok(())
+}
+
+/// Handle the `recover` subcommand: rebuild one record, or every deleted regular file.
+fn run_recover(
+    filesystem: &mut DetectedFs<ImageStream>,
+    matches: &ArgMatches,
+    algorithms: &[HashAlgorithm],
+    settings: &Defaults,
+) -> Result<(), Box<dyn Error>> {
+    let out_dir = settings.output_path(matches.get_one::<String>("out").unwrap())?;
+    let files = match matches.get_one::<u64>("record") {
+        Some(&id) => {
+            let path = find_names(filesystem, id)?
+                .into_iter()
+                .next()
+                .unwrap_or_else(|| format!("record_{}", id));
+            let record = filesystem.get_file(id)?;
+            if filesystem.is_deleted(&record) == Some(false) {
+                warn!(
+                    "Record {} is not deleted, recovering its current content",
+                    id
+                );
+            }
+            vec![filesystem.record_to_file(&record, id, &path)]
+        }
+        None => {
+            let mut deleted = filesystem.enumerate_deleted_files(&mut |msg| info!("{}", msg))?;
+            deleted.retain(|f| f.ftype.eq_ignore_ascii_case("file"));
+            info!("{} deleted regular files found", deleted.len());
+            deleted
+        }
+    };
+    let report = recover_files(
+        filesystem,
+        &files,
+        Path::new(&out_dir),
+        algorithms,
+        &mut |msg| info!("{}", msg),
+    )?;
+    let sidecar = Path::new(&out_dir).join("recovered.json");
+    std::fs::write(&sidecar, serde_json::to_string_pretty(&report)?)
+        .map_err(|e| format!("could not write '{}': {}", sidecar.display(), e))?;
+    info!(
+        "{} records recovered into {}: {} full, {} partial, {} unrecoverable",
+        report.records, out_dir, report.full, report.partial, report.unrecoverable
+    );
+    Ok(())
+}
+
+/// Handle the `carve` subcommand: scan the selected space for file signatures.
+fn run_carve(
+    filesystem: &mut DetectedFs<ImageStream>,
+    stream: ImageStream,
+    matches: &ArgMatches,
+    exclude: Option<&ExcludeSet>,
+    algorithms: &[HashAlgorithm],
+    settings: &Defaults,
+    partition_offset: u64,
+) -> Result<(), Box<dyn Error>> {
+    let source = *matches.get_one::<CarveSource>("source").unwrap();
+    let types = parse_carve_types(matches.get_one::<String>("types").unwrap())?;
+    let out_dir = settings.output_path(matches.get_one::<String>("out").unwrap())?;
+    let mut device = BlockDevice::new(stream, filesystem.block_size())?;
+    let regions = match source {
+        CarveSource::Unallocated => unallocated_regions(filesystem, &mut device)?,
+        CarveSource::Slack => slack_regions(filesystem, exclude)?,
+        CarveSource::All => vec![Region {
+            offset: 0,
+            length: device.len(),
+            record: None,
+        }],
+    };
+    info!(
+        "Scanning {} {} regions ({} bytes)",
+        regions.len(),
+        source,
+        regions.iter().map(|r| r.length).sum::<u64>()
+    );
+    let options = CarveOptions {
+        source,
+        types: &types,
+        out_dir: Path::new(&out_dir),
+        partition_offset,
+        algorithms,
+    };
+    let report = carve(&mut device, &regions, &options, &mut |msg| info!("{}", msg))?;
+    let sidecar = Path::new(&out_dir).join("carved.json");
+    std::fs::write(&sidecar, serde_json::to_string_pretty(&report)?)
+        .map_err(|e| format!("could not write '{}': {}", sidecar.display(), e))?;
+    info!(
+        "{} files carved into {} ({} headers without an end)",
+        report.files.len(),
+        out_dir,
+        report.unterminated
+    );
+    Ok(())
+}
+
+/// Handle the `du` subcommand: recursive logical and allocated sizes per directory.
+fn run_du(
+    filesystem: &mut DetectedFs<ImageStream>,
+    matches: &ArgMatches,
+) -> Result<(), Box<dyn Error>> {
+    let separator = filesystem.path_separator();
+    let path = matches
+        .get_one::<String>("path")
+        .cloned()
+        .unwrap_or(separator);
+    let dir_id = find_path_id(filesystem, &path)?;
+    let depth = matches.get_one::<usize>("depth").copied();
+    let usage = disk_usage(filesystem, Some(dir_id), &path, depth)?;
+
+    if matches.get_flag("json") {
+        println!("{}", serde_json::to_string_pretty(&usage)?);
+        return Ok(());
+    }
+    let human = matches.get_flag("human");
+    let size = |bytes: u64| {
+        if human {
+            HumanBytes(bytes).to_string()
+        } else {
+            bytes.to_string()
+        }
+    };
+    println!("{:>12} {:>12} {:>8} PATH", "ALLOCATED", "LOGICAL", "FILES");
+    for dir in usage {
+        println!(
+            "{:>12} {:>12} {:>8} {}",
+            size(dir.allocated),
+            size(dir.logical),
+            dir.files,
+            dir.path
+        );
+    }
+    Ok(())
+}
+
+/// Fill the signature fields of `file` from the first bytes of its content.
+fn detect_content_type(file: &mut File, reader: &mut dyn ReadSeek) {
+    match identify_reader(reader) {
+        Ok(Some(signature)) => signature.apply_to(file),
+        Ok(None) => {}
+        Err(e) => warn!("Could not read {}: {}", file.absolute_path, e),
+    }
+}
+
+/// Content pass of the walks exporting records: type detection, metadata extraction and
+/// hashing, as requested on the command line.
+#[derive(Clone, Copy)]
+struct ContentPass<'a> {
+    detect_type: bool,
+    enricher: Option<&'a Enricher>,
+    hash_algorithms: Option<&'a [HashAlgorithm]>,
+}
+
+impl ContentPass<'_> {
+    /// Whether the records' content has to be read at all.
+    fn is_active(&self) -> bool {
+        self.detect_type || self.enricher.is_some() || self.hash_algorithms.is_some()
+    }
+
+    fn analyze(&self, file: &mut File, reader: &mut dyn ReadSeek) {
+        if self.detect_type {
+            detect_content_type(file, reader);
+        }
+        if let Some(enricher) = self.enricher {
+            enricher.enrich(file, reader);
+        }
+        if let Some(algs) = self.hash_algorithms {
+            match hash_reader(reader, algs) {
+                Ok((hashes, _)) => hashes.apply_to(file),
+                Err(e) => warn!("Could not hash {}: {}", file.absolute_path, e),
+            }
+        }
+    }
+}
+
+/// Handle the `find` subcommand: print every record whose name matches a pattern.
+fn run_find(
+    filesystem: &mut DetectedFs<ImageStream>,
+    matches: &ArgMatches,
+    exclude: Option<&ExcludeSet>,
+    content: ContentPass,
+    mismatch_only: bool,
+) -> Result<(), Box<dyn Error>> {
+    let ignore_case = matches.get_flag("ignore_case");
+    let pattern = match matches.get_one::<String>("name") {
+        Some(name) if matches.get_flag("glob") => Some(NamePattern::glob(name, ignore_case)?),
+        Some(name) => Some(NamePattern::regex(name, ignore_case)?),
+        None => None,
+    };
+    let pattern = if matches.get_flag("match_path") {
+        pattern.map(NamePattern::on_path)
+    } else {
+        pattern
+    };
+    let query = matches.get_one::<Query>("where");
+    let format: ExportFormat = matches
+        .get_one::<String>("output_format")
+        .unwrap()
+        .parse()?;
+
+    let mut exporter = Exporter::new(BufWriter::new(io::stdout().lock()), format)?;
+    let mut write_error = None;
+    let mut content_visitor =
+        |file: &mut File, reader: &mut dyn ReadSeek| content.analyze(file, reader);
+    let options = WalkOptions {
+        exclude,
+        visitor: if content.is_active() {
+            Some(&mut content_visitor)
+        } else {
+            None
+        },
+        ..Default::default()
+    };
+    filesystem.walk_fs_with(options, &mut |event| {
+        if let WalkEvent::File(file) = event
+            && write_error.is_none()
+            && pattern.as_ref().is_none_or(|p| p.is_match(&file))
+            && query.is_none_or(|q| q.matches(&file))
+            && (!mismatch_only || file.ext_mismatch == Some(true))
+            && let Err(e) = exporter.write_file(&file)
+        {
+            write_error = Some(e);
+        }
+    })?;
+    if let Some(e) = write_error {
+        return Err(e.into());
+    }
+    info!("{} matching records", exporter.count());
+    exporter.finish()?.flush()?;
+    Ok(())
+}
+
+/// Handle the `index` subcommand: walk the filesystem into a new or existing SQLite index.
+fn run_index(
+    filesystem: &mut DetectedFs<ImageStream>,
+    matches: &ArgMatches,
+    exclude: Option<&ExcludeSet>,
+    content: ContentPass,
+    settings: &Defaults,
+    source: IndexSource,
+) -> Result<(), Box<dyn Error>> {
+    let db = settings.output_path(matches.get_one::<String>("db").unwrap())?;
+    let db = Path::new(&db);
+    if matches.get_flag("force") && db.exists() {
+        std::fs::remove_file(db)?;
+    }
+    let evidence = source.evidence.clone();
+    let mut index = if matches.get_flag("append") {
+        FileIndex::append(db, source)?
+    } else {
+        FileIndex::create(db, source)?
+    };
+    let progress = Progress::new(Some(filesystem.record_count()), ProgressUnit::Records);
+    let mut content_visitor =
+        |file: &mut File, reader: &mut dyn ReadSeek| content.analyze(file, reader);
+    let options = WalkOptions {
+        exclude,
+        visitor: if content.is_active() {
+            Some(&mut content_visitor)
+        } else {
+            None
+        },
+        ..Default::default()
+    };
+    // Records are inserted on another thread while the walk goes on.
+    let inserted = stream_walk(filesystem, options, STREAM_CAPACITY, |events| {
+        for event in events {
+            match event {
+                WalkEvent::File(file) => {
+                    progress.inc(1);
+                    index.add(file).map_err(|e| e.to_string())?;
+                }
+                WalkEvent::Status(msg) => info!("{}", msg),
+            }
+        }
+        Ok::<_, String>(())
+    })?;
+    progress.finish();
+    inserted?;
+    let records = index.finish(&[
+        ("evidence", evidence),
+        ("filesystem", filesystem.filesystem_type()),
+        ("separator", filesystem.path_separator()),
+    ])?;
+    info!("{} records indexed into {}", records, db.display());
+    Ok(())
+}
+
+/// Handle the `query` subcommand: search an index built by `index`.
+fn run_query(matches: &ArgMatches, settings: &Defaults) -> Result<(), Box<dyn Error>> {
+    let db = settings.output_path(matches.get_one::<String>("db").unwrap())?;
+    let index = FileIndex::open(Path::new(&db))?;
+    if matches.get_flag("info") {
+        for (key, value) in index.info()? {
+            println!("{}: {}", key, value);
+        }
+        return Ok(());
+    }
+    let files = index.search(&IndexQuery {
+        text: matches.get_one::<String>("text").map(String::as_str),
+        hash: matches.get_one::<String>("hash").map(String::as_str),
+        sql: matches.get_one::<String>("sql").map(String::as_str),
+        filter: matches.get_one::<Query>("where"),
+        limit: matches.get_one::<usize>("limit").copied(),
+    })?;
+    let format: ExportFormat = matches
+        .get_one::<String>("output_format")
+        .unwrap()
+        .parse()?;
+    let mut exporter = Exporter::new(BufWriter::new(io::stdout().lock()), format)?;
+    for file in &files {
+        exporter.write_file(file)?;
+    }
+    info!("{} matching records", exporter.count());
+    exporter.finish()?.flush()?;
+    Ok(())
+}
+
+/// Handle the `grep` subcommand: stream regular files through a regex or byte pattern.
+fn run_grep(
+    filesystem: &mut DetectedFs<ImageStream>,
+    matches: &ArgMatches,
+    exclude: Option<&ExcludeSet>,
+) -> Result<(), Box<dyn Error>> {
+    let pattern = matches.get_one::<String>("pattern").unwrap();
+    let regex = if matches.get_flag("hex") {
+        hex_regex(pattern)?
+    } else {
+        content_regex(pattern, matches.get_flag("ignore_case"))?
+    };
+    let include = matches
+        .get_one::<String>("include")
+        .map(|g| NamePattern::glob(g, true))
+        .transpose()?;
+    let min_size = matches.get_one::<u64>("min_size").copied().unwrap_or(0);
+    let max_size = matches
+        .get_one::<u64>("max_size")
+        .copied()
+        .unwrap_or(u64::MAX);
+    let max_count = matches
+        .get_one::<u64>("max_count")
+        .copied()
+        .unwrap_or(u64::MAX);
+    let json_output = matches.get_flag("json");
+
+    let mut out = BufWriter::new(io::stdout().lock());
+    let mut files_matched = 0u64;
+    let mut grep_visitor = |file: &mut File, reader: &mut dyn ReadSeek| {
+        if file.size < min_size || file.size > max_size {
+            return;
+        }
+        if let Some(include) = &include
+            && !include.is_match(file)
+        {
+            return;
+        }
+        let mut count = 0u64;
+        let result = grep_reader(reader, &regex, &mut |m| {
+            count += 1;
+            let written = if json_output {
+                writeln!(
+                    out,
+                    "{}",
+                    json!({
+                        "identifier": file.identifier,
+                        "path": file.absolute_path,
+                        "offset": m.offset,
+                        "line": m.line,
+                    })
+                )
+            } else {
+                writeln!(
+                    out,
+                    "[{}] {}:0x{:x}: {}",
+                    file.identifier, file.absolute_path, m.offset, m.line
+                )
+            };
+            written.is_ok() && count < max_count
+        });
+        if let Err(e) = result {
+            warn!("Could not read {}: {}", file.absolute_path, e);
+        }
+        if count > 0 {
+            files_matched += 1;
+        }
+    };
+    filesystem.walk_fs_with(
+        WalkOptions {
+            visitor: Some(&mut grep_visitor),
+            exclude,
+            ..Default::default()
+        },
+        &mut |_| {},
+    )?;
+    out.flush()?;
+    info!("{} files matched", files_matched);
+    Ok(())
+}
+
+/// Handle the `partitions` subcommand: print the partition table and probe every entry
+/// for a supported filesystem.
+fn run_partitions(
+    path: &str,
+    format: &str,
+    forced_sector_size: Option<u64>,
+    matches: &ArgMatches,
+) -> Result<(), Box<dyn Error>> {
+    let mut body = Body::new(path.to_owned(), format);
+    let sector_size = sector_size(&mut body, forced_sector_size);
+    let (scheme, entries) = read_partition_table(&mut body, sector_size)?;
+
+    let mut rows = Vec::with_capacity(entries.len());
+    for entry in &entries {
+        let filesystem = if entry.type_name == "Extended" {
+            None
+        } else {
+            detect_filesystem(&body, entry.start_offset, entry.size, None)
+                .ok()
+                .map(|fs| fs.filesystem_type())
+        };
+        rows.push((entry, filesystem));
+    }
+
+    if matches.get_flag("json") {
+        let json: Vec<Value> = rows
+            .iter()
+            .map(|(entry, filesystem)| {
+                let mut value = serde_json::to_value(entry)?;
+                value["filesystem"] = json!(filesystem);
+                Ok(value)
+            })
+            .collect::<Result<_, serde_json::Error>>()?;
+        println!("{}", serde_json::to_string_pretty(&json)?);
+        return Ok(());
+    }
+
+    println!(
+        "{} partition table, {} byte sectors",
+        format!("{:?}", scheme).to_uppercase(),
+        sector_size
+    );
+    println!(
+        "{:>3}  {:>14}  {:>12}  {:>12}  {:>10}  {:<24}  {:<8}  Name",
+        "#", "Offset", "Start", "Sectors", "Size", "Type", "FS"
+    );
+    for (entry, filesystem) in rows {
+        println!(
+            "{:>3}  {:>14}  {:>12}  {:>12}  {:>10}  {:<24}  {:<8}  {}",
+            entry.index,
+            format!("0x{:x}", entry.start_offset),
+            entry.start_sector,
+            entry.sector_count,
+            HumanBytes(entry.size).to_string(),
+            entry.type_name,
+            filesystem.as_deref().unwrap_or("-"),
+            entry.name.as_deref().unwrap_or("")
+        );
+    }
+    Ok(())
+}
+
+/// Handle the `triage` subcommand: screen every record with the suspicious file heuristics.
+fn run_triage(
+    filesystem: &mut DetectedFs<ImageStream>,
+    matches: &ArgMatches,
+    exclude: Option<&ExcludeSet>,
+) -> Result<(), Box<dyn Error>> {
+    let separator = filesystem.path_separator();
+    let mut triage = Triage::new(TriageOptions {
+        min_severity: *matches.get_one::<Severity>("min_severity").unwrap(),
+        recent_days: *matches.get_one::<u64>("recent_days").unwrap(),
+        gap_days: *matches.get_one::<u64>("gap_days").unwrap(),
+    });
+    let mut detect_visitor =
+        |file: &mut File, reader: &mut dyn ReadSeek| detect_content_type(file, reader);
+    let options = WalkOptions {
+        exclude,
+        visitor: Some(&mut detect_visitor),
+        ..Default::default()
+    };
+    filesystem.walk_fs_with(options, &mut |event| match event {
+        WalkEvent::File(file) => triage.add(&file, &separator),
+        WalkEvent::Status(msg) => info!("{}", msg),
+    })?;
+    let report = triage.report();
+
+    if matches.get_flag("json") {
+        println!("{}", serde_json::to_string_pretty(&report)?);
+        return Ok(());
+    }
+    let mut out = BufWriter::new(io::stdout().lock());
+    for finding in &report.findings {
+        writeln!(
+            out,
+            "{:<6} {:<20} [{}] {} - {}",
+            finding.severity.to_string().to_uppercase(),
+            finding.rule,
+            finding.identifier,
+            finding.path,
+            finding.detail
+        )?;
+    }
+    out.flush()?;
+    info!(
+        "{} high, {} medium, {} low findings",
+        report.high, report.medium, report.low
+    );
+    Ok(())
+}
+
+/// Handle `--verify-content`: hash every regular file and write the coverage report as
+/// JSON to `output` (or STDOUT).
+fn run_verify(
+    filesystem: &mut DetectedFs<ImageStream>,
+    algorithms: &[HashAlgorithm],
+    exclude: Option<&ExcludeSet>,
+    output: Option<&str>,
+) -> Result<(), Box<dyn Error>> {
+    let report = verify_content(filesystem, algorithms, exclude, &mut |msg| info!("{}", msg))?;
+    let json = serde_json::to_string_pretty(&report)?;
+    match output {
+        Some(path) => std::fs::write(path, json)
+            .map_err(|e| format!("could not write the report '{}': {}", path, e))?,
+        None => println!("{}", json),
+    }
+    info!(
+        "{} of {} files readable, {} of {} bytes read ({:.2}% coverage)",
+        report.readable, report.files, report.bytes_read, report.bytes_expected, report.coverage
+    );
+    for failure in &report.failures {
+        warn!(
+            "Unreadable record {} ({}): {}",
+            failure.identifier, failure.path, failure.reason
+        );
+    }
+    Ok(())
+}
+
+/// Handle the `dedupe` subcommand: list the groups of files sharing the same content.
+fn run_dedupe(
+    filesystem: &mut DetectedFs<ImageStream>,
+    matches: &ArgMatches,
+    algorithms: &[HashAlgorithm],
+    exclude: Option<&ExcludeSet>,
+) -> Result<(), Box<dyn Error>> {
+    let min_size = matches.get_one::<u64>("min_size").copied().unwrap_or(0);
+    let clusters = find_duplicates(filesystem, algorithms, min_size, exclude, &mut |msg| {
+        info!("{}", msg)
+    })?;
+
+    if matches.get_flag("json") {
+        println!("{}", serde_json::to_string_pretty(&clusters)?);
+        return Ok(());
+    }
+    let human = matches.get_flag("human");
+    let size = |bytes: u64| {
+        if human {
+            HumanBytes(bytes).to_string()
+        } else {
+            bytes.to_string()
+        }
+    };
+    let mut out = BufWriter::new(io::stdout().lock());
+    for cluster in &clusters {
+        let digest = [
+            ("sha256", &cluster.hashes.sha256),
+            ("sha1", &cluster.hashes.sha1),
+            ("md5", &cluster.hashes.md5),
+        ]
+        .into_iter()
+        .find_map(|(name, d)| d.as_ref().map(|d| format!("{}:{}", name, d)))
+        .unwrap_or_default();
+        writeln!(
+            out,
+            "{} copies of {} ({} redundant) {}",
+            cluster.files.len(),
+            size(cluster.size),
+            size(cluster.wasted()),
+            digest
+        )?;
+        for file in &cluster.files {
+            writeln!(out, "  [{}] {}", file.identifier, file.path)?;
+        }
+    }
+    out.flush()?;
+    info!(
+        "{} duplicate clusters, {} redundant",
+        clusters.len(),
+        HumanBytes(clusters.iter().map(|c| c.wasted()).sum())
+    );
+    Ok(())
+}
+
+/// Handle the `collect` subcommand: export every file matching the YAML targets.
+fn run_collect(
+    filesystem: &mut DetectedFs<ImageStream>,
+    matches: &ArgMatches,
+    algorithms: &[HashAlgorithm],
+    exclude: Option<&ExcludeSet>,
+    settings: &Defaults,
+    custody: Option<&mut CustodyManifest>,
+) -> Result<(), Box<dyn Error>> {
+    let targets = TargetSet::load(matches.get_one::<String>("targets").unwrap())?;
+    info!("{} targets loaded", targets.targets().len());
+    let (sink, destination) = match matches.get_one::<String>("archive") {
+        Some(archive) => {
+            let archive = settings.output_path(archive)?;
+            (CollectSink::tar(Path::new(&archive))?, archive)
+        }
+        None => {
+            let dir = settings.output_path(matches.get_one::<String>("output_dir").unwrap())?;
+            (CollectSink::directory(Path::new(&dir))?, dir)
+        }
+    };
+    let collected = collect(
+        filesystem,
+        &targets,
+        sink,
+        algorithms,
+        exclude,
+        &mut |msg| info!("{}", msg),
+    )?;
+    let failed = collected.iter().filter(|c| c.error.is_some()).count();
+    for target in targets.targets() {
+        let hits = collected
+            .iter()
+            .filter(|c| c.targets.contains(&target.name))
+            .count();
+        info!("{}: {} files", target.name, hits);
+    }
+    info!(
+        "{} files collected, {} failed",
+        collected.len() - failed,
+        failed
+    );
+
+    if let Some(manifest) = custody {
+        let partition_offset = manifest.evidence.partition_offset;
+        let extracted_at = custody_now();
+        for c in collected {
+            let runs = match filesystem.get_file(c.identifier) {
+                Ok(record) => byte_runs(filesystem, &record, c.size, partition_offset),
+                Err(_) => None,
+            };
+            manifest.entries.push(CustodyEntry {
+                identifier: c.identifier,
+                source_path: Some(c.source_path),
+                output: format!("{}/{}", destination, c.output_path),
+                size: c.size,
+                hashes: c.hashes,
+                byte_runs: runs,
+                extracted_at: extracted_at.clone(),
+                error: c.error,
+            });
+        }
+    }
+    Ok(())
+}
 
 fn main() {
-    let matches = Command::new("exhume_filesystem")
+    let command = Command::new("exhume_filesystem")
         .version(crate_version!())
         .author(crate_authors!())
         .about("Exhume in a standardized and normalized way files & directories from a given filesystem.")
@@ -20,8 +1453,7 @@ fn main() {
                 .short('b')
                 .long("body")
                 .value_parser(value_parser!(String))
-                .required(true)
-                .help("The path to the body to exhume."),
+                .help("The path to the body to exhume (required unless querying an index or running a batch)."),
         )
         .arg(
             Arg::new("format")
@@ -52,8 +1484,8 @@ fn main() {
             Arg::new("record")
                 .short('r')
                 .long("record")
-                .value_parser(maybe_hex::<usize>)
-                .help("Display the metadata about a specific file record using its record identifier."),
+                .value_parser(value_parser!(String))
+                .help("Display the metadata about file records: identifiers (42, 0x2A, mft:42, or volume:inode like 3:257 for APFS), ranges (100-200), paths, or @file / @- lists, comma separated."),
         )
         .arg(
             Arg::new("fvek")
@@ -61,6 +1493,12 @@ fn main() {
                 .value_parser(value_parser!(String))
                 .help("Full Volume Encryption Key (FVEK) for BitLocker, in hex format"),
         )
+        .arg(
+            Arg::new("snapshot")
+                .long("snapshot")
+                .value_parser(value_parser!(String))
+                .help("Operate on a volume shadow copy instead of the live volume: its index in 'snapshots' (1 is the oldest) or its store identifier."),
+        )
         .arg(
             Arg::new("enum")
                 .short('e')
@@ -93,32 +1531,915 @@ fn main() {
                 .requires("record")
                 .help("If --record is specified, print the content of the record to STDOUT."),
         )
-
-        .arg(
-            Arg::new("metadata")
-                .long("metadata")
-                .action(ArgAction::SetTrue)
-                .help("Print the filsystem metadata (JSON)."),
+
+        .arg(
+            Arg::new("hexdump")
+                .long("hexdump")
+                .action(ArgAction::SetTrue)
+                .requires("record")
+                .help("If --record is specified, display its content as an xxd-style hexdump."),
+        )
+        .arg(
+            Arg::new("content_offset")
+                .long("content-offset")
+                .value_parser(maybe_hex::<u64>)
+                .default_value("0")
+                .help("Start offset inside the record content for --hexdump and --strings (decimal or hex)."),
+        )
+        .arg(
+            Arg::new("length")
+                .long("length")
+                .value_parser(maybe_hex::<u64>)
+                .help("Number of content bytes processed by --hexdump and --strings (defaults to the end of the record)."),
+        )
+        .arg(
+            Arg::new("strings")
+                .long("strings")
+                .action(ArgAction::SetTrue)
+                .requires("record")
+                .help("If --record is specified, print the printable strings of its content with their offsets."),
+        )
+        .arg(
+            Arg::new("min_len")
+                .long("min-len")
+                .value_parser(value_parser!(usize))
+                .default_value("4")
+                .help("Minimum length of the strings reported by --strings."),
+        )
+        .arg(
+            Arg::new("encoding")
+                .long("encoding")
+                .value_parser(["ascii", "utf16le", "both"])
+                .default_value("ascii")
+                .help("Encodings searched by --strings."),
+        )
+        .arg(
+            Arg::new("metadata")
+                .long("metadata")
+                .action(ArgAction::SetTrue)
+                .help("Print the filsystem metadata (JSON)."),
+        )
+        .arg(
+            Arg::new("json")
+                .short('j')
+                .long("json")
+                .action(ArgAction::SetTrue)
+                .help("Output the result in a JSON format."),
+        )
+        .arg(
+            Arg::new("output_format")
+                .long("output-format")
+                .value_parser(["text", "json", "csv", "bodyfile"])
+                .help("Export format used by --enum: text, json, csv or bodyfile (defaults to text, or json with --json)."),
+        )
+        .arg(
+            Arg::new("output")
+                .long("output")
+                .value_parser(value_parser!(String))
+                .help("Write the --enum export or the --verify-content report into this file instead of STDOUT."),
+        )
+        .arg(
+            Arg::new("verify_content")
+                .long("verify-content")
+                .action(ArgAction::SetTrue)
+                .conflicts_with_all(["enum", "record", "dump", "list"])
+                .help("Read and hash every regular file without exporting anything, and report the coverage (readable files, bytes read, unreadable records with reasons)."),
+        )
+        .arg(
+            Arg::new("hash")
+                .long("hash")
+                .value_parser(value_parser!(String))
+                .help("Compute content digests (comma separated: md5,sha1,sha256) for --enum, --record, --dump, --verify-content, collect, dedupe and carve (sha256 by default)."),
+        )
+        .arg(
+            Arg::new("hash_threads")
+                .long("hash-threads")
+                .value_parser(value_parser!(usize))
+                .default_value("4")
+                .help("Threads digesting content while --enum reads the next records (1 hashes on the walking thread)."),
+        )
+        .arg(
+            Arg::new("detect_type")
+                .long("detect-type")
+                .action(ArgAction::SetTrue)
+                .help("Identify the content type of files from their magic bytes (detected_type field of --enum and find)."),
+        )
+        .arg(
+            Arg::new("extract_metadata")
+                .long("extract-metadata")
+                .value_parser(value_parser!(String))
+                .num_args(0..=1)
+                .default_missing_value("all")
+                .help("Merge metadata embedded in the content (comma separated: exif,pe,ole,ooxml; all by default) under 'embedded' in the metadata of --enum, find and index records."),
+        )
+        .arg(
+            Arg::new("mismatch_only")
+                .long("mismatch-only")
+                .action(ArgAction::SetTrue)
+                .conflicts_with_all(["checkpoint", "resume"])
+                .help("Only list files whose extension contradicts their detected content type (implies --detect-type)."),
+        )
+        .arg(
+            Arg::new("custody")
+                .long("custody")
+                .value_parser(value_parser!(String))
+                .help("Write a chain-of-custody manifest (evidence source, byte runs, hashes, tool version) for --dump or collect, one of which it requires."),
+        )
+        .arg(
+            Arg::new("sign_key")
+                .long("sign-key")
+                .value_parser(value_parser!(String))
+                .requires("custody")
+                .help("Sign the custody manifest with this ed25519 key (32 byte seed, raw or hex); the signature is written to <manifest>.sig."),
+        )
+        .arg(
+            Arg::new("where")
+                .long("where")
+                .value_parser(value_parser!(Query))
+                .requires("enum")
+                .conflicts_with_all(["checkpoint", "resume"])
+                .help("Only export the records matching an expression, e.g. 'size > 1M && mtime > 2024-01-01 && name ~ \"\\.ps1$\" && type == file'."),
+        )
+        .arg(
+            Arg::new("checkpoint")
+                .long("checkpoint")
+                .value_parser(value_parser!(String))
+                .requires("enum")
+                .requires("output")
+                .help("Periodically save the --enum progress into this checkpoint file."),
+        )
+        .arg(
+            Arg::new("checkpoint_interval")
+                .long("checkpoint-interval")
+                .value_parser(value_parser!(u64))
+                .default_value("10000")
+                .help("Number of records between two checkpoints."),
+        )
+        .arg(
+            Arg::new("resume")
+                .long("resume")
+                .value_parser(value_parser!(String))
+                .requires("enum")
+                .requires("output")
+                .help("Resume an interrupted --enum from a checkpoint file, appending to --output."),
+        )
+        .arg(
+            Arg::new("all_names")
+                .long("all-names")
+                .action(ArgAction::SetTrue)
+                .requires("enum")
+                .conflicts_with("threads")
+                .help("List records with several hard links once per link, instead of once under their primary name (NTFS) or the first path walked to them."),
+        )
+        .arg(
+            Arg::new("sorted")
+                .long("sorted")
+                .action(ArgAction::SetTrue)
+                .requires("enum")
+                .conflicts_with_all(["threads", "all_names"])
+                .help("Walk the entries of each directory of --enum in name order (byte order of the UTF-8 names: case-sensitive, independent of the locale), so listings can be compared from one run to the next. NTFS volumes are then walked along their directory tree instead of $MFT order."),
+        )
+        .arg(
+            Arg::new("threads")
+                .long("threads")
+                .value_parser(value_parser!(usize))
+                .requires("enum")
+                .conflicts_with_all(["checkpoint", "resume"])
+                .help("Walk independent subtrees of --enum on this many threads, each with its own handle on the evidence (records are then listed subtree by subtree)."),
+        )
+        .arg(
+            Arg::new("sector_size")
+                .long("sector-size")
+                .value_parser(
+                    PossibleValuesParser::new(["512", "1024", "2048", "4096"])
+                        .map(|s| s.parse::<u64>().unwrap()),
+                )
+                .help("Sector size --size and partition tables count in, instead of the one implied by the partition table or recorded by the image format (raw images of 4K native disks record none)."),
+        )
+        .arg(
+            Arg::new("apfs_prefix")
+                .long("apfs-prefix")
+                .value_name("ROLE=PREFIX")
+                .action(ArgAction::Append)
+                .help("Place the root of the APFS volumes with this role (system, data, preboot, ...) or fs_index at PREFIX instead of /volume_N, e.g. data=/ ; 'macos' merges the System and Data volumes at / like a live macOS. Repeatable."),
+        )
+        .arg(
+            Arg::new("zfs_dataset")
+                .long("zfs-dataset")
+                .value_name("NAME")
+                .value_parser(value_parser!(String))
+                .help("Browse this dataset or snapshot of a ZFS pool (pool/fs, pool/fs@snap) instead of its root dataset. The metadata lists them all."),
+        )
+        .arg(
+            Arg::new("ubi_volume")
+                .long("ubi-volume")
+                .value_name("NAME")
+                .value_parser(value_parser!(String))
+                .help("Browse this volume of a UBI image instead of the first one holding UBIFS. The metadata lists them all."),
+        )
+        .arg(
+            Arg::new("layer")
+                .long("layer")
+                .value_name("PATH")
+                .value_parser(value_parser!(String))
+                .action(ArgAction::Append)
+                .help("Stack this layer over --body and browse the merged view, as OverlayFS and Docker do (whiteouts and opaque directories hide lower entries): a folder, an uncompressed layer tarball or a filesystem image. Repeatable, from the lowest; the last is the upper layer."),
+        )
+        .arg(
+            Arg::new("fstype")
+                .long("fstype")
+                .value_parser(["auto", "ext", "ntfs", "apfs", "exfat", "zfs", "squashfs", "udf", "f2fs", "ufs", "refs", "hfs", "ubifs", "yaffs2", "cramfs"])
+                .default_value("auto")
+                .help("Open the partition as this filesystem instead of detecting it, for when detection picks the wrong one (e.g. an ext volume over a stale NTFS boot sector)."),
+        )
+        .arg(
+            Arg::new("follow_symlinks")
+                .long("follow-symlinks")
+                .action(ArgAction::SetTrue)
+                .help("When the body is a folder, describe and descend into the targets of symbolic links instead of the links (their targets are recorded either way)."),
+        )
+        .arg(
+            Arg::new("one_file_system")
+                .long("one-file-system")
+                .action(ArgAction::SetTrue)
+                .help("When the body is a folder, do not list directories on another device than the folder (mount points)."),
+        )
+        .arg(
+            Arg::new("timezone")
+                .long("timezone")
+                .allow_hyphen_values(true)
+                .value_parser(value_parser!(String))
+                .help("Time zone of displayed timestamps: IANA name (Europe/Paris), UTC, local or an offset (+02:00). Exports stay in UTC."),
+        )
+        .arg(
+            Arg::new("fat_time")
+                .long("fat-time")
+                .allow_hyphen_values(true)
+                .value_parser(value_parser!(String))
+                .help("How FAT/exFAT timestamps, stored as local time, are converted to UTC: entry-offset (the offset recorded with each timestamp, UTC without one; default), utc, or the time zone of the system (Europe/Paris, local, +02:00). The choice is recorded in the metadata of every record."),
+        )
+        .arg(
+            Arg::new("name_rendering")
+                .long("name-rendering")
+                .value_parser(["escape", "replace"])
+                .help("How names that are not valid UTF-8 appear in records: escape (invalid bytes as \\xNN, backslashes as \\x5c; default), which paths given back to lookups may use, or replace (U+FFFD). The raw bytes are kept in 'raw_name' either way."),
+        )
+        .arg(
+            Arg::new("time_format")
+                .long("time-format")
+                .value_parser(value_parser!(String))
+                .help("strftime format of displayed timestamps (defaults to '%Y-%m-%d %H:%M:%S (%Z)')."),
+        )
+        .arg(
+            Arg::new("config")
+                .long("config")
+                .value_parser(value_parser!(String))
+                .help("Read CLI defaults from this TOML file instead of ~/.config/exhume/config.toml."),
+        )
+        .arg(
+            Arg::new("case")
+                .long("case")
+                .value_parser(value_parser!(String))
+                .help("Apply the [cases.<name>] section of the config file (output directory, hashes, excludes)."),
+        )
+        .arg(
+            Arg::new("exclude")
+                .long("exclude")
+                .value_parser(value_parser!(String))
+                .action(ArgAction::Append)
+                .help("Skip paths matching this glob (and their subtrees) when walking. Can be repeated."),
+        )
+        .arg(
+            Arg::new("log_level")
+                .short('l')
+                .long("log-level")
+                .value_parser(["error", "warn", "info", "debug", "trace"])
+                .default_value("info")
+                .help("Set the log verbosity level"),
+        )
+        .arg(
+            Arg::new("log_file")
+                .long("log-file")
+                .value_parser(value_parser!(String))
+                .help("Append the logs to this file instead of STDERR; results stay on STDOUT."),
+        )
+        .arg(
+            Arg::new("audit_log")
+                .long("audit-log")
+                .value_parser(value_parser!(String))
+                .help("Append every read performed against the evidence (operation, record, offset, length, timestamp, SHA-256 of the result) to this JSON lines file."),
+        )
+        .arg(
+            Arg::new("cache_size")
+                .long("cache-size")
+                .value_parser(parse_size)
+                .default_value("32M")
+                .help("Memory given to the cache of filesystem metadata blocks (e.g. '256M'); 0 disables it."),
+        )
+        .arg(
+            Arg::new("read_buffer")
+                .long("read-buffer")
+                .value_parser(parse_size)
+                .default_value("64K")
+                .help("Size of the window small reads of the evidence are coalesced into (e.g. '256K'); 0 disables it."),
+        )
+        .arg(
+            Arg::new("max_read_rate")
+                .long("max-read-rate")
+                .value_parser(parse_size)
+                .help("Maximum amount of evidence read per second (e.g. '50M'), to share network-attached evidence with other examiners; cached reads do not count."),
+        )
+        .arg(
+            Arg::new("read_retries")
+                .long("read-retries")
+                .value_parser(value_parser!(u32))
+                .help("Retry failed reads of damaged evidence this many times, then zero-fill the sectors that still cannot be read and record them under 'common.bad_ranges' of the affected records instead of failing."),
+        )
+        .arg(
+            Arg::new("memory_limit")
+                .long("memory-limit")
+                .value_parser(parse_size)
+                .help("Memory budget for caches, walk state and buffers (e.g. '1G'); the records a walk has visited or queued beyond their share spill to temporary files."),
+        )
+        .arg(
+            Arg::new("log_format")
+                .long("log-format")
+                .value_parser(["text", "json"])
+                .default_value("text")
+                .help("Log line format: text, or json for one object per line."),
+        )
+        .subcommand(
+            Command::new("diff")
+                .about("Compare the --body filesystem (baseline) with another image, folder or snapshot.")
+                .arg(
+                    Arg::new("against")
+                        .long("against")
+                        .value_parser(value_parser!(String))
+                        .help("The path to the body (or folder) to compare against the baseline (defaults to the --body evidence, with --against-snapshot)."),
+                )
+                .arg(
+                    Arg::new("against_snapshot")
+                        .long("against-snapshot")
+                        .value_parser(value_parser!(String))
+                        .help("Compare against this shadow copy of the compared evidence (see 'snapshots')."),
+                )
+                .arg(
+                    Arg::new("renames")
+                        .long("renames")
+                        .action(ArgAction::SetTrue)
+                        .help("Report a removed and an added path holding the same record or content as a rename."),
+                )
+                .arg(
+                    Arg::new("timeline")
+                        .long("timeline")
+                        .action(ArgAction::SetTrue)
+                        .help("Output the changes (renames included) as a chronological timeline."),
+                )
+                .arg(
+                    Arg::new("against_format")
+                        .long("against-format")
+                        .value_parser(value_parser!(String))
+                        .help("The format of the compared body, either 'raw' or 'ewf'."),
+                )
+                .arg(
+                    Arg::new("against_offset")
+                        .long("against-offset")
+                        .value_parser(maybe_hex::<u64>)
+                        .help("The compared filesystem starts address (decimal or hex)."),
+                )
+                .arg(
+                    Arg::new("against_size")
+                        .long("against-size")
+                        .value_parser(maybe_hex::<u64>)
+                        .help("The size of the compared filesystem in sectors (decimal or hex)."),
+                )
+                .arg(
+                    Arg::new("hash")
+                        .long("hash")
+                        .value_parser(value_parser!(String))
+                        .help("Also compare content digests, e.g. 'sha256' or 'md5,sha1'."),
+                )
+                .arg(
+                    Arg::new("ignore")
+                        .long("ignore")
+                        .value_parser(value_parser!(String))
+                        .help("Comma separated fields that do not count as a modification (e.g. 'accessed')."),
+                )
+                .arg(
+                    Arg::new("output_format")
+                        .long("output-format")
+                        .value_parser(["json", "csv"])
+                        .default_value("json")
+                        .help("Format of the diff report."),
+                )
+                .arg(
+                    Arg::new("output")
+                        .long("output")
+                        .value_parser(value_parser!(String))
+                        .help("Write the diff report into this file instead of STDOUT."),
+                ),
+        )
+        .subcommand(
+            Command::new("fls")
+                .about("List directory entries like The Sleuth Kit 'fls'.")
+                .arg(
+                    Arg::new("inode")
+                        .value_parser(parse_record_id)
+                        .help("Directory record to list (defaults to the root)."),
+                )
+                .arg(Arg::new("recursive").short('r').action(ArgAction::SetTrue).help("Recurse into directories."))
+                .arg(Arg::new("full_path").short('p').action(ArgAction::SetTrue).help("Display full paths."))
+                .arg(Arg::new("long").short('l').action(ArgAction::SetTrue).help("Long listing with times, size and ownership."))
+                .arg(
+                    Arg::new("mactime")
+                        .short('m')
+                        .value_parser(value_parser!(String))
+                        .help("Body file output, with paths prefixed by this mount point."),
+                )
+                .arg(Arg::new("dirs_only").short('D').action(ArgAction::SetTrue).help("Display directories only."))
+                .arg(Arg::new("files_only").short('F').action(ArgAction::SetTrue).help("Display files only.")),
+        )
+        .subcommand(
+            Command::new("du")
+                .about("Recursive logical and allocated sizes per directory.")
+                .arg(
+                    Arg::new("path")
+                        .value_parser(value_parser!(String))
+                        .help("Directory to summarize (defaults to the root)."),
+                )
+                .arg(
+                    Arg::new("depth")
+                        .short('d')
+                        .long("depth")
+                        .value_parser(value_parser!(usize))
+                        .help("Only list directories down to this depth (totals stay recursive)."),
+                )
+                .arg(
+                    Arg::new("human")
+                        .short('H')
+                        .long("human")
+                        .action(ArgAction::SetTrue)
+                        .help("Print sizes in human readable units."),
+                )
+                .arg(
+                    Arg::new("json")
+                        .long("json")
+                        .action(ArgAction::SetTrue)
+                        .help("Output the usage as JSON."),
+                ),
+        )
+        .subcommand(
+            Command::new("stats")
+                .about("Volume profile as JSON: counts and sizes by extension, type, owner and year.")
+                .arg(
+                    Arg::new("top")
+                        .long("top")
+                        .value_parser(value_parser!(usize))
+                        .default_value("20")
+                        .help("Number of largest files and deepest paths to report."),
+                ),
+        )
+        .subcommand(
+            Command::new("find")
+                .about("Search the whole filesystem for records whose name matches a pattern or an expression.")
+                .arg(
+                    Arg::new("name")
+                        .long("name")
+                        .value_parser(value_parser!(String))
+                        .required_unless_present("where")
+                        .help("Regular expression (or glob with --glob) matched against file names."),
+                )
+                .arg(
+                    Arg::new("where")
+                        .long("where")
+                        .value_parser(value_parser!(Query))
+                        .help("Additional expression the records must match (see the global --where)."),
+                )
+                .arg(
+                    Arg::new("glob")
+                        .long("glob")
+                        .action(ArgAction::SetTrue)
+                        .help("Interpret --name as a shell glob (e.g. '*.ps1') instead of a regex."),
+                )
+                .arg(
+                    Arg::new("ignore_case")
+                        .short('i')
+                        .long("ignore-case")
+                        .action(ArgAction::SetTrue)
+                        .help("Case-insensitive matching."),
+                )
+                .arg(
+                    Arg::new("match_path")
+                        .long("match-path")
+                        .action(ArgAction::SetTrue)
+                        .help("Match against the absolute path instead of the file name."),
+                )
+                .arg(
+                    Arg::new("output_format")
+                        .long("output-format")
+                        .value_parser(["text", "json", "csv", "bodyfile"])
+                        .default_value("text")
+                        .help("Format of the matching records."),
+                ),
+        )
+        .subcommand(
+            Command::new("index")
+                .about("Walk the filesystem into a SQLite index (FTS5 over names, paths and metadata) for the query subcommand; honors --hash, --detect-type and --exclude.")
+                .arg(
+                    Arg::new("db")
+                        .long("db")
+                        .value_parser(value_parser!(String))
+                        .required(true)
+                        .help("Path of the SQLite index to create (or to add to with --append)."),
+                )
+                .arg(
+                    Arg::new("force")
+                        .long("force")
+                        .action(ArgAction::SetTrue)
+                        .help("Replace an existing index."),
+                )
+                .arg(
+                    Arg::new("append")
+                        .long("append")
+                        .action(ArgAction::SetTrue)
+                        .conflicts_with("force")
+                        .help("Add the records of this evidence, partition or snapshot to an existing index; each keeps its own identifier space and adding the same records twice fails."),
+                ),
+        )
+        .subcommand(
+            Command::new("query")
+                .about("Search a SQLite index built by the index subcommand, without --body.")
+                .arg(
+                    Arg::new("db")
+                        .long("db")
+                        .value_parser(value_parser!(String))
+                        .required(true)
+                        .help("Path of the SQLite index."),
+                )
+                .arg(
+                    Arg::new("text")
+                        .value_parser(value_parser!(String))
+                        .help("FTS5 full-text expression over names, paths and metadata (e.g. 'invoice* OR dropper')."),
+                )
+                .arg(
+                    Arg::new("hash")
+                        .long("hash")
+                        .value_parser(value_parser!(String))
+                        .help("Records whose MD5, SHA-1 or SHA-256 digest is this value."),
+                )
+                .arg(
+                    Arg::new("sql")
+                        .long("sql")
+                        .value_parser(value_parser!(String))
+                        .help("Raw SQL condition over the indexed columns, e.g. 'modified > 1700000000 AND size > 1000000'."),
+                )
+                .arg(
+                    Arg::new("where")
+                        .long("where")
+                        .value_parser(value_parser!(Query))
+                        .help("Only print the records matching an expression (same syntax as --where)."),
+                )
+                .arg(
+                    Arg::new("limit")
+                        .long("limit")
+                        .value_parser(value_parser!(usize))
+                        .help("Print at most this many records."),
+                )
+                .arg(
+                    Arg::new("info")
+                        .long("info")
+                        .action(ArgAction::SetTrue)
+                        .help("Print the description of the indexed evidence instead of records."),
+                )
+                .arg(
+                    Arg::new("output_format")
+                        .long("output-format")
+                        .value_parser(["text", "json", "csv", "bodyfile"])
+                        .default_value("text")
+                        .help("Format of the matching records."),
+                ),
+        )
+        .subcommand(
+            Command::new("triage")
+                .about("Prioritized report of suspicious files (setuid, unusual locations, hidden+system, double extensions, backdated executables).")
+                .arg(
+                    Arg::new("min_severity")
+                        .long("min-severity")
+                        .value_parser(value_parser!(Severity))
+                        .default_value("low")
+                        .help("Only report findings of at least this severity: low, medium or high."),
+                )
+                .arg(
+                    Arg::new("recent_days")
+                        .long("recent-days")
+                        .value_parser(value_parser!(u64))
+                        .default_value("30")
+                        .help("Creation window, in days before the newest creation time of the volume, for backdated executables."),
+                )
+                .arg(
+                    Arg::new("gap_days")
+                        .long("gap-days")
+                        .value_parser(value_parser!(u64))
+                        .default_value("365")
+                        .help("Minimum number of days between the modification and the later creation of a backdated executable."),
+                )
+                .arg(
+                    Arg::new("json")
+                        .long("json")
+                        .action(ArgAction::SetTrue)
+                        .help("Output the report as JSON."),
+                ),
+        )
+        .subcommand(
+            Command::new("dedupe")
+                .about("Report clusters of regular files with identical content.")
+                .arg(
+                    Arg::new("min_size")
+                        .long("min-size")
+                        .value_parser(parse_size)
+                        .help("Ignore files smaller than this size (e.g. '4K'); empty files are always ignored."),
+                )
+                .arg(
+                    Arg::new("human")
+                        .short('H')
+                        .long("human")
+                        .action(ArgAction::SetTrue)
+                        .help("Print sizes in human readable units."),
+                )
+                .arg(
+                    Arg::new("json")
+                        .long("json")
+                        .action(ArgAction::SetTrue)
+                        .help("Output the clusters as JSON."),
+                ),
+        )
+        .subcommand(
+            Command::new("grep")
+                .about("Search the content of every regular file for a regex or byte pattern.")
+                .arg(
+                    Arg::new("pattern")
+                        .value_parser(value_parser!(String))
+                        .required(true)
+                        .help("Regular expression, or hex bytes with --hex (e.g. '4d5a9000')."),
+                )
+                .arg(
+                    Arg::new("hex")
+                        .long("hex")
+                        .action(ArgAction::SetTrue)
+                        .help("Interpret the pattern as hex encoded bytes."),
+                )
+                .arg(
+                    Arg::new("ignore_case")
+                        .short('i')
+                        .long("ignore-case")
+                        .action(ArgAction::SetTrue)
+                        .help("Case-insensitive matching."),
+                )
+                .arg(
+                    Arg::new("include")
+                        .long("include")
+                        .value_parser(value_parser!(String))
+                        .help("Only search files whose name matches this glob (e.g. '*.log')."),
+                )
+                .arg(
+                    Arg::new("min_size")
+                        .long("min-size")
+                        .value_parser(parse_size)
+                        .help("Skip files smaller than this size (e.g. '1K')."),
+                )
+                .arg(
+                    Arg::new("max_size")
+                        .long("max-size")
+                        .value_parser(parse_size)
+                        .help("Skip files larger than this size (e.g. '100M')."),
+                )
+                .arg(
+                    Arg::new("max_count")
+                        .short('m')
+                        .long("max-count")
+                        .value_parser(value_parser!(u64))
+                        .help("Stop searching a file after this many matching lines."),
+                )
+                .arg(
+                    Arg::new("json")
+                        .long("json")
+                        .action(ArgAction::SetTrue)
+                        .help("Output one JSON object per match."),
+                ),
+        )
+        .subcommand(
+            Command::new("collect")
+                .about("Export the files matching YAML target definitions, preserving their paths.")
+                .arg(
+                    Arg::new("targets")
+                        .long("targets")
+                        .value_parser(value_parser!(String))
+                        .required(true)
+                        .help("YAML file listing the targets (name, paths globs, optional category)."),
+                )
+                .arg(
+                    Arg::new("output_dir")
+                        .long("output-dir")
+                        .value_parser(value_parser!(String))
+                        .help("Recreate the collected paths below this directory."),
+                )
+                .arg(
+                    Arg::new("archive")
+                        .long("archive")
+                        .value_parser(value_parser!(String))
+                        .help("Write the collected files into this tar archive."),
+                )
+                .group(
+                    ArgGroup::new("destination")
+                        .args(["output_dir", "archive"])
+                        .required(true),
+                ),
+        )
+        .subcommand(
+            Command::new("partitions")
+                .about("List the MBR / GPT partitions of the disk image with their detected filesystem.")
+                .arg(
+                    Arg::new("json")
+                        .long("json")
+                        .action(ArgAction::SetTrue)
+                        .help("Output the partitions as a JSON array."),
+                ),
+        )
+        .subcommand(
+            Command::new("ffind")
+                .about("List every name referencing a record like The Sleuth Kit 'ffind -a'.")
+                .arg(Arg::new("inode").value_parser(parse_record_id).required(true)),
+        )
+        .subcommand(
+            Command::new("ifind")
+                .about("Find the record owning a block or a path like The Sleuth Kit 'ifind'.")
+                .arg(
+                    Arg::new("block")
+                        .short('d')
+                        .long("block")
+                        .value_parser(maybe_hex::<u64>)
+                        .help("Block address to map back to a record."),
+                )
+                .arg(
+                    Arg::new("path")
+                        .short('n')
+                        .long("path")
+                        .value_parser(value_parser!(String))
+                        .help("Path to resolve to a record identifier."),
+                )
+                .arg(
+                    Arg::new("object_id")
+                        .long("object-id")
+                        .value_parser(value_parser!(String))
+                        .help("NTFS object identifier (GUID, e.g. from a LNK file) to resolve to a record identifier."),
+                )
+                .group(
+                    ArgGroup::new("target")
+                        .args(["block", "path", "object_id"])
+                        .required(true),
+                ),
+        )
+        .subcommand(
+            Command::new("icat")
+                .about("Write the content of a record to STDOUT like The Sleuth Kit 'icat'; the raw entry blocks of a directory (ext blocks, NTFS $I30 INDX blocks, exFAT clusters).")
+                .arg(Arg::new("inode").value_parser(parse_record_id).required(true))
+                .arg(
+                    Arg::new("xattr")
+                        .long("xattr")
+                        .value_parser(value_parser!(String))
+                        .help("Write the value of this extended attribute (NTFS $EA entry) instead of the content."),
+                ),
+        )
+        .subcommand(
+            Command::new("istat")
+                .about("Display the metadata of a record like The Sleuth Kit 'istat'.")
+                .arg(Arg::new("inode").value_parser(parse_record_id).required(true)),
+        )
+        .subcommand(
+            Command::new("batch")
+                .about("Process a case manifest (YAML or TOML): run its operations on every listed evidence and partition, with a consolidated case.log and a summary.json.")
+                .arg(
+                    Arg::new("manifest")
+                        .value_parser(value_parser!(String))
+                        .required(true)
+                        .help("Path to the case manifest."),
+                ),
+        )
+        .subcommand(
+            Command::new("snapshots")
+                .about("List the volume shadow copies of the partition (index, store identifier, creation time, volume size); select one with --snapshot.")
+                .arg(
+                    Arg::new("json")
+                        .long("json")
+                        .action(ArgAction::SetTrue)
+                        .help("Output the snapshots as JSON."),
+                ),
         )
-        .arg(
-            Arg::new("json")
-                .short('j')
-                .long("json")
-                .action(ArgAction::SetTrue)
-                .help("Output the result in a JSON format."),
+        .subcommand(
+            Command::new("recover")
+                .about("Recover the content of deleted records; the confidence of each recovered file goes to recovered.json in the output directory.")
+                .arg(
+                    Arg::new("record")
+                        .long("record")
+                        .value_parser(parse_record_id)
+                        .help("Identifier of the record to recover."),
+                )
+                .arg(
+                    Arg::new("all_deleted")
+                        .long("all-deleted")
+                        .action(ArgAction::SetTrue)
+                        .help("Recover every deleted regular file found in the record table."),
+                )
+                .group(
+                    ArgGroup::new("records")
+                        .args(["record", "all_deleted"])
+                        .required(true),
+                )
+                .arg(
+                    Arg::new("out")
+                        .long("out")
+                        .value_parser(value_parser!(String))
+                        .required(true)
+                        .help("Directory receiving the recovered files and recovered.json."),
+                ),
         )
-        .arg(
-            Arg::new("log_level")
-                .short('l')
-                .long("log-level")
-                .value_parser(["error", "warn", "info", "debug", "trace"])
-                .default_value("info")
-                .help("Set the log verbosity level"),
+        .subcommand(
+            Command::new("carve")
+                .about("Carve files by signature out of unallocated space, file slack or the whole partition; provenance goes to carved.json in the output directory.")
+                .arg(
+                    Arg::new("source")
+                        .long("source")
+                        .value_parser(value_parser!(CarveSource))
+                        .default_value("unallocated")
+                        .help("Space to scan: unallocated, slack or all."),
+                )
+                .arg(
+                    Arg::new("types")
+                        .long("types")
+                        .value_parser(value_parser!(String))
+                        .default_value("all")
+                        .help("Comma separated types to carve: jpg,png,gif,pdf,sqlite (all by default)."),
+                )
+                .arg(
+                    Arg::new("out")
+                        .long("out")
+                        .value_parser(value_parser!(String))
+                        .required(true)
+                        .help("Directory receiving the carved files and carved.json."),
+                ),
+        )
+        .subcommand(
+            Command::new("blk")
+                .about("Block level access (The Sleuth Kit 'blkcat' / 'blkstat').")
+                .subcommand_required(true)
+                .subcommand(
+                    Command::new("cat")
+                        .about("Print the raw content of one or more blocks.")
+                        .arg(Arg::new("addr").value_parser(maybe_hex::<u64>).required(true))
+                        .arg(
+                            Arg::new("count")
+                                .value_parser(maybe_hex::<u64>)
+                                .default_value("1"),
+                        )
+                        .arg(
+                            Arg::new("hexdump")
+                                .long("hexdump")
+                                .action(ArgAction::SetTrue)
+                                .help("Display the blocks as an xxd-style hexdump."),
+                        ),
+                )
+                .subcommand(
+                    Command::new("stat")
+                        .about("Print the allocation status of a block.")
+                        .arg(Arg::new("addr").value_parser(maybe_hex::<u64>).required(true)),
+                ),
         )
-        .get_matches();
+        .subcommand(
+            Command::new("check")
+                .about("Verify the on-disk metadata checksums (ext4 metadata_csum) and the file hashes of sealed APFS volumes; print the consistency report as JSON."),
+        )
+        .subcommand(
+            Command::new("shell")
+                .about("Browse the filesystem interactively (cd, ls, stat, cat, hash, dump)."),
+        );
+    #[cfg(feature = "tui")]
+    let command = command.subcommand(Command::new("tui").about(
+        "Browse the filesystem in a two-pane terminal UI (tree, metadata and hex preview).",
+    ));
+    let matches = command.clone().get_matches();
+
+    let settings = Config::load(matches.get_one::<String>("config").map(String::as_str))
+        .and_then(|config| config.resolve(matches.get_one::<String>("case").map(String::as_str)));
 
-    // Initialize logger.
-    let log_level_str = matches.get_one::<String>("log_level").unwrap();
+    // Initialize logger. An explicit --log-level wins over the config file.
+    let log_level_str = match settings.as_ref().ok().and_then(|s| s.log_level.as_ref()) {
+        Some(level)
+            if matches.value_source("log_level") == Some(parser::ValueSource::DefaultValue) =>
+        {
+            level
+        }
+        _ => matches.get_one::<String>("log_level").unwrap(),
+    };
     let level_filter = match log_level_str.as_str() {
         "error" => log::LevelFilter::Error,
         "warn" => log::LevelFilter::Warn,
@@ -127,9 +2448,100 @@ fn main() {
         "trace" => log::LevelFilter::Trace,
         _ => log::LevelFilter::Info,
     };
-    env_logger::Builder::new().filter_level(level_filter).init();
+    let log_file = matches.get_one::<String>("log_file");
+    let log_json = matches.get_one::<String>("log_format").unwrap() == "json";
+    if let Err(e) = cli::logging::init(level_filter, log_file.map(String::as_str), log_json) {
+        eprintln!("Could not open log file '{}': {}", log_file.unwrap(), e);
+        return;
+    }
+
+    let settings = match settings {
+        Ok(settings) => settings,
+        Err(e) => {
+            error!("{}", e);
+            return;
+        }
+    };
+
+    match TimeDisplay::new(
+        matches
+            .get_one::<String>("timezone")
+            .or(settings.timezone.as_ref())
+            .map(String::as_str),
+        matches
+            .get_one::<String>("time_format")
+            .or(settings.time_format.as_ref())
+            .map(String::as_str),
+    ) {
+        Ok(display) => set_time_display(display),
+        Err(e) => {
+            error!("{}", e);
+            return;
+        }
+    }
+    if let Some(policy) = matches
+        .get_one::<String>("fat_time")
+        .or(settings.fat_time.as_ref())
+    {
+        match LocalTimePolicy::parse(policy) {
+            Ok(policy) => set_local_time_policy(policy),
+            Err(e) => {
+                error!("invalid --fat-time: {}", e);
+                return;
+            }
+        }
+    }
+    if let Some(rendering) = matches
+        .get_one::<String>("name_rendering")
+        .or(settings.name_rendering.as_ref())
+    {
+        match NameRendering::parse(rendering) {
+            Ok(rendering) => set_name_rendering(rendering),
+            Err(e) => {
+                error!("invalid --name-rendering: {}", e);
+                return;
+            }
+        }
+    }
+
+    if let Some(("query", sub)) = matches.subcommand() {
+        if let Err(e) = run_query(sub, &settings) {
+            error!("{}", e);
+        }
+        return;
+    }
 
-    let file_path = matches.get_one::<String>("body").unwrap();
+    if let Some(("batch", sub)) = matches.subcommand() {
+        if let Err(e) = run_batch(&matches, sub) {
+            error!("{}", e);
+        }
+        return;
+    }
+
+    // The manifest records dumped and collected files: with nothing to extract it would be
+    // written empty.
+    if matches.contains_id("custody")
+        && !matches.get_flag("dump")
+        && !matches!(matches.subcommand(), Some(("collect", _)))
+    {
+        command
+            .clone()
+            .error(
+                error::ErrorKind::MissingRequiredArgument,
+                "--custody requires --dump or the collect subcommand",
+            )
+            .exit();
+    }
+
+    let Some(file_path) = matches.get_one::<String>("body") else {
+        command
+            .clone()
+            .error(
+                error::ErrorKind::MissingRequiredArgument,
+                "the following required arguments were not provided:\n  --body <body>",
+            )
+            .exit();
+    };
     let auto = String::from("auto");
     let format = matches.get_one::<String>("format").unwrap_or(&auto);
 
@@ -140,6 +2552,20 @@ fn main() {
     let offset = matches.get_one::<u64>("offset");
     let size = matches.get_one::<u64>("size");
 
+    if let Some(("partitions", sub)) = matches.subcommand() {
+        if is_directory {
+            error!("Partition listing requires a disk image, not a folder.");
+        } else if let Err(e) = run_partitions(
+            file_path,
+            format,
+            matches.get_one::<u64>("sector_size").copied(),
+            sub,
+        ) {
+            error!("{}", e);
+        }
+        return;
+    }
+
     // Validation for non-directory inputs
     if !is_directory && (offset.is_none() || size.is_none()) {
         // Need a way to enforce required args conditionally?
@@ -149,13 +2575,72 @@ fn main() {
         return;
     }
 
-    let file_id = matches.get_one::<usize>("record").copied().unwrap_or(0);
+    let records = match matches
+        .get_one::<String>("record")
+        .map(|r| parse_record_list(r))
+    {
+        Some(Ok(records)) => Some(records),
+        Some(Err(e)) => {
+            error!("Invalid --record value: {}", e);
+            return;
+        }
+        None => None,
+    };
     let list = matches.get_flag("list");
     let enumerate = matches.get_flag("enum");
+    let query = matches.get_one::<Query>("where");
     let metadata = matches.get_flag("metadata");
     let print = matches.get_flag("print");
     let dump = matches.get_flag("dump");
     let json_output = matches.get_flag("json");
+    let mismatch_only = matches.get_flag("mismatch_only");
+    let detect_type = matches.get_flag("detect_type") || mismatch_only;
+    let hash_algorithms = match matches
+        .get_one::<String>("hash")
+        .or(settings.hash.as_ref())
+        .map(|h| parse_hash_list(h))
+    {
+        Some(Ok(algs)) => Some(algs),
+        Some(Err(e)) => {
+            error!("Invalid --hash value: {}", e);
+            return;
+        }
+        None => None,
+    };
+
+    let enricher = match matches
+        .get_one::<String>("extract_metadata")
+        .map(|list| Enricher::from_names(list))
+    {
+        Some(Ok(enricher)) if enricher.is_empty() => None,
+        Some(Ok(enricher)) => Some(enricher),
+        Some(Err(e)) => {
+            error!("Invalid --extract-metadata value: {}", e);
+            return;
+        }
+        None => None,
+    };
+
+    let content = ContentPass {
+        detect_type,
+        enricher: enricher.as_ref(),
+        hash_algorithms: hash_algorithms.as_deref(),
+    };
+
+    let exclude_patterns: Vec<&String> = matches
+        .get_many::<String>("exclude")
+        .into_iter()
+        .flatten()
+        .chain(&settings.exclude)
+        .collect();
+    let exclude = match ExcludeSet::new(&exclude_patterns) {
+        Ok(set) if set.is_empty() => None,
+        Ok(set) => Some(set),
+        Err(e) => {
+            error!("{}", e);
+            return;
+        }
+    };
 
     let mut keys = None;
     if let Some(fvek_hex) = matches.get_one::<String>("fvek") {
@@ -169,26 +2654,398 @@ fn main() {
         }
     }
 
-    let mut filesystem: DetectedFs<exhume_filesystem::detected_fs::ImageStream> = if is_directory {
-        let fs = FolderFS::new(path.to_path_buf());
-        DetectedFs::Folder(fs)
-    } else {
-        let offset_val = *offset.unwrap();
-        let size_val = *size.unwrap();
+    let custody_path = match matches
+        .get_one::<String>("custody")
+        .map(|p| settings.output_path(p))
+    {
+        Some(Ok(p)) => Some(p),
+        Some(Err(e)) => {
+            error!("{}", e);
+            return;
+        }
+        None => None,
+    };
+    let sign_key = match matches
+        .get_one::<String>("sign_key")
+        .map(|p| load_signing_key(p))
+    {
+        Some(Ok(key)) => Some(key),
+        Some(Err(e)) => {
+            error!("{}", e);
+            return;
+        }
+        None => None,
+    };
 
-        let body = Body::new(file_path.to_owned(), format);
-        debug!("Created Body from '{}'", file_path);
+    let _audit_log = match matches.get_one::<String>("audit_log") {
+        Some(audit_log) => {
+            let installed = settings
+                .output_path(audit_log)
+                .and_then(|path| audit::install(&path).map_err(|e| format!("{}: {}", path, e)));
+            match installed {
+                Ok(guard) => Some(guard),
+                Err(e) => {
+                    error!("Could not open the audit log {}", e);
+                    return;
+                }
+            }
+        }
+        None => None,
+    };
 
-        let partition_size = size_val * body.get_sector_size() as u64;
+    if let Some(limit) = matches.get_one::<u64>("memory_limit") {
+        budget::set_limit(*limit);
+    }
+    if let Some(rate) = matches.get_one::<u64>("max_read_rate") {
+        throttle::set_rate(*rate);
+    }
+    tolerant::set_retries(matches.get_one::<u32>("read_retries").copied());
+    cache::set_capacity(*matches.get_one::<u64>("cache_size").unwrap());
+    cache::set_read_buffer(*matches.get_one::<u64>("read_buffer").unwrap());
+    let _cache_report = CacheReport;
 
-        match detect_filesystem(&body, offset_val, partition_size, keys) {
-            Ok(fs) => fs,
-            Err(e) => {
-                error!("Could not detect the provided filesystem: {e:?}");
-                return;
+    let snapshot = matches.get_one::<String>("snapshot").map(String::as_str);
+    let folder = FolderOptions {
+        follow_symlinks: matches.get_flag("follow_symlinks"),
+        one_file_system: matches.get_flag("one_file_system"),
+    };
+    let fstype = match FsType::parse(matches.get_one::<String>("fstype").unwrap()) {
+        Ok(fstype) => fstype,
+        Err(e) => {
+            error!("{}", e);
+            return;
+        }
+    };
+    let layers: Vec<String> = matches
+        .get_many::<String>("layer")
+        .unwrap_or_default()
+        .cloned()
+        .collect();
+    let apfs_prefixes = match VolumePrefixes::parse(
+        matches
+            .get_many::<String>("apfs_prefix")
+            .unwrap_or_default()
+            .map(String::as_str),
+    ) {
+        Ok(prefixes) => prefixes,
+        Err(e) => {
+            error!("Invalid --apfs-prefix: {}", e);
+            return;
+        }
+    };
+    let evidence = Evidence {
+        path: file_path,
+        format,
+        offset,
+        size,
+        keys: keys.clone(),
+        snapshot,
+        folder,
+        fstype,
+        sector_size: matches.get_one::<u64>("sector_size").copied(),
+        apfs_prefixes: &apfs_prefixes,
+        zfs_dataset: matches.get_one::<String>("zfs_dataset").map(String::as_str),
+        ubi_volume: matches.get_one::<String>("ubi_volume").map(String::as_str),
+        layers: &layers,
+    };
+    let mut filesystem = match open_filesystem(&evidence) {
+        Ok(fs) => fs,
+        Err(e) => {
+            error!("Could not detect the provided filesystem: {e:?}");
+            return;
+        }
+    };
+
+    let mut custody = custody_path.is_some().then(|| {
+        CustodyManifest::new(EvidenceSource {
+            path: file_path.clone(),
+            format: format.clone(),
+            partition_offset: offset.copied().unwrap_or(0),
+            partition_sectors: size.copied(),
+            snapshot: snapshot.map(str::to_string),
+            filesystem: filesystem.filesystem_type(),
+        })
+    });
+    let write_custody = |custody: Option<CustodyManifest>| {
+        if let (Some(manifest), Some(path)) = (custody, &custody_path) {
+            match manifest.write(Path::new(path), sign_key.as_ref()) {
+                Ok(()) => info!("Custody manifest written to {}", path),
+                Err(e) => error!("Could not write the custody manifest {}: {}", path, e),
+            }
+        }
+    };
+
+    if let Some(("diff", sub)) = matches.subcommand() {
+        run_diff(&mut filesystem, evidence, sub, &settings);
+        return;
+    }
+
+    if let Some(("snapshots", sub)) = matches.subcommand() {
+        let partition = (!is_directory).then(|| {
+            let mut body = Body::new(file_path.to_owned(), format);
+            let partition_size = size.unwrap() * sector_size(&mut body, evidence.sector_size);
+            open_partition_stream(&body, *offset.unwrap(), partition_size, keys)
+        });
+        let result = match partition {
+            Some(Ok(stream)) => run_snapshots(stream, sub),
+            Some(Err(e)) => Err(e),
+            None => Err("snapshots are listed from a disk image, not a folder".into()),
+        };
+        if let Err(e) = result {
+            error!("{}", e);
+        }
+        return;
+    }
+
+    if let Some(("blk", sub)) = matches.subcommand() {
+        let partition = (!is_directory).then(|| {
+            let mut body = Body::new(file_path.to_owned(), format);
+            let partition_size = size.unwrap() * sector_size(&mut body, evidence.sector_size);
+            open_partition_stream(&body, *offset.unwrap(), partition_size, keys)
+                .and_then(|stream| with_snapshot(stream, snapshot))
+        });
+        let result = match partition {
+            Some(Ok(stream)) => run_blk(&mut filesystem, stream, sub),
+            Some(Err(e)) => Err(e),
+            None => Err("block access requires a disk image, not a folder".into()),
+        };
+        if let Err(e) = result {
+            error!("{}", e);
+        }
+        return;
+    }
+
+    if matches.subcommand_matches("check").is_some() {
+        let partition = (!is_directory).then(|| {
+            let mut body = Body::new(file_path.to_owned(), format);
+            let partition_size = size.unwrap() * sector_size(&mut body, evidence.sector_size);
+            open_partition_stream(&body, *offset.unwrap(), partition_size, keys)
+                .and_then(|stream| with_snapshot(stream, snapshot))
+        });
+        let result = match partition {
+            Some(Ok(stream)) => BlockDevice::new(stream, filesystem.block_size())
+                .map_err(Into::into)
+                .and_then(|mut device| consistency::check(&mut device)),
+            Some(Err(e)) => Err(e),
+            None => Err("consistency checks require a disk image, not a folder".into()),
+        };
+        match result.and_then(|report| Ok(serde_json::to_string_pretty(&report)?)) {
+            Ok(json) => println!("{}", json),
+            Err(e) => error!("{}", e),
+        }
+        return;
+    }
+
+    if let Some(("recover", sub)) = matches.subcommand() {
+        let algorithms = hash_algorithms
+            .clone()
+            .unwrap_or_else(|| vec![HashAlgorithm::Sha256]);
+        if let Err(e) = run_recover(&mut filesystem, sub, &algorithms, &settings) {
+            error!("{}", e);
+        }
+        return;
+    }
+
+    if let Some(("carve", sub)) = matches.subcommand() {
+        let partition = (!is_directory).then(|| {
+            let mut body = Body::new(file_path.to_owned(), format);
+            let partition_size = size.unwrap() * sector_size(&mut body, evidence.sector_size);
+            open_partition_stream(&body, *offset.unwrap(), partition_size, keys)
+                .and_then(|stream| with_snapshot(stream, snapshot))
+        });
+        let algorithms = hash_algorithms.unwrap_or_else(|| vec![HashAlgorithm::Sha256]);
+        let result = match partition {
+            Some(Ok(stream)) => run_carve(
+                &mut filesystem,
+                stream,
+                sub,
+                exclude.as_ref(),
+                &algorithms,
+                &settings,
+                *offset.unwrap(),
+            ),
+            Some(Err(e)) => Err(e),
+            None => Err("carving requires a disk image, not a folder".into()),
+        };
+        if let Err(e) = result {
+            error!("{}", e);
+        }
+        return;
+    }
+
+    if let Some(("du", sub)) = matches.subcommand() {
+        if let Err(e) = run_du(&mut filesystem, sub) {
+            error!("{}", e);
+        }
+        return;
+    }
+
+    if let Some(("stats", sub)) = matches.subcommand() {
+        let separator = filesystem.path_separator();
+        let mut stats = VolumeStats::new(*sub.get_one::<usize>("top").unwrap());
+        let options = WalkOptions {
+            exclude: exclude.as_ref(),
+            ..Default::default()
+        };
+        let walked = filesystem.walk_fs_with(options, &mut |event| {
+            if let WalkEvent::File(file) = event {
+                stats.add(&file, &separator);
+            }
+        });
+        if let Err(e) = walked {
+            error!("Walk failed: {}", e);
+            return;
+        }
+        match serde_json::to_string_pretty(&stats.report()) {
+            Ok(json) => println!("{}", json),
+            Err(e) => error!("Could not serialize the statistics: {}", e),
+        }
+        return;
+    }
+
+    if let Some(("find", sub)) = matches.subcommand() {
+        if let Err(e) = run_find(
+            &mut filesystem,
+            sub,
+            exclude.as_ref(),
+            ContentPass {
+                detect_type,
+                enricher: enricher.as_ref(),
+                hash_algorithms: None,
+            },
+            mismatch_only,
+        ) {
+            error!("{}", e);
+        }
+        return;
+    }
+
+    if let Some(("grep", sub)) = matches.subcommand() {
+        if let Err(e) = run_grep(&mut filesystem, sub, exclude.as_ref()) {
+            error!("{}", e);
+        }
+        return;
+    }
+
+    if let Some(("index", sub)) = matches.subcommand() {
+        if let Err(e) = run_index(
+            &mut filesystem,
+            sub,
+            exclude.as_ref(),
+            content,
+            &settings,
+            IndexSource {
+                evidence: file_path.clone(),
+                partition: offset.map(u64::to_string).unwrap_or_default(),
+                namespace: snapshot
+                    .map(|s| format!("snapshot:{}", s))
+                    .unwrap_or_default(),
+            },
+        ) {
+            error!("{}", e);
+        }
+        return;
+    }
+
+    if let Some(("triage", sub)) = matches.subcommand() {
+        if let Err(e) = run_triage(&mut filesystem, sub, exclude.as_ref()) {
+            error!("{}", e);
+        }
+        return;
+    }
+
+    if let Some(("dedupe", sub)) = matches.subcommand() {
+        let algorithms = hash_algorithms.unwrap_or_else(|| vec![HashAlgorithm::Sha256]);
+        if let Err(e) = run_dedupe(&mut filesystem, sub, &algorithms, exclude.as_ref()) {
+            error!("{}", e);
+        }
+        return;
+    }
+
+    if let Some(("collect", sub)) = matches.subcommand() {
+        let algorithms = hash_algorithms.unwrap_or_else(|| vec![HashAlgorithm::Sha256]);
+        if let Err(e) = run_collect(
+            &mut filesystem,
+            sub,
+            &algorithms,
+            exclude.as_ref(),
+            &settings,
+            custody.as_mut(),
+        ) {
+            error!("{}", e);
+        }
+        write_custody(custody);
+        return;
+    }
+
+    let tsk_result = match matches.subcommand() {
+        Some(("fls", sub)) => {
+            let options = cli::tsk::FlsOptions {
+                recursive: sub.get_flag("recursive"),
+                full_path: sub.get_flag("full_path"),
+                long: sub.get_flag("long"),
+                mactime: sub.get_one::<String>("mactime").cloned(),
+                dirs_only: sub.get_flag("dirs_only"),
+                files_only: sub.get_flag("files_only"),
+            };
+            Some(cli::tsk::fls(
+                &mut filesystem,
+                sub.get_one::<u64>("inode").copied(),
+                &options,
+            ))
+        }
+        Some(("ffind", sub)) => Some(cli::tsk::ffind(
+            &mut filesystem,
+            *sub.get_one::<u64>("inode").unwrap(),
+        )),
+        Some(("ifind", sub)) => {
+            match (
+                sub.get_one::<u64>("block"),
+                sub.get_one::<String>("path"),
+                sub.get_one::<String>("object_id"),
+            ) {
+                (Some(block), _, _) => Some(cli::tsk::ifind_block(&mut filesystem, *block)),
+                (_, Some(path), _) => Some(cli::tsk::ifind_path(&mut filesystem, path)),
+                (_, _, Some(object_id)) => {
+                    Some(cli::tsk::ifind_object_id(&mut filesystem, object_id))
+                }
+                _ => unreachable!("ifind requires --block, --path or --object-id"),
             }
         }
+        Some(("icat", sub)) => {
+            let inode = *sub.get_one::<u64>("inode").unwrap();
+            Some(match sub.get_one::<String>("xattr") {
+                Some(name) => cli::tsk::icat_xattr(&mut filesystem, inode, name),
+                None => cli::tsk::icat(&mut filesystem, inode),
+            })
+        }
+        Some(("istat", sub)) => Some(cli::tsk::istat(
+            &mut filesystem,
+            *sub.get_one::<u64>("inode").unwrap(),
+        )),
+        _ => None,
     };
+    if let Some(result) = tsk_result {
+        if let Err(e) = result {
+            error!("{}", e);
+        }
+        return;
+    }
+
+    if let Some(("shell", _)) = matches.subcommand() {
+        if let Err(e) = cli::shell::run(filesystem) {
+            error!("Shell terminated with an error: {}", e);
+        }
+        return;
+    }
+
+    #[cfg(feature = "tui")]
+    if let Some(("tui", _)) = matches.subcommand() {
+        if let Err(e) = cli::tui::run(filesystem) {
+            error!("TUI terminated with an error: {}", e);
+        }
+        return;
+    }
 
     if metadata {
         if json_output {
@@ -196,111 +3053,340 @@ fn main() {
                 Ok(json_str) => {
                     println!("{}", json_str)
                 }
-                Err(e) => error!("Error serializing inode {} to JSON: {}", file_id, e),
+                Err(e) => error!("Error serializing the filesystem metadata to JSON: {}", e),
             }
         } else {
             println!("{}", &filesystem.get_metadata_pretty().unwrap());
         }
     }
 
-    if file_id > 0 {
-        let file = match filesystem.get_file(file_id as u64) {
-            Ok(file) => file,
-            Err(err) => {
-                error!("Could not fetch the requested file: {:?}", err);
+    if let Some(selectors) = &records {
+        let actions = RecordActions {
+            list,
+            dump,
+            print,
+            json: json_output,
+            hashes: hash_algorithms.as_deref(),
+            dump_dir: settings.output_dir.as_deref(),
+            hexdump: matches.get_flag("hexdump"),
+            strings: matches.get_flag("strings").then(|| {
+                (
+                    *matches.get_one::<usize>("min_len").unwrap(),
+                    matches
+                        .get_one::<String>("encoding")
+                        .unwrap()
+                        .parse::<StringEncoding>()
+                        .unwrap_or(StringEncoding::Ascii),
+                )
+            }),
+            content_offset: *matches.get_one::<u64>("content_offset").unwrap(),
+            length: matches.get_one::<u64>("length").copied(),
+        };
+        for selector in selectors {
+            process_record(&mut filesystem, selector, &actions, custody.as_mut());
+        }
+        write_custody(custody.take());
+    }
+
+    if matches.get_flag("verify_content") {
+        let algorithms = hash_algorithms.unwrap_or_else(|| vec![HashAlgorithm::Sha256]);
+        let output = match matches
+            .get_one::<String>("output")
+            .map(|p| settings.output_path(p))
+            .transpose()
+        {
+            Ok(output) => output,
+            Err(e) => {
+                error!("{}", e);
                 return;
             }
         };
+        if let Err(e) = run_verify(
+            &mut filesystem,
+            &algorithms,
+            exclude.as_ref(),
+            output.as_deref(),
+        ) {
+            error!("{}", e);
+        }
+        return;
+    }
 
-        if list {
-            if file.is_dir() {
-                match filesystem.list_dir(&file) {
-                    Ok(entries) => {
-                        if json_output {
-                            let arr: Vec<Value> = entries.iter().map(|de| de.to_json()).collect();
-                            let dir_json = json!(arr);
-                            println!("{}", serde_json::to_string_pretty(&dir_json).unwrap());
-                        } else {
-                            info!("Directory listing for file record {}:", file_id);
-                            for entry in entries {
-                                println!("[{}] - {}", entry.file_id(), entry.name());
-                            }
-                        }
-                    }
-                    Err(err) => {
+    if enumerate {
+        let export_format = match matches.get_one::<String>("output_format") {
+            Some(fmt) => fmt.parse::<ExportFormat>().unwrap_or(ExportFormat::Text),
+            None if json_output => ExportFormat::Json,
+            None => match settings
+                .output_format
+                .as_deref()
+                .map(str::parse::<ExportFormat>)
+            {
+                Some(Ok(fmt)) => fmt,
+                Some(Err(e)) => {
+                    error!("Invalid output_format in the config file: {}", e);
+                    return;
+                }
+                None => ExportFormat::Text,
+            },
+        };
+
+        // Relative paths land in the configured output directory.
+        let placed = |arg: &str| {
+            matches
+                .get_one::<String>(arg)
+                .map(|p| settings.output_path(p))
+                .transpose()
+        };
+        let (output, resume_path, checkpoint_path) =
+            match (placed("output"), placed("resume"), placed("checkpoint")) {
+                (Ok(output), Ok(resume), Ok(checkpoint)) => (output, resume, checkpoint),
+                (Err(e), _, _) | (_, Err(e), _) | (_, _, Err(e)) => {
+                    error!("{}", e);
+                    return;
+                }
+            };
+        let output = output.as_deref();
+        let resume_path = resume_path.as_deref();
+        let checkpoint_path = checkpoint_path.as_deref().or(resume_path).map(String::from);
+        let checkpoint_every = if checkpoint_path.is_some() {
+            *matches.get_one::<u64>("checkpoint_interval").unwrap()
+        } else {
+            0
+        };
+
+        let resume = match resume_path {
+            Some(path) => match WalkCheckpoint::load(path) {
+                Ok(cp) => match &cp.export {
+                    Some(export) if export.format == export_format.to_string() => Some(cp),
+                    Some(export) => {
                         error!(
-                            "Failed to list directory for file record {}: {}",
-                            file_id, err
+                            "Checkpoint '{}' was written for a {} export, not {}.",
+                            path, export.format, export_format
                         );
+                        return;
                     }
+                    None => {
+                        error!("Checkpoint '{}' was not written by --enum.", path);
+                        return;
+                    }
+                },
+                Err(e) => {
+                    error!("Could not load checkpoint '{}': {}", path, e);
+                    return;
                 }
-            } else {
-                error!(
-                    "Requested to list the directory entries for a file but {} is not a directory.",
-                    file_id
-                );
-            }
-        } else if json_output {
-            match serde_json::to_string_pretty(&file.to_json()) {
-                Ok(json_str) => {
-                    info!("File record {} metadata:", file_id);
-                    println!("{}", json_str)
+            },
+            None => None,
+        };
+        // Folder records are identified as they are listed: a checkpoint keeps the
+        // identifiers it refers to in a `.ids` file next to it.
+        let folder_ids = match &mut filesystem {
+            DetectedFs::Folder(folder) => {
+                if let Some(path) = resume_path {
+                    let ids_path = format!("{}.ids", path);
+                    if let Err(e) = folder.load_ids(&ids_path) {
+                        error!("Could not load folder identifiers '{}': {}", ids_path, e);
+                        return;
+                    }
                 }
-                Err(e) => error!("Error serializing inode {} to JSON: {}", file_id, e),
+                Some((folder.root_path.clone(), folder.ids()))
             }
-        } else {
-            println!("{}", file.to_string());
-        }
+            _ => None,
+        };
+
+        // Checkpoints are only loaded when written by an export, see above.
+        let resume_len = resume
+            .as_ref()
+            .and_then(|cp| cp.export.as_ref())
+            .map(|export| export.output_len);
+        let writer: Box<dyn Write> = match (output, resume_len) {
+            (Some(output), Some(len)) => match open_for_resume(output, len) {
+                Ok(f) => Box::new(BufWriter::new(f)),
+                Err(e) => {
+                    error!("Could not reopen output file '{}': {}", output, e);
+                    return;
+                }
+            },
+            (Some(output), None) => match StdFile::create(output) {
+                Ok(f) => Box::new(BufWriter::new(f)),
+                Err(e) => {
+                    error!("Could not create output file '{}': {}", output, e);
+                    return;
+                }
+            },
+            (None, _) => Box::new(BufWriter::new(io::stdout())),
+        };
+
+        let exporter = match &resume {
+            Some(cp) => Exporter::resume(writer, export_format, cp.emitted),
+            None => match Exporter::new(writer, export_format) {
+                Ok(exporter) => exporter,
+                Err(e) => {
+                    error!("Could not initialize the {} exporter: {}", export_format, e);
+                    return;
+                }
+            },
+        };
+        let exporter = RefCell::new(exporter);
 
-        if dump {
-            filesystem.dump_to_fs(&file);
+        // Drawn on STDERR when it is a terminal; a listing sent to the same terminal is
+        // written with the bar cleared.
+        let progress = Progress::new(Some(filesystem.record_count()), ProgressUnit::Records);
+        let listing_on_terminal = output.is_none() && io::stdout().is_terminal();
+        if let Some(cp) = &resume {
+            progress.inc(cp.emitted);
         }
 
-        if print {
-            match filesystem.read_file_prefix(&file, 8192) {
-                Ok(prefix) => println!("Successfully read prefix of length {}", prefix.len()),
-                Err(e) => println!("Error reading prefix: {}", e),
+        let threads = matches.get_one::<usize>("threads").copied().unwrap_or(1);
+        let write_error = RefCell::new(None);
+        let emit = |file: File| {
+            progress.inc(1);
+            if write_error.borrow().is_none()
+                && query.is_none_or(|q| q.matches(&file))
+                && (!mismatch_only || file.ext_mismatch == Some(true))
+                && let Err(e) = if listing_on_terminal {
+                    progress.suspend(|| {
+                        let mut exporter = exporter.borrow_mut();
+                        exporter.write_file(&file).and_then(|_| exporter.flush())
+                    })
+                } else {
+                    exporter.borrow_mut().write_file(&file)
+                }
+            {
+                *write_error.borrow_mut() = Some(e);
             }
-        }
-    }
+        };
 
-    if enumerate {
-        if json_output {
-            let mut files = Vec::new();
-            let collected = filesystem.walk_fs(&mut |event| match event {
-                exhume_filesystem::filesystem::WalkEvent::File(f) => files.push(f),
-                exhume_filesystem::filesystem::WalkEvent::Status(msg) => info!("{}", msg),
-            });
-            match collected {
-                Ok(_) => {
-                    println!("{}", serde_json::to_string_pretty(&files).unwrap());
+        // Digests are computed on worker threads while the walk reads the next records;
+        // the records are exported in walk order once hashed.
+        let hash_threads = *matches.get_one::<usize>("hash_threads").unwrap();
+        let pipeline = RefCell::new(match content.hash_algorithms {
+            Some(algorithms) if threads <= 1 && hash_threads > 1 => {
+                Some(HashPipeline::new(algorithms, hash_threads))
+            }
+            _ => None,
+        });
+        let walk_content = ContentPass {
+            detect_type: content.detect_type,
+            enricher: content.enricher,
+            hash_algorithms: content
+                .hash_algorithms
+                .filter(|_| pipeline.borrow().is_none()),
+        };
+
+        let mut content_visitor = |file: &mut File, reader: &mut dyn ReadSeek| {
+            walk_content.analyze(file, reader);
+            if let Some(pipeline) = pipeline.borrow_mut().as_mut() {
+                pipeline.feed(reader);
+            }
+        };
+        let mut checkpoint_writer = |state: &mut WalkCheckpoint| {
+            let (Some(cp_path), Some(output)) = (&checkpoint_path, output) else {
+                return;
+            };
+            // The checkpoint counts every record walked so far as exported.
+            if let Some(pipeline) = pipeline.borrow_mut().as_mut() {
+                pipeline.flush(&mut |file| emit(file));
+            }
+            if let Err(e) = exporter.borrow_mut().flush() {
+                error!("Could not flush the export before checkpointing: {}", e);
+                return;
+            }
+            if let Err(e) = audit::sync() {
+                error!("Could not sync the audit log before checkpointing: {}", e);
+            }
+            let output_len = match std::fs::metadata(output) {
+                Ok(m) => m.len(),
+                Err(e) => {
+                    error!("Could not stat output file '{}': {}", output, e);
+                    return;
                 }
-                Err(err) => {
-                    error!("Failed JSON enumeration: {:?}", err);
+            };
+            if let Some((root, ids)) = &folder_ids {
+                let ids_path = format!("{}.ids", cp_path);
+                let ids = ids.lock().unwrap_or_else(|e| e.into_inner());
+                if let Err(e) = ids.save(root, &ids_path) {
+                    error!("Could not save folder identifiers '{}': {}", ids_path, e);
+                    return;
                 }
             }
-        } else if let Err(err) = filesystem.walk_fs(&mut |event| match event {
+            state.export = Some(ExportPosition {
+                format: export_format.to_string(),
+                output_len,
+            });
+            match state.save(cp_path) {
+                Ok(_) => debug!("Checkpoint saved after {} records", state.emitted),
+                Err(e) => error!("Could not save checkpoint '{}': {}", cp_path, e),
+            }
+        };
+
+        let options = WalkOptions {
+            resume,
+            checkpoint_every,
+            on_checkpoint: Some(&mut checkpoint_writer),
+            visitor: if content.is_active() {
+                Some(&mut content_visitor)
+            } else {
+                None
+            },
+            exclude: exclude.as_ref(),
+            all_names: matches.get_flag("all_names"),
+            sorted: matches.get_flag("sorted"),
+            ..Default::default()
+        };
+
+        let mut on_event = |event| match event {
             exhume_filesystem::filesystem::WalkEvent::File(file) => {
-                if let Some(custom_display) = file.display {
-                    println!("{}", custom_display);
-                } else {
-                    println!(
-                        "[{}] - {} {} {} {} {} {}",
-                        file.identifier,
-                        file.permissions
-                            .clone()
-                            .unwrap_or_else(|| "??????????".to_string()),
-                        exhume_apfs::fmt_apfs_ns_utc(file.modified.unwrap_or(0) * 1_000_000_000),
-                        file.owner.clone().unwrap_or_else(|| "-".to_string()),
-                        file.group.clone().unwrap_or_else(|| "-".to_string()),
-                        file.size,
-                        file.absolute_path
-                    );
+                match pipeline.borrow_mut().as_mut() {
+                    Some(pipeline) => pipeline.submit(file, &mut |file| emit(file)),
+                    None => emit(file),
                 }
             }
-            exhume_filesystem::filesystem::WalkEvent::Status(msg) => info!("{}", msg),
-        }) {
+            exhume_filesystem::filesystem::WalkEvent::Status(msg) => {
+                progress.suspend(|| info!("{}", msg));
+                progress.set_message(msg);
+            }
+        };
+        let walked = if threads > 1 {
+            // Workers on a folder share its identifiers, assigned by whoever lists first.
+            let open = || match &folder_ids {
+                Some((root, ids)) => Ok(DetectedFs::Folder(FolderFS::with_ids(
+                    root.clone(),
+                    folder,
+                    Arc::clone(ids),
+                ))),
+                None => open_filesystem(&evidence),
+            };
+            let shared_visitor =
+                |file: &mut File, reader: &mut dyn ReadSeek| content.analyze(file, reader);
+            walk_parallel(
+                &mut filesystem,
+                &open,
+                threads,
+                exclude.as_ref(),
+                content
+                    .is_active()
+                    .then_some(&shared_visitor as &SharedVisitor),
+                &mut on_event,
+            )
+        } else {
+            filesystem.walk_fs_with(options, &mut on_event)
+        };
+        if let Some(pipeline) = pipeline.into_inner() {
+            pipeline.finish(&mut |file| emit(file));
+        }
+        progress.finish();
+
+        if let Err(err) = walked {
             error!("Could not enumerate the files: {:?}", err);
         }
+        if let Some(e) = write_error.into_inner() {
+            error!("Failed to write the {} export: {}", export_format, e);
+        }
+        let exporter = exporter.into_inner();
+        let count = exporter.count();
+        match exporter.finish() {
+            Ok(_) => debug!("Exported {} records as {}", count, export_format),
+            Err(e) => error!("Failed to finalize the {} export: {}", export_format, e),
+        }
     }
 }
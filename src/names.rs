@@ -0,0 +1,155 @@
+//! Names that are not valid UTF-8 (old ext volumes, host folders written in a legacy
+//! code page). Backends list such names in an escaped form that keeps every byte: the
+//! bytes of invalid sequences as `\xNN`, and backslashes as `\x5c` so that the form
+//! decodes unambiguously. Valid names without an `\xNN`-like sequence are their own form.
+//! Paths are built and looked up in that form; records are rendered following the
+//! policy chosen with `--name-rendering` and keep the raw bytes in `File.raw_name`.
+use std::borrow::Cow;
+use std::sync::OnceLock;
+
+static NAME_RENDERING: OnceLock<NameRendering> = OnceLock::new();
+
+/// How names that are not valid UTF-8 appear in records.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub enum NameRendering {
+    /// Escaped form (`caf\xe9.txt`), which paths given back to lookups may use.
+    #[default]
+    Escape,
+    /// Invalid sequences replaced by U+FFFD (`caf�.txt`), readable but ambiguous.
+    Replace,
+}
+
+impl NameRendering {
+    /// `escape` or `replace`.
+    pub fn parse(value: &str) -> Result<Self, String> {
+        match value {
+            "escape" => Ok(Self::Escape),
+            "replace" => Ok(Self::Replace),
+            other => Err(format!(
+                "unknown name rendering '{}' (escape, replace)",
+                other
+            )),
+        }
+    }
+}
+
+/// Set the rendering of the names for the rest of the process.
+pub fn set_name_rendering(rendering: NameRendering) {
+    let _ = NAME_RENDERING.set(rendering);
+}
+
+pub fn name_rendering() -> NameRendering {
+    NAME_RENDERING.get().copied().unwrap_or_default()
+}
+
+/// Value of the hex digit `c`.
+fn hex_digit(c: u8) -> Option<u8> {
+    (c as char).to_digit(16).map(|d| d as u8)
+}
+
+/// Byte of the `\xNN` sequence at the start of `s`.
+fn escape_at(s: &[u8]) -> Option<u8> {
+    match s {
+        [b'\\', b'x', high, low, ..] => Some(hex_digit(*high)? << 4 | hex_digit(*low)?),
+        _ => None,
+    }
+}
+
+/// Whether `s` holds an `\xNN` sequence.
+fn has_escape(s: &[u8]) -> bool {
+    (0..s.len()).any(|i| escape_at(&s[i..]).is_some())
+}
+
+/// Escaped form of the raw bytes of a name.
+pub fn escape_name(bytes: &[u8]) -> String {
+    if let Ok(name) = std::str::from_utf8(bytes)
+        && !has_escape(bytes)
+    {
+        return name.to_string();
+    }
+    let mut out = String::with_capacity(bytes.len() + 8);
+    for chunk in bytes.utf8_chunks() {
+        for c in chunk.valid().chars() {
+            match c {
+                '\\' => out.push_str("\\x5c"),
+                c => out.push(c),
+            }
+        }
+        for byte in chunk.invalid() {
+            out.push_str(&format!("\\x{:02x}", byte));
+        }
+    }
+    out
+}
+
+/// Raw bytes of an escaped name (or path), `None` when it is its own text.
+pub fn name_bytes(name: &str) -> Option<Vec<u8>> {
+    let s = name.as_bytes();
+    if !has_escape(s) {
+        return None;
+    }
+    let mut out = Vec::with_capacity(s.len());
+    let mut i = 0;
+    while i < s.len() {
+        match escape_at(&s[i..]) {
+            Some(byte) => {
+                out.push(byte);
+                i += 4;
+            }
+            None => {
+                out.push(s[i]);
+                i += 1;
+            }
+        }
+    }
+    Some(out)
+}
+
+/// An escaped name (or `/`-separated path) as records show it.
+pub fn render_name(name: &str) -> Cow<'_, str> {
+    match (name_rendering(), name_bytes(name)) {
+        (NameRendering::Replace, Some(bytes)) => {
+            Cow::Owned(String::from_utf8_lossy(&bytes).into_owned())
+        }
+        _ => Cow::Borrowed(name),
+    }
+}
+
+/// `render_name` for the paths of a filesystem using `separator`. Backslash-separated
+/// trees (NTFS, Windows folders) never hold escaped names and are left as they are.
+pub fn render_path<'a>(path: &'a str, separator: &str) -> Cow<'a, str> {
+    match separator {
+        "/" => render_name(path),
+        _ => Cow::Borrowed(path),
+    }
+}
+
+/// Components of a path given to a lookup, split on `/` and `\`. On filesystems using
+/// `/`, a backslash starting an `\xNN` sequence belongs to an escaped name.
+pub fn split_path<'a>(path: &'a str, separator: &str) -> Vec<&'a str> {
+    let s = path.as_bytes();
+    let mut components = Vec::new();
+    let mut start = 0;
+    for (i, &byte) in s.iter().enumerate() {
+        let split = match byte {
+            b'/' => true,
+            b'\\' => separator != "/" || escape_at(&s[i..]).is_none(),
+            _ => false,
+        };
+        if split {
+            components.push(&path[start..i]);
+            start = i + 1;
+        }
+    }
+    components.push(&path[start..]);
+    components.retain(|c| !c.is_empty());
+    components
+}
+
+/// Whether the listed (escaped) `name` is the path component `component`, given in the
+/// escaped form or as rendered with replacement characters.
+pub fn name_matches(name: &str, component: &str) -> bool {
+    name == component
+        || (component.contains('\u{FFFD}')
+            && name_bytes(name).is_some_and(|bytes| String::from_utf8_lossy(&bytes) == component))
+}
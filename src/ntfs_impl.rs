@@ -1,12 +1,728 @@
-use crate::filesystem::{DirectoryCommon, FileCommon};
-use crate::filesystem::{File, Filesystem};
-use exhume_ntfs::NTFS;
-use exhume_ntfs::mft::{
-    Attribute, AttributeType, DirectoryEntry, MFTRecord, StandardInformation,
+use crate::exfat_impl::{dos_attr_names, dos_attr_string};
+use crate::filesystem::walk_tree;
+use crate::filesystem::{ByteRange, DirectoryCommon, FileCommon};
+use crate::filesystem::{
+    DEVICE_KEY, FLAGS_KEY, STREAMS_KEY, SYMLINK_TARGET_KEY, namespaced_metadata,
 };
-use serde_json::Value;
+use crate::filesystem::{EXTENDED_ATTRIBUTES_KEY, ExtendedAttribute, File, Filesystem, unix_ftype};
+use crate::filesystem::{FsFileReadSeek, WalkEvent, WalkOptions, finish_analyzers, visit_content};
+use crate::search::ExcludeSet;
+use crate::timefmt::format_timestamp;
+use exhume_ntfs::NTFS;
+use exhume_ntfs::mft::{Attribute, AttributeType, DirectoryEntry, MFTRecord, StandardInformation};
+use serde::Serialize;
+use serde_json::{Value, json};
+use std::collections::{HashMap, HashSet};
 use std::error::Error;
-use std::io::{Read, Seek};
+use std::io::{Read, Seek, SeekFrom};
+
+const ROOT_RECORD: u64 = 5;
+/// Record number part of a file reference (the upper 16 bits are the sequence number).
+const REFERENCE_MASK: u64 = 0x0000_FFFF_FFFF_FFFF;
+/// Records read from $MFT at a time by sequential scans.
+const SCAN_CHUNK_RECORDS: u64 = 4096;
+/// Deeper parent chains are treated as loops.
+const MAX_PATH_DEPTH: usize = 1024;
+/// Namespace of the record fields in `File.metadata`.
+pub const METADATA_NAMESPACE: &str = "ntfs";
+/// Key of the $STANDARD_INFORMATION and $FILE_NAME timestamps (UNIX seconds) under
+/// `ntfs` in `File.metadata`.
+pub const TIMESTAMPS_KEY: &str = "timestamps";
+/// Key of every $FILE_NAME of a record under `ntfs` in `File.metadata`.
+pub const NAMES_KEY: &str = "names";
+/// Key of the $OBJECT_ID of a record under `ntfs` in `File.metadata`.
+pub const OBJECT_ID_KEY: &str = "object_id";
+/// Key of the $EA_INFORMATION summary of a record under `ntfs` in `File.metadata`.
+pub const EA_INFORMATION_KEY: &str = "ea_information";
+const EXTEND_RECORD: u64 = 11;
+const OBJECT_ID_INDEX: &str = "$ObjId";
+const OBJECT_ID_INDEX_NAME: &str = "$O";
+/// Name of the file name index of directories.
+const DIRECTORY_INDEX_NAME: &str = "$I30";
+const ATTR_DATA: u32 = 0x80;
+const ATTR_INDEX_ROOT: u32 = 0x90;
+const ATTR_INDEX_ALLOCATION: u32 = 0xA0;
+const ATTR_BITMAP: u32 = 0xB0;
+const ATTR_EA: u32 = 0xE0;
+/// Extended attributes are limited to 64 KiB per file.
+const MAX_EA_SIZE: u64 = 65536;
+/// $EA entry flag: the file cannot be interpreted without the attribute.
+const NEED_EA: u8 = 0x80;
+const ATTR_END: u32 = 0xFFFF_FFFF;
+const INDEX_ENTRY_LAST: u16 = 0x2;
+/// Update sequence arrays protect each 512 bytes of records and index blocks.
+const FIXUP_STRIDE: usize = 512;
+/// Reparse tags of the records that are links or WSL special files.
+const IO_REPARSE_TAG_MOUNT_POINT: u32 = 0xA000_0003;
+const IO_REPARSE_TAG_SYMLINK: u32 = 0xA000_000C;
+const IO_REPARSE_TAG_LX_SYMLINK: u32 = 0xA000_001D;
+const IO_REPARSE_TAG_AF_UNIX: u32 = 0x8000_0023;
+const IO_REPARSE_TAG_LX_FIFO: u32 = 0x8000_0024;
+const IO_REPARSE_TAG_LX_CHR: u32 = 0x8000_0025;
+const IO_REPARSE_TAG_LX_BLK: u32 = 0x8000_0026;
+
+/// One $FILE_NAME attribute of a record.
+#[derive(Debug, Clone, Serialize)]
+pub struct NtfsName {
+    pub name: String,
+    /// `posix`, `win32`, `dos` (8.3 alias of a Win32 name) or `win32+dos`.
+    pub namespace: &'static str,
+    /// Record number of the parent directory.
+    pub parent: u64,
+    /// Sequence number of the parent directory when the name was created.
+    pub parent_sequence: u16,
+}
+
+/// A resident $FILE_NAME value, namespace included: `FileNameAttr` leaves it out.
+struct FileNameValue {
+    /// Reference of the parent directory, sequence number included.
+    parent: u64,
+    /// Created, modified, MFT modified and accessed FILETIMEs.
+    times: [u64; 4],
+    namespace: u8,
+    name: String,
+}
+
+/// Every resident $FILE_NAME of `record`, in attribute order, decoded from its bytes.
+fn file_name_values(record: &MFTRecord) -> Vec<FileNameValue> {
+    record
+        .attributes
+        .iter()
+        .filter_map(|a| match a {
+            Attribute::Resident { header, value, .. }
+                if header.attr_type == AttributeType::FileName && value.len() >= 0x42 =>
+            {
+                let at = |offset: usize| {
+                    u64::from_le_bytes(value[offset..offset + 8].try_into().unwrap())
+                };
+                let length = value[0x40] as usize * 2;
+                let name: Vec<u16> = value
+                    .get(0x42..0x42 + length)?
+                    .chunks_exact(2)
+                    .map(|b| u16::from_le_bytes([b[0], b[1]]))
+                    .collect();
+                Some(FileNameValue {
+                    parent: at(0),
+                    times: [at(0x08), at(0x10), at(0x18), at(0x20)],
+                    namespace: value[0x41],
+                    name: String::from_utf16_lossy(&name),
+                })
+            }
+            _ => None,
+        })
+        .collect()
+}
+
+fn namespace_name(namespace: u8) -> &'static str {
+    match namespace {
+        0 => "posix",
+        1 => "win32",
+        2 => "dos",
+        3 => "win32+dos",
+        _ => "unknown",
+    }
+}
+
+/// Every $FILE_NAME of `record`, in attribute order.
+pub fn record_names(record: &MFTRecord) -> Vec<NtfsName> {
+    file_name_values(record)
+        .into_iter()
+        .map(|fnm| NtfsName {
+            namespace: namespace_name(fnm.namespace),
+            parent: fnm.parent & REFERENCE_MASK,
+            parent_sequence: (fnm.parent >> 48) as u16,
+            name: fnm.name,
+        })
+        .collect()
+}
+
+/// GUID in its registry form, without braces (`{:08x}-{:04x}-{:04x}-...`).
+fn format_guid(b: &[u8]) -> String {
+    format!(
+        "{:08x}-{:04x}-{:04x}-{:02x}{:02x}-{}",
+        u32::from_le_bytes(b[0..4].try_into().unwrap()),
+        u16::from_le_bytes([b[4], b[5]]),
+        u16::from_le_bytes([b[6], b[7]]),
+        b[8],
+        b[9],
+        b[10..16]
+            .iter()
+            .map(|b| format!("{:02x}", b))
+            .collect::<String>()
+    )
+}
+
+/// Object identifiers of a file, as stored in its $OBJECT_ID attribute and in the
+/// `$Extend\$ObjId` index. The birth identifiers are those of the file when the object
+/// identifier was assigned: they survive moves across volumes and are what shortcuts
+/// (LNK) and jump lists record for link tracking.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize)]
+pub struct NtfsObjectId {
+    pub object_id: String,
+    pub birth_volume_id: Option<String>,
+    pub birth_object_id: Option<String>,
+    pub domain_id: Option<String>,
+}
+
+impl NtfsObjectId {
+    /// Object identifier followed by the optional birth volume, birth object and domain
+    /// identifiers; the unset (zero) ones are `None`.
+    fn from_bytes(b: &[u8]) -> Option<Self> {
+        let guid = |at: usize| {
+            b.get(at..at + 16)
+                .filter(|g| g.iter().any(|b| *b != 0))
+                .map(format_guid)
+        };
+        Some(Self {
+            object_id: guid(0)?,
+            birth_volume_id: guid(16),
+            birth_object_id: guid(32),
+            domain_id: guid(48),
+        })
+    }
+}
+
+/// Entry of the $ObjId index: the record carrying an object identifier.
+#[derive(Debug, Clone, Serialize)]
+pub struct ObjectIdEntry {
+    pub record: u64,
+    /// Sequence number of the record when the identifier was indexed; a different
+    /// current sequence means the record was reused since.
+    pub sequence: u16,
+    #[serde(flatten)]
+    pub ids: NtfsObjectId,
+}
+
+/// $OBJECT_ID of `record`.
+pub fn record_object_id(record: &MFTRecord) -> Option<NtfsObjectId> {
+    record.attributes.iter().find_map(|a| match a {
+        Attribute::Resident { header, value, .. }
+            if header.attr_type == AttributeType::ObjectId =>
+        {
+            NtfsObjectId::from_bytes(value)
+        }
+        _ => None,
+    })
+}
+
+/// One entry of the $EA attribute (`FILE_FULL_EA_INFORMATION`). WSL keeps the Unix
+/// owner, mode and device numbers of its files there; some malware hides payloads.
+#[derive(Debug, Clone, Serialize)]
+pub struct NtfsEa {
+    pub name: String,
+    pub need_ea: bool,
+    pub size: usize,
+    /// Value, hex encoded.
+    pub value: String,
+    /// Decoded value of the attributes written by WSL.
+    pub decoded: Option<Value>,
+}
+
+/// Flags, name and value of each entry of a $EA value.
+pub(crate) fn ea_entries(data: &[u8]) -> Vec<(u8, String, &[u8])> {
+    let mut entries = Vec::new();
+    let mut at = 0;
+    while let Some(entry) = data.get(at..).filter(|e| e.len() >= 8) {
+        let next = u32::from_le_bytes(entry[0..4].try_into().unwrap()) as usize;
+        let name_len = entry[5] as usize;
+        let value_len = u16::from_le_bytes([entry[6], entry[7]]) as usize;
+        // The name is NUL terminated, the value follows.
+        let name = entry.get(8..8 + name_len);
+        let value = entry.get(9 + name_len..9 + name_len + value_len);
+        let (Some(name), Some(value)) = (name, value) else {
+            break;
+        };
+        entries.push((entry[4], String::from_utf8_lossy(name).into_owned(), value));
+        if next == 0 {
+            break;
+        }
+        at += next;
+    }
+    entries
+}
+
+/// Metadata WSL stores in `$LXUID`, `$LXGID`, `$LXMOD`, `$LXDEV` (WSL 2 and recent
+/// WSL 1) and `LXATTRB` (older WSL 1).
+fn decode_wsl_ea(name: &str, value: &[u8]) -> Option<Value> {
+    let u32_at = |at: usize| {
+        value
+            .get(at..at + 4)
+            .map(|b| u32::from_le_bytes(b.try_into().unwrap()))
+    };
+    let u64_at = |at: usize| {
+        value
+            .get(at..at + 8)
+            .map(|b| u64::from_le_bytes(b.try_into().unwrap()))
+    };
+    let mode = |mode: u32| json!({"mode": format!("{:o}", mode), "ftype": unix_ftype(mode)});
+    match name {
+        "$LXUID" => Some(json!({"uid": u32_at(0)?})),
+        "$LXGID" => Some(json!({"gid": u32_at(0)?})),
+        "$LXMOD" => Some(mode(u32_at(0)?)),
+        "$LXDEV" => Some(json!({"major": u32_at(0)?, "minor": u32_at(4)?})),
+        "LXATTRB" => {
+            let mut decoded = mode(u32_at(4)?);
+            decoded["uid"] = json!(u32_at(8)?);
+            decoded["gid"] = json!(u32_at(12)?);
+            decoded["rdev"] = json!(u32_at(16)?);
+            decoded["accessed"] = json!(u64_at(32)?);
+            decoded["modified"] = json!(u64_at(40)?);
+            decoded["changed"] = json!(u64_at(48)?);
+            Some(decoded)
+        }
+        _ => None,
+    }
+}
+
+/// Decoded entries of a resident $EA of `record`; `None` without one, or when it is
+/// non-resident (read it through `extended_attributes`).
+pub fn record_eas(record: &MFTRecord) -> Option<Vec<NtfsEa>> {
+    record.attributes.iter().find_map(|a| match a {
+        Attribute::Resident { header, value, .. } if header.attr_type == AttributeType::Ea => Some(
+            ea_entries(value)
+                .into_iter()
+                .map(|(flags, name, value)| NtfsEa {
+                    need_ea: flags & NEED_EA != 0,
+                    size: value.len(),
+                    value: value.iter().map(|b| format!("{:02x}", b)).collect(),
+                    decoded: decode_wsl_ea(&name, value),
+                    name,
+                })
+                .collect(),
+        ),
+        _ => None,
+    })
+}
+
+/// $EA_INFORMATION of `record`: sizes of its $EA and count of entries flagged NEED_EA.
+fn record_ea_information(record: &MFTRecord) -> Option<Value> {
+    record.attributes.iter().find_map(|a| match a {
+        Attribute::Resident { header, value, .. }
+            if header.attr_type == AttributeType::EaInformation && value.len() >= 8 =>
+        {
+            Some(json!({
+                "packed_size": u16::from_le_bytes([value[0], value[1]]),
+                "need_ea_count": u16::from_le_bytes([value[2], value[3]]),
+                "unpacked_size": u32::from_le_bytes(value[4..8].try_into().unwrap()),
+            }))
+        }
+        _ => None,
+    })
+}
+
+/// Tag and data of the resident $REPARSE_POINT of `record`.
+fn record_reparse_point(record: &MFTRecord) -> Option<(u32, &[u8])> {
+    record.attributes.iter().find_map(|a| match a {
+        Attribute::Resident { header, value, .. }
+            if header.attr_type == AttributeType::ReparsePoint && value.len() >= 8 =>
+        {
+            let tag = u32::from_le_bytes(value[0..4].try_into().unwrap());
+            let length = u16::from_le_bytes([value[4], value[5]]) as usize;
+            Some((tag, value.get(8..8 + length)?))
+        }
+        _ => None,
+    })
+}
+
+/// Target of a symbolic link or junction reparse point: its print name, else its
+/// substitute name (`\??\C:\...`).
+fn reparse_target(tag: u32, data: &[u8]) -> Option<String> {
+    let u16_at = |at: usize| {
+        data.get(at..at + 2)
+            .map(|b| u16::from_le_bytes([b[0], b[1]]) as usize)
+    };
+    let (substitute_at, substitute_len) = (u16_at(0)?, u16_at(2)?);
+    let (print_at, print_len) = (u16_at(4)?, u16_at(6)?);
+    // Symbolic links have a flags field before the names, junctions do not.
+    let names = data.get(if tag == IO_REPARSE_TAG_SYMLINK { 12 } else { 8 }..)?;
+    let name = |at: usize, len: usize| {
+        let units: Vec<u16> = names
+            .get(at..at + len)?
+            .chunks_exact(2)
+            .map(|b| u16::from_le_bytes([b[0], b[1]]))
+            .collect();
+        Some(String::from_utf16_lossy(&units)).filter(|name| !name.is_empty())
+    };
+    name(print_at, print_len).or_else(|| name(substitute_at, substitute_len))
+}
+
+/// Unix mode and device numbers WSL keeps in the $EA of `record` (`$LXMOD` and
+/// `$LXDEV`, or `LXATTRB`).
+fn record_wsl_mode(record: &MFTRecord) -> (Option<u32>, Option<(u32, u32)>) {
+    let (mut mode, mut device) = (None, None);
+    let Some(eas) = record.attributes.iter().find_map(|a| match a {
+        Attribute::Resident { header, value, .. } if header.attr_type == AttributeType::Ea => {
+            Some(ea_entries(value))
+        }
+        _ => None,
+    }) else {
+        return (mode, device);
+    };
+    let u32_at = |value: &[u8], at: usize| {
+        value
+            .get(at..at + 4)
+            .map(|b| u32::from_le_bytes(b.try_into().unwrap()))
+    };
+    for (_, name, value) in eas {
+        match name.as_str() {
+            "$LXMOD" => mode = u32_at(value, 0),
+            "$LXDEV" => device = u32_at(value, 0).zip(u32_at(value, 4)),
+            "LXATTRB" => {
+                mode = mode.or(u32_at(value, 4));
+                // Linux `dev_t` encoding of the device numbers.
+                device = device.or(u32_at(value, 16).map(|rdev| {
+                    (
+                        (rdev & 0xfff00) >> 8,
+                        (rdev & 0xff) | ((rdev >> 12) & 0xfff00),
+                    )
+                }));
+            }
+            _ => {}
+        }
+    }
+    (mode, device)
+}
+
+/// Type of a record, one of `FTYPES`, with what goes with it.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct NtfsRecordType {
+    pub ftype: &'static str,
+    /// Major and minor numbers of device files.
+    pub device: Option<(u32, u32)>,
+    /// Target of symbolic links and junctions.
+    pub symlink_target: Option<String>,
+}
+
+/// Type of `record`: symbolic links, junctions and WSL special files by their reparse
+/// tag, else by the Unix mode WSL keeps in the $EA, else a directory or a file.
+pub fn record_type(record: &MFTRecord) -> NtfsRecordType {
+    let (mode, device) = record_wsl_mode(record);
+    let reparse = record_reparse_point(record);
+    let mut symlink_target = None;
+    let ftype = match reparse {
+        Some((tag @ (IO_REPARSE_TAG_SYMLINK | IO_REPARSE_TAG_MOUNT_POINT), data)) => {
+            symlink_target = reparse_target(tag, data);
+            "symlink"
+        }
+        Some((IO_REPARSE_TAG_LX_SYMLINK, data)) => {
+            // A version number, then the UTF-8 target.
+            symlink_target = data
+                .get(4..)
+                .map(|target| String::from_utf8_lossy(target).into_owned());
+            "symlink"
+        }
+        Some((IO_REPARSE_TAG_AF_UNIX, _)) => "socket",
+        Some((IO_REPARSE_TAG_LX_FIFO, _)) => "fifo",
+        Some((IO_REPARSE_TAG_LX_CHR, _)) => "chardev",
+        Some((IO_REPARSE_TAG_LX_BLK, _)) => "blockdev",
+        _ => match mode.map(unix_ftype) {
+            Some(ftype) if ftype != "unknown" => ftype,
+            _ if record.is_dir() => "dir",
+            _ => "file",
+        },
+    };
+    NtfsRecordType {
+        ftype,
+        device: device.filter(|_| matches!(ftype, "chardev" | "blockdev")),
+        symlink_target,
+    }
+}
+
+/// Apply the update sequence array of a record or index block, checking that every
+/// protected sector ends with the sequence number.
+pub(crate) fn apply_fixups(block: &mut [u8]) -> Result<(), Box<dyn Error>> {
+    if block.len() < 8 {
+        return Err("block too short for an update sequence array".into());
+    }
+    let offset = u16::from_le_bytes([block[4], block[5]]) as usize;
+    let count = u16::from_le_bytes([block[6], block[7]]) as usize;
+    if count == 0 || offset + count * 2 > block.len() || (count - 1) * FIXUP_STRIDE > block.len() {
+        return Err("invalid update sequence array".into());
+    }
+    let usn = [block[offset], block[offset + 1]];
+    for sector in 1..count {
+        let end = sector * FIXUP_STRIDE - 2;
+        if block[end..end + 2] != usn {
+            return Err("torn write: update sequence mismatch".into());
+        }
+        let at = offset + sector * 2;
+        block[end] = block[at];
+        block[end + 1] = block[at + 1];
+    }
+    Ok(())
+}
+
+/// Type, name and content of each attribute of a raw record: the value of resident
+/// attributes, the mapping pairs and real size of non-resident ones.
+pub(crate) fn raw_attributes(record: &[u8]) -> Vec<(u32, String, RawAttribute<'_>)> {
+    let mut attributes = Vec::new();
+    let Some(&[low, high]) = record.get(0x14..0x16) else {
+        return attributes;
+    };
+    let mut at = u16::from_le_bytes([low, high]) as usize;
+    while at + 0x18 <= record.len() {
+        let kind = u32::from_le_bytes(record[at..at + 4].try_into().unwrap());
+        let length = u32::from_le_bytes(record[at + 4..at + 8].try_into().unwrap()) as usize;
+        if kind == ATTR_END || length < 0x18 || at + length > record.len() {
+            break;
+        }
+        let attr = &record[at..at + length];
+        let name_at = u16::from_le_bytes([attr[0x0A], attr[0x0B]]) as usize;
+        let name: Vec<u16> = attr
+            .get(name_at..name_at + attr[9] as usize * 2)
+            .unwrap_or_default()
+            .chunks_exact(2)
+            .map(|c| u16::from_le_bytes([c[0], c[1]]))
+            .collect();
+        let content = if attr[8] == 0 {
+            let value_len = u32::from_le_bytes(attr[0x10..0x14].try_into().unwrap()) as usize;
+            let value_at = u16::from_le_bytes([attr[0x14], attr[0x15]]) as usize;
+            attr.get(value_at..value_at + value_len)
+                .map(RawAttribute::Resident)
+        } else if attr.len() >= 0x40 {
+            let runs_at = u16::from_le_bytes([attr[0x20], attr[0x21]]) as usize;
+            let size = u64::from_le_bytes(attr[0x30..0x38].try_into().unwrap());
+            let compressed = attr[0x0C] != 0;
+            attr.get(runs_at..)
+                .map(|runs| RawAttribute::NonResident(runs, size, compressed))
+        } else {
+            None
+        };
+        if let Some(content) = content {
+            attributes.push((kind, String::from_utf16_lossy(&name), content));
+        }
+        at += length;
+    }
+    attributes
+}
+
+/// Clusters allocated to the unnamed `$DATA` attribute; resident content takes none
+/// outside the record. `None` for compressed and sparse attributes, whose allocated size
+/// also counts the clusters they save.
+fn data_size_on_disk(record: &MFTRecord) -> Option<u64> {
+    record.attributes.iter().find_map(|attr| match attr {
+        Attribute::Resident { header, .. }
+            if header.attr_type == AttributeType::Data && header.name_length == 0 =>
+        {
+            Some(Some(0))
+        }
+        Attribute::NonResident {
+            header,
+            non_resident,
+            ..
+        } if header.attr_type == AttributeType::Data && header.name_length == 0 => {
+            Some((header.flags & 0x8001 == 0).then_some(non_resident.allocated_size))
+        }
+        _ => None,
+    })?
+}
+
+pub(crate) enum RawAttribute<'a> {
+    Resident(&'a [u8]),
+    /// Mapping pairs, real size, and whether the attribute is compressed.
+    NonResident(&'a [u8], u64, bool),
+}
+
+/// Decode mapping pairs into (first cluster, cluster count) runs; sparse runs have no
+/// first cluster.
+pub(crate) fn decode_runs(mut pairs: &[u8]) -> Vec<(Option<u64>, u64)> {
+    let mut runs = Vec::new();
+    let mut lcn: i64 = 0;
+    while let Some(&header) = pairs.first() {
+        let (length_size, offset_size) = ((header & 0x0F) as usize, (header >> 4) as usize);
+        if header == 0 || length_size == 0 || length_size > 8 || offset_size > 8 {
+            break;
+        }
+        let Some(fields) = pairs.get(1..1 + length_size + offset_size) else {
+            break;
+        };
+        let mut length = [0u8; 8];
+        length[..length_size].copy_from_slice(&fields[..length_size]);
+        let length = u64::from_le_bytes(length);
+        if offset_size == 0 {
+            runs.push((None, length));
+        } else {
+            let offset = &fields[length_size..];
+            // Sign extend the relative offset.
+            let fill = if offset[offset_size - 1] & 0x80 != 0 {
+                0xFF
+            } else {
+                0
+            };
+            let mut delta = [fill; 8];
+            delta[..offset_size].copy_from_slice(offset);
+            // A run before the start of the volume, or past any, ends a corrupted list.
+            match lcn.checked_add(i64::from_le_bytes(delta)) {
+                Some(next) if next >= 0 => lcn = next,
+                _ => break,
+            }
+            runs.push((Some(lcn as u64), length));
+        }
+        pairs = &pairs[1 + length_size + offset_size..];
+    }
+    runs
+}
+
+/// Raw record `id` read from $MFT, with its fixups applied.
+fn raw_record<T: Read + Seek>(ntfs: &mut NTFS<T>, id: u64) -> Result<Vec<u8>, Box<dyn Error>> {
+    let scanner = MftScanner::new(ntfs, 0)?;
+    let mut record = ntfs.read_file_slice(
+        &scanner.mft,
+        id * scanner.record_size,
+        scanner.record_size as usize,
+    )?;
+    if record.len() < scanner.record_size as usize || &record[..4] != b"FILE" {
+        return Err(format!("record {} is not a FILE record", id).into());
+    }
+    apply_fixups(&mut record)?;
+    Ok(record)
+}
+
+/// The `size` bytes of a non-resident attribute, read along its mapping pairs.
+fn read_non_resident<T: Read + Seek>(
+    ntfs: &mut NTFS<T>,
+    pairs: &[u8],
+    size: u64,
+) -> Result<Vec<u8>, Box<dyn Error>> {
+    let cluster_size = ntfs.pbs.cluster_size() as u64;
+    let mut content = Vec::new();
+    for (lcn, clusters) in decode_runs(pairs) {
+        if content.len() as u64 >= size {
+            break;
+        }
+        let length = clusters
+            .saturating_mul(cluster_size)
+            .min(size - content.len() as u64);
+        match lcn {
+            // Read rather than allocated up front: a corrupted run cannot claim more
+            // memory than the volume holds.
+            Some(lcn) => {
+                let at = lcn
+                    .checked_mul(cluster_size)
+                    .ok_or("run past the end of the volume")?;
+                ntfs.body.seek(SeekFrom::Start(at))?;
+                if (&mut ntfs.body).take(length).read_to_end(&mut content)? < length as usize {
+                    return Err("run past the end of the volume".into());
+                }
+            }
+            None => content.resize(content.len() + length as usize, 0),
+        }
+    }
+    Ok(content)
+}
+
+/// Append the entries of an index node of $ObjId:$O to `out`; `node` starts at its
+/// node header.
+pub(crate) fn object_id_entries(node: &[u8], out: &mut Vec<ObjectIdEntry>) {
+    if node.len() < 16 {
+        return;
+    }
+    let mut at = u32::from_le_bytes(node[0..4].try_into().unwrap()) as usize;
+    let end = (u32::from_le_bytes(node[4..8].try_into().unwrap()) as usize).min(node.len());
+    while at + 0x10 <= end {
+        let entry = &node[at..end];
+        let data_at = u16::from_le_bytes([entry[0], entry[1]]) as usize;
+        let data_len = u16::from_le_bytes([entry[2], entry[3]]) as usize;
+        let length = u16::from_le_bytes([entry[8], entry[9]]) as usize;
+        let flags = u16::from_le_bytes([entry[0x0C], entry[0x0D]]);
+        if flags & INDEX_ENTRY_LAST != 0 || length < 0x10 {
+            break;
+        }
+        let key = entry.get(0x10..0x20);
+        let data = entry
+            .get(data_at..data_at + data_len)
+            .filter(|d| d.len() >= 8);
+        if let (Some(key), Some(data)) = (key, data) {
+            let reference = u64::from_le_bytes(data[0..8].try_into().unwrap());
+            let mut ids = key.to_vec();
+            ids.extend_from_slice(data.get(8..56).unwrap_or_default());
+            if let Some(ids) = NtfsObjectId::from_bytes(&ids) {
+                out.push(ObjectIdEntry {
+                    record: reference & REFERENCE_MASK,
+                    sequence: (reference >> 48) as u16,
+                    ids,
+                });
+            }
+        }
+        at += length;
+    }
+}
+
+/// Every entry of the `$Extend\$ObjId` index, in index order.
+pub fn object_id_index<T: Read + Seek>(
+    ntfs: &mut NTFS<T>,
+) -> Result<Vec<ObjectIdEntry>, Box<dyn Error>> {
+    let index = ntfs
+        .list_dir(EXTEND_RECORD)?
+        .into_iter()
+        .find(|entry| entry.name == OBJECT_ID_INDEX)
+        .ok_or("the volume has no $Extend\\$ObjId index")?;
+    let record = raw_record(ntfs, index.file_id & REFERENCE_MASK)?;
+    let mut entries = Vec::new();
+    let mut block_size = 0;
+    let mut allocation = None;
+    let mut bitmap = None;
+    for (kind, name, content) in raw_attributes(&record) {
+        if name != OBJECT_ID_INDEX_NAME {
+            continue;
+        }
+        match (kind, content) {
+            (ATTR_INDEX_ROOT, RawAttribute::Resident(root)) if root.len() >= 0x20 => {
+                block_size = u32::from_le_bytes(root[8..12].try_into().unwrap()) as usize;
+                object_id_entries(&root[0x10..], &mut entries);
+            }
+            (ATTR_INDEX_ALLOCATION, RawAttribute::NonResident(pairs, size, _)) => {
+                allocation = Some((pairs.to_vec(), size));
+            }
+            (ATTR_BITMAP, RawAttribute::Resident(bits)) => bitmap = Some(bits.to_vec()),
+            _ => {}
+        }
+    }
+    let Some((pairs, size)) = allocation else {
+        return Ok(entries);
+    };
+    if !(FIXUP_STRIDE..=65536).contains(&block_size) {
+        return Err(format!("invalid $ObjId index block size {}", block_size).into());
+    }
+    let mut content = read_non_resident(ntfs, &pairs, size)?;
+    for (number, block) in content.chunks_exact_mut(block_size).enumerate() {
+        // Blocks freed from the index keep their stale entries.
+        let in_use = bitmap.as_ref().is_none_or(|bits| {
+            bits.get(number / 8)
+                .is_some_and(|b| b & (1 << (number % 8)) != 0)
+        });
+        if !in_use || &block[..4] != b"INDX" || apply_fixups(block).is_err() {
+            continue;
+        }
+        object_id_entries(&block[0x18..], &mut entries);
+    }
+    Ok(entries)
+}
+
+/// Entry of the $ObjId index for `object_id`, given with or without braces.
+pub fn find_object_id<T: Read + Seek>(
+    ntfs: &mut NTFS<T>,
+    object_id: &str,
+) -> Result<Option<ObjectIdEntry>, Box<dyn Error>> {
+    let wanted = object_id
+        .trim_matches(|c| c == '{' || c == '}')
+        .to_ascii_lowercase();
+    Ok(object_id_index(ntfs)?
+        .into_iter()
+        .find(|entry| entry.ids.object_id == wanted))
+}
+
+/// Parent and name of every hard link of `record`: its names but the DOS 8.3 aliases.
+fn hard_links(record: &MFTRecord) -> Vec<(u64, String)> {
+    let mut links: Vec<(u64, String)> = Vec::new();
+    for name in record_names(record) {
+        let link = (name.parent, name.name);
+        if name.namespace != "dos" && !links.contains(&link) {
+            links.push(link);
+        }
+    }
+    links
+}
 
 impl FileCommon for MFTRecord {
     fn id(&self) -> u64 {
@@ -71,6 +787,210 @@ fn filetime_to_unix_secs(ft: u64) -> u64 {
     (ft / 10_000_000).saturating_sub(11_644_473_600)
 }
 
+/// `None` for an unset (zero) FILETIME.
+fn filetime_to_unix(ft: u64) -> Option<u64> {
+    (ft != 0).then(|| filetime_to_unix_secs(ft))
+}
+
+/// Sequential reader of the in-use records of $MFT: its content is read in large chunks
+/// and records are parsed from the buffer, instead of one seek and read per record.
+struct MftScanner {
+    mft: MFTRecord,
+    record_size: u64,
+    next: u64,
+    end: u64,
+    buffer: Vec<u8>,
+    buffer_first: u64,
+    /// Skip the records without an index, so only directories are parsed.
+    directories_only: bool,
+}
+
+impl MftScanner {
+    fn new<T: Read + Seek>(ntfs: &mut NTFS<T>, first: u64) -> Result<Self, Box<dyn Error>> {
+        let mft = ntfs.get_file_id(0)?;
+        let header = ntfs.read_file_slice(&mft, 0, 0x20)?;
+        if header.len() < 0x20 || &header[..4] != b"FILE" {
+            return Err("$MFT does not start with a FILE record".into());
+        }
+        let record_size = u32::from_le_bytes(header[0x1C..0x20].try_into().unwrap()) as u64;
+        if !record_size.is_power_of_two() || !(256..=65536).contains(&record_size) {
+            return Err(format!("invalid MFT record size {}", record_size).into());
+        }
+        let end = FileCommon::size(&mft) / record_size;
+        Ok(Self {
+            mft,
+            record_size,
+            next: first,
+            end,
+            buffer: Vec::new(),
+            buffer_first: first,
+            directories_only: false,
+        })
+    }
+
+    /// Next in-use record and its identifier. Base records spreading their attributes
+    /// over extension records are fetched whole through the parser.
+    fn next<T: Read + Seek>(
+        &mut self,
+        ntfs: &mut NTFS<T>,
+    ) -> Result<Option<(u64, MFTRecord)>, Box<dyn Error>> {
+        while self.next < self.end {
+            let id = self.next;
+            self.next += 1;
+            let buffered = self.buffer.len() as u64 / self.record_size;
+            if id < self.buffer_first || id >= self.buffer_first + buffered {
+                let count = SCAN_CHUNK_RECORDS.min(self.end - id);
+                self.buffer = ntfs.read_file_slice(
+                    &self.mft,
+                    id * self.record_size,
+                    (count * self.record_size) as usize,
+                )?;
+                self.buffer_first = id;
+                if (self.buffer.len() as u64) < self.record_size {
+                    return Err(format!("$MFT is truncated at record {}", id).into());
+                }
+            }
+            let at = ((id - self.buffer_first) * self.record_size) as usize;
+            let raw = &self.buffer[at..at + self.record_size as usize];
+            // FILE_RECORD_SEGMENT_IN_USE and FILE_NAME_INDEX_PRESENT
+            let flags = u16::from_le_bytes([raw[0x16], raw[0x17]]);
+            if &raw[..4] != b"FILE"
+                || flags & 0x0001 == 0
+                || self.directories_only && flags & 0x0002 == 0
+            {
+                continue;
+            }
+            let Ok(record) = MFTRecord::from_bytes(raw, Some(id)) else {
+                continue;
+            };
+            let has_list = record.attributes.iter().any(|a| match a {
+                Attribute::Resident { header, .. } | Attribute::NonResident { header, .. } => {
+                    header.attr_type == AttributeType::AttributeList
+                }
+            });
+            if !has_list {
+                return Ok(Some((id, record)));
+            }
+            if let Ok(record) = ntfs.get_file_id(id) {
+                return Ok(Some((id, record)));
+            }
+        }
+        Ok(None)
+    }
+}
+
+/// Parent record and name of the primary (Win32 when there is one) name of a record.
+fn parent_link(record: &MFTRecord) -> Option<(u64, String)> {
+    let primary = record.primary_name()?;
+    let names = record.file_names();
+    let link = names
+        .iter()
+        .find(|n| n.name == primary)
+        .or_else(|| names.first())?;
+    Some((link.parent_ref & REFERENCE_MASK, primary))
+}
+
+/// Parent and name of every directory, collected in one pass over $MFT. Paths are
+/// resolved from it without fetching any record, and it stays compact on volumes with
+/// millions of records.
+struct ParentMap {
+    separator: String,
+    directories: HashMap<u64, (u64, Box<str>)>,
+    /// Directories matching the exclude set of the walk.
+    excluded: HashSet<u64>,
+}
+
+impl ParentMap {
+    fn build<T: Read + Seek>(
+        ntfs: &mut NTFS<T>,
+        exclude: Option<&ExcludeSet>,
+    ) -> Result<Self, Box<dyn Error>> {
+        let mut directories = HashMap::new();
+        let mut scanner = MftScanner::new(ntfs, 0)?;
+        scanner.directories_only = true;
+        while let Some((id, record)) = scanner.next(ntfs)? {
+            if let Some((parent, name)) = parent_link(&record) {
+                directories.insert(id, (parent, name.into_boxed_str()));
+            }
+        }
+        let mut map = Self {
+            separator: ntfs.path_separator(),
+            directories,
+            excluded: HashSet::new(),
+        };
+        if let Some(exclude) = exclude {
+            let mut excluded: HashSet<u64> = map
+                .directories
+                .iter()
+                .filter(|(id, (parent, name))| {
+                    **id != ROOT_RECORD
+                        && map
+                            .resolve(*parent, name)
+                            .is_some_and(|p| exclude.is_excluded(&p, &map.separator))
+                })
+                .map(|(id, _)| *id)
+                .collect();
+            if exclude.is_excluded(&map.separator, &map.separator) {
+                excluded.insert(ROOT_RECORD);
+            }
+            map.excluded = excluded;
+        }
+        Ok(map)
+    }
+
+    /// Path of the entry `name` of directory `parent`, `None` when the directory is not
+    /// reachable from the root or lies in an excluded directory.
+    fn resolve(&self, parent: u64, name: &str) -> Option<String> {
+        let mut names = vec![name];
+        let mut current = parent;
+        while current != ROOT_RECORD {
+            if names.len() > MAX_PATH_DEPTH || self.excluded.contains(&current) {
+                return None;
+            }
+            let (parent, name) = self.directories.get(&current)?;
+            names.push(name);
+            current = *parent;
+        }
+        if self.excluded.contains(&ROOT_RECORD) {
+            return None;
+        }
+        names.reverse();
+        Some(format!("{}{}", self.separator, names.join(&self.separator)))
+    }
+
+    /// Path and name of the hard links of `record` other than its primary name, when
+    /// reachable from the root and not excluded.
+    fn link_paths(
+        &self,
+        record: &MFTRecord,
+        exclude: Option<&ExcludeSet>,
+    ) -> Vec<(String, String)> {
+        let primary = parent_link(record);
+        hard_links(record)
+            .into_iter()
+            .filter(|(parent, name)| {
+                primary
+                    .as_ref()
+                    .is_none_or(|(p, n)| p != parent || n != name)
+            })
+            .filter_map(|(parent, name)| Some((self.resolve(parent, &name)?, name)))
+            .filter(|(path, _)| !exclude.is_some_and(|x| x.is_excluded(path, &self.separator)))
+            .collect()
+    }
+
+    /// Path of `record`, `None` when it is not reachable from the root or excluded.
+    fn record_path(&self, id: u64, record: &MFTRecord) -> Option<String> {
+        if id == ROOT_RECORD {
+            return (!self.excluded.contains(&ROOT_RECORD)).then(|| self.separator.clone());
+        }
+        let (parent, name) = parent_link(record)?;
+        if self.excluded.contains(&id) {
+            return None;
+        }
+        self.resolve(parent, &name)
+    }
+}
+
 impl<T: Read + Seek> Filesystem for NTFS<T> {
     type FileType = MFTRecord;
     type DirectoryType = DirectoryEntry;
@@ -119,6 +1039,233 @@ impl<T: Read + Seek> Filesystem for NTFS<T> {
         5
     }
 
+    fn file_holes(
+        &mut self,
+        record: &Self::FileType,
+    ) -> Result<Option<Vec<ByteRange>>, Box<dyn Error>> {
+        let raw = raw_record(self, record.id)?;
+        let data = raw_attributes(&raw)
+            .into_iter()
+            .find(|(kind, name, _)| *kind == ATTR_DATA && name.is_empty());
+        let (runs, size) = match data {
+            Some((_, _, RawAttribute::Resident(_))) => return Ok(Some(Vec::new())),
+            // The sparse runs of compression units are not holes.
+            Some((_, _, RawAttribute::NonResident(pairs, size, false))) => {
+                (decode_runs(pairs), size)
+            }
+            // Compressed, or described by extension records.
+            _ => return Ok(None),
+        };
+        let cluster_size = self.pbs.cluster_size() as u64;
+        let mut holes = Vec::new();
+        let mut offset = 0;
+        for (lcn, clusters) in runs {
+            let length = clusters.saturating_mul(cluster_size);
+            if lcn.is_none() && offset < size {
+                holes.push((offset, length.min(size - offset)));
+            }
+            offset = offset.saturating_add(length);
+        }
+        // The rest of the runs is in an extension record.
+        if offset < size {
+            return Ok(None);
+        }
+        Ok(Some(holes))
+    }
+
+    fn extended_attributes(
+        &mut self,
+        record: &Self::FileType,
+    ) -> Result<Vec<ExtendedAttribute>, Box<dyn Error>> {
+        let mut data = None;
+        for attr in &record.attributes {
+            match attr {
+                Attribute::Resident { header, value, .. }
+                    if header.attr_type == AttributeType::Ea =>
+                {
+                    data = Some(value.clone());
+                }
+                Attribute::NonResident { header, .. } if header.attr_type == AttributeType::Ea => {
+                    let raw = raw_record(self, record.id)?;
+                    let (pairs, size) = raw_attributes(&raw)
+                        .into_iter()
+                        .find_map(|(kind, _, content)| match (kind, content) {
+                            (ATTR_EA, RawAttribute::NonResident(pairs, size, _)) => {
+                                Some((pairs.to_vec(), size))
+                            }
+                            _ => None,
+                        })
+                        .ok_or_else(|| {
+                            format!("the $EA of record {} is in an extension record", record.id)
+                        })?;
+                    data = Some(read_non_resident(self, &pairs, size.min(MAX_EA_SIZE))?);
+                }
+                _ => {}
+            }
+        }
+        Ok(data
+            .as_deref()
+            .map(ea_entries)
+            .unwrap_or_default()
+            .into_iter()
+            .map(|(_, name, value)| (name, value.to_vec()))
+            .collect())
+    }
+
+    /// The `$I30` INDX blocks of `$INDEX_ALLOCATION` as stored (fixups not applied, slack
+    /// included), or the `$INDEX_ROOT` value of directories small enough to have none.
+    fn read_directory_data(
+        &mut self,
+        record: &Self::FileType,
+    ) -> Result<Option<Vec<u8>>, Box<dyn Error>> {
+        if !record.is_dir() {
+            return Err("not a directory".into());
+        }
+        let raw = raw_record(self, record.id)?;
+        let mut root = None;
+        for (kind, name, content) in raw_attributes(&raw) {
+            if name != DIRECTORY_INDEX_NAME {
+                continue;
+            }
+            match (kind, content) {
+                (ATTR_INDEX_ALLOCATION, RawAttribute::NonResident(pairs, size, _)) => {
+                    return Ok(Some(read_non_resident(self, pairs, size)?));
+                }
+                (ATTR_INDEX_ROOT, RawAttribute::Resident(value)) => root = Some(value.to_vec()),
+                _ => {}
+            }
+        }
+        root.map(Some).ok_or_else(|| {
+            format!(
+                "record {} has no {} index in its base record",
+                record.id, DIRECTORY_INDEX_NAME
+            )
+            .into()
+        })
+    }
+
+    fn is_deleted(&self, record: &Self::FileType) -> Option<bool> {
+        // FILE_RECORD_SEGMENT_IN_USE is cleared when the record is freed; records never
+        // used carry no attributes at all.
+        Some(record.header.flags & 0x0001 == 0 && !record.attributes.is_empty())
+    }
+
+    /// NTFS walks read $MFT sequentially rather than following directory indexes, so
+    /// records are emitted in record order and a checkpoint is the last record emitted.
+    fn walk_fs_with(
+        &mut self,
+        options: WalkOptions,
+        callback: &mut dyn FnMut(WalkEvent),
+    ) -> Result<(), Box<dyn Error>> {
+        // $MFT order is not name order: sorted walks go down the directory tree.
+        if options.sorted {
+            if options.resume.as_ref().is_some_and(|s| s.queue.is_empty()) {
+                return Err("the checkpoint was written by an unsorted NTFS walk".into());
+            }
+            return walk_tree(self, options, callback);
+        }
+        let WalkOptions {
+            resume,
+            checkpoint_every,
+            mut on_checkpoint,
+            mut visitor,
+            exclude,
+            mut analyzers,
+            all_names,
+            sorted: _,
+        } = options;
+
+        let mut state = resume.unwrap_or_default();
+        if !state.queue.is_empty() {
+            return Err("the checkpoint was not written by an NTFS walk".into());
+        }
+        let first = state.last_record.map_or(0, |id| id + 1);
+        if first > 0 {
+            callback(WalkEvent::Status(format!(
+                "Resuming walk after {} records (MFT record {})",
+                state.emitted, first
+            )));
+        }
+
+        callback(WalkEvent::Status(
+            "Reading the directory records of $MFT...".into(),
+        ));
+        let parents = ParentMap::build(self, exclude)?;
+        callback(WalkEvent::Status(format!(
+            "{} directories found, reading $MFT...",
+            parents.directories.len()
+        )));
+        let mut scanner = MftScanner::new(self, first)?;
+        while let Some((record_id, record)) = scanner.next(self)? {
+            let Some(path) = parents.record_path(record_id, &record) else {
+                continue;
+            };
+            if exclude.is_some_and(|x| x.is_excluded(&path, &parents.separator)) {
+                continue;
+            }
+            let mut file_obj = self.record_to_file(&record, record_id, &path);
+            let link_paths = if all_names && record_id != ROOT_RECORD {
+                parents.link_paths(&record, exclude)
+            } else {
+                Vec::new()
+            };
+            if !record.is_dir() && (visitor.is_some() || !analyzers.is_empty()) {
+                let mut reader = FsFileReadSeek::new(self, record);
+                visit_content(visitor.as_mut(), &mut analyzers, &mut file_obj, &mut reader);
+            }
+
+            // The other hard links share the record, its content results included.
+            let links: Vec<File> = link_paths
+                .into_iter()
+                .map(|(path, name)| {
+                    let mut link = file_obj.clone();
+                    link.absolute_path = path;
+                    link.name = name;
+                    link
+                })
+                .collect();
+            callback(WalkEvent::File(file_obj));
+            for link in links {
+                callback(WalkEvent::File(link));
+            }
+
+            state.emitted += 1;
+            state.last_record = Some(record_id);
+            if checkpoint_every > 0
+                && state.emitted.is_multiple_of(checkpoint_every)
+                && let Some(on_checkpoint) = on_checkpoint.as_mut()
+            {
+                on_checkpoint(&mut state);
+            }
+        }
+
+        finish_analyzers(&mut analyzers)
+    }
+
+    fn block_allocation(&mut self, block: u64) -> Result<Option<bool>, Box<dyn Error>> {
+        // $Bitmap (MFT record 6) holds one bit per cluster.
+        let bitmap = self.get_file(6)?;
+        let byte = self.read_file_slice(&bitmap, block / 8, 1)?;
+        Ok(byte.first().map(|b| b & (1 << (block % 8)) != 0))
+    }
+
+    fn block_allocation_range(
+        &mut self,
+        first: u64,
+        count: u64,
+    ) -> Result<Vec<Option<bool>>, Box<dyn Error>> {
+        let bitmap = self.get_file(6)?;
+        let start = first / 8;
+        let end = (first + count).div_ceil(8);
+        let bytes = self.read_file_slice(&bitmap, start, (end - start) as usize)?;
+        Ok((first..first + count)
+            .map(|block| {
+                let byte = bytes.get((block / 8 - start) as usize)?;
+                Some(byte & (1 << (block % 8)) != 0)
+            })
+            .collect())
+    }
+
     fn read_file_slice(
         &mut self,
         record: &Self::FileType,
@@ -142,42 +1289,52 @@ impl<T: Read + Seek> Filesystem for NTFS<T> {
             .unwrap_or_else(|| format!("(MFT #{} – unnamed)", file_id));
 
         // Let's prefer $STANDARD_INFORMATION, fall back to first $FILE_NAME.
-        let (c_ft, mft_ft, a_ft) = record
-            .attributes
-            .iter()
-            .find_map(|a| match a {
-                Attribute::Resident { header, value, .. }
-                    if header.attr_type == AttributeType::StandardInformation =>
-                {
-                    StandardInformation::from_bytes(value)
-                        .map(|si| (si.created, si.mft_modified, si.accessed))
-                }
-                _ => None,
+        let si = record.attributes.iter().find_map(|a| match a {
+            Attribute::Resident { header, value, .. }
+                if header.attr_type == AttributeType::StandardInformation =>
+            {
+                StandardInformation::from_bytes(value)
+            }
+            _ => None,
+        });
+        let file_names = record.file_names();
+        let (c_ft, m_ft, mft_ft, a_ft, attrs) = si
+            .as_ref()
+            .map(|si| {
+                (
+                    si.created,
+                    si.modified,
+                    si.mft_modified,
+                    si.accessed,
+                    si.file_attrs,
+                )
             })
             .or_else(|| {
-                record
-                    .file_names()
-                    .into_iter()
-                    .next()
-                    .map(|fnm| (fnm.created, fnm.mft_modified, fnm.accessed))
+                file_names.first().map(|fnm| {
+                    (
+                        fnm.created,
+                        fnm.modified,
+                        fnm.mft_modified,
+                        fnm.accessed,
+                        fnm.flags,
+                    )
+                })
             })
-            .unwrap_or((0, 0, 0)); // if totally missing, leave zeros and map to None below
+            .unwrap_or((0, 0, 0, 0, 0)); // if totally missing, leave zeros and map to None below
 
-        let created = (c_ft != 0).then(|| filetime_to_unix_secs(c_ft));
-        let modified = (mft_ft != 0).then(|| filetime_to_unix_secs(mft_ft));
-        let accessed = (a_ft != 0).then(|| filetime_to_unix_secs(a_ft));
+        let created = filetime_to_unix(c_ft);
+        let modified = filetime_to_unix(m_ft);
+        let accessed = filetime_to_unix(a_ft);
+        let changed = filetime_to_unix(mft_ft);
 
         let mft_ts = if mft_ft == 0 {
             "-".to_string()
         } else {
-            exhume_ntfs::mft::filetime_to_local_datetime(mft_ft)
+            format_timestamp(filetime_to_unix_secs(mft_ft))
         };
 
-        let ftype = if record.is_dir() {
-            "Directory".to_string()
-        } else {
-            "File".to_string()
-        };
+        let record_type = record_type(record);
+        let ftype = record_type.ftype.to_string();
 
         let mut display = format!(
             "{id:<6} - {ftype:<10} - {size:>10} - {mft_ts} - {abs_path}",
@@ -188,33 +1345,82 @@ impl<T: Read + Seek> Filesystem for NTFS<T> {
             abs_path = absolute_path
         );
 
-
-        for fnm in record.file_names() {
+        for fnm in &file_names {
             display.push_str(&format!("\n  - {}", fnm.name));
         }
         for ads in record.alternate_data_streams() {
             display.push_str(&format!("\n  - ads:{}", ads.name));
         }
 
-        let metadata = record.to_json();
+        // Both timestamp sets, so that $SI values predating their $FN counterparts
+        // (timestomping) can be spotted.
+        let mut own = record.to_json();
+        own[NAMES_KEY] = json!(record_names(record));
+        own[OBJECT_ID_KEY] = json!(record_object_id(record));
+        own[EA_INFORMATION_KEY] = json!(record_ea_information(record));
+        own[TIMESTAMPS_KEY] = json!({
+            "standard_information": si.as_ref().map(|si| json!({
+                "created": filetime_to_unix(si.created),
+                "modified": filetime_to_unix(si.modified),
+                "mft_modified": filetime_to_unix(si.mft_modified),
+                "accessed": filetime_to_unix(si.accessed),
+            })),
+            "file_name": file_name_values(record)
+                .iter()
+                .map(|fnm| json!({
+                    "name": fnm.name,
+                    "namespace": namespace_name(fnm.namespace),
+                    "created": filetime_to_unix(fnm.times[0]),
+                    "modified": filetime_to_unix(fnm.times[1]),
+                    "mft_modified": filetime_to_unix(fnm.times[2]),
+                    "accessed": filetime_to_unix(fnm.times[3]),
+                }))
+                .collect::<Vec<_>>(),
+        });
+        let mut common = json!({
+            FLAGS_KEY: dos_attr_names(attrs),
+            STREAMS_KEY: record
+                .alternate_data_streams()
+                .into_iter()
+                .map(|ads| json!({ "name": ads.name, "size": ads.size }))
+                .collect::<Vec<_>>(),
+        });
+        if let Some(eas) = record_eas(record) {
+            common[EXTENDED_ATTRIBUTES_KEY] = json!(eas);
+        }
+        if let Some((major, minor)) = record_type.device {
+            common[DEVICE_KEY] = json!({ "major": major, "minor": minor });
+        }
+        if let Some(target) = record_type.symlink_target {
+            common[SYMLINK_TARGET_KEY] = json!(target);
+        }
+        let metadata = namespaced_metadata(METADATA_NAMESPACE, own, common);
 
         File {
             id: None,
             identifier: file_id,
             absolute_path: absolute_path.to_owned(),
             name,
+            raw_name: None,
             created,
             modified,
             accessed,
-            permissions: None,
+            changed,
+            permissions: Some(dos_attr_string(attrs, record.is_dir())),
             owner: None,
             group: None,
             ftype,
             size: record.size(),
+            size_on_disk: data_size_on_disk(record),
             display: Some(display),
             sig_name: None,
             sig_mime: None,
             sig_exts: None,
+            detected_type: None,
+            ext_mismatch: None,
+            md5: None,
+            sha1: None,
+            sha256: None,
             metadata,
         }
     }
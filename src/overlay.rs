@@ -0,0 +1,733 @@
+//! Union view of stacked layers (`--layer`), for container evidence: Docker image
+//! layers, OverlayFS lower and upper directories. Layers are merged from the lowest to the
+//! uppermost, an upper record replacing the lower one at the same path and directories
+//! merging their entries, following the OverlayFS rules:
+//!
+//! - whiteouts hide a lower path: `.wh.<name>` files (Docker layer tarballs, AUFS) and
+//!   0/0 character devices (OverlayFS upper directories);
+//! - opaque directories hide every lower entry below them: `.wh..wh..opq` files and the
+//!   `trusted.overlay.opaque` (or `user.overlay.opaque`) attribute set to `y`.
+//!
+//! Whiteouts and opaque markers are not listed. Each record keeps the metadata of the
+//! layer providing it, plus its layer under the `overlay` namespace.
+use crate::detected_fs::{DetectedFs, ImageStream};
+use crate::filesystem::{
+    ByteRange, COMMON_KEY, DEVICE_KEY, DirectoryCommon, EXTENDED_ATTRIBUTES_KEY, ExtendedAttribute,
+    File, FileCommon, Filesystem, SYMLINK_TARGET_KEY, namespaced_metadata, unix_ftype,
+};
+use crate::folder_impl::FolderFS;
+use crate::names::{escape_name, name_bytes, render_name, render_path};
+use log::{debug, warn};
+use serde_json::{Value, json};
+use std::collections::{BTreeMap, HashMap};
+use std::error::Error;
+use std::fs;
+use std::io::{Read, Seek, SeekFrom};
+use std::path::Path;
+
+/// Namespace of the layer of a record in `File.metadata`.
+pub const METADATA_NAMESPACE: &str = "overlay";
+/// Namespace of the tar header fields of the records of layer tarballs.
+const TAR_NAMESPACE: &str = "tar";
+const WHITEOUT_PREFIX: &str = ".wh.";
+const OPAQUE_MARKER: &str = ".wh..wh..opq";
+const OPAQUE_XATTRS: [&str; 2] = ["trusted.overlay.opaque", "user.overlay.opaque"];
+/// Prefix of the extended attributes recorded in pax headers.
+const PAX_XATTR_PREFIX: &str = "SCHILY.xattr.";
+
+/// A record of a layer, its path normalized to `/`-separated and absolute.
+struct LayerRecord {
+    file: File,
+    /// Directory marked opaque by an attribute.
+    opaque: bool,
+}
+
+/// An extracted (uncompressed) layer tarball, indexed once when opened.
+pub struct TarLayer {
+    file: fs::File,
+    records: Vec<LayerRecord>,
+    /// Offset in the archive and size of the content of each record, by identifier.
+    contents: Vec<Option<(u64, u64)>>,
+}
+
+/// Absolute `/`-separated form of a path of an archive, `None` for paths leaving it.
+fn archive_path(bytes: &[u8]) -> Option<String> {
+    let escaped = escape_name(bytes);
+    let mut components = Vec::new();
+    for component in escaped.split('/') {
+        match component {
+            "" | "." => {}
+            ".." => return None,
+            component => components.push(component),
+        }
+    }
+    Some(format!("/{}", components.join("/")))
+}
+
+/// Parent directory and name of an absolute path.
+fn split_parent(path: &str) -> (&str, &str) {
+    match path.rsplit_once('/') {
+        Some(("", name)) => ("/", name),
+        Some((parent, name)) => (parent, name),
+        None => ("/", path),
+    }
+}
+
+fn join(parent: &str, name: &str) -> String {
+    match parent {
+        "/" => format!("/{}", name),
+        parent => format!("{}/{}", parent, name),
+    }
+}
+
+/// Record of a layer tarball, or a directory the archive only implies.
+fn tar_record(identifier: u64, path: &str, mode: u32, own: Value, common: Value) -> File {
+    let name = split_parent(path).1.to_string();
+    File {
+        id: None,
+        identifier,
+        absolute_path: path.to_string(),
+        raw_name: name_bytes(&name).map(hex::encode),
+        name,
+        ftype: unix_ftype(mode).to_string(),
+        size: 0,
+        size_on_disk: None,
+        created: None,
+        modified: None,
+        accessed: None,
+        changed: None,
+        permissions: Some(format!("{:o}", mode)),
+        owner: None,
+        group: None,
+        display: None,
+        sig_name: None,
+        sig_mime: None,
+        sig_exts: None,
+        detected_type: None,
+        ext_mismatch: None,
+        md5: None,
+        sha1: None,
+        sha256: None,
+        metadata: namespaced_metadata(TAR_NAMESPACE, own, common),
+    }
+}
+
+impl TarLayer {
+    pub fn open(path: &Path) -> Result<Self, Box<dyn Error>> {
+        let mut archive = tar::Archive::new(fs::File::open(path)?);
+        let mut records: Vec<LayerRecord> = Vec::new();
+        let mut contents = Vec::new();
+        let mut by_path: HashMap<String, usize> = HashMap::new();
+        for entry in archive.entries_with_seek()? {
+            let mut entry = entry?;
+            let Some(path) = archive_path(&entry.path_bytes()) else {
+                warn!(
+                    "Skipping {:?} of layer {}: it leaves the archive",
+                    String::from_utf8_lossy(&entry.path_bytes()),
+                    path.display()
+                );
+                continue;
+            };
+            let header = entry.header();
+            let kind = header.entry_type();
+            let type_bits = match kind {
+                tar::EntryType::Regular | tar::EntryType::Continuous => 0o100000,
+                tar::EntryType::Directory => 0o040000,
+                tar::EntryType::Symlink => 0o120000,
+                tar::EntryType::Char => 0o020000,
+                tar::EntryType::Block => 0o060000,
+                tar::EntryType::Fifo => 0o010000,
+                tar::EntryType::Link => 0,
+                other => {
+                    debug!("Skipping tar entry {} of type {:?}", path, other);
+                    continue;
+                }
+            };
+            // Layer builders leave numeric fields blank at times: unknown rather than fatal.
+            let mode = type_bits | (header.mode().unwrap_or(0) & 0o7777);
+            let owner = header.uid().ok().map(|uid| uid.to_string());
+            let group = header.gid().ok().map(|gid| gid.to_string());
+            let modified = header.mtime().ok();
+            let own = json!({
+                "header_offset": entry.raw_header_position(),
+                "uname": header.username().ok().flatten(),
+                "gname": header.groupname().ok().flatten(),
+            });
+            let mut common = json!({});
+            let link = entry.link_name_bytes().map(|l| escape_name(&l));
+            if matches!(kind, tar::EntryType::Char | tar::EntryType::Block) {
+                common[DEVICE_KEY] = json!({
+                    "major": header.device_major()?.unwrap_or(0),
+                    "minor": header.device_minor()?.unwrap_or(0),
+                });
+            }
+            if kind == tar::EntryType::Symlink {
+                common[SYMLINK_TARGET_KEY] = json!(link);
+            }
+            let (size, data_offset) = (entry.size(), entry.raw_file_position());
+            let mut xattrs = Vec::new();
+            if let Some(extensions) = entry.pax_extensions()? {
+                for extension in extensions {
+                    let extension = extension?;
+                    if let Ok(Some(name)) =
+                        extension.key().map(|k| k.strip_prefix(PAX_XATTR_PREFIX))
+                    {
+                        xattrs.push((name.to_string(), extension.value_bytes().to_vec()));
+                    }
+                }
+            }
+            let opaque = xattrs
+                .iter()
+                .any(|(name, value)| OPAQUE_XATTRS.contains(&name.as_str()) && value == b"y");
+            if !xattrs.is_empty() {
+                common[EXTENDED_ATTRIBUTES_KEY] = json!(
+                    xattrs
+                        .iter()
+                        .map(|(name, value)| json!({
+                            "name": name,
+                            "size": value.len(),
+                            "value": hex::encode(value),
+                        }))
+                        .collect::<Vec<_>>()
+                );
+            }
+
+            let identifier = records.len() as u64;
+            let (mut file, content) = if kind == tar::EntryType::Link {
+                // A hard link shares the record of an earlier entry of the archive.
+                let target = link.as_deref().and_then(|l| archive_path(l.as_bytes()));
+                let Some(&target) = target.as_ref().and_then(|t| by_path.get(t)) else {
+                    warn!("Skipping hard link {} to a path not in the archive", path);
+                    continue;
+                };
+                let mut file = records[target].file.clone();
+                file.identifier = identifier;
+                file.absolute_path = path.clone();
+                file.name = split_parent(&path).1.to_string();
+                file.raw_name = name_bytes(&file.name).map(hex::encode);
+                (file, contents[target])
+            } else {
+                let mut file = tar_record(identifier, &path, mode, own, common);
+                file.owner = owner;
+                file.group = group;
+                file.modified = modified;
+                let content = (type_bits == 0o100000).then_some((data_offset, size));
+                file.size = content.map_or(0, |(_, size)| size);
+                (file, content)
+            };
+            if path == "/" {
+                file.name = String::new();
+            }
+            match by_path.get(&path) {
+                // A later entry of the same path replaces the earlier one.
+                Some(&index) => {
+                    file.identifier = index as u64;
+                    records[index] = LayerRecord { file, opaque };
+                    contents[index] = content;
+                }
+                None => {
+                    by_path.insert(path, records.len());
+                    records.push(LayerRecord { file, opaque });
+                    contents.push(content);
+                }
+            }
+        }
+
+        // Archives often omit the directories above their entries.
+        let mut implied = Vec::new();
+        for record in &records {
+            let mut path = record.file.absolute_path.as_str();
+            while path != "/" {
+                path = split_parent(path).0;
+                if !by_path.contains_key(path) {
+                    by_path.insert(path.to_string(), records.len() + implied.len());
+                    implied.push(path.to_string());
+                }
+            }
+        }
+        for path in implied {
+            let identifier = records.len() as u64;
+            let mut file = tar_record(identifier, &path, 0o040755, json!({}), json!({}));
+            if path == "/" {
+                file.name = String::new();
+            }
+            records.push(LayerRecord {
+                file,
+                opaque: false,
+            });
+            contents.push(None);
+        }
+        Ok(Self {
+            file: archive.into_inner(),
+            records,
+            contents,
+        })
+    }
+
+    fn read_slice(
+        &mut self,
+        identifier: u64,
+        offset: u64,
+        length: usize,
+    ) -> Result<Vec<u8>, Box<dyn Error>> {
+        let (start, size) = self
+            .contents
+            .get(identifier as usize)
+            .copied()
+            .flatten()
+            .ok_or("the record has no content in the layer tarball")?;
+        if offset >= size {
+            return Ok(Vec::new());
+        }
+        let mut buf = vec![0u8; length.min((size - offset) as usize)];
+        self.file.seek(SeekFrom::Start(start + offset))?;
+        self.file.read_exact(&mut buf)?;
+        Ok(buf)
+    }
+}
+
+enum LayerBackend {
+    Fs(Box<DetectedFs<ImageStream>>),
+    Tar(TarLayer),
+}
+
+/// One layer of an overlay and where it comes from.
+pub struct OverlayLayer {
+    pub source: String,
+    backend: LayerBackend,
+}
+
+impl OverlayLayer {
+    /// A layer over an opened filesystem: a partition of a disk image, a host folder.
+    pub fn from_fs(source: &str, fs: DetectedFs<ImageStream>) -> Self {
+        Self {
+            source: source.to_string(),
+            backend: LayerBackend::Fs(Box::new(fs)),
+        }
+    }
+
+    /// Open the layer at `path`: a folder (an extracted layer, an OverlayFS lower or
+    /// upper directory), an uncompressed layer tarball, or an image of a filesystem.
+    pub fn open(path: &str) -> Result<Self, Box<dyn Error>> {
+        let host = Path::new(path);
+        if host.is_dir() {
+            return Ok(Self::from_fs(
+                path,
+                DetectedFs::Folder(FolderFS::new(host.to_path_buf())),
+            ));
+        }
+        let mut head = [0u8; 512];
+        let read = fs::File::open(host)?.read(&mut head)?;
+        if read == head.len() && &head[257..262] == b"ustar" {
+            return Ok(Self {
+                source: path.to_string(),
+                backend: LayerBackend::Tar(TarLayer::open(host)?),
+            });
+        }
+        let sectors = fs::metadata(host)?.len() / 512;
+        Ok(Self::from_fs(
+            path,
+            DetectedFs::from_image(path, 0, sectors)?,
+        ))
+    }
+
+    fn filesystem_type(&self) -> String {
+        match &self.backend {
+            LayerBackend::Fs(fs) => fs.filesystem_type(),
+            LayerBackend::Tar(_) => "tar".to_string(),
+        }
+    }
+
+    /// Every record of the layer, taken once when the overlay is built.
+    fn records(&mut self) -> Result<Vec<LayerRecord>, Box<dyn Error>> {
+        let fs = match &mut self.backend {
+            LayerBackend::Tar(tar) => return Ok(std::mem::take(&mut tar.records)),
+            LayerBackend::Fs(fs) => fs,
+        };
+        let separator = fs.path_separator();
+        let mut records = Vec::new();
+        for mut file in fs.enumerate_all_files()? {
+            let path = file.absolute_path.replace(separator.as_str(), "/");
+            file.absolute_path = match path.trim_end_matches('/') {
+                "" => "/".to_string(),
+                path => path.to_string(),
+            };
+            let opaque = file.ftype == "dir"
+                && fs
+                    .get_file(file.identifier)
+                    .and_then(|record| fs.extended_attributes(&record))
+                    .is_ok_and(|xattrs| {
+                        xattrs.iter().any(|(name, value)| {
+                            OPAQUE_XATTRS.contains(&name.as_str()) && value == b"y"
+                        })
+                    });
+            records.push(LayerRecord { file, opaque });
+        }
+        Ok(records)
+    }
+
+    fn read_slice(
+        &mut self,
+        identifier: u64,
+        offset: u64,
+        length: usize,
+    ) -> Result<Vec<u8>, Box<dyn Error>> {
+        match &mut self.backend {
+            LayerBackend::Fs(fs) => {
+                let record = fs.get_file(identifier)?;
+                fs.read_file_slice(&record, offset, length)
+            }
+            LayerBackend::Tar(tar) => tar.read_slice(identifier, offset, length),
+        }
+    }
+}
+
+/// A record of the merged view and the layer providing it.
+#[derive(Debug, Clone)]
+pub struct OverlayRecord {
+    pub id: u64,
+    pub layer: usize,
+    /// Record as its layer describes it, at its `/`-separated path.
+    pub file: File,
+}
+
+impl FileCommon for OverlayRecord {
+    fn id(&self) -> u64 {
+        self.id
+    }
+    fn size(&self) -> u64 {
+        self.file.size
+    }
+    fn is_dir(&self) -> bool {
+        self.file.ftype == "dir"
+    }
+    fn to_string(&self) -> String {
+        format!(
+            "OverlayRecord {{ id: {}, layer: {}, path: {} }}",
+            self.id, self.layer, self.file.absolute_path
+        )
+    }
+    fn to_json(&self) -> Value {
+        json!({
+            "id": self.id,
+            "layer": self.layer,
+            "file": self.file,
+        })
+    }
+}
+
+pub struct OverlayDirectory {
+    pub file_id: u64,
+    pub name: String,
+}
+
+impl DirectoryCommon for OverlayDirectory {
+    fn file_id(&self) -> u64 {
+        self.file_id
+    }
+    fn name(&self) -> &str {
+        &self.name
+    }
+    fn to_string(&self) -> String {
+        format!(
+            "OverlayDirectory {{ file_id: {}, name: {} }}",
+            self.file_id, self.name
+        )
+    }
+    fn to_json(&self) -> Value {
+        json!({
+            "file_id": self.file_id,
+            "name": self.name
+        })
+    }
+}
+
+/// Remove the records below `dir` (not `dir` itself).
+fn remove_below(merged: &mut BTreeMap<String, OverlayRecord>, dir: &str) {
+    let prefix = join(dir, "");
+    let below: Vec<String> = merged
+        .range(prefix.clone()..)
+        .map(|(path, _)| path)
+        .take_while(|path| path.starts_with(&prefix))
+        .cloned()
+        .collect();
+    for path in below {
+        merged.remove(&path);
+    }
+}
+
+/// Whether a record is an OverlayFS whiteout, a character device numbered 0/0.
+fn is_device_whiteout(file: &File) -> bool {
+    file.ftype == "chardev"
+        && file.metadata[COMMON_KEY][DEVICE_KEY] == json!({ "major": 0, "minor": 0 })
+}
+
+/// Union view of layers, the first the lowest. Records are numbered from 1 (the root)
+/// in path order.
+pub struct OverlayFS {
+    layers: Vec<OverlayLayer>,
+    records: Vec<OverlayRecord>,
+    children: Vec<Vec<u64>>,
+    whiteouts: u64,
+    opaque_directories: u64,
+}
+
+impl OverlayFS {
+    /// Merge `layers`, the first the lowest and the last the uppermost.
+    pub fn new(mut layers: Vec<OverlayLayer>) -> Result<Self, Box<dyn Error>> {
+        if layers.is_empty() {
+            return Err("an overlay needs at least one layer".into());
+        }
+        let mut merged: BTreeMap<String, OverlayRecord> = BTreeMap::new();
+        let (mut whiteouts, mut opaque_directories) = (0, 0);
+        for (index, layer) in layers.iter_mut().enumerate() {
+            let mut hidden = Vec::new();
+            let mut opaque = Vec::new();
+            let mut entries = Vec::new();
+            for record in layer.records()? {
+                let path = record.file.absolute_path.clone();
+                let (parent, name) = split_parent(&path);
+                if name == OPAQUE_MARKER {
+                    opaque.push(parent.to_string());
+                } else if let Some(target) = name.strip_prefix(WHITEOUT_PREFIX) {
+                    hidden.push(join(parent, target));
+                } else if is_device_whiteout(&record.file) {
+                    hidden.push(path);
+                } else {
+                    if record.opaque {
+                        opaque.push(path);
+                    }
+                    entries.push(record.file);
+                }
+            }
+            whiteouts += hidden.len() as u64;
+            opaque_directories += opaque.len() as u64;
+            // Markers only hide the layers below theirs.
+            for path in &hidden {
+                merged.remove(path);
+                remove_below(&mut merged, path);
+            }
+            for dir in &opaque {
+                remove_below(&mut merged, dir);
+            }
+            for file in entries {
+                if file.ftype != "dir" {
+                    remove_below(&mut merged, &file.absolute_path);
+                }
+                let record = OverlayRecord {
+                    id: 0,
+                    layer: index,
+                    file,
+                };
+                merged.insert(record.file.absolute_path.clone(), record);
+            }
+        }
+        if !merged.contains_key("/") {
+            return Err("no layer of the overlay has a root directory".into());
+        }
+
+        let mut ids: HashMap<String, u64> = HashMap::new();
+        let mut records = Vec::with_capacity(merged.len());
+        for (path, mut record) in merged {
+            record.id = records.len() as u64 + 1;
+            ids.insert(path, record.id);
+            records.push(record);
+        }
+        let mut children = vec![Vec::new(); records.len() + 1];
+        for record in &records[1..] {
+            let parent = split_parent(&record.file.absolute_path).0;
+            match ids.get(parent) {
+                Some(&parent) => children[parent as usize].push(record.id),
+                None => debug!(
+                    "{} of layer {} has no parent directory in the overlay",
+                    record.file.absolute_path, record.layer
+                ),
+            }
+        }
+        Ok(Self {
+            layers,
+            records,
+            children,
+            whiteouts,
+            opaque_directories,
+        })
+    }
+
+    fn record(&self, file_id: u64) -> Result<&OverlayRecord, Box<dyn Error>> {
+        file_id
+            .checked_sub(1)
+            .and_then(|index| self.records.get(index as usize))
+            .ok_or_else(|| format!("no record {} in the overlay", file_id).into())
+    }
+
+    /// Filesystem of the layer providing `record`, `None` for layer tarballs.
+    fn fs_layer(&mut self, record: &OverlayRecord) -> Option<&mut DetectedFs<ImageStream>> {
+        match &mut self.layers[record.layer].backend {
+            LayerBackend::Fs(fs) => Some(fs),
+            LayerBackend::Tar(_) => None,
+        }
+    }
+}
+
+impl Filesystem for OverlayFS {
+    type FileType = OverlayRecord;
+    type DirectoryType = OverlayDirectory;
+
+    fn filesystem_type(&self) -> String {
+        "Overlay".to_string()
+    }
+
+    fn path_separator(&self) -> String {
+        "/".to_string()
+    }
+
+    fn record_count(&mut self) -> u64 {
+        self.records.len() as u64
+    }
+
+    fn block_size(&self) -> u64 {
+        self.layers
+            .iter()
+            .find_map(|layer| match &layer.backend {
+                LayerBackend::Fs(fs) => Some(fs.block_size()),
+                LayerBackend::Tar(_) => None,
+            })
+            .unwrap_or(512)
+    }
+
+    fn get_metadata(&self) -> Result<Value, Box<dyn Error>> {
+        let mut per_layer = vec![0u64; self.layers.len()];
+        for record in &self.records {
+            per_layer[record.layer] += 1;
+        }
+        Ok(json!({
+            "layers": self
+                .layers
+                .iter()
+                .zip(per_layer)
+                .enumerate()
+                .map(|(index, (layer, records))| json!({
+                    "index": index,
+                    "source": layer.source,
+                    "filesystem": layer.filesystem_type(),
+                    "records": records,
+                }))
+                .collect::<Vec<_>>(),
+            "records": self.records.len(),
+            "whiteouts": self.whiteouts,
+            "opaque_directories": self.opaque_directories,
+        }))
+    }
+
+    fn get_metadata_pretty(&self) -> Result<String, Box<dyn Error>> {
+        let mut out = format!(
+            "Overlay of {} layers, {} records ({} whiteouts, {} opaque directories)\n",
+            self.layers.len(),
+            self.records.len(),
+            self.whiteouts,
+            self.opaque_directories
+        );
+        for (index, layer) in self.layers.iter().enumerate() {
+            let role = if index + 1 == self.layers.len() {
+                "upper"
+            } else {
+                "lower"
+            };
+            out.push_str(&format!(
+                "  {} {} ({}): {}\n",
+                index,
+                role,
+                layer.filesystem_type(),
+                layer.source
+            ));
+        }
+        Ok(out)
+    }
+
+    fn get_file(&mut self, file_id: u64) -> Result<Self::FileType, Box<dyn Error>> {
+        self.record(file_id).cloned()
+    }
+
+    fn read_file_content(&mut self, file: &Self::FileType) -> Result<Vec<u8>, Box<dyn Error>> {
+        self.read_file_slice(file, 0, file.file.size as usize)
+    }
+
+    fn read_file_prefix(
+        &mut self,
+        file: &Self::FileType,
+        length: usize,
+    ) -> Result<Vec<u8>, Box<dyn Error>> {
+        self.read_file_slice(file, 0, length)
+    }
+
+    fn read_file_slice(
+        &mut self,
+        file: &Self::FileType,
+        offset: u64,
+        length: usize,
+    ) -> Result<Vec<u8>, Box<dyn Error>> {
+        self.layers[file.layer].read_slice(file.file.identifier, offset, length)
+    }
+
+    fn list_dir(
+        &mut self,
+        inode: &Self::FileType,
+    ) -> Result<Vec<Self::DirectoryType>, Box<dyn Error>> {
+        let children = self
+            .children
+            .get(inode.id as usize)
+            .ok_or_else(|| format!("no record {} in the overlay", inode.id))?;
+        Ok(children
+            .iter()
+            .map(|&id| OverlayDirectory {
+                file_id: id,
+                name: split_parent(&self.records[id as usize - 1].file.absolute_path)
+                    .1
+                    .to_string(),
+            })
+            .collect())
+    }
+
+    fn record_to_file(&self, file: &Self::FileType, file_id: u64, absolute_path: &str) -> File {
+        let mut out = file.file.clone();
+        out.metadata[METADATA_NAMESPACE] = json!({
+            "layer": file.layer,
+            "source": self.layers[file.layer].source,
+            "identifier": file.file.identifier,
+        });
+        out.identifier = file_id;
+        out.absolute_path = render_path(absolute_path, "/").into_owned();
+        out.name = render_name(&out.name).into_owned();
+        out
+    }
+
+    fn get_root_file_id(&self) -> u64 {
+        1
+    }
+
+    fn file_holes(
+        &mut self,
+        file: &Self::FileType,
+    ) -> Result<Option<Vec<ByteRange>>, Box<dyn Error>> {
+        match self.fs_layer(file) {
+            Some(fs) => {
+                let record = fs.get_file(file.file.identifier)?;
+                fs.file_holes(&record)
+            }
+            None => Ok(None),
+        }
+    }
+
+    fn extended_attributes(
+        &mut self,
+        file: &Self::FileType,
+    ) -> Result<Vec<ExtendedAttribute>, Box<dyn Error>> {
+        match self.fs_layer(file) {
+            Some(fs) => {
+                let record = fs.get_file(file.file.identifier)?;
+                fs.extended_attributes(&record)
+            }
+            None => Ok(Vec::new()),
+        }
+    }
+}
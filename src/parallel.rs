@@ -0,0 +1,185 @@
+//! Opt-in parallel walks. The top of the tree is walked on the caller's handle until
+//! enough independent subtrees are queued; worker threads then walk those subtrees, each
+//! on its own filesystem handle, so directory listing and record parsing run concurrently.
+//! Subtrees are emitted in queue order once complete, so the output does not depend on
+//! thread scheduling.
+use crate::filesystem::{
+    ChildEntry, DirectoryCommon, File, FileCommon, Filesystem, FsFileReadSeek, ReadSeek,
+    WalkCheckpoint, WalkEvent, attach_bad_ranges, walk_breadth_first,
+};
+use crate::search::ExcludeSet;
+use crate::spill::WalkQueue;
+use crate::tolerant;
+use log::warn;
+use std::collections::BTreeMap;
+use std::error::Error;
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::sync::mpsc;
+use std::thread;
+
+/// Subtrees queued per worker before they start, so that uneven subtrees balance out.
+const SUBTREES_PER_WORKER: usize = 8;
+
+/// Opens another handle on the walked filesystem, for a worker thread.
+pub type Opener<'a, F> = dyn Fn() -> Result<F, Box<dyn Error>> + Sync + 'a;
+
+/// Content processing of the non-directory records, called from the worker threads.
+pub type SharedVisitor<'a> = dyn Fn(&mut File, &mut dyn ReadSeek) + Sync + 'a;
+
+/// The `File` of a record and its entries to walk into.
+fn visit_record<F: Filesystem>(
+    fs: &mut F,
+    record_id: u64,
+    path: &str,
+    visitor: Option<&SharedVisitor>,
+) -> Option<(File, Vec<ChildEntry>)> {
+    // Handles resolving records through a traversal cache (folders) reach the subtree
+    // roots by path.
+    let record = fs
+        .get_file(record_id)
+        .or_else(|_| fs.get_file_by_path(path, record_id))
+        .ok()?;
+    let mut file = fs.record_to_file(&record, record_id, path);
+    let mut children = Vec::new();
+    if record.is_dir() {
+        if let Ok(entries) = fs.list_dir(&record) {
+            children = entries
+                .iter()
+                .map(|e| (e.file_id(), e.name().to_string()))
+                .collect();
+        }
+    } else if let Some(visitor) = visitor {
+        let mut reader = FsFileReadSeek::new(fs, record);
+        tolerant::take_thread_ranges();
+        visitor(&mut file, &mut reader);
+        attach_bad_ranges(&mut file);
+    }
+    Some((file, children))
+}
+
+/// Walk `fs` with `threads` workers, each opening its own handle with `open`. Records are
+/// emitted through `callback` on the calling thread; a record reachable through several
+/// subtrees is emitted once.
+pub fn walk_parallel<F: Filesystem>(
+    fs: &mut F,
+    open: &Opener<F>,
+    threads: usize,
+    exclude: Option<&ExcludeSet>,
+    visitor: Option<&SharedVisitor>,
+    callback: &mut dyn FnMut(WalkEvent),
+) -> Result<(), Box<dyn Error>> {
+    let threads = threads.max(1);
+    let separator = fs.path_separator();
+    let mut top = WalkCheckpoint::default();
+    top.queue
+        .push_back((fs.get_root_file_id(), separator.clone()));
+    walk_breadth_first(
+        &mut top,
+        &separator,
+        exclude,
+        0,
+        None,
+        threads * SUBTREES_PER_WORKER,
+        &mut |record_id, path, first| {
+            if !first {
+                return None;
+            }
+            let (file, children) = visit_record(fs, record_id, path, visitor)?;
+            callback(WalkEvent::File(file));
+            Some(children)
+        },
+    );
+    let subtrees = top.queue.drain_all();
+    if subtrees.is_empty() {
+        return Ok(());
+    }
+    callback(WalkEvent::Status(format!(
+        "Walking {} subtrees on {} threads...",
+        subtrees.len(),
+        threads
+    )));
+
+    let mut emitted = top.seen.clone();
+    let seen = &top.seen;
+    let next = AtomicUsize::new(0);
+    let (subtrees, next) = (&subtrees, &next);
+    let (tx, rx) = mpsc::channel::<Result<(usize, Vec<File>), String>>();
+    thread::scope(|scope| {
+        for _ in 0..threads.min(subtrees.len()) {
+            let tx = tx.clone();
+            scope.spawn(move || {
+                let mut fs = match open() {
+                    Ok(fs) => fs,
+                    Err(e) => {
+                        let _ = tx.send(Err(e.to_string()));
+                        return;
+                    }
+                };
+                let separator = fs.path_separator();
+                loop {
+                    let index = next.fetch_add(1, Ordering::Relaxed);
+                    let Some(subtree) = subtrees.get(index) else {
+                        return;
+                    };
+                    let mut state = WalkCheckpoint::default();
+                    state.queue = WalkQueue::from_iter([subtree.clone()]);
+                    state.seen = seen.clone();
+                    let mut files = Vec::new();
+                    walk_breadth_first(
+                        &mut state,
+                        &separator,
+                        exclude,
+                        0,
+                        None,
+                        0,
+                        &mut |record_id, path, first| {
+                            if !first {
+                                return None;
+                            }
+                            let (file, children) = visit_record(&mut fs, record_id, path, visitor)?;
+                            files.push(file);
+                            Some(children)
+                        },
+                    );
+                    if tx.send(Ok((index, files))).is_err() {
+                        return;
+                    }
+                }
+            });
+        }
+        drop(tx);
+
+        // Subtrees completed out of order wait for the ones queued before them.
+        let mut pending = BTreeMap::new();
+        let mut next_out = 0;
+        let mut failure = None;
+        for message in rx {
+            match message {
+                Ok((index, files)) => {
+                    pending.insert(index, files);
+                }
+                Err(e) => {
+                    warn!("A walk worker could not open the filesystem: {}", e);
+                    failure.get_or_insert(e);
+                }
+            }
+            while let Some(files) = pending.remove(&next_out) {
+                for file in files {
+                    if emitted.insert(file.identifier) {
+                        callback(WalkEvent::File(file));
+                    }
+                }
+                next_out += 1;
+            }
+        }
+        match failure {
+            Some(e) if next_out < subtrees.len() => Err(format!(
+                "{} subtrees were not walked: {}",
+                subtrees.len() - next_out,
+                e
+            )
+            .into()),
+            _ => Ok(()),
+        }
+    })
+}
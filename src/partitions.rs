@@ -0,0 +1,356 @@
+use serde::Serialize;
+use std::error::Error;
+use std::io::{Read, Seek, SeekFrom};
+
+const MBR_SIGNATURE: [u8; 2] = [0x55, 0xaa];
+const GPT_SIGNATURE: &[u8; 8] = b"EFI PART";
+const MBR_PROTECTIVE: u8 = 0xee;
+const MBR_EXTENDED: &[u8] = &[0x05, 0x0f, 0x85];
+/// Upper bounds guarding against corrupted tables.
+const MAX_GPT_ENTRIES: u32 = 1024;
+const MAX_LOGICAL_PARTITIONS: usize = 128;
+
+/// Partitioning scheme of a disk image.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize)]
+#[serde(rename_all = "lowercase")]
+pub enum PartitionScheme {
+    Mbr,
+    Gpt,
+}
+
+/// One entry of a partition table.
+#[derive(Debug, Clone, Serialize)]
+pub struct PartitionEntry {
+    /// 1-based index; MBR logical partitions start at 5 like on Linux.
+    pub index: usize,
+    pub scheme: PartitionScheme,
+    /// MBR type byte (`0x83`) or GPT type GUID.
+    pub type_id: String,
+    pub type_name: String,
+    /// GPT partition name.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub name: Option<String>,
+    pub bootable: bool,
+    pub start_sector: u64,
+    pub sector_count: u64,
+    pub sector_size: u64,
+    pub start_offset: u64,
+    pub size: u64,
+}
+
+fn mbr_type_name(ptype: u8) -> &'static str {
+    match ptype {
+        0x01 => "FAT12",
+        0x04 | 0x06 | 0x0e => "FAT16",
+        0x05 | 0x0f | 0x85 => "Extended",
+        0x07 => "NTFS / exFAT",
+        0x0b | 0x0c => "FAT32",
+        0x27 => "Windows RE",
+        0x82 => "Linux swap",
+        0x83 => "Linux",
+        0x8e => "Linux LVM",
+        0xa5 => "FreeBSD",
+        0xa8 => "Apple UFS",
+        0xaf => "Apple HFS+ / APFS",
+        0xee => "GPT protective",
+        0xef => "EFI System",
+        0xfd => "Linux RAID",
+        _ => "Unknown",
+    }
+}
+
+fn gpt_type_name(guid: &str) -> &'static str {
+    match guid {
+        "C12A7328-F81F-11D2-BA4B-00A0C93EC93B" => "EFI System",
+        "21686148-6449-6E6F-744E-656564454649" => "BIOS boot",
+        "E3C9E316-0B5C-4DB8-817D-F92DF00215AE" => "Microsoft reserved",
+        "EBD0A0A2-B9E5-4433-87C0-68B6B72699C7" => "Microsoft basic data",
+        "DE94BBA4-06D1-4D40-A16A-BFD50179D6AC" => "Windows RE",
+        "5808C8AA-7E8F-42E0-85D2-E1E90434CFB3" => "Windows LDM metadata",
+        "AF9B60A0-1431-4F62-BC68-3311714A69AD" => "Windows LDM data",
+        "0FC63DAF-8483-4772-8E79-3D69D8477DE4" => "Linux filesystem",
+        "4F68BCE3-E8CD-4DB1-96E7-FBCAF984B709" => "Linux root (x86-64)",
+        "933AC7E1-2EB4-4F13-B844-0E14E2AEF915" => "Linux home",
+        "0657FD6D-A4AB-43C4-84E5-0933C84B4F4F" => "Linux swap",
+        "E6D6D379-F507-44C2-A23C-238F2A3DF928" => "Linux LVM",
+        "A19D880F-05FC-4D3B-A006-743F0F84911E" => "Linux RAID",
+        "7C3457EF-0000-11AA-AA11-00306543ECAC" => "Apple APFS",
+        "48465300-0000-11AA-AA11-00306543ECAC" => "Apple HFS+",
+        "426F6F74-0000-11AA-AA11-00306543ECAC" => "Apple boot",
+        "516E7CB6-6ECF-11D6-8FF8-00022D09712B" => "FreeBSD UFS",
+        "6A898CC3-1DD2-11B2-99A6-080020736631" => "ZFS",
+        _ => "Unknown",
+    }
+}
+
+/// Mixed-endian textual form of an on-disk GUID.
+fn format_guid(b: &[u8]) -> String {
+    format!(
+        "{:08X}-{:04X}-{:04X}-{:02X}{:02X}-{}",
+        u32::from_le_bytes([b[0], b[1], b[2], b[3]]),
+        u16::from_le_bytes([b[4], b[5]]),
+        u16::from_le_bytes([b[6], b[7]]),
+        b[8],
+        b[9],
+        b[10..16]
+            .iter()
+            .map(|x| format!("{:02X}", x))
+            .collect::<String>()
+    )
+}
+
+fn read_at<R: Read + Seek>(
+    reader: &mut R,
+    offset: u64,
+    len: usize,
+) -> Result<Vec<u8>, Box<dyn Error>> {
+    let mut buf = vec![0u8; len];
+    reader.seek(SeekFrom::Start(offset))?;
+    reader.read_exact(&mut buf)?;
+    Ok(buf)
+}
+
+fn u32_at(buf: &[u8], off: usize) -> u32 {
+    u32::from_le_bytes(buf[off..off + 4].try_into().unwrap())
+}
+
+fn u64_at(buf: &[u8], off: usize) -> u64 {
+    u64::from_le_bytes(buf[off..off + 8].try_into().unwrap())
+}
+
+/// A used slot of an MBR or EBR sector.
+struct MbrSlot {
+    /// Position in the sector (0-3).
+    slot: usize,
+    bootable: bool,
+    ptype: u8,
+    start: u64,
+    count: u64,
+}
+
+fn mbr_slots(sector: &[u8]) -> Vec<MbrSlot> {
+    (0..4)
+        .map(|slot| {
+            let e = &sector[446 + slot * 16..446 + (slot + 1) * 16];
+            MbrSlot {
+                slot,
+                bootable: e[0] == 0x80,
+                ptype: e[4],
+                start: u32_at(e, 8) as u64,
+                count: u32_at(e, 12) as u64,
+            }
+        })
+        .filter(|s| s.ptype != 0 && s.count != 0)
+        .collect()
+}
+
+impl PartitionEntry {
+    fn new(
+        index: usize,
+        scheme: PartitionScheme,
+        start_sector: u64,
+        sector_count: u64,
+        sector_size: u64,
+    ) -> Self {
+        Self {
+            index,
+            scheme,
+            type_id: String::new(),
+            type_name: String::new(),
+            name: None,
+            bootable: false,
+            start_sector,
+            sector_count,
+            sector_size,
+            start_offset: start_sector.saturating_mul(sector_size),
+            size: sector_count.saturating_mul(sector_size),
+        }
+    }
+
+    fn mbr(index: usize, slot: &MbrSlot, start_sector: u64, sector_size: u64) -> Self {
+        Self {
+            type_id: format!("0x{:02x}", slot.ptype),
+            type_name: mbr_type_name(slot.ptype).to_string(),
+            bootable: slot.bootable,
+            ..Self::new(
+                index,
+                PartitionScheme::Mbr,
+                start_sector,
+                slot.count,
+                sector_size,
+            )
+        }
+    }
+}
+
+fn read_gpt<R: Read + Seek>(
+    reader: &mut R,
+    sector_size: u64,
+) -> Result<Option<Vec<PartitionEntry>>, Box<dyn Error>> {
+    let header = read_at(reader, sector_size, 92)?;
+    if &header[0..8] != GPT_SIGNATURE {
+        return Ok(None);
+    }
+    let entries_lba = u64_at(&header, 72);
+    let entry_count = u32_at(&header, 80);
+    let entry_size = u32_at(&header, 84) as usize;
+    if entry_count > MAX_GPT_ENTRIES || !(128..=4096).contains(&entry_size) {
+        return Err(format!(
+            "implausible GPT header ({} entries of {} bytes)",
+            entry_count, entry_size
+        )
+        .into());
+    }
+    let entries_offset = entries_lba
+        .checked_mul(sector_size)
+        .ok_or("implausible GPT header (partition entries past any disk)")?;
+    let table = read_at(reader, entries_offset, entry_count as usize * entry_size)?;
+
+    let mut out = Vec::new();
+    for (i, raw) in table.chunks_exact(entry_size).enumerate() {
+        if raw[0..16].iter().all(|b| *b == 0) {
+            continue;
+        }
+        let type_id = format_guid(&raw[0..16]);
+        let first = u64_at(raw, 32);
+        let last = u64_at(raw, 40);
+        let attributes = u64_at(raw, 48);
+        let name_units: Vec<u16> = raw[56..128]
+            .chunks_exact(2)
+            .map(|c| u16::from_le_bytes([c[0], c[1]]))
+            .take_while(|u| *u != 0)
+            .collect();
+        let mut e = PartitionEntry::new(
+            i + 1,
+            PartitionScheme::Gpt,
+            first,
+            last.saturating_sub(first).saturating_add(1),
+            sector_size,
+        );
+        e.type_name = gpt_type_name(&type_id).to_string();
+        e.type_id = type_id;
+        // Legacy BIOS bootable attribute.
+        e.bootable = attributes & 0x4 != 0;
+        e.name = Some(String::from_utf16_lossy(&name_units)).filter(|n| !n.is_empty());
+        out.push(e);
+    }
+    Ok(Some(out))
+}
+
+/// Follow the EBR chain of an extended partition starting at `ext_start`.
+fn read_logical<R: Read + Seek>(
+    reader: &mut R,
+    ext_start: u64,
+    sector_size: u64,
+    out: &mut Vec<PartitionEntry>,
+) -> Result<(), Box<dyn Error>> {
+    let mut ebr = ext_start;
+    let mut index = 5;
+    while out.len() < MAX_LOGICAL_PARTITIONS {
+        let sector = read_at(reader, ebr * sector_size, 512)?;
+        if sector[510..512] != MBR_SIGNATURE {
+            break;
+        }
+        let mut next = None;
+        for slot in mbr_slots(&sector) {
+            if MBR_EXTENDED.contains(&slot.ptype) {
+                next = Some(ext_start + slot.start);
+            } else {
+                out.push(PartitionEntry::mbr(
+                    index,
+                    &slot,
+                    ebr + slot.start,
+                    sector_size,
+                ));
+                index += 1;
+            }
+        }
+        match next {
+            Some(n) if n > ebr => ebr = n,
+            _ => break,
+        }
+    }
+    Ok(())
+}
+
+/// Whether a filesystem starts at `offset`: a boot sector signature (NTFS, FAT, exFAT),
+/// or the ext or APFS superblock magic.
+fn filesystem_at<R: Read + Seek>(reader: &mut R, offset: u64) -> bool {
+    let Ok(head) = read_at(reader, offset, 2048) else {
+        return false;
+    };
+    head[510..512] == MBR_SIGNATURE || head[1080..1082] == [0x53, 0xef] || &head[32..36] == b"NXSB"
+}
+
+/// Logical sector size of a disk image, from its partition table: the GPT header lies
+/// in the second sector, and an MBR partition starts with a filesystem. Raw images of
+/// 4K native (4Kn) disks do not record it, and default to 512. `None` when the table
+/// does not tell.
+pub fn detect_sector_size<R: Read + Seek>(reader: &mut R) -> Option<u64> {
+    for sector_size in [512, 4096] {
+        if read_at(reader, sector_size, 8).is_ok_and(|h| &h[..] == GPT_SIGNATURE) {
+            return Some(sector_size);
+        }
+    }
+    let mbr = read_at(reader, 0, 512).ok()?;
+    if mbr[510..512] != MBR_SIGNATURE {
+        return None;
+    }
+    for slot in mbr_slots(&mbr) {
+        if slot.start == 0 || slot.ptype == MBR_PROTECTIVE || MBR_EXTENDED.contains(&slot.ptype) {
+            continue;
+        }
+        for sector_size in [512, 4096] {
+            if filesystem_at(reader, slot.start * sector_size) {
+                return Some(sector_size);
+            }
+        }
+    }
+    None
+}
+
+/// Read the partition table of a disk image: GPT when a protective MBR (or a bare GPT
+/// header) is found, otherwise MBR including logical partitions. The GPT header is
+/// looked for with `sector_size`, then with the other common sector size (512 / 4096).
+pub fn read_partition_table<R: Read + Seek>(
+    reader: &mut R,
+    sector_size: u64,
+) -> Result<(PartitionScheme, Vec<PartitionEntry>), Box<dyn Error>> {
+    let mbr = read_at(reader, 0, 512)?;
+    let slots = if mbr[510..512] == MBR_SIGNATURE {
+        mbr_slots(&mbr)
+    } else {
+        Vec::new()
+    };
+    let protective = slots.is_empty() || slots.iter().any(|s| s.ptype == MBR_PROTECTIVE);
+
+    if protective {
+        let alternate = if sector_size == 4096 { 512 } else { 4096 };
+        for ss in [sector_size, alternate] {
+            if let Ok(Some(entries)) = read_gpt(reader, ss) {
+                return Ok((PartitionScheme::Gpt, entries));
+            }
+        }
+        if slots.is_empty() {
+            return Err("no MBR or GPT partition table found".into());
+        }
+    }
+
+    let mut out = Vec::new();
+    let mut extended = None;
+    for slot in &slots {
+        if MBR_EXTENDED.contains(&slot.ptype) && extended.is_none() {
+            extended = Some(slot.start);
+        }
+        out.push(PartitionEntry::mbr(
+            slot.slot + 1,
+            slot,
+            slot.start,
+            sector_size,
+        ));
+    }
+    if let Some(ext_start) = extended {
+        read_logical(reader, ext_start, sector_size, &mut out)?;
+    }
+    Ok((PartitionScheme::Mbr, out))
+}
@@ -0,0 +1,94 @@
+use indicatif::{ProgressBar, ProgressDrawTarget, ProgressStyle};
+use std::borrow::Cow;
+use std::io::{IsTerminal, Read};
+
+/// What a progress display is counting.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ProgressUnit {
+    /// File records (driven by `Filesystem::record_count()` when known).
+    Records,
+    /// Content bytes (dumps, hashing).
+    Bytes,
+}
+
+/// Progress display for long-running operations.
+///
+/// The bar is drawn on STDERR and is automatically hidden when STDERR is not
+/// a terminal, so redirected runs and pipelines never receive escape codes.
+pub struct Progress {
+    bar: ProgressBar,
+}
+
+impl Progress {
+    /// Create a progress display. When `total` is unknown (or zero) a spinner
+    /// with a running count is shown instead of a bar with an ETA.
+    pub fn new(total: Option<u64>, unit: ProgressUnit) -> Self {
+        if !std::io::stderr().is_terminal() {
+            return Self::hidden();
+        }
+
+        let total = total.filter(|t| *t > 0);
+        let template = match (unit, total.is_some()) {
+            (ProgressUnit::Records, true) => {
+                "{spinner} [{elapsed_precise}] [{wide_bar}] {human_pos}/{human_len} records ({per_sec}, ETA {eta}) {msg}"
+            }
+            (ProgressUnit::Records, false) => {
+                "{spinner} [{elapsed_precise}] {human_pos} records ({per_sec}) {msg}"
+            }
+            (ProgressUnit::Bytes, true) => {
+                "{spinner} [{elapsed_precise}] [{wide_bar}] {bytes}/{total_bytes} ({binary_bytes_per_sec}, ETA {eta}) {msg}"
+            }
+            (ProgressUnit::Bytes, false) => {
+                "{spinner} [{elapsed_precise}] {bytes} ({binary_bytes_per_sec}) {msg}"
+            }
+        };
+
+        let bar = match total {
+            Some(t) => ProgressBar::new(t),
+            None => ProgressBar::new_spinner(),
+        };
+        if let Ok(style) = ProgressStyle::with_template(template) {
+            bar.set_style(style.progress_chars("=> "));
+        }
+        bar.set_draw_target(ProgressDrawTarget::stderr());
+        Self { bar }
+    }
+
+    /// A progress display that never draws anything.
+    pub fn hidden() -> Self {
+        Self {
+            bar: ProgressBar::hidden(),
+        }
+    }
+
+    pub fn is_hidden(&self) -> bool {
+        self.bar.is_hidden()
+    }
+
+    pub fn inc(&self, delta: u64) {
+        self.bar.inc(delta);
+    }
+
+    /// Adjust the total once it becomes known (e.g. after a metadata pass).
+    pub fn set_total(&self, total: u64) {
+        self.bar.set_length(total);
+    }
+
+    /// Wrap a reader so that every byte read advances the display.
+    pub fn wrap_read<R: Read>(&self, reader: R) -> impl Read {
+        self.bar.wrap_read(reader)
+    }
+
+    pub fn set_message(&self, msg: impl Into<Cow<'static, str>>) {
+        self.bar.set_message(msg);
+    }
+
+    /// Run `f` with the bar temporarily cleared, so log lines don't tear it.
+    pub fn suspend<F: FnOnce() -> R, R>(&self, f: F) -> R {
+        self.bar.suspend(f)
+    }
+
+    pub fn finish(&self) {
+        self.bar.finish_and_clear();
+    }
+}
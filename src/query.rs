@@ -0,0 +1,540 @@
+//! A small find(1)-like expression language evaluated against `File` records, e.g.
+//! `size > 1M && mtime > 2024-01-01 && name ~ "\.ps1$" && type == file`.
+//!
+//! Grammar:
+//! ```text
+//! expr       := and ( ("||" | "or") and )*
+//! and        := unary ( ("&&" | "and") unary )*
+//! unary      := ("!" | "not") unary | "(" expr ")" | comparison
+//! comparison := field op value
+//! op         := "==" | "=" | "!=" | "<" | "<=" | ">" | ">=" | "~" | "!~"
+//! ```
+//! Sizes accept `K`/`M`/`G`/`T` suffixes, times accept `YYYY-MM-DD[ HH:MM[:SS]]` (UTC)
+//! or a UNIX timestamp, and `~` / `!~` match a regular expression. `flags == immutable`
+//! tests whether a record has that flag.
+use crate::filesystem::{COMMON_KEY, FLAGS_KEY, File};
+use crate::search::parse_size;
+use regex::Regex;
+use std::fmt;
+use std::str::FromStr;
+
+#[derive(Debug, Clone, PartialEq)]
+enum Token {
+    LParen,
+    RParen,
+    And,
+    Or,
+    Not,
+    Op(Op),
+    Word(String),
+    Quoted(String),
+}
+
+impl fmt::Display for Token {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Token::LParen => write!(f, "'('"),
+            Token::RParen => write!(f, "')'"),
+            Token::And => write!(f, "'&&'"),
+            Token::Or => write!(f, "'||'"),
+            Token::Not => write!(f, "'!'"),
+            Token::Op(op) => write!(f, "'{}'", op),
+            Token::Word(w) => write!(f, "'{}'", w),
+            Token::Quoted(q) => write!(f, "\"{}\"", q),
+        }
+    }
+}
+
+/// Describe the next token for an error message.
+fn describe(token: Option<&Token>) -> String {
+    token.map_or_else(|| "the end of the expression".to_string(), Token::to_string)
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum Op {
+    Eq,
+    Ne,
+    Lt,
+    Le,
+    Gt,
+    Ge,
+    Match,
+    NotMatch,
+}
+
+impl fmt::Display for Op {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(match self {
+            Op::Eq => "==",
+            Op::Ne => "!=",
+            Op::Lt => "<",
+            Op::Le => "<=",
+            Op::Gt => ">",
+            Op::Ge => ">=",
+            Op::Match => "~",
+            Op::NotMatch => "!~",
+        })
+    }
+}
+
+impl Op {
+    fn compare<T: PartialOrd>(self, left: T, right: T) -> bool {
+        match self {
+            Op::Eq => left == right,
+            Op::Ne => left != right,
+            Op::Lt => left < right,
+            Op::Le => left <= right,
+            Op::Gt => left > right,
+            Op::Ge => left >= right,
+            Op::Match | Op::NotMatch => false,
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum Field {
+    Id,
+    Name,
+    Path,
+    Ext,
+    Type,
+    Size,
+    SizeOnDisk,
+    Created,
+    Modified,
+    Accessed,
+    Changed,
+    Owner,
+    Group,
+    Permissions,
+    Md5,
+    Sha1,
+    Sha256,
+    Detected,
+    Mismatch,
+    Flags,
+}
+
+impl FromStr for Field {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s.to_ascii_lowercase().as_str() {
+            "id" | "identifier" | "inode" => Ok(Field::Id),
+            "name" => Ok(Field::Name),
+            "path" => Ok(Field::Path),
+            "ext" | "extension" => Ok(Field::Ext),
+            "type" | "ftype" => Ok(Field::Type),
+            "size" => Ok(Field::Size),
+            "size_on_disk" | "allocated" => Ok(Field::SizeOnDisk),
+            "crtime" | "created" | "btime" => Ok(Field::Created),
+            "mtime" | "modified" => Ok(Field::Modified),
+            "atime" | "accessed" => Ok(Field::Accessed),
+            "ctime" | "changed" => Ok(Field::Changed),
+            "owner" | "uid" => Ok(Field::Owner),
+            "group" | "gid" => Ok(Field::Group),
+            "perm" | "permissions" | "mode" => Ok(Field::Permissions),
+            "md5" => Ok(Field::Md5),
+            "sha1" => Ok(Field::Sha1),
+            "sha256" => Ok(Field::Sha256),
+            "detected" | "detected_type" | "magic" => Ok(Field::Detected),
+            "mismatch" | "ext_mismatch" => Ok(Field::Mismatch),
+            "flag" | "flags" | "attr" => Ok(Field::Flags),
+            other => Err(format!("unknown field '{}'", other)),
+        }
+    }
+}
+
+#[derive(Debug, Clone)]
+enum Value {
+    Number(u64),
+    Text(String),
+    Pattern(Regex),
+}
+
+#[derive(Debug, Clone)]
+enum Expr {
+    And(Box<Expr>, Box<Expr>),
+    Or(Box<Expr>, Box<Expr>),
+    Not(Box<Expr>),
+    Compare(Field, Op, Value),
+}
+
+fn tokenize(input: &str) -> Result<Vec<Token>, String> {
+    const SPECIAL: &str = "()<>=!~&|\"'";
+    let chars: Vec<char> = input.chars().collect();
+    let mut tokens = Vec::new();
+    let mut i = 0;
+    while i < chars.len() {
+        let c = chars[i];
+        let next = chars.get(i + 1).copied();
+        match c {
+            c if c.is_whitespace() => i += 1,
+            '(' => {
+                tokens.push(Token::LParen);
+                i += 1;
+            }
+            ')' => {
+                tokens.push(Token::RParen);
+                i += 1;
+            }
+            '&' if next == Some('&') => {
+                tokens.push(Token::And);
+                i += 2;
+            }
+            '|' if next == Some('|') => {
+                tokens.push(Token::Or);
+                i += 2;
+            }
+            '=' => {
+                tokens.push(Token::Op(Op::Eq));
+                i += if next == Some('=') { 2 } else { 1 };
+            }
+            '!' if next == Some('=') => {
+                tokens.push(Token::Op(Op::Ne));
+                i += 2;
+            }
+            '!' if next == Some('~') => {
+                tokens.push(Token::Op(Op::NotMatch));
+                i += 2;
+            }
+            '!' => {
+                tokens.push(Token::Not);
+                i += 1;
+            }
+            '<' | '>' => {
+                let or_equal = next == Some('=');
+                tokens.push(Token::Op(match (c, or_equal) {
+                    ('<', false) => Op::Lt,
+                    ('<', true) => Op::Le,
+                    ('>', false) => Op::Gt,
+                    _ => Op::Ge,
+                }));
+                i += if or_equal { 2 } else { 1 };
+            }
+            '~' => {
+                tokens.push(Token::Op(Op::Match));
+                i += 1;
+            }
+            '"' | '\'' => {
+                let quote = c;
+                let mut text = String::new();
+                i += 1;
+                loop {
+                    match chars.get(i) {
+                        None => return Err("unterminated string".to_string()),
+                        Some(&ch) if ch == quote => break,
+                        Some('\\') => match chars.get(i + 1) {
+                            Some(&esc) if esc == quote || esc == '\\' => {
+                                text.push(esc);
+                                i += 1;
+                            }
+                            _ => text.push('\\'),
+                        },
+                        Some(&ch) => text.push(ch),
+                    }
+                    i += 1;
+                }
+                tokens.push(Token::Quoted(text));
+                i += 1;
+            }
+            _ => {
+                let start = i;
+                while i < chars.len() && !chars[i].is_whitespace() && !SPECIAL.contains(chars[i]) {
+                    i += 1;
+                }
+                if start == i {
+                    return Err(format!("unexpected character '{}'", c));
+                }
+                let word: String = chars[start..i].iter().collect();
+                tokens.push(match word.to_ascii_lowercase().as_str() {
+                    "and" => Token::And,
+                    "or" => Token::Or,
+                    "not" => Token::Not,
+                    _ => Token::Word(word),
+                });
+            }
+        }
+    }
+    Ok(tokens)
+}
+
+/// Days since 1970-01-01 of a proleptic Gregorian date (H. Hinnant).
+fn days_from_civil(year: i64, month: i64, day: i64) -> i64 {
+    let y = if month <= 2 { year - 1 } else { year };
+    let era = y.div_euclid(400);
+    let yoe = y - era * 400;
+    let mp = (month + 9) % 12;
+    let doy = (153 * mp + 2) / 5 + day - 1;
+    let doe = yoe * 365 + yoe / 4 - yoe / 100 + doy;
+    era * 146_097 + doe - 719_468
+}
+
+/// Parse `YYYY-MM-DD[( |T)HH:MM[:SS]][Z]` (UTC) or a UNIX timestamp.
+fn parse_time(value: &str) -> Result<u64, String> {
+    if let Ok(ts) = value.parse::<u64>() {
+        return Ok(ts);
+    }
+    let invalid = || format!("invalid date '{}', expected YYYY-MM-DD[ HH:MM:SS]", value);
+    let value = value.trim_end_matches('Z');
+    let (date, time) = match value.split_once(['T', ' ']) {
+        Some((date, time)) => (date, Some(time)),
+        None => (value, None),
+    };
+    let parts: Vec<i64> = date
+        .split('-')
+        .map(|p| p.parse().map_err(|_| invalid()))
+        .collect::<Result<_, _>>()?;
+    let [year, month, day] = parts[..] else {
+        return Err(invalid());
+    };
+    if !(1..=12).contains(&month) || !(1..=31).contains(&day) {
+        return Err(invalid());
+    }
+    let mut seconds = days_from_civil(year, month, day) * 86_400;
+    if let Some(time) = time {
+        let parts: Vec<i64> = time
+            .split(':')
+            .map(|p| p.parse().map_err(|_| invalid()))
+            .collect::<Result<_, _>>()?;
+        let (h, m, s) = match parts[..] {
+            [h, m] => (h, m, 0),
+            [h, m, s] => (h, m, s),
+            _ => return Err(invalid()),
+        };
+        seconds += h * 3600 + m * 60 + s;
+    }
+    u64::try_from(seconds).map_err(|_| invalid())
+}
+
+struct Parser {
+    tokens: Vec<Token>,
+    pos: usize,
+}
+
+impl Parser {
+    fn peek(&self) -> Option<&Token> {
+        self.tokens.get(self.pos)
+    }
+
+    fn next(&mut self) -> Option<Token> {
+        let token = self.tokens.get(self.pos).cloned();
+        self.pos += 1;
+        token
+    }
+
+    fn or(&mut self) -> Result<Expr, String> {
+        let mut left = self.and()?;
+        while self.peek() == Some(&Token::Or) {
+            self.pos += 1;
+            left = Expr::Or(Box::new(left), Box::new(self.and()?));
+        }
+        Ok(left)
+    }
+
+    fn and(&mut self) -> Result<Expr, String> {
+        let mut left = self.unary()?;
+        while self.peek() == Some(&Token::And) {
+            self.pos += 1;
+            left = Expr::And(Box::new(left), Box::new(self.unary()?));
+        }
+        Ok(left)
+    }
+
+    fn unary(&mut self) -> Result<Expr, String> {
+        match self.next() {
+            Some(Token::Not) => Ok(Expr::Not(Box::new(self.unary()?))),
+            Some(Token::LParen) => {
+                let expr = self.or()?;
+                match self.next() {
+                    Some(Token::RParen) => Ok(expr),
+                    _ => Err("expected ')'".to_string()),
+                }
+            }
+            Some(Token::Word(field)) => self.comparison(field.parse()?),
+            other => Err(format!(
+                "expected a field name, found {}",
+                describe(other.as_ref())
+            )),
+        }
+    }
+
+    fn comparison(&mut self, field: Field) -> Result<Expr, String> {
+        let op = match self.next() {
+            Some(Token::Op(op)) => op,
+            other => {
+                return Err(format!(
+                    "expected an operator, found {}",
+                    describe(other.as_ref())
+                ));
+            }
+        };
+        let raw = match self.next() {
+            Some(Token::Word(w)) | Some(Token::Quoted(w)) => w,
+            other => {
+                return Err(format!(
+                    "expected a value, found {}",
+                    describe(other.as_ref())
+                ));
+            }
+        };
+
+        let value = match (field, op) {
+            (_, Op::Match | Op::NotMatch) => {
+                if matches!(field, Field::Id | Field::Size | Field::SizeOnDisk) {
+                    return Err(format!("'{}' cannot be used on {:?}", op, field).to_lowercase());
+                }
+                Value::Pattern(Regex::new(&raw).map_err(|e| e.to_string())?)
+            }
+            (Field::Size | Field::SizeOnDisk, _) => Value::Number(parse_size(&raw)?),
+            (Field::Id, _) => Value::Number(
+                clap_num::maybe_hex::<u64>(&raw).map_err(|_| format!("invalid id '{}'", raw))?,
+            ),
+            (Field::Created | Field::Modified | Field::Accessed | Field::Changed, _) => {
+                Value::Number(parse_time(&raw)?)
+            }
+            (_, Op::Eq | Op::Ne) => Value::Text(raw),
+            _ => {
+                return Err(
+                    format!("only ==, !=, ~ and !~ can be used on {:?}", field).to_lowercase()
+                );
+            }
+        };
+        Ok(Expr::Compare(field, op, value))
+    }
+}
+
+/// Lowercase `ftype` and fold backend specific spellings (`Directory`, `regular`).
+fn normalized_type(ftype: &str) -> String {
+    match ftype.to_ascii_lowercase().as_str() {
+        "directory" => "dir".to_string(),
+        "regular" => "file".to_string(),
+        "link" => "symlink".to_string(),
+        other => other.to_string(),
+    }
+}
+
+fn text_field(field: Field, file: &File) -> Option<String> {
+    match field {
+        Field::Name => Some(file.name.clone()),
+        Field::Path => Some(file.absolute_path.clone()),
+        Field::Ext => Some(
+            file.name
+                .rsplit_once('.')
+                .map(|(_, ext)| ext.to_ascii_lowercase())
+                .unwrap_or_default(),
+        ),
+        Field::Type => Some(normalized_type(&file.ftype)),
+        Field::Owner => file.owner.clone(),
+        Field::Group => file.group.clone(),
+        Field::Permissions => file.permissions.clone(),
+        Field::Md5 => file.md5.clone(),
+        Field::Sha1 => file.sha1.clone(),
+        Field::Sha256 => file.sha256.clone(),
+        Field::Detected => file.detected_type.clone(),
+        Field::Mismatch => file.ext_mismatch.map(|m| m.to_string()),
+        Field::Flags => file
+            .metadata
+            .get(COMMON_KEY)?
+            .get(FLAGS_KEY)?
+            .as_array()
+            .map(|flags| {
+                flags
+                    .iter()
+                    .filter_map(|f| f.as_str())
+                    .collect::<Vec<_>>()
+                    .join(",")
+            }),
+        _ => None,
+    }
+}
+
+fn number_field(field: Field, file: &File) -> Option<u64> {
+    match field {
+        Field::Id => Some(file.identifier),
+        Field::Size => Some(file.size),
+        Field::SizeOnDisk => file.size_on_disk,
+        Field::Created => file.created,
+        Field::Modified => file.modified,
+        Field::Accessed => file.accessed,
+        Field::Changed => file.changed,
+        _ => None,
+    }
+}
+
+impl Expr {
+    fn eval(&self, file: &File) -> bool {
+        match self {
+            Expr::And(a, b) => a.eval(file) && b.eval(file),
+            Expr::Or(a, b) => a.eval(file) || b.eval(file),
+            Expr::Not(e) => !e.eval(file),
+            Expr::Compare(field, op, Value::Number(n)) => {
+                number_field(*field, file).is_some_and(|v| op.compare(v, *n))
+            }
+            Expr::Compare(field, op, Value::Text(t)) => {
+                text_field(*field, file).is_some_and(|v| match field {
+                    // Whether the flag is set, rather than a comparison of the whole list.
+                    Field::Flags if matches!(op, Op::Eq | Op::Ne) => {
+                        v.split(',').any(|f| f.eq_ignore_ascii_case(t)) == (*op == Op::Eq)
+                    }
+                    Field::Type
+                    | Field::Ext
+                    | Field::Md5
+                    | Field::Sha1
+                    | Field::Sha256
+                    | Field::Detected
+                    | Field::Mismatch => {
+                        let t = if *field == Field::Type {
+                            normalized_type(t)
+                        } else {
+                            t.clone()
+                        };
+                        op.compare(v.to_ascii_lowercase(), t.to_ascii_lowercase())
+                    }
+                    _ => op.compare(v.as_str(), t.as_str()),
+                })
+            }
+            Expr::Compare(field, op, Value::Pattern(re)) => {
+                text_field(*field, file).is_some_and(|v| re.is_match(&v) == (*op == Op::Match))
+            }
+        }
+    }
+}
+
+/// A parsed `--where` expression.
+#[derive(Debug, Clone)]
+pub struct Query {
+    source: String,
+    expr: Expr,
+}
+
+impl Query {
+    pub fn matches(&self, file: &File) -> bool {
+        self.expr.eval(file)
+    }
+}
+
+impl FromStr for Query {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let mut parser = Parser {
+            tokens: tokenize(s)?,
+            pos: 0,
+        };
+        let expr = parser.or()?;
+        if let Some(token) = parser.peek() {
+            return Err(format!("unexpected {} after the expression", token));
+        }
+        Ok(Query {
+            source: s.to_string(),
+            expr,
+        })
+    }
+}
+
+impl fmt::Display for Query {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}", self.source)
+    }
+}
@@ -0,0 +1,217 @@
+//! Deleted file recovery: the content of a record is rebuilt from the extents it still
+//! describes, and each recovered file states how far it can be trusted (complete
+//! content from blocks nobody reused, partial content, or nothing at all).
+use crate::filesystem::{File, Filesystem};
+use crate::hashing::{FileHashes, HashAlgorithm, MultiHasher};
+use serde::Serialize;
+use std::error::Error;
+use std::fs;
+use std::io::Write;
+use std::path::Path;
+
+/// Bytes read from the record at a time.
+const READ_CHUNK: usize = 1024 * 1024;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize)]
+#[serde(rename_all = "lowercase")]
+pub enum Confidence {
+    /// Every byte was read from extents whose blocks are not allocated to another record.
+    Full,
+    /// Some content was read, but it is short, zero-filled or partly stored in reused blocks.
+    Partial,
+    /// Nothing could be read.
+    None,
+}
+
+#[derive(Debug, Clone, Serialize)]
+pub struct RecoveredFile {
+    pub identifier: u64,
+    pub path: String,
+    /// Output file name, `None` when nothing was written.
+    pub file: Option<String>,
+    pub deleted: Option<bool>,
+    pub size: u64,
+    /// Bytes actually recovered.
+    pub recovered: u64,
+    pub confidence: Confidence,
+    /// Blocks of the record's extents now allocated, when the backend maps records to
+    /// blocks.
+    pub reallocated_blocks: Option<u64>,
+    #[serde(skip_serializing_if = "Vec::is_empty")]
+    pub notes: Vec<String>,
+    #[serde(flatten)]
+    pub hashes: FileHashes,
+}
+
+#[derive(Debug, Clone, Default, Serialize)]
+pub struct RecoverReport {
+    pub records: u64,
+    pub full: u64,
+    pub partial: u64,
+    pub unrecoverable: u64,
+    pub files: Vec<RecoveredFile>,
+}
+
+impl RecoverReport {
+    fn add(&mut self, recovered: RecoveredFile) {
+        self.records += 1;
+        match recovered.confidence {
+            Confidence::Full => self.full += 1,
+            Confidence::Partial => self.partial += 1,
+            Confidence::None => self.unrecoverable += 1,
+        }
+        self.files.push(recovered);
+    }
+}
+
+/// Output name of a recovered record: its identifier followed by its name, with the
+/// characters that are not portable in a file name replaced.
+fn output_name(file: &File) -> String {
+    let name: String = file
+        .name
+        .chars()
+        .map(|c| match c {
+            '/' | '\\' | ':' | '*' | '?' | '"' | '<' | '>' | '|' => '_',
+            c if c.is_control() => '_',
+            c => c,
+        })
+        .collect();
+    format!("{}_{}", file.identifier, name)
+}
+
+/// Blocks of `record`'s extents currently allocated, or `None` when the backend cannot
+/// map records to blocks or does not track allocation.
+fn reallocated_blocks<F: Filesystem + ?Sized>(
+    fs: &mut F,
+    record: &F::FileType,
+) -> Result<Option<u64>, Box<dyn Error>> {
+    let Some(runs) = fs.file_block_runs(record)? else {
+        return Ok(None);
+    };
+    let mut allocated = 0;
+    for (first, count) in runs {
+        let status = fs.block_allocation_range(first, count)?;
+        if status.iter().any(Option::is_none) {
+            return Ok(None);
+        }
+        allocated += status.iter().filter(|s| **s == Some(true)).count() as u64;
+    }
+    Ok(Some(allocated))
+}
+
+/// Recover the content of `file` into `out_dir`.
+pub fn recover_file<F: Filesystem + ?Sized>(
+    fs: &mut F,
+    file: &File,
+    out_dir: &Path,
+    algorithms: &[HashAlgorithm],
+) -> Result<RecoveredFile, Box<dyn Error>> {
+    let record = fs.get_file(file.identifier)?;
+    let deleted = fs.is_deleted(&record);
+    let mut notes = Vec::new();
+    // The blocks of a live record are allocated to itself.
+    let reallocated = if deleted == Some(false) {
+        None
+    } else {
+        reallocated_blocks(fs, &record).unwrap_or_else(|e| {
+            notes.push(format!("allocation status unavailable: {}", e));
+            None
+        })
+    };
+
+    let name = output_name(file);
+    let path = out_dir.join(&name);
+    let mut output = fs::File::create(&path)
+        .map_err(|e| format!("could not create '{}': {}", path.display(), e))?;
+    let mut hasher = MultiHasher::new(algorithms);
+    let mut recovered = 0u64;
+    let mut zero_filled = true;
+    let mut buffer = vec![0u8; READ_CHUNK];
+    while recovered < file.size {
+        let length = (file.size - recovered).min(READ_CHUNK as u64) as usize;
+        let chunk = match fs.read_file_slice_into(&record, recovered, &mut buffer[..length]) {
+            Ok(0) => break,
+            Ok(n) => &buffer[..n],
+            Err(e) => {
+                notes.push(format!("read failed at offset {}: {}", recovered, e));
+                break;
+            }
+        };
+        zero_filled &= chunk.iter().all(|b| *b == 0);
+        hasher.update(chunk);
+        output.write_all(chunk)?;
+        recovered += chunk.len() as u64;
+    }
+    drop(output);
+
+    if recovered < file.size {
+        notes.push(format!("short read: {} of {} bytes", recovered, file.size));
+    }
+    if recovered > 0 && zero_filled {
+        notes.push("content is zero-filled, the extents were likely wiped".to_string());
+    }
+    if let Some(blocks) = reallocated.filter(|b| *b > 0) {
+        notes.push(format!("{} blocks are allocated to other records", blocks));
+    }
+    let confidence = if recovered == 0 && file.size > 0 {
+        Confidence::None
+    } else if recovered < file.size || zero_filled && recovered > 0 || reallocated > Some(0) {
+        Confidence::Partial
+    } else {
+        Confidence::Full
+    };
+    let written = if confidence == Confidence::None {
+        fs::remove_file(&path)?;
+        None
+    } else {
+        Some(name)
+    };
+
+    Ok(RecoveredFile {
+        identifier: file.identifier,
+        path: file.absolute_path.clone(),
+        file: written,
+        deleted,
+        size: file.size,
+        recovered,
+        confidence,
+        reallocated_blocks: reallocated,
+        notes,
+        hashes: hasher.finalize(),
+    })
+}
+
+/// Recover every record of `files` into `out_dir`. Records that cannot even be fetched
+/// are reported as unrecoverable.
+pub fn recover_files<F: Filesystem + ?Sized>(
+    fs: &mut F,
+    files: &[File],
+    out_dir: &Path,
+    algorithms: &[HashAlgorithm],
+    on_status: &mut dyn FnMut(String),
+) -> Result<RecoverReport, Box<dyn Error>> {
+    fs::create_dir_all(out_dir)?;
+    let mut report = RecoverReport::default();
+    for (n, file) in files.iter().enumerate() {
+        if n > 0 && n % 1000 == 0 {
+            on_status(format!("{} of {} records recovered", n, files.len()));
+        }
+        let recovered = match recover_file(fs, file, out_dir, algorithms) {
+            Ok(recovered) => recovered,
+            Err(e) => RecoveredFile {
+                identifier: file.identifier,
+                path: file.absolute_path.clone(),
+                file: None,
+                deleted: None,
+                size: file.size,
+                recovered: 0,
+                confidence: Confidence::None,
+                reallocated_blocks: None,
+                notes: vec![e.to_string()],
+                hashes: FileHashes::default(),
+            },
+        };
+        report.add(recovered);
+    }
+    Ok(report)
+}
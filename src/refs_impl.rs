@@ -0,0 +1,746 @@
+//! ReFS 3.x volumes (Windows Server 2016 and later, Windows 10 and 11 Dev Drives). Every
+//! structure past the boot sector lives in Minstore B+ trees: the superblock at cluster
+//! 30 points at the checkpoints, the most recent of which references the root of each
+//! system table. The container table maps the virtual clusters used everywhere else to
+//! physical ones; the object table maps object ids to the root of their table, each
+//! directory being one table whose rows are its files and subdirectories.
+//!
+//! Directories are identified by their object id (the root is 0x600), files by the object
+//! id of their directory shifted left 32 bits plus their file id in it. Files have their
+//! attributes in a table embedded in their directory row; their data and named streams
+//! are read from run tables. ReFS 1.x volumes, reparse points, compressed and encrypted
+//! data are not supported.
+use crate::exfat_impl::{dos_attr_names, dos_attr_string};
+use crate::filesystem::{
+    BlockRun, ByteRange, DirectoryCommon, FLAGS_KEY, File, FileCommon, Filesystem, STREAMS_KEY,
+    namespaced_metadata,
+};
+use crate::timefmt::format_timestamp;
+use serde::Serialize;
+use serde_json::{Value, json};
+use std::collections::HashMap;
+use std::error::Error;
+use std::io::{Read, Seek, SeekFrom};
+
+/// Namespace of the record fields in `File.metadata`.
+pub const METADATA_NAMESPACE: &str = "refs";
+
+const SUPERBLOCK_CLUSTER: u64 = 30;
+/// Header of every metadata page: signature, clocks, its clusters and table id.
+const PAGE_HEADER: usize = 0x50;
+const PAGE_CLUSTERS: usize = 0x20;
+const TREE_SIGNATURE: &[u8; 4] = b"MSB+";
+const CHECKPOINT_REFERENCES: usize = 0x70;
+const CHECKPOINT_CLOCK: usize = 0x60;
+const CHECKPOINT_POINTERS: usize = 0x90;
+const TABLE_OBJECTS: usize = 0;
+const TABLE_CONTAINERS: usize = 7;
+/// Root directory object id.
+const ROOT_DIRECTORY: u64 = 0x600;
+/// Block reference of a table root in an object table row, after the update clocks.
+const OBJECT_REFERENCE: usize = 0x20;
+/// First physical cluster of a container in its container table row.
+const CONTAINER_START: usize = 0xa0;
+const ROW_DELETED: u16 = 0x04;
+const NODE_INNER: u8 = 0x01;
+/// Directory table row types, first in their key before the UTF-16 name.
+const ROW_FILE: u32 = 0x0001_0030;
+const ROW_DIRECTORY: u32 = 0x0002_0030;
+const ATTRIBUTE_DATA: u32 = 0x80;
+const MAX_TREE_DEPTH: usize = 16;
+/// Largest object table kept in memory, in rows.
+const MAX_OBJECTS: usize = 1 << 20;
+
+fn le_u16(bytes: &[u8], at: usize) -> u16 {
+    u16::from_le_bytes(bytes[at..at + 2].try_into().unwrap())
+}
+
+fn le_u32(bytes: &[u8], at: usize) -> u32 {
+    u32::from_le_bytes(bytes[at..at + 4].try_into().unwrap())
+}
+
+fn le_u64(bytes: &[u8], at: usize) -> u64 {
+    u64::from_le_bytes(bytes[at..at + 8].try_into().unwrap())
+}
+
+/// `le_u64`, or 0 past the end of `bytes`.
+fn field_u64(bytes: &[u8], at: usize) -> u64 {
+    bytes.get(at..at + 8).map_or(0, |b| le_u64(b, 0))
+}
+
+fn filetime_to_unix(filetime: u64) -> Option<u64> {
+    (filetime != 0).then(|| (filetime / 10_000_000).saturating_sub(11_644_473_600))
+}
+
+fn utf16_name(bytes: &[u8]) -> String {
+    let units: Vec<u16> = bytes
+        .chunks_exact(2)
+        .map(|c| u16::from_le_bytes([c[0], c[1]]))
+        .collect();
+    String::from_utf16_lossy(&units)
+}
+
+/// One row of a Minstore node: its key, value and flags.
+struct Row {
+    key: Vec<u8>,
+    value: Vec<u8>,
+    flags: u16,
+}
+
+/// Rows of the node starting at `base` of `bytes`: its root element, whose first field is
+/// its size, then its index header and the key index pointing at the rows. Also returns
+/// whether the node is an inner one, whose values are references to child pages.
+fn node_rows(bytes: &[u8], base: usize) -> Result<(Vec<Row>, bool), Box<dyn Error>> {
+    let truncated = || "truncated ReFS node";
+    let header = base + le_u32(bytes.get(base..base + 4).ok_or_else(truncated)?, 0) as usize;
+    let index = bytes.get(header..header + 0x20).ok_or_else(truncated)?;
+    let inner = index[0x0d] & NODE_INNER != 0;
+    let (key_index, count) = (le_u32(index, 0x10) as usize, le_u32(index, 0x14) as usize);
+    let mut rows = Vec::with_capacity(count);
+    for n in 0..count {
+        let at = header + key_index + 4 * n;
+        let entry = bytes.get(at..at + 4).ok_or_else(truncated)?;
+        let row = header + (le_u32(entry, 0) & 0xffff) as usize;
+        let fields = bytes.get(row..row + 14).ok_or_else(truncated)?;
+        let (key_offset, key_length) = (le_u16(fields, 4) as usize, le_u16(fields, 6) as usize);
+        let flags = le_u16(fields, 8);
+        let (value_offset, value_length) =
+            (le_u16(fields, 10) as usize, le_u16(fields, 12) as usize);
+        let key = bytes
+            .get(row + key_offset..row + key_offset + key_length)
+            .ok_or_else(truncated)?;
+        let value = bytes
+            .get(row + value_offset..row + value_offset + value_length)
+            .ok_or_else(truncated)?;
+        rows.push(Row {
+            key: key.to_vec(),
+            value: value.to_vec(),
+            flags,
+        });
+    }
+    Ok((rows, inner))
+}
+
+#[derive(Debug, Clone, Serialize)]
+pub struct VolumeHeader {
+    pub version: (u8, u8),
+    pub sectors: u64,
+    pub bytes_per_sector: u32,
+    pub cluster_size: u32,
+    pub serial_number: String,
+    pub container_size: u64,
+    pub checkpoint_clock: u64,
+}
+
+/// A directory or a file.
+#[derive(Debug, Clone, Serialize)]
+pub struct RefsFile {
+    pub id: u64,
+    pub name: String,
+    pub is_dir: bool,
+    /// Object id of the table of a directory.
+    pub object_id: Option<u64>,
+    pub attributes: u32,
+    pub created: Option<u64>,
+    pub modified: Option<u64>,
+    pub changed: Option<u64>,
+    pub accessed: Option<u64>,
+    pub size: u64,
+    pub allocated_size: u64,
+    /// Named streams and their sizes.
+    pub streams: Vec<(String, u64)>,
+    /// Runs of the default stream: first virtual cluster in the file, first virtual
+    /// cluster on the volume and count.
+    #[serde(skip)]
+    pub runs: Vec<(u64, u64, u64)>,
+}
+
+impl FileCommon for RefsFile {
+    fn id(&self) -> u64 {
+        self.id
+    }
+    fn size(&self) -> u64 {
+        self.size
+    }
+    fn is_dir(&self) -> bool {
+        self.is_dir
+    }
+    fn to_string(&self) -> String {
+        format!(
+            "RefsFile {{ id: {:#x}, name: {}, size: {} }}",
+            self.id, self.name, self.size
+        )
+    }
+    fn to_json(&self) -> Value {
+        serde_json::to_value(self).unwrap_or(Value::Null)
+    }
+}
+
+#[derive(Debug, Clone)]
+pub struct RefsDirEntry {
+    pub id: u64,
+    pub name: String,
+    pub is_dir: bool,
+}
+
+impl DirectoryCommon for RefsDirEntry {
+    fn file_id(&self) -> u64 {
+        self.id
+    }
+    fn name(&self) -> &str {
+        &self.name
+    }
+    fn to_string(&self) -> String {
+        format!("RefsDirEntry {{ id: {:#x}, name: {} }}", self.id, self.name)
+    }
+    fn to_json(&self) -> Value {
+        json!({ "id": self.id, "name": self.name, "is_dir": self.is_dir })
+    }
+}
+
+pub struct RefsFS<T: Read + Seek> {
+    body: T,
+    header: VolumeHeader,
+    page_size: usize,
+    /// First physical cluster of each container.
+    containers: HashMap<u64, u64>,
+    /// Block reference of the table of each object.
+    objects: HashMap<u64, Vec<u8>>,
+    /// Records seen while listing directories, by id.
+    records: HashMap<u64, RefsFile>,
+}
+
+impl<T: Read + Seek> RefsFS<T> {
+    pub fn new(mut body: T) -> Result<Self, Box<dyn Error>> {
+        let mut boot = [0u8; 512];
+        body.seek(SeekFrom::Start(0))?;
+        body.read_exact(&mut boot)?;
+        if &boot[3..11] != b"ReFS\0\0\0\0" || &boot[16..20] != b"FSRS" {
+            return Err("not a ReFS volume".into());
+        }
+        let bytes_per_sector = le_u32(&boot, 0x20);
+        let cluster_size = bytes_per_sector.saturating_mul(le_u32(&boot, 0x24));
+        if !matches!(cluster_size, 4096 | 65536) {
+            return Err(format!("unsupported ReFS cluster size {}", cluster_size).into());
+        }
+        let version = (boot[0x28], boot[0x29]);
+        if version.0 < 3 {
+            return Err(format!("ReFS {}.{} is not supported", version.0, version.1).into());
+        }
+        let container_size = le_u64(&boot, 0x40);
+        if container_size < cluster_size as u64
+            || !container_size.is_multiple_of(cluster_size as u64)
+        {
+            return Err(format!("bad ReFS container size {}", container_size).into());
+        }
+        let mut fs = Self {
+            body,
+            header: VolumeHeader {
+                version,
+                sectors: le_u64(&boot, 0x18),
+                bytes_per_sector,
+                cluster_size,
+                serial_number: format!("{:016X}", le_u64(&boot, 0x38)),
+                container_size,
+                checkpoint_clock: 0,
+            },
+            page_size: (cluster_size as usize).max(16384),
+            containers: HashMap::new(),
+            objects: HashMap::new(),
+            records: HashMap::new(),
+        };
+
+        let superblock = fs.read_page(&[SUPERBLOCK_CLUSTER], false)?;
+        if &superblock[..4] != b"SUPB" {
+            return Err("no ReFS superblock".into());
+        }
+        let (offset, count) = (
+            le_u32(&superblock, CHECKPOINT_REFERENCES) as usize,
+            le_u32(&superblock, CHECKPOINT_REFERENCES + 4) as usize,
+        );
+        let mut checkpoint: Option<Vec<u8>> = None;
+        for n in 0..count.min(4) {
+            let cluster = field_u64(&superblock, offset + 8 * n);
+            let Ok(page) = fs.read_page(&[cluster], false) else {
+                continue;
+            };
+            if &page[..4] == b"CHKP"
+                && checkpoint
+                    .as_ref()
+                    .is_none_or(|c| le_u64(&page, CHECKPOINT_CLOCK) > le_u64(c, CHECKPOINT_CLOCK))
+            {
+                checkpoint = Some(page);
+            }
+        }
+        let checkpoint = checkpoint.ok_or("no valid ReFS checkpoint")?;
+        fs.header.checkpoint_clock = le_u64(&checkpoint, CHECKPOINT_CLOCK);
+        let table = |index: usize| -> Result<Vec<u8>, Box<dyn Error>> {
+            if index >= le_u32(&checkpoint, CHECKPOINT_POINTERS) as usize {
+                return Err(format!("ReFS checkpoint without table {}", index).into());
+            }
+            let at = le_u32(&checkpoint, CHECKPOINT_POINTERS + 4 + 4 * index) as usize;
+            Ok(checkpoint
+                .get(at..at + PAGE_CLUSTERS)
+                .ok_or("ReFS table reference past its checkpoint")?
+                .to_vec())
+        };
+
+        // The container table is addressed physically, all else virtually.
+        for row in fs.tree_rows(&table(TABLE_CONTAINERS)?, false)? {
+            if row.key.len() >= 8 && row.value.len() >= CONTAINER_START + 8 {
+                fs.containers
+                    .insert(le_u64(&row.key, 0), le_u64(&row.value, CONTAINER_START));
+            }
+        }
+        for row in fs.tree_rows(&table(TABLE_OBJECTS)?, true)? {
+            if row.key.len() >= 16 && row.value.len() >= OBJECT_REFERENCE + PAGE_CLUSTERS {
+                fs.objects.insert(
+                    le_u64(&row.key, 8),
+                    row.value[OBJECT_REFERENCE..OBJECT_REFERENCE + PAGE_CLUSTERS].to_vec(),
+                );
+            }
+            if fs.objects.len() > MAX_OBJECTS {
+                return Err("ReFS object table too large".into());
+            }
+        }
+        if !fs.objects.contains_key(&ROOT_DIRECTORY) {
+            return Err("ReFS root directory not in the object table".into());
+        }
+        Ok(fs)
+    }
+
+    pub fn header(&self) -> &VolumeHeader {
+        &self.header
+    }
+
+    fn clusters_per_container(&self) -> u64 {
+        self.header.container_size / self.header.cluster_size as u64
+    }
+
+    /// Physical cluster of virtual cluster `cluster`.
+    fn physical(&self, cluster: u64) -> Result<u64, Box<dyn Error>> {
+        let per_container = self.clusters_per_container();
+        let start = self
+            .containers
+            .get(&(cluster / per_container))
+            .ok_or_else(|| format!("ReFS cluster {:#x} in no container", cluster))?;
+        Ok(start + cluster % per_container)
+    }
+
+    fn read_cluster(&mut self, cluster: u64) -> Result<Vec<u8>, Box<dyn Error>> {
+        let size = self.header.cluster_size as u64;
+        if cluster.saturating_mul(size / self.header.bytes_per_sector as u64) >= self.header.sectors
+        {
+            return Err(format!("ReFS cluster {:#x} past the end of the volume", cluster).into());
+        }
+        let mut data = vec![0u8; size as usize];
+        self.body.seek(SeekFrom::Start(cluster * size))?;
+        self.body.read_exact(&mut data)?;
+        Ok(data)
+    }
+
+    /// The metadata page whose clusters are listed first in `clusters`, contiguous when
+    /// only the first is given.
+    fn read_page(&mut self, clusters: &[u64], virtual_: bool) -> Result<Vec<u8>, Box<dyn Error>> {
+        let count = self.page_size / self.header.cluster_size as usize;
+        let mut page = Vec::with_capacity(self.page_size);
+        for n in 0..count {
+            let cluster = match clusters.get(n) {
+                Some(cluster) if n == 0 || clusters.len() > 1 => *cluster,
+                _ => clusters[0] + n as u64,
+            };
+            let cluster = match virtual_ {
+                true => self.physical(cluster)?,
+                false => cluster,
+            };
+            page.extend(self.read_cluster(cluster)?);
+        }
+        Ok(page)
+    }
+
+    /// The tree page of a block reference: its clusters first.
+    fn tree_page(&mut self, reference: &[u8], virtual_: bool) -> Result<Vec<u8>, Box<dyn Error>> {
+        let clusters: Vec<u64> = (0..4).map(|n| le_u64(reference, 8 * n)).collect();
+        let count = (self.page_size / self.header.cluster_size as usize).min(4);
+        let page = self.read_page(&clusters[..count], virtual_)?;
+        if &page[..4] != TREE_SIGNATURE {
+            return Err(format!("no ReFS tree page at cluster {:#x}", clusters[0]).into());
+        }
+        Ok(page)
+    }
+
+    /// Leaf rows of the tree whose root page is referenced by `reference`, deleted ones
+    /// left out.
+    fn tree_rows(&mut self, reference: &[u8], virtual_: bool) -> Result<Vec<Row>, Box<dyn Error>> {
+        let page = self.tree_page(reference, virtual_)?;
+        let mut out = Vec::new();
+        self.collect_rows(&page, PAGE_HEADER, virtual_, 0, &mut out)?;
+        Ok(out)
+    }
+
+    fn collect_rows(
+        &mut self,
+        bytes: &[u8],
+        base: usize,
+        virtual_: bool,
+        depth: usize,
+        out: &mut Vec<Row>,
+    ) -> Result<(), Box<dyn Error>> {
+        if depth > MAX_TREE_DEPTH {
+            return Err("ReFS tree too deep".into());
+        }
+        let (rows, inner) = node_rows(bytes, base)?;
+        for row in rows {
+            if row.flags & ROW_DELETED != 0 {
+                continue;
+            }
+            if !inner {
+                out.push(row);
+                continue;
+            }
+            if row.value.len() < PAGE_CLUSTERS {
+                return Err("short ReFS child reference".into());
+            }
+            let child = self.tree_page(&row.value, virtual_)?;
+            self.collect_rows(&child, PAGE_HEADER, virtual_, depth + 1, out)?;
+        }
+        Ok(())
+    }
+
+    /// Rows of the table embedded in `value` or, for a large one, paged out of it.
+    fn embedded_rows(&mut self, value: &[u8]) -> Result<Vec<Row>, Box<dyn Error>> {
+        let mut out = Vec::new();
+        self.collect_rows(value, 0, true, 0, &mut out)?;
+        Ok(out)
+    }
+
+    /// The directory `object_id` with the fields of its link row, when known.
+    fn directory(&self, object_id: u64, name: &str, link: Option<&[u8]>) -> RefsFile {
+        let link = link.unwrap_or_default();
+        let time = |at: usize| filetime_to_unix(field_u64(link, at));
+        RefsFile {
+            id: object_id,
+            name: name.to_string(),
+            is_dir: true,
+            object_id: Some(object_id),
+            attributes: link.get(0x40..0x44).map_or(0x10, |a| le_u32(a, 0)),
+            created: time(0x18),
+            modified: time(0x20),
+            changed: time(0x28),
+            accessed: time(0x30),
+            size: 0,
+            allocated_size: 0,
+            streams: Vec::new(),
+            runs: Vec::new(),
+        }
+    }
+
+    /// The file of directory `directory` described by the table embedded in `value`.
+    fn file(
+        &mut self,
+        directory: u64,
+        name: &str,
+        value: &[u8],
+    ) -> Result<RefsFile, Box<dyn Error>> {
+        let root_size = le_u32(value.get(..4).ok_or("short ReFS file row")?, 0) as usize;
+        let root = value.get(..root_size).ok_or("short ReFS file row")?;
+        let time = |at: usize| filetime_to_unix(field_u64(root, at));
+        let mut file = RefsFile {
+            id: directory << 32 | (field_u64(root, 0x58) & 0xffff_ffff),
+            name: name.to_string(),
+            is_dir: false,
+            object_id: None,
+            attributes: root.get(0x48..0x4c).map_or(0, |a| le_u32(a, 0)),
+            created: time(0x28),
+            modified: time(0x30),
+            changed: time(0x38),
+            accessed: time(0x40),
+            size: field_u64(root, 0x68),
+            allocated_size: field_u64(root, 0x70),
+            streams: Vec::new(),
+            runs: Vec::new(),
+        };
+        for attribute in self.embedded_rows(value)? {
+            if attribute.key.len() < 8 || le_u32(&attribute.key, 0) != ATTRIBUTE_DATA {
+                continue;
+            }
+            let stream = utf16_name(&attribute.key[8..]);
+            if !stream.is_empty() {
+                file.streams
+                    .push((stream, field_u64(&attribute.value, 0x08)));
+                continue;
+            }
+            for run in self.embedded_rows(&attribute.value)? {
+                if run.key.len() >= 8 && run.value.len() >= 16 {
+                    file.runs.push((
+                        le_u64(&run.key, 0),
+                        le_u64(&run.value, 0),
+                        le_u64(&run.value, 8),
+                    ));
+                }
+            }
+        }
+        file.runs.sort_unstable();
+        Ok(file)
+    }
+
+    /// Files and subdirectories of the directory `object_id`.
+    fn directory_entries(&mut self, object_id: u64) -> Result<Vec<RefsFile>, Box<dyn Error>> {
+        let reference = self
+            .objects
+            .get(&object_id)
+            .ok_or_else(|| format!("ReFS object {:#x} not in the object table", object_id))?
+            .clone();
+        let mut entries = Vec::new();
+        for row in self.tree_rows(&reference, true)? {
+            if row.key.len() < 4 {
+                continue;
+            }
+            let name = utf16_name(&row.key[4..]);
+            match le_u32(&row.key, 0) {
+                ROW_FILE => entries.push(self.file(object_id, &name, &row.value)?),
+                ROW_DIRECTORY if row.value.len() >= 0x10 => {
+                    let child = le_u64(&row.value, 0x08);
+                    entries.push(self.directory(child, &name, Some(&row.value)));
+                }
+                _ => {}
+            }
+        }
+        Ok(entries)
+    }
+
+    fn read_content(
+        &mut self,
+        file: &RefsFile,
+        offset: u64,
+        length: usize,
+    ) -> Result<Vec<u8>, Box<dyn Error>> {
+        if offset >= file.size {
+            return Ok(Vec::new());
+        }
+        let length = length.min((file.size - offset) as usize);
+        let cluster_size = self.header.cluster_size as u64;
+        let mut out = Vec::with_capacity(length);
+        let mut position = offset;
+        let end = offset + length as u64;
+        while position < end {
+            let vcn = position / cluster_size;
+            let start = (position % cluster_size) as usize;
+            let count = (cluster_size as usize - start).min((end - position) as usize);
+            let run = file
+                .runs
+                .iter()
+                .find(|(first, _, clusters)| (*first..first + clusters).contains(&vcn));
+            match run {
+                Some((first, cluster, _)) => {
+                    let physical = self.physical(cluster + (vcn - first))?;
+                    out.extend_from_slice(&self.read_cluster(physical)?[start..start + count]);
+                }
+                None => out.resize(out.len() + count, 0),
+            }
+            position += count as u64;
+        }
+        Ok(out)
+    }
+}
+
+impl<T: Read + Seek> Filesystem for RefsFS<T> {
+    type FileType = RefsFile;
+    type DirectoryType = RefsDirEntry;
+
+    fn filesystem_type(&self) -> String {
+        "ReFS".to_string()
+    }
+
+    fn path_separator(&self) -> String {
+        "\\".to_string()
+    }
+
+    /// Objects of the object table: directories and system tables.
+    fn record_count(&mut self) -> u64 {
+        self.objects.len() as u64
+    }
+
+    fn block_size(&self) -> u64 {
+        self.header.cluster_size as u64
+    }
+
+    fn get_metadata(&self) -> Result<Value, Box<dyn Error>> {
+        Ok(serde_json::to_value(&self.header)?)
+    }
+
+    fn get_metadata_pretty(&self) -> Result<String, Box<dyn Error>> {
+        let h = &self.header;
+        Ok(format!(
+            "ReFS {}.{} volume {}\n\
+             Clusters: {} bytes, {} sectors of {} bytes\n\
+             Containers: {} bytes, {} mapped\n\
+             Checkpoint clock: {}, objects: {}\n",
+            h.version.0,
+            h.version.1,
+            h.serial_number,
+            h.cluster_size,
+            h.sectors,
+            h.bytes_per_sector,
+            h.container_size,
+            self.containers.len(),
+            h.checkpoint_clock,
+            self.objects.len()
+        ))
+    }
+
+    fn get_file(&mut self, id: u64) -> Result<Self::FileType, Box<dyn Error>> {
+        if let Some(record) = self.records.get(&id) {
+            return Ok(record.clone());
+        }
+        let directory = id >> 32;
+        if directory == 0 {
+            if !self.objects.contains_key(&id) {
+                return Err(format!("ReFS object {:#x} not in the object table", id).into());
+            }
+            return Ok(self.directory(id, "", None));
+        }
+        self.directory_entries(directory)?
+            .into_iter()
+            .find(|f| f.id == id)
+            .ok_or_else(|| format!("no ReFS file {:#x}", id).into())
+    }
+
+    fn read_file_content(&mut self, file: &Self::FileType) -> Result<Vec<u8>, Box<dyn Error>> {
+        self.read_content(file, 0, file.size as usize)
+    }
+
+    fn read_file_prefix(
+        &mut self,
+        file: &Self::FileType,
+        length: usize,
+    ) -> Result<Vec<u8>, Box<dyn Error>> {
+        self.read_content(file, 0, length)
+    }
+
+    fn read_file_slice(
+        &mut self,
+        file: &Self::FileType,
+        offset: u64,
+        length: usize,
+    ) -> Result<Vec<u8>, Box<dyn Error>> {
+        self.read_content(file, offset, length)
+    }
+
+    fn list_dir(
+        &mut self,
+        file: &Self::FileType,
+    ) -> Result<Vec<Self::DirectoryType>, Box<dyn Error>> {
+        let object_id = file.object_id.ok_or("not a directory")?;
+        let entries = self.directory_entries(object_id)?;
+        let listing = entries
+            .iter()
+            .map(|e| RefsDirEntry {
+                id: e.id,
+                name: e.name.clone(),
+                is_dir: e.is_dir,
+            })
+            .collect();
+        for entry in entries {
+            self.records.insert(entry.id, entry);
+        }
+        Ok(listing)
+    }
+
+    fn record_to_file(&self, file: &Self::FileType, file_id: u64, absolute_path: &str) -> File {
+        let common = json!({
+            FLAGS_KEY: dos_attr_names(file.attributes),
+            STREAMS_KEY: file
+                .streams
+                .iter()
+                .map(|(name, size)| json!({ "name": name, "size": size }))
+                .collect::<Vec<_>>(),
+        });
+        let metadata = namespaced_metadata(METADATA_NAMESPACE, file.to_json(), common);
+        let name = match absolute_path.rsplit_once(['/', '\\']) {
+            Some((_, name)) if !name.is_empty() => name,
+            _ => absolute_path,
+        };
+        let permissions = dos_attr_string(file.attributes, file.is_dir);
+        File {
+            id: None,
+            identifier: file_id,
+            absolute_path: absolute_path.to_string(),
+            name: name.to_string(),
+            raw_name: None,
+            ftype: if file.is_dir { "dir" } else { "file" }.to_string(),
+            size: file.size,
+            size_on_disk: Some(file.allocated_size),
+            created: file.created,
+            modified: file.modified,
+            accessed: file.accessed,
+            changed: file.changed,
+            permissions: Some(permissions.clone()),
+            owner: None,
+            group: None,
+            display: Some(format!(
+                "[{:#x}] - {} {:>5} {} {}",
+                file_id,
+                permissions,
+                file.size,
+                format_timestamp(file.modified.unwrap_or(0)),
+                absolute_path
+            )),
+            sig_name: None,
+            sig_mime: None,
+            sig_exts: None,
+            detected_type: None,
+            ext_mismatch: None,
+            md5: None,
+            sha1: None,
+            sha256: None,
+            metadata,
+        }
+    }
+
+    fn get_root_file_id(&self) -> u64 {
+        ROOT_DIRECTORY
+    }
+
+    fn file_block_runs(
+        &mut self,
+        file: &Self::FileType,
+    ) -> Result<Option<Vec<BlockRun>>, Box<dyn Error>> {
+        let mut runs: Vec<BlockRun> = Vec::new();
+        for (_, first, count) in file.runs.clone() {
+            for cluster in first..first + count {
+                let physical = self.physical(cluster)?;
+                match runs.last_mut() {
+                    Some((start, length)) if *start + *length == physical => *length += 1,
+                    _ => runs.push((physical, 1)),
+                }
+            }
+        }
+        Ok(Some(runs))
+    }
+
+    fn file_holes(
+        &mut self,
+        file: &Self::FileType,
+    ) -> Result<Option<Vec<ByteRange>>, Box<dyn Error>> {
+        if file.is_dir {
+            return Ok(Some(Vec::new()));
+        }
+        let cluster_size = self.header.cluster_size as u64;
+        let mut holes: Vec<ByteRange> = Vec::new();
+        let mut position = 0;
+        let runs = file
+            .runs
+            .iter()
+            .map(|(first, _, count)| (first * cluster_size, count * cluster_size))
+            .chain([(file.size, 0)]);
+        for (start, length) in runs {
+            let start = start.min(file.size);
+            if start > position {
+                holes.push((position, start - position));
+            }
+            position = position.max(start + length);
+        }
+        Ok(Some(holes))
+    }
+}
@@ -0,0 +1,130 @@
+use crate::filesystem::{DirectoryCommon, FileCommon, Filesystem};
+use crate::names::{name_matches, split_path};
+use std::collections::{HashSet, VecDeque};
+use std::error::Error;
+
+/// Called for every directory entry with the entry's record identifier, path and record.
+type EntryVisitor<'a, F> =
+    dyn FnMut(&mut F, u64, &str, &<F as Filesystem>::FileType) -> Result<bool, Box<dyn Error>> + 'a;
+
+/// Breadth-First listing of every directory entry, unlike `walk_fs` which emits each
+/// record once: a record with several hard links is visited once per name.
+/// `visit` returns `false` to stop the traversal.
+fn for_each_entry<F: Filesystem + ?Sized>(
+    fs: &mut F,
+    visit: &mut EntryVisitor<F>,
+) -> Result<(), Box<dyn Error>> {
+    let separator = fs.path_separator();
+    let root = fs.get_root_file_id();
+    let mut seen = HashSet::from([root]);
+    let mut queue = VecDeque::from([(root, separator.clone())]);
+
+    while let Some((dir_id, path)) = queue.pop_front() {
+        let dir = match fs.get_file(dir_id) {
+            Ok(dir) => dir,
+            Err(_) => continue,
+        };
+        let entries = match fs.list_dir(&dir) {
+            Ok(entries) => entries,
+            Err(_) => continue,
+        };
+        for entry in entries {
+            if entry.name() == "." || entry.name() == ".." {
+                continue;
+            }
+            let child_id = entry.file_id();
+            let child = match fs.get_file(child_id) {
+                Ok(child) => child,
+                Err(_) => continue,
+            };
+            let child_path = if path == separator {
+                format!("{}{}", separator, entry.name())
+            } else {
+                format!("{}{}{}", path, separator, entry.name())
+            };
+            if !visit(fs, child_id, &child_path, &child)? {
+                return Ok(());
+            }
+            if child.is_dir() && seen.insert(child_id) {
+                queue.push_back((child_id, child_path));
+            }
+        }
+    }
+    Ok(())
+}
+
+/// Every path referencing `file_id`, including all of its hard links.
+pub fn find_names<F: Filesystem + ?Sized>(
+    fs: &mut F,
+    file_id: u64,
+) -> Result<Vec<String>, Box<dyn Error>> {
+    if file_id == fs.get_root_file_id() {
+        return Ok(vec![fs.path_separator()]);
+    }
+    let mut names = Vec::new();
+    for_each_entry(fs, &mut |_, id, path, _| {
+        if id == file_id {
+            names.push(path.to_string());
+        }
+        Ok(true)
+    })?;
+    Ok(names)
+}
+
+/// Records (identifier and first path found) whose content is stored in `block`.
+/// Fails when the backend cannot map records to blocks.
+pub fn find_block_owners<F: Filesystem + ?Sized>(
+    fs: &mut F,
+    block: u64,
+) -> Result<Vec<(u64, String)>, Box<dyn Error>> {
+    let mut owners = Vec::new();
+    let mut seen = HashSet::new();
+    let mut supported = true;
+    for_each_entry(fs, &mut |fs, id, path, record| {
+        if !seen.insert(id) {
+            return Ok(true);
+        }
+        match fs.file_block_runs(record)? {
+            Some(runs) => {
+                if runs
+                    .iter()
+                    .any(|(start, count)| block >= *start && block - start < *count)
+                {
+                    owners.push((id, path.to_string()));
+                }
+                Ok(true)
+            }
+            None => {
+                supported = false;
+                Ok(false)
+            }
+        }
+    })?;
+    if !supported {
+        return Err(format!(
+            "block to record mapping is not available for {}",
+            fs.filesystem_type()
+        )
+        .into());
+    }
+    Ok(owners)
+}
+
+/// Resolve a path to the record identifier found in its parent directory listing.
+pub fn find_path_id<F: Filesystem + ?Sized>(fs: &mut F, path: &str) -> Result<u64, Box<dyn Error>> {
+    let mut current = fs.get_root_file_id();
+    let separator = fs.path_separator();
+    for component in split_path(path, &separator) {
+        let dir = fs.get_file(current)?;
+        if !dir.is_dir() {
+            return Err(format!("'{}' is not a directory", component).into());
+        }
+        current = fs
+            .list_dir(&dir)?
+            .iter()
+            .find(|e| name_matches(e.name(), component))
+            .map(|e| e.file_id())
+            .ok_or_else(|| format!("'{}' not found in '{}'", component, path))?;
+    }
+    Ok(current)
+}
@@ -0,0 +1,256 @@
+use crate::filesystem::File;
+use globset::{GlobBuilder, GlobSet, GlobSetBuilder};
+use regex::bytes::{Regex, RegexBuilder};
+use std::error::Error;
+
+/// A file name (or path) pattern, given either as a regular expression or a shell glob.
+#[derive(Debug, Clone)]
+pub struct NamePattern {
+    regex: Regex,
+    match_path: bool,
+}
+
+impl NamePattern {
+    /// Build a pattern from a regular expression (unanchored, like `grep`).
+    pub fn regex(pattern: &str, case_insensitive: bool) -> Result<Self, Box<dyn Error>> {
+        let regex = RegexBuilder::new(pattern)
+            .case_insensitive(case_insensitive)
+            .build()?;
+        Ok(Self {
+            regex,
+            match_path: false,
+        })
+    }
+
+    /// Build a pattern from a shell glob (`*.ps1`, `report-??.{doc,docx}`), matched
+    /// against the whole name.
+    pub fn glob(pattern: &str, case_insensitive: bool) -> Result<Self, Box<dyn Error>> {
+        let glob = GlobBuilder::new(pattern)
+            .case_insensitive(case_insensitive)
+            .literal_separator(true)
+            .build()?;
+        Ok(Self {
+            regex: Regex::new(glob.regex())?,
+            match_path: false,
+        })
+    }
+
+    /// Match against the absolute path instead of the file name.
+    pub fn on_path(mut self) -> Self {
+        self.match_path = true;
+        self
+    }
+
+    pub fn is_match_str(&self, value: &str) -> bool {
+        self.regex.is_match(value.as_bytes())
+    }
+
+    pub fn is_match(&self, file: &File) -> bool {
+        if self.match_path {
+            self.is_match_str(&file.absolute_path)
+        } else {
+            self.is_match_str(&file.name)
+        }
+    }
+}
+
+/// Normalize a path glob to the `/`-separated, rooted form paths are matched in. Both `/`
+/// and `\` are accepted as separators and a leading drive letter (`C:`) is dropped.
+pub(crate) fn normalize_path_glob(pattern: &str) -> String {
+    let pattern = pattern.trim().replace('\\', "/");
+    let bytes = pattern.as_bytes();
+    let pattern = if bytes.len() >= 2 && bytes[1] == b':' && bytes[0].is_ascii_alphabetic() {
+        &pattern[2..]
+    } else {
+        &pattern[..]
+    };
+    if pattern.starts_with('/') {
+        pattern.to_string()
+    } else {
+        format!("/{}", pattern)
+    }
+}
+
+/// A filesystem path with `separator` replaced by `/`, as matched by path globs.
+pub(crate) fn slash_path(path: &str, separator: &str) -> String {
+    if separator == "/" {
+        path.to_string()
+    } else {
+        path.replace(separator, "/")
+    }
+}
+
+/// Path globs (`/proc/**`, `**/node_modules`) whose matches are skipped by walks, along
+/// with everything below them.
+#[derive(Debug, Clone)]
+pub struct ExcludeSet {
+    set: GlobSet,
+}
+
+impl ExcludeSet {
+    pub fn new<S: AsRef<str>>(patterns: &[S]) -> Result<Self, Box<dyn Error>> {
+        let mut builder = GlobSetBuilder::new();
+        for pattern in patterns {
+            let glob = GlobBuilder::new(&normalize_path_glob(pattern.as_ref()))
+                .literal_separator(true)
+                .build()
+                .map_err(|e| format!("invalid exclude pattern '{}': {}", pattern.as_ref(), e))?;
+            builder.add(glob);
+        }
+        Ok(Self {
+            set: builder.build()?,
+        })
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.set.is_empty()
+    }
+
+    pub fn is_excluded(&self, path: &str, separator: &str) -> bool {
+        self.set.is_match(slash_path(path, separator))
+    }
+}
+
+/// Lines longer than this are searched in windows, to keep memory bounded on binary content.
+const MAX_LINE: usize = 64 * 1024;
+/// Overlap kept between windows of an over-long line; longer matches may be missed there.
+const WINDOW_OVERLAP: usize = 4096;
+/// Bytes of context shown on each side of a match in a long line.
+const CONTEXT: usize = 80;
+const READ_CHUNK: usize = 64 * 1024;
+
+/// A content match: offset of the first matching byte and the (lossy) matching line.
+#[derive(Debug, Clone, PartialEq, Eq, serde::Serialize)]
+pub struct GrepMatch {
+    pub offset: u64,
+    pub line: String,
+}
+
+/// Compile a content regex (matched on raw bytes).
+pub fn content_regex(pattern: &str, case_insensitive: bool) -> Result<Regex, Box<dyn Error>> {
+    Ok(RegexBuilder::new(pattern)
+        .case_insensitive(case_insensitive)
+        .build()?)
+}
+
+/// Compile a hex byte pattern such as `4d5a9000` or `4d 5a 90 00`.
+pub fn hex_regex(hex_pattern: &str) -> Result<Regex, Box<dyn Error>> {
+    let compact: String = hex_pattern.split_whitespace().collect();
+    let bytes = hex::decode(&compact).map_err(|e| format!("invalid hex pattern: {}", e))?;
+    if bytes.is_empty() {
+        return Err("empty hex pattern".into());
+    }
+    let escaped: String = bytes.iter().map(|b| format!("\\x{:02x}", b)).collect();
+    Ok(RegexBuilder::new(&format!("(?-u){}", escaped)).build()?)
+}
+
+/// Printable rendering of a line, cut around the match when it is long.
+fn render_line(line: &[u8], start: usize, end: usize) -> String {
+    let line = line.strip_suffix(b"\r").unwrap_or(line);
+    let (from, to) = if line.len() > 2 * CONTEXT + (end - start) {
+        (
+            start.saturating_sub(CONTEXT),
+            (end + CONTEXT).min(line.len()),
+        )
+    } else {
+        (0, line.len())
+    };
+    String::from_utf8_lossy(&line[from..to.max(from)])
+        .chars()
+        .map(|c| {
+            if (c.is_control() && c != '\t') || c == char::REPLACEMENT_CHARACTER {
+                '.'
+            } else {
+                c
+            }
+        })
+        .collect()
+}
+
+/// Report the first match of a line, if any match starts before `limit`.
+fn search_line(
+    regex: &Regex,
+    line: &[u8],
+    line_offset: u64,
+    limit: usize,
+    callback: &mut dyn FnMut(GrepMatch) -> bool,
+) -> bool {
+    match regex.find(line) {
+        Some(m) if m.start() < limit => callback(GrepMatch {
+            offset: line_offset + m.start() as u64,
+            line: render_line(line, m.start(), m.end()),
+        }),
+        _ => true,
+    }
+}
+
+/// Stream `reader` line by line and report the first match of every matching line.
+/// `callback` returns `false` to stop early. Memory is bounded by `MAX_LINE`.
+pub fn grep_reader<R: std::io::Read + ?Sized>(
+    reader: &mut R,
+    regex: &Regex,
+    callback: &mut dyn FnMut(GrepMatch) -> bool,
+) -> std::io::Result<()> {
+    let mut buf = vec![0u8; READ_CHUNK];
+    let mut pending: Vec<u8> = Vec::new();
+    let mut pending_offset = 0u64;
+
+    loop {
+        let n = match reader.read(&mut buf) {
+            Ok(0) => break,
+            Ok(n) => n,
+            Err(e) if e.kind() == std::io::ErrorKind::Interrupted => continue,
+            Err(e) => return Err(e),
+        };
+        pending.extend_from_slice(&buf[..n]);
+
+        let mut consumed = 0;
+        while let Some(nl) = pending[consumed..].iter().position(|b| *b == b'\n') {
+            let line = &pending[consumed..consumed + nl];
+            let offset = pending_offset + consumed as u64;
+            if !search_line(regex, line, offset, line.len().max(1), callback) {
+                return Ok(());
+            }
+            consumed += nl + 1;
+        }
+        pending.drain(..consumed);
+        pending_offset += consumed as u64;
+
+        if pending.len() > MAX_LINE {
+            // Report matches starting in the part dropped now; the overlap is searched
+            // again with the next window.
+            let keep_from = pending.len() - WINDOW_OVERLAP;
+            if !search_line(regex, &pending, pending_offset, keep_from, callback) {
+                return Ok(());
+            }
+            pending.drain(..keep_from);
+            pending_offset += keep_from as u64;
+        }
+    }
+
+    if !pending.is_empty() {
+        search_line(regex, &pending, pending_offset, pending.len(), callback);
+    }
+    Ok(())
+}
+
+/// Parse a size such as `4096`, `10K`, `1.5M` or `2G` (binary multiples).
+pub fn parse_size(value: &str) -> Result<u64, String> {
+    let value = value.trim();
+    let split = value
+        .find(|c: char| !(c.is_ascii_digit() || c == '.'))
+        .unwrap_or(value.len());
+    let (number, unit) = value.split_at(split);
+    let number: f64 = number
+        .parse()
+        .map_err(|_| format!("invalid size: {}", value))?;
+    let multiplier: u64 = match unit.trim().to_ascii_uppercase().as_str() {
+        "" | "B" => 1,
+        "K" | "KB" | "KIB" => 1 << 10,
+        "M" | "MB" | "MIB" => 1 << 20,
+        "G" | "GB" | "GIB" => 1 << 30,
+        "T" | "TB" | "TIB" => 1 << 40,
+        other => return Err(format!("invalid size unit: {}", other)),
+    };
+    Ok((number * multiplier as f64) as u64)
+}
@@ -0,0 +1,125 @@
+use crate::apfs_impl::{PACKED_INODE_MASK, pack_identifier};
+use clap_num::maybe_hex;
+use std::error::Error;
+use std::fmt;
+use std::io::{self, BufRead, BufReader};
+
+/// Largest range accepted in a record list, to catch typos like `1-99999999999`.
+const MAX_RANGE_LEN: u64 = 10_000_000;
+
+/// A record requested on the command line, either by identifier or by path.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum RecordSelector {
+    Id(u64),
+    Path(String),
+}
+
+impl fmt::Display for RecordSelector {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            RecordSelector::Id(id) => write!(f, "{}", id),
+            RecordSelector::Path(path) => write!(f, "{}", path),
+        }
+    }
+}
+
+/// Prefixes naming the record kind explicitly (`mft:1234`, `inode:12`), ignored for lookup.
+const ID_PREFIXES: &[&str] = &["mft", "inode", "ino", "record", "id"];
+
+/// Parse a record identifier: `42`, `0x2A`, `mft:1234` or `volume:inode` (APFS, e.g. `3:257`).
+/// Returns `None` when the token is not an identifier, so it can be taken as a path.
+fn parse_id(token: &str) -> Option<Result<u64, String>> {
+    if let Ok(id) = maybe_hex::<u64>(token) {
+        return Some(Ok(id));
+    }
+    let (prefix, rest) = token.split_once(':')?;
+    if ID_PREFIXES.contains(&prefix.to_ascii_lowercase().as_str()) {
+        return Some(
+            maybe_hex::<u64>(rest).map_err(|_| format!("invalid record identifier: {}", token)),
+        );
+    }
+    let (Ok(volume), Ok(inode)) = (maybe_hex::<u64>(prefix), maybe_hex::<u64>(rest)) else {
+        return None;
+    };
+    if volume > 0xff || inode > PACKED_INODE_MASK {
+        return Some(Err(format!(
+            "volume:inode out of range (volume <= 255, inode < 2^56): {}",
+            token
+        )));
+    }
+    Some(Ok(pack_identifier(volume as u32, inode)))
+}
+
+/// Parse a single record identifier in any of the `--record` identifier forms.
+pub fn parse_record_id(token: &str) -> Result<u64, String> {
+    parse_id(token.trim()).unwrap_or_else(|| Err(format!("invalid record identifier: {}", token)))
+}
+
+/// Parse a single token: an identifier (decimal or hex), a range `A-B` or a path.
+fn parse_token(token: &str, out: &mut Vec<RecordSelector>) -> Result<(), Box<dyn Error>> {
+    let token = token.trim();
+    if token.is_empty() {
+        return Ok(());
+    }
+    if let Some(id) = parse_id(token) {
+        out.push(RecordSelector::Id(id?));
+        return Ok(());
+    }
+    if let Some((start, end)) = token.split_once('-')
+        && let (Some(start), Some(end)) = (parse_id(start.trim()), parse_id(end.trim()))
+    {
+        let (start, end) = (start?, end?);
+        if end < start {
+            return Err(format!("invalid record range: {}", token).into());
+        }
+        if end - start >= MAX_RANGE_LEN {
+            return Err(format!("record range too large: {}", token).into());
+        }
+        out.extend((start..=end).map(RecordSelector::Id));
+        return Ok(());
+    }
+    out.push(RecordSelector::Path(token.to_string()));
+    Ok(())
+}
+
+/// Read one selector per line from a list file, or STDIN when `source` is `-`.
+/// Empty lines and lines starting with `#` are ignored.
+fn parse_list_file(source: &str, out: &mut Vec<RecordSelector>) -> Result<(), Box<dyn Error>> {
+    let reader: Box<dyn BufRead> = if source == "-" {
+        Box::new(BufReader::new(io::stdin()))
+    } else {
+        Box::new(BufReader::new(std::fs::File::open(source).map_err(
+            |e| format!("could not open record list '{}': {}", source, e),
+        )?))
+    };
+    for line in reader.lines() {
+        let line = line?;
+        let line = line.trim();
+        if line.is_empty() || line.starts_with('#') {
+            continue;
+        }
+        parse_token(line, out)?;
+    }
+    Ok(())
+}
+
+/// Parse a `--record` argument.
+///
+/// Accepted forms, combinable with commas:
+/// - `42`, `0x2A`: a single record identifier
+/// - `mft:1234`, `inode:12`: the same, with the record kind spelled out
+/// - `3:257`: inode 257 of APFS volume 3 (packed as `3 << 56 | 257`)
+/// - `100-200`: an inclusive range of identifiers
+/// - `/etc/passwd`: a path resolved from the filesystem root
+/// - `@list.txt`, `@-`: one identifier, range or path per line from a file or STDIN
+pub fn parse_record_list(arg: &str) -> Result<Vec<RecordSelector>, Box<dyn Error>> {
+    let mut out = Vec::new();
+    for token in arg.split(',') {
+        if let Some(source) = token.trim().strip_prefix('@') {
+            parse_list_file(source, &mut out)?;
+        } else {
+            parse_token(token, &mut out)?;
+        }
+    }
+    Ok(out)
+}
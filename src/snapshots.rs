@@ -0,0 +1,319 @@
+//! Point-in-time views of a volume. NTFS volume shadow copies are listed from the
+//! catalog the Volume Shadow Copy Service keeps at the start of the volume, and a
+//! selected one is exposed as a `Read + Seek` stream over the reconstructed volume, so
+//! every subcommand can run against it like against the live volume.
+//!
+//! Reconstruction follows the copy-on-write stores: a block changed after the shadow copy
+//! was taken is read from the oldest store (the selected one or a later one) holding its
+//! previous content, otherwise from the live volume.
+use crate::timefmt::format_timestamp;
+use serde::Serialize;
+use std::collections::HashMap;
+use std::error::Error;
+use std::io::{self, Read, Seek, SeekFrom};
+
+/// Offset of the VSS volume header in the volume.
+const VSS_HEADER_OFFSET: u64 = 0x1e00;
+/// `{3808876b-c176-4e48-b7ae-04046e6cc752}`, identifying every VSS structure.
+const VSS_IDENTIFIER: [u8; 16] = [
+    0x6b, 0x87, 0x08, 0x38, 0x76, 0xc1, 0x48, 0x4e, 0xb7, 0xae, 0x04, 0x04, 0x6e, 0x6c, 0xc7, 0x52,
+];
+/// Size of catalog and store blocks, and of the copy-on-write unit.
+const VSS_BLOCK_SIZE: u64 = 0x4000;
+/// Size of the header of catalog and store blocks.
+const VSS_BLOCK_HEADER: usize = 128;
+const CATALOG_ENTRY_SIZE: usize = 128;
+const DESCRIPTOR_SIZE: usize = 32;
+/// Upper bound on the blocks of a catalog or store list, against looping chains.
+const MAX_LIST_BLOCKS: usize = 1 << 20;
+
+const FLAG_FORWARDER: u32 = 0x1;
+const FLAG_OVERLAY: u32 = 0x2;
+const FLAG_NOT_USED: u32 = 0x4;
+
+/// A point in time of a volume.
+#[derive(Debug, Clone, Serialize)]
+pub struct Snapshot {
+    /// 1-based position, oldest first.
+    pub index: usize,
+    /// Store identifier.
+    pub id: String,
+    pub name: String,
+    /// `vss` for NTFS volume shadow copies.
+    pub kind: String,
+    /// Creation time, Unix seconds.
+    pub created: Option<u64>,
+    pub volume_size: u64,
+    /// Offset of the store block list in the volume.
+    #[serde(skip)]
+    block_list: u64,
+}
+
+impl Snapshot {
+    pub fn to_line(&self) -> String {
+        format!(
+            "{:<3} {:<6} {} {} {:>14} {}",
+            self.index,
+            self.kind,
+            self.id,
+            self.created
+                .map(format_timestamp)
+                .unwrap_or_else(|| "-".into()),
+            self.volume_size,
+            self.name
+        )
+    }
+}
+
+fn u32_at(buf: &[u8], at: usize) -> u32 {
+    u32::from_le_bytes(buf[at..at + 4].try_into().unwrap())
+}
+
+fn u64_at(buf: &[u8], at: usize) -> u64 {
+    u64::from_le_bytes(buf[at..at + 8].try_into().unwrap())
+}
+
+/// Textual form of a little-endian (Microsoft) GUID.
+fn guid_at(buf: &[u8], at: usize) -> String {
+    let b = &buf[at..at + 16];
+    format!(
+        "{:08x}-{:04x}-{:04x}-{}-{}",
+        u32_at(b, 0),
+        u16::from_le_bytes([b[4], b[5]]),
+        u16::from_le_bytes([b[6], b[7]]),
+        hex::encode(&b[8..10]),
+        hex::encode(&b[10..16])
+    )
+}
+
+fn read_at<R: Read + Seek>(stream: &mut R, offset: u64, buf: &mut [u8]) -> io::Result<()> {
+    stream.seek(SeekFrom::Start(offset))?;
+    stream.read_exact(buf)
+}
+
+/// Read a chain of VSS blocks starting at `offset`, checking each header.
+fn read_block_chain<R: Read + Seek>(
+    stream: &mut R,
+    mut offset: u64,
+    record_type: u32,
+) -> Result<Vec<Vec<u8>>, Box<dyn Error>> {
+    let mut blocks = Vec::new();
+    while offset != 0 {
+        if blocks.len() >= MAX_LIST_BLOCKS {
+            return Err("VSS block chain does not terminate".into());
+        }
+        let mut block = vec![0u8; VSS_BLOCK_SIZE as usize];
+        read_at(stream, offset, &mut block)?;
+        if block[..16] != VSS_IDENTIFIER || u32_at(&block, 20) != record_type {
+            return Err(format!("invalid VSS block at offset {:#x}", offset).into());
+        }
+        offset = u64_at(&block, 40);
+        blocks.push(block);
+    }
+    Ok(blocks)
+}
+
+/// Shadow copies of the volume in `stream`, oldest first. A volume without the VSS
+/// header (or with an empty catalog) has none.
+pub fn list_snapshots<R: Read + Seek>(stream: &mut R) -> Result<Vec<Snapshot>, Box<dyn Error>> {
+    let mut header = [0u8; 128];
+    if read_at(stream, VSS_HEADER_OFFSET, &mut header).is_err() || header[..16] != VSS_IDENTIFIER {
+        return Ok(Vec::new());
+    }
+    let catalog = u64_at(&header, 48);
+    let mut snapshots = Vec::new();
+    let mut block_lists = HashMap::new();
+    for block in read_block_chain(stream, catalog, 2)? {
+        for entry in block[VSS_BLOCK_HEADER..].chunks_exact(CATALOG_ENTRY_SIZE) {
+            match u64_at(entry, 0) {
+                2 => {
+                    let filetime = u64_at(entry, 48);
+                    snapshots.push(Snapshot {
+                        index: 0,
+                        id: guid_at(entry, 16),
+                        name: String::new(),
+                        kind: "vss".to_string(),
+                        created: (filetime != 0)
+                            .then(|| (filetime / 10_000_000).saturating_sub(11_644_473_600)),
+                        volume_size: u64_at(entry, 8),
+                        block_list: 0,
+                    });
+                }
+                3 => {
+                    block_lists.insert(guid_at(entry, 16), u64_at(entry, 8));
+                }
+                _ => {}
+            }
+        }
+    }
+    snapshots.retain_mut(|s| match block_lists.get(&s.id) {
+        Some(&offset) => {
+            s.block_list = offset;
+            true
+        }
+        None => false,
+    });
+    snapshots.sort_by_key(|s| s.created);
+    for (i, s) in snapshots.iter_mut().enumerate() {
+        s.index = i + 1;
+        s.name = format!("Shadow copy {}", s.index);
+    }
+    Ok(snapshots)
+}
+
+/// Where the previous content of a block is kept.
+#[derive(Debug, Clone, Copy)]
+enum Location {
+    /// Copy in the store, at this volume offset.
+    Store(u64),
+    /// Same content as this other block of the volume.
+    Forward(u64),
+}
+
+/// Copy-on-write records of one store.
+#[derive(Debug, Default)]
+struct Store {
+    blocks: HashMap<u64, Location>,
+    /// Partial copies: store offset and the bitmap of the 512-byte sectors they hold.
+    overlays: HashMap<u64, (u64, u32)>,
+}
+
+impl Store {
+    fn load<R: Read + Seek>(stream: &mut R, block_list: u64) -> Result<Self, Box<dyn Error>> {
+        let mut store = Store::default();
+        for block in read_block_chain(stream, block_list, 3)? {
+            for d in block[VSS_BLOCK_HEADER..].chunks_exact(DESCRIPTOR_SIZE) {
+                let (original, relative, data, flags) =
+                    (u64_at(d, 0), u64_at(d, 8), u64_at(d, 16), u32_at(d, 24));
+                if original == 0 && data == 0 && flags == 0 {
+                    break;
+                }
+                if flags & FLAG_NOT_USED != 0 {
+                    continue;
+                }
+                if flags & FLAG_OVERLAY != 0 {
+                    store.overlays.insert(original, (data, u32_at(d, 28)));
+                } else if flags & FLAG_FORWARDER != 0 {
+                    store.blocks.insert(original, Location::Forward(relative));
+                } else {
+                    store
+                        .blocks
+                        .entry(original)
+                        .or_insert(Location::Store(data));
+                }
+            }
+        }
+        Ok(store)
+    }
+}
+
+/// The volume as it was when a shadow copy was taken.
+pub struct ShadowCopyStream<R: Read + Seek> {
+    inner: R,
+    /// Stores from the selected one to the most recent.
+    stores: Vec<Store>,
+    size: u64,
+    pos: u64,
+    cached: Option<(u64, Vec<u8>)>,
+}
+
+impl<R: Read + Seek> ShadowCopyStream<R> {
+    /// Open the shadow copy of `stream` chosen by `selector`: its index in
+    /// `list_snapshots` (1 is the oldest) or its store identifier.
+    pub fn open(mut inner: R, selector: &str) -> Result<Self, Box<dyn Error>> {
+        let snapshots = list_snapshots(&mut inner)?;
+        if snapshots.is_empty() {
+            return Err("the volume has no shadow copies".into());
+        }
+        let selected = snapshots
+            .iter()
+            .position(|s| {
+                s.id.eq_ignore_ascii_case(selector.trim_matches(['{', '}']))
+                    || selector.parse::<usize>() == Ok(s.index)
+            })
+            .ok_or_else(|| {
+                format!(
+                    "no shadow copy '{}' (1 to {} or a store identifier)",
+                    selector,
+                    snapshots.len()
+                )
+            })?;
+        let mut stores = Vec::new();
+        for snapshot in &snapshots[selected..] {
+            stores.push(Store::load(&mut inner, snapshot.block_list)?);
+        }
+        Ok(Self {
+            inner,
+            stores,
+            size: snapshots[selected].volume_size,
+            pos: 0,
+            cached: None,
+        })
+    }
+
+    /// Content of the copy-on-write block at `offset` in the shadow copy.
+    fn read_block(&mut self, offset: u64) -> io::Result<Vec<u8>> {
+        let mut block = vec![0u8; VSS_BLOCK_SIZE as usize];
+        let mut source = offset;
+        let mut from_store = None;
+        for store in &self.stores {
+            match store.blocks.get(&source) {
+                Some(Location::Store(data)) => {
+                    from_store = Some(*data);
+                    break;
+                }
+                Some(Location::Forward(target)) => source = *target,
+                None => {}
+            }
+        }
+        read_at(&mut self.inner, from_store.unwrap_or(source), &mut block)?;
+        if let Some(&(data, bitmap)) = self.stores[0].overlays.get(&offset) {
+            for sector in 0..32 {
+                if bitmap & (1 << sector) != 0 {
+                    let at = sector * 512;
+                    read_at(&mut self.inner, data + at as u64, &mut block[at..at + 512])?;
+                }
+            }
+        }
+        Ok(block)
+    }
+}
+
+impl<R: Read + Seek> Read for ShadowCopyStream<R> {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        if self.pos >= self.size || buf.is_empty() {
+            return Ok(0);
+        }
+        let start = self.pos - self.pos % VSS_BLOCK_SIZE;
+        if self.cached.as_ref().map(|(at, _)| *at) != Some(start) {
+            self.cached = Some((start, self.read_block(start)?));
+        }
+        let block = &self.cached.as_ref().unwrap().1;
+        let within = (self.pos - start) as usize;
+        let n = buf
+            .len()
+            .min(block.len() - within)
+            .min((self.size - self.pos) as usize);
+        buf[..n].copy_from_slice(&block[within..within + n]);
+        self.pos += n as u64;
+        Ok(n)
+    }
+}
+
+impl<R: Read + Seek> Seek for ShadowCopyStream<R> {
+    fn seek(&mut self, pos: SeekFrom) -> io::Result<u64> {
+        let target = match pos {
+            SeekFrom::Start(p) => p as i128,
+            SeekFrom::End(d) => self.size as i128 + d as i128,
+            SeekFrom::Current(d) => self.pos as i128 + d as i128,
+        };
+        if target < 0 {
+            return Err(io::Error::new(
+                io::ErrorKind::InvalidInput,
+                "seek before the start of the shadow copy",
+            ));
+        }
+        self.pos = target as u64;
+        Ok(self.pos)
+    }
+}
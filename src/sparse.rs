@@ -0,0 +1,72 @@
+use crate::filesystem::ByteRange;
+use std::fs::File as StdFile;
+use std::io::{self, BufWriter, Seek, SeekFrom, Write};
+
+/// Bytes of a `size` byte content outside of its `holes`, i.e. backed by storage.
+pub fn allocated_size(size: u64, holes: &[ByteRange]) -> u64 {
+    let in_holes: u64 = holes
+        .iter()
+        .map(|&(offset, length)| offset.saturating_add(length).min(size) - offset.min(size))
+        .sum();
+    size.saturating_sub(in_holes)
+}
+
+/// Writes the content of a record into a file, seeking over its holes instead of
+/// writing their zeros, so that the copy is sparse as well where the host filesystem
+/// supports it. Bytes of a hole that are not zeros are written anyway.
+pub struct SparseWriter {
+    file: BufWriter<StdFile>,
+    /// Sorted by offset.
+    holes: Vec<ByteRange>,
+    next_hole: usize,
+    position: u64,
+}
+
+impl SparseWriter {
+    pub fn new(file: StdFile, mut holes: Vec<ByteRange>) -> Self {
+        holes.sort_unstable();
+        Self {
+            file: BufWriter::new(file),
+            holes,
+            next_hole: 0,
+            position: 0,
+        }
+    }
+
+    /// Flush the content and extend the file over a trailing hole.
+    pub fn finish(self) -> io::Result<StdFile> {
+        let file = self.file.into_inner().map_err(|e| e.into_error())?;
+        file.set_len(self.position)?;
+        Ok(file)
+    }
+}
+
+impl Write for SparseWriter {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        while let Some(&(offset, length)) = self.holes.get(self.next_hole)
+            && offset + length <= self.position
+        {
+            self.next_hole += 1;
+        }
+        let (count, in_hole) = match self.holes.get(self.next_hole) {
+            Some(&(offset, length)) if offset <= self.position => {
+                (offset + length - self.position, true)
+            }
+            Some(&(offset, _)) => (offset - self.position, false),
+            None => (buf.len() as u64, false),
+        };
+        let count = count.min(buf.len() as u64) as usize;
+        if in_hole && buf[..count].iter().all(|&b| b == 0) {
+            self.file
+                .seek(SeekFrom::Start(self.position + count as u64))?;
+        } else {
+            self.file.write_all(&buf[..count])?;
+        }
+        self.position += count as u64;
+        Ok(count)
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        self.file.flush()
+    }
+}
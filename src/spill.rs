@@ -0,0 +1,546 @@
+//! Disk-backed walk state. The visited set and the queue of a breadth-first walk grow with
+//! the volume; under a memory budget (see `budget`) they move to temporary files beyond
+//! their share, so walks of tens of millions of records fit on small machines. Both
+//! serialize like the `HashSet` and `VecDeque` they replace, keeping checkpoints readable.
+//!
+//! Spill files go to the system temporary directory (`TMPDIR`) and are removed when the
+//! structure holding them is dropped.
+use crate::budget;
+use log::{error, warn};
+use serde::de::{SeqAccess, Visitor};
+use serde::ser::{Error as _, SerializeSeq};
+use serde::{Deserialize, Deserializer, Serialize, Serializer};
+use std::collections::{HashSet, VecDeque};
+use std::fmt;
+use std::fs::{self, File as StdFile, OpenOptions};
+use std::io::{self, BufWriter, Read, Seek, SeekFrom, Write};
+use std::path::PathBuf;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::{Arc, Mutex};
+
+/// Memory accounted per visited record kept in memory (hash set slot and overhead).
+const SEEN_ENTRY_BYTES: u64 = 32;
+/// Memory accounted per queued record on top of its path.
+const QUEUE_ENTRY_BYTES: u64 = 48;
+/// Lower bounds of what is spilled at once, so a tiny budget does not turn every
+/// insertion into a disk write.
+const MIN_SPILLED_VALUES: u64 = 65_536;
+const MIN_SEGMENT_BYTES: u64 = 1024 * 1024;
+/// Values per page of a sorted run; one page is read per lookup.
+const RUN_PAGE: usize = 512;
+/// Sorted runs are merged into one beyond this count, bounding the pages read per lookup.
+const MAX_RUNS: usize = 8;
+/// Bloom filter bits per spilled value, so most lookups of new records read no page.
+const BLOOM_BITS: usize = 10;
+const BLOOM_HASHES: u64 = 4;
+
+static NEXT_SPILL: AtomicU64 = AtomicU64::new(0);
+
+/// A walk queue entry: record identifier and absolute path.
+pub type QueuedRecord = (u64, String);
+
+/// Temporary file, removed when dropped.
+#[derive(Debug)]
+struct SpillFile {
+    path: PathBuf,
+    file: Mutex<StdFile>,
+}
+
+impl SpillFile {
+    fn create() -> io::Result<Self> {
+        let path = std::env::temp_dir().join(format!(
+            "exhume_spill_{}_{}",
+            std::process::id(),
+            NEXT_SPILL.fetch_add(1, Ordering::Relaxed)
+        ));
+        let file = OpenOptions::new()
+            .read(true)
+            .write(true)
+            .create_new(true)
+            .open(&path)?;
+        Ok(Self {
+            path,
+            file: Mutex::new(file),
+        })
+    }
+
+    fn read_at(&self, offset: u64, buf: &mut [u8]) -> io::Result<()> {
+        let mut file = self.file.lock().unwrap_or_else(|e| e.into_inner());
+        file.seek(SeekFrom::Start(offset))?;
+        file.read_exact(buf)
+    }
+
+    fn write_at(&self, offset: u64, data: &[u8]) -> io::Result<()> {
+        let mut file = self.file.lock().unwrap_or_else(|e| e.into_inner());
+        file.seek(SeekFrom::Start(offset))?;
+        file.write_all(data)
+    }
+
+    fn truncate(&self) -> io::Result<()> {
+        let file = self.file.lock().unwrap_or_else(|e| e.into_inner());
+        file.set_len(0)
+    }
+}
+
+impl Drop for SpillFile {
+    fn drop(&mut self) {
+        let _ = fs::remove_file(&self.path);
+    }
+}
+
+fn bloom_positions(value: u64, bits: usize) -> impl Iterator<Item = usize> {
+    // splitmix64 finalizer, split into the two halves of a double hash.
+    let mut h = value.wrapping_add(0x9e37_79b9_7f4a_7c15);
+    h = (h ^ (h >> 30)).wrapping_mul(0xbf58_476d_1ce4_e5b9);
+    h = (h ^ (h >> 27)).wrapping_mul(0x94d0_49bb_1331_11eb);
+    h ^= h >> 31;
+    let (h1, h2) = (h & 0xffff_ffff, h >> 32);
+    (0..BLOOM_HASHES).map(move |i| (h1.wrapping_add(i.wrapping_mul(h2)) % bits as u64) as usize)
+}
+
+/// Sorted, duplicate-free values on disk, with the first value of every page and a
+/// Bloom filter kept in memory.
+#[derive(Debug)]
+struct SortedRun {
+    file: SpillFile,
+    len: usize,
+    pages: Vec<u64>,
+    bloom: Vec<u64>,
+}
+
+impl SortedRun {
+    /// Write the values returned by `next`, in increasing order, to a new run.
+    fn create(
+        expected: usize,
+        next: &mut dyn FnMut() -> io::Result<Option<u64>>,
+    ) -> io::Result<Self> {
+        let file = SpillFile::create()?;
+        let mut bloom = vec![0u64; (expected * BLOOM_BITS).div_ceil(64).max(1)];
+        let bits = bloom.len() * 64;
+        let mut pages = Vec::new();
+        let mut len = 0;
+        {
+            let mut handle = file.file.lock().unwrap_or_else(|e| e.into_inner());
+            let mut out = BufWriter::new(&mut *handle);
+            while let Some(value) = next()? {
+                if len % RUN_PAGE == 0 {
+                    pages.push(value);
+                }
+                for bit in bloom_positions(value, bits) {
+                    bloom[bit / 64] |= 1 << (bit % 64);
+                }
+                out.write_all(&value.to_le_bytes())?;
+                len += 1;
+            }
+            out.flush()?;
+        }
+        Ok(Self {
+            file,
+            len,
+            pages,
+            bloom,
+        })
+    }
+
+    fn page(&self, index: usize) -> io::Result<Vec<u64>> {
+        let count = RUN_PAGE.min(self.len - index * RUN_PAGE);
+        let mut raw = vec![0u8; count * 8];
+        self.file.read_at((index * RUN_PAGE * 8) as u64, &mut raw)?;
+        Ok(raw
+            .chunks_exact(8)
+            .map(|b| u64::from_le_bytes(b.try_into().unwrap()))
+            .collect())
+    }
+
+    fn contains(&self, value: u64) -> io::Result<bool> {
+        let bits = self.bloom.len() * 64;
+        if bloom_positions(value, bits).any(|bit| self.bloom[bit / 64] & (1 << (bit % 64)) == 0) {
+            return Ok(false);
+        }
+        let page = self.pages.partition_point(|first| *first <= value);
+        if page == 0 {
+            return Ok(false);
+        }
+        Ok(self.page(page - 1)?.binary_search(&value).is_ok())
+    }
+}
+
+/// Sequential reader of a sorted run.
+struct RunCursor<'a> {
+    run: &'a SortedRun,
+    page: usize,
+    values: VecDeque<u64>,
+}
+
+impl<'a> RunCursor<'a> {
+    fn new(run: &'a SortedRun) -> Self {
+        Self {
+            run,
+            page: 0,
+            values: VecDeque::new(),
+        }
+    }
+
+    fn peek(&mut self) -> io::Result<Option<u64>> {
+        if self.values.is_empty() && self.page < self.run.pages.len() {
+            self.values = self.run.page(self.page)?.into();
+            self.page += 1;
+        }
+        Ok(self.values.front().copied())
+    }
+}
+
+/// Set of visited record identifiers, spilled to sorted runs on disk beyond its share of
+/// the memory budget.
+#[derive(Debug, Clone, Default)]
+pub struct SeenSet {
+    memory: HashSet<u64>,
+    runs: Vec<Arc<SortedRun>>,
+    spilled: usize,
+    spill_failed: bool,
+}
+
+impl SeenSet {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn len(&self) -> usize {
+        self.memory.len() + self.spilled
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+
+    /// Whether `value` was inserted. A spilled run that cannot be read counts as not
+    /// holding it, so a failing disk yields duplicates rather than missing records.
+    pub fn contains(&self, value: u64) -> bool {
+        self.memory.contains(&value)
+            || self.runs.iter().any(|run| {
+                run.contains(value).unwrap_or_else(|e| {
+                    error!("Could not read the spilled visited records: {}", e);
+                    false
+                })
+            })
+    }
+
+    /// Add `value`, returning whether it was not present yet.
+    pub fn insert(&mut self, value: u64) -> bool {
+        if self.contains(value) {
+            return false;
+        }
+        self.memory.insert(value);
+        let limit = budget::share(budget::SEEN_SHARE)
+            .map(|bytes| (bytes / SEEN_ENTRY_BYTES).max(MIN_SPILLED_VALUES));
+        if !self.spill_failed
+            && limit.is_some_and(|limit| self.memory.len() as u64 >= limit)
+            && let Err(e) = self.spill()
+        {
+            warn!(
+                "Could not spill the visited records to disk, keeping them in memory: {}",
+                e
+            );
+            self.spill_failed = true;
+        }
+        true
+    }
+
+    fn spill(&mut self) -> io::Result<()> {
+        let mut values: Vec<u64> = self.memory.iter().copied().collect();
+        values.sort_unstable();
+        let mut sorted = values.iter().copied();
+        let run = SortedRun::create(values.len(), &mut || Ok(sorted.next()))?;
+        self.spilled += values.len();
+        self.memory.clear();
+        self.memory.shrink_to_fit();
+        self.runs.push(Arc::new(run));
+        if self.runs.len() > MAX_RUNS {
+            let mut cursors: Vec<RunCursor> = self.runs.iter().map(|r| RunCursor::new(r)).collect();
+            let merged = SortedRun::create(self.spilled, &mut || {
+                let mut lowest: Option<(usize, u64)> = None;
+                for (index, cursor) in cursors.iter_mut().enumerate() {
+                    if let Some(value) = cursor.peek()?
+                        && lowest.is_none_or(|(_, low)| value < low)
+                    {
+                        lowest = Some((index, value));
+                    }
+                }
+                Ok(lowest.map(|(index, value)| {
+                    cursors[index].values.pop_front();
+                    value
+                }))
+            })?;
+            self.runs = vec![Arc::new(merged)];
+        }
+        Ok(())
+    }
+
+    /// Call `f` with every value, in no particular order.
+    pub fn for_each(&self, f: &mut dyn FnMut(u64)) -> io::Result<()> {
+        self.memory.iter().for_each(|v| f(*v));
+        for run in &self.runs {
+            for page in 0..run.pages.len() {
+                run.page(page)?.into_iter().for_each(&mut *f);
+            }
+        }
+        Ok(())
+    }
+}
+
+impl FromIterator<u64> for SeenSet {
+    fn from_iter<I: IntoIterator<Item = u64>>(iter: I) -> Self {
+        let mut set = SeenSet::new();
+        for value in iter {
+            set.insert(value);
+        }
+        set
+    }
+}
+
+impl Serialize for SeenSet {
+    fn serialize<S: Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        let mut seq = serializer.serialize_seq(Some(self.len()))?;
+        let mut failed = None;
+        self.for_each(&mut |value| {
+            if failed.is_none() {
+                failed = seq.serialize_element(&value).err();
+            }
+        })
+        .map_err(S::Error::custom)?;
+        match failed {
+            Some(e) => Err(e),
+            None => seq.end(),
+        }
+    }
+}
+
+impl<'de> Deserialize<'de> for SeenSet {
+    fn deserialize<D: Deserializer<'de>>(deserializer: D) -> Result<Self, D::Error> {
+        struct SeenVisitor;
+        impl<'de> Visitor<'de> for SeenVisitor {
+            type Value = SeenSet;
+            fn expecting(&self, f: &mut fmt::Formatter) -> fmt::Result {
+                f.write_str("a sequence of record identifiers")
+            }
+            fn visit_seq<A: SeqAccess<'de>>(self, mut seq: A) -> Result<SeenSet, A::Error> {
+                let mut set = SeenSet::new();
+                while let Some(value) = seq.next_element()? {
+                    set.insert(value);
+                }
+                Ok(set)
+            }
+        }
+        deserializer.deserialize_seq(SeenVisitor)
+    }
+}
+
+/// First-in first-out queue of records to visit. Beyond its share of the memory budget,
+/// the most recently queued records move to a spill file in segments, read back in order.
+#[derive(Debug, Default)]
+pub struct WalkQueue {
+    /// Records popped next.
+    front: VecDeque<QueuedRecord>,
+    /// Spilled segments, oldest first: offset in the spill file and record count.
+    segments: VecDeque<(u64, usize)>,
+    /// Records queued after the spilled ones.
+    back: VecDeque<QueuedRecord>,
+    back_bytes: u64,
+    file: Option<SpillFile>,
+    file_len: u64,
+    spilled: usize,
+    spill_failed: bool,
+}
+
+impl WalkQueue {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn len(&self) -> usize {
+        self.front.len() + self.spilled + self.back.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+
+    pub fn push_back(&mut self, record: QueuedRecord) {
+        self.back_bytes += QUEUE_ENTRY_BYTES + record.1.len() as u64;
+        self.back.push_back(record);
+        // Half of the share for the queued records, half for the segment being consumed.
+        let limit =
+            budget::share(budget::QUEUE_SHARE).map(|bytes| (bytes / 2).max(MIN_SEGMENT_BYTES));
+        if !self.spill_failed
+            && limit.is_some_and(|limit| self.back_bytes > limit)
+            && let Err(e) = self.spill()
+        {
+            warn!(
+                "Could not spill the walk queue to disk, keeping it in memory: {}",
+                e
+            );
+            self.spill_failed = true;
+        }
+    }
+
+    pub fn pop_front(&mut self) -> Option<QueuedRecord> {
+        if self.front.is_empty() {
+            if let Some((offset, count)) = self.segments.pop_front() {
+                self.spilled -= count;
+                let end = self
+                    .segments
+                    .front()
+                    .map_or(self.file_len, |(next, _)| *next);
+                match self.read_segment(offset, end, count) {
+                    Ok(records) => self.front = records,
+                    Err(e) => error!("Could not read {} spilled queued records: {}", count, e),
+                }
+                if self.segments.is_empty() {
+                    self.reset_file();
+                }
+            } else {
+                std::mem::swap(&mut self.front, &mut self.back);
+                self.back_bytes = 0;
+            }
+        }
+        self.front.pop_front()
+    }
+
+    /// Remove and return every queued record, in order.
+    pub fn drain_all(&mut self) -> Vec<QueuedRecord> {
+        let mut records = Vec::with_capacity(self.len());
+        while let Some(record) = self.pop_front() {
+            records.push(record);
+        }
+        records
+    }
+
+    fn spill(&mut self) -> io::Result<()> {
+        if self.file.is_none() {
+            self.file = Some(SpillFile::create()?);
+        }
+        let mut data = Vec::with_capacity(self.back_bytes as usize);
+        for (id, path) in &self.back {
+            data.extend_from_slice(&id.to_le_bytes());
+            data.extend_from_slice(&(path.len() as u32).to_le_bytes());
+            data.extend_from_slice(path.as_bytes());
+        }
+        if let Some(file) = &self.file {
+            file.write_at(self.file_len, &data)?;
+        }
+        self.segments.push_back((self.file_len, self.back.len()));
+        self.file_len += data.len() as u64;
+        self.spilled += self.back.len();
+        self.back = VecDeque::new();
+        self.back_bytes = 0;
+        Ok(())
+    }
+
+    /// Records of the segment stored between `offset` and `end` in the spill file.
+    fn read_segment(
+        &self,
+        offset: u64,
+        end: u64,
+        count: usize,
+    ) -> io::Result<VecDeque<QueuedRecord>> {
+        let file = self
+            .file
+            .as_ref()
+            .ok_or_else(|| io::Error::other("no spill file"))?;
+        let mut data = vec![0u8; (end - offset) as usize];
+        file.read_at(offset, &mut data)?;
+        let truncated = || io::Error::other("truncated queue segment");
+        let mut records = VecDeque::with_capacity(count);
+        let mut at = 0;
+        while records.len() < count {
+            let header = data.get(at..at + 12).ok_or_else(truncated)?;
+            let id = u64::from_le_bytes(header[..8].try_into().unwrap());
+            let len = u32::from_le_bytes(header[8..].try_into().unwrap()) as usize;
+            let path = data.get(at + 12..at + 12 + len).ok_or_else(truncated)?;
+            records.push_back((id, String::from_utf8_lossy(path).into_owned()));
+            at += 12 + len;
+        }
+        Ok(records)
+    }
+
+    /// Reclaim the spill file once every segment has been read back.
+    fn reset_file(&mut self) {
+        if let Some(file) = &self.file
+            && let Err(e) = file.truncate()
+        {
+            warn!("Could not truncate the walk queue spill file: {}", e);
+        }
+        self.file_len = 0;
+    }
+
+    /// Call `f` with every queued record, in order.
+    pub fn for_each(&self, f: &mut dyn FnMut(&QueuedRecord)) -> io::Result<()> {
+        self.front.iter().for_each(&mut *f);
+        let mut segments = self.segments.iter().peekable();
+        while let Some((offset, count)) = segments.next() {
+            let end = segments.peek().map_or(self.file_len, |(next, _)| *next);
+            self.read_segment(*offset, end, *count)?
+                .iter()
+                .for_each(&mut *f);
+        }
+        self.back.iter().for_each(f);
+        Ok(())
+    }
+}
+
+impl Clone for WalkQueue {
+    fn clone(&self) -> Self {
+        let mut queue = WalkQueue::new();
+        if let Err(e) = self.for_each(&mut |record| queue.push_back(record.clone())) {
+            error!("Could not read the spilled walk queue: {}", e);
+        }
+        queue
+    }
+}
+
+impl FromIterator<QueuedRecord> for WalkQueue {
+    fn from_iter<I: IntoIterator<Item = QueuedRecord>>(iter: I) -> Self {
+        let mut queue = WalkQueue::new();
+        for record in iter {
+            queue.push_back(record);
+        }
+        queue
+    }
+}
+
+impl Serialize for WalkQueue {
+    fn serialize<S: Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        let mut seq = serializer.serialize_seq(Some(self.len()))?;
+        let mut failed = None;
+        self.for_each(&mut |record| {
+            if failed.is_none() {
+                failed = seq.serialize_element(record).err();
+            }
+        })
+        .map_err(S::Error::custom)?;
+        match failed {
+            Some(e) => Err(e),
+            None => seq.end(),
+        }
+    }
+}
+
+impl<'de> Deserialize<'de> for WalkQueue {
+    fn deserialize<D: Deserializer<'de>>(deserializer: D) -> Result<Self, D::Error> {
+        struct QueueVisitor;
+        impl<'de> Visitor<'de> for QueueVisitor {
+            type Value = WalkQueue;
+            fn expecting(&self, f: &mut fmt::Formatter) -> fmt::Result {
+                f.write_str("a sequence of queued records")
+            }
+            fn visit_seq<A: SeqAccess<'de>>(self, mut seq: A) -> Result<WalkQueue, A::Error> {
+                let mut queue = WalkQueue::new();
+                while let Some(record) = seq.next_element()? {
+                    queue.push_back(record);
+                }
+                Ok(queue)
+            }
+        }
+        deserializer.deserialize_seq(QueueVisitor)
+    }
+}
@@ -0,0 +1,822 @@
+//! SquashFS 4.0 images (`hsqs`), as found in firmware and on live systems. Inodes,
+//! directories and the lookup tables (ids, fragments, extended attributes) live in
+//! metadata blocks of up to 8 KiB; file data in blocks of the superblock size, the tail
+//! of a file often packed into a shared fragment block.
+//!
+//! Blocks may be compressed with gzip, LZMA, xz, LZ4 or zstd (LZO is not supported).
+//! Records are identified by their inode reference: the offset of the metadata block
+//! holding the inode from the start of the inode table, shifted left 16 bits, plus the
+//! offset of the inode within that block.
+use crate::compression::{self, Codec};
+use crate::filesystem::{
+    ByteRange, DEVICE_KEY, DirectoryCommon, ExtendedAttribute, FLAGS_KEY, File, FileCommon,
+    Filesystem, SYMLINK_TARGET_KEY, namespaced_metadata, unix_ftype, unix_mode_string,
+};
+use crate::names::{escape_name, name_bytes, render_name};
+use crate::timefmt::format_timestamp;
+use serde::Serialize;
+use serde_json::{Value, json};
+use std::collections::HashMap;
+use std::error::Error;
+use std::io::{Read, Seek, SeekFrom};
+
+/// Namespace of the inode fields in `File.metadata`.
+pub const METADATA_NAMESPACE: &str = "squashfs";
+
+const MAGIC: &[u8; 4] = b"hsqs";
+const SUPERBLOCK_SIZE: usize = 96;
+const METADATA_SIZE: usize = 8192;
+const METADATA_UNCOMPRESSED: u16 = 0x8000;
+const DATA_UNCOMPRESSED: u32 = 1 << 24;
+const NO_FRAGMENT: u32 = u32::MAX;
+const NO_XATTR: u32 = u32::MAX;
+const NO_TABLE: u64 = u64::MAX;
+/// Decompressed metadata and data blocks kept for reuse.
+const CACHE_BLOCKS: usize = 64;
+
+/// Superblock flags, named after their meaning.
+const SUPERBLOCK_FLAGS: [(u16, &str); 12] = [
+    (0x0001, "uncompressed_inodes"),
+    (0x0002, "uncompressed_data"),
+    (0x0004, "check"),
+    (0x0008, "uncompressed_fragments"),
+    (0x0010, "no_fragments"),
+    (0x0020, "always_fragments"),
+    (0x0040, "duplicates"),
+    (0x0080, "exportable"),
+    (0x0100, "uncompressed_xattrs"),
+    (0x0200, "no_xattrs"),
+    (0x0400, "compressor_options"),
+    (0x0800, "uncompressed_ids"),
+];
+
+fn le_u16(bytes: &[u8], at: usize) -> u16 {
+    u16::from_le_bytes(bytes[at..at + 2].try_into().unwrap())
+}
+
+fn le_u32(bytes: &[u8], at: usize) -> u32 {
+    u32::from_le_bytes(bytes[at..at + 4].try_into().unwrap())
+}
+
+fn le_u64(bytes: &[u8], at: usize) -> u64 {
+    u64::from_le_bytes(bytes[at..at + 8].try_into().unwrap())
+}
+
+#[derive(Debug, Clone, Serialize)]
+pub struct Superblock {
+    pub inode_count: u32,
+    pub modification_time: u32,
+    pub block_size: u32,
+    pub fragment_count: u32,
+    pub compressor: u16,
+    pub flags: u16,
+    pub id_count: u16,
+    pub version: (u16, u16),
+    pub root_inode: u64,
+    pub bytes_used: u64,
+    pub id_table: u64,
+    pub xattr_id_table: u64,
+    pub inode_table: u64,
+    pub directory_table: u64,
+    pub fragment_table: u64,
+    pub export_table: u64,
+}
+
+impl Superblock {
+    fn parse(bytes: &[u8]) -> Result<Self, Box<dyn Error>> {
+        if bytes.len() < SUPERBLOCK_SIZE || &bytes[..4] != MAGIC {
+            return Err("not a SquashFS superblock".into());
+        }
+        let superblock = Self {
+            inode_count: le_u32(bytes, 4),
+            modification_time: le_u32(bytes, 8),
+            block_size: le_u32(bytes, 12),
+            fragment_count: le_u32(bytes, 16),
+            compressor: le_u16(bytes, 20),
+            flags: le_u16(bytes, 24),
+            id_count: le_u16(bytes, 26),
+            version: (le_u16(bytes, 28), le_u16(bytes, 30)),
+            root_inode: le_u64(bytes, 32),
+            bytes_used: le_u64(bytes, 40),
+            id_table: le_u64(bytes, 48),
+            xattr_id_table: le_u64(bytes, 56),
+            inode_table: le_u64(bytes, 64),
+            directory_table: le_u64(bytes, 72),
+            fragment_table: le_u64(bytes, 80),
+            export_table: le_u64(bytes, 88),
+        };
+        if superblock.version.0 != 4 {
+            return Err(format!(
+                "SquashFS {}.{} is not supported, only 4.0",
+                superblock.version.0, superblock.version.1
+            )
+            .into());
+        }
+        let block_log = le_u16(bytes, 22) as u32;
+        if !(12..=20).contains(&block_log) || superblock.block_size != 1 << block_log {
+            return Err(format!("bad SquashFS block size {}", superblock.block_size).into());
+        }
+        Ok(superblock)
+    }
+
+    fn codec(&self) -> Result<Codec, Box<dyn Error>> {
+        match self.compressor {
+            1 => Ok(Codec::Zlib),
+            2 => Ok(Codec::Lzma),
+            4 => Ok(Codec::Xz),
+            5 => Ok(Codec::Lz4),
+            6 => Ok(Codec::Zstd),
+            3 => Err("LZO-compressed SquashFS images are not supported".into()),
+            other => Err(format!("unknown SquashFS compressor {}", other).into()),
+        }
+    }
+
+    fn compressor_name(&self) -> &'static str {
+        match self.compressor {
+            1 => "gzip",
+            2 => "lzma",
+            3 => "lzo",
+            4 => "xz",
+            5 => "lz4",
+            6 => "zstd",
+            _ => "unknown",
+        }
+    }
+}
+
+/// Names of the flags set in the superblock.
+pub fn superblock_flag_names(flags: u16) -> Vec<&'static str> {
+    SUPERBLOCK_FLAGS
+        .iter()
+        .filter(|(bit, _)| flags & bit != 0)
+        .map(|(_, name)| *name)
+        .collect()
+}
+
+/// Where the entries of a directory are in the directory table.
+#[derive(Debug, Clone, Copy, Serialize)]
+pub struct Listing {
+    /// Offset of the first metadata block from the start of the directory table.
+    pub block: u32,
+    pub offset: u16,
+    /// Bytes of entries and their headers.
+    pub size: u32,
+}
+
+/// An inode, basic or extended.
+#[derive(Debug, Clone, Serialize)]
+pub struct SquashInode {
+    pub reference: u64,
+    #[serde(rename = "type")]
+    pub inode_type: u16,
+    /// File type bits and permissions, as `st_mode`.
+    pub mode: u32,
+    pub uid: u32,
+    pub gid: u32,
+    pub mtime: u32,
+    pub inode_number: u32,
+    pub links: u32,
+    pub size: u64,
+    /// Start of the data blocks of a file.
+    pub blocks_start: u64,
+    /// Compressed size of each full data block, with the uncompressed bit; 0 for a hole.
+    #[serde(skip)]
+    pub block_sizes: Vec<u32>,
+    /// Fragment holding the tail of a file, and the offset of the tail in it.
+    pub fragment: Option<(u32, u32)>,
+    /// Bytes of holes, as recorded by extended file inodes.
+    pub sparse: u64,
+    pub listing: Option<Listing>,
+    pub parent_inode: Option<u32>,
+    pub rdev: Option<u32>,
+    pub xattr_index: Option<u32>,
+    #[serde(skip)]
+    pub symlink: Option<Vec<u8>>,
+}
+
+impl FileCommon for SquashInode {
+    fn id(&self) -> u64 {
+        self.reference
+    }
+    fn size(&self) -> u64 {
+        self.size
+    }
+    fn is_dir(&self) -> bool {
+        self.listing.is_some()
+    }
+    fn to_string(&self) -> String {
+        format!(
+            "SquashInode {{ reference: {:#x}, inode: {}, mode: {:o}, size: {} }}",
+            self.reference, self.inode_number, self.mode, self.size
+        )
+    }
+    fn to_json(&self) -> Value {
+        serde_json::to_value(self).unwrap_or(Value::Null)
+    }
+}
+
+/// An entry of a directory listing.
+#[derive(Debug, Clone)]
+pub struct SquashDirEntry {
+    pub reference: u64,
+    pub inode_number: u32,
+    pub name: String,
+    /// Basic inode type of the entry.
+    pub kind: u16,
+}
+
+impl DirectoryCommon for SquashDirEntry {
+    fn file_id(&self) -> u64 {
+        self.reference
+    }
+    fn name(&self) -> &str {
+        &self.name
+    }
+    fn to_string(&self) -> String {
+        format!(
+            "SquashDirEntry {{ reference: {:#x}, inode: {}, name: {} }}",
+            self.reference, self.inode_number, self.name
+        )
+    }
+    fn to_json(&self) -> Value {
+        json!({
+            "reference": self.reference,
+            "inode_number": self.inode_number,
+            "name": self.name,
+            "kind": self.kind,
+        })
+    }
+}
+
+pub struct SquashFS<T: Read + Seek> {
+    body: T,
+    superblock: Superblock,
+    codec: Codec,
+    ids: Vec<u32>,
+    /// Decompressed metadata blocks by position, with the position of the next one.
+    metadata: HashMap<u64, (Vec<u8>, u64)>,
+    /// Decompressed data and fragment blocks by position.
+    blocks: HashMap<u64, Vec<u8>>,
+}
+
+impl<T: Read + Seek> SquashFS<T> {
+    pub fn new(mut body: T) -> Result<Self, Box<dyn Error>> {
+        let mut raw = [0u8; SUPERBLOCK_SIZE];
+        body.seek(SeekFrom::Start(0))?;
+        body.read_exact(&mut raw)?;
+        let superblock = Superblock::parse(&raw)?;
+        let codec = superblock.codec()?;
+        let mut fs = Self {
+            body,
+            superblock,
+            codec,
+            ids: Vec::new(),
+            metadata: HashMap::new(),
+            blocks: HashMap::new(),
+        };
+        for index in 0..fs.superblock.id_count as u64 {
+            let id = fs.table_entry(fs.superblock.id_table, index, 4)?;
+            fs.ids.push(le_u32(&id, 0));
+        }
+        Ok(fs)
+    }
+
+    pub fn superblock(&self) -> &Superblock {
+        &self.superblock
+    }
+
+    fn read_at(&mut self, position: u64, length: usize) -> Result<Vec<u8>, Box<dyn Error>> {
+        let mut data = vec![0u8; length];
+        self.body.seek(SeekFrom::Start(position))?;
+        self.body.read_exact(&mut data)?;
+        Ok(data)
+    }
+
+    /// The metadata block at `position`, and the position of the next one.
+    fn metadata_block(&mut self, position: u64) -> Result<(&[u8], u64), Box<dyn Error>> {
+        if !self.metadata.contains_key(&position) {
+            let header = le_u16(&self.read_at(position, 2)?, 0);
+            let length = (header & !METADATA_UNCOMPRESSED) as usize;
+            if length == 0 || length > METADATA_SIZE {
+                return Err(format!("metadata block of {} bytes", length).into());
+            }
+            let raw = self.read_at(position + 2, length)?;
+            let block = match header & METADATA_UNCOMPRESSED {
+                0 => compression::bounded(self.codec, &raw, METADATA_SIZE)?,
+                _ => raw,
+            };
+            if self.metadata.len() >= CACHE_BLOCKS {
+                self.metadata.clear();
+            }
+            self.metadata
+                .insert(position, (block, position + 2 + length as u64));
+        }
+        let (block, next) = &self.metadata[&position];
+        Ok((block, *next))
+    }
+
+    /// `length` bytes of metadata from `offset` in the block at `position`, following
+    /// the next blocks as needed. The cursor is left after them.
+    fn read_metadata(
+        &mut self,
+        cursor: &mut (u64, usize),
+        length: usize,
+    ) -> Result<Vec<u8>, Box<dyn Error>> {
+        let mut out = Vec::with_capacity(length);
+        while out.len() < length {
+            let (block, next) = self.metadata_block(cursor.0)?;
+            if cursor.1 >= block.len() {
+                if block.is_empty() || cursor.1 > block.len() {
+                    return Err("metadata offset past the end of its block".into());
+                }
+                *cursor = (next, cursor.1 - block.len());
+                continue;
+            }
+            let take = (block.len() - cursor.1).min(length - out.len());
+            out.extend_from_slice(&block[cursor.1..cursor.1 + take]);
+            cursor.1 += take;
+            if cursor.1 == block.len() {
+                *cursor = (next, 0);
+            }
+        }
+        Ok(out)
+    }
+
+    /// Entry `index` of `size` bytes of a lookup table (ids, fragments, xattr ids): an
+    /// array of pointers to the metadata blocks holding the entries.
+    fn table_entry(
+        &mut self,
+        table: u64,
+        index: u64,
+        size: usize,
+    ) -> Result<Vec<u8>, Box<dyn Error>> {
+        let per_block = (METADATA_SIZE / size) as u64;
+        let pointer = self.read_at(table + 8 * (index / per_block), 8)?;
+        let mut cursor = (le_u64(&pointer, 0), (index % per_block) as usize * size);
+        self.read_metadata(&mut cursor, size)
+    }
+
+    fn id(&self, index: u16) -> Result<u32, Box<dyn Error>> {
+        self.ids
+            .get(index as usize)
+            .copied()
+            .ok_or_else(|| format!("id index {} out of range", index).into())
+    }
+
+    /// Parse the inode at `reference`.
+    fn inode(&mut self, reference: u64) -> Result<SquashInode, Box<dyn Error>> {
+        let mut cursor = (
+            self.superblock.inode_table + (reference >> 16),
+            (reference & 0xffff) as usize,
+        );
+        let header = self.read_metadata(&mut cursor, 16)?;
+        let inode_type = le_u16(&header, 0);
+        let file_type = match inode_type {
+            1 | 8 => 0o040000,
+            2 | 9 => 0o100000,
+            3 | 10 => 0o120000,
+            4 | 11 => 0o060000,
+            5 | 12 => 0o020000,
+            6 | 13 => 0o010000,
+            7 | 14 => 0o140000,
+            other => return Err(format!("unknown SquashFS inode type {}", other).into()),
+        };
+        let mut inode = SquashInode {
+            reference,
+            inode_type,
+            mode: file_type | (le_u16(&header, 2) & 0o7777) as u32,
+            uid: self.id(le_u16(&header, 4))?,
+            gid: self.id(le_u16(&header, 6))?,
+            mtime: le_u32(&header, 8),
+            inode_number: le_u32(&header, 12),
+            links: 1,
+            size: 0,
+            blocks_start: 0,
+            block_sizes: Vec::new(),
+            fragment: None,
+            sparse: 0,
+            listing: None,
+            parent_inode: None,
+            rdev: None,
+            xattr_index: None,
+            symlink: None,
+        };
+        let xattr = |index: u32| (index != NO_XATTR).then_some(index);
+        match inode_type {
+            1 => {
+                let b = self.read_metadata(&mut cursor, 16)?;
+                inode.links = le_u32(&b, 4);
+                inode.listing = Some(Listing {
+                    block: le_u32(&b, 0),
+                    offset: le_u16(&b, 10),
+                    size: (le_u16(&b, 8) as u32).saturating_sub(3),
+                });
+                inode.parent_inode = Some(le_u32(&b, 12));
+            }
+            8 => {
+                let b = self.read_metadata(&mut cursor, 24)?;
+                inode.links = le_u32(&b, 0);
+                inode.listing = Some(Listing {
+                    block: le_u32(&b, 8),
+                    offset: le_u16(&b, 18),
+                    size: le_u32(&b, 4).saturating_sub(3),
+                });
+                inode.parent_inode = Some(le_u32(&b, 12));
+                inode.xattr_index = xattr(le_u32(&b, 20));
+            }
+            2 | 9 => {
+                let (fragment, offset);
+                if inode_type == 2 {
+                    let b = self.read_metadata(&mut cursor, 16)?;
+                    inode.blocks_start = le_u32(&b, 0) as u64;
+                    (fragment, offset) = (le_u32(&b, 4), le_u32(&b, 8));
+                    inode.size = le_u32(&b, 12) as u64;
+                } else {
+                    let b = self.read_metadata(&mut cursor, 40)?;
+                    inode.blocks_start = le_u64(&b, 0);
+                    inode.size = le_u64(&b, 8);
+                    inode.sparse = le_u64(&b, 16);
+                    inode.links = le_u32(&b, 24);
+                    (fragment, offset) = (le_u32(&b, 28), le_u32(&b, 32));
+                    inode.xattr_index = xattr(le_u32(&b, 36));
+                }
+                let block_size = self.superblock.block_size as u64;
+                let count = match fragment {
+                    NO_FRAGMENT => inode.size.div_ceil(block_size),
+                    _ => inode.size / block_size,
+                };
+                if count > (1 << 32) / 4 {
+                    return Err(format!("file of {} blocks", count).into());
+                }
+                let sizes = self.read_metadata(&mut cursor, count as usize * 4)?;
+                inode.block_sizes = sizes.chunks_exact(4).map(|s| le_u32(s, 0)).collect();
+                inode.fragment = (fragment != NO_FRAGMENT).then_some((fragment, offset));
+            }
+            3 | 10 => {
+                let b = self.read_metadata(&mut cursor, 8)?;
+                inode.links = le_u32(&b, 0);
+                let length = le_u32(&b, 4) as usize;
+                if length > 1 << 16 {
+                    return Err(format!("symbolic link target of {} bytes", length).into());
+                }
+                let target = self.read_metadata(&mut cursor, length)?;
+                inode.size = target.len() as u64;
+                inode.symlink = Some(target);
+                if inode_type == 10 {
+                    inode.xattr_index = xattr(le_u32(&self.read_metadata(&mut cursor, 4)?, 0));
+                }
+            }
+            4 | 5 | 11 | 12 => {
+                let b = self.read_metadata(&mut cursor, if inode_type < 8 { 8 } else { 12 })?;
+                inode.links = le_u32(&b, 0);
+                inode.rdev = Some(le_u32(&b, 4));
+                if inode_type >= 8 {
+                    inode.xattr_index = xattr(le_u32(&b, 8));
+                }
+            }
+            _ => {
+                let b = self.read_metadata(&mut cursor, if inode_type < 8 { 4 } else { 8 })?;
+                inode.links = le_u32(&b, 0);
+                if inode_type >= 8 {
+                    inode.xattr_index = xattr(le_u32(&b, 4));
+                }
+            }
+        }
+        Ok(inode)
+    }
+
+    /// The data or fragment block at `position` whose size field is `field`, expanded.
+    fn data_block(&mut self, position: u64, field: u32) -> Result<&[u8], Box<dyn Error>> {
+        if !self.blocks.contains_key(&position) {
+            let length = (field & (DATA_UNCOMPRESSED - 1)) as usize;
+            let block_size = self.superblock.block_size as usize;
+            if length > block_size {
+                return Err(format!("data block of {} bytes", length).into());
+            }
+            let raw = self.read_at(position, length)?;
+            let block = match field & DATA_UNCOMPRESSED {
+                0 => compression::bounded(self.codec, &raw, block_size)?,
+                _ => raw,
+            };
+            if self.blocks.len() >= CACHE_BLOCKS {
+                self.blocks.clear();
+            }
+            self.blocks.insert(position, block);
+        }
+        Ok(&self.blocks[&position])
+    }
+
+    /// Position and size field of the fragment block `index`.
+    fn fragment(&mut self, index: u32) -> Result<(u64, u32), Box<dyn Error>> {
+        if index >= self.superblock.fragment_count {
+            return Err(format!("fragment {} out of range", index).into());
+        }
+        let entry = self.table_entry(self.superblock.fragment_table, index as u64, 16)?;
+        Ok((le_u64(&entry, 0), le_u32(&entry, 8)))
+    }
+
+    /// Bytes `start..start + length` of block `index` of a file, `length` not going past
+    /// the end of the block.
+    fn file_block(
+        &mut self,
+        inode: &SquashInode,
+        index: usize,
+        start: usize,
+        length: usize,
+    ) -> Result<Vec<u8>, Box<dyn Error>> {
+        let (position, field, start) = match inode.block_sizes.get(index) {
+            Some(0) => return Ok(vec![0u8; length]),
+            Some(&field) => {
+                let position = inode.blocks_start
+                    + inode.block_sizes[..index]
+                        .iter()
+                        .map(|s| (s & (DATA_UNCOMPRESSED - 1)) as u64)
+                        .sum::<u64>();
+                (position, field, start)
+            }
+            None => {
+                let (fragment, offset) = inode
+                    .fragment
+                    .ok_or("read past the blocks of a file without fragment")?;
+                let (position, field) = self.fragment(fragment)?;
+                (position, field, offset as usize + start)
+            }
+        };
+        let block = self.data_block(position, field)?;
+        block
+            .get(start..start + length)
+            .map(<[u8]>::to_vec)
+            .ok_or_else(|| "data block shorter than the file says".into())
+    }
+
+    /// Key-value pairs of extended attribute set `index`.
+    fn xattrs(&mut self, index: u32) -> Result<Vec<ExtendedAttribute>, Box<dyn Error>> {
+        let header = self.read_at(self.superblock.xattr_id_table, 16)?;
+        let (table, count) = (le_u64(&header, 0), le_u32(&header, 8));
+        if index >= count {
+            return Err(format!("xattr set {} out of range", index).into());
+        }
+        let entry = self.table_entry(self.superblock.xattr_id_table + 16, index as u64, 16)?;
+        let (reference, pairs) = (le_u64(&entry, 0), le_u32(&entry, 8));
+        let mut cursor = (table + (reference >> 16), (reference & 0xffff) as usize);
+        let mut attributes = Vec::new();
+        for _ in 0..pairs {
+            let key = self.read_metadata(&mut cursor, 4)?;
+            let (kind, name_length) = (le_u16(&key, 0), le_u16(&key, 2) as usize);
+            let name = self.read_metadata(&mut cursor, name_length)?;
+            let prefix = match kind & 0xff {
+                0 => "user.",
+                1 => "trusted.",
+                2 => "security.",
+                _ => "",
+            };
+            let length = le_u32(&self.read_metadata(&mut cursor, 4)?, 0) as usize;
+            let mut value = self.read_metadata(&mut cursor, length.min(1 << 16))?;
+            // Values shared by several files are stored once, elsewhere.
+            if kind & 0x100 != 0 && value.len() == 8 {
+                let reference = le_u64(&value, 0);
+                let mut at = (table + (reference >> 16), (reference & 0xffff) as usize);
+                let length = le_u32(&self.read_metadata(&mut at, 4)?, 0) as usize;
+                value = self.read_metadata(&mut at, length.min(1 << 16))?;
+            }
+            attributes.push((
+                format!("{}{}", prefix, String::from_utf8_lossy(&name)),
+                value,
+            ));
+        }
+        Ok(attributes)
+    }
+}
+
+impl<T: Read + Seek> Filesystem for SquashFS<T> {
+    type FileType = SquashInode;
+    type DirectoryType = SquashDirEntry;
+
+    fn filesystem_type(&self) -> String {
+        "SquashFS".to_string()
+    }
+
+    fn path_separator(&self) -> String {
+        "/".to_string()
+    }
+
+    fn record_count(&mut self) -> u64 {
+        self.superblock.inode_count as u64
+    }
+
+    fn block_size(&self) -> u64 {
+        self.superblock.block_size as u64
+    }
+
+    fn get_metadata(&self) -> Result<Value, Box<dyn Error>> {
+        let mut metadata = serde_json::to_value(&self.superblock)?;
+        metadata["compressor_name"] = json!(self.superblock.compressor_name());
+        metadata["flag_names"] = json!(superblock_flag_names(self.superblock.flags));
+        Ok(metadata)
+    }
+
+    fn get_metadata_pretty(&self) -> Result<String, Box<dyn Error>> {
+        let sb = &self.superblock;
+        Ok(format!(
+            "SquashFS {}.{}, {} compression, {} byte blocks\n\
+             Inodes: {}, fragments: {}, ids: {}\n\
+             Bytes used: {}\n\
+             Created: {}\n\
+             Flags: {}\n",
+            sb.version.0,
+            sb.version.1,
+            sb.compressor_name(),
+            sb.block_size,
+            sb.inode_count,
+            sb.fragment_count,
+            sb.id_count,
+            sb.bytes_used,
+            format_timestamp(sb.modification_time as u64),
+            superblock_flag_names(sb.flags).join(", ")
+        ))
+    }
+
+    fn get_file(&mut self, reference: u64) -> Result<Self::FileType, Box<dyn Error>> {
+        self.inode(reference)
+    }
+
+    fn read_file_content(&mut self, file: &Self::FileType) -> Result<Vec<u8>, Box<dyn Error>> {
+        self.read_file_slice(file, 0, file.size as usize)
+    }
+
+    fn read_file_prefix(
+        &mut self,
+        file: &Self::FileType,
+        length: usize,
+    ) -> Result<Vec<u8>, Box<dyn Error>> {
+        self.read_file_slice(file, 0, length)
+    }
+
+    fn read_file_slice(
+        &mut self,
+        file: &Self::FileType,
+        offset: u64,
+        length: usize,
+    ) -> Result<Vec<u8>, Box<dyn Error>> {
+        if offset >= file.size {
+            return Ok(Vec::new());
+        }
+        let length = length.min((file.size - offset) as usize);
+        if let Some(target) = &file.symlink {
+            return Ok(target[offset as usize..offset as usize + length].to_vec());
+        }
+        if file.listing.is_some() {
+            return Err("cannot read the content of a directory".into());
+        }
+        let block_size = self.superblock.block_size as u64;
+        let mut out = Vec::with_capacity(length);
+        let end = offset + length as u64;
+        let mut position = offset;
+        while position < end {
+            let index = (position / block_size) as usize;
+            let start = (position % block_size) as usize;
+            let count = ((block_size - start as u64).min(end - position)) as usize;
+            out.extend(self.file_block(file, index, start, count)?);
+            position += count as u64;
+        }
+        Ok(out)
+    }
+
+    fn list_dir(
+        &mut self,
+        file: &Self::FileType,
+    ) -> Result<Vec<Self::DirectoryType>, Box<dyn Error>> {
+        let listing = file.listing.ok_or("not a directory")?;
+        let mut cursor = (
+            self.superblock.directory_table + listing.block as u64,
+            listing.offset as usize,
+        );
+        let data = self.read_metadata(&mut cursor, listing.size as usize)?;
+        let mut entries = Vec::new();
+        let mut pos = 0;
+        while pos + 12 <= data.len() {
+            let count = le_u32(&data, pos) as usize + 1;
+            let (start, base) = (le_u32(&data, pos + 4) as u64, le_u32(&data, pos + 8));
+            pos += 12;
+            for _ in 0..count.min(256) {
+                let Some(entry) = data.get(pos..pos + 8) else {
+                    return Err("truncated directory entry".into());
+                };
+                let name_length = le_u16(entry, 6) as usize + 1;
+                let name = data
+                    .get(pos + 8..pos + 8 + name_length)
+                    .ok_or("truncated directory entry name")?;
+                entries.push(SquashDirEntry {
+                    reference: start << 16 | le_u16(entry, 0) as u64,
+                    inode_number: base.wrapping_add_signed(le_u16(entry, 2) as i16 as i32),
+                    name: escape_name(name),
+                    kind: le_u16(entry, 4),
+                });
+                pos += 8 + name_length;
+            }
+        }
+        Ok(entries)
+    }
+
+    fn record_to_file(&self, file: &Self::FileType, file_id: u64, absolute_path: &str) -> File {
+        let file_type = unix_ftype(file.mode);
+        let mut common = json!({ FLAGS_KEY: [] });
+        if let Some(rdev) = file.rdev {
+            common[DEVICE_KEY] = json!({
+                "major": (rdev & 0xfff00) >> 8,
+                "minor": (rdev & 0xff) | ((rdev >> 12) & 0xfff00),
+            });
+        }
+        if let Some(target) = &file.symlink {
+            common[SYMLINK_TARGET_KEY] = json!(render_name(&escape_name(target)));
+        }
+        let metadata = namespaced_metadata(METADATA_NAMESPACE, file.to_json(), common);
+        let name = match absolute_path.rsplit_once('/') {
+            Some((_, name)) if !name.is_empty() => name,
+            _ => absolute_path,
+        };
+        let absolute_path = render_name(absolute_path);
+        let permissions = unix_mode_string(file.mode);
+        let on_disk = file
+            .block_sizes
+            .iter()
+            .map(|s| (s & (DATA_UNCOMPRESSED - 1)) as u64)
+            .sum();
+        File {
+            id: None,
+            identifier: file_id,
+            absolute_path: absolute_path.to_string(),
+            name: render_name(name).into_owned(),
+            raw_name: name_bytes(name).map(hex::encode),
+            ftype: file_type.to_string(),
+            size: file.size,
+            size_on_disk: Some(on_disk),
+            created: None,
+            modified: Some(file.mtime as u64),
+            accessed: None,
+            changed: None,
+            permissions: Some(permissions.clone()),
+            owner: Some(file.uid.to_string()),
+            group: Some(file.gid.to_string()),
+            display: Some(format!(
+                "[{}] - {} {} {} {} {:>5} {} {}",
+                file_id,
+                permissions,
+                file.links,
+                file.uid,
+                file.gid,
+                file.size,
+                format_timestamp(file.mtime as u64),
+                absolute_path
+            )),
+            sig_name: None,
+            sig_mime: None,
+            sig_exts: None,
+            detected_type: None,
+            ext_mismatch: None,
+            md5: None,
+            sha1: None,
+            sha256: None,
+            metadata,
+        }
+    }
+
+    fn get_root_file_id(&self) -> u64 {
+        self.superblock.root_inode
+    }
+
+    fn file_holes(
+        &mut self,
+        file: &Self::FileType,
+    ) -> Result<Option<Vec<ByteRange>>, Box<dyn Error>> {
+        if unix_ftype(file.mode) != "file" {
+            return Ok(None);
+        }
+        let block_size = self.superblock.block_size as u64;
+        let mut holes: Vec<ByteRange> = Vec::new();
+        for (index, _) in file
+            .block_sizes
+            .iter()
+            .enumerate()
+            .filter(|(_, s)| **s == 0)
+        {
+            let start = index as u64 * block_size;
+            let length = block_size.min(file.size - start);
+            match holes.last_mut() {
+                Some((offset, len)) if *offset + *len == start => *len += length,
+                _ => holes.push((start, length)),
+            }
+        }
+        Ok(Some(holes))
+    }
+
+    fn extended_attributes(
+        &mut self,
+        file: &Self::FileType,
+    ) -> Result<Vec<ExtendedAttribute>, Box<dyn Error>> {
+        match file.xattr_index {
+            Some(index) if self.superblock.xattr_id_table != NO_TABLE => self.xattrs(index),
+            _ => Ok(Vec::new()),
+        }
+    }
+}
@@ -0,0 +1,215 @@
+use crate::filesystem::File;
+use serde::Serialize;
+use std::cmp::Reverse;
+use std::collections::{BTreeMap, BinaryHeap};
+
+/// Upper bounds (exclusive) of the size histogram buckets.
+const SIZE_BUCKETS: &[(u64, &str)] = &[
+    (1, "0 B"),
+    (1 << 10, "< 1 KiB"),
+    (64 << 10, "< 64 KiB"),
+    (1 << 20, "< 1 MiB"),
+    (16 << 20, "< 16 MiB"),
+    (256 << 20, "< 256 MiB"),
+    (1 << 30, "< 1 GiB"),
+    (u64::MAX, ">= 1 GiB"),
+];
+
+/// Count and cumulated size of a group of files.
+#[derive(Debug, Clone, Default, Serialize)]
+pub struct Bucket {
+    pub count: u64,
+    pub bytes: u64,
+}
+
+impl Bucket {
+    fn add(&mut self, size: u64) {
+        self.count += 1;
+        self.bytes += size;
+    }
+}
+
+/// One bucket of the size histogram.
+#[derive(Debug, Clone, Serialize)]
+pub struct SizeBucket {
+    pub range: &'static str,
+    pub count: u64,
+    pub bytes: u64,
+}
+
+#[derive(Debug, Clone, Serialize, PartialEq, Eq, PartialOrd, Ord)]
+pub struct RankedFile {
+    pub rank: u64,
+    pub identifier: u64,
+    pub path: String,
+}
+
+/// Keeps the `limit` entries with the highest rank.
+#[derive(Debug, Clone)]
+struct TopN {
+    limit: usize,
+    heap: BinaryHeap<Reverse<RankedFile>>,
+}
+
+impl TopN {
+    fn new(limit: usize) -> Self {
+        Self {
+            limit,
+            heap: BinaryHeap::new(),
+        }
+    }
+
+    fn offer(&mut self, rank: u64, file: &File) {
+        if self.limit == 0 {
+            return;
+        }
+        if self.heap.len() == self.limit {
+            match self.heap.peek() {
+                Some(Reverse(min)) if min.rank >= rank => return,
+                _ => {
+                    self.heap.pop();
+                }
+            }
+        }
+        self.heap.push(Reverse(RankedFile {
+            rank,
+            identifier: file.identifier,
+            path: file.absolute_path.clone(),
+        }));
+    }
+
+    fn sorted(&self) -> Vec<RankedFile> {
+        let mut out: Vec<RankedFile> = self.heap.iter().map(|r| r.0.clone()).collect();
+        out.sort_by(|a, b| b.cmp(a));
+        out
+    }
+}
+
+/// Calendar year of a UNIX timestamp (UTC).
+fn unix_year(ts: u64) -> i64 {
+    // Civil-from-days (H. Hinnant), only the year is needed.
+    let z = (ts / 86_400) as i64 + 719_468;
+    let era = z.div_euclid(146_097);
+    let doe = z - era * 146_097;
+    let yoe = (doe - doe / 1460 + doe / 36_524 - doe / 146_096) / 365;
+    let doy = doe - (365 * yoe + yoe / 4 - yoe / 100);
+    let mp = (5 * doy + 2) / 153;
+    let year = yoe + era * 400;
+    if mp >= 10 { year + 1 } else { year }
+}
+
+fn extension(name: &str) -> String {
+    match name.rsplit_once('.') {
+        Some((stem, ext)) if !stem.is_empty() && !ext.is_empty() => ext.to_ascii_lowercase(),
+        _ => String::from("(none)"),
+    }
+}
+
+/// Volume profile accumulated from the records of a walk.
+#[derive(Debug, Clone, Serialize)]
+pub struct VolumeStats {
+    pub files: u64,
+    pub dirs: u64,
+    pub total_bytes: u64,
+    pub by_extension: BTreeMap<String, Bucket>,
+    pub by_type: BTreeMap<String, Bucket>,
+    pub by_owner: BTreeMap<String, Bucket>,
+    pub by_modified_year: BTreeMap<String, Bucket>,
+    pub size_histogram: Vec<SizeBucket>,
+    #[serde(skip)]
+    largest: TopN,
+    #[serde(skip)]
+    deepest: TopN,
+}
+
+/// Serialized form of `VolumeStats`, with the top lists resolved.
+#[derive(Serialize)]
+pub struct StatsReport<'a> {
+    #[serde(flatten)]
+    pub stats: &'a VolumeStats,
+    pub largest_files: Vec<RankedFile>,
+    pub deepest_paths: Vec<RankedFile>,
+}
+
+impl VolumeStats {
+    /// `top` is the number of largest files and deepest paths kept.
+    pub fn new(top: usize) -> Self {
+        let size_histogram = SIZE_BUCKETS
+            .iter()
+            .map(|(_, range)| SizeBucket {
+                range,
+                count: 0,
+                bytes: 0,
+            })
+            .collect();
+        Self {
+            files: 0,
+            dirs: 0,
+            total_bytes: 0,
+            by_extension: BTreeMap::new(),
+            by_type: BTreeMap::new(),
+            by_owner: BTreeMap::new(),
+            by_modified_year: BTreeMap::new(),
+            size_histogram,
+            largest: TopN::new(top),
+            deepest: TopN::new(top),
+        }
+    }
+
+    pub fn add(&mut self, file: &File, separator: &str) {
+        let depth = file
+            .absolute_path
+            .split(separator)
+            .filter(|c| !c.is_empty())
+            .count() as u64;
+        self.deepest.offer(depth, file);
+        self.by_type
+            .entry(file.ftype.clone())
+            .or_default()
+            .add(file.size);
+
+        if file.ftype.eq_ignore_ascii_case("dir") || file.ftype.eq_ignore_ascii_case("directory") {
+            self.dirs += 1;
+            return;
+        }
+
+        self.files += 1;
+        self.total_bytes += file.size;
+        self.largest.offer(file.size, file);
+        self.by_extension
+            .entry(extension(&file.name))
+            .or_default()
+            .add(file.size);
+        self.by_owner
+            .entry(
+                file.owner
+                    .clone()
+                    .unwrap_or_else(|| String::from("(unknown)")),
+            )
+            .or_default()
+            .add(file.size);
+        let year = match file.modified {
+            Some(ts) if ts > 0 => unix_year(ts).to_string(),
+            _ => String::from("(unknown)"),
+        };
+        self.by_modified_year
+            .entry(year)
+            .or_default()
+            .add(file.size);
+        if let Some(i) = SIZE_BUCKETS
+            .iter()
+            .position(|(bound, _)| file.size < *bound)
+        {
+            self.size_histogram[i].count += 1;
+            self.size_histogram[i].bytes += file.size;
+        }
+    }
+
+    pub fn report(&self) -> StatsReport<'_> {
+        StatsReport {
+            stats: self,
+            largest_files: self.largest.sorted(),
+            deepest_paths: self.deepest.sorted(),
+        }
+    }
+}
@@ -0,0 +1,148 @@
+use std::fmt;
+use std::io::{self, Read};
+use std::str::FromStr;
+
+const READ_CHUNK: usize = 64 * 1024;
+
+/// Encodings searched by the strings extractor.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum StringEncoding {
+    Ascii,
+    Utf16Le,
+    Both,
+}
+
+impl FromStr for StringEncoding {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s.to_ascii_lowercase().as_str() {
+            "ascii" => Ok(StringEncoding::Ascii),
+            "utf16le" | "utf-16le" | "utf16" => Ok(StringEncoding::Utf16Le),
+            "both" => Ok(StringEncoding::Both),
+            other => Err(format!("unsupported string encoding: {}", other)),
+        }
+    }
+}
+
+impl fmt::Display for StringEncoding {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let s = match self {
+            StringEncoding::Ascii => "ascii",
+            StringEncoding::Utf16Le => "utf16le",
+            StringEncoding::Both => "both",
+        };
+        write!(f, "{}", s)
+    }
+}
+
+/// A printable string found in a content stream.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct FoundString {
+    /// Offset of the first byte of the string in the stream.
+    pub offset: u64,
+    /// `Ascii` or `Utf16Le` (never `Both`).
+    pub encoding: StringEncoding,
+    pub text: String,
+}
+
+#[inline]
+fn printable(b: u8) -> bool {
+    (0x20..=0x7e).contains(&b) || b == b'\t'
+}
+
+/// Accumulates one run of printable characters.
+struct Run {
+    start: u64,
+    text: String,
+}
+
+impl Run {
+    fn new() -> Self {
+        Self {
+            start: 0,
+            text: String::new(),
+        }
+    }
+
+    fn push(&mut self, at: u64, c: char) {
+        if self.text.is_empty() {
+            self.start = at;
+        }
+        self.text.push(c);
+    }
+
+    fn flush(
+        &mut self,
+        min_len: usize,
+        encoding: StringEncoding,
+        callback: &mut dyn FnMut(FoundString),
+    ) {
+        if self.text.len() >= min_len {
+            callback(FoundString {
+                offset: self.start,
+                encoding,
+                text: std::mem::take(&mut self.text),
+            });
+        }
+        self.text.clear();
+    }
+}
+
+/// Stream `reader` and report every run of at least `min_len` printable characters.
+///
+/// `base_offset` is added to the reported offsets. UTF-16LE strings are searched at both
+/// even and odd alignments. Memory usage is bounded by the longest string found.
+pub fn extract_strings<R: Read + ?Sized>(
+    reader: &mut R,
+    base_offset: u64,
+    min_len: usize,
+    encoding: StringEncoding,
+    callback: &mut dyn FnMut(FoundString),
+) -> io::Result<()> {
+    let want_ascii = matches!(encoding, StringEncoding::Ascii | StringEncoding::Both);
+    let want_utf16 = matches!(encoding, StringEncoding::Utf16Le | StringEncoding::Both);
+    let min_len = min_len.max(1);
+
+    let mut ascii = Run::new();
+    // One UTF-16 scanner per alignment (pairs starting at even / odd offsets).
+    let mut utf16 = [Run::new(), Run::new()];
+    let mut prev: Option<u8> = None;
+
+    let mut buf = vec![0u8; READ_CHUNK];
+    let mut pos = base_offset;
+    loop {
+        let n = match reader.read(&mut buf) {
+            Ok(0) => break,
+            Ok(n) => n,
+            Err(e) if e.kind() == io::ErrorKind::Interrupted => continue,
+            Err(e) => return Err(e),
+        };
+        for &b in &buf[..n] {
+            if want_ascii {
+                if printable(b) {
+                    ascii.push(pos, b as char);
+                } else {
+                    ascii.flush(min_len, StringEncoding::Ascii, callback);
+                }
+            }
+            if want_utf16 && let Some(lo) = prev {
+                // The pair (lo, b) starts at pos - 1.
+                let run = &mut utf16[((pos - 1 - base_offset) % 2) as usize];
+                if b == 0 && printable(lo) {
+                    run.push(pos - 1, lo as char);
+                } else {
+                    run.flush(min_len, StringEncoding::Utf16Le, callback);
+                }
+            }
+            prev = Some(b);
+            pos += 1;
+        }
+    }
+
+    ascii.flush(min_len, StringEncoding::Ascii, callback);
+    for run in utf16.iter_mut() {
+        run.flush(min_len, StringEncoding::Utf16Le, callback);
+    }
+    Ok(())
+}
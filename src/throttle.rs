@@ -0,0 +1,85 @@
+//! Read-rate limit (`--max-read-rate`) for evidence on shared storage, so a long
+//! enumeration over a network share leaves bandwidth to the other examiners. Every read
+//! from the evidence, whatever the thread, draws from one process-wide token bucket;
+//! reads served by the block cache do not count.
+use std::io::{self, Read, Seek, SeekFrom};
+use std::sync::Mutex;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::thread;
+use std::time::{Duration, Instant};
+
+/// Bytes that may be read in a burst after an idle period, in seconds of the rate.
+const BURST_SECONDS: f64 = 0.25;
+
+static RATE: AtomicU64 = AtomicU64::new(0);
+static WAITED_NANOS: AtomicU64 = AtomicU64::new(0);
+/// Bytes available (negative when in debt) and when that was computed.
+static BUCKET: Mutex<Option<(f64, Instant)>> = Mutex::new(None);
+
+/// Limit the reads from the evidence to `bytes_per_second`; 0 removes the limit.
+pub fn set_rate(bytes_per_second: u64) {
+    RATE.store(bytes_per_second, Ordering::Relaxed);
+}
+
+pub fn rate() -> Option<u64> {
+    match RATE.load(Ordering::Relaxed) {
+        0 => None,
+        rate => Some(rate),
+    }
+}
+
+/// Total time spent waiting for the rate limit.
+pub fn waited() -> Duration {
+    Duration::from_nanos(WAITED_NANOS.load(Ordering::Relaxed))
+}
+
+/// Account for `bytes` just read from the evidence, sleeping until the rate allows them.
+pub fn consume(bytes: usize) {
+    let Some(rate) = rate() else {
+        return;
+    };
+    if bytes == 0 {
+        return;
+    }
+    let rate = rate as f64;
+    let wait = {
+        let mut bucket = BUCKET.lock().unwrap_or_else(|e| e.into_inner());
+        let now = Instant::now();
+        let (available, updated) = bucket.get_or_insert((rate * BURST_SECONDS, now));
+        *available = (*available + now.duration_since(*updated).as_secs_f64() * rate)
+            .min(rate * BURST_SECONDS)
+            - bytes as f64;
+        *updated = now;
+        // Concurrent readers each wait out the debt as it stood after their own read.
+        (*available < 0.0).then(|| Duration::from_secs_f64(-*available / rate))
+    };
+    if let Some(wait) = wait {
+        WAITED_NANOS.fetch_add(wait.as_nanos() as u64, Ordering::Relaxed);
+        thread::sleep(wait);
+    }
+}
+
+/// `Read + Seek` adapter accounting every read of `inner` against the rate limit.
+pub struct Throttled<R: Read + Seek> {
+    inner: R,
+}
+
+impl<R: Read + Seek> Throttled<R> {
+    pub fn new(inner: R) -> Self {
+        Self { inner }
+    }
+}
+
+impl<R: Read + Seek> Read for Throttled<R> {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        let n = self.inner.read(buf)?;
+        consume(n);
+        Ok(n)
+    }
+}
+
+impl<R: Read + Seek> Seek for Throttled<R> {
+    fn seek(&mut self, pos: SeekFrom) -> io::Result<u64> {
+        self.inner.seek(pos)
+    }
+}
@@ -0,0 +1,173 @@
+//! Rendering of timestamps in human-readable output. Records always hold UTC UNIX times;
+//! the display zone and format are chosen once per process (`--timezone`, `--time-format`).
+//! Filesystems storing local times (FAT, exFAT) are converted to UTC following the
+//! policy chosen with `--fat-time`.
+use jiff::Timestamp;
+use jiff::civil::DateTime;
+use jiff::tz::{AmbiguousOffset, Offset, TimeZone};
+use std::sync::OnceLock;
+
+/// `strftime`-like format used when none is given, close to The Sleuth Kit output.
+pub const DEFAULT_TIME_FORMAT: &str = "%Y-%m-%d %H:%M:%S (%Z)";
+
+static TIME_DISPLAY: OnceLock<TimeDisplay> = OnceLock::new();
+static LOCAL_TIME_POLICY: OnceLock<LocalTimePolicy> = OnceLock::new();
+
+/// Time zone and format applied to displayed timestamps.
+#[derive(Debug, Clone)]
+pub struct TimeDisplay {
+    zone: TimeZone,
+    format: String,
+}
+
+impl Default for TimeDisplay {
+    fn default() -> Self {
+        Self {
+            zone: TimeZone::UTC,
+            format: DEFAULT_TIME_FORMAT.to_string(),
+        }
+    }
+}
+
+/// Parse a fixed offset such as `+02:00`, `-0530` or `+9`.
+fn parse_offset(value: &str) -> Option<Offset> {
+    let sign = match value.as_bytes().first()? {
+        b'+' => 1,
+        b'-' => -1,
+        _ => return None,
+    };
+    let digits = value[1..].replace(':', "");
+    let (hours, minutes) = match digits.len() {
+        1 | 2 => (digits.parse::<i32>().ok()?, 0),
+        4 => (digits[..2].parse().ok()?, digits[2..].parse().ok()?),
+        _ => return None,
+    };
+    if hours > 25 || minutes > 59 {
+        return None;
+    }
+    Offset::from_seconds(sign * (hours * 3600 + minutes * 60)).ok()
+}
+
+/// An IANA name (`Europe/Paris`), `UTC`, `local` or a fixed offset (`+02:00`).
+fn parse_zone(tz: &str) -> Result<TimeZone, String> {
+    if tz.eq_ignore_ascii_case("utc") {
+        return Ok(TimeZone::UTC);
+    }
+    if tz.eq_ignore_ascii_case("local") {
+        return Ok(TimeZone::system());
+    }
+    match parse_offset(tz) {
+        Some(offset) => Ok(TimeZone::fixed(offset)),
+        None => TimeZone::get(tz).map_err(|e| format!("unknown time zone '{}': {}", tz, e)),
+    }
+}
+
+impl TimeDisplay {
+    /// `timezone` is an IANA name (`Europe/Paris`), `UTC`, `local` or a fixed offset
+    /// (`+02:00`); `format` uses `strftime` conversions.
+    pub fn new(timezone: Option<&str>, format: Option<&str>) -> Result<Self, String> {
+        let zone = match timezone {
+            None => TimeZone::UTC,
+            Some(tz) => parse_zone(tz)?,
+        };
+        let display = Self {
+            zone,
+            format: format.unwrap_or(DEFAULT_TIME_FORMAT).to_string(),
+        };
+        // Surface format errors now rather than on every rendered timestamp.
+        jiff::fmt::strtime::format(
+            &display.format,
+            &Timestamp::UNIX_EPOCH.to_zoned(display.zone.clone()),
+        )
+        .map_err(|e| format!("invalid time format '{}': {}", display.format, e))?;
+        Ok(display)
+    }
+
+    /// Render UNIX seconds.
+    pub fn format(&self, secs: u64) -> String {
+        match Timestamp::from_second(secs as i64) {
+            Ok(ts) => jiff::fmt::strtime::format(&self.format, &ts.to_zoned(self.zone.clone()))
+                .unwrap_or_else(|_| secs.to_string()),
+            Err(_) => secs.to_string(),
+        }
+    }
+}
+
+/// Install the display settings for the whole process. Only the first call has an effect.
+pub fn set_time_display(display: TimeDisplay) {
+    let _ = TIME_DISPLAY.set(display);
+}
+
+/// Render UNIX seconds with the process display settings (UTC by default).
+pub fn format_timestamp(secs: u64) -> String {
+    TIME_DISPLAY.get_or_init(TimeDisplay::default).format(secs)
+}
+
+/// How timestamps stored as local time (FAT, exFAT) are converted to UTC.
+#[derive(Debug, Clone, Default)]
+pub enum LocalTimePolicy {
+    /// The UTC offset recorded with the timestamp (exFAT), or UTC without one.
+    #[default]
+    EntryOffset,
+    /// Stored times are UTC; recorded offsets are ignored.
+    Utc,
+    /// Stored times are local to this zone (kept with the name it was given). Around DST
+    /// changes, a recorded offset picks between the two candidate offsets.
+    Zone(String, TimeZone),
+}
+
+impl LocalTimePolicy {
+    /// `entry-offset`, `utc`, or a zone as accepted by `--timezone`.
+    pub fn parse(value: &str) -> Result<Self, String> {
+        match value {
+            "entry-offset" => Ok(Self::EntryOffset),
+            "utc" | "UTC" => Ok(Self::Utc),
+            zone => Ok(Self::Zone(zone.to_string(), parse_zone(zone)?)),
+        }
+    }
+
+    pub fn name(&self) -> String {
+        match self {
+            Self::EntryOffset => "entry-offset".to_string(),
+            Self::Utc => "utc".to_string(),
+            Self::Zone(name, _) => format!("zone:{}", name),
+        }
+    }
+
+    /// UNIX seconds of the local time `local`, given the offset recorded with it if any,
+    /// and how it was interpreted: the policy, qualified when it could not fully apply.
+    pub fn to_utc(&self, local: DateTime, recorded: Option<Offset>) -> Option<(i64, String)> {
+        let (offset, interpretation) = match self {
+            Self::EntryOffset => match recorded {
+                Some(offset) => (offset, self.name()),
+                None => (Offset::UTC, format!("{} (none recorded, utc)", self.name())),
+            },
+            Self::Utc => (Offset::UTC, self.name()),
+            Self::Zone(_, zone) => match zone.to_ambiguous_timestamp(local).offset() {
+                AmbiguousOffset::Unambiguous { offset } => (offset, self.name()),
+                AmbiguousOffset::Gap { before, after }
+                | AmbiguousOffset::Fold { before, after } => {
+                    match recorded.filter(|r| *r == before || *r == after) {
+                        Some(offset) => (offset, format!("{} (DST from the entry)", self.name())),
+                        None => (
+                            before,
+                            format!("{} (ambiguous, offset before the change)", self.name()),
+                        ),
+                    }
+                }
+            },
+        };
+        let seconds = offset.to_timestamp(local).ok()?.as_second();
+        Some((seconds, interpretation))
+    }
+}
+
+/// Install the local time policy for the whole process. Only the first call has an effect.
+pub fn set_local_time_policy(policy: LocalTimePolicy) {
+    let _ = LOCAL_TIME_POLICY.set(policy);
+}
+
+/// The process local time policy (`entry-offset` by default).
+pub fn local_time_policy() -> &'static LocalTimePolicy {
+    LOCAL_TIME_POLICY.get_or_init(LocalTimePolicy::default)
+}
@@ -0,0 +1,148 @@
+//! Fault-tolerant reads (`--read-retries`) for damaged media. A failed read of the
+//! evidence is retried, then redone sector by sector, and the sectors that still cannot
+//! be read are zero-filled so the walk goes on with explicit gaps. The unreadable ranges
+//! are kept process-wide for the end-of-run report, and per thread so that walks can
+//! attach to a record the ranges met while reading its content.
+use crate::filesystem::ByteRange;
+use std::cell::RefCell;
+use std::io::{self, Read, Seek, SeekFrom};
+use std::sync::Mutex;
+use std::sync::atomic::{AtomicU32, Ordering};
+
+/// Unit of the zero-filled ranges, the smallest one a disk reads.
+const SECTOR_SIZE: u64 = 512;
+
+/// Retries of a failed read plus one, 0 when reads are not made tolerant.
+static ATTEMPTS: AtomicU32 = AtomicU32::new(0);
+/// Unreadable ranges met so far, sorted and merged.
+static BAD_RANGES: Mutex<Vec<ByteRange>> = Mutex::new(Vec::new());
+
+thread_local! {
+    static THREAD_RANGES: RefCell<Vec<ByteRange>> = const { RefCell::new(Vec::new()) };
+}
+
+/// Make the reads of the evidence opened from now on tolerant, retrying each failed read
+/// `retries` times before zero-filling the sectors that cannot be read; `None` lets read
+/// errors through.
+pub fn set_retries(retries: Option<u32>) {
+    ATTEMPTS.store(
+        retries.map_or(0, |r| r.saturating_add(1)),
+        Ordering::Relaxed,
+    );
+}
+
+pub fn retries() -> Option<u32> {
+    match ATTEMPTS.load(Ordering::Relaxed) {
+        0 => None,
+        attempts => Some(attempts - 1),
+    }
+}
+
+/// Every range zero-filled so far, sorted and merged.
+pub fn bad_ranges() -> Vec<ByteRange> {
+    BAD_RANGES.lock().unwrap_or_else(|e| e.into_inner()).clone()
+}
+
+/// Take the ranges zero-filled by the reads of this thread since the last call.
+pub fn take_thread_ranges() -> Vec<ByteRange> {
+    THREAD_RANGES.with(|ranges| std::mem::take(&mut *ranges.borrow_mut()))
+}
+
+fn record(offset: u64, length: u64) {
+    THREAD_RANGES.with(|ranges| merge_range(&mut ranges.borrow_mut(), offset, length));
+    merge_range(
+        &mut BAD_RANGES.lock().unwrap_or_else(|e| e.into_inner()),
+        offset,
+        length,
+    );
+}
+
+/// Insert a range into a sorted list, merging it with the ranges it touches.
+fn merge_range(ranges: &mut Vec<ByteRange>, offset: u64, length: u64) {
+    let (mut start, mut end) = (offset, offset + length);
+    let first = ranges.partition_point(|&(o, l)| o + l < start);
+    let mut last = first;
+    while last < ranges.len() && ranges[last].0 <= end {
+        start = start.min(ranges[last].0);
+        end = end.max(ranges[last].0 + ranges[last].1);
+        last += 1;
+    }
+    ranges.splice(first..last, [(start, end - start)]);
+}
+
+/// `Read + Seek` adapter retrying the failed reads of `inner` and zero-filling the
+/// sectors it cannot read.
+pub struct Tolerant<R: Read + Seek> {
+    inner: R,
+    attempts: u32,
+    pos: u64,
+    /// Whether a failed read may have left `inner` somewhere else than `pos`.
+    lost: bool,
+}
+
+impl<R: Read + Seek> Tolerant<R> {
+    /// Wrap `inner` with the retries set by `set_retries` (none when unset).
+    pub fn new(mut inner: R) -> io::Result<Self> {
+        let pos = inner.stream_position()?;
+        Ok(Self {
+            inner,
+            attempts: ATTEMPTS.load(Ordering::Relaxed).max(1),
+            pos,
+            lost: false,
+        })
+    }
+
+    fn read_at(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        if self.lost {
+            self.inner.seek(SeekFrom::Start(self.pos))?;
+            self.lost = false;
+        }
+        let result = self.inner.read(buf);
+        self.lost = result.is_err();
+        result
+    }
+}
+
+impl<R: Read + Seek> Read for Tolerant<R> {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        for _ in 0..self.attempts {
+            if let Ok(n) = self.read_at(buf) {
+                self.pos += n as u64;
+                return Ok(n);
+            }
+        }
+
+        // Sector by sector, so that only the unreadable ones are lost.
+        let start = self.pos;
+        let end = start + buf.len() as u64;
+        while self.pos < end {
+            let sector_end = ((self.pos / SECTOR_SIZE + 1) * SECTOR_SIZE).min(end);
+            let chunk = &mut buf[(self.pos - start) as usize..(sector_end - start) as usize];
+            match self.read_at(chunk) {
+                Ok(0) => break,
+                Ok(n) => self.pos += n as u64,
+                Err(_) => {
+                    chunk.fill(0);
+                    record(self.pos, sector_end - self.pos);
+                    self.pos = sector_end;
+                }
+            }
+        }
+        Ok((self.pos - start) as usize)
+    }
+}
+
+impl<R: Read + Seek> Seek for Tolerant<R> {
+    fn seek(&mut self, pos: SeekFrom) -> io::Result<u64> {
+        self.pos = match pos {
+            SeekFrom::Current(delta) => self.inner.seek(SeekFrom::Start(
+                self.pos
+                    .checked_add_signed(delta)
+                    .ok_or_else(|| io::Error::other("seek before the start of the evidence"))?,
+            ))?,
+            pos => self.inner.seek(pos)?,
+        };
+        self.lost = false;
+        Ok(self.pos)
+    }
+}
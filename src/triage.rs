@@ -0,0 +1,363 @@
+//! Suspicious file heuristics: a walk is screened for setuid/setgid binaries, executables
+//! in unusual locations, hidden+system files, double extensions and executables created
+//! recently with a much older modification time, and the hits are ranked for review.
+use crate::filesystem::File;
+use serde::Serialize;
+use std::fmt;
+use std::str::FromStr;
+
+const DAY: u64 = 86_400;
+
+/// Extensions treated as executable content when no signature was detected.
+const EXECUTABLE_EXTENSIONS: &[&str] = &[
+    "exe", "dll", "sys", "scr", "com", "pif", "cpl", "msi", "bat", "cmd", "ps1", "psm1", "vbs",
+    "vbe", "js", "jse", "wsf", "hta", "lnk", "jar", "elf", "so", "sh", "py", "pl", "dylib",
+];
+/// Extensions used as decoys in front of an executable one (`invoice.pdf.exe`).
+const DECOY_EXTENSIONS: &[&str] = &[
+    "pdf", "doc", "docx", "xls", "xlsx", "ppt", "pptx", "rtf", "txt", "jpg", "jpeg", "png", "gif",
+    "bmp", "mp3", "mp4", "avi", "zip", "rar", "7z", "csv", "htm", "html",
+];
+/// Detected content types that run code.
+const EXECUTABLE_TYPES: &[&str] = &["pe", "msdos", "elf", "macho", "script", "java-class"];
+
+/// Directories where setuid/setgid binaries are expected.
+const SYSTEM_BIN_DIRS: &[&str] = &[
+    "/bin/",
+    "/sbin/",
+    "/usr/bin/",
+    "/usr/sbin/",
+    "/usr/lib/",
+    "/usr/lib64/",
+    "/usr/libexec/",
+    "/usr/local/bin/",
+    "/usr/local/sbin/",
+    "/lib/",
+    "/lib64/",
+    "/opt/",
+    "/snap/",
+];
+/// Writable or staging locations where executables seldom belong, with a severity.
+const UNUSUAL_LOCATIONS: &[(&str, Severity)] = &[
+    ("/dev/shm/", Severity::High),
+    ("/$recycle.bin/", Severity::High),
+    ("/recycler/", Severity::High),
+    ("/system volume information/", Severity::High),
+    ("/tmp/", Severity::Medium),
+    ("/var/tmp/", Severity::Medium),
+    ("/windows/temp/", Severity::Medium),
+    ("/appdata/local/temp/", Severity::Medium),
+    ("/users/public/", Severity::Medium),
+    ("/programdata/", Severity::Low),
+    ("/appdata/roaming/", Severity::Low),
+    ("/windows/fonts/", Severity::Medium),
+    ("/windows/debug/", Severity::Medium),
+    ("/windows/tasks/", Severity::Medium),
+    ("/perflogs/", Severity::Medium),
+    ("/private/tmp/", Severity::Medium),
+    ("/var/www/", Severity::Low),
+];
+/// Hidden+system files that are normal on Windows volumes.
+const EXPECTED_HIDDEN_SYSTEM: &[&str] = &[
+    "pagefile.sys",
+    "hiberfil.sys",
+    "swapfile.sys",
+    "desktop.ini",
+    "thumbs.db",
+    "bootmgr",
+    "bootnxt",
+    "bootsect.bak",
+    "autorun.inf",
+    "ntuser.dat",
+    "ntuser.ini",
+    "iconcache.db",
+];
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Serialize)]
+#[serde(rename_all = "lowercase")]
+pub enum Severity {
+    Low,
+    Medium,
+    High,
+}
+
+impl FromStr for Severity {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s.to_ascii_lowercase().as_str() {
+            "low" => Ok(Severity::Low),
+            "medium" | "med" => Ok(Severity::Medium),
+            "high" => Ok(Severity::High),
+            other => Err(format!("unknown severity: {}", other)),
+        }
+    }
+}
+
+impl fmt::Display for Severity {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let s = match self {
+            Severity::Low => "low",
+            Severity::Medium => "medium",
+            Severity::High => "high",
+        };
+        write!(f, "{}", s)
+    }
+}
+
+/// One heuristic hit.
+#[derive(Debug, Clone, Serialize)]
+pub struct Finding {
+    pub severity: Severity,
+    /// Heuristic name (`setuid`, `unusual-location`, ...).
+    pub rule: &'static str,
+    pub identifier: u64,
+    pub path: String,
+    pub detail: String,
+}
+
+/// Tunables of the triage pass.
+#[derive(Debug, Clone)]
+pub struct TriageOptions {
+    /// Findings below this severity are dropped.
+    pub min_severity: Severity,
+    /// "Recent" creation window, counted back from the newest creation time on the volume.
+    pub recent_days: u64,
+    /// Minimum distance between an older modification time and the creation time.
+    pub gap_days: u64,
+}
+
+impl Default for TriageOptions {
+    fn default() -> Self {
+        Self {
+            min_severity: Severity::Low,
+            recent_days: 30,
+            gap_days: 365,
+        }
+    }
+}
+
+/// Special bits of a `permissions` string: `(setuid, setgid, executable)`. Symbolic
+/// (`-rwsr-xr-x`) and octal (`104755`) forms are understood.
+fn unix_bits(permissions: &str) -> Option<(bool, bool, bool)> {
+    let chars: Vec<char> = permissions.chars().collect();
+    if chars.len() == 10 && "-dlbcps?".contains(chars[0]) {
+        let exec = [3, 6, 9].iter().any(|&i| "xst".contains(chars[i]));
+        return Some(("sS".contains(chars[3]), "sS".contains(chars[6]), exec));
+    }
+    let mode = u32::from_str_radix(permissions, 8).ok()?;
+    Some((mode & 0o4000 != 0, mode & 0o2000 != 0, mode & 0o111 != 0))
+}
+
+/// Hidden and system flags of a DOS attribute string (`RHSDA`, exFAT / NTFS).
+fn dos_hidden_system(permissions: &str) -> Option<(bool, bool)> {
+    if permissions.is_empty() || !permissions.chars().all(|c| "RHSDA".contains(c)) {
+        return None;
+    }
+    Some((permissions.contains('H'), permissions.contains('S')))
+}
+
+fn is_regular(file: &File) -> bool {
+    file.ftype.eq_ignore_ascii_case("file") || file.ftype.eq_ignore_ascii_case("regular")
+}
+
+/// Lowercase path with `/` separators and a trailing `/` on the parent directory.
+fn normalized_parent(path: &str, separator: &str) -> String {
+    let path = path.replace(separator, "/").to_ascii_lowercase();
+    match path.rfind('/') {
+        Some(pos) => path[..=pos].to_string(),
+        None => String::from("/"),
+    }
+}
+
+/// Collects findings from the records of a walk.
+#[derive(Debug, Clone)]
+pub struct Triage {
+    options: TriageOptions,
+    findings: Vec<Finding>,
+    /// Executables with a creation time later than their modification time, checked
+    /// against the volume's newest creation time once the walk is over.
+    backdated: Vec<(u64, String, u64, u64)>,
+    newest_created: u64,
+}
+
+/// Serialized result of a triage pass, most severe findings first.
+#[derive(Debug, Clone, Serialize)]
+pub struct TriageReport {
+    pub high: usize,
+    pub medium: usize,
+    pub low: usize,
+    pub findings: Vec<Finding>,
+}
+
+impl Triage {
+    pub fn new(options: TriageOptions) -> Self {
+        Self {
+            options,
+            findings: Vec::new(),
+            backdated: Vec::new(),
+            newest_created: 0,
+        }
+    }
+
+    fn push(&mut self, severity: Severity, rule: &'static str, file: &File, detail: String) {
+        if severity >= self.options.min_severity {
+            self.findings.push(Finding {
+                severity,
+                rule,
+                identifier: file.identifier,
+                path: file.absolute_path.clone(),
+                detail,
+            });
+        }
+    }
+
+    pub fn add(&mut self, file: &File, separator: &str) {
+        if let Some(created) = file.created {
+            self.newest_created = self.newest_created.max(created);
+        }
+        if !is_regular(file) {
+            return;
+        }
+        let name = file.name.to_ascii_lowercase();
+        let parent = normalized_parent(&file.absolute_path, separator);
+        let extension = name
+            .rsplit_once('.')
+            .map(|(_, ext)| ext)
+            .unwrap_or_default();
+        let unix = file.permissions.as_deref().and_then(unix_bits);
+        let detected_executable = file
+            .detected_type
+            .as_deref()
+            .is_some_and(|t| EXECUTABLE_TYPES.contains(&t));
+        let executable = detected_executable
+            || EXECUTABLE_EXTENSIONS.contains(&extension)
+            || unix.is_some_and(|(_, _, exec)| exec);
+
+        if let Some((setuid, setgid, _)) = unix
+            && (setuid || setgid)
+        {
+            let expected = SYSTEM_BIN_DIRS.iter().any(|dir| parent.starts_with(dir));
+            let bits = match (setuid, setgid) {
+                (true, true) => "setuid and setgid",
+                (true, false) => "setuid",
+                _ => "setgid",
+            };
+            if expected {
+                self.push(
+                    Severity::Low,
+                    "setuid",
+                    file,
+                    format!("{} binary in a system directory", bits),
+                );
+            } else {
+                self.push(
+                    Severity::High,
+                    "setuid",
+                    file,
+                    format!("{} binary outside the system directories", bits),
+                );
+            }
+        }
+
+        if executable {
+            if let Some((dir, severity)) = UNUSUAL_LOCATIONS
+                .iter()
+                .find(|(dir, _)| parent.contains(dir))
+            {
+                self.push(
+                    *severity,
+                    "unusual-location",
+                    file,
+                    format!("executable below {}", dir.trim_end_matches('/')),
+                );
+            } else if parent.split('/').any(|c| c.starts_with('.') && c.len() > 1) {
+                self.push(
+                    Severity::Low,
+                    "unusual-location",
+                    file,
+                    String::from("executable inside a hidden directory"),
+                );
+            }
+        }
+
+        if let Some((true, true)) = file.permissions.as_deref().and_then(dos_hidden_system)
+            && !name.starts_with('$')
+            && !EXPECTED_HIDDEN_SYSTEM.contains(&name.as_str())
+        {
+            let severity = if executable {
+                Severity::High
+            } else {
+                Severity::Medium
+            };
+            self.push(
+                severity,
+                "hidden-system",
+                file,
+                String::from("hidden and system attributes set"),
+            );
+        }
+
+        let parts: Vec<&str> = name.split('.').collect();
+        if parts.len() >= 3 && !parts[0].trim().is_empty() {
+            let last = parts[parts.len() - 1];
+            let decoy = parts[parts.len() - 2].trim();
+            if EXECUTABLE_EXTENSIONS.contains(&last) && DECOY_EXTENSIONS.contains(&decoy) {
+                self.push(
+                    Severity::High,
+                    "double-extension",
+                    file,
+                    format!(".{} posing as .{}", last, decoy),
+                );
+            }
+        }
+
+        if executable
+            && let (Some(created), Some(modified)) = (file.created, file.modified)
+            && created > modified.saturating_add(self.options.gap_days * DAY)
+        {
+            self.backdated.push((
+                file.identifier,
+                file.absolute_path.clone(),
+                created,
+                modified,
+            ));
+        }
+    }
+
+    /// Resolve the time based heuristic and rank the findings.
+    pub fn report(mut self) -> TriageReport {
+        let recent_from = self
+            .newest_created
+            .saturating_sub(self.options.recent_days * DAY);
+        let min_severity = self.options.min_severity;
+        for (identifier, path, created, modified) in std::mem::take(&mut self.backdated) {
+            if created >= recent_from && Severity::Medium >= min_severity {
+                self.findings.push(Finding {
+                    severity: Severity::Medium,
+                    rule: "backdated-executable",
+                    identifier,
+                    path,
+                    detail: format!(
+                        "created {} days after its modification time, within the last {} days of activity",
+                        (created - modified) / DAY,
+                        self.options.recent_days
+                    ),
+                });
+            }
+        }
+        self.findings.sort_by(|a, b| {
+            b.severity
+                .cmp(&a.severity)
+                .then_with(|| a.rule.cmp(b.rule))
+                .then_with(|| a.path.cmp(&b.path))
+        });
+        let count = |s: Severity| self.findings.iter().filter(|f| f.severity == s).count();
+        TriageReport {
+            high: count(Severity::High),
+            medium: count(Severity::Medium),
+            low: count(Severity::Low),
+            findings: self.findings,
+        }
+    }
+}
@@ -0,0 +1,1137 @@
+//! UBIFS volumes of raw NAND dumps and UBI images (`ubinize` output), or of bare UBIFS
+//! images (`mkfs.ubifs` output). UBI splits the flash in physical erase blocks, each
+//! starting with an erase counter header and a volume identifier header that tells the
+//! volume and logical erase block (LEB) it holds; the copy with the highest sequence
+//! number of each LEB wins. The volume table names the volumes; the first holding UBIFS
+//! is opened unless another one is selected with `select_volume`.
+//!
+//! UBIFS keeps every record in nodes: the superblock in LEB 0, the master node in LEBs 1
+//! and 2, then a B+ tree (the index) whose leaves are the inode, directory entry and data
+//! nodes, keyed by inode number, key type and name hash or block number. Nodes written
+//! since the last commit are only in the journal: the buds listed by the log are replayed
+//! over the index as the kernel does on mount. Records are identified by inode number
+//! (the root is 1). Data nodes are 4 KiB blocks, stored or compressed with LZO, zlib or
+//! zstd; missing ones are holes. Encrypted and authenticated volumes are not supported.
+use crate::compression;
+use crate::filesystem::{
+    BlockRun, ByteRange, DEVICE_KEY, DirectoryCommon, ExtendedAttribute, FLAGS_KEY, File,
+    FileCommon, Filesystem, SYMLINK_TARGET_KEY, namespaced_metadata, unix_ftype, unix_mode_string,
+};
+use crate::names::{escape_name, name_bytes, render_name};
+use crate::timefmt::format_timestamp;
+use serde::Serialize;
+use serde_json::{Value, json};
+use std::collections::{BTreeMap, HashMap};
+use std::error::Error;
+use std::io::{Read, Seek, SeekFrom};
+
+/// Namespace of the inode fields in `File.metadata`.
+pub const METADATA_NAMESPACE: &str = "ubifs";
+
+const EC_MAGIC: &[u8; 4] = b"UBI#";
+const VID_MAGIC: &[u8; 4] = b"UBI!";
+const VID_HEADER_SIZE: usize = 64;
+/// Smallest physical erase block: the step of the search for erase counter headers.
+const MIN_PEB_SIZE: u64 = 16 << 10;
+const LAYOUT_VOLUME: u32 = 0x7fff_efff;
+const VTABLE_RECORD: usize = 172;
+const MAX_VOLUMES: usize = 128;
+
+const NODE_MAGIC: u32 = 0x0610_1831;
+const COMMON_HEADER: usize = 24;
+const PADDING_BYTE: u8 = 0xce;
+const INO_NODE: u8 = 0;
+const DATA_NODE: u8 = 1;
+const DENT_NODE: u8 = 2;
+const XENT_NODE: u8 = 3;
+const TRUN_NODE: u8 = 4;
+const PAD_NODE: u8 = 5;
+const SB_NODE: u8 = 6;
+const MST_NODE: u8 = 7;
+const REF_NODE: u8 = 8;
+const IDX_NODE: u8 = 9;
+const CS_NODE: u8 = 10;
+const INO_HEADER: usize = 160;
+const DATA_HEADER: usize = 48;
+const DENT_HEADER: usize = 56;
+const LOG_LNUM: u32 = 3;
+const ROOT_INO: u32 = 1;
+const BLOCK_SIZE: u64 = 4096;
+/// Branch of an index node: LEB, offset, length, then the 8-byte key.
+const BRANCH_SIZE: usize = 20;
+const FLAG_ENCRYPTION: u32 = 0x10;
+const FLAG_AUTHENTICATION: u32 = 0x20;
+const XATTR_FLAG: u32 = 0x20;
+const MAX_INDEX_DEPTH: usize = 64;
+
+const INODE_FLAGS: [(u32, &str); 7] = [
+    (0x01, "compr"),
+    (0x02, "sync"),
+    (0x04, "immutable"),
+    (0x08, "append"),
+    (0x10, "dirsync"),
+    (0x20, "xattr"),
+    (0x40, "crypt"),
+];
+
+fn be_u16(bytes: &[u8], at: usize) -> u16 {
+    u16::from_be_bytes(bytes[at..at + 2].try_into().unwrap())
+}
+
+fn be_u32(bytes: &[u8], at: usize) -> u32 {
+    u32::from_be_bytes(bytes[at..at + 4].try_into().unwrap())
+}
+
+fn be_u64(bytes: &[u8], at: usize) -> u64 {
+    u64::from_be_bytes(bytes[at..at + 8].try_into().unwrap())
+}
+
+fn le_u16(bytes: &[u8], at: usize) -> u16 {
+    u16::from_le_bytes(bytes[at..at + 2].try_into().unwrap())
+}
+
+fn le_u32(bytes: &[u8], at: usize) -> u32 {
+    u32::from_le_bytes(bytes[at..at + 4].try_into().unwrap())
+}
+
+fn le_u64(bytes: &[u8], at: usize) -> u64 {
+    u64::from_le_bytes(bytes[at..at + 8].try_into().unwrap())
+}
+
+/// CRC-32 as UBI and UBIFS compute it: seeded with all ones, not inverted at the end.
+pub fn ubi_crc32(bytes: &[u8]) -> u32 {
+    let mut crc = !0u32;
+    for byte in bytes {
+        crc ^= *byte as u32;
+        for _ in 0..8 {
+            crc = (crc >> 1) ^ (0xedb8_8320 & (crc & 1).wrapping_neg());
+        }
+    }
+    crc
+}
+
+/// Key of the inode `inum` of type `kind` (0 inode, 1 data, 2 entry, 3 extended
+/// attribute) with its name hash or block number: ordered as the index orders them.
+fn key(inum: u32, kind: u32, value: u32) -> u64 {
+    (inum as u64) << 32 | (kind << 29 | (value & 0x1fff_ffff)) as u64
+}
+
+/// Key of a node, from its first 8 bytes after the common header.
+fn node_key(node: &[u8]) -> u64 {
+    key_of(&node[COMMON_HEADER..COMMON_HEADER + 8])
+}
+
+fn key_of(bytes: &[u8]) -> u64 {
+    (le_u32(bytes, 0) as u64) << 32 | le_u32(bytes, 4) as u64
+}
+
+/// Names of the inode flags set in `flags`.
+pub fn inode_flag_names(flags: u32) -> Vec<&'static str> {
+    INODE_FLAGS
+        .iter()
+        .filter(|(bit, _)| flags & bit != 0)
+        .map(|(_, name)| *name)
+        .collect()
+}
+
+/// Where a node is: LEB, offset and length.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+struct Location {
+    lnum: u32,
+    offs: u32,
+    len: u32,
+}
+
+/// Level and branches (first key, child) of an index node.
+type IndexNode = (u16, Vec<(u64, Location)>);
+/// Offset of a node in its LEB, and the node.
+type ScannedNode = (u32, Vec<u8>);
+/// Name, inode and type of a directory or extended attribute entry.
+type EntryRecord = (Vec<u8>, u32, u8);
+
+/// A volume of the UBI volume table.
+#[derive(Debug, Clone, Serialize)]
+pub struct UbiVolume {
+    pub id: u32,
+    pub name: String,
+    /// `dynamic` or `static`.
+    pub kind: &'static str,
+    pub reserved_pebs: u32,
+    pub mapped_lebs: usize,
+    pub ubifs: bool,
+}
+
+/// UBI layer of an image: erase block geometry and where each LEB of each volume is.
+struct Ubi {
+    peb_size: u64,
+    data_offset: u64,
+    image_seq: u32,
+    volumes: Vec<UbiVolume>,
+    /// Physical erase block and sequence number of each LEB, by volume.
+    lebs: HashMap<u32, HashMap<u32, (u64, u64)>>,
+}
+
+impl Ubi {
+    /// The UBI layer of `body`, `None` when it does not start with an erase counter
+    /// header.
+    fn scan<T: Read + Seek>(body: &mut T) -> Result<Option<Self>, Box<dyn Error>> {
+        let size = body.seek(SeekFrom::End(0))?;
+        let mut header = [0u8; 64];
+        body.seek(SeekFrom::Start(0))?;
+        if body.read_exact(&mut header).is_err() || &header[..4] != EC_MAGIC {
+            return Ok(None);
+        }
+        if ubi_crc32(&header[..60]) != be_u32(&header, 60) {
+            return Err("bad UBI erase counter header CRC".into());
+        }
+        let (vid_offset, data_offset) = (be_u32(&header, 16) as u64, be_u32(&header, 20) as u64);
+        let image_seq = be_u32(&header, 24);
+        let peb_size = Self::peb_size(body, size)?;
+        if data_offset >= peb_size || vid_offset + VID_HEADER_SIZE as u64 > data_offset {
+            return Err("bad UBI header offsets".into());
+        }
+
+        let mut lebs: HashMap<u32, HashMap<u32, (u64, u64)>> = HashMap::new();
+        let mut vid = [0u8; VID_HEADER_SIZE];
+        for peb in 0..size / peb_size {
+            body.seek(SeekFrom::Start(peb * peb_size + vid_offset))?;
+            body.read_exact(&mut vid)?;
+            if &vid[..4] != VID_MAGIC || ubi_crc32(&vid[..60]) != be_u32(&vid, 60) {
+                continue;
+            }
+            let (volume, lnum, sqnum) = (be_u32(&vid, 8), be_u32(&vid, 12), be_u64(&vid, 40));
+            let slot = lebs
+                .entry(volume)
+                .or_default()
+                .entry(lnum)
+                .or_insert((peb, sqnum));
+            if sqnum > slot.1 {
+                *slot = (peb, sqnum);
+            }
+        }
+        let mut ubi = Self {
+            peb_size,
+            data_offset,
+            image_seq,
+            volumes: Vec::new(),
+            lebs,
+        };
+        ubi.volumes = ubi.volume_table(body)?;
+        Ok(Some(ubi))
+    }
+
+    /// Erase block size: the smallest gap between two erase counter headers, which each
+    /// erase block but the erased ones starts with.
+    fn peb_size<T: Read + Seek>(body: &mut T, size: u64) -> Result<u64, Box<dyn Error>> {
+        let mut header = [0u8; 64];
+        let (mut previous, mut smallest) = (0, u64::MAX);
+        for offset in (MIN_PEB_SIZE..size).step_by(MIN_PEB_SIZE as usize) {
+            body.seek(SeekFrom::Start(offset))?;
+            if body.read_exact(&mut header).is_err() {
+                break;
+            }
+            if &header[..4] == EC_MAGIC && ubi_crc32(&header[..60]) == be_u32(&header, 60) {
+                smallest = smallest.min(offset - previous);
+                previous = offset;
+            }
+        }
+        if smallest == u64::MAX {
+            return Err("only one UBI erase counter header".into());
+        }
+        Ok(smallest)
+    }
+
+    fn volume_table<T: Read + Seek>(&self, body: &mut T) -> Result<Vec<UbiVolume>, Box<dyn Error>> {
+        let layout = self.lebs.get(&LAYOUT_VOLUME).ok_or("no UBI volume table")?;
+        let (peb, _) = layout
+            .get(&0)
+            .or_else(|| layout.get(&1))
+            .ok_or("no UBI volume table")?;
+        let mut table = vec![0u8; VTABLE_RECORD * MAX_VOLUMES];
+        body.seek(SeekFrom::Start(peb * self.peb_size + self.data_offset))?;
+        body.read_exact(&mut table)?;
+        let mut volumes = Vec::new();
+        for (id, record) in table.chunks_exact(VTABLE_RECORD).enumerate() {
+            let name_length = (be_u16(record, 14) as usize).min(127);
+            if be_u32(record, 0) == 0 || name_length == 0 {
+                continue;
+            }
+            volumes.push(UbiVolume {
+                id: id as u32,
+                name: String::from_utf8_lossy(&record[16..16 + name_length]).into_owned(),
+                kind: if record[12] == 2 { "static" } else { "dynamic" },
+                reserved_pebs: be_u32(record, 0),
+                mapped_lebs: self.lebs.get(&(id as u32)).map_or(0, HashMap::len),
+                ubifs: false,
+            });
+        }
+        Ok(volumes)
+    }
+
+    /// Byte offset of `offs` in LEB `lnum` of `volume`, `None` when the LEB is unmapped.
+    fn offset(&self, volume: u32, lnum: u32, offs: u32) -> Option<u64> {
+        let (peb, _) = self.lebs.get(&volume)?.get(&lnum)?;
+        Some(peb * self.peb_size + self.data_offset + offs as u64)
+    }
+}
+
+#[derive(Debug, Clone, Default, Serialize)]
+pub struct Superblock {
+    pub key_hash: u8,
+    pub key_format: u8,
+    pub flags: u32,
+    pub min_io_size: u32,
+    pub leb_size: u32,
+    pub leb_count: u32,
+    pub max_leb_count: u32,
+    pub log_lebs: u32,
+    pub lpt_lebs: u32,
+    pub orphan_lebs: u32,
+    pub journal_heads: u32,
+    pub fanout: u32,
+    pub format_version: u32,
+    pub default_compressor: u16,
+    pub time_granularity: u32,
+    pub uuid: String,
+}
+
+#[derive(Debug, Clone, Default, Serialize)]
+pub struct Master {
+    pub sqnum: u64,
+    pub highest_inum: u64,
+    pub commit_number: u64,
+    pub flags: u32,
+    pub log_lnum: u32,
+    pub root_lnum: u32,
+    pub root_offs: u32,
+    pub root_len: u32,
+    pub index_size: u64,
+    pub total_free: u64,
+    pub total_dirty: u64,
+    pub total_used: u64,
+}
+
+/// An inode.
+#[derive(Debug, Clone, Serialize)]
+pub struct UbifsInode {
+    pub inum: u32,
+    pub creation_sqnum: u64,
+    pub size: u64,
+    pub atime: u64,
+    pub ctime: u64,
+    pub mtime: u64,
+    pub nlink: u32,
+    pub uid: u32,
+    pub gid: u32,
+    pub mode: u32,
+    pub flags: u32,
+    pub xattr_count: u32,
+    pub compressor: u16,
+    /// Symbolic link target or device number, kept in the inode node.
+    #[serde(skip)]
+    pub inline: Vec<u8>,
+}
+
+impl UbifsInode {
+    fn parse(node: &[u8]) -> Result<Self, Box<dyn Error>> {
+        if node.len() < INO_HEADER || node[20] != INO_NODE {
+            return Err("not a UBIFS inode node".into());
+        }
+        let data_len = le_u32(node, 112) as usize;
+        Ok(Self {
+            inum: le_u32(node, 24),
+            creation_sqnum: le_u64(node, 40),
+            size: le_u64(node, 48),
+            atime: le_u64(node, 56),
+            ctime: le_u64(node, 64),
+            mtime: le_u64(node, 72),
+            nlink: le_u32(node, 92),
+            uid: le_u32(node, 96),
+            gid: le_u32(node, 100),
+            mode: le_u32(node, 104),
+            flags: le_u32(node, 108),
+            xattr_count: le_u32(node, 116),
+            compressor: le_u16(node, 132),
+            inline: node
+                .get(INO_HEADER..INO_HEADER + data_len)
+                .ok_or("truncated UBIFS inode node")?
+                .to_vec(),
+        })
+    }
+}
+
+impl FileCommon for UbifsInode {
+    fn id(&self) -> u64 {
+        self.inum as u64
+    }
+    fn size(&self) -> u64 {
+        self.size
+    }
+    fn is_dir(&self) -> bool {
+        self.mode & 0o170000 == 0o040000
+    }
+    fn to_string(&self) -> String {
+        format!(
+            "UbifsInode {{ inum: {}, mode: {:o}, size: {} }}",
+            self.inum, self.mode, self.size
+        )
+    }
+    fn to_json(&self) -> Value {
+        serde_json::to_value(self).unwrap_or(Value::Null)
+    }
+}
+
+#[derive(Debug, Clone)]
+pub struct UbifsDirEntry {
+    pub inum: u32,
+    pub name: String,
+    /// Inode type: 0 regular, 1 directory, 2 link, 3 block, 4 character, 5 FIFO, 6 socket.
+    pub kind: u8,
+}
+
+impl DirectoryCommon for UbifsDirEntry {
+    fn file_id(&self) -> u64 {
+        self.inum as u64
+    }
+    fn name(&self) -> &str {
+        &self.name
+    }
+    fn to_string(&self) -> String {
+        format!(
+            "UbifsDirEntry {{ inum: {}, name: {} }}",
+            self.inum, self.name
+        )
+    }
+    fn to_json(&self) -> Value {
+        json!({ "inum": self.inum, "name": self.name, "type": self.kind })
+    }
+}
+
+/// Nodes of the journal, replayed over the index: by key, and by name for entries.
+/// `None` removes what the index holds.
+#[derive(Default)]
+struct Journal {
+    nodes: BTreeMap<(u64, Vec<u8>), Option<Location>>,
+    /// First block of each inode dropped from the index by a truncation.
+    truncated: HashMap<u32, u64>,
+}
+
+pub struct UbifsFS<T: Read + Seek> {
+    body: T,
+    ubi: Option<Ubi>,
+    /// Selected UBI volume.
+    volume: u32,
+    superblock: Superblock,
+    master: Master,
+    journal: Journal,
+    /// Branches of the index nodes read so far.
+    index: HashMap<(u32, u32), IndexNode>,
+}
+
+impl<T: Read + Seek> UbifsFS<T> {
+    pub fn new(mut body: T) -> Result<Self, Box<dyn Error>> {
+        let ubi = Ubi::scan(&mut body)?;
+        let mut fs = Self {
+            body,
+            ubi,
+            volume: 0,
+            superblock: Superblock::default(),
+            master: Master::default(),
+            journal: Journal::default(),
+            index: HashMap::new(),
+        };
+        let Some(ubi) = &fs.ubi else {
+            fs.open_volume()?;
+            return Ok(fs);
+        };
+        let ids: Vec<u32> = ubi.volumes.iter().map(|v| v.id).collect();
+        let mut first = None;
+        for (n, id) in ids.into_iter().enumerate() {
+            fs.volume = id;
+            let ubifs = fs.read_node(0, 0).is_ok_and(|node| node[20] == SB_NODE);
+            if let Some(ubi) = fs.ubi.as_mut() {
+                ubi.volumes[n].ubifs = ubifs;
+            }
+            if ubifs && first.is_none() {
+                first = Some(id);
+            }
+        }
+        fs.volume = first.ok_or("no UBIFS volume in the UBI image")?;
+        fs.open_volume()?;
+        Ok(fs)
+    }
+
+    /// Browse the UBI volume named `name` instead.
+    pub fn select_volume(&mut self, name: &str) -> Result<(), Box<dyn Error>> {
+        let ubi = self
+            .ubi
+            .as_ref()
+            .ok_or("not a UBI image: it holds one volume")?;
+        let volume = ubi
+            .volumes
+            .iter()
+            .find(|v| v.name == name)
+            .ok_or_else(|| format!("no UBI volume named '{}'", name))?;
+        if !volume.ubifs {
+            return Err(format!("UBI volume '{}' does not hold UBIFS", name).into());
+        }
+        self.volume = volume.id;
+        self.open_volume()
+    }
+
+    /// Volumes of the UBI image, empty for a bare UBIFS image.
+    pub fn volumes(&self) -> &[UbiVolume] {
+        self.ubi.as_ref().map_or(&[], |u| &u.volumes)
+    }
+
+    /// Read the superblock and master node of the selected volume and replay its journal.
+    fn open_volume(&mut self) -> Result<(), Box<dyn Error>> {
+        self.index.clear();
+        self.journal = Journal::default();
+        let sb = self.read_node(0, 0)?;
+        if sb[20] != SB_NODE || sb.len() < 128 {
+            return Err("no UBIFS superblock node".into());
+        }
+        self.superblock = Superblock {
+            key_hash: sb[26],
+            key_format: sb[27],
+            flags: le_u32(&sb, 28),
+            min_io_size: le_u32(&sb, 32),
+            leb_size: le_u32(&sb, 36),
+            leb_count: le_u32(&sb, 40),
+            max_leb_count: le_u32(&sb, 44),
+            log_lebs: le_u32(&sb, 56),
+            lpt_lebs: le_u32(&sb, 60),
+            orphan_lebs: le_u32(&sb, 64),
+            journal_heads: le_u32(&sb, 68),
+            fanout: le_u32(&sb, 72),
+            format_version: le_u32(&sb, 80),
+            default_compressor: le_u16(&sb, 84),
+            time_granularity: le_u32(&sb, 104),
+            uuid: hex::encode(&sb[108..124]),
+        };
+        if self.superblock.flags & (FLAG_ENCRYPTION | FLAG_AUTHENTICATION) != 0 {
+            return Err("encrypted and authenticated UBIFS volumes are not supported".into());
+        }
+        if self.superblock.key_format != 0 {
+            return Err("unsupported UBIFS key format".into());
+        }
+
+        let mut master: Option<Vec<u8>> = None;
+        for lnum in [1, 2] {
+            for (_, node) in self.scan_leb(lnum, 0, false)? {
+                if node[20] == MST_NODE
+                    && node.len() >= 120
+                    && master
+                        .as_ref()
+                        .is_none_or(|m| le_u64(&node, 8) > le_u64(m, 8))
+                {
+                    master = Some(node);
+                }
+            }
+        }
+        let m = master.ok_or("no UBIFS master node")?;
+        self.master = Master {
+            sqnum: le_u64(&m, 8),
+            highest_inum: le_u64(&m, 24),
+            commit_number: le_u64(&m, 32),
+            flags: le_u32(&m, 40),
+            log_lnum: le_u32(&m, 44),
+            root_lnum: le_u32(&m, 48),
+            root_offs: le_u32(&m, 52),
+            root_len: le_u32(&m, 56),
+            index_size: le_u64(&m, 72),
+            total_free: le_u64(&m, 80),
+            total_dirty: le_u64(&m, 88),
+            total_used: le_u64(&m, 96),
+        };
+        self.replay()
+    }
+
+    pub fn superblock(&self) -> &Superblock {
+        &self.superblock
+    }
+
+    pub fn master(&self) -> &Master {
+        &self.master
+    }
+
+    /// `length` bytes at `offs` of LEB `lnum`; unmapped LEBs read as erased flash.
+    fn read_leb(&mut self, lnum: u32, offs: u32, length: usize) -> Result<Vec<u8>, Box<dyn Error>> {
+        let offset = match &self.ubi {
+            Some(ubi) => match ubi.offset(self.volume, lnum, offs) {
+                Some(offset) => offset,
+                None => return Ok(vec![0xff; length]),
+            },
+            None => lnum as u64 * self.superblock.leb_size as u64 + offs as u64,
+        };
+        let mut data = vec![0u8; length];
+        self.body.seek(SeekFrom::Start(offset))?;
+        self.body.read_exact(&mut data)?;
+        Ok(data)
+    }
+
+    /// The node at `offs` of LEB `lnum`.
+    fn read_node(&mut self, lnum: u32, offs: u32) -> Result<Vec<u8>, Box<dyn Error>> {
+        let header = self.read_leb(lnum, offs, COMMON_HEADER)?;
+        if le_u32(&header, 0) != NODE_MAGIC {
+            return Err(format!("no UBIFS node at {}:{}", lnum, offs).into());
+        }
+        let length = le_u32(&header, 16) as usize;
+        if length < COMMON_HEADER
+            || self.superblock.leb_size != 0 && length > self.superblock.leb_size as usize
+        {
+            return Err(format!("bad UBIFS node length {} at {}:{}", length, lnum, offs).into());
+        }
+        self.read_leb(lnum, offs, length)
+    }
+
+    /// Nodes of LEB `lnum` from `offs` until erased space or, when `checked`, a node
+    /// failing its CRC: the end of what was written.
+    fn scan_leb(
+        &mut self,
+        lnum: u32,
+        mut offs: u32,
+        checked: bool,
+    ) -> Result<Vec<ScannedNode>, Box<dyn Error>> {
+        let leb_size = self.superblock.leb_size;
+        let data = self.read_leb(lnum, 0, leb_size as usize)?;
+        let mut nodes = Vec::new();
+        while (offs as usize) + COMMON_HEADER <= data.len() {
+            let at = offs as usize;
+            if data[at] == PADDING_BYTE {
+                offs += 1;
+                continue;
+            }
+            if le_u32(&data, at) != NODE_MAGIC {
+                break;
+            }
+            let length = le_u32(&data, at + 16) as usize;
+            let Some(node) = data
+                .get(at..at + length)
+                .filter(|_| length >= COMMON_HEADER)
+            else {
+                break;
+            };
+            if checked && ubi_crc32(&node[8..]) != le_u32(node, 4) {
+                break;
+            }
+            if node[20] == PAD_NODE {
+                offs += (length + le_u32(node, 24) as usize) as u32;
+                continue;
+            }
+            offs += length.next_multiple_of(8) as u32;
+            nodes.push((at as u32, node.to_vec()));
+        }
+        Ok(nodes)
+    }
+
+    /// Replay the buds of the log over the index, in sequence number order.
+    fn replay(&mut self) -> Result<(), Box<dyn Error>> {
+        let log_lebs = self.superblock.log_lebs.max(1);
+        if !(LOG_LNUM..LOG_LNUM + log_lebs).contains(&self.master.log_lnum) {
+            return Err("UBIFS master node log LEB outside the log".into());
+        }
+        let mut buds = Vec::new();
+        let mut started = false;
+        'log: for n in 0..log_lebs {
+            let lnum = LOG_LNUM + (self.master.log_lnum - LOG_LNUM + n) % log_lebs;
+            for (_, node) in self.scan_leb(lnum, 0, true)? {
+                match node[20] {
+                    CS_NODE if !started => {
+                        if le_u64(&node, 24) != self.master.commit_number {
+                            return Ok(());
+                        }
+                        started = true;
+                    }
+                    CS_NODE => break 'log,
+                    REF_NODE if started => buds.push((le_u32(&node, 24), le_u32(&node, 28))),
+                    _ => {}
+                }
+            }
+        }
+
+        let mut nodes = Vec::new();
+        for (lnum, offs) in buds {
+            for (at, node) in self.scan_leb(lnum, offs, true)? {
+                let location = Location {
+                    lnum,
+                    offs: at,
+                    len: node.len() as u32,
+                };
+                nodes.push((le_u64(&node, 8), location, node));
+            }
+        }
+        nodes.sort_by_key(|(sqnum, _, _)| *sqnum);
+        for (_, location, node) in nodes {
+            match node[20] {
+                INO_NODE | DATA_NODE if node.len() >= COMMON_HEADER + 8 => {
+                    self.journal
+                        .nodes
+                        .insert((node_key(&node), Vec::new()), Some(location));
+                }
+                DENT_NODE | XENT_NODE if node.len() >= DENT_HEADER => {
+                    let name_length = le_u16(&node, 50) as usize;
+                    let name =
+                        node[DENT_HEADER..(DENT_HEADER + name_length).min(node.len())].to_vec();
+                    let target = (le_u64(&node, 40) != 0).then_some(location);
+                    self.journal.nodes.insert((node_key(&node), name), target);
+                }
+                TRUN_NODE if node.len() >= 56 => {
+                    let inum = le_u32(&node, 24);
+                    let first = le_u64(&node, 48).div_ceil(BLOCK_SIZE);
+                    let (from, to) = (key(inum, 1, first as u32), key(inum, 1, 0x1fff_ffff));
+                    self.journal
+                        .nodes
+                        .retain(|(k, _), _| !(from..=to).contains(k));
+                    let limit = self.journal.truncated.entry(inum).or_insert(first);
+                    *limit = (*limit).min(first);
+                }
+                _ => {}
+            }
+        }
+        Ok(())
+    }
+
+    /// Branches of the index node at `location`, and its level.
+    fn index_node(&mut self, location: Location) -> Result<IndexNode, Box<dyn Error>> {
+        if let Some(node) = self.index.get(&(location.lnum, location.offs)) {
+            return Ok(node.clone());
+        }
+        let node = self.read_node(location.lnum, location.offs)?;
+        if node[20] != IDX_NODE || node.len() < 28 {
+            return Err(
+                format!("no UBIFS index node at {}:{}", location.lnum, location.offs).into(),
+            );
+        }
+        let (count, level) = (le_u16(&node, 24) as usize, le_u16(&node, 26));
+        let mut branches = Vec::with_capacity(count);
+        for n in 0..count {
+            let at = 28 + n * BRANCH_SIZE;
+            let branch = node
+                .get(at..at + BRANCH_SIZE)
+                .ok_or("truncated UBIFS index node")?;
+            branches.push((
+                key_of(&branch[12..20]),
+                Location {
+                    lnum: le_u32(branch, 0),
+                    offs: le_u32(branch, 4),
+                    len: le_u32(branch, 8),
+                },
+            ));
+        }
+        let parsed = (level, branches);
+        self.index
+            .insert((location.lnum, location.offs), parsed.clone());
+        Ok(parsed)
+    }
+
+    /// Leaf nodes of the index whose key is within `from..=to`.
+    fn index_range(&mut self, from: u64, to: u64) -> Result<Vec<(u64, Location)>, Box<dyn Error>> {
+        let mut out = Vec::new();
+        let root = Location {
+            lnum: self.master.root_lnum,
+            offs: self.master.root_offs,
+            len: self.master.root_len,
+        };
+        self.collect_range(root, from, to, 0, &mut out)?;
+        Ok(out)
+    }
+
+    fn collect_range(
+        &mut self,
+        location: Location,
+        from: u64,
+        to: u64,
+        depth: usize,
+        out: &mut Vec<(u64, Location)>,
+    ) -> Result<(), Box<dyn Error>> {
+        if depth > MAX_INDEX_DEPTH {
+            return Err("UBIFS index too deep".into());
+        }
+        let (level, branches) = self.index_node(location)?;
+        for (n, (branch_key, child)) in branches.iter().enumerate() {
+            if *branch_key > to {
+                break;
+            }
+            if level == 0 {
+                if *branch_key >= from {
+                    out.push((*branch_key, *child));
+                }
+                continue;
+            }
+            // Entries sharing a hash may straddle two children.
+            if branches.get(n + 1).is_none_or(|(next, _)| *next >= from) {
+                self.collect_range(*child, from, to, depth + 1, out)?;
+            }
+        }
+        Ok(())
+    }
+
+    /// The node of key `k`, from the journal or the index.
+    fn lookup(&mut self, k: u64) -> Result<Option<Vec<u8>>, Box<dyn Error>> {
+        let location = match self.journal.nodes.get(&(k, Vec::new())) {
+            Some(location) => *location,
+            None => self.index_range(k, k)?.first().map(|(_, l)| *l),
+        };
+        location.map(|l| self.read_node(l.lnum, l.offs)).transpose()
+    }
+
+    /// Directory or extended attribute entries (`kind` 2 or 3) of `inum`: name, target
+    /// inode and type.
+    fn entries(&mut self, inum: u32, kind: u32) -> Result<Vec<EntryRecord>, Box<dyn Error>> {
+        let (from, to) = (key(inum, kind, 0), key(inum, kind, 0x1fff_ffff));
+        let mut locations: BTreeMap<(u64, Vec<u8>), Location> = BTreeMap::new();
+        for (k, location) in self.index_range(from, to)? {
+            let node = self.read_node(location.lnum, location.offs)?;
+            if node.len() < DENT_HEADER {
+                continue;
+            }
+            let name_length = le_u16(&node, 50) as usize;
+            let name = node[DENT_HEADER..(DENT_HEADER + name_length).min(node.len())].to_vec();
+            locations.insert((k, name), location);
+        }
+        let journal: Vec<_> = self
+            .journal
+            .nodes
+            .range((from, Vec::new())..=(to, vec![0xff; 256]))
+            .map(|(k, l)| (k.clone(), *l))
+            .collect();
+        for (k, location) in journal {
+            match location {
+                Some(location) => locations.insert(k, location),
+                None => locations.remove(&k),
+            };
+        }
+        let mut entries = Vec::with_capacity(locations.len());
+        for ((_, name), location) in locations {
+            let node = self.read_node(location.lnum, location.offs)?;
+            let target = le_u64(&node, 40) as u32;
+            if target != 0 {
+                entries.push((name, target, node[49]));
+            }
+        }
+        Ok(entries)
+    }
+
+    /// Content of the data node `node`, decompressed to its block.
+    fn data_block(&self, node: &[u8]) -> Result<Vec<u8>, Box<dyn Error>> {
+        if node.len() < DATA_HEADER {
+            return Err("truncated UBIFS data node".into());
+        }
+        let size = le_u32(node, 40) as usize;
+        let data = &node[DATA_HEADER..];
+        if size as u64 > BLOCK_SIZE {
+            return Err("UBIFS data node larger than a block".into());
+        }
+        match le_u16(node, 44) {
+            0 => Ok(data[..size.min(data.len())].to_vec()),
+            1 => compression::lzo1x(data, size),
+            2 => compression::deflate(data, size),
+            3 => compression::zstd(data, size),
+            other => Err(format!("unknown UBIFS compressor {}", other).into()),
+        }
+    }
+
+    /// Data nodes of `inode` covering `first..=last`, by block.
+    fn data_nodes(
+        &mut self,
+        inode: &UbifsInode,
+        first: u64,
+        last: u64,
+    ) -> Result<BTreeMap<u64, Location>, Box<dyn Error>> {
+        let (from, to) = (
+            key(inode.inum, 1, first as u32),
+            key(inode.inum, 1, last.min(0x1fff_ffff) as u32),
+        );
+        let limit = self.journal.truncated.get(&inode.inum).copied();
+        let mut blocks: BTreeMap<u64, Location> = self
+            .index_range(from, to)?
+            .into_iter()
+            .map(|(k, l)| ((k & 0x1fff_ffff), l))
+            .filter(|(block, _)| limit.is_none_or(|limit| *block < limit))
+            .collect();
+        for ((k, _), location) in self
+            .journal
+            .nodes
+            .range((from, Vec::new())..=(to, Vec::new()))
+        {
+            if let Some(location) = location {
+                blocks.insert(k & 0x1fff_ffff, *location);
+            }
+        }
+        Ok(blocks)
+    }
+
+    fn read_content(
+        &mut self,
+        inode: &UbifsInode,
+        offset: u64,
+        length: usize,
+    ) -> Result<Vec<u8>, Box<dyn Error>> {
+        if offset >= inode.size || length == 0 || inode.is_dir() {
+            return Ok(Vec::new());
+        }
+        let end = inode.size.min(offset + length as u64);
+        let (first, last) = (offset / BLOCK_SIZE, (end - 1) / BLOCK_SIZE);
+        let mut out = vec![0u8; (end - offset) as usize];
+        for (block, location) in self.data_nodes(inode, first, last)? {
+            let node = self.read_node(location.lnum, location.offs)?;
+            let data = self.data_block(&node)?;
+            let start = block * BLOCK_SIZE;
+            let (from, to) = (start.max(offset), (start + data.len() as u64).min(end));
+            if from < to {
+                out[(from - offset) as usize..(to - offset) as usize]
+                    .copy_from_slice(&data[(from - start) as usize..(to - start) as usize]);
+            }
+        }
+        Ok(out)
+    }
+}
+
+impl<T: Read + Seek> Filesystem for UbifsFS<T> {
+    type FileType = UbifsInode;
+    type DirectoryType = UbifsDirEntry;
+
+    fn filesystem_type(&self) -> String {
+        "UBIFS".to_string()
+    }
+
+    fn path_separator(&self) -> String {
+        "/".to_string()
+    }
+
+    fn record_count(&mut self) -> u64 {
+        self.master.highest_inum
+    }
+
+    fn block_size(&self) -> u64 {
+        BLOCK_SIZE
+    }
+
+    fn get_metadata(&self) -> Result<Value, Box<dyn Error>> {
+        let mut metadata = json!({
+            "superblock": self.superblock,
+            "master": self.master,
+        });
+        if let Some(ubi) = &self.ubi {
+            metadata["ubi"] = json!({
+                "peb_size": ubi.peb_size,
+                "data_offset": ubi.data_offset,
+                "image_seq": ubi.image_seq,
+                "volumes": ubi.volumes,
+                "selected_volume": self.volume,
+            });
+        }
+        Ok(metadata)
+    }
+
+    fn get_metadata_pretty(&self) -> Result<String, Box<dyn Error>> {
+        let (sb, m) = (&self.superblock, &self.master);
+        let mut out = String::new();
+        if let Some(ubi) = &self.ubi {
+            out.push_str(&format!(
+                "UBI image: {} byte erase blocks, data at {}, image sequence {:#x}\n",
+                ubi.peb_size, ubi.data_offset, ubi.image_seq
+            ));
+            for v in &ubi.volumes {
+                out.push_str(&format!(
+                    "  volume {} '{}': {}, {} LEBs mapped{}{}\n",
+                    v.id,
+                    v.name,
+                    v.kind,
+                    v.mapped_lebs,
+                    if v.ubifs { ", UBIFS" } else { "" },
+                    if v.id == self.volume {
+                        " (selected)"
+                    } else {
+                        ""
+                    }
+                ));
+            }
+        }
+        out.push_str(&format!(
+            "UBIFS format {} ({})\n\
+             LEBs: {} of {} bytes, min I/O {}, fanout {}\n\
+             Commit {}, highest inode {}, index {} bytes\n",
+            sb.format_version,
+            sb.uuid,
+            sb.leb_count,
+            sb.leb_size,
+            sb.min_io_size,
+            sb.fanout,
+            m.commit_number,
+            m.highest_inum,
+            m.index_size
+        ));
+        Ok(out)
+    }
+
+    fn get_file(&mut self, inum: u64) -> Result<Self::FileType, Box<dyn Error>> {
+        let inum = u32::try_from(inum).map_err(|_| format!("bad UBIFS inode {}", inum))?;
+        let node = self
+            .lookup(key(inum, 0, 0))?
+            .ok_or_else(|| format!("no UBIFS inode {}", inum))?;
+        let inode = UbifsInode::parse(&node)?;
+        if inode.nlink == 0 {
+            return Err(format!("UBIFS inode {} is deleted", inum).into());
+        }
+        Ok(inode)
+    }
+
+    fn read_file_content(&mut self, inode: &Self::FileType) -> Result<Vec<u8>, Box<dyn Error>> {
+        self.read_content(inode, 0, inode.size as usize)
+    }
+
+    fn read_file_prefix(
+        &mut self,
+        inode: &Self::FileType,
+        length: usize,
+    ) -> Result<Vec<u8>, Box<dyn Error>> {
+        self.read_content(inode, 0, length)
+    }
+
+    fn read_file_slice(
+        &mut self,
+        inode: &Self::FileType,
+        offset: u64,
+        length: usize,
+    ) -> Result<Vec<u8>, Box<dyn Error>> {
+        self.read_content(inode, offset, length)
+    }
+
+    fn list_dir(
+        &mut self,
+        inode: &Self::FileType,
+    ) -> Result<Vec<Self::DirectoryType>, Box<dyn Error>> {
+        if !inode.is_dir() {
+            return Err("not a directory".into());
+        }
+        Ok(self
+            .entries(inode.inum, DENT_NODE as u32)?
+            .into_iter()
+            .map(|(name, inum, kind)| UbifsDirEntry {
+                inum,
+                name: escape_name(&name),
+                kind,
+            })
+            .collect())
+    }
+
+    fn record_to_file(&self, inode: &Self::FileType, file_id: u64, absolute_path: &str) -> File {
+        let file_type = unix_ftype(inode.mode);
+        let mut common = json!({ FLAGS_KEY: inode_flag_names(inode.flags) });
+        if matches!(file_type, "chardev" | "blockdev") && inode.inline.len() >= 4 {
+            let rdev = le_u32(&inode.inline, 0);
+            common[DEVICE_KEY] = json!({
+                "major": (rdev & 0xfff00) >> 8,
+                "minor": (rdev & 0xff) | ((rdev >> 12) & 0xfff00),
+            });
+        }
+        if file_type == "symlink" {
+            common[SYMLINK_TARGET_KEY] = json!(render_name(&escape_name(&inode.inline)));
+        }
+        let metadata = namespaced_metadata(METADATA_NAMESPACE, inode.to_json(), common);
+        let name = match absolute_path.rsplit_once('/') {
+            Some((_, name)) if !name.is_empty() => name,
+            _ => absolute_path,
+        };
+        let absolute_path = render_name(absolute_path);
+        let permissions = unix_mode_string(inode.mode);
+        File {
+            id: None,
+            identifier: file_id,
+            absolute_path: absolute_path.to_string(),
+            name: render_name(name).into_owned(),
+            raw_name: name_bytes(name).map(hex::encode),
+            ftype: file_type.to_string(),
+            size: inode.size,
+            size_on_disk: None,
+            created: None,
+            modified: Some(inode.mtime),
+            accessed: Some(inode.atime),
+            changed: Some(inode.ctime),
+            permissions: Some(permissions.clone()),
+            owner: Some(inode.uid.to_string()),
+            group: Some(inode.gid.to_string()),
+            display: Some(format!(
+                "[{}] - {} {} {} {} {:>5} {} {}",
+                file_id,
+                permissions,
+                inode.nlink,
+                inode.uid,
+                inode.gid,
+                inode.size,
+                format_timestamp(inode.mtime),
+                absolute_path
+            )),
+            sig_name: None,
+            sig_mime: None,
+            sig_exts: None,
+            detected_type: None,
+            ext_mismatch: None,
+            md5: None,
+            sha1: None,
+            sha256: None,
+            metadata,
+        }
+    }
+
+    fn get_root_file_id(&self) -> u64 {
+        ROOT_INO as u64
+    }
+
+    /// Data nodes are placed anywhere in the LEBs and compressed: no block runs.
+    fn file_block_runs(
+        &mut self,
+        _inode: &Self::FileType,
+    ) -> Result<Option<Vec<BlockRun>>, Box<dyn Error>> {
+        Ok(None)
+    }
+
+    fn file_holes(
+        &mut self,
+        inode: &Self::FileType,
+    ) -> Result<Option<Vec<ByteRange>>, Box<dyn Error>> {
+        if inode.is_dir() || inode.size == 0 {
+            return Ok(Some(Vec::new()));
+        }
+        let last = (inode.size - 1) / BLOCK_SIZE;
+        let mut holes: Vec<ByteRange> = Vec::new();
+        let mut position = 0;
+        let blocks: Vec<u64> = self.data_nodes(inode, 0, last)?.into_keys().collect();
+        for start in blocks
+            .into_iter()
+            .map(|b| b * BLOCK_SIZE)
+            .chain([inode.size])
+        {
+            let start = start.min(inode.size);
+            if start > position {
+                holes.push((position, start - position));
+            }
+            position = position.max(start + BLOCK_SIZE);
+        }
+        Ok(Some(holes))
+    }
+
+    /// From the extended attribute entries, whose inodes hold the values.
+    fn extended_attributes(
+        &mut self,
+        inode: &Self::FileType,
+    ) -> Result<Vec<ExtendedAttribute>, Box<dyn Error>> {
+        if inode.xattr_count == 0 {
+            return Ok(Vec::new());
+        }
+        let mut attributes = Vec::new();
+        for (name, inum, _) in self.entries(inode.inum, XENT_NODE as u32)? {
+            let Some(node) = self.lookup(key(inum, 0, 0))? else {
+                continue;
+            };
+            let value = UbifsInode::parse(&node)?;
+            if value.flags & XATTR_FLAG != 0 {
+                attributes.push((String::from_utf8_lossy(&name).into_owned(), value.inline));
+            }
+        }
+        Ok(attributes)
+    }
+}
@@ -0,0 +1,967 @@
+//! UDF volumes (ECMA-167, OSTA UDF 1.02 to 2.60), as written on DVDs, Blu-ray discs and
+//! some USB media. The anchor volume descriptor pointer at sector 256 leads to the volume
+//! descriptor sequence, which describes the partitions and the logical volume; the file
+//! set descriptor of the logical volume gives the root directory.
+//!
+//! Records are identified by the location of their file entry (ICB): the partition
+//! reference number shifted left 32 bits, plus the logical block in that partition.
+//! Type 1 partition maps and the metadata partition of UDF 2.50 and later (Blu-ray) are
+//! supported; sparable and virtual (VAT) partitions of rewritable and incrementally
+//! written media are not.
+use crate::filesystem::{
+    BlockRun, ByteRange, DEVICE_KEY, DirectoryCommon, FLAGS_KEY, File, FileCommon, Filesystem,
+    SYMLINK_TARGET_KEY, namespaced_metadata, unix_ftype, unix_mode_string,
+};
+use crate::names::{name_bytes, render_name};
+use crate::timefmt::{format_timestamp, local_time_policy};
+use jiff::civil::DateTime;
+use jiff::tz::Offset;
+use serde::Serialize;
+use serde_json::{Value, json};
+use std::error::Error;
+use std::io::{Read, Seek, SeekFrom};
+
+/// Namespace of the file entry fields in `File.metadata`.
+pub const METADATA_NAMESPACE: &str = "udf";
+
+const ANCHOR_SECTOR: u64 = 256;
+/// Sector sizes tried for the anchor, the most common first.
+const SECTOR_SIZES: [u64; 3] = [2048, 512, 4096];
+const TAG_PRIMARY_VOLUME: u16 = 1;
+const TAG_ANCHOR: u16 = 2;
+const TAG_PARTITION: u16 = 5;
+const TAG_LOGICAL_VOLUME: u16 = 6;
+const TAG_TERMINATING: u16 = 8;
+const TAG_FILE_SET: u16 = 256;
+const TAG_FILE_IDENTIFIER: u16 = 257;
+const TAG_ALLOCATION_EXTENT: u16 = 258;
+const TAG_FILE_ENTRY: u16 = 261;
+const TAG_EXTENDED_FILE_ENTRY: u16 = 266;
+/// Descriptors read from a volume descriptor sequence before giving up on its end.
+const MAX_DESCRIPTORS: u64 = 64;
+/// Allocation extent descriptors followed for one file.
+const MAX_EXTENT_BLOCKS: usize = 1024;
+/// Largest directory read whole.
+const MAX_DIRECTORY: u64 = 64 << 20;
+
+const FILE_TYPE_DIRECTORY: u8 = 4;
+const FILE_TYPE_SYMLINK: u8 = 12;
+const FID_DELETED: u8 = 0x04;
+const FID_PARENT: u8 = 0x08;
+
+/// ICB flags, named after their meaning.
+const ICB_FLAGS: [(u16, &str); 8] = [
+    (0x0040, "setuid"),
+    (0x0080, "setgid"),
+    (0x0100, "sticky"),
+    (0x0200, "contiguous"),
+    (0x0400, "system"),
+    (0x0800, "transformed"),
+    (0x1000, "multi_versions"),
+    (0x2000, "stream"),
+];
+
+fn le_u16(bytes: &[u8], at: usize) -> u16 {
+    u16::from_le_bytes(bytes[at..at + 2].try_into().unwrap())
+}
+
+fn le_u32(bytes: &[u8], at: usize) -> u32 {
+    u32::from_le_bytes(bytes[at..at + 4].try_into().unwrap())
+}
+
+fn le_u64(bytes: &[u8], at: usize) -> u64 {
+    u64::from_le_bytes(bytes[at..at + 8].try_into().unwrap())
+}
+
+/// Identifier of the descriptor tag starting `bytes` found at sector or block `location`,
+/// once its checksum and recorded location are checked.
+fn tag_id(bytes: &[u8], location: u32) -> Option<u16> {
+    if bytes.len() < 16 {
+        return None;
+    }
+    let checksum = bytes[..16]
+        .iter()
+        .enumerate()
+        .filter(|(i, _)| *i != 4)
+        .fold(0u8, |sum, (_, b)| sum.wrapping_add(*b));
+    (checksum == bytes[4] && le_u32(bytes, 12) == location).then(|| le_u16(bytes, 0))
+}
+
+/// A name in OSTA compressed Unicode: a compression id of 8 (Latin-1) or 16 (UCS-2,
+/// big-endian) and the characters.
+pub fn decode_chars(bytes: &[u8]) -> String {
+    match bytes.split_first() {
+        Some((8, chars)) => chars.iter().map(|c| *c as char).collect(),
+        Some((16, chars)) => {
+            let units: Vec<u16> = chars
+                .chunks_exact(2)
+                .map(|c| u16::from_be_bytes([c[0], c[1]]))
+                .collect();
+            String::from_utf16_lossy(&units)
+        }
+        _ => String::new(),
+    }
+}
+
+/// A fixed-size d-string, its length in its last byte.
+fn decode_dstring(field: &[u8]) -> String {
+    let length = *field.last().unwrap_or(&0) as usize;
+    decode_chars(&field[..length.min(field.len().saturating_sub(1))])
+}
+
+/// UNIX seconds of a 12-byte timestamp, or `None` when it is not set. Timestamps without
+/// a recorded offset follow the process local time policy.
+pub fn decode_timestamp(bytes: &[u8]) -> Option<i64> {
+    let type_and_zone = le_u16(bytes, 0);
+    let year = le_u16(bytes, 2) as i16;
+    if year == 0 {
+        return None;
+    }
+    let local = DateTime::new(
+        year,
+        bytes[4] as i8,
+        bytes[5] as i8,
+        bytes[6] as i8,
+        bytes[7] as i8,
+        (bytes[8] as i8).min(59),
+        0,
+    )
+    .ok()?;
+    // Sign-extend the 12-bit offset in minutes; -2047 means none was recorded.
+    let minutes = (((type_and_zone << 4) as i16) >> 4) as i32;
+    let recorded = match (type_and_zone >> 12, minutes) {
+        (0, _) => Some(Offset::UTC),
+        (1, -2047) => None,
+        (1, minutes) => Offset::from_seconds(minutes * 60).ok(),
+        _ => None,
+    };
+    Some(local_time_policy().to_utc(local, recorded)?.0)
+}
+
+/// Names of the ICB flags set.
+pub fn icb_flag_names(flags: u16) -> Vec<&'static str> {
+    ICB_FLAGS
+        .iter()
+        .filter(|(bit, _)| flags & bit != 0)
+        .map(|(_, name)| *name)
+        .collect()
+}
+
+/// Where a partition reference number points.
+#[derive(Debug, Clone, Serialize)]
+pub enum PartitionMap {
+    /// Blocks of a partition descriptor.
+    Physical {
+        number: u16,
+        /// First sector of the partition.
+        start: u64,
+        length: u64,
+    },
+    /// UDF 2.50 metadata partition: blocks of the metadata file, itself in the physical
+    /// partition `physical`.
+    Metadata {
+        number: u16,
+        physical: usize,
+        file_location: u32,
+        #[serde(skip)]
+        extents: Vec<Extent>,
+    },
+}
+
+/// Volume descriptors and file set descriptor fields.
+#[derive(Debug, Clone, Serialize)]
+pub struct Volume {
+    pub sector_size: u64,
+    pub block_size: u64,
+    pub volume_identifier: String,
+    pub volume_set_identifier: String,
+    pub recording_time: Option<i64>,
+    pub logical_volume_identifier: String,
+    pub domain: String,
+    pub file_set_identifier: String,
+    pub partitions: Vec<PartitionMap>,
+    /// Location of the root directory file entry (see the module docs).
+    pub root: u64,
+}
+
+/// A run of file content: `length` bytes at block `block` of partition reference
+/// `partition`, or a hole when `block` is `None` (not recorded, read as zeros).
+#[derive(Debug, Clone, Copy, Serialize)]
+pub struct Extent {
+    pub length: u64,
+    pub block: Option<(u16, u32)>,
+}
+
+/// A file entry or extended file entry.
+#[derive(Debug, Clone, Serialize)]
+pub struct UdfFile {
+    pub location: u64,
+    pub extended: bool,
+    pub file_type: u8,
+    pub icb_flags: u16,
+    pub uid: u32,
+    pub gid: u32,
+    /// ECMA-167 permissions: 5 bits (execute, write, read, change attributes, delete) for
+    /// other, group and owner.
+    pub permissions: u32,
+    pub links: u16,
+    pub size: u64,
+    pub blocks_recorded: u64,
+    pub accessed: Option<i64>,
+    pub modified: Option<i64>,
+    pub created: Option<i64>,
+    pub attribute_changed: Option<i64>,
+    pub unique_id: u64,
+    /// Major and minor numbers of a device, from its device specification attribute.
+    pub device: Option<(u32, u32)>,
+    /// Target of a symbolic link, from its path components.
+    pub symlink: Option<String>,
+    #[serde(skip)]
+    pub extents: Vec<Extent>,
+    /// Content held in the file entry itself.
+    #[serde(skip)]
+    pub embedded: Option<Vec<u8>>,
+}
+
+impl UdfFile {
+    /// `st_mode` of the file type, permissions and ICB flags.
+    pub fn mode(&self) -> u32 {
+        let file_type = match self.file_type {
+            FILE_TYPE_DIRECTORY => 0o040000,
+            FILE_TYPE_SYMLINK => 0o120000,
+            6 => 0o060000,
+            7 => 0o020000,
+            9 => 0o010000,
+            10 => 0o140000,
+            _ => 0o100000,
+        };
+        let mut mode = file_type;
+        for (class, shift) in [(0, 0), (1, 3), (2, 6)] {
+            // Execute, write and read come in the order of the Unix bits.
+            mode |= (self.permissions >> (class * 5) & 7) << shift;
+        }
+        for (flag, bit) in [(0x0040, 0o4000), (0x0080, 0o2000), (0x0100, 0o1000)] {
+            if self.icb_flags & flag != 0 {
+                mode |= bit;
+            }
+        }
+        mode
+    }
+}
+
+impl FileCommon for UdfFile {
+    fn id(&self) -> u64 {
+        self.location
+    }
+    fn size(&self) -> u64 {
+        self.size
+    }
+    fn is_dir(&self) -> bool {
+        self.file_type == FILE_TYPE_DIRECTORY
+    }
+    fn to_string(&self) -> String {
+        format!(
+            "UdfFile {{ location: {:#x}, type: {}, size: {} }}",
+            self.location, self.file_type, self.size
+        )
+    }
+    fn to_json(&self) -> Value {
+        serde_json::to_value(self).unwrap_or(Value::Null)
+    }
+}
+
+/// A file identifier descriptor of a directory.
+#[derive(Debug, Clone)]
+pub struct UdfDirEntry {
+    pub location: u64,
+    pub name: String,
+    pub characteristics: u8,
+}
+
+impl DirectoryCommon for UdfDirEntry {
+    fn file_id(&self) -> u64 {
+        self.location
+    }
+    fn name(&self) -> &str {
+        &self.name
+    }
+    fn to_string(&self) -> String {
+        format!(
+            "UdfDirEntry {{ location: {:#x}, name: {} }}",
+            self.location, self.name
+        )
+    }
+    fn to_json(&self) -> Value {
+        json!({
+            "location": self.location,
+            "name": self.name,
+            "characteristics": self.characteristics,
+        })
+    }
+}
+
+/// Long allocation descriptor: extent length, block and partition reference.
+fn long_ad(bytes: &[u8]) -> (u32, u32, u16) {
+    (le_u32(bytes, 0), le_u32(bytes, 4), le_u16(bytes, 8))
+}
+
+pub struct UdfFS<T: Read + Seek> {
+    body: T,
+    volume: Volume,
+}
+
+impl<T: Read + Seek> UdfFS<T> {
+    pub fn new(mut body: T) -> Result<Self, Box<dyn Error>> {
+        let mut anchor = None;
+        for sector_size in SECTOR_SIZES {
+            let mut sector = vec![0u8; 512];
+            body.seek(SeekFrom::Start(ANCHOR_SECTOR * sector_size))?;
+            if body.read_exact(&mut sector).is_ok()
+                && tag_id(&sector, ANCHOR_SECTOR as u32) == Some(TAG_ANCHOR)
+            {
+                anchor = Some((sector_size, le_u32(&sector, 16), le_u32(&sector, 20)));
+                break;
+            }
+        }
+        let (sector_size, sequence_length, sequence_start) =
+            anchor.ok_or("no UDF anchor volume descriptor pointer")?;
+        let mut fs = Self {
+            body,
+            volume: Volume {
+                sector_size,
+                block_size: sector_size,
+                volume_identifier: String::new(),
+                volume_set_identifier: String::new(),
+                recording_time: None,
+                logical_volume_identifier: String::new(),
+                domain: String::new(),
+                file_set_identifier: String::new(),
+                partitions: Vec::new(),
+                root: 0,
+            },
+        };
+
+        let mut partitions = Vec::new();
+        let mut logical_volume = None;
+        let count = (sequence_length as u64 / sector_size).min(MAX_DESCRIPTORS);
+        for index in 0..count {
+            let location = sequence_start + index as u32;
+            let sector = fs.read_at(location as u64 * sector_size, sector_size as usize)?;
+            match tag_id(&sector, location) {
+                Some(TAG_PRIMARY_VOLUME) => {
+                    fs.volume.volume_identifier = decode_dstring(&sector[24..56]);
+                    fs.volume.volume_set_identifier = decode_dstring(&sector[72..200]);
+                    fs.volume.recording_time = decode_timestamp(&sector[376..388]);
+                }
+                Some(TAG_PARTITION) => {
+                    partitions.push((
+                        le_u16(&sector, 22),
+                        le_u32(&sector, 188) as u64,
+                        le_u32(&sector, 192) as u64,
+                    ));
+                }
+                Some(TAG_LOGICAL_VOLUME) => logical_volume = Some(sector),
+                Some(TAG_TERMINATING) | None => break,
+                Some(_) => {}
+            }
+        }
+        let lvd = logical_volume.ok_or("no UDF logical volume descriptor")?;
+        let block_size = le_u32(&lvd, 212) as u64;
+        if !block_size.is_power_of_two() || !(512..=65536).contains(&block_size) {
+            return Err(format!("bad UDF logical block size {}", block_size).into());
+        }
+        fs.volume.block_size = block_size;
+        fs.volume.logical_volume_identifier = decode_dstring(&lvd[84..212]);
+        fs.volume.domain = String::from_utf8_lossy(&lvd[217..240])
+            .trim_end_matches('\0')
+            .to_string();
+        let (_, fsd_block, fsd_partition) = long_ad(&lvd[248..264]);
+
+        // Partition maps, by reference number.
+        let (table_length, map_count) = (le_u32(&lvd, 264) as usize, le_u32(&lvd, 268));
+        let table = lvd
+            .get(440..440 + table_length)
+            .ok_or("UDF partition map table past its descriptor")?;
+        let mut at = 0;
+        for _ in 0..map_count {
+            let (kind, length) = match table.get(at..at + 2) {
+                Some(header) if header[1] >= 2 => (header[0], header[1] as usize),
+                _ => return Err("truncated UDF partition map".into()),
+            };
+            let map = table
+                .get(at..at + length)
+                .ok_or("truncated UDF partition map")?;
+            let physical = |number: u16| {
+                partitions
+                    .iter()
+                    .position(|(n, _, _)| *n == number)
+                    .ok_or_else(|| format!("no descriptor for UDF partition {}", number))
+            };
+            match kind {
+                1 if length >= 6 => {
+                    let (number, start, length) = partitions[physical(le_u16(map, 4))?];
+                    fs.volume.partitions.push(PartitionMap::Physical {
+                        number,
+                        start,
+                        length,
+                    });
+                }
+                2 if length >= 64 && map[5..28].starts_with(b"*UDF Metadata Partition") => {
+                    let number = le_u16(map, 38);
+                    physical(number)?;
+                    fs.volume.partitions.push(PartitionMap::Metadata {
+                        number,
+                        physical: usize::MAX,
+                        file_location: le_u32(map, 40),
+                        extents: Vec::new(),
+                    });
+                }
+                2 => {
+                    let identifier = map.get(5..28).unwrap_or_default();
+                    return Err(format!(
+                        "UDF partition map {} is not supported",
+                        String::from_utf8_lossy(identifier).trim_end_matches('\0')
+                    )
+                    .into());
+                }
+                other => return Err(format!("unknown UDF partition map type {}", other).into()),
+            }
+            at += length;
+        }
+        // Metadata partitions read their file from the type 1 map of the same partition.
+        for index in 0..fs.volume.partitions.len() {
+            if let PartitionMap::Metadata {
+                number,
+                file_location,
+                ..
+            } = fs.volume.partitions[index]
+            {
+                let physical = fs
+                    .volume
+                    .partitions
+                    .iter()
+                    .position(
+                        |p| matches!(p, PartitionMap::Physical { number: n, .. } if *n == number),
+                    )
+                    .ok_or("UDF metadata partition without a physical map")?;
+                let file = fs.file_entry(physical as u16, file_location)?;
+                fs.volume.partitions[index] = PartitionMap::Metadata {
+                    number,
+                    physical,
+                    file_location,
+                    extents: file.extents,
+                };
+            }
+        }
+
+        let fsd = fs.read_block(fsd_partition, fsd_block)?;
+        if tag_id(&fsd, fsd_block) != Some(TAG_FILE_SET) {
+            return Err("no UDF file set descriptor".into());
+        }
+        fs.volume.logical_volume_identifier = decode_dstring(&fsd[112..240]);
+        fs.volume.file_set_identifier = decode_dstring(&fsd[304..336]);
+        let (_, root_block, root_partition) = long_ad(&fsd[400..416]);
+        fs.volume.root = (root_partition as u64) << 32 | root_block as u64;
+        Ok(fs)
+    }
+
+    pub fn volume(&self) -> &Volume {
+        &self.volume
+    }
+
+    fn read_at(&mut self, position: u64, length: usize) -> Result<Vec<u8>, Box<dyn Error>> {
+        let mut data = vec![0u8; length];
+        self.body.seek(SeekFrom::Start(position))?;
+        self.body.read_exact(&mut data)?;
+        Ok(data)
+    }
+
+    /// Byte position of block `block` of partition reference `partition`.
+    fn block_position(&self, partition: u16, block: u32) -> Result<u64, Box<dyn Error>> {
+        let block_size = self.volume.block_size;
+        match self.volume.partitions.get(partition as usize) {
+            Some(PartitionMap::Physical { start, length, .. }) => {
+                let position = block as u64 * block_size;
+                if position >= length * self.volume.sector_size {
+                    return Err(format!("block {} past UDF partition {}", block, partition).into());
+                }
+                Ok(start * self.volume.sector_size + position)
+            }
+            Some(PartitionMap::Metadata {
+                physical, extents, ..
+            }) => {
+                let mut offset = block as u64 * block_size;
+                for extent in extents {
+                    if offset < extent.length {
+                        let (_, first) = extent.block.ok_or("hole in the UDF metadata file")?;
+                        return self.block_position(
+                            *physical as u16,
+                            first + (offset / block_size) as u32,
+                        );
+                    }
+                    offset -= extent.length;
+                }
+                Err(format!("block {} past the UDF metadata file", block).into())
+            }
+            None => Err(format!("no UDF partition {}", partition).into()),
+        }
+    }
+
+    fn read_block(&mut self, partition: u16, block: u32) -> Result<Vec<u8>, Box<dyn Error>> {
+        let position = self.block_position(partition, block)?;
+        self.read_at(position, self.volume.block_size as usize)
+    }
+
+    /// Allocation descriptors of `kind` (0 short, 1 long, 2 extended) in `bytes`,
+    /// following the allocation extent descriptors they continue into.
+    fn allocation_descriptors(
+        &mut self,
+        mut bytes: Vec<u8>,
+        kind: u16,
+        partition: u16,
+    ) -> Result<Vec<Extent>, Box<dyn Error>> {
+        let size = match kind {
+            0 => 8,
+            1 => 16,
+            2 => 20,
+            _ => unreachable!(),
+        };
+        let mut extents = Vec::new();
+        for _ in 0..MAX_EXTENT_BLOCKS {
+            let mut next = None;
+            for ad in bytes.chunks_exact(size) {
+                let raw_length = le_u32(ad, 0);
+                let (length, extent_type) = ((raw_length & 0x3fff_ffff) as u64, raw_length >> 30);
+                if length == 0 {
+                    break;
+                }
+                let (block, reference) = match kind {
+                    0 => (le_u32(ad, 4), partition),
+                    1 => (le_u32(ad, 4), le_u16(ad, 8)),
+                    _ => (le_u32(ad, 12), le_u16(ad, 16)),
+                };
+                match extent_type {
+                    0 => extents.push(Extent {
+                        length,
+                        block: Some((reference, block)),
+                    }),
+                    1 | 2 => extents.push(Extent {
+                        length,
+                        block: None,
+                    }),
+                    _ => {
+                        next = Some((reference, block));
+                        break;
+                    }
+                }
+            }
+            let Some((reference, block)) = next else {
+                return Ok(extents);
+            };
+            let aed = self.read_block(reference, block)?;
+            if tag_id(&aed, block) != Some(TAG_ALLOCATION_EXTENT) {
+                return Err("bad UDF allocation extent descriptor".into());
+            }
+            let length = (le_u32(&aed, 20) as usize).min(aed.len() - 24);
+            bytes = aed[24..24 + length].to_vec();
+        }
+        Err("too many UDF allocation extent descriptors".into())
+    }
+
+    /// Parse the file entry at block `block` of partition reference `partition`.
+    fn file_entry(&mut self, partition: u16, block: u32) -> Result<UdfFile, Box<dyn Error>> {
+        let raw = self.read_block(partition, block)?;
+        let extended = match tag_id(&raw, block) {
+            Some(TAG_FILE_ENTRY) => false,
+            Some(TAG_EXTENDED_FILE_ENTRY) => true,
+            Some(other) => {
+                return Err(format!("UDF descriptor {} is not a file entry", other).into());
+            }
+            None => return Err(format!("no UDF file entry at block {}", block).into()),
+        };
+        let icb_flags = le_u16(&raw, 34);
+        let (times, unique_id, ea_length, ad_length, ea_start) = match extended {
+            false => (
+                [72, 84, 0, 96],
+                le_u64(&raw, 160),
+                le_u32(&raw, 168),
+                le_u32(&raw, 172),
+                176,
+            ),
+            true => (
+                [80, 92, 104, 116],
+                le_u64(&raw, 200),
+                le_u32(&raw, 208),
+                le_u32(&raw, 212),
+                216,
+            ),
+        };
+        let ad_start = ea_start + ea_length as usize;
+        let descriptors = raw
+            .get(ad_start..ad_start + ad_length as usize)
+            .ok_or("UDF allocation descriptors past their file entry")?
+            .to_vec();
+        let time = |at: usize| match at {
+            0 => None,
+            at => decode_timestamp(&raw[at..at + 12]),
+        };
+        let mut file = UdfFile {
+            location: (partition as u64) << 32 | block as u64,
+            extended,
+            file_type: raw[27],
+            icb_flags,
+            uid: le_u32(&raw, 36),
+            gid: le_u32(&raw, 40),
+            permissions: le_u32(&raw, 44),
+            links: le_u16(&raw, 48),
+            size: le_u64(&raw, 56),
+            blocks_recorded: le_u64(&raw, if extended { 72 } else { 64 }),
+            accessed: time(times[0]),
+            modified: time(times[1]),
+            created: time(times[2]),
+            attribute_changed: time(times[3]),
+            unique_id,
+            device: device_specification(&raw[ea_start..ad_start]),
+            symlink: None,
+            extents: Vec::new(),
+            embedded: None,
+        };
+        match icb_flags & 7 {
+            3 => file.embedded = Some(descriptors),
+            kind @ 0..=2 => {
+                file.extents = self.allocation_descriptors(descriptors, kind, partition)?
+            }
+            other => return Err(format!("unknown UDF allocation type {}", other).into()),
+        }
+        if file.file_type == FILE_TYPE_SYMLINK {
+            file.symlink = Some(self.symlink_target(&file)?);
+        }
+        Ok(file)
+    }
+
+    /// Content of `file` from `offset`, `length` bytes at most.
+    fn read_content(
+        &mut self,
+        file: &UdfFile,
+        offset: u64,
+        length: usize,
+    ) -> Result<Vec<u8>, Box<dyn Error>> {
+        if offset >= file.size {
+            return Ok(Vec::new());
+        }
+        let length = length.min((file.size - offset) as usize);
+        if let Some(data) = &file.embedded {
+            let start = (offset as usize).min(data.len());
+            return Ok(data[start..(start + length).min(data.len())].to_vec());
+        }
+        let mut out = Vec::with_capacity(length);
+        let mut extent_start = 0;
+        for extent in &file.extents {
+            let end = offset + out.len() as u64;
+            if out.len() == length {
+                break;
+            }
+            if end < extent_start + extent.length {
+                let within = end - extent_start;
+                let count = ((extent.length - within) as usize).min(length - out.len());
+                match extent.block {
+                    Some((partition, block)) => {
+                        let position = self.block_position(partition, block)? + within;
+                        out.extend(self.read_at(position, count)?);
+                    }
+                    None => out.resize(out.len() + count, 0),
+                }
+            }
+            extent_start += extent.length;
+        }
+        // Content past the last extent reads as zeros.
+        out.resize(length, 0);
+        Ok(out)
+    }
+
+    /// Target of a symbolic link, from its path components.
+    fn symlink_target(&mut self, file: &UdfFile) -> Result<String, Box<dyn Error>> {
+        let data = self.read_content(file, 0, file.size.min(1 << 16) as usize)?;
+        let mut parts: Vec<String> = Vec::new();
+        let mut absolute = false;
+        let mut at = 0;
+        while at + 4 <= data.len() {
+            let (kind, length) = (data[at], data[at + 1] as usize);
+            let identifier = data
+                .get(at + 4..at + 4 + length)
+                .ok_or("truncated UDF path component")?;
+            match kind {
+                1 | 2 => {
+                    absolute = true;
+                    parts.clear();
+                }
+                3 => parts.push("..".to_string()),
+                4 => parts.push(".".to_string()),
+                5 => parts.push(decode_chars(identifier)),
+                _ => {}
+            }
+            at += 4 + length;
+        }
+        let target = parts.join("/");
+        Ok(if absolute {
+            format!("/{}", target)
+        } else {
+            target
+        })
+    }
+}
+
+/// Major and minor numbers of the device specification extended attribute (type 12),
+/// from the extended attributes of a file entry.
+fn device_specification(attributes: &[u8]) -> Option<(u32, u32)> {
+    // Extended attribute header descriptor, then the attributes.
+    let mut at = 24;
+    while at + 12 <= attributes.len() {
+        let (kind, length) = (le_u32(attributes, at), le_u32(attributes, at + 8) as usize);
+        if length < 12 || at + length > attributes.len() {
+            return None;
+        }
+        if kind == 12 && length >= 24 {
+            return Some((le_u32(attributes, at + 16), le_u32(attributes, at + 20)));
+        }
+        at += length;
+    }
+    None
+}
+
+impl<T: Read + Seek> Filesystem for UdfFS<T> {
+    type FileType = UdfFile;
+    type DirectoryType = UdfDirEntry;
+
+    fn filesystem_type(&self) -> String {
+        "UDF".to_string()
+    }
+
+    fn path_separator(&self) -> String {
+        "/".to_string()
+    }
+
+    fn record_count(&mut self) -> u64 {
+        // UDF does not count its file entries; the logical volume integrity descriptor
+        // may, but is often stale on closed discs.
+        0
+    }
+
+    fn block_size(&self) -> u64 {
+        self.volume.block_size
+    }
+
+    fn get_metadata(&self) -> Result<Value, Box<dyn Error>> {
+        Ok(serde_json::to_value(&self.volume)?)
+    }
+
+    fn get_metadata_pretty(&self) -> Result<String, Box<dyn Error>> {
+        let volume = &self.volume;
+        let partitions: Vec<String> = volume
+            .partitions
+            .iter()
+            .map(|p| match p {
+                PartitionMap::Physical {
+                    number,
+                    start,
+                    length,
+                } => format!("{} (sectors {}+{})", number, start, length),
+                PartitionMap::Metadata { number, .. } => format!("{} (metadata)", number),
+            })
+            .collect();
+        Ok(format!(
+            "UDF volume '{}' ({}), {} byte blocks\n\
+             Volume set: {}\n\
+             File set: {}\n\
+             Recorded: {}\n\
+             Partitions: {}\n",
+            volume.logical_volume_identifier,
+            volume.domain,
+            volume.block_size,
+            volume.volume_set_identifier,
+            volume.file_set_identifier,
+            volume
+                .recording_time
+                .map_or_else(|| "-".to_string(), |t| format_timestamp(t.max(0) as u64)),
+            partitions.join(", ")
+        ))
+    }
+
+    fn get_file(&mut self, location: u64) -> Result<Self::FileType, Box<dyn Error>> {
+        self.file_entry((location >> 32) as u16, location as u32)
+    }
+
+    fn read_file_content(&mut self, file: &Self::FileType) -> Result<Vec<u8>, Box<dyn Error>> {
+        self.read_content(file, 0, file.size as usize)
+    }
+
+    fn read_file_prefix(
+        &mut self,
+        file: &Self::FileType,
+        length: usize,
+    ) -> Result<Vec<u8>, Box<dyn Error>> {
+        self.read_content(file, 0, length)
+    }
+
+    fn read_file_slice(
+        &mut self,
+        file: &Self::FileType,
+        offset: u64,
+        length: usize,
+    ) -> Result<Vec<u8>, Box<dyn Error>> {
+        self.read_content(file, offset, length)
+    }
+
+    fn list_dir(
+        &mut self,
+        file: &Self::FileType,
+    ) -> Result<Vec<Self::DirectoryType>, Box<dyn Error>> {
+        if !file.is_dir() {
+            return Err("not a directory".into());
+        }
+        if file.size > MAX_DIRECTORY {
+            return Err(format!("directory of {} bytes", file.size).into());
+        }
+        let data = self.read_content(file, 0, file.size as usize)?;
+        let mut entries = Vec::new();
+        let mut at = 0;
+        while at + 38 <= data.len() {
+            if le_u16(&data, at) != TAG_FILE_IDENTIFIER {
+                return Err(format!("bad UDF file identifier at offset {}", at).into());
+            }
+            let fid = &data[at..];
+            let characteristics = fid[18];
+            let name_length = fid[19] as usize;
+            let (_, block, partition) = long_ad(&fid[20..36]);
+            let name_start = 38 + le_u16(fid, 36) as usize;
+            let name = fid
+                .get(name_start..name_start + name_length)
+                .ok_or("truncated UDF file identifier")?;
+            if characteristics & (FID_DELETED | FID_PARENT) == 0 {
+                entries.push(UdfDirEntry {
+                    location: (partition as u64) << 32 | block as u64,
+                    name: decode_chars(name),
+                    characteristics,
+                });
+            }
+            at += (name_start + name_length).next_multiple_of(4);
+        }
+        Ok(entries)
+    }
+
+    fn record_to_file(&self, file: &Self::FileType, file_id: u64, absolute_path: &str) -> File {
+        let mode = file.mode();
+        let file_type = unix_ftype(mode);
+        let mut common = json!({ FLAGS_KEY: icb_flag_names(file.icb_flags) });
+        if let Some((major, minor)) = file.device {
+            common[DEVICE_KEY] = json!({ "major": major, "minor": minor });
+        }
+        if let Some(target) = &file.symlink {
+            common[SYMLINK_TARGET_KEY] = json!(target);
+        }
+        let metadata = namespaced_metadata(METADATA_NAMESPACE, file.to_json(), common);
+        let name = match absolute_path.rsplit_once('/') {
+            Some((_, name)) if !name.is_empty() => name,
+            _ => absolute_path,
+        };
+        let absolute_path = render_name(absolute_path);
+        let permissions = unix_mode_string(mode);
+        let on_disk = file.blocks_recorded * self.volume.block_size;
+        let time = |t: Option<i64>| t.map(|t| t.max(0) as u64);
+        File {
+            id: None,
+            identifier: file_id,
+            absolute_path: absolute_path.to_string(),
+            name: render_name(name).into_owned(),
+            raw_name: name_bytes(name).map(hex::encode),
+            ftype: file_type.to_string(),
+            size: file.size,
+            size_on_disk: Some(on_disk),
+            created: time(file.created),
+            modified: time(file.modified),
+            accessed: time(file.accessed),
+            changed: time(file.attribute_changed),
+            permissions: Some(permissions.clone()),
+            owner: Some(file.uid.to_string()),
+            group: Some(file.gid.to_string()),
+            display: Some(format!(
+                "[{}] - {} {} {} {} {:>5} {} {}",
+                file_id,
+                permissions,
+                file.links,
+                file.uid,
+                file.gid,
+                file.size,
+                time(file.modified).map_or_else(|| "-".to_string(), format_timestamp),
+                absolute_path
+            )),
+            sig_name: None,
+            sig_mime: None,
+            sig_exts: None,
+            detected_type: None,
+            ext_mismatch: None,
+            md5: None,
+            sha1: None,
+            sha256: None,
+            metadata,
+        }
+    }
+
+    fn get_root_file_id(&self) -> u64 {
+        self.volume.root
+    }
+
+    fn file_block_runs(
+        &mut self,
+        file: &Self::FileType,
+    ) -> Result<Option<Vec<BlockRun>>, Box<dyn Error>> {
+        if file.embedded.is_some() {
+            return Ok(Some(Vec::new()));
+        }
+        let block_size = self.volume.block_size;
+        let mut runs = Vec::new();
+        for extent in &file.extents {
+            let Some((partition, block)) = extent.block else {
+                continue;
+            };
+            let first = self.block_position(partition, block)? / block_size;
+            runs.push((first, extent.length.div_ceil(block_size)));
+        }
+        Ok(Some(runs))
+    }
+
+    fn file_holes(
+        &mut self,
+        file: &Self::FileType,
+    ) -> Result<Option<Vec<ByteRange>>, Box<dyn Error>> {
+        if file.embedded.is_some() || file.is_dir() {
+            return Ok(Some(Vec::new()));
+        }
+        let mut holes: Vec<ByteRange> = Vec::new();
+        let mut start = 0;
+        for extent in &file.extents {
+            let length = extent.length.min(file.size.saturating_sub(start));
+            if extent.block.is_none() && length > 0 {
+                match holes.last_mut() {
+                    Some((offset, len)) if *offset + *len == start => *len += length,
+                    _ => holes.push((start, length)),
+                }
+            }
+            start += extent.length;
+        }
+        if start < file.size {
+            holes.push((start, file.size - start));
+        }
+        Ok(Some(holes))
+    }
+
+    fn read_directory_data(
+        &mut self,
+        dir: &Self::FileType,
+    ) -> Result<Option<Vec<u8>>, Box<dyn Error>> {
+        if dir.size > MAX_DIRECTORY {
+            return Err(format!("directory of {} bytes", dir.size).into());
+        }
+        self.read_content(dir, 0, dir.size as usize).map(Some)
+    }
+}
@@ -0,0 +1,772 @@
+//! UFS volumes of the BSDs: UFS2 (FreeBSD since 5.0, NetBSD and OpenBSD as FFS2) and
+//! UFS1 (4.4BSD FFS, still the OpenBSD default on small disks). The superblock, at 64 KiB
+//! for UFS2 and 8 KiB for UFS1, describes the cylinder groups; each holds its share of
+//! the inodes after its cylinder group block, whose maps tell the free fragments.
+//!
+//! Records are identified by inode number. Block pointers are in fragments; files are
+//! mapped by twelve direct pointers, then single, double and triple indirect blocks.
+//! Little-endian volumes only; the extended attribute area of UFS2 inodes is read, soft
+//! updates journals and snapshots are not.
+use crate::filesystem::{
+    BlockRun, ByteRange, DEVICE_KEY, DirectoryCommon, ExtendedAttribute, FLAGS_KEY, File,
+    FileCommon, Filesystem, SYMLINK_TARGET_KEY, namespaced_metadata, unix_ftype, unix_mode_string,
+};
+use crate::folder_impl::bsd_flag_names;
+use crate::names::{escape_name, name_bytes, render_name};
+use crate::timefmt::format_timestamp;
+use serde::Serialize;
+use serde_json::{Value, json};
+use std::collections::HashMap;
+use std::error::Error;
+use std::io::{Read, Seek, SeekFrom};
+
+/// Namespace of the inode fields in `File.metadata`.
+pub const METADATA_NAMESPACE: &str = "ufs";
+
+const UFS1_MAGIC: i32 = 0x0001_1954;
+const UFS2_MAGIC: i32 = 0x1954_0119;
+const CG_MAGIC: i32 = 0x0009_0255;
+/// Superblock locations, in the order FreeBSD tries them: UFS2, UFS1, floppy, piggy.
+const SUPERBLOCK_OFFSETS: [u64; 4] = [65536, 8192, 0, 262144];
+const SUPERBLOCK_SIZE: usize = 1376;
+const ROOT_INODE: u64 = 2;
+const DIRECT_BLOCKS: u64 = 12;
+const DIRECTORY_BLOCK: usize = 512;
+/// Largest directory read whole.
+const MAX_DIRECTORY: u64 = 64 << 20;
+/// Indirect blocks kept in memory at most.
+const INDIRECT_CACHE: usize = 64;
+
+fn le_u16(bytes: &[u8], at: usize) -> u16 {
+    u16::from_le_bytes(bytes[at..at + 2].try_into().unwrap())
+}
+
+fn le_i32(bytes: &[u8], at: usize) -> i32 {
+    i32::from_le_bytes(bytes[at..at + 4].try_into().unwrap())
+}
+
+fn le_u32(bytes: &[u8], at: usize) -> u32 {
+    u32::from_le_bytes(bytes[at..at + 4].try_into().unwrap())
+}
+
+fn le_i64(bytes: &[u8], at: usize) -> i64 {
+    i64::from_le_bytes(bytes[at..at + 8].try_into().unwrap())
+}
+
+/// Text of a NUL-padded field.
+fn fixed_string(bytes: &[u8]) -> String {
+    let end = bytes.iter().position(|b| *b == 0).unwrap_or(bytes.len());
+    String::from_utf8_lossy(&bytes[..end]).into_owned()
+}
+
+#[derive(Debug, Clone, Serialize)]
+pub struct Superblock {
+    /// 1 or 2.
+    pub version: u8,
+    /// Byte offset of the superblock in the partition.
+    pub location: u64,
+    pub cblkno: u32,
+    pub iblkno: u32,
+    pub dblkno: u32,
+    pub old_cgoffset: i32,
+    pub old_cgmask: i32,
+    pub ncg: u32,
+    pub bsize: u32,
+    pub fsize: u32,
+    pub frag: u32,
+    pub fragshift: u32,
+    pub nindir: u32,
+    pub inopb: u32,
+    pub ipg: u32,
+    pub fpg: u32,
+    pub size: u64,
+    pub dsize: u64,
+    pub clean: bool,
+    pub last_mounted_on: String,
+    pub volume_name: String,
+    pub time: i64,
+    pub flags: i32,
+    pub maxsymlinklen: i32,
+    pub free_blocks: i64,
+    pub free_inodes: i64,
+    pub directories: i64,
+}
+
+impl Superblock {
+    fn parse(bytes: &[u8], location: u64) -> Result<Self, Box<dyn Error>> {
+        let version = match le_i32(bytes, 1372) {
+            UFS2_MAGIC => 2,
+            UFS1_MAGIC => 1,
+            _ => return Err("not a UFS superblock".into()),
+        };
+        // A UFS1 superblock at the UFS2 location is a backup, and a UFS2 one records
+        // where it belongs.
+        if (version == 1 && location == SUPERBLOCK_OFFSETS[0])
+            || (version == 2 && le_i64(bytes, 1000) as u64 != location)
+        {
+            return Err("UFS superblock out of place".into());
+        }
+        let sb = Self {
+            version,
+            location,
+            cblkno: le_u32(bytes, 12),
+            iblkno: le_u32(bytes, 16),
+            dblkno: le_u32(bytes, 20),
+            old_cgoffset: le_i32(bytes, 24),
+            old_cgmask: le_i32(bytes, 28),
+            ncg: le_u32(bytes, 44),
+            bsize: le_u32(bytes, 48),
+            fsize: le_u32(bytes, 52),
+            frag: le_u32(bytes, 56),
+            fragshift: le_u32(bytes, 96),
+            nindir: le_u32(bytes, 116),
+            inopb: le_u32(bytes, 120),
+            ipg: le_u32(bytes, 184),
+            fpg: le_u32(bytes, 188),
+            size: match version {
+                2 => le_i64(bytes, 1080) as u64,
+                _ => le_i32(bytes, 36) as u64,
+            },
+            dsize: match version {
+                2 => le_i64(bytes, 1088) as u64,
+                _ => le_i32(bytes, 40) as u64,
+            },
+            clean: bytes[209] != 0,
+            last_mounted_on: fixed_string(&bytes[212..680]),
+            volume_name: fixed_string(&bytes[680..712]),
+            time: match version {
+                2 => le_i64(bytes, 1072),
+                _ => le_i32(bytes, 32) as i64,
+            },
+            flags: le_i32(bytes, 1312),
+            maxsymlinklen: le_i32(bytes, 1320),
+            free_blocks: match version {
+                2 => le_i64(bytes, 1016),
+                _ => le_i32(bytes, 196) as i64,
+            },
+            free_inodes: match version {
+                2 => le_i64(bytes, 1024),
+                _ => le_i32(bytes, 200) as i64,
+            },
+            directories: match version {
+                2 => le_i64(bytes, 1008),
+                _ => le_i32(bytes, 192) as i64,
+            },
+        };
+        let valid = sb.ncg > 0
+            && sb.ipg > 0
+            && sb.fpg > 0
+            && (512..=65536).contains(&sb.fsize)
+            && sb.fsize.is_power_of_two()
+            && matches!(sb.frag, 1 | 2 | 4 | 8)
+            && sb.bsize == sb.fsize * sb.frag
+            && sb.frag == 1 << sb.fragshift
+            && sb.nindir == sb.bsize / sb.pointer_size() as u32
+            && sb.inopb == sb.bsize / sb.inode_size() as u32;
+        match valid {
+            true => Ok(sb),
+            false => Err("inconsistent UFS superblock".into()),
+        }
+    }
+
+    fn inode_size(&self) -> usize {
+        match self.version {
+            2 => 256,
+            _ => 128,
+        }
+    }
+
+    fn pointer_size(&self) -> usize {
+        match self.version {
+            2 => 8,
+            _ => 4,
+        }
+    }
+
+    /// First fragment of cylinder group `cg`; UFS1 staggers its metadata.
+    fn cgstart(&self, cg: u64) -> u64 {
+        let base = cg * self.fpg as u64;
+        match self.version {
+            2 => base,
+            _ => base + (self.old_cgoffset as u64) * (cg & !(self.old_cgmask as i64 as u64)),
+        }
+    }
+}
+
+/// An inode, with the fields of UFS1 widened to those of UFS2.
+#[derive(Debug, Clone, Serialize)]
+pub struct UfsInode {
+    pub ino: u64,
+    pub mode: u16,
+    pub nlink: i16,
+    pub uid: u32,
+    pub gid: u32,
+    pub size: u64,
+    /// 512-byte sectors in use, indirect blocks included.
+    pub blocks: u64,
+    pub atime: i64,
+    pub mtime: i64,
+    pub ctime: i64,
+    pub birthtime: Option<i64>,
+    pub generation: u32,
+    pub flags: u32,
+    /// Bytes of the extended attribute area (UFS2).
+    pub extsize: u32,
+    #[serde(skip)]
+    pub extb: [u64; 2],
+    #[serde(skip)]
+    pub direct: [u64; 12],
+    #[serde(skip)]
+    pub indirect: [u64; 3],
+    /// Target of a symbolic link short enough to be kept in the block pointers.
+    pub symlink: Option<Vec<u8>>,
+}
+
+impl FileCommon for UfsInode {
+    fn id(&self) -> u64 {
+        self.ino
+    }
+    fn size(&self) -> u64 {
+        self.size
+    }
+    fn is_dir(&self) -> bool {
+        unix_ftype(self.mode as u32) == "dir"
+    }
+    fn to_string(&self) -> String {
+        format!(
+            "UfsInode {{ ino: {}, mode: {:o}, size: {} }}",
+            self.ino, self.mode, self.size
+        )
+    }
+    fn to_json(&self) -> Value {
+        serde_json::to_value(self).unwrap_or(Value::Null)
+    }
+}
+
+/// An entry of a directory block.
+#[derive(Debug, Clone)]
+pub struct UfsDirEntry {
+    pub ino: u32,
+    pub name: String,
+    pub d_type: u8,
+}
+
+impl DirectoryCommon for UfsDirEntry {
+    fn file_id(&self) -> u64 {
+        self.ino as u64
+    }
+    fn name(&self) -> &str {
+        &self.name
+    }
+    fn to_string(&self) -> String {
+        format!("UfsDirEntry {{ ino: {}, name: {} }}", self.ino, self.name)
+    }
+    fn to_json(&self) -> Value {
+        json!({ "ino": self.ino, "name": self.name, "d_type": self.d_type })
+    }
+}
+
+/// Extended attributes of a UFS2 extended attribute area: each record has its length,
+/// namespace, content padding and name length, then its name and content, both padded
+/// to 8 bytes.
+fn parse_extattrs(area: &[u8]) -> Vec<ExtendedAttribute> {
+    let mut attributes = Vec::new();
+    let mut at = 0;
+    while at + 8 <= area.len() {
+        let length = le_u32(area, at) as usize;
+        if length < 8 || at + length > area.len() {
+            break;
+        }
+        let (namespace, padding, name_length) = (area[at + 4], area[at + 5], area[at + 6]);
+        let base = (7 + name_length as usize).next_multiple_of(8);
+        let name = &area[at + 7..at + 7 + name_length as usize];
+        let prefix = match namespace {
+            1 => "user.",
+            2 => "system.",
+            _ => "",
+        };
+        if let Some(content) = length
+            .checked_sub(base + padding as usize)
+            .and_then(|size| area.get(at + base..at + base + size))
+        {
+            attributes.push((
+                format!("{}{}", prefix, String::from_utf8_lossy(name)),
+                content.to_vec(),
+            ));
+        }
+        at += length;
+    }
+    attributes
+}
+
+pub struct UfsFS<T: Read + Seek> {
+    body: T,
+    superblock: Superblock,
+    /// Indirect blocks by fragment address.
+    indirect_cache: HashMap<u64, Vec<u8>>,
+}
+
+impl<T: Read + Seek> UfsFS<T> {
+    pub fn new(mut body: T) -> Result<Self, Box<dyn Error>> {
+        let mut raw = vec![0u8; SUPERBLOCK_SIZE];
+        for location in SUPERBLOCK_OFFSETS {
+            body.seek(SeekFrom::Start(location))?;
+            if body.read_exact(&mut raw).is_err() {
+                continue;
+            }
+            if let Ok(superblock) = Superblock::parse(&raw, location) {
+                return Ok(Self {
+                    body,
+                    superblock,
+                    indirect_cache: HashMap::new(),
+                });
+            }
+        }
+        Err("no UFS superblock found".into())
+    }
+
+    pub fn superblock(&self) -> &Superblock {
+        &self.superblock
+    }
+
+    fn read_at(&mut self, position: u64, length: usize) -> Result<Vec<u8>, Box<dyn Error>> {
+        let mut data = vec![0u8; length];
+        self.body.seek(SeekFrom::Start(position))?;
+        self.body.read_exact(&mut data)?;
+        Ok(data)
+    }
+
+    /// `length` bytes from fragment `fragment`.
+    fn read_fragments(&mut self, fragment: u64, length: usize) -> Result<Vec<u8>, Box<dyn Error>> {
+        if fragment >= self.superblock.size {
+            return Err(format!("UFS fragment {} past the end of the volume", fragment).into());
+        }
+        self.read_at(fragment * self.superblock.fsize as u64, length)
+    }
+
+    fn inode(&mut self, ino: u64) -> Result<UfsInode, Box<dyn Error>> {
+        let sb = &self.superblock;
+        if ino >= sb.ncg as u64 * sb.ipg as u64 {
+            return Err(format!("UFS inode {} out of range", ino).into());
+        }
+        let (cg, index) = (ino / sb.ipg as u64, ino % sb.ipg as u64);
+        let fragment =
+            sb.cgstart(cg) + sb.iblkno as u64 + ((index / sb.inopb as u64) << sb.fragshift);
+        let position =
+            fragment * sb.fsize as u64 + (index % sb.inopb as u64) * sb.inode_size() as u64;
+        let (size, version, maxsymlinklen) =
+            (sb.inode_size(), sb.version, sb.maxsymlinklen.max(0) as u64);
+        let raw = self.read_at(position, size)?;
+        let mut inode = match version {
+            2 => UfsInode {
+                ino,
+                mode: le_u16(&raw, 0),
+                nlink: le_u16(&raw, 2) as i16,
+                uid: le_u32(&raw, 4),
+                gid: le_u32(&raw, 8),
+                size: le_i64(&raw, 16) as u64,
+                blocks: le_i64(&raw, 24) as u64,
+                atime: le_i64(&raw, 32),
+                mtime: le_i64(&raw, 40),
+                ctime: le_i64(&raw, 48),
+                birthtime: Some(le_i64(&raw, 56)),
+                generation: le_u32(&raw, 80),
+                flags: le_u32(&raw, 88),
+                extsize: le_u32(&raw, 92),
+                extb: std::array::from_fn(|i| le_i64(&raw, 96 + 8 * i) as u64),
+                direct: std::array::from_fn(|i| le_i64(&raw, 112 + 8 * i) as u64),
+                indirect: std::array::from_fn(|i| le_i64(&raw, 208 + 8 * i) as u64),
+                symlink: None,
+            },
+            _ => UfsInode {
+                ino,
+                mode: le_u16(&raw, 0),
+                nlink: le_u16(&raw, 2) as i16,
+                uid: le_u32(&raw, 112),
+                gid: le_u32(&raw, 116),
+                size: le_i64(&raw, 8) as u64,
+                blocks: le_u32(&raw, 104) as u64,
+                atime: le_i32(&raw, 16) as i64,
+                mtime: le_i32(&raw, 24) as i64,
+                ctime: le_i32(&raw, 32) as i64,
+                birthtime: None,
+                generation: le_u32(&raw, 108),
+                flags: le_u32(&raw, 100),
+                extsize: 0,
+                extb: [0; 2],
+                direct: std::array::from_fn(|i| le_u32(&raw, 40 + 4 * i) as u64),
+                indirect: std::array::from_fn(|i| le_u32(&raw, 88 + 4 * i) as u64),
+                symlink: None,
+            },
+        };
+        // Short symbolic links keep their target in place of the block pointers.
+        if unix_ftype(inode.mode as u32) == "symlink"
+            && inode.size < maxsymlinklen
+            && inode.blocks == 0
+        {
+            let start = if version == 2 { 112 } else { 40 };
+            inode.symlink = Some(raw[start..start + inode.size as usize].to_vec());
+        }
+        Ok(inode)
+    }
+
+    /// Pointer `index` of the indirect block at fragment `fragment`.
+    fn indirect_pointer(&mut self, fragment: u64, index: u64) -> Result<u64, Box<dyn Error>> {
+        if !self.indirect_cache.contains_key(&fragment) {
+            if self.indirect_cache.len() >= INDIRECT_CACHE {
+                self.indirect_cache.clear();
+            }
+            let block = self.read_fragments(fragment, self.superblock.bsize as usize)?;
+            self.indirect_cache.insert(fragment, block);
+        }
+        let block = &self.indirect_cache[&fragment];
+        let at = index as usize * self.superblock.pointer_size();
+        Ok(match self.superblock.version {
+            2 => le_i64(block, at) as u64,
+            _ => le_u32(block, at) as u64,
+        })
+    }
+
+    /// Fragment address of logical block `lbn` of `inode`, 0 for a hole.
+    fn block_address(&mut self, inode: &UfsInode, lbn: u64) -> Result<u64, Box<dyn Error>> {
+        if lbn < DIRECT_BLOCKS {
+            return Ok(inode.direct[lbn as usize]);
+        }
+        let nindir = self.superblock.nindir as u64;
+        let mut index = lbn - DIRECT_BLOCKS;
+        let mut span = nindir;
+        for level in 0..3 {
+            if index < span {
+                let mut fragment = inode.indirect[level];
+                for _ in 0..=level {
+                    if fragment == 0 {
+                        return Ok(0);
+                    }
+                    span /= nindir;
+                    fragment = self.indirect_pointer(fragment, index / span)?;
+                    index %= span;
+                }
+                return Ok(fragment);
+            }
+            index -= span;
+            span *= nindir;
+        }
+        Err(format!("UFS block {} past the largest file", lbn).into())
+    }
+
+    fn read_content(
+        &mut self,
+        inode: &UfsInode,
+        offset: u64,
+        length: usize,
+    ) -> Result<Vec<u8>, Box<dyn Error>> {
+        if offset >= inode.size {
+            return Ok(Vec::new());
+        }
+        let length = length.min((inode.size - offset) as usize);
+        if let Some(target) = &inode.symlink {
+            return Ok(target[offset as usize..offset as usize + length].to_vec());
+        }
+        let bsize = self.superblock.bsize as u64;
+        let mut out = Vec::with_capacity(length);
+        let mut position = offset;
+        let end = offset + length as u64;
+        while position < end {
+            let start = position % bsize;
+            let count = (bsize - start).min(end - position) as usize;
+            match self.block_address(inode, position / bsize)? {
+                0 => out.resize(out.len() + count, 0),
+                fragment => {
+                    let data =
+                        self.read_at(fragment * self.superblock.fsize as u64 + start, count)?;
+                    out.extend(data);
+                }
+            }
+            position += count as u64;
+        }
+        Ok(out)
+    }
+
+    /// Fragments of `inode` as (fragment, count), in logical order; the last block of a
+    /// small file may be a run of fragments shorter than a block.
+    fn fragments(&mut self, inode: &UfsInode) -> Result<Vec<(u64, u64)>, Box<dyn Error>> {
+        let sb = &self.superblock;
+        let (bsize, fsize, frag) = (sb.bsize as u64, sb.fsize as u64, sb.frag as u64);
+        let mut out = Vec::new();
+        if inode.symlink.is_some() {
+            return Ok(out);
+        }
+        for lbn in 0..inode.size.div_ceil(bsize) {
+            let address = self.block_address(inode, lbn)?;
+            if address != 0 {
+                let count = (inode.size - lbn * bsize)
+                    .min(bsize)
+                    .div_ceil(fsize)
+                    .min(frag);
+                out.push((address, count));
+            }
+        }
+        Ok(out)
+    }
+}
+
+impl<T: Read + Seek> Filesystem for UfsFS<T> {
+    type FileType = UfsInode;
+    type DirectoryType = UfsDirEntry;
+
+    fn filesystem_type(&self) -> String {
+        format!("UFS{}", self.superblock.version)
+    }
+
+    fn path_separator(&self) -> String {
+        "/".to_string()
+    }
+
+    fn record_count(&mut self) -> u64 {
+        self.superblock.ncg as u64 * self.superblock.ipg as u64
+    }
+
+    fn block_size(&self) -> u64 {
+        self.superblock.fsize as u64
+    }
+
+    fn get_metadata(&self) -> Result<Value, Box<dyn Error>> {
+        Ok(serde_json::to_value(&self.superblock)?)
+    }
+
+    fn get_metadata_pretty(&self) -> Result<String, Box<dyn Error>> {
+        let sb = &self.superblock;
+        Ok(format!(
+            "UFS{} volume '{}' (last mounted on '{}', {})\n\
+             Blocks: {} bytes, fragments: {} bytes, {} fragments\n\
+             Cylinder groups: {} of {} inodes and {} fragments\n\
+             Free: {} blocks, {} inodes; {} directories\n\
+             Last written: {}\n",
+            sb.version,
+            sb.volume_name,
+            sb.last_mounted_on,
+            if sb.clean { "clean" } else { "dirty" },
+            sb.bsize,
+            sb.fsize,
+            sb.size,
+            sb.ncg,
+            sb.ipg,
+            sb.fpg,
+            sb.free_blocks,
+            sb.free_inodes,
+            sb.directories,
+            format_timestamp(sb.time.max(0) as u64)
+        ))
+    }
+
+    fn get_file(&mut self, ino: u64) -> Result<Self::FileType, Box<dyn Error>> {
+        self.inode(ino)
+    }
+
+    fn read_file_content(&mut self, file: &Self::FileType) -> Result<Vec<u8>, Box<dyn Error>> {
+        self.read_content(file, 0, file.size as usize)
+    }
+
+    fn read_file_prefix(
+        &mut self,
+        file: &Self::FileType,
+        length: usize,
+    ) -> Result<Vec<u8>, Box<dyn Error>> {
+        self.read_content(file, 0, length)
+    }
+
+    fn read_file_slice(
+        &mut self,
+        file: &Self::FileType,
+        offset: u64,
+        length: usize,
+    ) -> Result<Vec<u8>, Box<dyn Error>> {
+        self.read_content(file, offset, length)
+    }
+
+    fn list_dir(
+        &mut self,
+        file: &Self::FileType,
+    ) -> Result<Vec<Self::DirectoryType>, Box<dyn Error>> {
+        if !file.is_dir() {
+            return Err("not a directory".into());
+        }
+        if file.size > MAX_DIRECTORY {
+            return Err(format!("directory of {} bytes", file.size).into());
+        }
+        let data = self.read_content(file, 0, file.size as usize)?;
+        let mut entries = Vec::new();
+        // Entries never cross a directory block.
+        for chunk in data.chunks(DIRECTORY_BLOCK) {
+            let mut at = 0;
+            while at + 8 <= chunk.len() {
+                let (ino, reclen) = (le_u32(chunk, at), le_u16(chunk, at + 4) as usize);
+                if reclen < 8 || at + reclen > chunk.len() {
+                    break;
+                }
+                let (d_type, name_length) = (chunk[at + 6], chunk[at + 7] as usize);
+                let name = &chunk[at + 8..(at + 8 + name_length).min(at + reclen)];
+                if ino != 0 && !matches!(name, b"." | b"..") {
+                    entries.push(UfsDirEntry {
+                        ino,
+                        name: escape_name(name),
+                        d_type,
+                    });
+                }
+                at += reclen;
+            }
+        }
+        Ok(entries)
+    }
+
+    fn record_to_file(&self, file: &Self::FileType, file_id: u64, absolute_path: &str) -> File {
+        let mode = file.mode as u32;
+        let file_type = unix_ftype(mode);
+        let mut common = json!({ FLAGS_KEY: bsd_flag_names(file.flags) });
+        if matches!(file_type, "chardev" | "blockdev") {
+            // FreeBSD keeps the device number in the first block pointer.
+            let rdev = file.direct[0];
+            common[DEVICE_KEY] = json!({
+                "major": (rdev >> 8) & 0xff,
+                "minor": (rdev & 0xffff_00ff),
+            });
+        }
+        if let Some(target) = &file.symlink {
+            common[SYMLINK_TARGET_KEY] = json!(render_name(&escape_name(target)));
+        }
+        let metadata = namespaced_metadata(METADATA_NAMESPACE, file.to_json(), common);
+        let name = match absolute_path.rsplit_once('/') {
+            Some((_, name)) if !name.is_empty() => name,
+            _ => absolute_path,
+        };
+        let absolute_path = render_name(absolute_path);
+        let permissions = unix_mode_string(mode);
+        let time = |t: i64| (t > 0).then_some(t as u64);
+        File {
+            id: None,
+            identifier: file_id,
+            absolute_path: absolute_path.to_string(),
+            name: render_name(name).into_owned(),
+            raw_name: name_bytes(name).map(hex::encode),
+            ftype: file_type.to_string(),
+            size: file.size,
+            size_on_disk: Some(file.blocks * 512),
+            created: file.birthtime.and_then(time),
+            modified: time(file.mtime),
+            accessed: time(file.atime),
+            changed: time(file.ctime),
+            permissions: Some(permissions.clone()),
+            owner: Some(file.uid.to_string()),
+            group: Some(file.gid.to_string()),
+            display: Some(format!(
+                "[{}] - {} {} {} {} {:>5} {} {}",
+                file_id,
+                permissions,
+                file.nlink,
+                file.uid,
+                file.gid,
+                file.size,
+                format_timestamp(file.mtime.max(0) as u64),
+                absolute_path
+            )),
+            sig_name: None,
+            sig_mime: None,
+            sig_exts: None,
+            detected_type: None,
+            ext_mismatch: None,
+            md5: None,
+            sha1: None,
+            sha256: None,
+            metadata,
+        }
+    }
+
+    fn get_root_file_id(&self) -> u64 {
+        ROOT_INODE
+    }
+
+    /// From the free fragment map of the cylinder group block.
+    fn block_allocation(&mut self, fragment: u64) -> Result<Option<bool>, Box<dyn Error>> {
+        let sb = &self.superblock;
+        if fragment >= sb.size {
+            return Ok(None);
+        }
+        let cg = fragment / sb.fpg as u64;
+        let block = sb.cgstart(cg) + sb.cblkno as u64;
+        let relative = fragment - cg * sb.fpg as u64;
+        let bsize = sb.bsize as usize;
+        let header = self.read_fragments(block, bsize)?;
+        if le_i32(&header, 4) != CG_MAGIC {
+            return Ok(None);
+        }
+        let free_map = le_u32(&header, 96) as usize + relative as usize / 8;
+        Ok(header
+            .get(free_map)
+            .map(|byte| byte & (1 << (relative % 8)) == 0))
+    }
+
+    fn file_block_runs(
+        &mut self,
+        file: &Self::FileType,
+    ) -> Result<Option<Vec<BlockRun>>, Box<dyn Error>> {
+        let mut runs: Vec<BlockRun> = Vec::new();
+        for (fragment, count) in self.fragments(file)? {
+            match runs.last_mut() {
+                Some((first, length)) if *first + *length == fragment => *length += count,
+                _ => runs.push((fragment, count)),
+            }
+        }
+        Ok(Some(runs))
+    }
+
+    fn file_holes(
+        &mut self,
+        file: &Self::FileType,
+    ) -> Result<Option<Vec<ByteRange>>, Box<dyn Error>> {
+        if file.symlink.is_some() || file.is_dir() {
+            return Ok(Some(Vec::new()));
+        }
+        let bsize = self.superblock.bsize as u64;
+        let mut holes: Vec<ByteRange> = Vec::new();
+        for lbn in 0..file.size.div_ceil(bsize) {
+            if self.block_address(file, lbn)? != 0 {
+                continue;
+            }
+            let start = lbn * bsize;
+            let length = bsize.min(file.size - start);
+            match holes.last_mut() {
+                Some((offset, len)) if *offset + *len == start => *len += length,
+                _ => holes.push((start, length)),
+            }
+        }
+        Ok(Some(holes))
+    }
+
+    fn read_directory_data(
+        &mut self,
+        dir: &Self::FileType,
+    ) -> Result<Option<Vec<u8>>, Box<dyn Error>> {
+        if dir.size > MAX_DIRECTORY {
+            return Err(format!("directory of {} bytes", dir.size).into());
+        }
+        self.read_content(dir, 0, dir.size as usize).map(Some)
+    }
+
+    fn extended_attributes(
+        &mut self,
+        file: &Self::FileType,
+    ) -> Result<Vec<ExtendedAttribute>, Box<dyn Error>> {
+        let bsize = self.superblock.bsize as usize;
+        let mut area = Vec::new();
+        let mut remaining = (file.extsize as usize).min(2 * bsize);
+        for fragment in file.extb {
+            if remaining == 0 || fragment == 0 {
+                break;
+            }
+            let count = remaining.min(bsize);
+            area.extend(self.read_fragments(fragment, count)?);
+            remaining -= count;
+        }
+        Ok(parse_extattrs(&area))
+    }
+}
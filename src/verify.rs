@@ -0,0 +1,131 @@
+//! Content verification: every regular file is streamed and hashed without writing
+//! anything, and the records that cannot be read in full are reported, to validate an
+//! image end to end before relying on it.
+use crate::filesystem::{File, Filesystem, ReadSeek, WalkEvent, WalkOptions};
+use crate::hashing::{FileHashes, HashAlgorithm, hash_reader};
+use crate::search::ExcludeSet;
+use serde::Serialize;
+use std::error::Error;
+use std::io::{self, Read};
+
+/// A regular file read in full.
+#[derive(Debug, Clone, Serialize)]
+pub struct VerifiedFile {
+    pub identifier: u64,
+    pub path: String,
+    pub size: u64,
+    #[serde(flatten)]
+    pub hashes: FileHashes,
+}
+
+/// A regular file whose content could not be read in full.
+#[derive(Debug, Clone, Serialize)]
+pub struct UnreadableFile {
+    pub identifier: u64,
+    pub path: String,
+    pub size: u64,
+    /// Bytes read before the failure.
+    pub read: u64,
+    pub reason: String,
+}
+
+#[derive(Debug, Clone, Default, Serialize)]
+pub struct VerifyReport {
+    pub files: u64,
+    pub readable: u64,
+    pub unreadable: u64,
+    /// Sum of the logical sizes of the regular files.
+    pub bytes_expected: u64,
+    pub bytes_read: u64,
+    /// Share of the expected bytes actually read, in percent.
+    pub coverage: f64,
+    pub failures: Vec<UnreadableFile>,
+    pub verified: Vec<VerifiedFile>,
+}
+
+/// Counts the bytes going through, so partial reads are known when hashing fails.
+struct Counting<'a> {
+    inner: &'a mut dyn ReadSeek,
+    count: u64,
+}
+
+impl Read for Counting<'_> {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        let n = self.inner.read(buf)?;
+        self.count += n as u64;
+        Ok(n)
+    }
+}
+
+fn is_regular(ftype: &str) -> bool {
+    ftype.eq_ignore_ascii_case("file") || ftype.eq_ignore_ascii_case("regular")
+}
+
+impl VerifyReport {
+    fn add(&mut self, file: &File, reader: &mut dyn ReadSeek, algorithms: &[HashAlgorithm]) {
+        self.files += 1;
+        self.bytes_expected += file.size;
+        let mut counting = Counting {
+            inner: reader,
+            count: 0,
+        };
+        let result = hash_reader(&mut counting, algorithms);
+        let read = counting.count;
+        self.bytes_read += read;
+        let failure = match result {
+            Ok((hashes, _)) if read == file.size => {
+                self.readable += 1;
+                self.verified.push(VerifiedFile {
+                    identifier: file.identifier,
+                    path: file.absolute_path.clone(),
+                    size: file.size,
+                    hashes,
+                });
+                return;
+            }
+            Ok(_) => format!("short read: {} of {} bytes", read, file.size),
+            Err(e) => e.to_string(),
+        };
+        self.unreadable += 1;
+        self.failures.push(UnreadableFile {
+            identifier: file.identifier,
+            path: file.absolute_path.clone(),
+            size: file.size,
+            read,
+            reason: failure,
+        });
+    }
+}
+
+/// Stream and hash every regular file of `fs` with `algorithms`.
+pub fn verify_content<F: Filesystem + ?Sized>(
+    fs: &mut F,
+    algorithms: &[HashAlgorithm],
+    exclude: Option<&ExcludeSet>,
+    on_status: &mut dyn FnMut(String),
+) -> Result<VerifyReport, Box<dyn Error>> {
+    let mut report = VerifyReport::default();
+    let mut visitor = |file: &mut File, reader: &mut dyn ReadSeek| {
+        if is_regular(&file.ftype) {
+            report.add(file, reader, algorithms);
+        }
+    };
+    fs.walk_fs_with(
+        WalkOptions {
+            visitor: Some(&mut visitor),
+            exclude,
+            ..Default::default()
+        },
+        &mut |event| {
+            if let WalkEvent::Status(msg) = event {
+                on_status(msg)
+            }
+        },
+    )?;
+    report.coverage = if report.bytes_expected == 0 {
+        100.0
+    } else {
+        report.bytes_read as f64 * 100.0 / report.bytes_expected as f64
+    };
+    Ok(report)
+}
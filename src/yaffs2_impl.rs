@@ -0,0 +1,748 @@
+//! YAFFS2 dumps of raw NAND flash (`nanddump` with the out-of-band area, or
+//! `mkyaffs2image` output): pages of a data chunk followed by their spare bytes, where
+//! the packed tags tell the object, the chunk of it and the sequence number of the erase
+//! block. YAFFS2 never rewrites a page: the newest copy of a chunk is the one of the
+//! highest sequence number, and of the latest page within its block. There are no
+//! on-flash tables, so every page is scanned when the dump is opened and the object tree
+//! rebuilt from the object headers (chunk 0), whose parent links make the directories.
+//!
+//! Records are identified by object id (the root is 1). Chunk `n` of a file holds its
+//! bytes from `(n - 1)` chunks on; missing chunks are holes. Deleted and unlinked
+//! objects are moved under the hidden directories 4 and 3 until garbage collection
+//! erases their chunks: they are enumerated as deleted records, named after their last
+//! header before the deletion. The chunk and spare sizes and where the tags sit in the
+//! spare bytes are guessed from the first written page; tags within the data chunk
+//! (inband tags) and YAFFS1 are not supported.
+use crate::filesystem::{
+    ByteRange, DEVICE_KEY, DirectoryCommon, ExtendedAttribute, FLAGS_KEY, File, FileCommon,
+    Filesystem, SYMLINK_TARGET_KEY, namespaced_metadata, unix_ftype, unix_mode_string,
+};
+use crate::names::{escape_name, name_bytes, render_name};
+use crate::timefmt::format_timestamp;
+use serde::Serialize;
+use serde_json::{Value, json};
+use std::collections::{BTreeMap, HashMap};
+use std::error::Error;
+use std::io::{Read, Seek, SeekFrom};
+
+/// Namespace of the object header fields in `File.metadata`.
+pub const METADATA_NAMESPACE: &str = "yaffs2";
+
+/// Chunk and spare sizes tried, most common first.
+const GEOMETRIES: [(u64, u64); 5] = [(2048, 64), (4096, 128), (4096, 224), (8192, 448), (512, 16)];
+/// Offsets of the packed tags in the spare bytes: `mkyaffs2image`, then after the bad
+/// block marker as the MTD layouts place them.
+const TAG_OFFSETS: [u64; 2] = [0, 2];
+const TAGS_SIZE: usize = 16;
+/// Pages searched for the first written one when guessing the geometry.
+const PROBE_PAGES: u64 = 256;
+const LOWEST_SEQUENCE: u32 = 0x0000_1000;
+const HIGHEST_SEQUENCE: u32 = 0xefff_ff00;
+const EXTRA_HEADER_INFO: u32 = 0x8000_0000;
+const OBJECT_ID_MASK: u32 = 0x0fff_ffff;
+const HEADER_SIZE: usize = 512;
+const MAX_NAME: usize = 256;
+const MAX_ALIAS: usize = 160;
+
+const ROOT_ID: u32 = 1;
+const LOST_AND_FOUND_ID: u32 = 2;
+const UNLINKED_ID: u32 = 3;
+const DELETED_ID: u32 = 4;
+/// Summary chunks closing the blocks of recent YAFFS2: not object data.
+const SUMMARY_ID: u32 = 0x10;
+
+const TYPE_FILE: u32 = 1;
+const TYPE_SYMLINK: u32 = 2;
+const TYPE_DIRECTORY: u32 = 3;
+const TYPE_HARDLINK: u32 = 4;
+const TYPE_SPECIAL: u32 = 5;
+
+fn le_u32(bytes: &[u8], at: usize) -> u32 {
+    u32::from_le_bytes(bytes[at..at + 4].try_into().unwrap())
+}
+
+/// Bytes of a NUL-terminated field.
+fn c_string(bytes: &[u8]) -> &[u8] {
+    let end = bytes.iter().position(|b| *b == 0).unwrap_or(bytes.len());
+    &bytes[..end]
+}
+
+/// Packed tags of a page, `None` for an erased or unused one.
+#[derive(Debug, Clone, Copy)]
+struct Tags {
+    sequence: u32,
+    object: u32,
+    chunk: u32,
+    bytes: u32,
+}
+
+impl Tags {
+    fn parse(spare: &[u8]) -> Option<Self> {
+        let sequence = le_u32(spare, 0);
+        if !(LOWEST_SEQUENCE..=HIGHEST_SEQUENCE).contains(&sequence) {
+            return None;
+        }
+        let (mut object, mut chunk) = (le_u32(spare, 4), le_u32(spare, 8));
+        let mut bytes = le_u32(spare, 12);
+        // Object headers may carry the object type, parent and size in their tags.
+        if chunk & EXTRA_HEADER_INFO != 0 {
+            object &= OBJECT_ID_MASK;
+            chunk = 0;
+            bytes = 0;
+        }
+        (object != 0).then_some(Self {
+            sequence,
+            object,
+            chunk,
+            bytes,
+        })
+    }
+}
+
+/// Chunk size, spare size and offset of the tags in the spare bytes of the YAFFS2 dump
+/// `body`, `None` when its first written page is no object header.
+pub fn geometry<R: Read + Seek>(body: &mut R) -> std::io::Result<Option<(u64, u64, u64)>> {
+    let size = body.seek(SeekFrom::End(0))?;
+    Ok(GEOMETRIES
+        .iter()
+        .flat_map(|&(chunk, spare)| TAG_OFFSETS.map(|tags| (chunk, spare, tags)))
+        .find(|&(chunk, spare, tags)| probe(body, size, chunk, spare, tags)))
+}
+
+/// Whether the first pages of the geometry are erased (spare bytes and the start of the
+/// chunk) or tagged, data chunks holding at most a chunk, up to an object header.
+fn probe<R: Read + Seek>(
+    body: &mut R,
+    size: u64,
+    chunk: u64,
+    spare: u64,
+    tags_offset: u64,
+) -> bool {
+    let page_size = chunk + spare;
+    if !size.is_multiple_of(page_size) || size < page_size || tags_offset + TAGS_SIZE as u64 > spare
+    {
+        return false;
+    }
+    let mut page = vec![0u8; page_size as usize];
+    let tags_at = (chunk + tags_offset) as usize;
+    for index in 0..(size / page_size).min(PROBE_PAGES) {
+        if body.seek(SeekFrom::Start(index * page_size)).is_err()
+            || body.read_exact(&mut page).is_err()
+        {
+            return false;
+        }
+        let spare_bytes = &page[chunk as usize..];
+        if spare_bytes.iter().all(|b| *b == 0xff) {
+            if page[..HEADER_SIZE.min(chunk as usize)]
+                .iter()
+                .any(|b| *b != 0xff)
+            {
+                return false;
+            }
+            continue;
+        }
+        let Some(tags) = Tags::parse(&page[tags_at..tags_at + TAGS_SIZE]) else {
+            return false;
+        };
+        if tags.chunk != 0 {
+            if tags.bytes as u64 > chunk {
+                return false;
+            }
+            continue;
+        }
+        let header = &page[..HEADER_SIZE.min(chunk as usize)];
+        if header.len() < HEADER_SIZE {
+            return false;
+        }
+        let name = &header[10..10 + MAX_NAME];
+        let at = Page { sequence: 0, index };
+        return YaffsObject::parse(tags.object, header, at).is_ok()
+            && name.contains(&0)
+            && !c_string(name).is_empty()
+            && le_u32(header, 4) <= OBJECT_ID_MASK;
+    }
+    false
+}
+
+/// A page holding a chunk: where it is and when it was written.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord)]
+struct Page {
+    sequence: u32,
+    index: u64,
+}
+
+/// An object: file, directory, symbolic link, hard link or special file, as its newest
+/// header describes it.
+#[derive(Debug, Clone, Serialize)]
+pub struct YaffsObject {
+    pub id: u32,
+    /// 1 file, 2 symbolic link, 3 directory, 4 hard link, 5 special file.
+    pub object_type: u32,
+    pub parent: u32,
+    pub name: String,
+    pub mode: u32,
+    pub uid: u32,
+    pub gid: u32,
+    pub atime: u32,
+    pub mtime: u32,
+    pub ctime: u32,
+    pub size: u64,
+    pub rdev: u32,
+    /// Object a hard link stands for.
+    pub equivalent: u32,
+    pub shrink: bool,
+    /// Sequence number of the block holding the header.
+    pub sequence: u32,
+    /// Page of the header, `None` for the directories YAFFS2 makes up (root, lost+found).
+    pub header_page: Option<u64>,
+    /// Name and parent before the object was deleted or unlinked.
+    pub previous_name: Option<String>,
+    pub previous_parent: Option<u32>,
+    #[serde(skip)]
+    pub alias: Vec<u8>,
+}
+
+impl YaffsObject {
+    fn parse(id: u32, header: &[u8], page: Page) -> Result<Self, Box<dyn Error>> {
+        let object_type = le_u32(header, 0);
+        if !(TYPE_FILE..=TYPE_SPECIAL).contains(&object_type) {
+            return Err(format!("bad YAFFS2 object type {}", object_type).into());
+        }
+        let size_high = match le_u32(header, 496) {
+            0xffff_ffff => 0,
+            high => high as u64,
+        };
+        let size = match object_type {
+            TYPE_FILE => size_high << 32 | le_u32(header, 292) as u64,
+            TYPE_SYMLINK => c_string(&header[300..300 + MAX_ALIAS]).len() as u64,
+            _ => 0,
+        };
+        Ok(Self {
+            id,
+            object_type,
+            parent: le_u32(header, 4),
+            name: escape_name(c_string(&header[10..10 + MAX_NAME])),
+            mode: le_u32(header, 268),
+            uid: le_u32(header, 272),
+            gid: le_u32(header, 276),
+            atime: le_u32(header, 280),
+            mtime: le_u32(header, 284),
+            ctime: le_u32(header, 288),
+            size,
+            rdev: le_u32(header, 460),
+            equivalent: le_u32(header, 296),
+            shrink: le_u32(header, 508) != 0 && le_u32(header, 508) != 0xffff_ffff,
+            sequence: page.sequence,
+            header_page: Some(page.index),
+            previous_name: None,
+            previous_parent: None,
+            alias: c_string(&header[300..300 + MAX_ALIAS]).to_vec(),
+        })
+    }
+
+    /// Directory YAFFS2 keeps without a header until one is changed.
+    fn made_up(id: u32) -> Self {
+        Self {
+            id,
+            object_type: TYPE_DIRECTORY,
+            parent: ROOT_ID,
+            name: if id == LOST_AND_FOUND_ID {
+                "lost+found"
+            } else {
+                ""
+            }
+            .to_string(),
+            mode: 0o040755,
+            uid: 0,
+            gid: 0,
+            atime: 0,
+            mtime: 0,
+            ctime: 0,
+            size: 0,
+            rdev: 0,
+            equivalent: 0,
+            shrink: false,
+            sequence: 0,
+            header_page: None,
+            previous_name: None,
+            previous_parent: None,
+            alias: Vec::new(),
+        }
+    }
+
+    fn deleted(&self) -> bool {
+        matches!(self.parent, UNLINKED_ID | DELETED_ID)
+    }
+
+    /// Mode with the file type bits, which hard links and old headers may lack.
+    fn full_mode(&self) -> u32 {
+        let kind = match self.object_type {
+            TYPE_SYMLINK => 0o120000,
+            TYPE_DIRECTORY => 0o040000,
+            TYPE_SPECIAL => return self.mode,
+            _ => 0o100000,
+        };
+        kind | self.mode & 0o7777
+    }
+}
+
+impl FileCommon for YaffsObject {
+    fn id(&self) -> u64 {
+        self.id as u64
+    }
+    fn size(&self) -> u64 {
+        self.size
+    }
+    fn is_dir(&self) -> bool {
+        self.object_type == TYPE_DIRECTORY
+    }
+    fn to_string(&self) -> String {
+        format!(
+            "YaffsObject {{ id: {}, type: {}, name: {}, size: {} }}",
+            self.id, self.object_type, self.name, self.size
+        )
+    }
+    fn to_json(&self) -> Value {
+        serde_json::to_value(self).unwrap_or(Value::Null)
+    }
+}
+
+#[derive(Debug, Clone)]
+pub struct YaffsDirEntry {
+    /// Object the entry leads to: the target of a hard link.
+    pub id: u32,
+    pub name: String,
+    pub object_type: u32,
+}
+
+impl DirectoryCommon for YaffsDirEntry {
+    fn file_id(&self) -> u64 {
+        self.id as u64
+    }
+    fn name(&self) -> &str {
+        &self.name
+    }
+    fn to_string(&self) -> String {
+        format!("YaffsDirEntry {{ id: {}, name: {} }}", self.id, self.name)
+    }
+    fn to_json(&self) -> Value {
+        json!({ "id": self.id, "name": self.name, "type": self.object_type })
+    }
+}
+
+pub struct Yaffs2FS<T: Read + Seek> {
+    body: T,
+    chunk_size: u64,
+    spare_size: u64,
+    tags_offset: u64,
+    pages: u64,
+    written_pages: u64,
+    highest_sequence: u32,
+    objects: HashMap<u32, YaffsObject>,
+    children: HashMap<u32, Vec<u32>>,
+    /// Newest page and byte count of each data chunk, by object.
+    chunks: HashMap<u32, BTreeMap<u32, (Page, u32)>>,
+}
+
+impl<T: Read + Seek> Yaffs2FS<T> {
+    pub fn new(mut body: T) -> Result<Self, Box<dyn Error>> {
+        let (chunk_size, spare_size, tags_offset) =
+            geometry(&mut body)?.ok_or("no YAFFS2 object header in the first pages")?;
+        let size = body.seek(SeekFrom::End(0))?;
+        let mut fs = Self {
+            body,
+            chunk_size,
+            spare_size,
+            tags_offset,
+            pages: size / (chunk_size + spare_size),
+            written_pages: 0,
+            highest_sequence: 0,
+            objects: HashMap::new(),
+            children: HashMap::new(),
+            chunks: HashMap::new(),
+        };
+        fs.scan()?;
+        Ok(fs)
+    }
+
+    fn page_size(&self) -> u64 {
+        self.chunk_size + self.spare_size
+    }
+
+    fn read_chunk(&mut self, page: u64, length: usize) -> Result<Vec<u8>, Box<dyn Error>> {
+        let mut data = vec![0u8; length];
+        self.body.seek(SeekFrom::Start(page * self.page_size()))?;
+        self.body.read_exact(&mut data)?;
+        Ok(data)
+    }
+
+    /// Read the tags of every page, then the newest header of each object.
+    fn scan(&mut self) -> Result<(), Box<dyn Error>> {
+        let mut headers: HashMap<u32, Vec<Page>> = HashMap::new();
+        let mut spare = vec![0u8; self.spare_size as usize];
+        let at = self.tags_offset as usize;
+        for index in 0..self.pages {
+            self.body
+                .seek(SeekFrom::Start(index * self.page_size() + self.chunk_size))?;
+            self.body.read_exact(&mut spare)?;
+            let Some(tags) = Tags::parse(&spare[at..at + TAGS_SIZE]) else {
+                continue;
+            };
+            self.written_pages += 1;
+            self.highest_sequence = self.highest_sequence.max(tags.sequence);
+            if tags.object == SUMMARY_ID {
+                continue;
+            }
+            let page = Page {
+                sequence: tags.sequence,
+                index,
+            };
+            if tags.chunk == 0 {
+                headers.entry(tags.object).or_default().push(page);
+                continue;
+            }
+            let chunk = self
+                .chunks
+                .entry(tags.object)
+                .or_default()
+                .entry(tags.chunk)
+                .or_insert((page, tags.bytes));
+            if page > chunk.0 {
+                *chunk = (page, tags.bytes);
+            }
+        }
+
+        for (id, mut pages) in headers {
+            pages.sort_unstable_by(|a, b| b.cmp(a));
+            let header = self.read_chunk(pages[0].index, HEADER_SIZE)?;
+            let Ok(mut object) = YaffsObject::parse(id, &header, pages[0]) else {
+                continue;
+            };
+            if object.deleted() {
+                // The name it had lives on in an older header until garbage collection.
+                for page in &pages[1..] {
+                    let header = self.read_chunk(page.index, HEADER_SIZE)?;
+                    if let Ok(before) = YaffsObject::parse(id, &header, *page)
+                        && !before.deleted()
+                    {
+                        object.previous_name = Some(before.name);
+                        object.previous_parent = Some(before.parent);
+                        break;
+                    }
+                }
+            }
+            self.objects.insert(id, object);
+        }
+        for id in [ROOT_ID, LOST_AND_FOUND_ID] {
+            self.objects
+                .entry(id)
+                .or_insert_with(|| YaffsObject::made_up(id));
+        }
+
+        // Chunks past the size are left over from before a truncation.
+        for (id, chunks) in self.chunks.iter_mut() {
+            let size = self.objects.get(id).map_or(0, |o| o.size);
+            let chunk_size = self.chunk_size;
+            chunks.retain(|n, _| (*n as u64 - 1) * chunk_size < size);
+        }
+        for object in self.objects.values() {
+            if object.id != ROOT_ID && !object.deleted() {
+                self.children
+                    .entry(object.parent)
+                    .or_default()
+                    .push(object.id);
+            }
+        }
+        for children in self.children.values_mut() {
+            children.sort_unstable();
+        }
+        Ok(())
+    }
+
+    fn read_content(
+        &mut self,
+        object: &YaffsObject,
+        offset: u64,
+        length: usize,
+    ) -> Result<Vec<u8>, Box<dyn Error>> {
+        if object.object_type != TYPE_FILE || offset >= object.size || length == 0 {
+            return Ok(Vec::new());
+        }
+        let end = object.size.min(offset + length as u64);
+        let mut out = vec![0u8; (end - offset) as usize];
+        let (first, last) = (
+            (offset / self.chunk_size) as u32 + 1,
+            ((end - 1) / self.chunk_size) as u32 + 1,
+        );
+        let chunks: Vec<(u32, (Page, u32))> = self
+            .chunks
+            .get(&object.id)
+            .map(|c| c.range(first..=last).map(|(n, c)| (*n, *c)).collect())
+            .unwrap_or_default();
+        for (n, (page, bytes)) in chunks {
+            let start = (n as u64 - 1) * self.chunk_size;
+            let stored = (bytes as u64).min(self.chunk_size);
+            let (from, to) = (start.max(offset), (start + stored).min(end));
+            if from >= to {
+                continue;
+            }
+            let data = self.read_chunk(page.index, stored as usize)?;
+            out[(from - offset) as usize..(to - offset) as usize]
+                .copy_from_slice(&data[(from - start) as usize..(to - start) as usize]);
+        }
+        Ok(out)
+    }
+
+    /// The object a hard link stands for, or the object itself.
+    fn resolve(&self, id: u32) -> u32 {
+        match self.objects.get(&id) {
+            Some(o) if o.object_type == TYPE_HARDLINK => o.equivalent,
+            _ => id,
+        }
+    }
+}
+
+impl<T: Read + Seek> Filesystem for Yaffs2FS<T> {
+    type FileType = YaffsObject;
+    type DirectoryType = YaffsDirEntry;
+
+    fn filesystem_type(&self) -> String {
+        "YAFFS2".to_string()
+    }
+
+    fn path_separator(&self) -> String {
+        "/".to_string()
+    }
+
+    fn record_count(&mut self) -> u64 {
+        self.objects.keys().max().copied().unwrap_or(0) as u64
+    }
+
+    fn block_size(&self) -> u64 {
+        self.chunk_size
+    }
+
+    fn get_metadata(&self) -> Result<Value, Box<dyn Error>> {
+        let deleted = self.objects.values().filter(|o| o.deleted()).count();
+        Ok(json!({
+            "chunk_size": self.chunk_size,
+            "spare_size": self.spare_size,
+            "tags_offset": self.tags_offset,
+            "pages": self.pages,
+            "written_pages": self.written_pages,
+            "highest_sequence": self.highest_sequence,
+            "objects": self.objects.len(),
+            "deleted_objects": deleted,
+        }))
+    }
+
+    fn get_metadata_pretty(&self) -> Result<String, Box<dyn Error>> {
+        let deleted = self.objects.values().filter(|o| o.deleted()).count();
+        Ok(format!(
+            "YAFFS2: {} byte chunks, {} spare bytes (tags at {})\n\
+             Pages: {} of {} written, highest sequence number {:#x}\n\
+             Objects: {} ({} deleted or unlinked)\n",
+            self.chunk_size,
+            self.spare_size,
+            self.tags_offset,
+            self.written_pages,
+            self.pages,
+            self.highest_sequence,
+            self.objects.len(),
+            deleted
+        ))
+    }
+
+    fn get_file(&mut self, id: u64) -> Result<Self::FileType, Box<dyn Error>> {
+        u32::try_from(id)
+            .ok()
+            .and_then(|id| self.objects.get(&id))
+            .cloned()
+            .ok_or_else(|| format!("no YAFFS2 object {}", id).into())
+    }
+
+    fn read_file_content(&mut self, object: &Self::FileType) -> Result<Vec<u8>, Box<dyn Error>> {
+        self.read_content(object, 0, object.size as usize)
+    }
+
+    fn read_file_prefix(
+        &mut self,
+        object: &Self::FileType,
+        length: usize,
+    ) -> Result<Vec<u8>, Box<dyn Error>> {
+        self.read_content(object, 0, length)
+    }
+
+    fn read_file_slice(
+        &mut self,
+        object: &Self::FileType,
+        offset: u64,
+        length: usize,
+    ) -> Result<Vec<u8>, Box<dyn Error>> {
+        self.read_content(object, offset, length)
+    }
+
+    fn list_dir(
+        &mut self,
+        object: &Self::FileType,
+    ) -> Result<Vec<Self::DirectoryType>, Box<dyn Error>> {
+        if !object.is_dir() {
+            return Err("not a directory".into());
+        }
+        let mut entries = Vec::new();
+        for id in self.children.get(&object.id).into_iter().flatten() {
+            let child = &self.objects[id];
+            // lost+found is shown only when something was put there.
+            if *id == LOST_AND_FOUND_ID && !self.children.contains_key(id) {
+                continue;
+            }
+            let target = self.resolve(*id);
+            entries.push(YaffsDirEntry {
+                id: target,
+                name: child.name.clone(),
+                object_type: self.objects.get(&target).map_or(0, |o| o.object_type),
+            });
+        }
+        Ok(entries)
+    }
+
+    fn record_to_file(&self, object: &Self::FileType, file_id: u64, absolute_path: &str) -> File {
+        let mode = object.full_mode();
+        let file_type = unix_ftype(mode);
+        let mut flags = Vec::new();
+        match object.parent {
+            UNLINKED_ID => flags.push("unlinked"),
+            DELETED_ID => flags.push("deleted"),
+            _ => {}
+        }
+        if object.shrink {
+            flags.push("shrink");
+        }
+        let mut common = json!({ FLAGS_KEY: flags });
+        if matches!(file_type, "chardev" | "blockdev") {
+            let rdev = object.rdev;
+            common[DEVICE_KEY] = json!({
+                "major": (rdev & 0xfff00) >> 8,
+                "minor": (rdev & 0xff) | ((rdev >> 12) & 0xfff00),
+            });
+        }
+        if object.object_type == TYPE_SYMLINK {
+            common[SYMLINK_TARGET_KEY] = json!(render_name(&escape_name(&object.alias)));
+        }
+        let metadata = namespaced_metadata(METADATA_NAMESPACE, object.to_json(), common);
+        // Deleted records come without a path: they keep the name they had.
+        let name = match absolute_path.rsplit_once('/') {
+            Some((_, name)) if !name.is_empty() => name,
+            _ if absolute_path.is_empty() => object.previous_name.as_deref().unwrap_or(""),
+            _ => absolute_path,
+        };
+        let absolute_path = render_name(absolute_path);
+        let permissions = unix_mode_string(mode);
+        let time = |t: u32| (t != 0).then_some(t as u64);
+        File {
+            id: None,
+            identifier: file_id,
+            absolute_path: absolute_path.to_string(),
+            name: render_name(name).into_owned(),
+            raw_name: name_bytes(name).map(hex::encode),
+            ftype: file_type.to_string(),
+            size: object.size,
+            size_on_disk: Some(
+                self.chunks
+                    .get(&object.id)
+                    .map_or(0, |c| c.len() as u64 * self.chunk_size),
+            ),
+            created: None,
+            modified: time(object.mtime),
+            accessed: time(object.atime),
+            changed: time(object.ctime),
+            permissions: Some(permissions.clone()),
+            owner: Some(object.uid.to_string()),
+            group: Some(object.gid.to_string()),
+            display: Some(format!(
+                "[{}] - {} {} {} {:>5} {} {}",
+                file_id,
+                permissions,
+                object.uid,
+                object.gid,
+                object.size,
+                format_timestamp(object.mtime as u64),
+                absolute_path
+            )),
+            sig_name: None,
+            sig_mime: None,
+            sig_exts: None,
+            detected_type: None,
+            ext_mismatch: None,
+            md5: None,
+            sha1: None,
+            sha256: None,
+            metadata,
+        }
+    }
+
+    fn get_root_file_id(&self) -> u64 {
+        ROOT_ID as u64
+    }
+
+    fn file_holes(
+        &mut self,
+        object: &Self::FileType,
+    ) -> Result<Option<Vec<ByteRange>>, Box<dyn Error>> {
+        if object.object_type != TYPE_FILE {
+            return Ok(Some(Vec::new()));
+        }
+        let mut holes: Vec<ByteRange> = Vec::new();
+        let mut position = 0;
+        let starts: Vec<u64> = self
+            .chunks
+            .get(&object.id)
+            .map(|c| {
+                c.keys()
+                    .map(|n| (*n as u64 - 1) * self.chunk_size)
+                    .collect()
+            })
+            .unwrap_or_default();
+        for start in starts.into_iter().chain([object.size]) {
+            let start = start.min(object.size);
+            if start > position {
+                holes.push((position, start - position));
+            }
+            position = position.max(start + self.chunk_size);
+        }
+        Ok(Some(holes))
+    }
+
+    /// From the name-value records after the header in its chunk: a length including
+    /// itself, the NUL-terminated name, then the value.
+    fn extended_attributes(
+        &mut self,
+        object: &Self::FileType,
+    ) -> Result<Vec<ExtendedAttribute>, Box<dyn Error>> {
+        let Some(page) = object.header_page else {
+            return Ok(Vec::new());
+        };
+        let chunk = self.read_chunk(page, self.chunk_size as usize)?;
+        let area = &chunk[HEADER_SIZE..];
+        let mut attributes = Vec::new();
+        let mut at = 0;
+        while at + 4 <= area.len() {
+            let size = i32::from_le_bytes(area[at..at + 4].try_into().unwrap());
+            if size <= 4 || at + size as usize > area.len() {
+                break;
+            }
+            let record = &area[at + 4..at + size as usize];
+            let name = c_string(record);
+            if name.len() < record.len() {
+                let value = record[name.len() + 1..].to_vec();
+                attributes.push((String::from_utf8_lossy(name).into_owned(), value));
+            }
+            at += size as usize;
+        }
+        Ok(attributes)
+    }
+
+    fn is_deleted(&self, object: &Self::FileType) -> Option<bool> {
+        Some(object.deleted())
+    }
+}
@@ -0,0 +1,1536 @@
+//! ZFS pools, read from one vdev: a single-disk pool or one side of a mirror. The
+//! uberblock of the highest transaction group leads to the meta object set, whose DSL
+//! directories name the datasets. The root dataset is walked unless another one, or a
+//! snapshot (`pool/fs@snap`), is selected with `select_dataset`.
+//!
+//! Blocks may be compressed (LZJB, gzip, ZLE, LZ4, zstd) or embedded in their block
+//! pointer. Checksums are not verified; gang blocks, RAID-Z vdevs and encrypted datasets
+//! are not supported.
+use crate::compression;
+use crate::filesystem::{
+    ByteRange, DEVICE_KEY, DirectoryCommon, ExtendedAttribute, FLAGS_KEY, File, FileCommon,
+    Filesystem, SYMLINK_TARGET_KEY, namespaced_metadata, unix_ftype, unix_mode_string,
+};
+use crate::names::{escape_name, name_bytes, render_name};
+use crate::timefmt::format_timestamp;
+use log::{debug, warn};
+use serde::Serialize;
+use serde_json::{Value, json};
+use std::collections::{HashMap, HashSet};
+use std::error::Error;
+use std::io::{Read, Seek, SeekFrom};
+
+/// Namespace of the dnode and znode fields in `File.metadata`.
+pub const METADATA_NAMESPACE: &str = "zfs";
+
+const LABEL_SIZE: u64 = 256 << 10;
+/// Packed nvlist of the pool configuration, in each label.
+const LABEL_NVLIST_OFFSET: usize = 16 << 10;
+const LABEL_NVLIST_SIZE: usize = (112 << 10) - 40;
+/// Ring of uberblocks, in each label. Slots are 1 KiB or `1 << ashift` bytes.
+const UBERBLOCK_RING_OFFSET: usize = 128 << 10;
+const UBERBLOCK_SLOT: usize = 1 << 10;
+const UBERBLOCK_MAGIC: u64 = 0x00ba_b10c;
+/// Allocatable space starts after the two front labels and the boot area.
+const DATA_START: u64 = 4 << 20;
+const BLKPTR_SIZE: usize = 128;
+const DNODE_SIZE: usize = 512;
+/// Object directory of the meta object set, and master node of a ZPL object set.
+const DIRECTORY_OBJECT: u64 = 1;
+const MASTER_NODE_OBJECT: u64 = 1;
+const OBJSET_TYPE_ZFS: u64 = 2;
+const BONUS_ZNODE: u8 = 17;
+const BONUS_SA: u8 = 44;
+const DNODE_FLAG_USED_BYTES: u8 = 1;
+const DNODE_FLAG_SPILL_BLKPTR: u8 = 4;
+const ZAP_MICRO: u64 = (1 << 63) + 3;
+const ZAP_HEADER: u64 = (1 << 63) + 1;
+const ZAP_LEAF: u64 = 1 << 63;
+const ZAP_MAGIC: u64 = 0x0002_f52a_b2ab;
+const ZAP_LEAF_MAGIC: u32 = 0x02ab_1eaf;
+const ZAP_CHUNK_ENTRY: u8 = 252;
+const ZAP_CHUNK_ARRAY: u8 = 251;
+const ZAP_CHUNK_SIZE: usize = 24;
+const ZAP_ARRAY_BYTES: usize = 21;
+const SA_MAGIC: u32 = 0x2f_505a;
+/// Object number in the low bits of a directory entry value.
+const DIRENT_OBJECT_MASK: u64 = (1 << 48) - 1;
+/// Decompressed blocks of metadata (indirect blocks, dnodes, ZAPs) kept for reuse.
+const CACHE_BLOCKS: usize = 256;
+/// Nesting of DSL directories followed when listing datasets.
+const MAX_DATASET_DEPTH: usize = 64;
+
+/// `zp_flags` bits, named after their meaning.
+const ZNODE_FLAGS: [(u64, &str); 17] = [
+    (0x0000_0000_0000_0001, "xattr"),
+    (0x0000_0001_0000_0000, "readonly"),
+    (0x0000_0002_0000_0000, "hidden"),
+    (0x0000_0004_0000_0000, "system"),
+    (0x0000_0008_0000_0000, "archive"),
+    (0x0000_0010_0000_0000, "immutable"),
+    (0x0000_0020_0000_0000, "nounlink"),
+    (0x0000_0040_0000_0000, "append_only"),
+    (0x0000_0080_0000_0000, "nodump"),
+    (0x0000_0100_0000_0000, "opaque"),
+    (0x0000_0200_0000_0000, "av_quarantined"),
+    (0x0000_0400_0000_0000, "av_modified"),
+    (0x0000_0800_0000_0000, "reparse"),
+    (0x0000_1000_0000_0000, "offline"),
+    (0x0000_2000_0000_0000, "sparse"),
+    (0x0000_4000_0000_0000, "project_inherit"),
+    (0x0000_8000_0000_0000, "project_id"),
+];
+
+fn le_u16(bytes: &[u8], at: usize) -> u16 {
+    u16::from_le_bytes(bytes[at..at + 2].try_into().unwrap())
+}
+
+fn le_u32(bytes: &[u8], at: usize) -> u32 {
+    u32::from_le_bytes(bytes[at..at + 4].try_into().unwrap())
+}
+
+fn le_u64(bytes: &[u8], at: usize) -> u64 {
+    u64::from_le_bytes(bytes[at..at + 8].try_into().unwrap())
+}
+
+/// Value of a packed nvlist (pool configuration, system attribute xattrs).
+#[derive(Debug, Clone)]
+enum NvValue {
+    Bool(bool),
+    Int(i64),
+    Uint(u64),
+    String(String),
+    Bytes(Vec<u8>),
+    Uints(Vec<u64>),
+    Strings(Vec<String>),
+    List(NvList),
+    Lists(Vec<NvList>),
+    Unsupported(i32),
+}
+
+type NvList = Vec<(String, NvValue)>;
+
+fn nv_get<'a>(list: &'a NvList, name: &str) -> Option<&'a NvValue> {
+    list.iter().find(|(n, _)| n == name).map(|(_, v)| v)
+}
+
+fn nv_json(list: &NvList) -> Value {
+    let mut out = serde_json::Map::new();
+    for (name, value) in list {
+        let value = match value {
+            NvValue::Bool(b) => json!(b),
+            NvValue::Int(i) => json!(i),
+            NvValue::Uint(u) => json!(u),
+            NvValue::String(s) => json!(s),
+            NvValue::Bytes(b) => json!(hex::encode(b)),
+            NvValue::Uints(u) => json!(u),
+            NvValue::Strings(s) => json!(s),
+            NvValue::List(l) => nv_json(l),
+            NvValue::Lists(l) => Value::Array(l.iter().map(nv_json).collect()),
+            NvValue::Unsupported(kind) => json!(format!("<type {}>", kind)),
+        };
+        out.insert(name.clone(), value);
+    }
+    Value::Object(out)
+}
+
+/// Reader of the XDR encoding of packed nvlists: big-endian, 4-byte aligned.
+struct Xdr<'a> {
+    data: &'a [u8],
+    pos: usize,
+}
+
+impl Xdr<'_> {
+    fn take(&mut self, length: usize) -> Result<&[u8], Box<dyn Error>> {
+        let padded = length.checked_add(3).ok_or("nvlist length overflow")? & !3;
+        let end = self
+            .pos
+            .checked_add(padded)
+            .ok_or("nvlist length overflow")?;
+        let bytes = self.data.get(self.pos..end).ok_or("truncated nvlist")?;
+        self.pos = end;
+        Ok(&bytes[..length])
+    }
+
+    fn u32(&mut self) -> Result<u32, Box<dyn Error>> {
+        Ok(u32::from_be_bytes(self.take(4)?.try_into().unwrap()))
+    }
+
+    fn u64(&mut self) -> Result<u64, Box<dyn Error>> {
+        Ok(u64::from_be_bytes(self.take(8)?.try_into().unwrap()))
+    }
+
+    fn string(&mut self) -> Result<String, Box<dyn Error>> {
+        let length = self.u32()? as usize;
+        Ok(String::from_utf8_lossy(self.take(length)?).into_owned())
+    }
+
+    /// An nvlist: version and flags, then pairs up to an empty one.
+    fn list(&mut self, depth: usize) -> Result<NvList, Box<dyn Error>> {
+        if depth > 16 {
+            return Err("nvlist nested too deep".into());
+        }
+        self.u32()?;
+        self.u32()?;
+        let mut list = Vec::new();
+        loop {
+            let start = self.pos;
+            let (encoded, decoded) = (self.u32()? as usize, self.u32()?);
+            if encoded == 0 && decoded == 0 {
+                return Ok(list);
+            }
+            let name = self.string()?;
+            let kind = self.u32()? as i32;
+            let count = self.u32()? as usize;
+            let value = match kind {
+                1 => NvValue::Bool(true),
+                21 => NvValue::Bool(self.u32()? != 0),
+                2..=6 | 22 | 23 => NvValue::Uint(self.u32()? as u64),
+                7 | 18 => NvValue::Int(self.u64()? as i64),
+                8 => NvValue::Uint(self.u64()?),
+                9 => NvValue::String(self.string()?),
+                10 | 25 | 26 => NvValue::Bytes(self.take(count)?.to_vec()),
+                15 | 16 => {
+                    let n = self.u32()? as usize;
+                    NvValue::Uints(
+                        (0..n.min(count))
+                            .map(|_| self.u64())
+                            .collect::<Result<_, _>>()?,
+                    )
+                }
+                17 => NvValue::Strings(
+                    (0..count)
+                        .map(|_| self.string())
+                        .collect::<Result<_, _>>()?,
+                ),
+                19 => NvValue::List(self.list(depth + 1)?),
+                20 => NvValue::Lists(
+                    (0..count)
+                        .map(|_| self.list(depth + 1))
+                        .collect::<Result<_, _>>()?,
+                ),
+                other => {
+                    self.pos = start
+                        .checked_add(encoded)
+                        .filter(|&end| end <= self.data.len())
+                        .ok_or("truncated nvlist")?;
+                    NvValue::Unsupported(other)
+                }
+            };
+            list.push((name, value));
+        }
+    }
+}
+
+/// Decode a packed nvlist, which must be XDR-encoded.
+fn unpack_nvlist(packed: &[u8]) -> Result<NvList, Box<dyn Error>> {
+    match packed.first() {
+        Some(1) => Xdr {
+            data: packed,
+            pos: 4,
+        }
+        .list(0),
+        Some(encoding) => Err(format!("unsupported nvlist encoding {}", encoding).into()),
+        None => Err("empty nvlist".into()),
+    }
+}
+
+/// A block pointer (`blkptr_t`): up to three copies (DVAs) of a block, its sizes,
+/// compression and level, or the block itself when embedded.
+#[derive(Clone, Copy)]
+struct BlockPointer([u8; BLKPTR_SIZE]);
+
+impl BlockPointer {
+    fn parse(bytes: &[u8]) -> Result<Self, Box<dyn Error>> {
+        Ok(Self(
+            bytes
+                .get(..BLKPTR_SIZE)
+                .ok_or("truncated block pointer")?
+                .try_into()
+                .unwrap(),
+        ))
+    }
+
+    fn word(&self, index: usize) -> u64 {
+        le_u64(&self.0, index * 8)
+    }
+
+    fn properties(&self) -> u64 {
+        self.word(6)
+    }
+
+    fn is_embedded(&self) -> bool {
+        self.properties() >> 39 & 1 == 1
+    }
+
+    fn is_hole(&self) -> bool {
+        !self.is_embedded() && self.word(0) == 0 && self.word(1) == 0
+    }
+
+    fn is_encrypted(&self) -> bool {
+        self.properties() >> 61 & 1 == 1
+    }
+
+    fn compression(&self) -> u8 {
+        (self.properties() >> 32 & 0x7f) as u8
+    }
+
+    fn logical_size(&self) -> usize {
+        let properties = self.properties();
+        match self.is_embedded() {
+            true => (properties & 0x1ff_ffff) as usize + 1,
+            false => ((properties & 0xffff) as usize + 1) << 9,
+        }
+    }
+
+    fn physical_size(&self) -> usize {
+        let properties = self.properties();
+        match self.is_embedded() {
+            true => (properties >> 25 & 0x7f) as usize + 1,
+            false => ((properties >> 16 & 0xffff) as usize + 1) << 9,
+        }
+    }
+
+    /// Vdev, byte offset (from the start of the vdev) and gang bit of each copy.
+    fn copies(&self) -> impl Iterator<Item = (u64, u64, bool)> + '_ {
+        (0..3).filter_map(|i| {
+            let (w0, w1) = (self.word(2 * i), self.word(2 * i + 1));
+            (w0 != 0 || w1 != 0).then_some((
+                w0 >> 32,
+                DATA_START + ((w1 & !(1 << 63)) << 9),
+                w1 >> 63 == 1,
+            ))
+        })
+    }
+
+    /// Data of an embedded block pointer: every word but the properties and the birth.
+    fn embedded_payload(&self) -> Vec<u8> {
+        [0..48, 56..80, 88..128]
+            .into_iter()
+            .flat_map(|range| self.0[range].to_vec())
+            .collect()
+    }
+
+    fn cache_key(&self) -> [u64; 4] {
+        [self.word(0), self.word(1), self.word(6), self.word(10)]
+    }
+}
+
+impl std::fmt::Debug for BlockPointer {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("BlockPointer")
+            .field("copies", &self.copies().collect::<Vec<_>>())
+            .field("logical_size", &self.logical_size())
+            .field("compression", &self.compression())
+            .field("embedded", &self.is_embedded())
+            .finish()
+    }
+}
+
+/// Expand a block of `compression` (`zio_compress`) to `logical` bytes.
+fn decompress(data: &[u8], compression: u8, logical: usize) -> Result<Vec<u8>, Box<dyn Error>> {
+    match compression {
+        // Inherited and "on" only appear in properties, never in block pointers.
+        0 | 2 => {
+            let mut out = data[..data.len().min(logical)].to_vec();
+            out.resize(logical, 0);
+            Ok(out)
+        }
+        3 => compression::lzjb(data, logical),
+        4 => Ok(vec![0; logical]),
+        5..=13 => compression::zlib(data, logical),
+        14 => compression::zle(data, logical, 64),
+        15 => {
+            let length =
+                u32::from_be_bytes(data.get(..4).ok_or("truncated LZ4 block")?.try_into()?);
+            let block = data
+                .get(4..4 + length as usize)
+                .ok_or("truncated LZ4 block")?;
+            compression::lz4_block(block, logical)
+        }
+        16 => {
+            let length =
+                u32::from_be_bytes(data.get(..4).ok_or("truncated zstd block")?.try_into()?);
+            let frame = data
+                .get(8..8 + length as usize)
+                .ok_or("truncated zstd block")?;
+            compression::zstd(frame, logical)
+        }
+        other => Err(format!("unsupported ZFS compression {}", other).into()),
+    }
+}
+
+/// An object (`dnode_phys_t`): its type, how its data blocks are mapped and its bonus
+/// buffer (znode, system attributes, DSL directory or dataset).
+#[derive(Debug, Clone, Default, Serialize)]
+pub struct Dnode {
+    #[serde(rename = "type")]
+    pub object_type: u8,
+    pub levels: u8,
+    pub bonus_type: u8,
+    pub data_block_size: u64,
+    pub indirect_block_shift: u8,
+    pub max_block_id: u64,
+    /// Bytes allocated to the object.
+    pub used: u64,
+    pub flags: u8,
+    #[serde(skip)]
+    blkptrs: Vec<BlockPointer>,
+    #[serde(skip)]
+    bonus: Vec<u8>,
+    #[serde(skip)]
+    spill: Option<BlockPointer>,
+}
+
+impl Dnode {
+    fn parse(bytes: &[u8]) -> Result<Self, Box<dyn Error>> {
+        if bytes.len() < DNODE_SIZE {
+            return Err("truncated dnode".into());
+        }
+        let size = (1 + bytes[12] as usize) * DNODE_SIZE;
+        let bytes = bytes.get(..size).ok_or("truncated dnode")?;
+        let (nblkptr, flags) = (bytes[3] as usize, bytes[7]);
+        let spill = flags & DNODE_FLAG_SPILL_BLKPTR != 0;
+        let bonus_start = 64 + nblkptr * BLKPTR_SIZE;
+        let bonus_end = size - if spill { BLKPTR_SIZE } else { 0 };
+        if nblkptr == 0 || bonus_start > bonus_end {
+            return Err(format!("dnode with {} block pointers", nblkptr).into());
+        }
+        let bonus_length = (le_u16(bytes, 10) as usize).min(bonus_end - bonus_start);
+        let sectors = le_u16(bytes, 8) as u64;
+        let used = le_u64(bytes, 24);
+        Ok(Self {
+            object_type: bytes[0],
+            indirect_block_shift: bytes[1],
+            levels: bytes[2].max(1),
+            bonus_type: bytes[4],
+            flags,
+            data_block_size: sectors << 9,
+            max_block_id: le_u64(bytes, 16),
+            used: if flags & DNODE_FLAG_USED_BYTES != 0 {
+                used
+            } else {
+                used << 9
+            },
+            blkptrs: (0..nblkptr)
+                .map(|i| BlockPointer::parse(&bytes[64 + i * BLKPTR_SIZE..]))
+                .collect::<Result<_, _>>()?,
+            bonus: bytes[bonus_start..bonus_start + bonus_length].to_vec(),
+            spill: match spill {
+                true => Some(BlockPointer::parse(&bytes[size - BLKPTR_SIZE..])?),
+                false => None,
+            },
+        })
+    }
+}
+
+/// An entry of a ZAP object: its name and integers of `int_size` bytes.
+#[derive(Debug, Clone)]
+struct ZapEntry {
+    name: Vec<u8>,
+    int_size: u8,
+    values: Vec<u64>,
+}
+
+impl ZapEntry {
+    fn first(&self) -> u64 {
+        self.values.first().copied().unwrap_or(0)
+    }
+
+    /// Value of a string entry (integers of one byte, NUL-terminated).
+    fn string(&self) -> String {
+        let bytes: Vec<u8> = self.values.iter().map(|&v| v as u8).collect();
+        let end = bytes.iter().position(|&b| b == 0).unwrap_or(bytes.len());
+        String::from_utf8_lossy(&bytes[..end]).into_owned()
+    }
+}
+
+fn zap_get(entries: &[ZapEntry], name: &str) -> Option<u64> {
+    entries
+        .iter()
+        .find(|e| e.name == name.as_bytes())
+        .map(ZapEntry::first)
+}
+
+/// Bytes of the chained array chunks of a fat ZAP leaf starting at `chunk`.
+fn zap_leaf_array(
+    leaf: &[u8],
+    chunks_at: usize,
+    chunk_count: usize,
+    mut chunk: usize,
+    length: usize,
+) -> Result<Vec<u8>, Box<dyn Error>> {
+    let mut out = Vec::with_capacity(length);
+    while out.len() < length {
+        if chunk >= chunk_count {
+            return Err("ZAP leaf array chunk out of range".into());
+        }
+        let at = chunks_at + chunk * ZAP_CHUNK_SIZE;
+        let bytes = &leaf[at..at + ZAP_CHUNK_SIZE];
+        if bytes[0] != ZAP_CHUNK_ARRAY {
+            return Err("ZAP leaf array chain is broken".into());
+        }
+        let take = ZAP_ARRAY_BYTES.min(length - out.len());
+        out.extend_from_slice(&bytes[1..1 + take]);
+        chunk = le_u16(bytes, 22) as usize;
+    }
+    Ok(out)
+}
+
+/// Entries of one fat ZAP leaf block. Names are NUL-terminated, values big-endian.
+fn zap_leaf_entries(leaf: &[u8], entries: &mut Vec<ZapEntry>) -> Result<(), Box<dyn Error>> {
+    let shift = leaf.len().trailing_zeros() as usize;
+    if !leaf.len().is_power_of_two() || shift < 9 {
+        return Err("ZAP leaf of an odd size".into());
+    }
+    let hash_entries = 1 << (shift - 5);
+    let chunks_at = 48 + 2 * hash_entries;
+    let chunk_count = (leaf.len() - 2 * hash_entries) / ZAP_CHUNK_SIZE - 2;
+    for i in 0..chunk_count {
+        let at = chunks_at + i * ZAP_CHUNK_SIZE;
+        let chunk = &leaf[at..at + ZAP_CHUNK_SIZE];
+        if chunk[0] != ZAP_CHUNK_ENTRY {
+            continue;
+        }
+        let int_size = chunk[1];
+        if !matches!(int_size, 1 | 2 | 4 | 8) {
+            continue;
+        }
+        let name_length = le_u16(chunk, 6) as usize;
+        let value_count = le_u16(chunk, 10) as usize;
+        let mut name = zap_leaf_array(
+            leaf,
+            chunks_at,
+            chunk_count,
+            le_u16(chunk, 4) as usize,
+            name_length,
+        )?;
+        if name.last() == Some(&0) {
+            name.pop();
+        }
+        let raw = zap_leaf_array(
+            leaf,
+            chunks_at,
+            chunk_count,
+            le_u16(chunk, 8) as usize,
+            value_count * int_size as usize,
+        )?;
+        let values = raw
+            .chunks_exact(int_size as usize)
+            .map(|int| int.iter().fold(0u64, |v, &b| v << 8 | b as u64))
+            .collect();
+        entries.push(ZapEntry {
+            name,
+            int_size,
+            values,
+        });
+    }
+    Ok(())
+}
+
+/// System attributes (`sa_attr_type_t` name and length, 0 when variable) by attribute
+/// number, and attribute numbers of each layout.
+#[derive(Debug, Clone, Default)]
+struct SaTables {
+    registry: HashMap<u64, (String, usize)>,
+    layouts: HashMap<u64, Vec<u64>>,
+}
+
+/// Attributes of a file (`znode_phys_t`, or its system attributes).
+#[derive(Debug, Clone, Default, Serialize)]
+pub struct Znode {
+    pub mode: u64,
+    pub size: u64,
+    pub uid: u64,
+    pub gid: u64,
+    pub links: u64,
+    pub parent: u64,
+    pub flags: u64,
+    pub generation: u64,
+    pub rdev: u64,
+    /// Object of the hidden directory holding the extended attributes stored as files.
+    pub xattr_directory: u64,
+    /// Seconds and nanoseconds.
+    pub atime: [u64; 2],
+    pub mtime: [u64; 2],
+    pub ctime: [u64; 2],
+    pub crtime: [u64; 2],
+    pub project_id: Option<u64>,
+    /// Whether it was read from system attributes rather than a legacy znode.
+    pub system_attributes: bool,
+    #[serde(skip)]
+    symlink: Option<Vec<u8>>,
+    /// Packed nvlist of the extended attributes stored as system attributes.
+    #[serde(skip)]
+    sa_xattrs: Option<Vec<u8>>,
+}
+
+impl Znode {
+    /// Legacy `znode_phys_t` bonus buffer, the symlink target following it when it fits.
+    fn from_legacy(bonus: &[u8]) -> Result<Self, Box<dyn Error>> {
+        if bonus.len() < 176 {
+            return Err("truncated znode".into());
+        }
+        let pair = |at| [le_u64(bonus, at), le_u64(bonus, at + 8)];
+        let mut znode = Self {
+            atime: pair(0),
+            mtime: pair(16),
+            ctime: pair(32),
+            crtime: pair(48),
+            generation: le_u64(bonus, 64),
+            mode: le_u64(bonus, 72),
+            size: le_u64(bonus, 80),
+            parent: le_u64(bonus, 88),
+            links: le_u64(bonus, 96),
+            xattr_directory: le_u64(bonus, 104),
+            rdev: le_u64(bonus, 112),
+            flags: le_u64(bonus, 120),
+            uid: le_u64(bonus, 128),
+            gid: le_u64(bonus, 136),
+            ..Default::default()
+        };
+        if unix_ftype(znode.mode as u32) == "symlink" {
+            znode.symlink = bonus
+                .get(264..264 + znode.size as usize)
+                .map(<[u8]>::to_vec);
+        }
+        Ok(znode)
+    }
+
+    fn apply_attribute(&mut self, name: &str, value: &[u8]) {
+        let u64_at = |at: usize| value.get(at..at + 8).map_or(0, |b| le_u64(b, 0));
+        let pair = || [u64_at(0), u64_at(8)];
+        match name {
+            "ZPL_MODE" => self.mode = u64_at(0),
+            "ZPL_SIZE" => self.size = u64_at(0),
+            "ZPL_UID" => self.uid = u64_at(0),
+            "ZPL_GID" => self.gid = u64_at(0),
+            "ZPL_LINKS" => self.links = u64_at(0),
+            "ZPL_PARENT" => self.parent = u64_at(0),
+            "ZPL_FLAGS" => self.flags = u64_at(0),
+            "ZPL_GEN" => self.generation = u64_at(0),
+            "ZPL_RDEV" => self.rdev = u64_at(0),
+            "ZPL_XATTR" => self.xattr_directory = u64_at(0),
+            "ZPL_PROJID" => self.project_id = Some(u64_at(0)),
+            "ZPL_ATIME" => self.atime = pair(),
+            "ZPL_MTIME" => self.mtime = pair(),
+            "ZPL_CTIME" => self.ctime = pair(),
+            "ZPL_CRTIME" => self.crtime = pair(),
+            "ZPL_SYMLINK" => self.symlink = Some(value.to_vec()),
+            "ZPL_DXATTR" => self.sa_xattrs = Some(value.to_vec()),
+            _ => {}
+        }
+    }
+
+    /// Apply the system attributes of one SA buffer (bonus or spill block).
+    fn apply_sa(&mut self, buffer: &[u8], tables: &SaTables) -> Result<(), Box<dyn Error>> {
+        if buffer.len() < 8 || le_u32(buffer, 0) != SA_MAGIC {
+            return Err("bad system attribute header".into());
+        }
+        let info = le_u16(buffer, 4) as u64;
+        let layout = tables
+            .layouts
+            .get(&(info & 0x3ff))
+            .ok_or_else(|| format!("unknown system attribute layout {}", info & 0x3ff))?;
+        let mut offset = ((info >> 10) & 0x3f) as usize * 8;
+        let mut variable = 0;
+        for number in layout {
+            let (name, length) = tables
+                .registry
+                .get(number)
+                .ok_or_else(|| format!("unregistered system attribute {}", number))?;
+            let length = match *length {
+                0 => {
+                    let at = 6 + 2 * variable;
+                    variable += 1;
+                    buffer
+                        .get(at..at + 2)
+                        .map(|b| le_u16(b, 0) as usize)
+                        .ok_or("truncated system attribute lengths")?
+                }
+                length => length,
+            };
+            let value = buffer
+                .get(offset..offset + length)
+                .ok_or("system attribute past the end of its buffer")?;
+            self.apply_attribute(name, value);
+            offset += length.div_ceil(8) * 8;
+        }
+        self.system_attributes = true;
+        Ok(())
+    }
+}
+
+/// A record of the selected dataset: its object number, dnode and attributes.
+#[derive(Debug, Clone)]
+pub struct ZfsFile {
+    pub object: u64,
+    pub dnode: Dnode,
+    pub znode: Znode,
+}
+
+impl FileCommon for ZfsFile {
+    fn id(&self) -> u64 {
+        self.object
+    }
+    fn size(&self) -> u64 {
+        self.znode.size
+    }
+    fn is_dir(&self) -> bool {
+        unix_ftype(self.znode.mode as u32) == "dir"
+    }
+    fn to_string(&self) -> String {
+        format!(
+            "ZfsFile {{ object: {}, mode: {:o}, size: {} }}",
+            self.object, self.znode.mode, self.znode.size
+        )
+    }
+    fn to_json(&self) -> Value {
+        json!({
+            "object": self.object,
+            "dnode": self.dnode,
+            "znode": self.znode,
+        })
+    }
+}
+
+/// An entry of a directory ZAP.
+#[derive(Debug, Clone)]
+pub struct ZfsDirEntry {
+    pub object: u64,
+    pub name: String,
+    /// `DT_*` type recorded in the entry.
+    pub kind: u8,
+}
+
+impl DirectoryCommon for ZfsDirEntry {
+    fn file_id(&self) -> u64 {
+        self.object
+    }
+    fn name(&self) -> &str {
+        &self.name
+    }
+    fn to_string(&self) -> String {
+        format!(
+            "ZfsDirEntry {{ object: {}, name: {}, kind: {} }}",
+            self.object, self.name, self.kind
+        )
+    }
+    fn to_json(&self) -> Value {
+        json!({
+            "object": self.object,
+            "name": self.name,
+            "kind": self.kind,
+        })
+    }
+}
+
+/// A dataset or snapshot of the pool.
+#[derive(Debug, Clone, Serialize)]
+pub struct Dataset {
+    /// `pool/fs`, or `pool/fs@snapshot`.
+    pub name: String,
+    pub dsl_directory: u64,
+    pub dsl_dataset: u64,
+    pub snapshot: bool,
+    pub guid: u64,
+    pub created: u64,
+    pub referenced_bytes: u64,
+    pub mountpoint: Option<String>,
+    #[serde(skip)]
+    objset: BlockPointer,
+}
+
+#[derive(Debug, Clone, Copy, Serialize)]
+struct Uberblock {
+    version: u64,
+    txg: u64,
+    guid_sum: u64,
+    timestamp: u64,
+    #[serde(skip)]
+    root: BlockPointer,
+}
+
+pub struct ZfsFS<T: Read + Seek> {
+    body: T,
+    config: NvList,
+    uberblock: Uberblock,
+    ashift: u32,
+    /// Meta dnode of the meta object set.
+    mos: Dnode,
+    datasets: Vec<Dataset>,
+    /// Index of the selected dataset in `datasets`.
+    selected: usize,
+    /// Meta dnode of the object set of the selected dataset.
+    objset: Dnode,
+    root: u64,
+    zpl_version: u64,
+    sa: Option<SaTables>,
+    cache: HashMap<[u64; 4], Vec<u8>>,
+}
+
+impl<T: Read + Seek> ZfsFS<T> {
+    /// Open the pool on `body`, a vdev from its first byte, and select its root dataset.
+    pub fn new(mut body: T) -> Result<Self, Box<dyn Error>> {
+        let size = body.seek(SeekFrom::End(0))?;
+        let mut labels = vec![0, LABEL_SIZE];
+        if size >= 4 * LABEL_SIZE {
+            labels.extend([size - 2 * LABEL_SIZE, size - LABEL_SIZE]);
+        }
+        let mut config = None;
+        let mut best: Option<Uberblock> = None;
+        let mut big_endian = false;
+        for start in labels {
+            let mut label = vec![0u8; LABEL_SIZE as usize];
+            body.seek(SeekFrom::Start(start))?;
+            if body.read_exact(&mut label).is_err() {
+                continue;
+            }
+            if config.is_none() {
+                let packed = &label[LABEL_NVLIST_OFFSET..LABEL_NVLIST_OFFSET + LABEL_NVLIST_SIZE];
+                match unpack_nvlist(packed) {
+                    Ok(list) => config = Some(list),
+                    Err(e) => debug!("ZFS label at {}: {}", start, e),
+                }
+            }
+            for slot in label[UBERBLOCK_RING_OFFSET..].chunks_exact(UBERBLOCK_SLOT) {
+                match le_u64(slot, 0) {
+                    UBERBLOCK_MAGIC => {}
+                    magic if magic.swap_bytes() == UBERBLOCK_MAGIC => {
+                        big_endian = true;
+                        continue;
+                    }
+                    _ => continue,
+                }
+                let uberblock = Uberblock {
+                    version: le_u64(slot, 8),
+                    txg: le_u64(slot, 16),
+                    guid_sum: le_u64(slot, 24),
+                    timestamp: le_u64(slot, 32),
+                    root: BlockPointer::parse(&slot[40..])?,
+                };
+                if best.is_none_or(|b| (uberblock.txg, uberblock.timestamp) > (b.txg, b.timestamp))
+                {
+                    best = Some(uberblock);
+                }
+            }
+        }
+        let uberblock = match best {
+            Some(uberblock) => uberblock,
+            None if big_endian => return Err("big-endian ZFS pools are not supported".into()),
+            None => return Err("no ZFS uberblock found".into()),
+        };
+        let config = config.ok_or("no readable ZFS label configuration")?;
+        let vdev_tree = match nv_get(&config, "vdev_tree") {
+            Some(NvValue::List(tree)) => tree.clone(),
+            _ => Vec::new(),
+        };
+        if let Some(NvValue::String(kind)) = nv_get(&vdev_tree, "type")
+            && (kind.starts_with("raidz") || kind.starts_with("draid"))
+        {
+            return Err(format!("{} vdevs are not supported", kind).into());
+        }
+        let ashift = match nv_get(&vdev_tree, "ashift") {
+            Some(NvValue::Uint(ashift)) => *ashift as u32,
+            _ => 9,
+        };
+
+        let mut fs = Self {
+            body,
+            config,
+            uberblock,
+            ashift,
+            mos: Dnode::default(),
+            datasets: Vec::new(),
+            selected: 0,
+            objset: Dnode::default(),
+            root: 0,
+            zpl_version: 0,
+            sa: None,
+            cache: HashMap::new(),
+        };
+        fs.mos = fs.open_objset(&uberblock.root)?.0;
+        let mos = fs.mos.clone();
+        let directory = fs.dnode(&mos, DIRECTORY_OBJECT)?;
+        let entries = fs.zap(&directory)?;
+        let root_dir = zap_get(&entries, "root_dataset").ok_or("no root dataset in the pool")?;
+        let pool = match nv_get(&fs.config, "name") {
+            Some(NvValue::String(name)) => name.clone(),
+            _ => "pool".to_string(),
+        };
+        let mut visited = HashSet::new();
+        fs.list_datasets(root_dir, &pool, 0, &mut visited)?;
+        let root_name = fs
+            .datasets
+            .first()
+            .map(|d| d.name.clone())
+            .ok_or("the pool has no root dataset")?;
+        fs.select_dataset(&root_name)?;
+        Ok(fs)
+    }
+
+    /// Datasets and snapshots of the pool, the root dataset first.
+    pub fn datasets(&self) -> &[Dataset] {
+        &self.datasets
+    }
+
+    /// Walk `name` (`pool/fs` or `pool/fs@snapshot`) instead of the current dataset.
+    pub fn select_dataset(&mut self, name: &str) -> Result<(), Box<dyn Error>> {
+        let index = self
+            .datasets
+            .iter()
+            .position(|d| d.name == name)
+            .ok_or_else(|| {
+                format!(
+                    "no dataset '{}' in the pool (datasets: {})",
+                    name,
+                    self.datasets
+                        .iter()
+                        .map(|d| d.name.as_str())
+                        .collect::<Vec<_>>()
+                        .join(", ")
+                )
+            })?;
+        let bp = self.datasets[index].objset;
+        let (objset, objset_type) = self.open_objset(&bp)?;
+        if objset_type != OBJSET_TYPE_ZFS {
+            return Err(format!(
+                "dataset '{}' is not a filesystem (object set type {})",
+                name, objset_type
+            )
+            .into());
+        }
+        let master = self.dnode(&objset, MASTER_NODE_OBJECT)?;
+        let entries = self.zap(&master)?;
+        let root = zap_get(&entries, "ROOT").ok_or("no root directory in the dataset")?;
+        let sa = match zap_get(&entries, "SA_ATTRS") {
+            Some(object) => Some(self.sa_tables(&objset, object)?),
+            None => None,
+        };
+        self.selected = index;
+        self.objset = objset;
+        self.root = root;
+        self.zpl_version = zap_get(&entries, "VERSION").unwrap_or(0);
+        self.sa = sa;
+        Ok(())
+    }
+
+    fn sa_tables(&mut self, objset: &Dnode, object: u64) -> Result<SaTables, Box<dyn Error>> {
+        let master = self.dnode(objset, object)?;
+        let entries = self.zap(&master)?;
+        let mut tables = SaTables::default();
+        if let Some(registry) = zap_get(&entries, "REGISTRY") {
+            let registry = self.dnode(objset, registry)?;
+            for entry in self.zap(&registry)? {
+                let value = entry.first();
+                tables.registry.insert(
+                    value & 0xffff,
+                    (
+                        String::from_utf8_lossy(&entry.name).into_owned(),
+                        (value >> 24 & 0xffff) as usize,
+                    ),
+                );
+            }
+        }
+        if let Some(layouts) = zap_get(&entries, "LAYOUTS") {
+            let layouts = self.dnode(objset, layouts)?;
+            for entry in self.zap(&layouts)? {
+                if let Ok(number) = String::from_utf8_lossy(&entry.name).parse::<u64>() {
+                    tables.layouts.insert(number, entry.values);
+                }
+            }
+        }
+        Ok(tables)
+    }
+
+    /// Add the dataset of DSL directory `dir`, its snapshots and its children.
+    fn list_datasets(
+        &mut self,
+        dir: u64,
+        name: &str,
+        depth: usize,
+        visited: &mut HashSet<u64>,
+    ) -> Result<(), Box<dyn Error>> {
+        if depth > MAX_DATASET_DEPTH || !visited.insert(dir) {
+            return Err("DSL directories loop".into());
+        }
+        let mos = self.mos.clone();
+        let dnode = self.dnode(&mos, dir)?;
+        let bonus = &dnode.bonus;
+        if bonus.len() < 88 {
+            return Err(format!("DSL directory {} has no attributes", dir).into());
+        }
+        let (head, children, props) = (le_u64(bonus, 8), le_u64(bonus, 32), le_u64(bonus, 80));
+        let mountpoint = match props {
+            0 => None,
+            props => {
+                let props = self.dnode(&mos, props)?;
+                self.zap(&props)?
+                    .iter()
+                    .find(|e| e.name == b"mountpoint" && e.int_size == 1)
+                    .map(ZapEntry::string)
+            }
+        };
+        if head != 0 {
+            let (dataset, snapshots) = self.dataset(head, name, false, mountpoint)?;
+            self.datasets.push(dataset);
+            if snapshots != 0 {
+                let snapshots = self.dnode(&mos, snapshots)?;
+                let mut entries = self.zap(&snapshots)?;
+                entries.sort_by(|a, b| a.name.cmp(&b.name));
+                for entry in entries {
+                    let snapshot = format!("{}@{}", name, String::from_utf8_lossy(&entry.name));
+                    match self.dataset(entry.first(), &snapshot, true, None) {
+                        Ok((dataset, _)) => self.datasets.push(dataset),
+                        Err(e) => warn!("Skipping ZFS snapshot {}: {}", snapshot, e),
+                    }
+                }
+            }
+        }
+        if children != 0 {
+            let children = self.dnode(&mos, children)?;
+            let mut entries = self.zap(&children)?;
+            entries.sort_by(|a, b| a.name.cmp(&b.name));
+            for entry in entries {
+                // $MOS, $FREE and $ORIGIN hold pool bookkeeping, not datasets.
+                if entry.name.first() == Some(&b'$') {
+                    continue;
+                }
+                let child = format!("{}/{}", name, String::from_utf8_lossy(&entry.name));
+                if let Err(e) = self.list_datasets(entry.first(), &child, depth + 1, visited) {
+                    warn!("Skipping ZFS dataset {}: {}", child, e);
+                }
+            }
+        }
+        Ok(())
+    }
+
+    /// DSL dataset `object` and the ZAP of its snapshots.
+    fn dataset(
+        &mut self,
+        object: u64,
+        name: &str,
+        snapshot: bool,
+        mountpoint: Option<String>,
+    ) -> Result<(Dataset, u64), Box<dyn Error>> {
+        let mos = self.mos.clone();
+        let dnode = self.dnode(&mos, object)?;
+        let bonus = &dnode.bonus;
+        if bonus.len() < 256 {
+            return Err(format!("DSL dataset {} has no attributes", object).into());
+        }
+        Ok((
+            Dataset {
+                name: name.to_string(),
+                dsl_directory: le_u64(bonus, 0),
+                dsl_dataset: object,
+                snapshot,
+                guid: le_u64(bonus, 112),
+                created: le_u64(bonus, 48),
+                referenced_bytes: le_u64(bonus, 72),
+                mountpoint,
+                objset: BlockPointer::parse(&bonus[128..])?,
+            },
+            le_u64(bonus, 32),
+        ))
+    }
+
+    /// Read (and expand) the block `bp` points to.
+    fn read_block(&mut self, bp: &BlockPointer) -> Result<Vec<u8>, Box<dyn Error>> {
+        let logical = bp.logical_size();
+        if bp.is_hole() {
+            return Ok(vec![0; logical]);
+        }
+        if bp.is_embedded() {
+            let payload = bp.embedded_payload();
+            let physical = bp.physical_size().min(payload.len());
+            return decompress(&payload[..physical], bp.compression(), logical);
+        }
+        if bp.is_encrypted() {
+            return Err("encrypted ZFS blocks are not supported".into());
+        }
+        let mut last_error: Box<dyn Error> = "block pointer without a copy".into();
+        for (vdev, offset, gang) in bp.copies() {
+            if gang {
+                last_error = "ZFS gang blocks are not supported".into();
+                continue;
+            }
+            if vdev != 0 {
+                last_error = format!("block on vdev {}, only vdev 0 is read", vdev).into();
+                continue;
+            }
+            let mut data = vec![0u8; bp.physical_size()];
+            let read = self
+                .body
+                .seek(SeekFrom::Start(offset))
+                .and_then(|_| self.body.read_exact(&mut data));
+            match read {
+                Ok(()) => match decompress(&data, bp.compression(), logical) {
+                    Ok(block) => return Ok(block),
+                    Err(e) => last_error = e,
+                },
+                Err(e) => last_error = e.into(),
+            }
+        }
+        Err(last_error)
+    }
+
+    /// `read_block`, through the cache of metadata blocks.
+    fn cached_block(&mut self, bp: &BlockPointer) -> Result<&[u8], Box<dyn Error>> {
+        let key = bp.cache_key();
+        if !self.cache.contains_key(&key) {
+            let block = self.read_block(bp)?;
+            if self.cache.len() >= CACHE_BLOCKS {
+                self.cache.clear();
+            }
+            self.cache.insert(key, block);
+        }
+        Ok(&self.cache[&key])
+    }
+
+    /// Meta dnode and type of the object set `bp` points to.
+    fn open_objset(&mut self, bp: &BlockPointer) -> Result<(Dnode, u64), Box<dyn Error>> {
+        let block = self.read_block(bp)?;
+        if block.len() < 712 {
+            return Err("truncated object set".into());
+        }
+        Ok((Dnode::parse(&block[..DNODE_SIZE])?, le_u64(&block, 704)))
+    }
+
+    /// Block pointer of data block `blkid` of an object, `None` past its last block.
+    fn block_pointer(
+        &mut self,
+        dnode: &Dnode,
+        blkid: u64,
+    ) -> Result<Option<BlockPointer>, Box<dyn Error>> {
+        if blkid > dnode.max_block_id {
+            return Ok(None);
+        }
+        let per_block_shift = (dnode.indirect_block_shift as u32)
+            .checked_sub(7)
+            .filter(|&s| s > 0 && s < 24)
+            .ok_or("bad indirect block size")?;
+        let levels = dnode.levels as u32;
+        let top_shift = per_block_shift * (levels - 1);
+        let top = blkid.checked_shr(top_shift).unwrap_or(0) as usize;
+        let Some(mut bp) = dnode.blkptrs.get(top).copied() else {
+            return Ok(None);
+        };
+        for level in (0..levels - 1).rev() {
+            if bp.is_hole() {
+                return Ok(Some(bp));
+            }
+            let index =
+                (blkid >> (per_block_shift * level) & ((1 << per_block_shift) - 1)) as usize;
+            let block = self.cached_block(&bp)?;
+            bp = BlockPointer::parse(
+                block
+                    .get(index * BLKPTR_SIZE..)
+                    .ok_or("indirect block too small")?,
+            )?;
+        }
+        Ok(Some(bp))
+    }
+
+    /// `length` bytes of the data of an object from `offset`, holes read as zeros.
+    fn read_object(
+        &mut self,
+        dnode: &Dnode,
+        offset: u64,
+        length: usize,
+    ) -> Result<Vec<u8>, Box<dyn Error>> {
+        let block_size = dnode.data_block_size.max(512);
+        let mut out = vec![0u8; length];
+        let mut done = 0;
+        while done < length {
+            let position = offset + done as u64;
+            let (blkid, within) = (position / block_size, (position % block_size) as usize);
+            let count = (block_size as usize - within).min(length - done);
+            if let Some(bp) = self.block_pointer(dnode, blkid)?
+                && !bp.is_hole()
+            {
+                let block = self.read_block(&bp)?;
+                if let Some(data) = block.get(within..) {
+                    let n = data.len().min(count);
+                    out[done..done + n].copy_from_slice(&data[..n]);
+                }
+            }
+            done += count;
+        }
+        Ok(out)
+    }
+
+    /// Object `object` of the object set whose meta dnode is `meta`.
+    fn dnode(&mut self, meta: &Dnode, object: u64) -> Result<Dnode, Box<dyn Error>> {
+        let block_size = meta.data_block_size.max(DNODE_SIZE as u64);
+        let position = object
+            .checked_mul(DNODE_SIZE as u64)
+            .ok_or("object number out of range")?;
+        let (blkid, within) = (position / block_size, (position % block_size) as usize);
+        let bp = self
+            .block_pointer(meta, blkid)?
+            .ok_or_else(|| format!("object {} out of range", object))?;
+        let block = self.cached_block(&bp)?;
+        let bytes = block
+            .get(within..)
+            .ok_or_else(|| format!("object {} out of range", object))?;
+        if bytes.first() == Some(&0) {
+            return Err(format!("object {} is free", object).into());
+        }
+        Dnode::parse(bytes)
+    }
+
+    /// Entries of a ZAP object, micro or fat.
+    fn zap(&mut self, dnode: &Dnode) -> Result<Vec<ZapEntry>, Box<dyn Error>> {
+        let block_size = dnode.data_block_size as usize;
+        let first = self.read_object(dnode, 0, block_size)?;
+        if first.len() < 64 {
+            return Err("ZAP object too small".into());
+        }
+        let mut entries = Vec::new();
+        match le_u64(&first, 0) {
+            ZAP_MICRO => {
+                for chunk in first[64..].chunks_exact(64) {
+                    let name = &chunk[14..];
+                    let name = &name[..name.iter().position(|&b| b == 0).unwrap_or(name.len())];
+                    if !name.is_empty() {
+                        entries.push(ZapEntry {
+                            name: name.to_vec(),
+                            int_size: 8,
+                            values: vec![le_u64(chunk, 0)],
+                        });
+                    }
+                }
+            }
+            ZAP_HEADER => {
+                if le_u64(&first, 8) != ZAP_MAGIC {
+                    return Err("bad fat ZAP magic".into());
+                }
+                for blkid in 1..=dnode.max_block_id {
+                    let leaf = self.read_object(dnode, blkid * block_size as u64, block_size)?;
+                    if le_u64(&leaf, 0) == ZAP_LEAF && le_u32(&leaf, 24) == ZAP_LEAF_MAGIC {
+                        zap_leaf_entries(&leaf, &mut entries)?;
+                    }
+                }
+            }
+            other => return Err(format!("unknown ZAP block type {:#x}", other).into()),
+        }
+        Ok(entries)
+    }
+
+    /// Attributes of a file from its bonus buffer, and its spill block when it has one.
+    fn znode(&mut self, dnode: &Dnode) -> Result<Znode, Box<dyn Error>> {
+        match dnode.bonus_type {
+            BONUS_ZNODE => Znode::from_legacy(&dnode.bonus),
+            BONUS_SA => {
+                let tables = self
+                    .sa
+                    .clone()
+                    .ok_or("system attributes without registry")?;
+                let mut znode = Znode::default();
+                znode.apply_sa(&dnode.bonus, &tables)?;
+                if let Some(spill) = dnode.spill {
+                    let block = self.read_block(&spill)?;
+                    znode.apply_sa(&block, &tables)?;
+                }
+                Ok(znode)
+            }
+            other => Err(format!("object has no file attributes (bonus type {})", other).into()),
+        }
+    }
+
+    /// Data blocks of an object that are allocated (holes excluded), as runs of block
+    /// ids.
+    fn allocated_runs(&mut self, dnode: &Dnode) -> Result<Vec<(u64, u64)>, Box<dyn Error>> {
+        let per_block_shift = (dnode.indirect_block_shift as u32).saturating_sub(7).max(1);
+        let span_shift = per_block_shift * (dnode.levels as u32 - 1);
+        let mut pending: Vec<(BlockPointer, u32, u64)> = dnode
+            .blkptrs
+            .iter()
+            .enumerate()
+            .map(|(i, bp)| (*bp, dnode.levels as u32 - 1, (i as u64) << span_shift))
+            .collect();
+        let mut blocks = Vec::new();
+        while let Some((bp, level, first)) = pending.pop() {
+            if bp.is_hole() || first > dnode.max_block_id {
+                continue;
+            }
+            if level == 0 {
+                blocks.push(first);
+                continue;
+            }
+            let block = self.cached_block(&bp)?.to_vec();
+            for (i, child) in block.chunks_exact(BLKPTR_SIZE).enumerate() {
+                let child_first = first + ((i as u64) << (per_block_shift * (level - 1)));
+                pending.push((BlockPointer::parse(child)?, level - 1, child_first));
+            }
+        }
+        blocks.sort_unstable();
+        let mut runs: Vec<(u64, u64)> = Vec::new();
+        for blkid in blocks {
+            match runs.last_mut() {
+                Some((start, count)) if *start + *count == blkid => *count += 1,
+                _ => runs.push((blkid, 1)),
+            }
+        }
+        Ok(runs)
+    }
+}
+
+/// Names of the flags set in `zp_flags`.
+pub fn znode_flag_names(flags: u64) -> Vec<&'static str> {
+    ZNODE_FLAGS
+        .iter()
+        .filter(|(bit, _)| flags & bit != 0)
+        .map(|(_, name)| *name)
+        .collect()
+}
+
+impl<T: Read + Seek> Filesystem for ZfsFS<T> {
+    type FileType = ZfsFile;
+    type DirectoryType = ZfsDirEntry;
+
+    fn filesystem_type(&self) -> String {
+        "ZFS".to_string()
+    }
+
+    fn path_separator(&self) -> String {
+        "/".to_string()
+    }
+
+    fn record_count(&mut self) -> u64 {
+        (self.objset.max_block_id + 1) * self.objset.data_block_size / DNODE_SIZE as u64
+    }
+
+    fn block_size(&self) -> u64 {
+        1 << self.ashift
+    }
+
+    fn get_metadata(&self) -> Result<Value, Box<dyn Error>> {
+        let config = nv_json(&self.config);
+        Ok(json!({
+            "pool": config["name"],
+            "pool_guid": config["pool_guid"],
+            "pool_version": config["version"],
+            "uberblock": self.uberblock,
+            "ashift": self.ashift,
+            "dataset": self.datasets[self.selected].name,
+            "zpl_version": self.zpl_version,
+            "system_attributes": self.sa.is_some(),
+            "datasets": self.datasets,
+            "config": config,
+        }))
+    }
+
+    fn get_metadata_pretty(&self) -> Result<String, Box<dyn Error>> {
+        let config = nv_json(&self.config);
+        let mut out = format!(
+            "ZFS pool {} (guid {}, version {}), txg {} written {}\n",
+            config["name"].as_str().unwrap_or("?"),
+            config["pool_guid"],
+            config["version"],
+            self.uberblock.txg,
+            format_timestamp(self.uberblock.timestamp)
+        );
+        out.push_str(&format!(
+            "Vdev: {} (ashift {})\n",
+            config["vdev_tree"]["type"].as_str().unwrap_or("?"),
+            self.ashift
+        ));
+        out.push_str("Datasets:\n");
+        for (i, dataset) in self.datasets.iter().enumerate() {
+            out.push_str(&format!(
+                "  {} {} ({} bytes referenced, created {}){}\n",
+                if i == self.selected { '*' } else { ' ' },
+                dataset.name,
+                dataset.referenced_bytes,
+                format_timestamp(dataset.created),
+                dataset
+                    .mountpoint
+                    .as_deref()
+                    .map(|m| format!(", mounted at {}", m))
+                    .unwrap_or_default()
+            ));
+        }
+        Ok(out)
+    }
+
+    fn get_file(&mut self, object: u64) -> Result<Self::FileType, Box<dyn Error>> {
+        let objset = self.objset.clone();
+        let dnode = self.dnode(&objset, object)?;
+        let znode = self.znode(&dnode)?;
+        Ok(ZfsFile {
+            object,
+            dnode,
+            znode,
+        })
+    }
+
+    fn read_file_content(&mut self, file: &Self::FileType) -> Result<Vec<u8>, Box<dyn Error>> {
+        self.read_file_slice(file, 0, file.znode.size as usize)
+    }
+
+    fn read_file_prefix(
+        &mut self,
+        file: &Self::FileType,
+        length: usize,
+    ) -> Result<Vec<u8>, Box<dyn Error>> {
+        self.read_file_slice(file, 0, length)
+    }
+
+    fn read_file_slice(
+        &mut self,
+        file: &Self::FileType,
+        offset: u64,
+        length: usize,
+    ) -> Result<Vec<u8>, Box<dyn Error>> {
+        let size = file.znode.size;
+        if offset >= size {
+            return Ok(Vec::new());
+        }
+        let length = length.min((size - offset) as usize);
+        // Symbolic link targets kept in the attributes have no data blocks.
+        if let Some(target) = &file.znode.symlink {
+            let start = (offset as usize).min(target.len());
+            return Ok(target[start..(start + length).min(target.len())].to_vec());
+        }
+        self.read_object(&file.dnode, offset, length)
+    }
+
+    fn list_dir(
+        &mut self,
+        file: &Self::FileType,
+    ) -> Result<Vec<Self::DirectoryType>, Box<dyn Error>> {
+        if !file.is_dir() {
+            return Err("not a directory".into());
+        }
+        Ok(self
+            .zap(&file.dnode)?
+            .into_iter()
+            .map(|entry| ZfsDirEntry {
+                object: entry.first() & DIRENT_OBJECT_MASK,
+                name: escape_name(&entry.name),
+                kind: (entry.first() >> 60) as u8,
+            })
+            .collect())
+    }
+
+    fn record_to_file(&self, file: &Self::FileType, file_id: u64, absolute_path: &str) -> File {
+        let znode = &file.znode;
+        let file_type = unix_ftype(znode.mode as u32);
+        let mut common = json!({ FLAGS_KEY: znode_flag_names(znode.flags) });
+        if matches!(file_type, "chardev" | "blockdev") {
+            common[DEVICE_KEY] = json!({
+                "major": znode.rdev >> 32,
+                "minor": znode.rdev & 0xffff_ffff,
+            });
+        }
+        if let Some(target) = &znode.symlink {
+            common[SYMLINK_TARGET_KEY] = json!(render_name(&escape_name(target)));
+        }
+        let metadata = namespaced_metadata(METADATA_NAMESPACE, file.to_json(), common);
+        let name = match absolute_path.rsplit_once('/') {
+            Some((_, name)) if !name.is_empty() => name,
+            _ => absolute_path,
+        };
+        let absolute_path = render_name(absolute_path);
+        let permissions = unix_mode_string(znode.mode as u32);
+        File {
+            id: None,
+            identifier: file_id,
+            absolute_path: absolute_path.to_string(),
+            name: render_name(name).into_owned(),
+            raw_name: name_bytes(name).map(hex::encode),
+            ftype: file_type.to_string(),
+            size: znode.size,
+            size_on_disk: Some(file.dnode.used),
+            created: Some(znode.crtime[0]),
+            modified: Some(znode.mtime[0]),
+            accessed: Some(znode.atime[0]),
+            changed: Some(znode.ctime[0]),
+            permissions: Some(permissions.clone()),
+            owner: Some(znode.uid.to_string()),
+            group: Some(znode.gid.to_string()),
+            display: Some(format!(
+                "[{}] - {} {} {} {} {:>5} {} {}",
+                file_id,
+                permissions,
+                znode.links,
+                znode.uid,
+                znode.gid,
+                znode.size,
+                format_timestamp(znode.mtime[0]),
+                absolute_path
+            )),
+            sig_name: None,
+            sig_mime: None,
+            sig_exts: None,
+            detected_type: None,
+            ext_mismatch: None,
+            md5: None,
+            sha1: None,
+            sha256: None,
+            metadata,
+        }
+    }
+
+    fn get_root_file_id(&self) -> u64 {
+        self.root
+    }
+
+    fn file_holes(
+        &mut self,
+        file: &Self::FileType,
+    ) -> Result<Option<Vec<ByteRange>>, Box<dyn Error>> {
+        if file.is_dir() || file.znode.symlink.is_some() {
+            return Ok(None);
+        }
+        let size = file.znode.size;
+        let block_size = file.dnode.data_block_size;
+        let mut holes = Vec::new();
+        let mut covered = 0;
+        for (first, count) in self.allocated_runs(&file.dnode)? {
+            let start = first * block_size;
+            if start > covered {
+                holes.push((covered, start - covered));
+            }
+            covered = (first + count) * block_size;
+        }
+        if covered < size {
+            holes.push((covered, size - covered));
+        }
+        Ok(Some(
+            holes
+                .into_iter()
+                .filter(|&(offset, _)| offset < size)
+                .map(|(offset, length)| (offset, length.min(size - offset)))
+                .collect(),
+        ))
+    }
+
+    /// Attributes stored as system attributes (`xattr=sa`), then the files of the hidden
+    /// attribute directory.
+    fn extended_attributes(
+        &mut self,
+        file: &Self::FileType,
+    ) -> Result<Vec<ExtendedAttribute>, Box<dyn Error>> {
+        let mut attributes = Vec::new();
+        if let Some(packed) = &file.znode.sa_xattrs {
+            for (name, value) in unpack_nvlist(packed)? {
+                match value {
+                    NvValue::Bytes(bytes) => attributes.push((name, bytes)),
+                    other => debug!("Skipping ZFS xattr {} of type {:?}", name, other),
+                }
+            }
+        }
+        if file.znode.xattr_directory != 0 {
+            let directory = self.get_file(file.znode.xattr_directory)?;
+            for entry in self.list_dir(&directory)? {
+                let attribute = self.get_file(entry.object)?;
+                let value = self.read_file_content(&attribute)?;
+                attributes.push((entry.name, value));
+            }
+        }
+        Ok(attributes)
+    }
+}
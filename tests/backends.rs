@@ -0,0 +1,1002 @@
+//! The same checks against every backend, on images built from `common::sample()`.
+mod common;
+
+use common::{Entry, Node, Scratch};
+use exhume_filesystem::filesystem::{
+    COMMON_KEY, Filesystem, STREAMS_KEY, WalkCheckpoint, WalkEvent, WalkOptions,
+};
+use exhume_filesystem::folder_impl::FolderFS;
+use exhume_filesystem::hashing::HashAlgorithm;
+use exhume_filesystem::{diff, export};
+use flate2::read::GzDecoder;
+use std::collections::BTreeSet;
+use std::io::Read;
+use std::path::Path;
+
+#[test]
+fn folder() {
+    let scratch = Scratch::new("folder");
+    let root = scratch.0.join("tree");
+    let entries: Vec<Entry> = common::sample()
+        .into_iter()
+        .filter(|e| {
+            !matches!(e.node, Node::Stream { .. })
+                && (cfg!(unix) || !matches!(e.node, Node::Symlink(_)))
+        })
+        .collect();
+    common::populate(&root, &entries);
+    for entry in &entries {
+        if let Node::Deleted(_) = entry.node {
+            std::fs::remove_file(root.join(entry.path)).unwrap();
+        }
+    }
+    common::check_tree(&mut FolderFS::new(root), &entries);
+}
+
+#[test]
+fn ext4() {
+    if !common::tools(&["mke2fs", "debugfs"]) {
+        return;
+    }
+    let scratch = Scratch::new("ext4");
+    let entries: Vec<Entry> = common::sample()
+        .into_iter()
+        .filter(|e| !matches!(e.node, Node::Stream { .. }))
+        .collect();
+    let image = common::build_ext4(&scratch.0, &entries).unwrap();
+    let mut fs = common::open_image(&image);
+    common::check_tree(&mut fs, &entries);
+
+    let sparse = fs.get_file_by_path("/sparse.bin", 0).unwrap();
+    let holes = fs
+        .file_holes(&sparse)
+        .unwrap()
+        .expect("ext4 maps its holes");
+    assert!(
+        holes
+            .iter()
+            .any(|&(offset, length)| offset == 0 && length >= 4096),
+        "leading hole of sparse.bin, got {:?}",
+        holes
+    );
+
+    let deleted = fs.enumerate_deleted_files(&mut |_| {}).unwrap();
+    assert!(
+        deleted.iter().any(|f| f.name == "gone.txt"),
+        "gone.txt not recovered, got {:?}",
+        deleted.iter().map(|f| &f.absolute_path).collect::<Vec<_>>()
+    );
+}
+
+#[test]
+fn ext4_hard_links() {
+    if !common::tools(&["mke2fs", "debugfs"]) {
+        return;
+    }
+    let scratch = Scratch::new("ext4-links");
+    let entries = common::sample_without(|node| !matches!(node, Node::File(_) | Node::Dir));
+    let image = common::build_ext4(&scratch.0, &entries).unwrap();
+    let image_str = image.to_str().unwrap();
+    common::run(
+        "debugfs",
+        &["-w", "-R", "ln /hello.txt /docs/again.txt", image_str],
+    )
+    .unwrap();
+    let mut fs = common::open_image(&image);
+
+    // A diff must see the record at both of its paths, whichever is walked first.
+    let index = diff::index_by_path(&mut fs, &[HashAlgorithm::Sha256]).unwrap();
+    let (first, second) = (&index["/hello.txt"], &index["/docs/again.txt"]);
+    assert_eq!(first.identifier, second.identifier);
+    assert!(first.sha256.is_some());
+    assert_eq!(first.sha256, second.sha256);
+    assert!(diff::diff_indexes(&index, &index, &[]).is_empty());
+}
+
+#[test]
+fn ntfs() {
+    if !common::tools(&["mkntfs", "ntfscp"]) {
+        return;
+    }
+    let scratch = Scratch::new("ntfs");
+    let entries: Vec<Entry> = common::sample()
+        .into_iter()
+        .filter(|e| !e.path.contains('/') && matches!(e.node, Node::File(_) | Node::Stream { .. }))
+        .collect();
+    let image = common::build_ntfs(&scratch.0, &entries).unwrap();
+    let mut fs = common::open_image(&image);
+    let files = common::check_tree(&mut fs, &entries);
+
+    let streams = &files["/hello.txt"].metadata[COMMON_KEY][STREAMS_KEY];
+    assert!(
+        streams
+            .as_array()
+            .is_some_and(|s| s.iter().any(|s| s["name"] == "Zone.Identifier")),
+        "Zone.Identifier stream of hello.txt, got {}",
+        streams
+    );
+
+    // Plain walks scan $MFT in record order, sorted ones follow the directory indexes:
+    // both must find the same paths.
+    let mut sorted = BTreeSet::new();
+    let options = WalkOptions {
+        sorted: true,
+        ..Default::default()
+    };
+    fs.walk_fs_with(options, &mut |event| {
+        if let WalkEvent::File(file) = event {
+            sorted.insert(file.absolute_path.replace('\\', "/"));
+        }
+    })
+    .unwrap();
+    assert_eq!(files.keys().cloned().collect::<BTreeSet<_>>(), sorted);
+}
+
+#[test]
+fn ntfs_names() {
+    use exhume_filesystem::ntfs_impl::record_names;
+    use exhume_ntfs::mft::MFTRecord;
+
+    // A long name in the root directory and its 8.3 alias, as Windows creates them.
+    let root = 5 | 5 << 48;
+    let raw = common::ntfs::file_record(&[
+        common::ntfs::file_name(root, 1, "Long file name.txt"),
+        common::ntfs::file_name(root, 2, "LONGFI~1.TXT"),
+    ]);
+    let record = MFTRecord::from_bytes(&raw, Some(64)).unwrap();
+    let names: Vec<_> = record_names(&record)
+        .into_iter()
+        .map(|n| (n.name, n.namespace, n.parent, n.parent_sequence))
+        .collect();
+    assert_eq!(
+        names,
+        vec![
+            ("Long file name.txt".to_string(), "win32", 5, 5),
+            ("LONGFI~1.TXT".to_string(), "dos", 5, 5),
+        ]
+    );
+}
+
+#[test]
+fn ntfs_extended_attributes() {
+    use exhume_filesystem::ntfs_impl::record_eas;
+    use exhume_ntfs::mft::MFTRecord;
+
+    // A $EA entry as WSL writes it: flags, name and value lengths, NUL-terminated name.
+    let mut ea = vec![0u8; 8];
+    ea[5] = b"$LXUID".len() as u8;
+    ea[6] = 4;
+    ea.extend_from_slice(b"$LXUID\0");
+    ea.extend_from_slice(&1000u32.to_le_bytes());
+    let mut information = vec![0u8; 8];
+    information[4..8].copy_from_slice(&(ea.len() as u32).to_le_bytes());
+    let raw = common::ntfs::file_record(&[
+        common::ntfs::file_name(5 | 5 << 48, 1, "wsl.txt"),
+        (common::ntfs::ATTR_EA_INFORMATION, information),
+        (common::ntfs::ATTR_EA, ea),
+    ]);
+    let record = MFTRecord::from_bytes(&raw, Some(64)).unwrap();
+    let eas = record_eas(&record).expect("resident $EA");
+    assert_eq!(eas.len(), 1);
+    assert_eq!(eas[0].name, "$LXUID");
+    assert_eq!(eas[0].value, "e8030000");
+    assert!(!eas[0].need_ea);
+    assert!(eas[0].decoded.is_some(), "WSL uid not decoded");
+}
+
+#[test]
+fn ntfs_special_files() {
+    use exhume_filesystem::ntfs_impl::record_type;
+    use exhume_ntfs::mft::MFTRecord;
+    const IO_REPARSE_TAG_SYMLINK: u32 = 0xA000_000C;
+    const IO_REPARSE_TAG_LX_BLK: u32 = 0x8000_0026;
+
+    let parse = |attributes: &[(u32, Vec<u8>)]| {
+        MFTRecord::from_bytes(&common::ntfs::file_record(attributes), Some(64)).unwrap()
+    };
+    let link = parse(&[
+        common::ntfs::file_name(5 | 5 << 48, 1, "link.txt"),
+        common::ntfs::reparse_point(
+            IO_REPARSE_TAG_SYMLINK,
+            &common::ntfs::symlink_data("target.txt", true),
+        ),
+    ]);
+    let link = record_type(&link);
+    assert_eq!(link.ftype, "symlink");
+    assert_eq!(link.symlink_target.as_deref(), Some("target.txt"));
+
+    // A WSL character device: its mode and device numbers are in the $EA.
+    let device = parse(&[
+        common::ntfs::file_name(5 | 5 << 48, 1, "null"),
+        common::ntfs::ea(&[
+            ("$LXMOD", (0o020666u32).to_le_bytes().to_vec()),
+            ("$LXDEV", [1u32.to_le_bytes(), 3u32.to_le_bytes()].concat()),
+        ]),
+    ]);
+    let device = record_type(&device);
+    assert_eq!(device.ftype, "chardev");
+    assert_eq!(device.device, Some((1, 3)));
+
+    // Newer WSL marks them with a reparse tag instead.
+    let block = parse(&[
+        common::ntfs::file_name(5 | 5 << 48, 1, "sda"),
+        common::ntfs::reparse_point(IO_REPARSE_TAG_LX_BLK, &[]),
+        common::ntfs::ea(&[("$LXDEV", [8u32.to_le_bytes(), 0u32.to_le_bytes()].concat())]),
+    ]);
+    let block = record_type(&block);
+    assert_eq!(block.ftype, "blockdev");
+    assert_eq!(block.device, Some((8, 0)));
+}
+
+#[test]
+fn exfat() {
+    let scratch = Scratch::new("exfat");
+    let entries: Vec<Entry> = common::sample()
+        .into_iter()
+        .filter(|e| matches!(e.node, Node::Dir | Node::File(_) | Node::Deleted(_)))
+        .collect();
+    let image = scratch.0.join("exfat.img");
+    std::fs::write(&image, common::exfat::build(&entries)).unwrap();
+    let mut fs = common::open_image(&image);
+    common::check_tree(&mut fs, &entries);
+
+    // The raw root directory keeps the name entries of live and deleted files.
+    let root = fs.get_file(fs.get_root_file_id()).unwrap();
+    let data = fs
+        .read_directory_data(&root)
+        .unwrap()
+        .expect("exFAT directory data");
+    for name in ["hello.txt", "gone.txt"] {
+        let units: Vec<u8> = name.encode_utf16().flat_map(u16::to_le_bytes).collect();
+        assert!(
+            data.windows(units.len()).any(|w| w == units),
+            "{} not in the root directory data",
+            name
+        );
+    }
+}
+
+#[test]
+fn overlay() {
+    use exhume_filesystem::overlay::{OverlayFS, OverlayLayer};
+
+    let scratch = Scratch::new("overlay");
+    let (lower, upper) = (scratch.0.join("lower"), scratch.0.join("upper"));
+    let entry = |path, node| Entry { path, node };
+    common::populate(
+        &lower,
+        &[
+            entry("hello.txt", Node::File(b"lower\n".to_vec())),
+            entry("kept.txt", Node::File(b"kept\n".to_vec())),
+            entry("docs", Node::Dir),
+            entry("docs/old.txt", Node::File(b"old\n".to_vec())),
+            entry("etc", Node::Dir),
+            entry("etc/passwd", Node::File(b"root:x:0:0\n".to_vec())),
+        ],
+    );
+    common::populate(
+        &upper,
+        &[
+            entry(".wh.kept.txt", Node::File(Vec::new())),
+            entry("docs", Node::Dir),
+            entry("docs/.wh..wh..opq", Node::File(Vec::new())),
+            entry("docs/new.txt", Node::File(b"new\n".to_vec())),
+        ],
+    );
+
+    // Layer tarball on top: replaces hello.txt, adds a file below a directory it omits.
+    let tarball = scratch.0.join("layer.tar");
+    let mut builder = tar::Builder::new(std::fs::File::create(&tarball).unwrap());
+    for (path, data) in [
+        ("hello.txt", &b"upper\n"[..]),
+        ("./etc/shadow", b"root:*\n"),
+    ] {
+        let mut header = tar::Header::new_ustar();
+        header.set_size(data.len() as u64);
+        header.set_mode(0o640);
+        header.set_cksum();
+        builder.append_data(&mut header, path, data).unwrap();
+    }
+    builder.finish().unwrap();
+    drop(builder);
+
+    let layers = [&lower, &upper, &tarball]
+        .iter()
+        .map(|path| OverlayLayer::open(path.to_str().unwrap()).unwrap())
+        .collect();
+    let mut fs = OverlayFS::new(layers).unwrap();
+    let files = common::check_tree(
+        &mut fs,
+        &[
+            entry("hello.txt", Node::File(b"upper\n".to_vec())),
+            entry("kept.txt", Node::Deleted(Vec::new())),
+            entry("docs", Node::Dir),
+            entry("docs/old.txt", Node::Deleted(Vec::new())),
+            entry("docs/new.txt", Node::File(b"new\n".to_vec())),
+            entry("etc/passwd", Node::File(b"root:x:0:0\n".to_vec())),
+            entry("etc/shadow", Node::File(b"root:*\n".to_vec())),
+        ],
+    );
+    assert!(
+        !files.keys().any(|path| path.contains(".wh.")),
+        "whiteouts were walked: {:?}",
+        files.keys()
+    );
+    assert_eq!(files["/hello.txt"].metadata["overlay"]["layer"], 2);
+    assert_eq!(files["/etc/passwd"].metadata["overlay"]["layer"], 0);
+}
+
+#[test]
+fn overlay_tar_layer() {
+    use exhume_filesystem::overlay::{OverlayFS, OverlayLayer};
+
+    if !common::tools(&["tar"]) {
+        return;
+    }
+    let scratch = Scratch::new("overlay-tar");
+    let (lower, upper) = (scratch.0.join("lower"), scratch.0.join("upper"));
+    let entry = |path, node| Entry { path, node };
+    common::populate(
+        &lower,
+        &[
+            entry("hello.txt", Node::File(b"lower\n".to_vec())),
+            entry("kept.txt", Node::File(b"kept\n".to_vec())),
+            entry("docs", Node::Dir),
+            entry("docs/old.txt", Node::File(b"old\n".to_vec())),
+        ],
+    );
+    // The upper layer as an image builder exports it: a tarball of the changed tree, with
+    // its whiteouts.
+    common::populate(
+        &upper,
+        &[
+            entry("hello.txt", Node::File(b"upper\n".to_vec())),
+            entry(".wh.kept.txt", Node::File(Vec::new())),
+            entry("docs", Node::Dir),
+            entry("docs/.wh..wh..opq", Node::File(Vec::new())),
+            entry("docs/new.txt", Node::File(b"new\n".to_vec())),
+        ],
+    );
+    let tarball = scratch.0.join("layer.tar");
+    common::run(
+        "tar",
+        &[
+            "-cf",
+            tarball.to_str().unwrap(),
+            "-C",
+            upper.to_str().unwrap(),
+            ".",
+        ],
+    )
+    .unwrap();
+
+    let layers = [&lower, &tarball]
+        .iter()
+        .map(|path| OverlayLayer::open(path.to_str().unwrap()).unwrap())
+        .collect();
+    let mut fs = OverlayFS::new(layers).unwrap();
+    let files = common::check_tree(
+        &mut fs,
+        &[
+            entry("hello.txt", Node::File(b"upper\n".to_vec())),
+            entry("kept.txt", Node::Deleted(Vec::new())),
+            entry("docs", Node::Dir),
+            entry("docs/old.txt", Node::Deleted(Vec::new())),
+            entry("docs/new.txt", Node::File(b"new\n".to_vec())),
+        ],
+    );
+    assert_eq!(files["/hello.txt"].metadata["overlay"]["layer"], 1);
+}
+
+#[test]
+fn zfs() {
+    use exhume_filesystem::zfs_impl::ZfsFS;
+
+    let entries = common::sample_without(|n| matches!(n, Node::Stream { .. }));
+    let image = common::zfs::build(&entries);
+    let (mut fs, _) = common::check_image(image.clone(), "ZFS", &entries);
+
+    let sparse = fs.get_file_by_path("/sparse.bin", 0).unwrap();
+    assert_eq!(
+        fs.file_holes(&sparse).unwrap(),
+        Some(vec![(0, 512 << 10), (640 << 10, 384 << 10)])
+    );
+
+    let mut pool = ZfsFS::new(std::io::Cursor::new(image)).unwrap();
+    let names: Vec<&str> = pool.datasets().iter().map(|d| d.name.as_str()).collect();
+    assert_eq!(names, ["tank", "tank@snap1", "tank/data"]);
+    pool.select_dataset("tank/data").unwrap();
+    let files = common::check_tree(
+        &mut pool,
+        &[Entry {
+            path: "other.txt",
+            node: Node::File(b"in the second dataset\n".to_vec()),
+        }],
+    );
+    assert!(!files.contains_key("/hello.txt"));
+    assert_eq!(pool.get_metadata().unwrap()["dataset"], "tank/data");
+}
+
+#[test]
+fn zfs_zpool() {
+    // Pools are populated through their mount, which needs the kernel module.
+    if !std::path::Path::new("/dev/zfs").exists() {
+        eprintln!("skipped: /dev/zfs not found");
+        return;
+    }
+    let scratch = Scratch::new("zfs-zpool");
+    let pool = format!("exhume{}", std::process::id());
+    let entries = common::sample_without(|n| matches!(n, Node::Stream { .. } | Node::Deleted(_)));
+    common::check_tool_image(
+        &scratch.0,
+        "ZFS",
+        &entries,
+        128 << 20,
+        &[
+            &[
+                "zpool",
+                "create",
+                "-O",
+                "compression=off",
+                "-m",
+                "{image}.mnt",
+                &pool,
+                "{image}",
+            ],
+            &["cp", "-a", "--sparse=always", "{tree}/.", "{image}.mnt"],
+            &["zpool", "export", &pool],
+        ],
+    );
+}
+
+#[test]
+fn squashfs() {
+    let entries = common::sample_without(|n| matches!(n, Node::Stream { .. }));
+    let (mut fs, _) = common::check_image(common::squashfs::build(&entries), "SquashFS", &entries);
+
+    let sparse = fs.get_file_by_path("/sparse.bin", 0).unwrap();
+    assert_eq!(
+        fs.file_holes(&sparse).unwrap(),
+        Some(vec![(0, 512 << 10), (516 << 10, 508 << 10)])
+    );
+    assert_eq!(fs.get_metadata().unwrap()["compressor_name"], "gzip");
+}
+
+#[test]
+fn squashfs_mksquashfs() {
+    let scratch = Scratch::new("squashfs-tool");
+    let entries = common::sample_without(|n| matches!(n, Node::Stream { .. } | Node::Deleted(_)));
+    let Some((fs, _)) = common::check_tool_image(
+        &scratch.0,
+        "SquashFS",
+        &entries,
+        0,
+        &[&[
+            "mksquashfs",
+            "{tree}",
+            "{image}",
+            "-noappend",
+            "-comp",
+            "xz",
+        ]],
+    ) else {
+        return;
+    };
+    assert_eq!(fs.get_metadata().unwrap()["compressor_name"], "xz");
+}
+
+#[test]
+fn udf() {
+    let entries = common::sample_without(|n| matches!(n, Node::Stream { .. }));
+    let (mut fs, _) = common::check_image(common::udf::build(&entries), "UDF", &entries);
+
+    let sparse = fs.get_file_by_path("/sparse.bin", 0).unwrap();
+    assert_eq!(
+        fs.file_holes(&sparse).unwrap(),
+        Some(vec![(0, 512 << 10), (514 << 10, 510 << 10)])
+    );
+    assert_eq!(
+        fs.get_metadata().unwrap()["file_set_identifier"],
+        "fixture set"
+    );
+}
+
+#[test]
+fn udf_mkudffs() {
+    // mkudffs formats an empty volume: populating it needs a mount.
+    let scratch = Scratch::new("udf-tool");
+    let Some((fs, files)) = common::check_tool_image(
+        &scratch.0,
+        "UDF",
+        &[],
+        16 << 20,
+        &[&[
+            "mkudffs",
+            "--media-type=hd",
+            "--blocksize=2048",
+            "--fsid=fixture set",
+            "{image}",
+        ]],
+    ) else {
+        return;
+    };
+    assert_eq!(files.keys().collect::<Vec<_>>(), ["/"]);
+    assert_eq!(
+        fs.get_metadata().unwrap()["file_set_identifier"],
+        "fixture set"
+    );
+}
+
+#[test]
+fn f2fs() {
+    let entries = common::sample_without(|n| matches!(n, Node::Stream { .. }));
+    let (mut fs, _) = common::check_image(common::f2fs::build(&entries), "F2FS", &entries);
+
+    let sparse = fs.get_file_by_path("/sparse.bin", 0).unwrap();
+    assert_eq!(
+        fs.file_holes(&sparse).unwrap(),
+        Some(vec![(0, 512 << 10), (516 << 10, 508 << 10)])
+    );
+    let hello = fs.get_file_by_path("/hello.txt", 0).unwrap();
+    assert_eq!(
+        fs.extended_attributes(&hello).unwrap(),
+        vec![(
+            "security.selinux".to_string(),
+            b"u:object_r:fixture_file:s0\0".to_vec()
+        )]
+    );
+    let runs = fs.file_block_runs(&sparse).unwrap().unwrap();
+    assert_eq!(fs.block_allocation(runs[0].0).unwrap(), Some(true));
+    assert_eq!(fs.block_allocation(runs[0].0 + 64).unwrap(), Some(false));
+}
+
+#[test]
+fn f2fs_sload() {
+    let scratch = Scratch::new("f2fs-tool");
+    let entries = common::sample_without(|n| matches!(n, Node::Stream { .. } | Node::Deleted(_)));
+    common::check_tool_image(
+        &scratch.0,
+        "F2FS",
+        &entries,
+        64 << 20,
+        &[
+            &["mkfs.f2fs", "-q", "-f", "-l", "fixture", "{image}"],
+            &["sload.f2fs", "-f", "{tree}", "{image}"],
+        ],
+    );
+}
+
+#[test]
+fn ufs() {
+    let entries = common::sample_without(|n| matches!(n, Node::Stream { .. }));
+    let (mut fs, _) = common::check_image(common::ufs::build(&entries), "UFS2", &entries);
+
+    let sparse = fs.get_file_by_path("/sparse.bin", 0).unwrap();
+    assert_eq!(
+        fs.file_holes(&sparse).unwrap(),
+        Some(vec![(0, 512 << 10), (520 << 10, 504 << 10)])
+    );
+    let hello = fs.get_file_by_path("/hello.txt", 0).unwrap();
+    assert_eq!(
+        fs.extended_attributes(&hello).unwrap(),
+        vec![("user.comment".to_string(), b"fixture".to_vec())]
+    );
+    let runs = fs.file_block_runs(&sparse).unwrap().unwrap();
+    assert_eq!(fs.block_allocation(runs[0].0).unwrap(), Some(true));
+    let end = fs.get_metadata().unwrap()["size"].as_u64().unwrap();
+    assert_eq!(fs.block_allocation(end - 1).unwrap(), Some(false));
+}
+
+#[test]
+fn ufs_makefs() {
+    let scratch = Scratch::new("ufs-tool");
+    let entries = common::sample_without(|n| matches!(n, Node::Stream { .. } | Node::Deleted(_)));
+    common::check_tool_image(
+        &scratch.0,
+        "UFS2",
+        &entries,
+        0,
+        &[&[
+            "makefs",
+            "-t",
+            "ffs",
+            "-o",
+            "version=2",
+            "{image}",
+            "{tree}",
+        ]],
+    );
+}
+
+#[test]
+fn refs() {
+    let entries = common::sample_without(|n| matches!(n, Node::Symlink(_)));
+    let (mut fs, files) = common::check_image(common::refs::build(&entries), "ReFS", &entries);
+
+    let streams = &files["/hello.txt"].metadata[COMMON_KEY][STREAMS_KEY];
+    assert_eq!(
+        streams,
+        &serde_json::json!([{ "name": "Zone.Identifier", "size": 26 }])
+    );
+    let sparse = fs.get_file_by_path("/sparse.bin", 0).unwrap();
+    assert_eq!(
+        fs.file_holes(&sparse).unwrap(),
+        Some(vec![(0, 512 << 10), (516 << 10, 508 << 10)])
+    );
+    let runs = fs.file_block_runs(&sparse).unwrap().unwrap();
+    assert_eq!(runs.len(), 1);
+}
+
+#[test]
+fn refs_windows_volume() {
+    use exhume_filesystem::filesystem::DirectoryCommon;
+
+    // Only Windows formats ReFS. The fixture is a volume it formatted, with the files and
+    // directories of the sample copied in, its free space zeroed and gzip-compressed;
+    // EXHUME_REFS_IMAGE checks another such image instead.
+    let fixture = Path::new(env!("CARGO_MANIFEST_DIR")).join("tests/fixtures/refs-windows.img.gz");
+    let image = match std::env::var_os("EXHUME_REFS_IMAGE") {
+        Some(image) => std::fs::read(image).unwrap(),
+        None if fixture.exists() => {
+            let mut image = Vec::new();
+            GzDecoder::new(std::fs::File::open(&fixture).unwrap())
+                .read_to_end(&mut image)
+                .unwrap();
+            image
+        }
+        None => {
+            eprintln!("skipped: no {} fixture", fixture.display());
+            return;
+        }
+    };
+    let entries = common::sample_without(|n| !matches!(n, Node::File(_) | Node::Dir));
+    let (mut fs, _) = common::check_image(image, "ReFS", &entries);
+
+    let root = fs.get_file(fs.get_root_file_id()).unwrap();
+    let names: BTreeSet<String> = fs
+        .list_dir(&root)
+        .unwrap()
+        .iter()
+        .map(|e| e.name().to_string())
+        .collect();
+    for name in ["hello.txt", "empty.txt", "docs"] {
+        assert!(
+            names.contains(name),
+            "{} not in the root, got {:?}",
+            name,
+            names
+        );
+    }
+    let hello = fs.get_file_by_path("/hello.txt", 0).unwrap();
+    assert_eq!(fs.read_file_content(&hello).unwrap(), b"hello, fixture\n");
+}
+
+#[test]
+fn hfs() {
+    let entries = common::sample_without(|n| matches!(n, Node::Stream { .. } | Node::Symlink(_)));
+    let (mut fs, files) = common::check_image(common::hfs::build(&entries), "HFS", &entries);
+
+    let streams = &files["/hello.txt"].metadata[COMMON_KEY][STREAMS_KEY];
+    assert_eq!(
+        streams,
+        &serde_json::json!([{ "name": "rsrc", "size": 22 }])
+    );
+    let hello = fs.get_file_by_path("/hello.txt", 0).unwrap();
+    let attributes = fs.extended_attributes(&hello).unwrap();
+    assert!(attributes[0].1.starts_with(b"TEXTttxt"));
+    assert_eq!(
+        attributes[1],
+        (
+            "com.apple.ResourceFork".to_string(),
+            b"resource fork of hello".to_vec()
+        )
+    );
+    // Five extents of two blocks, the last two from the extents overflow file.
+    let big = fs.get_file_by_path("/docs/big.bin", 0).unwrap();
+    let runs = fs.file_block_runs(&big).unwrap().unwrap();
+    assert_eq!(runs.len(), 5);
+    assert_eq!(fs.block_allocation(runs[4].0).unwrap(), Some(true));
+    assert_eq!(
+        fs.block_allocation(runs[4].0 + runs[4].1).unwrap(),
+        Some(false)
+    );
+}
+
+#[test]
+fn hfs_hfsutils() {
+    let scratch = Scratch::new("hfs-tool");
+    let entries = common::sample_without(|n| {
+        matches!(n, Node::Stream { .. } | Node::Symlink(_) | Node::Deleted(_))
+    });
+    // hfsutils work on the volume `hmount` records, with `:` separated paths.
+    let mut commands: Vec<Vec<String>> = vec![
+        vec![
+            "hformat".into(),
+            "-l".into(),
+            "fixture".into(),
+            "{image}".into(),
+        ],
+        vec!["hmount".into(), "{image}".into()],
+    ];
+    for entry in &entries {
+        let path = format!(":{}", entry.path.replace('/', ":"));
+        commands.push(match entry.node {
+            Node::Dir => vec!["hmkdir".into(), path],
+            _ => vec![
+                "hcopy".into(),
+                "-r".into(),
+                format!("{{tree}}/{}", entry.path),
+                path,
+            ],
+        });
+    }
+    commands.push(vec!["humount".into()]);
+    let commands: Vec<Vec<&str>> = commands
+        .iter()
+        .map(|command| command.iter().map(String::as_str).collect())
+        .collect();
+    let commands: Vec<&[&str]> = commands.iter().map(Vec::as_slice).collect();
+    common::check_tool_image(&scratch.0, "HFS", &entries, 16 << 20, &commands);
+}
+
+#[test]
+fn ubifs() {
+    use exhume_filesystem::detected_fs::DetectedFs;
+
+    let entries = common::sample_without(|n| matches!(n, Node::Stream { .. }));
+    let (mut fs, _) = common::check_image(common::ubifs::build(&entries), "UBIFS", &entries);
+
+    let sparse = fs.get_file_by_path("/sparse.bin", 0).unwrap();
+    assert_eq!(
+        fs.file_holes(&sparse).unwrap(),
+        Some(vec![(0, 512 << 10), (516 << 10, 508 << 10)])
+    );
+    let hello = fs.get_file_by_path("/hello.txt", 0).unwrap();
+    assert_eq!(
+        fs.extended_attributes(&hello).unwrap(),
+        vec![("user.comment".to_string(), b"fixture".to_vec())]
+    );
+    let volumes = &fs.get_metadata().unwrap()["ubi"]["volumes"];
+    assert_eq!(volumes[0]["name"], "kernel");
+    assert_eq!(volumes[1]["ubifs"], true);
+    let DetectedFs::Ubifs(ubi) = &mut fs else {
+        panic!("not opened as UBIFS");
+    };
+    assert!(ubi.select_volume("kernel").is_err());
+    ubi.select_volume("rootfs").unwrap();
+}
+
+#[test]
+fn ubifs_ubinize() {
+    let scratch = Scratch::new("ubifs-tool");
+    let entries = common::sample_without(|n| matches!(n, Node::Stream { .. } | Node::Deleted(_)));
+    // 128 KiB erase blocks of 2 KiB pages, less the two headers of each.
+    let ini = scratch.0.join("ubinize.ini");
+    let volume = scratch.0.join("rootfs.ubifs");
+    std::fs::write(
+        &ini,
+        format!(
+            "[rootfs]\nmode=ubi\nimage={}\nvol_id=0\nvol_type=dynamic\nvol_name=rootfs\n",
+            volume.display()
+        ),
+    )
+    .unwrap();
+    let (ini, volume) = (ini.to_str().unwrap(), volume.to_str().unwrap());
+    common::check_tool_image(
+        &scratch.0,
+        "UBIFS",
+        &entries,
+        0,
+        &[
+            &[
+                "mkfs.ubifs",
+                "-r",
+                "{tree}",
+                "-m",
+                "2048",
+                "-e",
+                "126976",
+                "-c",
+                "64",
+                "-o",
+                volume,
+            ],
+            &[
+                "ubinize", "-o", "{image}", "-m", "2048", "-p", "128KiB", "-s", "2048", ini,
+            ],
+        ],
+    );
+}
+
+#[test]
+fn yaffs2() {
+    let entries = common::sample_without(|n| matches!(n, Node::Stream { .. }));
+    let (mut fs, _) = common::check_image(common::yaffs2::build(&entries), "YAFFS2", &entries);
+
+    let sparse = fs.get_file_by_path("/sparse.bin", 0).unwrap();
+    assert_eq!(
+        fs.file_holes(&sparse).unwrap(),
+        Some(vec![(0, 512 << 10), (514 << 10, 510 << 10)])
+    );
+    let hello = fs.get_file_by_path("/hello.txt", 0).unwrap();
+    assert_eq!(
+        fs.extended_attributes(&hello).unwrap(),
+        vec![("user.comment".to_string(), b"fixture".to_vec())]
+    );
+    // The deleted file keeps its chunks and, in its older header, its name.
+    let deleted = fs.enumerate_deleted_files(&mut |_| {}).unwrap();
+    assert_eq!(deleted.len(), 1);
+    assert_eq!(deleted[0].name, "gone.txt");
+    let gone = fs.get_file(deleted[0].identifier).unwrap();
+    assert_eq!(fs.read_file_content(&gone).unwrap(), b"deleted content\n");
+}
+
+#[test]
+fn yaffs2_mkyaffs2image() {
+    let scratch = Scratch::new("yaffs2-tool");
+    let entries = common::sample_without(|n| matches!(n, Node::Stream { .. } | Node::Deleted(_)));
+    common::check_tool_image(
+        &scratch.0,
+        "YAFFS2",
+        &entries,
+        0,
+        &[&["mkyaffs2image", "{tree}", "{image}"]],
+    );
+}
+
+#[test]
+fn cramfs() {
+    let entries = common::sample();
+    // Little-endian as made on x86, big-endian and padded as on MIPS firmware.
+    for (big_endian, padded) in [(false, false), (true, true)] {
+        let image = common::cramfs::build(&entries, big_endian, padded);
+        let (mut fs, _) = common::check_image(image, "CramFS", &entries);
+
+        let sparse = fs.get_file_by_path("/sparse.bin", 0).unwrap();
+        assert_eq!(
+            fs.file_holes(&sparse).unwrap(),
+            Some(vec![(0, 512 << 10), (516 << 10, 508 << 10)])
+        );
+        // Across the direct compressed first block of big.bin and the next one.
+        let big = fs.get_file_by_path("/docs/big.bin", 0).unwrap();
+        let content = fs.read_file_content(&big).unwrap();
+        assert_eq!(
+            fs.read_file_slice(&big, 4090, 12).unwrap(),
+            &content[4090..4102]
+        );
+    }
+}
+
+#[test]
+fn cramfs_mkfs() {
+    let scratch = Scratch::new("cramfs-tool");
+    let entries = common::sample_without(|n| matches!(n, Node::Stream { .. } | Node::Deleted(_)));
+    common::check_tool_image(
+        &scratch.0,
+        "CramFS",
+        &entries,
+        0,
+        &[&["mkfs.cramfs", "-z", "{tree}", "{image}"]],
+    );
+}
+
+#[test]
+fn checkpoint_resume() {
+    let scratch = Scratch::new("checkpoint");
+    let root = scratch.0.join("tree");
+    let entries: Vec<Entry> = common::sample()
+        .into_iter()
+        .filter(|e| matches!(e.node, Node::File(_) | Node::Dir))
+        .collect();
+    common::populate(&root, &entries);
+    let mut fs = FolderFS::new(root);
+
+    // Every checkpoint appends to the same `.seen` file; the first one is kept aside.
+    let (path, first) = (scratch.0.join("walk.json"), scratch.0.join("first.json"));
+    let (path, first) = (path.to_str().unwrap(), first.to_str().unwrap());
+    let mut saves = 0;
+    let mut on_checkpoint = |state: &mut WalkCheckpoint| {
+        state.save(path).unwrap();
+        saves += 1;
+        if saves == 1 {
+            std::fs::copy(path, first).unwrap();
+            std::fs::copy(format!("{}.seen", path), format!("{}.seen", first)).unwrap();
+        }
+    };
+    let mut paths = Vec::new();
+    fs.walk_fs_with(
+        WalkOptions {
+            checkpoint_every: 2,
+            on_checkpoint: Some(&mut on_checkpoint),
+            ..Default::default()
+        },
+        &mut |event| {
+            if let WalkEvent::File(file) = event {
+                paths.push(file.absolute_path);
+            }
+        },
+    )
+    .unwrap();
+    assert!(
+        saves > 1,
+        "only {} checkpoints for {} records",
+        saves,
+        paths.len()
+    );
+
+    let last = WalkCheckpoint::load(path).unwrap();
+    assert_eq!(last.emitted, saves * 2);
+    assert_eq!(last.seen.len() as u64, last.emitted);
+
+    let mut resumed = Vec::new();
+    fs.walk_fs_with(
+        WalkOptions {
+            resume: Some(WalkCheckpoint::load(first).unwrap()),
+            ..Default::default()
+        },
+        &mut |event| {
+            if let WalkEvent::File(file) = event {
+                resumed.push(file.absolute_path);
+            }
+        },
+    )
+    .unwrap();
+    assert_eq!(resumed, paths[2..]);
+}
+
+#[cfg(unix)]
+#[test]
+fn bodyfile_separator_in_name() {
+    let scratch = Scratch::new("bodyfile");
+    let root = scratch.0.join("tree");
+    std::fs::create_dir_all(&root).unwrap();
+    std::fs::write(root.join("a|b\nc.txt"), b"x").unwrap();
+    let files = common::walk(&mut FolderFS::new(root));
+    let line = export::bodyfile_line(&files["/a|b\nc.txt"]);
+    assert_eq!(
+        line.split('|').nth(1),
+        Some("/a\\x7cb\\x0ac.txt"),
+        "{}",
+        line
+    );
+    assert_eq!(line.split('|').count(), 11, "{}", line);
+}
+
+#[test]
+fn custody_requires_dump() {
+    let scratch = Scratch::new("custody");
+    let root = scratch.0.join("tree");
+    common::populate(
+        &root,
+        &common::sample_without(|n| !matches!(n, Node::File(_) | Node::Dir)),
+    );
+    let manifest = scratch.0.join("custody.json");
+    let run = |extra: &[&str]| {
+        std::process::Command::new(env!("CARGO_BIN_EXE_exhume_filesystem"))
+            .current_dir(&scratch.0)
+            .args([
+                "-b",
+                root.to_str().unwrap(),
+                "-r",
+                "/hello.txt",
+                "--custody",
+            ])
+            .arg(&manifest)
+            .args(extra)
+            .output()
+            .unwrap()
+    };
+
+    let refused = run(&[]);
+    assert!(!refused.status.success());
+    assert!(
+        String::from_utf8_lossy(&refused.stderr).contains("--custody requires --dump"),
+        "{}",
+        String::from_utf8_lossy(&refused.stderr)
+    );
+    assert!(!manifest.exists(), "an empty manifest was written");
+
+    assert!(run(&["--dump"]).status.success());
+    let written = std::fs::read_to_string(&manifest).unwrap();
+    assert!(written.contains("/hello.txt"), "{}", written);
+}
@@ -0,0 +1,193 @@
+//! Minimal CramFS image, in either byte order and after 512 bytes of padding if asked:
+//! the superblock and root inode, the directory entries from the root on, then the block
+//! pointers and blocks of each file. Blocks are zlib-compressed but for all-zero ones,
+//! left as holes; `hello.txt` is a direct uncompressed block, and `docs/big.bin` starts
+//! with a direct compressed block and ends with an uncompressed one, the extended block
+//! pointers of execute-in-place images.
+use super::{Entry, Node};
+use flate2::Compression;
+use flate2::write::ZlibEncoder;
+use std::io::Write;
+
+const MAGIC: u32 = 0x28cd_3d45;
+const BLOCK: usize = 4096;
+const PADDING: usize = 512;
+const INODE_SIZE: usize = 12;
+/// Version 2 of the fsid, sorted directories, holes and extended block pointers.
+const FLAGS: u32 = 0x1 | 0x2 | 0x100 | 0x800;
+const UNCOMPRESSED: u32 = 1 << 31;
+const DIRECT: u32 = 1 << 30;
+
+struct Writer {
+    big_endian: bool,
+    image: Vec<u8>,
+}
+
+impl Writer {
+    fn word(&self, value: u32) -> [u8; 4] {
+        if self.big_endian {
+            value.to_be_bytes()
+        } else {
+            value.to_le_bytes()
+        }
+    }
+
+    fn put32(&mut self, at: usize, value: u32) {
+        let word = self.word(value);
+        self.image[at..at + 4].copy_from_slice(&word);
+    }
+
+    /// Inode at `at`, followed by its name padded to 4 bytes.
+    fn inode(&mut self, at: usize, mode: u32, size: u32, offset: usize, name: &str) {
+        let name_length = name.len().div_ceil(4) as u32;
+        let offset = offset as u32 / 4;
+        let words = if self.big_endian {
+            [mode << 16, size << 8, name_length << 26 | offset]
+        } else {
+            [mode, size, name_length | offset << 6]
+        };
+        for (n, word) in words.into_iter().enumerate() {
+            self.put32(at + 4 * n, word);
+        }
+        self.image[at + INODE_SIZE..at + INODE_SIZE + name.len()].copy_from_slice(name.as_bytes());
+    }
+
+    fn align(&mut self) {
+        self.image.resize(self.image.len().next_multiple_of(4), 0);
+    }
+
+    /// Pointer table and blocks of `data`, returning where the table is.
+    fn data(&mut self, path: &str, data: &[u8]) -> usize {
+        self.align();
+        let table = self.image.len();
+        let blocks: Vec<&[u8]> = data.chunks(BLOCK).collect();
+        self.image.resize(table + 4 * blocks.len(), 0);
+        for (n, block) in blocks.iter().enumerate() {
+            let pointer = match (path, n) {
+                ("hello.txt", 0) => {
+                    self.align();
+                    let start = self.image.len();
+                    self.image.extend_from_slice(block);
+                    UNCOMPRESSED | DIRECT | (start / 4) as u32
+                }
+                ("docs/big.bin", 0) => {
+                    self.align();
+                    let start = self.image.len();
+                    let compressed = zlib(block);
+                    let length = compressed.len() as u16;
+                    if self.big_endian {
+                        self.image.extend_from_slice(&length.to_be_bytes());
+                    } else {
+                        self.image.extend_from_slice(&length.to_le_bytes());
+                    }
+                    self.image.extend_from_slice(&compressed);
+                    DIRECT | (start / 4) as u32
+                }
+                ("docs/big.bin", n) if n + 1 == blocks.len() => {
+                    self.image.extend_from_slice(block);
+                    UNCOMPRESSED | self.image.len() as u32
+                }
+                _ if block.iter().all(|b| *b == 0) => self.image.len() as u32,
+                _ => {
+                    self.image.extend_from_slice(&zlib(block));
+                    self.image.len() as u32
+                }
+            };
+            self.put32(table + 4 * n, pointer);
+        }
+        table
+    }
+}
+
+fn zlib(data: &[u8]) -> Vec<u8> {
+    let mut encoder = ZlibEncoder::new(Vec::new(), Compression::best());
+    encoder.write_all(data).unwrap();
+    encoder.finish().unwrap()
+}
+
+pub fn build(entries: &[Entry], big_endian: bool, padded: bool) -> Vec<u8> {
+    let entries: Vec<&Entry> = entries
+        .iter()
+        .filter(|e| !matches!(e.node, Node::Deleted(_) | Node::Stream { .. }))
+        .collect();
+    let children = |parent: &str| {
+        let mut children: Vec<&Entry> = entries
+            .iter()
+            .filter(|e| e.path.rsplit_once('/').map_or("", |(p, _)| p) == parent)
+            .copied()
+            .collect();
+        children.sort_by_key(|e| e.path);
+        children
+    };
+    let entry_size = |e: &Entry| {
+        let name = e.path.rsplit('/').next().unwrap();
+        INODE_SIZE + name.len().next_multiple_of(4)
+    };
+    let base = if padded { PADDING } else { 0 };
+
+    // Directory entries from the root on, breadth first: where each directory's are.
+    let mut order = vec![""];
+    let mut n = 0;
+    while n < order.len() {
+        order.extend(
+            children(order[n])
+                .into_iter()
+                .filter(|e| matches!(e.node, Node::Dir))
+                .map(|e| e.path),
+        );
+        n += 1;
+    }
+    let mut directories = Vec::new();
+    let mut end = base + 76;
+    for directory in order {
+        directories.push((directory, end));
+        end += children(directory)
+            .iter()
+            .map(|e| entry_size(e))
+            .sum::<usize>();
+    }
+    let start_of = |path: &str| directories.iter().find(|(p, _)| *p == path).unwrap().1;
+    let dir_size = |path: &str| children(path).iter().map(|e| entry_size(e)).sum::<usize>();
+
+    let mut writer = Writer {
+        big_endian,
+        image: vec![0u8; end],
+    };
+    writer.inode(base + 64, 0o040755, dir_size("") as u32, start_of(""), "");
+    for &(directory, start) in &directories {
+        let mut at = start;
+        for child in children(directory) {
+            let name = child.path.rsplit('/').next().unwrap();
+            let (mode, size, offset) = match &child.node {
+                Node::Dir => (0o040755, dir_size(child.path), start_of(child.path)),
+                Node::Symlink(target) => (
+                    0o120777,
+                    target.len(),
+                    writer.data(child.path, target.as_bytes()),
+                ),
+                Node::File(data) if data.is_empty() => (0o100644, 0, 0),
+                Node::File(data) => (0o100644, data.len(), writer.data(child.path, data)),
+                Node::Sparse { size, offset, data } => {
+                    let mut content = vec![0u8; *size as usize];
+                    content[*offset as usize..*offset as usize + data.len()].copy_from_slice(data);
+                    (0o100644, content.len(), writer.data(child.path, &content))
+                }
+                Node::Deleted(_) | Node::Stream { .. } => unreachable!(),
+            };
+            writer.inode(at, mode, size as u32, offset, name);
+            at += entry_size(child);
+        }
+    }
+
+    writer.align();
+    let size = writer.image.len();
+    let blocks = size.div_ceil(BLOCK) as u32;
+    writer.put32(base, MAGIC);
+    writer.put32(base + 4, size as u32);
+    writer.put32(base + 8, FLAGS);
+    writer.image[base + 16..base + 32].copy_from_slice(b"Compressed ROMFS");
+    writer.put32(base + 40, blocks);
+    writer.put32(base + 44, entries.len() as u32 + 1);
+    writer.image[base + 48..base + 58].copy_from_slice(b"Compressed");
+    writer.image
+}
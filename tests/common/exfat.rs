@@ -0,0 +1,252 @@
+//! Minimal exFAT writer: 4 MiB volume of 512-byte sectors and 4 KiB clusters, one cluster
+//! per directory and contiguous FAT chains. Holds directories, files and deleted files
+//! (entry sets with the in-use bits cleared and their clusters free in the bitmap).
+use super::{Entry, Node};
+
+const SECTOR: usize = 512;
+const CLUSTER: usize = 4096;
+const VOLUME_SECTORS: usize = 8192;
+const FAT_OFFSET: usize = 32;
+const FAT_LENGTH: usize = 16;
+const HEAP_OFFSET: usize = 128;
+const CLUSTER_COUNT: usize = (VOLUME_SECTORS - HEAP_OFFSET) * SECTOR / CLUSTER;
+const BITMAP_CLUSTER: u32 = 2;
+const UPCASE_CLUSTER: u32 = 3;
+const ROOT_CLUSTER: u32 = 4;
+/// 2024-01-02 03:04:06, as a DOS date and time.
+const TIMESTAMP: u32 = ((2024 - 1980) << 25) | (1 << 21) | (2 << 16) | (3 << 11) | (4 << 5) | 3;
+const ATTR_DIRECTORY: u16 = 0x10;
+const ATTR_ARCHIVE: u16 = 0x20;
+
+/// Checksum of the boot region and tables: rotate right, add the byte.
+fn checksum32(bytes: &[u8], skip: &[usize]) -> u32 {
+    bytes
+        .iter()
+        .enumerate()
+        .filter(|(i, _)| !skip.contains(i))
+        .fold(0u32, |sum, (_, &b)| {
+            sum.rotate_right(1).wrapping_add(b as u32)
+        })
+}
+
+fn checksum16(bytes: &[u8], skip: &[usize]) -> u16 {
+    bytes
+        .iter()
+        .enumerate()
+        .filter(|(i, _)| !skip.contains(i))
+        .fold(0u16, |sum, (_, &b)| {
+            sum.rotate_right(1).wrapping_add(b as u16)
+        })
+}
+
+fn upcase_table() -> Vec<u8> {
+    (0u16..128)
+        .map(|c| (c as u8).to_ascii_uppercase() as u16)
+        .flat_map(u16::to_le_bytes)
+        .collect()
+}
+
+struct Image {
+    data: Vec<u8>,
+    next_cluster: u32,
+}
+
+impl Image {
+    fn cluster(&mut self, cluster: u32) -> &mut [u8] {
+        let start = HEAP_OFFSET * SECTOR + (cluster as usize - 2) * CLUSTER;
+        &mut self.data[start..start + CLUSTER]
+    }
+
+    fn set_fat(&mut self, cluster: u32, value: u32) {
+        let at = FAT_OFFSET * SECTOR + cluster as usize * 4;
+        self.data[at..at + 4].copy_from_slice(&value.to_le_bytes());
+    }
+
+    /// Allocate a chain holding `content` (at least one cluster), marked in the bitmap
+    /// when `in_use`, and return its first cluster.
+    fn allocate(&mut self, content: &[u8], in_use: bool) -> u32 {
+        let first = self.next_cluster;
+        let count = content.len().div_ceil(CLUSTER).max(1) as u32;
+        assert!(
+            (first + count - 2) as usize <= CLUSTER_COUNT,
+            "exFAT fixture is full"
+        );
+        self.next_cluster += count;
+        for (i, cluster) in (first..first + count).enumerate() {
+            let next = if i as u32 + 1 == count {
+                u32::MAX
+            } else {
+                cluster + 1
+            };
+            self.set_fat(cluster, next);
+            let chunk =
+                &content[(i * CLUSTER).min(content.len())..((i + 1) * CLUSTER).min(content.len())];
+            self.cluster(cluster)[..chunk.len()].copy_from_slice(chunk);
+            if in_use {
+                let bit = (cluster - 2) as usize;
+                let bitmap = self.cluster(BITMAP_CLUSTER);
+                bitmap[bit / 8] |= 1 << (bit % 8);
+            }
+        }
+        first
+    }
+
+    /// Write the entries directly below `prefix` (`""` for the root) into the directory
+    /// cluster `dir`.
+    fn write_directory(&mut self, dir: u32, prefix: &str, entries: &[Entry], mut slot: usize) {
+        for entry in entries {
+            let Some(name) = entry
+                .path
+                .strip_prefix(prefix)
+                .filter(|name| !name.is_empty() && !name.contains('/'))
+            else {
+                continue;
+            };
+            let set = match &entry.node {
+                Node::Dir => {
+                    let cluster = self.allocate(&[], true);
+                    self.write_directory(cluster, &format!("{}/", entry.path), entries, 0);
+                    entry_set(name, ATTR_DIRECTORY, cluster, CLUSTER as u64, true)
+                }
+                Node::File(data) | Node::Deleted(data) => {
+                    let in_use = matches!(entry.node, Node::File(_));
+                    let cluster = match data.is_empty() {
+                        true => 0,
+                        false => self.allocate(data, in_use),
+                    };
+                    entry_set(name, ATTR_ARCHIVE, cluster, data.len() as u64, in_use)
+                }
+                other => panic!("exFAT fixtures cannot hold {:?}", other),
+            };
+            assert!(
+                (slot * 32 + set.len()) <= CLUSTER,
+                "exFAT fixture directory is full"
+            );
+            self.cluster(dir)[slot * 32..slot * 32 + set.len()].copy_from_slice(&set);
+            slot += set.len() / 32;
+        }
+    }
+}
+
+/// File, stream extension and name entries of a record.
+fn entry_set(name: &str, attributes: u16, cluster: u32, size: u64, in_use: bool) -> Vec<u8> {
+    let name: Vec<u16> = name.encode_utf16().collect();
+    let name_entries = name.len().div_ceil(15);
+    let mut set = vec![0u8; 32 * (2 + name_entries)];
+    set[0] = 0x85;
+    set[1] = (1 + name_entries) as u8;
+    set[4..6].copy_from_slice(&attributes.to_le_bytes());
+    for at in [8, 12, 16] {
+        set[at..at + 4].copy_from_slice(&TIMESTAMP.to_le_bytes());
+    }
+
+    let hash = checksum16(
+        &name
+            .iter()
+            .map(|&c| {
+                if c < 128 {
+                    (c as u8).to_ascii_uppercase() as u16
+                } else {
+                    c
+                }
+            })
+            .flat_map(u16::to_le_bytes)
+            .collect::<Vec<_>>(),
+        &[],
+    );
+    let stream = &mut set[32..64];
+    stream[0] = 0xC0;
+    stream[1] = 0x01;
+    stream[3] = name.len() as u8;
+    stream[4..6].copy_from_slice(&hash.to_le_bytes());
+    stream[8..16].copy_from_slice(&size.to_le_bytes());
+    stream[20..24].copy_from_slice(&cluster.to_le_bytes());
+    stream[24..32].copy_from_slice(&size.to_le_bytes());
+
+    for (i, chunk) in name.chunks(15).enumerate() {
+        let entry = &mut set[64 + 32 * i..96 + 32 * i];
+        entry[0] = 0xC1;
+        for (j, c) in chunk.iter().enumerate() {
+            entry[2 + 2 * j..4 + 2 * j].copy_from_slice(&c.to_le_bytes());
+        }
+    }
+
+    let checksum = checksum16(&set, &[2, 3]);
+    set[2..4].copy_from_slice(&checksum.to_le_bytes());
+    if !in_use {
+        for entry in set.chunks_mut(32) {
+            entry[0] &= 0x7F;
+        }
+    }
+    set
+}
+
+fn boot_region() -> Vec<u8> {
+    let mut region = vec![0u8; 12 * SECTOR];
+    let boot = &mut region[..SECTOR];
+    boot[0..3].copy_from_slice(&[0xEB, 0x76, 0x90]);
+    boot[3..11].copy_from_slice(b"EXFAT   ");
+    boot[72..80].copy_from_slice(&(VOLUME_SECTORS as u64).to_le_bytes());
+    boot[80..84].copy_from_slice(&(FAT_OFFSET as u32).to_le_bytes());
+    boot[84..88].copy_from_slice(&(FAT_LENGTH as u32).to_le_bytes());
+    boot[88..92].copy_from_slice(&(HEAP_OFFSET as u32).to_le_bytes());
+    boot[92..96].copy_from_slice(&(CLUSTER_COUNT as u32).to_le_bytes());
+    boot[96..100].copy_from_slice(&ROOT_CLUSTER.to_le_bytes());
+    boot[100..104].copy_from_slice(&0x1234_5678u32.to_le_bytes());
+    boot[104..106].copy_from_slice(&0x0100u16.to_le_bytes());
+    boot[108] = SECTOR.trailing_zeros() as u8;
+    boot[109] = (CLUSTER / SECTOR).trailing_zeros() as u8;
+    boot[110] = 1;
+    boot[111] = 0x80;
+    boot[112] = 0xFF;
+    boot[510..512].copy_from_slice(&[0x55, 0xAA]);
+    for sector in 1..9 {
+        region[sector * SECTOR + 510..sector * SECTOR + 512].copy_from_slice(&[0x55, 0xAA]);
+    }
+    let checksum = checksum32(&region[..11 * SECTOR], &[106, 107, 112]);
+    for word in region[11 * SECTOR..].chunks_mut(4) {
+        word.copy_from_slice(&checksum.to_le_bytes());
+    }
+    region
+}
+
+/// exFAT volume holding the entries.
+pub fn build(entries: &[Entry]) -> Vec<u8> {
+    let mut image = Image {
+        data: vec![0u8; VOLUME_SECTORS * SECTOR],
+        next_cluster: 2,
+    };
+    let boot = boot_region();
+    image.data[..boot.len()].copy_from_slice(&boot);
+    image.data[boot.len()..2 * boot.len()].copy_from_slice(&boot);
+    image.set_fat(0, 0xFFFF_FFF8);
+    image.set_fat(1, u32::MAX);
+
+    let bitmap_length = CLUSTER_COUNT.div_ceil(8);
+    assert_eq!(
+        image.allocate(&vec![0; bitmap_length], true),
+        BITMAP_CLUSTER
+    );
+    let upcase = upcase_table();
+    assert_eq!(image.allocate(&upcase, true), UPCASE_CLUSTER);
+    assert_eq!(image.allocate(&[], true), ROOT_CLUSTER);
+
+    let mut label = [0u8; 32];
+    label[0] = 0x83;
+    let mut bitmap = [0u8; 32];
+    bitmap[0] = 0x81;
+    bitmap[20..24].copy_from_slice(&BITMAP_CLUSTER.to_le_bytes());
+    bitmap[24..32].copy_from_slice(&(bitmap_length as u64).to_le_bytes());
+    let mut upcase_entry = [0u8; 32];
+    upcase_entry[0] = 0x82;
+    upcase_entry[4..8].copy_from_slice(&checksum32(&upcase, &[]).to_le_bytes());
+    upcase_entry[20..24].copy_from_slice(&UPCASE_CLUSTER.to_le_bytes());
+    upcase_entry[24..32].copy_from_slice(&(upcase.len() as u64).to_le_bytes());
+    let root = image.cluster(ROOT_CLUSTER);
+    root[..32].copy_from_slice(&label);
+    root[32..64].copy_from_slice(&bitmap);
+    root[64..96].copy_from_slice(&upcase_entry);
+
+    image.write_directory(ROOT_CLUSTER, "", entries, 3);
+    image.data
+}
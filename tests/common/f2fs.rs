@@ -0,0 +1,328 @@
+//! Minimal F2FS writer: 4 KiB blocks, 2 MiB segments, one checkpoint pack with compacted
+//! summaries, then the SIT, NAT and SSA areas and the main area, where node and data
+//! blocks are allocated in turn. Every inode keeps an inline xattr area; files of up to
+//! 3488 bytes and symbolic links are inline, others are mapped by the addresses of their
+//! inode, leaving all-zero blocks as holes. The root directory has a dentry block, the
+//! others inline dentries. The root inode is mapped in the NAT journal, as `mkfs.f2fs`
+//! does, the others in the NAT. `hello.txt` carries an SELinux label.
+use super::{Entry, Node};
+use std::collections::BTreeMap;
+
+const BLOCK: usize = 4096;
+const LOG_BLOCKS_PER_SEG: u32 = 9;
+const BLOCKS_PER_SEG: u32 = 1 << LOG_BLOCKS_PER_SEG;
+const CP_BLKADDR: u32 = BLOCKS_PER_SEG;
+const SIT_BLKADDR: u32 = CP_BLKADDR + 2 * BLOCKS_PER_SEG;
+const NAT_BLKADDR: u32 = SIT_BLKADDR + 2 * BLOCKS_PER_SEG;
+const SSA_BLKADDR: u32 = NAT_BLKADDR + 2 * BLOCKS_PER_SEG;
+const MAIN_BLKADDR: u32 = SSA_BLKADDR + BLOCKS_PER_SEG;
+const ROOT_INO: u32 = 3;
+const INLINE_XATTR_ADDRS: usize = 50;
+const INLINE_MAX: usize = 4 * (923 - 1 - INLINE_XATTR_ADDRS);
+const INLINE_XATTR: u8 = 0x01;
+const INLINE_DATA: u8 = 0x02;
+const INLINE_DENTRY: u8 = 0x04;
+const DATA_EXIST: u8 = 0x08;
+/// 2024-01-02 03:04:05 UTC.
+const TIMESTAMP: u64 = 1_704_164_645;
+const SELINUX_LABEL: &[u8] = b"u:object_r:fixture_file:s0\0";
+
+fn put16(buffer: &mut [u8], at: usize, value: u16) {
+    buffer[at..at + 2].copy_from_slice(&value.to_le_bytes());
+}
+
+fn put32(buffer: &mut [u8], at: usize, value: u32) {
+    buffer[at..at + 4].copy_from_slice(&value.to_le_bytes());
+}
+
+fn put64(buffer: &mut [u8], at: usize, value: u64) {
+    buffer[at..at + 8].copy_from_slice(&value.to_le_bytes());
+}
+
+/// CRC-32 seeded with the superblock magic, not inverted.
+fn crc(bytes: &[u8]) -> u32 {
+    let mut crc = 0xf2f5_2010u32;
+    for byte in bytes {
+        crc ^= *byte as u32;
+        for _ in 0..8 {
+            crc = (crc >> 1) ^ (0xedb8_8320 & (crc & 1).wrapping_neg());
+        }
+    }
+    crc
+}
+
+/// Dentries of `children` (name, inode, file type) in an area of `count` slots laid out
+/// as bitmap, reserved bytes, dentries and names.
+fn dentries(area: &mut [u8], count: usize, bitmap: usize, children: &[(String, u32, u8)]) {
+    let dentries = area.len() - count * (11 + 8);
+    let names = dentries + count * 11;
+    assert!(dentries >= bitmap);
+    let mut slot = 0;
+    for (name, ino, file_type) in children {
+        let slots = name.len().div_ceil(8);
+        assert!(slot + slots <= count, "too many dentries");
+        for bit in slot..slot + slots {
+            area[bit / 8] |= 1 << (bit % 8);
+        }
+        let at = dentries + slot * 11;
+        put32(area, at + 4, *ino);
+        put16(area, at + 8, name.len() as u16);
+        area[at + 10] = *file_type;
+        area[names + slot * 8..names + slot * 8 + name.len()].copy_from_slice(name.as_bytes());
+        slot += slots;
+    }
+}
+
+struct Writer {
+    /// Blocks of the main area.
+    blocks: Vec<[u8; BLOCK]>,
+    /// Node id to block address.
+    nat: BTreeMap<u32, u32>,
+    next_nid: u32,
+}
+
+impl Writer {
+    fn allocate(&mut self) -> u32 {
+        self.blocks.push([0; BLOCK]);
+        MAIN_BLKADDR + (self.blocks.len() - 1) as u32
+    }
+
+    fn block(&mut self, address: u32) -> &mut [u8; BLOCK] {
+        &mut self.blocks[(address - MAIN_BLKADDR) as usize]
+    }
+
+    /// Write the inode `ino` with `content` (dentries for directories), inline when it
+    /// fits.
+    fn inode(
+        &mut self,
+        ino: u32,
+        parent: u32,
+        name: &str,
+        mode: u16,
+        content: &[u8],
+        xattrs: &[(u8, &str, &[u8])],
+    ) {
+        let mut node = [0u8; BLOCK];
+        let mut blocks = 1u64;
+        let mut inline = INLINE_XATTR;
+        if content.len() <= INLINE_MAX {
+            inline |= match mode >> 12 {
+                4 => INLINE_DENTRY,
+                _ => INLINE_DATA,
+            };
+            if !content.is_empty() {
+                inline |= DATA_EXIST;
+            }
+            node[364..364 + content.len()].copy_from_slice(content);
+        } else {
+            for (index, chunk) in content.chunks(BLOCK).enumerate() {
+                if chunk.iter().all(|b| *b == 0) {
+                    continue;
+                }
+                let address = self.allocate();
+                self.block(address)[..chunk.len()].copy_from_slice(chunk);
+                put32(&mut node, 360 + 4 * index, address);
+                blocks += 1;
+            }
+        }
+        if !xattrs.is_empty() {
+            let area = &mut node[360 + 4 * (923 - INLINE_XATTR_ADDRS)..360 + 4 * 923];
+            put32(area, 0, 0xf2f5_2011);
+            put32(area, 4, 1);
+            let mut at = 24;
+            for (index, name, value) in xattrs {
+                area[at] = *index;
+                area[at + 1] = name.len() as u8;
+                put16(area, at + 2, value.len() as u16);
+                area[at + 4..at + 4 + name.len()].copy_from_slice(name.as_bytes());
+                area[at + 4 + name.len()..at + 4 + name.len() + value.len()].copy_from_slice(value);
+                at += (4 + name.len() + value.len()).next_multiple_of(4);
+            }
+        }
+        put16(&mut node, 0, mode);
+        node[3] = inline;
+        put32(&mut node, 12, if mode >> 12 == 4 { 2 } else { 1 });
+        put64(&mut node, 16, content.len() as u64);
+        put64(&mut node, 24, blocks);
+        for at in [32, 40, 48] {
+            put64(&mut node, at, TIMESTAMP);
+        }
+        put32(&mut node, 84, parent);
+        put32(&mut node, 88, name.len() as u32);
+        node[92..92 + name.len()].copy_from_slice(name.as_bytes());
+        put32(&mut node, BLOCK - 24, ino);
+        put32(&mut node, BLOCK - 20, ino);
+        put64(&mut node, BLOCK - 12, 1);
+        let address = self.allocate();
+        *self.block(address) = node;
+        self.nat.insert(ino, address);
+    }
+
+    /// Write directory `path` as inode `ino`, its children first.
+    fn directory(
+        &mut self,
+        path: &str,
+        ino: u32,
+        parent: u32,
+        tree: &BTreeMap<String, Vec<&Entry>>,
+    ) {
+        let mut children = vec![(".".to_string(), ino, 2), ("..".to_string(), parent, 2)];
+        for entry in tree.get(path).into_iter().flatten() {
+            let child = self.next_nid;
+            self.next_nid += 1;
+            let name = entry.path.rsplit('/').next().unwrap();
+            let file_type = match &entry.node {
+                Node::Dir => {
+                    self.directory(entry.path, child, ino, tree);
+                    2
+                }
+                Node::File(data) => {
+                    let xattrs: &[(u8, &str, &[u8])] = match entry.path {
+                        "hello.txt" => &[(6, "selinux", SELINUX_LABEL)],
+                        _ => &[],
+                    };
+                    self.inode(child, ino, name, 0o100644, data, xattrs);
+                    1
+                }
+                Node::Sparse { size, offset, data } => {
+                    let content = super::sparse_content(*size, *offset, data);
+                    self.inode(child, ino, name, 0o100644, &content, &[]);
+                    1
+                }
+                Node::Symlink(target) => {
+                    self.inode(child, ino, name, 0o120777, target.as_bytes(), &[]);
+                    7
+                }
+                other => panic!("F2FS fixtures cannot hold {:?}", other),
+            };
+            children.push((name.to_string(), child, file_type));
+        }
+        let name = path.rsplit('/').next().unwrap();
+        if ino == ROOT_INO {
+            let mut block = [0u8; BLOCK];
+            dentries(&mut block, 214, 27, &children);
+            self.inode(ino, parent, name, 0o40755, &block, &[]);
+        } else {
+            let mut area = vec![0u8; INLINE_MAX];
+            dentries(&mut area, 182, 23, &children);
+            self.inode(ino, parent, name, 0o40755, &area, &[]);
+        }
+    }
+}
+
+/// Image holding the entries, deleted ones left out: F2FS frees their inode and NAT
+/// entry.
+pub fn build(entries: &[Entry]) -> Vec<u8> {
+    let mut tree: BTreeMap<String, Vec<&Entry>> = BTreeMap::new();
+    for entry in entries {
+        if let Node::Deleted(_) = entry.node {
+            continue;
+        }
+        let parent = entry.path.rsplit_once('/').map_or("", |(parent, _)| parent);
+        tree.entry(parent.to_string()).or_default().push(entry);
+    }
+    let mut writer = Writer {
+        blocks: Vec::new(),
+        nat: BTreeMap::new(),
+        next_nid: ROOT_INO + 1,
+    };
+    writer.directory("", ROOT_INO, ROOT_INO, &tree);
+
+    let main_segments = (writer.blocks.len() as u32).div_ceil(BLOCKS_PER_SEG);
+    let block_count = MAIN_BLKADDR + main_segments * BLOCKS_PER_SEG;
+    let mut image = vec![0u8; block_count as usize * BLOCK];
+    let block = |n: u32| n as usize * BLOCK..(n as usize + 1) * BLOCK;
+
+    let mut superblock = vec![0u8; 3072];
+    put32(&mut superblock, 0, 0xf2f5_2010);
+    put16(&mut superblock, 4, 1);
+    put16(&mut superblock, 6, 16);
+    for (at, value) in [
+        (8, 9),
+        (12, 3),
+        (16, 12),
+        (20, LOG_BLOCKS_PER_SEG),
+        (24, 1),
+        (28, 1),
+    ] {
+        put32(&mut superblock, at, value);
+    }
+    put64(&mut superblock, 36, block_count as u64);
+    put32(&mut superblock, 44, main_segments);
+    put32(&mut superblock, 48, block_count / BLOCKS_PER_SEG - 1);
+    for (at, value) in [
+        (52, 2),
+        (56, 2),
+        (60, 2),
+        (64, 1),
+        (68, main_segments),
+        (72, CP_BLKADDR),
+        (76, CP_BLKADDR),
+        (80, SIT_BLKADDR),
+        (84, NAT_BLKADDR),
+        (88, SSA_BLKADDR),
+        (92, MAIN_BLKADDR),
+        (96, ROOT_INO),
+        (100, 1),
+        (104, 2),
+    ] {
+        put32(&mut superblock, at, value);
+    }
+    superblock[108..124].copy_from_slice(&[0x5a; 16]);
+    for (i, unit) in "fixture".encode_utf16().enumerate() {
+        put16(&mut superblock, 124 + 2 * i, unit);
+    }
+    image[1024..1024 + 3072].copy_from_slice(&superblock);
+    image[BLOCK + 1024..BLOCK + 1024 + 3072].copy_from_slice(&superblock);
+
+    // Checkpoint, compacted summaries with the root inode in the NAT journal, checkpoint.
+    let used = writer.blocks.len() as u64;
+    let mut checkpoint = [0u8; BLOCK];
+    put64(&mut checkpoint, 0, 1);
+    put64(&mut checkpoint, 8, (main_segments * BLOCKS_PER_SEG) as u64);
+    put64(&mut checkpoint, 16, used);
+    put32(&mut checkpoint, 132, 0x1 | 0x4);
+    put32(&mut checkpoint, 136, 3);
+    put32(&mut checkpoint, 140, 1);
+    put32(&mut checkpoint, 144, writer.nat.len() as u32);
+    put32(&mut checkpoint, 148, writer.nat.len() as u32);
+    put32(&mut checkpoint, 152, writer.next_nid);
+    put32(&mut checkpoint, 156, BLOCKS_PER_SEG / 8);
+    put32(&mut checkpoint, 160, BLOCKS_PER_SEG / 8);
+    put32(&mut checkpoint, 164, (BLOCK - 4) as u32);
+    let checksum = crc(&checkpoint[..BLOCK - 4]);
+    put32(&mut checkpoint, BLOCK - 4, checksum);
+    let mut summary = [0u8; BLOCK];
+    put16(&mut summary, 0, 1);
+    put32(&mut summary, 2, ROOT_INO);
+    put32(&mut summary, 7, ROOT_INO);
+    put32(&mut summary, 11, writer.nat[&ROOT_INO]);
+    image[block(CP_BLKADDR)].copy_from_slice(&checkpoint);
+    image[block(CP_BLKADDR + 1)].copy_from_slice(&summary);
+    image[block(CP_BLKADDR + 2)].copy_from_slice(&checkpoint);
+
+    // One SIT entry per main segment: valid block count, then the validity bitmap.
+    for segment in 0..main_segments {
+        let at = SIT_BLKADDR as usize * BLOCK + segment as usize * 74;
+        let first = segment * BLOCKS_PER_SEG;
+        let valid = (used as u32).saturating_sub(first).min(BLOCKS_PER_SEG);
+        put16(&mut image, at, valid as u16);
+        for offset in 0..valid as usize {
+            image[at + 2 + offset / 8] |= 0x80 >> (offset % 8);
+        }
+    }
+
+    for (nid, address) in &writer.nat {
+        if *nid == ROOT_INO {
+            continue;
+        }
+        let at = NAT_BLKADDR as usize * BLOCK + *nid as usize * 9;
+        put32(&mut image, at + 1, *nid);
+        put32(&mut image, at + 5, *address);
+    }
+
+    for (n, data) in writer.blocks.iter().enumerate() {
+        image[block(MAIN_BLKADDR + n as u32)].copy_from_slice(data);
+    }
+    image
+}
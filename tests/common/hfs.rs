@@ -0,0 +1,367 @@
+//! Minimal HFS writer: 1 KiB allocation blocks from sector 5, the volume bitmap in sectors
+//! 3 and 4. File data is written first, then the extents overflow and catalog B-trees;
+//! the catalog gets an index node once its records need more than one leaf.
+//! `docs/big.bin` is split in extents of two blocks with a free block between them, the
+//! last two in the extents overflow file; `hello.txt` has a resource fork, a type and
+//! creator and a thread record. Deleted files keep their data in free blocks.
+use super::{Entry, Node};
+use std::collections::BTreeMap;
+
+const SECTOR: usize = 512;
+const BLOCK: usize = 1024;
+const BITMAP_START: usize = 3;
+const FIRST_BLOCK: usize = 5;
+const NODE: usize = 512;
+const FIRST_ID: u32 = 16;
+const ROOT_ID: u32 = 2;
+/// 2024-01-02 03:04:05, seconds since 1904.
+const TIMESTAMP: u32 = 1_704_164_645 + 2_082_844_800;
+const HELLO_RESOURCE: &[u8] = b"resource fork of hello";
+
+type Extent = (u16, u16);
+
+fn put16(buffer: &mut [u8], at: usize, value: u16) {
+    buffer[at..at + 2].copy_from_slice(&value.to_be_bytes());
+}
+
+fn put32(buffer: &mut [u8], at: usize, value: u32) {
+    buffer[at..at + 4].copy_from_slice(&value.to_be_bytes());
+}
+
+fn put_extents(buffer: &mut [u8], at: usize, extents: &[Extent]) {
+    for (n, (start, count)) in extents.iter().take(3).enumerate() {
+        put16(buffer, at + 4 * n, *start);
+        put16(buffer, at + 4 * n + 2, *count);
+    }
+}
+
+/// Catalog key of `name` in `parent`, `padded` to the fixed size of index keys.
+fn catalog_key(parent: u32, name: &str, padded: bool) -> Vec<u8> {
+    let length = if padded { 0x25 } else { 6 + name.len() };
+    let mut key = vec![0u8; length + 1];
+    key[0] = length as u8;
+    put32(&mut key, 2, parent);
+    key[6] = name.len() as u8;
+    key[7..7 + name.len()].copy_from_slice(name.as_bytes());
+    key
+}
+
+/// A B-tree record: `key`, padding to an even offset, then `data`.
+fn record(key: &[u8], data: &[u8]) -> Vec<u8> {
+    let mut record = key.to_vec();
+    record.resize(key.len().next_multiple_of(2), 0);
+    record.extend_from_slice(data);
+    record
+}
+
+/// Node of `kind` at `height` holding `records`, linked to `previous` and `next`.
+fn node(kind: u8, height: u8, records: &[Vec<u8>], previous: u32, next: u32) -> Vec<u8> {
+    let mut node = vec![0u8; NODE];
+    put32(&mut node, 0, next);
+    put32(&mut node, 4, previous);
+    node[8] = kind;
+    node[9] = height;
+    put16(&mut node, 10, records.len() as u16);
+    let mut at = 14;
+    for (n, record) in records.iter().enumerate() {
+        node[at..at + record.len()].copy_from_slice(record);
+        put16(&mut node, NODE - 2 * (n + 1), at as u16);
+        at += record.len();
+    }
+    put16(&mut node, NODE - 2 * (records.len() + 1), at as u16);
+    node
+}
+
+/// B-tree file of `records`, sorted, packed in leaves below at most one index node.
+fn btree(
+    records: &[(Vec<u8>, Vec<u8>)],
+    max_key: u16,
+    index_key: impl Fn(&[u8]) -> Vec<u8>,
+) -> Vec<u8> {
+    let mut leaves: Vec<Vec<&(Vec<u8>, Vec<u8>)>> = Vec::new();
+    let mut used = NODE;
+    for entry in records {
+        let size = record(&entry.0, &entry.1).len() + 2;
+        if used + size + 2 > NODE - 14 {
+            leaves.push(Vec::new());
+            used = 0;
+        }
+        leaves.last_mut().unwrap().push(entry);
+        used += size;
+    }
+    let mut nodes = vec![Vec::new()];
+    let count = leaves.len() as u32;
+    for (n, leaf) in leaves.iter().enumerate() {
+        let n = n as u32 + 1;
+        let records: Vec<_> = leaf.iter().map(|(k, d)| record(k, d)).collect();
+        let next = if n < count { n + 1 } else { 0 };
+        nodes.push(node(0xff, 1, &records, n - 1, next));
+    }
+    let (root, depth) = match count {
+        0 => (0, 0),
+        1 => (1, 1),
+        _ => {
+            let records: Vec<_> = leaves
+                .iter()
+                .enumerate()
+                .map(|(n, leaf)| record(&index_key(&leaf[0].0), &(n as u32 + 1).to_be_bytes()))
+                .collect();
+            nodes.push(node(0x00, 2, &records, 0, 0));
+            (count + 1, 2)
+        }
+    };
+
+    let mut header = vec![0u8; 106];
+    put16(&mut header, 0, depth);
+    put32(&mut header, 2, root);
+    put32(&mut header, 6, records.len() as u32);
+    put32(&mut header, 10, (count > 0) as u32);
+    put32(&mut header, 14, count);
+    put16(&mut header, 18, NODE as u16);
+    put16(&mut header, 20, max_key);
+    put32(&mut header, 22, nodes.len() as u32);
+    let mut map = vec![0u8; NODE - 248 - 8];
+    for n in 0..nodes.len() {
+        map[n / 8] |= 0x80 >> (n % 8);
+    }
+    nodes[0] = node(0x01, 0, &[header, vec![0u8; 128], map], 0, 0);
+    let mut tree = nodes.concat();
+    tree.resize(tree.len().next_multiple_of(BLOCK), 0);
+    tree
+}
+
+struct Writer {
+    /// Allocation blocks.
+    data: Vec<u8>,
+    used: Vec<bool>,
+    next_id: u32,
+    catalog: Vec<(Vec<u8>, Vec<u8>)>,
+    /// Extents overflow records: fork, file id, first block in the fork, extents.
+    overflow: Vec<(u8, u32, u16, Vec<Extent>)>,
+    files: u32,
+    directories: u32,
+}
+
+impl Writer {
+    /// New blocks holding `bytes`, marked used when `used`.
+    fn allocate(&mut self, bytes: &[u8], used: bool) -> Extent {
+        let start = self.data.len() / BLOCK;
+        let count = bytes.len().div_ceil(BLOCK);
+        self.data.resize((start + count) * BLOCK, 0);
+        self.data[start * BLOCK..start * BLOCK + bytes.len()].copy_from_slice(bytes);
+        self.used.resize(start + count, used);
+        (start as u16, count as u16)
+    }
+
+    /// Extents of `content`, in pieces of `blocks` blocks with a free block between
+    /// them when given.
+    fn fork(&mut self, content: &[u8], blocks: Option<usize>) -> Vec<Extent> {
+        match blocks {
+            None if content.is_empty() => Vec::new(),
+            None => vec![self.allocate(content, true)],
+            Some(blocks) => content
+                .chunks(blocks * BLOCK)
+                .map(|piece| {
+                    let extent = self.allocate(piece, true);
+                    self.allocate(&[0u8; BLOCK], false);
+                    extent
+                })
+                .collect(),
+        }
+    }
+
+    fn new_id(&mut self) -> u32 {
+        self.next_id += 1;
+        self.next_id - 1
+    }
+
+    fn thread(&mut self, kind: u8, id: u32, parent: u32, name: &str) {
+        let mut data = vec![0u8; 46];
+        data[0] = kind;
+        put32(&mut data, 10, parent);
+        data[14] = name.len() as u8;
+        data[15..15 + name.len()].copy_from_slice(name.as_bytes());
+        self.catalog.push((catalog_key(id, "", false), data));
+    }
+
+    fn file(&mut self, path: &str, id: u32, parent: u32, name: &str, content: &[u8]) {
+        let (blocks, resource) = match path {
+            "docs/big.bin" => (Some(2), &[][..]),
+            "hello.txt" => (None, HELLO_RESOURCE),
+            _ => (None, &[][..]),
+        };
+        let data_extents = self.fork(content, blocks);
+        let resource_extents = self.fork(resource, None);
+        let physical =
+            |extents: &[Extent]| extents.iter().map(|e| e.1 as u32).sum::<u32>() * BLOCK as u32;
+        let mut data = vec![0u8; 102];
+        data[0] = 2;
+        if path == "hello.txt" {
+            data[2] = 0x02;
+            data[4..12].copy_from_slice(b"TEXTttxt");
+            put16(&mut data, 12, 0x0100);
+            self.thread(4, id, parent, name);
+        }
+        put32(&mut data, 20, id);
+        put16(&mut data, 24, data_extents.first().map_or(0, |e| e.0));
+        put32(&mut data, 26, content.len() as u32);
+        put32(&mut data, 30, physical(&data_extents));
+        put16(&mut data, 34, resource_extents.first().map_or(0, |e| e.0));
+        put32(&mut data, 36, resource.len() as u32);
+        put32(&mut data, 40, physical(&resource_extents));
+        for at in [44, 48] {
+            put32(&mut data, at, TIMESTAMP);
+        }
+        put_extents(&mut data, 74, &data_extents);
+        put_extents(&mut data, 86, &resource_extents);
+        if data_extents.len() > 3 {
+            let first_block = data_extents[..3].iter().map(|e| e.1).sum();
+            self.overflow
+                .push((0, id, first_block, data_extents[3..].to_vec()));
+        }
+        self.catalog.push((catalog_key(parent, name, false), data));
+        self.files += 1;
+    }
+
+    /// Write directory `path` as `id` in `parent`, its children first.
+    fn directory(
+        &mut self,
+        path: &str,
+        id: u32,
+        parent: u32,
+        name: &str,
+        tree: &BTreeMap<String, Vec<&Entry>>,
+    ) {
+        let children = tree.get(path).cloned().unwrap_or_default();
+        let mut valence = 0;
+        for entry in &children {
+            let child_name = entry.path.rsplit('/').next().unwrap();
+            match &entry.node {
+                Node::Dir => {
+                    let child = self.new_id();
+                    self.directory(entry.path, child, id, child_name, tree);
+                }
+                Node::File(data) => {
+                    let child = self.new_id();
+                    self.file(entry.path, child, id, child_name, data);
+                }
+                Node::Sparse { size, offset, data } => {
+                    let content = super::sparse_content(*size, *offset, data);
+                    let child = self.new_id();
+                    self.file(entry.path, child, id, child_name, &content);
+                }
+                Node::Deleted(data) => {
+                    self.allocate(data, false);
+                    continue;
+                }
+                other => panic!("HFS fixtures cannot hold {:?}", other),
+            }
+            valence += 1;
+        }
+        let mut data = vec![0u8; 70];
+        data[0] = 1;
+        put16(&mut data, 4, valence);
+        put32(&mut data, 6, id);
+        for at in [10, 14] {
+            put32(&mut data, at, TIMESTAMP);
+        }
+        self.catalog.push((catalog_key(parent, name, false), data));
+        self.thread(3, id, parent, name);
+        if id != ROOT_ID {
+            self.directories += 1;
+        }
+    }
+}
+
+/// Image of the entries.
+pub fn build(entries: &[Entry]) -> Vec<u8> {
+    let mut tree: BTreeMap<String, Vec<&Entry>> = BTreeMap::new();
+    for entry in entries {
+        let parent = entry.path.rsplit_once('/').map_or("", |(parent, _)| parent);
+        tree.entry(parent.to_string()).or_default().push(entry);
+    }
+    let mut writer = Writer {
+        data: Vec::new(),
+        used: Vec::new(),
+        next_id: FIRST_ID,
+        catalog: Vec::new(),
+        overflow: Vec::new(),
+        files: 0,
+        directories: 0,
+    };
+    writer.directory("", ROOT_ID, 1, "fixture", &tree);
+    let root_files = tree[""]
+        .iter()
+        .filter(|e| matches!(e.node, Node::File(_) | Node::Sparse { .. }))
+        .count();
+
+    let sort_key = |key: &[u8]| {
+        let parent = u32::from_be_bytes(key[2..6].try_into().unwrap());
+        (parent, key[7..].to_ascii_lowercase())
+    };
+    writer.catalog.sort_by_key(|(key, _)| sort_key(key));
+    let catalog = btree(&writer.catalog, 0x25, |key| {
+        let name = std::str::from_utf8(&key[7..]).unwrap();
+        catalog_key(
+            u32::from_be_bytes(key[2..6].try_into().unwrap()),
+            name,
+            true,
+        )
+    });
+    let overflow: Vec<_> = writer
+        .overflow
+        .iter()
+        .map(|(fork, id, first_block, extents)| {
+            let mut key = vec![7, *fork, 0, 0, 0, 0, 0, 0];
+            put32(&mut key, 2, *id);
+            put16(&mut key, 6, *first_block);
+            let mut data = vec![0u8; 12];
+            put_extents(&mut data, 0, extents);
+            (key, data)
+        })
+        .collect();
+    let extents_file = btree(&overflow, 7, |key| key.to_vec());
+    let extents_extent = writer.allocate(&extents_file, true);
+    let catalog_extent = writer.allocate(&catalog, true);
+
+    let blocks = writer.data.len() / BLOCK;
+    let sectors = FIRST_BLOCK + blocks * BLOCK / SECTOR + 2;
+    let mut image = vec![0u8; sectors * SECTOR];
+    image[FIRST_BLOCK * SECTOR..FIRST_BLOCK * SECTOR + writer.data.len()]
+        .copy_from_slice(&writer.data);
+    for (block, used) in writer.used.iter().enumerate() {
+        if *used {
+            image[BITMAP_START * SECTOR + block / 8] |= 0x80 >> (block % 8);
+        }
+    }
+
+    let mut mdb = vec![0u8; 162];
+    put16(&mut mdb, 0, 0x4244);
+    put32(&mut mdb, 2, TIMESTAMP);
+    put32(&mut mdb, 6, TIMESTAMP);
+    put16(&mut mdb, 10, 0x0100);
+    put16(&mut mdb, 12, root_files as u16);
+    put16(&mut mdb, 14, BITMAP_START as u16);
+    put16(&mut mdb, 18, blocks as u16);
+    put32(&mut mdb, 20, BLOCK as u32);
+    put32(&mut mdb, 24, 4 * BLOCK as u32);
+    put16(&mut mdb, 28, FIRST_BLOCK as u16);
+    put32(&mut mdb, 30, writer.next_id);
+    put16(
+        &mut mdb,
+        34,
+        writer.used.iter().filter(|u| !**u).count() as u16,
+    );
+    mdb[36] = 7;
+    mdb[37..44].copy_from_slice(b"fixture");
+    put32(&mut mdb, 84, writer.files);
+    put32(&mut mdb, 88, writer.directories);
+    put32(&mut mdb, 130, extents_file.len() as u32);
+    put_extents(&mut mdb, 134, &[extents_extent]);
+    put32(&mut mdb, 146, catalog.len() as u32);
+    put_extents(&mut mdb, 150, &[catalog_extent]);
+    image[1024..1024 + mdb.len()].copy_from_slice(&mdb);
+    let alternate = (sectors - 2) * SECTOR;
+    image[alternate..alternate + mdb.len()].copy_from_slice(&mdb);
+    image
+}
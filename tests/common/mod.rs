@@ -0,0 +1,391 @@
+//! Test images built from a description of their content, and the checks every backend
+//! must pass on them.
+//!
+//! Images come from the usual tools where they can be populated without mounting
+//! (`mke2fs -d` and `debugfs` for ext4, `mkntfs` and `ntfscp` for NTFS) and from
+//! pure-Rust writers for NTFS records, exFAT and the backends of this crate (ZFS,
+//! SquashFS, UDF, F2FS, UFS, ReFS, HFS, UBIFS, YAFFS2, CramFS), which also get an image
+//! made by their usual tool (see `check_tool_image`). The tests needing a tool skip
+//! themselves when it is not installed, see `tools`.
+pub mod cramfs;
+pub mod exfat;
+pub mod f2fs;
+pub mod hfs;
+pub mod ntfs;
+pub mod refs;
+pub mod squashfs;
+pub mod ubifs;
+pub mod udf;
+pub mod ufs;
+pub mod yaffs2;
+pub mod zfs;
+
+use exhume_filesystem::detected_fs::{DetectedFs, ImageStream};
+use exhume_filesystem::filesystem::{COMMON_KEY, SYMLINK_TARGET_KEY, WalkEvent};
+use exhume_filesystem::{File, Filesystem};
+use std::collections::BTreeMap;
+use std::fs;
+use std::io::Cursor;
+use std::path::{Path, PathBuf};
+use std::process::Command;
+
+/// What a fixture path holds.
+#[derive(Debug, Clone)]
+pub enum Node {
+    Dir,
+    File(Vec<u8>),
+    /// `size` bytes reading as zeros but for `data` at `offset`, the rest left as holes.
+    Sparse {
+        size: u64,
+        offset: u64,
+        data: Vec<u8>,
+    },
+    Symlink(&'static str),
+    /// A file created, then deleted: it must not be walked.
+    Deleted(Vec<u8>),
+    /// Named data stream of the file at the same path (NTFS).
+    Stream {
+        name: &'static str,
+        data: Vec<u8>,
+    },
+}
+
+/// A path (relative, `/`-separated) and what it holds.
+#[derive(Debug, Clone)]
+pub struct Entry {
+    pub path: &'static str,
+    pub node: Node,
+}
+
+/// Content every backend gets, filtered down to what it can hold.
+pub fn sample() -> Vec<Entry> {
+    let entry = |path, node| Entry { path, node };
+    vec![
+        entry("hello.txt", Node::File(b"hello, fixture\n".to_vec())),
+        entry(
+            "hello.txt",
+            Node::Stream {
+                name: "Zone.Identifier",
+                data: b"[ZoneTransfer]\r\nZoneId=3\r\n".to_vec(),
+            },
+        ),
+        entry("empty.txt", Node::File(Vec::new())),
+        entry("docs", Node::Dir),
+        entry(
+            "docs/big.bin",
+            Node::File((0..10_000u32).map(|i| (i * 7 % 251) as u8).collect()),
+        ),
+        entry(
+            "sparse.bin",
+            Node::Sparse {
+                size: 1 << 20,
+                offset: 512 << 10,
+                data: b"middle of nowhere".to_vec(),
+            },
+        ),
+        entry("link", Node::Symlink("hello.txt")),
+        entry("gone.txt", Node::Deleted(b"deleted content\n".to_vec())),
+    ]
+}
+
+/// Content of `sample()` but the nodes `skip` matches.
+pub fn sample_without(skip: impl Fn(&Node) -> bool) -> Vec<Entry> {
+    sample().into_iter().filter(|e| !skip(&e.node)).collect()
+}
+
+/// Full content of a sparse node.
+fn sparse_content(size: u64, offset: u64, data: &[u8]) -> Vec<u8> {
+    let mut content = vec![0u8; size as usize];
+    content[offset as usize..offset as usize + data.len()].copy_from_slice(data);
+    content
+}
+
+/// Directory removed with everything in it when dropped.
+pub struct Scratch(pub PathBuf);
+
+impl Scratch {
+    pub fn new(name: &str) -> Self {
+        let dir =
+            std::env::temp_dir().join(format!("exhume-fixture-{}-{}", std::process::id(), name));
+        let _ = fs::remove_dir_all(&dir);
+        fs::create_dir_all(&dir).unwrap();
+        Self(dir)
+    }
+}
+
+impl Drop for Scratch {
+    fn drop(&mut self) {
+        let _ = fs::remove_dir_all(&self.0);
+    }
+}
+
+/// Write the entries as a host tree below `root`, deleted files included (the caller
+/// deletes them once they are in the image).
+pub fn populate(root: &Path, entries: &[Entry]) {
+    fs::create_dir_all(root).unwrap();
+    for entry in entries {
+        let path = root.join(entry.path);
+        match &entry.node {
+            Node::Dir => fs::create_dir_all(&path).unwrap(),
+            Node::File(data) | Node::Deleted(data) => fs::write(&path, data).unwrap(),
+            Node::Sparse { size, offset, data } => {
+                use std::io::{Seek, SeekFrom, Write};
+                let mut file = fs::File::create(&path).unwrap();
+                file.set_len(*size).unwrap();
+                file.seek(SeekFrom::Start(*offset)).unwrap();
+                file.write_all(data).unwrap();
+            }
+            #[cfg(unix)]
+            Node::Symlink(target) => std::os::unix::fs::symlink(target, &path).unwrap(),
+            #[cfg(not(unix))]
+            Node::Symlink(_) => panic!("symbolic links need a unix host"),
+            Node::Stream { .. } => {}
+        }
+    }
+}
+
+/// Whether every program of `programs` is in `PATH`; the caller skips its test when not.
+pub fn tools(programs: &[&str]) -> bool {
+    let path = std::env::var_os("PATH").unwrap_or_default();
+    let missing: Vec<&str> = programs
+        .iter()
+        .copied()
+        .filter(|program| !std::env::split_paths(&path).any(|dir| dir.join(program).is_file()))
+        .collect();
+    if !missing.is_empty() {
+        eprintln!("skipped: {} not found", missing.join(", "));
+    }
+    missing.is_empty()
+}
+
+pub fn run(program: &str, args: &[&str]) -> Result<(), String> {
+    let output = Command::new(program)
+        .args(args)
+        .output()
+        .map_err(|e| format!("{}: {}", program, e))?;
+    if !output.status.success() {
+        return Err(format!(
+            "{} {:?} failed: {}",
+            program,
+            args,
+            String::from_utf8_lossy(&output.stderr)
+        ));
+    }
+    Ok(())
+}
+
+/// ext4 image of the entries: `mke2fs -d` copies the tree (holes included), `debugfs`
+/// deletes the deleted files.
+pub fn build_ext4(scratch: &Path, entries: &[Entry]) -> Result<PathBuf, String> {
+    let tree = scratch.join("ext4-tree");
+    populate(&tree, entries);
+    let image = scratch.join("ext4.img");
+    let (tree, image_str) = (tree.to_str().unwrap(), image.to_str().unwrap());
+    run(
+        "mke2fs",
+        &[
+            "-q",
+            "-F",
+            "-t",
+            "ext4",
+            "-O",
+            "^has_journal",
+            "-b",
+            "1024",
+            "-d",
+            tree,
+            image_str,
+            "8M",
+        ],
+    )?;
+    for entry in entries {
+        if let Node::Deleted(_) = entry.node {
+            run(
+                "debugfs",
+                &["-w", "-R", &format!("rm /{}", entry.path), image_str],
+            )?;
+        }
+    }
+    Ok(image)
+}
+
+/// NTFS image of the entries, which must all be in the root directory: `ntfscp` cannot
+/// create directories. Streams are written with `ntfscp -N`.
+pub fn build_ntfs(scratch: &Path, entries: &[Entry]) -> Result<PathBuf, String> {
+    let image = scratch.join("ntfs.img");
+    fs::File::create(&image)
+        .and_then(|f| f.set_len(16 << 20))
+        .map_err(|e| e.to_string())?;
+    let image_str = image.to_str().unwrap();
+    run("mkntfs", &["-q", "-F", "-f", "-L", "fixture", image_str])?;
+    for (i, entry) in entries.iter().enumerate() {
+        assert!(!entry.path.contains('/'), "NTFS fixtures are flat");
+        let source = scratch.join(format!("ntfs-source-{}", i));
+        let destination = format!("/{}", entry.path);
+        match &entry.node {
+            Node::File(data) => {
+                fs::write(&source, data).map_err(|e| e.to_string())?;
+                run(
+                    "ntfscp",
+                    &["-q", image_str, source.to_str().unwrap(), &destination],
+                )?;
+            }
+            Node::Stream { name, data } => {
+                fs::write(&source, data).map_err(|e| e.to_string())?;
+                run(
+                    "ntfscp",
+                    &[
+                        "-q",
+                        "-N",
+                        name,
+                        image_str,
+                        source.to_str().unwrap(),
+                        &destination,
+                    ],
+                )?;
+            }
+            other => panic!("NTFS fixtures cannot hold {:?}", other),
+        }
+    }
+    Ok(image)
+}
+
+pub fn open_image(image: &Path) -> DetectedFs<ImageStream> {
+    let sectors = fs::metadata(image).unwrap().len() / 512;
+    DetectedFs::from_image(image.to_str().unwrap(), 0, sectors)
+        .unwrap_or_else(|e| panic!("could not open {}: {}", image.display(), e))
+}
+
+/// An image opened in memory, with its records walked by `check_tree`.
+pub type Checked = (DetectedFs<Cursor<Vec<u8>>>, BTreeMap<String, File>);
+
+/// Open `image` as `filesystem_type` and run `check_tree` on it.
+pub fn check_image(image: Vec<u8>, filesystem_type: &str, entries: &[Entry]) -> Checked {
+    let mut fs = DetectedFs::from_reader(Cursor::new(image)).unwrap();
+    assert_eq!(fs.filesystem_type(), filesystem_type);
+    let files = check_tree(&mut fs, entries);
+    (fs, files)
+}
+
+/// `check_image` on the image `commands` make of a host tree of `entries`, or `None` when
+/// one of their programs is not installed. `{tree}` and `{image}` in the arguments stand
+/// for the tree and the image, which is first created `image_size` bytes long unless
+/// zero. Tools have no deleted files to show.
+pub fn check_tool_image(
+    scratch: &Path,
+    filesystem_type: &str,
+    entries: &[Entry],
+    image_size: u64,
+    commands: &[&[&str]],
+) -> Option<Checked> {
+    let mut programs: Vec<&str> = commands.iter().map(|command| command[0]).collect();
+    programs.sort_unstable();
+    programs.dedup();
+    if !tools(&programs) {
+        return None;
+    }
+    assert!(
+        !entries.iter().any(|e| matches!(e.node, Node::Deleted(_))),
+        "tool images hold no deleted files"
+    );
+    let tree = scratch.join("tool-tree");
+    populate(&tree, entries);
+    let image = scratch.join("tool.img");
+    if image_size > 0 {
+        fs::File::create(&image)
+            .and_then(|f| f.set_len(image_size))
+            .unwrap();
+    }
+    let (tree, image_str) = (tree.to_str().unwrap(), image.to_str().unwrap());
+    for command in commands {
+        let args: Vec<String> = command[1..]
+            .iter()
+            .map(|arg| arg.replace("{tree}", tree).replace("{image}", image_str))
+            .collect();
+        let args: Vec<&str> = args.iter().map(String::as_str).collect();
+        run(command[0], &args).unwrap();
+    }
+    Some(check_image(
+        fs::read(&image).unwrap(),
+        filesystem_type,
+        entries,
+    ))
+}
+
+/// Walked records by `/`-separated path.
+pub fn walk<F: Filesystem + ?Sized>(fs: &mut F) -> BTreeMap<String, File> {
+    let separator = fs.path_separator();
+    let mut files = BTreeMap::new();
+    fs.walk_fs(&mut |event| {
+        if let WalkEvent::File(file) = event {
+            files.insert(file.absolute_path.replace(separator.as_str(), "/"), file);
+        }
+    })
+    .unwrap();
+    files
+}
+
+/// Checks every backend must pass: each entry is walked at its path with its type and
+/// size, its content reads back whole and in slices, symbolic links name their target
+/// and deleted files are not walked. Returns the walked records.
+pub fn check_tree<F: Filesystem + ?Sized>(fs: &mut F, entries: &[Entry]) -> BTreeMap<String, File> {
+    let files = walk(fs);
+    for entry in entries {
+        let path = format!("/{}", entry.path);
+        let content = match &entry.node {
+            Node::Deleted(_) => {
+                assert!(!files.contains_key(&path), "deleted {} was walked", path);
+                continue;
+            }
+            Node::Stream { .. } => continue,
+            Node::File(data) => Some(data.clone()),
+            Node::Sparse { size, offset, data } => Some(sparse_content(*size, *offset, data)),
+            Node::Dir | Node::Symlink(_) => None,
+        };
+        let file = files
+            .get(&path)
+            .unwrap_or_else(|| panic!("{} was not walked, got {:?}", path, files.keys()));
+        let ftype = match entry.node {
+            Node::Dir => "dir",
+            Node::Symlink(_) => "symlink",
+            _ => "file",
+        };
+        assert_eq!(file.ftype, ftype, "type of {}", path);
+        assert_eq!(file.name, entry.path.rsplit('/').next().unwrap());
+        if let Node::Symlink(target) = entry.node {
+            assert_eq!(
+                file.metadata[COMMON_KEY][SYMLINK_TARGET_KEY], target,
+                "target of {}",
+                path
+            );
+        }
+        let Some(content) = content else {
+            continue;
+        };
+        assert_eq!(file.size, content.len() as u64, "size of {}", path);
+        let record = fs
+            .get_file_by_path(&path, file.identifier)
+            .unwrap_or_else(|e| panic!("lookup of {}: {}", path, e));
+        assert_eq!(
+            fs.read_file_content(&record).unwrap(),
+            content,
+            "content of {}",
+            path
+        );
+        let (offset, length) = (content.len() / 3, 7.min(content.len()));
+        assert_eq!(
+            fs.read_file_slice(&record, offset as u64, length).unwrap(),
+            &content[offset..offset + length.min(content.len() - offset)],
+            "slice of {}",
+            path
+        );
+        assert_eq!(
+            fs.read_file_slice(&record, content.len() as u64, 16)
+                .unwrap(),
+            b"",
+            "slice past the end of {}",
+            path
+        );
+    }
+    files
+}
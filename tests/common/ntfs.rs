@@ -0,0 +1,120 @@
+//! Single NTFS file records, for what `mkntfs` and `ntfscp` cannot make: a 1 KiB FILE
+//! record with its update sequence array and resident attributes ($FILE_NAME, $EA...).
+
+const RECORD_SIZE: usize = 1024;
+const ATTRIBUTES_OFFSET: usize = 0x38;
+pub const ATTR_FILE_NAME: u32 = 0x30;
+pub const ATTR_REPARSE_POINT: u32 = 0xC0;
+pub const ATTR_EA_INFORMATION: u32 = 0xD0;
+pub const ATTR_EA: u32 = 0xE0;
+const UPDATE_SEQUENCE: u16 = 1;
+
+fn put16(buffer: &mut [u8], at: usize, value: u16) {
+    buffer[at..at + 2].copy_from_slice(&value.to_le_bytes());
+}
+
+fn put32(buffer: &mut [u8], at: usize, value: u32) {
+    buffer[at..at + 4].copy_from_slice(&value.to_le_bytes());
+}
+
+/// $FILE_NAME attribute: parent reference, zero times and sizes, then the namespace and
+/// name.
+pub fn file_name(parent: u64, namespace: u8, name: &str) -> (u32, Vec<u8>) {
+    let units: Vec<u16> = name.encode_utf16().collect();
+    let mut value = vec![0u8; 0x42];
+    value[..8].copy_from_slice(&parent.to_le_bytes());
+    value[0x40] = units.len() as u8;
+    value[0x41] = namespace;
+    value.extend(units.iter().flat_map(|u| u.to_le_bytes()));
+    (ATTR_FILE_NAME, value)
+}
+
+/// In-use file record holding `attributes`, resident, as (type, value).
+pub fn file_record(attributes: &[(u32, Vec<u8>)]) -> Vec<u8> {
+    let links = attributes
+        .iter()
+        .filter(|(kind, _)| *kind == ATTR_FILE_NAME)
+        .count();
+    let mut record = vec![0u8; RECORD_SIZE];
+    record[..4].copy_from_slice(b"FILE");
+    // Update sequence array after the header: the number, then a word per sector.
+    let sectors = RECORD_SIZE / 512;
+    put16(&mut record, 0x04, 0x30);
+    put16(&mut record, 0x06, sectors as u16 + 1);
+    // Sequence number of the record.
+    put16(&mut record, 0x10, 1);
+    put16(&mut record, 0x12, links as u16);
+    put16(&mut record, 0x14, ATTRIBUTES_OFFSET as u16);
+    put16(&mut record, 0x16, 0x0001);
+    put32(&mut record, 0x1C, RECORD_SIZE as u32);
+
+    let mut at = ATTRIBUTES_OFFSET;
+    for (id, (kind, value)) in attributes.iter().enumerate() {
+        let length = (0x18 + value.len()).next_multiple_of(8);
+        put32(&mut record, at, *kind);
+        put32(&mut record, at + 4, length as u32);
+        put16(&mut record, at + 0x0E, id as u16);
+        put32(&mut record, at + 0x10, value.len() as u32);
+        put16(&mut record, at + 0x14, 0x18);
+        record[at + 0x18..at + 0x18 + value.len()].copy_from_slice(value);
+        at += length;
+    }
+    put32(&mut record, at, 0xFFFF_FFFF);
+    put32(&mut record, 0x18, (at + 8) as u32);
+    put16(&mut record, 0x28, attributes.len() as u16);
+
+    // The last word of each sector goes to the array, replaced by the sequence number.
+    put16(&mut record, 0x30, UPDATE_SEQUENCE);
+    for sector in 0..sectors {
+        let end = (sector + 1) * 512 - 2;
+        record.copy_within(end..end + 2, 0x32 + 2 * sector);
+        put16(&mut record, end, UPDATE_SEQUENCE);
+    }
+    record
+}
+
+/// $EA attribute holding `entries`, as (name, value), each aligned on 4 bytes.
+pub fn ea(entries: &[(&str, Vec<u8>)]) -> (u32, Vec<u8>) {
+    let mut value = Vec::new();
+    for (i, (name, data)) in entries.iter().enumerate() {
+        let mut entry = vec![0u8; 8];
+        entry[5] = name.len() as u8;
+        entry[6..8].copy_from_slice(&(data.len() as u16).to_le_bytes());
+        entry.extend_from_slice(name.as_bytes());
+        entry.push(0);
+        entry.extend_from_slice(data);
+        entry.resize(entry.len().next_multiple_of(4), 0);
+        if i + 1 < entries.len() {
+            let next = entry.len() as u32;
+            entry[..4].copy_from_slice(&next.to_le_bytes());
+        }
+        value.extend(entry);
+    }
+    (ATTR_EA, value)
+}
+
+/// $REPARSE_POINT attribute of a Microsoft tag: the tag, the data length, then `data`.
+pub fn reparse_point(tag: u32, data: &[u8]) -> (u32, Vec<u8>) {
+    let mut value = vec![0u8; 8];
+    put32(&mut value, 0, tag);
+    put16(&mut value, 4, data.len() as u16);
+    value.extend_from_slice(data);
+    (ATTR_REPARSE_POINT, value)
+}
+
+/// Data of a symbolic link reparse point to `target`, as both its substitute and print
+/// names.
+pub fn symlink_data(target: &str, relative: bool) -> Vec<u8> {
+    let name: Vec<u8> = target
+        .encode_utf16()
+        .flat_map(|u| u.to_le_bytes())
+        .collect();
+    let mut data = vec![0u8; 12];
+    put16(&mut data, 2, name.len() as u16);
+    put16(&mut data, 4, name.len() as u16);
+    put16(&mut data, 6, name.len() as u16);
+    put32(&mut data, 8, relative as u32);
+    data.extend_from_slice(&name);
+    data.extend_from_slice(&name);
+    data
+}
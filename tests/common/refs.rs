@@ -0,0 +1,347 @@
+//! Minimal ReFS 3.4 writer: 4 KiB clusters, 16 KiB metadata pages and 1 MiB containers.
+//! The boot sector, superblock, both checkpoints and the container table sit at their
+//! physical clusters; everything else is allocated from virtual container 2 on, which the
+//! container table maps to physical cluster 64 on. The root directory table is split into
+//! two leaves below an inner node. Files keep their attributes and run tables embedded in
+//! their directory row, all-zero clusters left as holes; named streams get a data
+//! attribute of their own. Deleted files keep their row, flagged as deleted.
+use super::{Entry, Node};
+use std::collections::BTreeMap;
+
+const CLUSTER: usize = 4096;
+const PAGE: usize = 16384;
+const CONTAINER_CLUSTERS: u64 = 256;
+const SUPERBLOCK: u64 = 30;
+const CHECKPOINTS: [u64; 2] = [34, 38];
+const CONTAINER_TABLE: u64 = 42;
+const FIRST_CONTAINER: u64 = 2;
+const DATA_START: u64 = 64;
+const ROOT_DIRECTORY: u64 = 0x600;
+/// 2024-01-02 03:04:05 UTC, as a FILETIME.
+const TIMESTAMP: u64 = (1_704_164_645 + 11_644_473_600) * 10_000_000;
+
+fn put16(buffer: &mut [u8], at: usize, value: u16) {
+    buffer[at..at + 2].copy_from_slice(&value.to_le_bytes());
+}
+
+fn put32(buffer: &mut [u8], at: usize, value: u32) {
+    buffer[at..at + 4].copy_from_slice(&value.to_le_bytes());
+}
+
+fn put64(buffer: &mut [u8], at: usize, value: u64) {
+    buffer[at..at + 8].copy_from_slice(&value.to_le_bytes());
+}
+
+fn utf16(name: &str) -> Vec<u8> {
+    name.encode_utf16().flat_map(u16::to_le_bytes).collect()
+}
+
+/// Minstore node: `root` (its size field filled in), the index header, the rows (key,
+/// value, flags) and the key index.
+fn node(root: &[u8], rows: &[(Vec<u8>, Vec<u8>, u16)], inner: bool) -> Vec<u8> {
+    let mut element = root.to_vec();
+    element.resize(element.len().max(8), 0);
+    let length = element.len() as u32;
+    put32(&mut element, 0, length);
+    let mut body = vec![0u8; 0x20];
+    let mut offsets = Vec::new();
+    for (key, value, flags) in rows {
+        let at = body.len();
+        let key_at = 0x10;
+        let value_at = key_at + key.len().next_multiple_of(8);
+        let size = (value_at + value.len()).next_multiple_of(8);
+        let mut row = vec![0u8; size];
+        put32(&mut row, 0, size as u32);
+        put16(&mut row, 4, key_at as u16);
+        put16(&mut row, 6, key.len() as u16);
+        put16(&mut row, 8, *flags);
+        put16(&mut row, 10, value_at as u16);
+        put16(&mut row, 12, value.len() as u16);
+        row[key_at..key_at + key.len()].copy_from_slice(key);
+        row[value_at..value_at + value.len()].copy_from_slice(value);
+        body.extend(row);
+        offsets.push(at as u32);
+    }
+    let key_index = body.len();
+    for offset in &offsets {
+        body.extend(offset.to_le_bytes());
+    }
+    put32(&mut body, 0x00, 0x20);
+    put32(&mut body, 0x04, key_index as u32);
+    put32(&mut body, 0x08, 0);
+    body[0x0c] = inner as u8;
+    body[0x0d] = if inner { 0x01 } else { 0x00 } | 0x02;
+    put32(&mut body, 0x10, key_index as u32);
+    put32(&mut body, 0x14, offsets.len() as u32);
+    let end = body.len() as u32;
+    put32(&mut body, 0x18, end);
+    element.extend(body);
+    element
+}
+
+/// Page header of `signature` at `cluster` for table `table`, then `content`.
+fn page(signature: &[u8; 4], cluster: u64, table: u64, content: &[u8]) -> Vec<u8> {
+    let mut page = vec![0u8; PAGE];
+    page[..4].copy_from_slice(signature);
+    put32(&mut page, 0x04, 2);
+    for n in 0..PAGE / CLUSTER {
+        put64(&mut page, 0x20 + 8 * n, cluster + n as u64);
+    }
+    put64(&mut page, 0x40, table);
+    assert!(0x50 + content.len() <= PAGE, "ReFS fixture page overflow");
+    page[0x50..0x50 + content.len()].copy_from_slice(content);
+    page
+}
+
+/// Block reference of the page at `cluster`: its clusters, then a checksum left empty.
+fn reference(cluster: u64) -> Vec<u8> {
+    let mut reference = vec![0u8; 0x30];
+    for n in 0..PAGE / CLUSTER {
+        put64(&mut reference, 8 * n, cluster + n as u64);
+    }
+    reference
+}
+
+struct Writer {
+    /// Clusters from the first virtual cluster of the first container.
+    data: Vec<u8>,
+    /// Table root of each directory object.
+    objects: Vec<(u64, u64)>,
+    next_object: u64,
+    next_file: u64,
+}
+
+impl Writer {
+    /// First virtual cluster of new clusters holding `bytes`.
+    fn allocate(&mut self, bytes: &[u8]) -> u64 {
+        let cluster = FIRST_CONTAINER * CONTAINER_CLUSTERS + (self.data.len() / CLUSTER) as u64;
+        let start = self.data.len();
+        self.data.resize(
+            start + bytes.len().next_multiple_of(CLUSTER).max(CLUSTER),
+            0,
+        );
+        self.data[start..start + bytes.len()].copy_from_slice(bytes);
+        cluster
+    }
+
+    fn tree_page(&mut self, table: u64, content: &[u8]) -> u64 {
+        let cluster = FIRST_CONTAINER * CONTAINER_CLUSTERS + (self.data.len() / CLUSTER) as u64;
+        self.allocate(&page(b"MSB+", cluster, table, content))
+    }
+
+    /// Run table of `content`: one run per stretch of non-zero clusters.
+    fn run_table(&mut self, content: &[u8]) -> Vec<u8> {
+        let mut runs: Vec<(u64, u64, u64)> = Vec::new();
+        for (vcn, chunk) in content.chunks(CLUSTER).enumerate() {
+            if chunk.iter().all(|b| *b == 0) {
+                continue;
+            }
+            let cluster = self.allocate(chunk);
+            match runs.last_mut() {
+                Some((first, start, count))
+                    if *first + *count == vcn as u64 && *start + *count == cluster =>
+                {
+                    *count += 1
+                }
+                _ => runs.push((vcn as u64, cluster, 1)),
+            }
+        }
+        let rows: Vec<_> = runs
+            .iter()
+            .map(|(vcn, cluster, count)| {
+                let mut value = vec![0u8; 16];
+                put64(&mut value, 0, *cluster);
+                put64(&mut value, 8, *count);
+                (vcn.to_le_bytes().to_vec(), value, 0)
+            })
+            .collect();
+        let mut root = vec![0u8; 0x18];
+        put64(&mut root, 0x08, content.len() as u64);
+        put64(
+            &mut root,
+            0x10,
+            (runs.iter().map(|r| r.2).sum::<u64>()) * CLUSTER as u64,
+        );
+        node(&root, &rows, false)
+    }
+
+    /// File table of `content` and its named `streams`.
+    fn file_table(&mut self, content: &[u8], streams: &[(&str, &[u8])]) -> Vec<u8> {
+        self.next_file += 1;
+        let mut rows = Vec::new();
+        let mut allocated = 0;
+        for (name, data) in [("", content)].into_iter().chain(streams.iter().copied()) {
+            let mut key = vec![0u8; 8];
+            put32(&mut key, 0, 0x80);
+            key.extend(utf16(name));
+            let table = self.run_table(data);
+            if name.is_empty() {
+                allocated = u64::from_le_bytes(table[0x10..0x18].try_into().unwrap());
+            }
+            rows.push((key, table, 0));
+        }
+        let mut root = vec![0u8; 0x78];
+        for at in [0x28, 0x30, 0x38, 0x40] {
+            put64(&mut root, at, TIMESTAMP);
+        }
+        put32(&mut root, 0x48, 0x20);
+        put64(&mut root, 0x58, self.next_file);
+        put64(&mut root, 0x68, content.len() as u64);
+        put64(&mut root, 0x70, allocated);
+        node(&root, &rows, false)
+    }
+
+    /// Write directory `path` as object `object`, its children first.
+    fn directory(&mut self, path: &str, object: u64, tree: &BTreeMap<String, Vec<&Entry>>) {
+        let children = tree.get(path).cloned().unwrap_or_default();
+        let mut rows = Vec::new();
+        for entry in &children {
+            let name = entry.path.rsplit('/').next().unwrap();
+            let streams: Vec<(&str, &[u8])> = children
+                .iter()
+                .filter(|e| e.path == entry.path)
+                .filter_map(|e| match &e.node {
+                    Node::Stream { name, data } => Some((*name, data.as_slice())),
+                    _ => None,
+                })
+                .collect();
+            let (row_type, value, flags) = match &entry.node {
+                Node::Dir => {
+                    let child = self.next_object;
+                    self.next_object += 1;
+                    self.directory(entry.path, child, tree);
+                    let mut link = vec![0u8; 0x48];
+                    put64(&mut link, 0x08, child);
+                    for at in [0x18, 0x20, 0x28, 0x30] {
+                        put64(&mut link, at, TIMESTAMP);
+                    }
+                    put32(&mut link, 0x40, 0x10);
+                    (0x0002_0030u32, link, 0)
+                }
+                Node::File(data) => (0x0001_0030, self.file_table(data, &streams), 0),
+                Node::Sparse { size, offset, data } => {
+                    let content = super::sparse_content(*size, *offset, data);
+                    (0x0001_0030, self.file_table(&content, &[]), 0)
+                }
+                Node::Deleted(data) => (0x0001_0030, self.file_table(data, &[]), 0x04),
+                Node::Stream { .. } => continue,
+                other => panic!("ReFS fixtures cannot hold {:?}", other),
+            };
+            let mut key = row_type.to_le_bytes().to_vec();
+            key.extend(utf16(name));
+            rows.push((key, value, flags));
+        }
+        rows.sort_by(|a, b| a.0.cmp(&b.0));
+
+        let table = if object == ROOT_DIRECTORY && rows.len() > 1 {
+            let right = rows.split_off(rows.len() / 2);
+            let mut children = Vec::new();
+            for leaf in [rows, right] {
+                let first = leaf[0].0.clone();
+                let cluster = self.tree_page(object, &node(&[], &leaf, false));
+                children.push((first, reference(cluster), 0));
+            }
+            self.tree_page(object, &node(&[], &children, true))
+        } else {
+            self.tree_page(object, &node(&[], &rows, false))
+        };
+        self.objects.push((object, table));
+    }
+}
+
+/// Image of the entries.
+pub fn build(entries: &[Entry]) -> Vec<u8> {
+    let mut tree: BTreeMap<String, Vec<&Entry>> = BTreeMap::new();
+    for entry in entries {
+        let parent = entry.path.rsplit_once('/').map_or("", |(parent, _)| parent);
+        tree.entry(parent.to_string()).or_default().push(entry);
+    }
+    let mut writer = Writer {
+        data: Vec::new(),
+        objects: Vec::new(),
+        next_object: 0x701,
+        next_file: 0,
+    };
+    writer.directory("", ROOT_DIRECTORY, &tree);
+
+    let mut objects: Vec<_> = writer
+        .objects
+        .iter()
+        .map(|(object, table)| {
+            let mut key = vec![0u8; 16];
+            put64(&mut key, 8, *object);
+            let mut value = vec![0u8; 0x20];
+            value.extend(reference(*table));
+            (key, value, 0)
+        })
+        .collect();
+    objects.sort_by(|a, b| a.0.cmp(&b.0));
+    let object_table = writer.tree_page(2, &node(&[], &objects, false));
+
+    let data_clusters = (writer.data.len() / CLUSTER) as u64;
+    let containers = data_clusters.div_ceil(CONTAINER_CLUSTERS);
+    let clusters = DATA_START + data_clusters + 16;
+    let mut image = vec![0u8; clusters as usize * CLUSTER];
+    let at = DATA_START as usize * CLUSTER;
+    image[at..at + writer.data.len()].copy_from_slice(&writer.data);
+
+    let container_rows: Vec<_> = (0..containers)
+        .map(|n| {
+            let mut value = vec![0u8; 0xa8];
+            put64(&mut value, 0xa0, DATA_START + n * CONTAINER_CLUSTERS);
+            ((FIRST_CONTAINER + n).to_le_bytes().to_vec(), value, 0)
+        })
+        .collect();
+    let container_table = page(
+        b"MSB+",
+        CONTAINER_TABLE,
+        0xb,
+        &node(&[], &container_rows, false),
+    );
+    let at = CONTAINER_TABLE as usize * CLUSTER;
+    image[at..at + PAGE].copy_from_slice(&container_table);
+
+    // Checkpoints: 13 table references, the object and container tables filled in; the
+    // second one is older.
+    for (n, cluster) in CHECKPOINTS.iter().enumerate() {
+        let mut content = vec![0u8; 0x78 + 13 * 4 + 13 * 0x30];
+        put16(&mut content, 0x04, 3);
+        put16(&mut content, 0x06, 4);
+        put64(&mut content, 0x10, 5 - n as u64);
+        put32(&mut content, 0x40, 13);
+        for table in 0..13 {
+            let at = 0x94 + 13 * 4 + table * 0x30;
+            put32(&mut content, 0x44 + 4 * table, at as u32);
+            let reference = match table {
+                0 => reference(object_table),
+                7 => reference(CONTAINER_TABLE),
+                _ => continue,
+            };
+            content[at - 0x50..at - 0x50 + 0x30].copy_from_slice(&reference);
+        }
+        let checkpoint = page(b"CHKP", *cluster, 0, &content);
+        let at = *cluster as usize * CLUSTER;
+        image[at..at + PAGE].copy_from_slice(&checkpoint);
+    }
+
+    let mut superblock = vec![0u8; 0x40];
+    put32(&mut superblock, 0x20, 0x80);
+    put32(&mut superblock, 0x24, 2);
+    put64(&mut superblock, 0x30, CHECKPOINTS[0]);
+    put64(&mut superblock, 0x38, CHECKPOINTS[1]);
+    let superblock = page(b"SUPB", SUPERBLOCK, 0, &superblock);
+    let at = SUPERBLOCK as usize * CLUSTER;
+    image[at..at + PAGE].copy_from_slice(&superblock);
+
+    let boot = &mut image[..512];
+    boot[3..11].copy_from_slice(b"ReFS\0\0\0\0");
+    boot[16..20].copy_from_slice(b"FSRS");
+    put64(boot, 0x18, clusters * (CLUSTER as u64 / 512));
+    put32(boot, 0x20, 512);
+    put32(boot, 0x24, (CLUSTER / 512) as u32);
+    boot[0x28] = 3;
+    boot[0x29] = 4;
+    put64(boot, 0x38, 0x0123_4567_89ab_cdef);
+    put64(boot, 0x40, CONTAINER_CLUSTERS * CLUSTER as u64);
+    image
+}
@@ -0,0 +1,315 @@
+//! Minimal SquashFS 4.0 writer: gzip-compressed data and metadata blocks of 4 KiB and
+//! 8 KiB, the tail of each file packed into a shared fragment block, all-zero blocks left
+//! as holes. Inodes are basic ones owned by root; there are no extended attributes nor
+//! export table.
+use super::{Entry, Node};
+use flate2::Compression;
+use flate2::write::ZlibEncoder;
+use std::collections::BTreeMap;
+use std::io::Write;
+
+const BLOCK_LOG: u16 = 12;
+const BLOCK_SIZE: usize = 1 << BLOCK_LOG;
+const METADATA_SIZE: usize = 8192;
+const UNCOMPRESSED: u32 = 1 << 24;
+const NONE: u64 = u64::MAX;
+/// 2024-01-02 03:04:05 UTC.
+const TIMESTAMP: u32 = 1_704_164_645;
+
+/// Compressed `data`, or `data` itself and the uncompressed bit when it does not shrink.
+fn compress(data: &[u8]) -> (Vec<u8>, bool) {
+    let mut encoder = ZlibEncoder::new(Vec::new(), Compression::best());
+    encoder.write_all(data).unwrap();
+    let compressed = encoder.finish().unwrap();
+    match compressed.len() < data.len() {
+        true => (compressed, false),
+        false => (data.to_vec(), true),
+    }
+}
+
+/// A table of metadata blocks, written as it fills.
+#[derive(Default)]
+struct Metadata {
+    blocks: Vec<u8>,
+    pending: Vec<u8>,
+}
+
+impl Metadata {
+    /// Reference of the next byte: its block from the start of the table, shifted left
+    /// 16 bits, plus its offset in the block.
+    fn reference(&self) -> u64 {
+        (self.blocks.len() as u64) << 16 | self.pending.len() as u64
+    }
+
+    fn write(&mut self, mut bytes: &[u8]) {
+        while !bytes.is_empty() {
+            let take = (METADATA_SIZE - self.pending.len()).min(bytes.len());
+            self.pending.extend_from_slice(&bytes[..take]);
+            bytes = &bytes[take..];
+            if self.pending.len() == METADATA_SIZE {
+                self.flush();
+            }
+        }
+    }
+
+    fn flush(&mut self) {
+        if self.pending.is_empty() {
+            return;
+        }
+        let (block, raw) = compress(&std::mem::take(&mut self.pending));
+        let header = block.len() as u16 | if raw { 0x8000 } else { 0 };
+        self.blocks.extend_from_slice(&header.to_le_bytes());
+        self.blocks.extend_from_slice(&block);
+    }
+
+    fn finish(mut self) -> Vec<u8> {
+        self.flush();
+        self.blocks
+    }
+}
+
+struct Writer {
+    image: Vec<u8>,
+    inodes: Metadata,
+    directories: Metadata,
+    fragments: Vec<(u64, u32)>,
+    fragment: Vec<u8>,
+    inode_count: u32,
+}
+
+/// Common inode header: type, mode, uid and gid indexes, mtime and inode number.
+fn inode_header(inode_type: u16, mode: u16, number: u32) -> Vec<u8> {
+    let mut header = Vec::with_capacity(16);
+    header.extend_from_slice(&inode_type.to_le_bytes());
+    header.extend_from_slice(&mode.to_le_bytes());
+    header.extend_from_slice(&[0; 4]);
+    header.extend_from_slice(&TIMESTAMP.to_le_bytes());
+    header.extend_from_slice(&number.to_le_bytes());
+    header
+}
+
+impl Writer {
+    fn flush_fragment(&mut self) {
+        if self.fragment.is_empty() {
+            return;
+        }
+        let (block, raw) = compress(&std::mem::take(&mut self.fragment));
+        let size = block.len() as u32 | if raw { UNCOMPRESSED } else { 0 };
+        self.fragments.push((self.image.len() as u64, size));
+        self.image.extend_from_slice(&block);
+    }
+
+    /// Write the blocks of a file and its inode; returns its reference and inode number.
+    fn file(&mut self, content: &[u8]) -> (u64, u32) {
+        let blocks_start = self.image.len() as u32;
+        let tail = content.len() % BLOCK_SIZE;
+        let mut sizes = Vec::new();
+        for block in content[..content.len() - tail].chunks(BLOCK_SIZE) {
+            if block.iter().all(|b| *b == 0) {
+                sizes.push(0);
+                continue;
+            }
+            let (data, raw) = compress(block);
+            sizes.push(data.len() as u32 | if raw { UNCOMPRESSED } else { 0 });
+            self.image.extend_from_slice(&data);
+        }
+        let (fragment, offset) = match tail {
+            0 => (u32::MAX, 0),
+            _ => {
+                if self.fragment.len() + tail > BLOCK_SIZE {
+                    self.flush_fragment();
+                }
+                let offset = self.fragment.len() as u32;
+                self.fragment
+                    .extend_from_slice(&content[content.len() - tail..]);
+                (self.fragments.len() as u32, offset)
+            }
+        };
+        self.inode_count += 1;
+        let mut inode = inode_header(2, 0o644, self.inode_count);
+        for value in [blocks_start, fragment, offset, content.len() as u32] {
+            inode.extend_from_slice(&value.to_le_bytes());
+        }
+        for size in sizes {
+            inode.extend_from_slice(&size.to_le_bytes());
+        }
+        let reference = self.inodes.reference();
+        self.inodes.write(&inode);
+        (reference, self.inode_count)
+    }
+
+    fn symlink(&mut self, target: &str) -> (u64, u32) {
+        self.inode_count += 1;
+        let mut inode = inode_header(3, 0o777, self.inode_count);
+        inode.extend_from_slice(&1u32.to_le_bytes());
+        inode.extend_from_slice(&(target.len() as u32).to_le_bytes());
+        inode.extend_from_slice(target.as_bytes());
+        let reference = self.inodes.reference();
+        self.inodes.write(&inode);
+        (reference, self.inode_count)
+    }
+
+    /// Write the children of directory `path`, then its listing and inode.
+    fn directory(
+        &mut self,
+        path: &str,
+        tree: &BTreeMap<String, Vec<&Entry>>,
+        parent: u32,
+    ) -> (u64, u32) {
+        // The children are numbered before the directory, which takes the next number.
+        let mut children = Vec::new();
+        let mut subdirectories = 0;
+        for entry in tree.get(path).into_iter().flatten() {
+            let (reference, number, kind) = match &entry.node {
+                Node::Dir => {
+                    subdirectories += 1;
+                    let (reference, number) = self.directory(entry.path, tree, 0);
+                    (reference, number, 1u16)
+                }
+                Node::File(data) => {
+                    let (reference, number) = self.file(data);
+                    (reference, number, 2)
+                }
+                Node::Sparse { size, offset, data } => {
+                    let (reference, number) =
+                        self.file(&super::sparse_content(*size, *offset, data));
+                    (reference, number, 2)
+                }
+                Node::Symlink(target) => {
+                    let (reference, number) = self.symlink(target);
+                    (reference, number, 3)
+                }
+                other => panic!("SquashFS fixtures cannot hold {:?}", other),
+            };
+            let name = entry.path.rsplit('/').next().unwrap();
+            children.push((name, reference, number, kind));
+        }
+        children.sort_by(|a, b| a.0.cmp(b.0));
+
+        // One header per run of entries whose inodes are in the same metadata block.
+        let listing = self.directories.reference();
+        let mut bytes = Vec::new();
+        let mut run = 0;
+        while run < children.len() {
+            let block = children[run].1 >> 16;
+            let base = children[run].2;
+            let end = children[run..]
+                .iter()
+                .position(|c| c.1 >> 16 != block)
+                .map_or(children.len(), |p| run + p)
+                .min(run + 256);
+            bytes.extend_from_slice(&((end - run - 1) as u32).to_le_bytes());
+            bytes.extend_from_slice(&(block as u32).to_le_bytes());
+            bytes.extend_from_slice(&base.to_le_bytes());
+            for (name, reference, number, kind) in &children[run..end] {
+                bytes.extend_from_slice(&(*reference as u16).to_le_bytes());
+                bytes.extend_from_slice(&((*number as i32 - base as i32) as i16).to_le_bytes());
+                bytes.extend_from_slice(&kind.to_le_bytes());
+                bytes.extend_from_slice(&(name.len() as u16 - 1).to_le_bytes());
+                bytes.extend_from_slice(name.as_bytes());
+            }
+            run = end;
+        }
+        self.directories.write(&bytes);
+
+        self.inode_count += 1;
+        let number = self.inode_count;
+        let mut inode = inode_header(1, 0o755, number);
+        inode.extend_from_slice(&((listing >> 16) as u32).to_le_bytes());
+        inode.extend_from_slice(&(2 + subdirectories as u32).to_le_bytes());
+        inode.extend_from_slice(&(bytes.len() as u16 + 3).to_le_bytes());
+        inode.extend_from_slice(&(listing as u16).to_le_bytes());
+        let parent = if parent == 0 { number + 1 } else { parent };
+        inode.extend_from_slice(&parent.to_le_bytes());
+        let reference = self.inodes.reference();
+        self.inodes.write(&inode);
+        (reference, number)
+    }
+
+    /// Write `entries` (of `size` bytes each) as a lookup table: metadata blocks, then
+    /// the array of their positions. Returns the position of the array.
+    fn table(&mut self, entries: &[u8], size: usize) -> u64 {
+        let mut pointers = Vec::new();
+        for chunk in entries.chunks(METADATA_SIZE / size * size) {
+            pointers.push(self.image.len() as u64);
+            let mut block = Metadata::default();
+            block.write(chunk);
+            self.image.extend_from_slice(&block.finish());
+        }
+        let position = self.image.len() as u64;
+        for pointer in pointers {
+            self.image.extend_from_slice(&pointer.to_le_bytes());
+        }
+        position
+    }
+}
+
+/// Image holding the entries, deleted ones left out: SquashFS is written once.
+pub fn build(entries: &[Entry]) -> Vec<u8> {
+    let mut tree: BTreeMap<String, Vec<&Entry>> = BTreeMap::new();
+    for entry in entries {
+        if let Node::Deleted(_) = entry.node {
+            continue;
+        }
+        let parent = entry.path.rsplit_once('/').map_or("", |(parent, _)| parent);
+        tree.entry(parent.to_string()).or_default().push(entry);
+    }
+    let mut writer = Writer {
+        image: vec![0u8; 96],
+        inodes: Metadata::default(),
+        directories: Metadata::default(),
+        fragments: Vec::new(),
+        fragment: Vec::new(),
+        inode_count: 0,
+    };
+    let (root, _) = writer.directory("", &tree, 0);
+    writer.flush_fragment();
+
+    let inode_table = writer.image.len() as u64;
+    let inodes = std::mem::take(&mut writer.inodes).finish();
+    writer.image.extend_from_slice(&inodes);
+    let directory_table = writer.image.len() as u64;
+    let directories = std::mem::take(&mut writer.directories).finish();
+    writer.image.extend_from_slice(&directories);
+    let mut fragments = Vec::new();
+    for (start, size) in &writer.fragments {
+        fragments.extend_from_slice(&start.to_le_bytes());
+        fragments.extend_from_slice(&size.to_le_bytes());
+        fragments.extend_from_slice(&[0; 4]);
+    }
+    let fragment_table = writer.table(&fragments, 16);
+    let id_table = writer.table(&0u32.to_le_bytes(), 4);
+    let bytes_used = writer.image.len() as u64;
+
+    let mut superblock = Vec::with_capacity(96);
+    superblock.extend_from_slice(b"hsqs");
+    for value in [
+        writer.inode_count,
+        TIMESTAMP,
+        BLOCK_SIZE as u32,
+        writer.fragments.len() as u32,
+    ] {
+        superblock.extend_from_slice(&value.to_le_bytes());
+    }
+    // gzip, the block size log, no flags, one id and version 4.0.
+    for value in [1u16, BLOCK_LOG, 0, 1, 4, 0] {
+        superblock.extend_from_slice(&value.to_le_bytes());
+    }
+    for value in [
+        root,
+        bytes_used,
+        id_table,
+        NONE,
+        inode_table,
+        directory_table,
+        fragment_table,
+        NONE,
+    ] {
+        superblock.extend_from_slice(&value.to_le_bytes());
+    }
+    writer.image[..96].copy_from_slice(&superblock);
+    writer
+        .image
+        .resize(bytes_used.next_multiple_of(4096) as usize, 0);
+    writer.image
+}
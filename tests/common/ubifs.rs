@@ -0,0 +1,565 @@
+//! Minimal UBI image holding a static `kernel` volume and a dynamic `rootfs` volume with
+//! UBIFS: 64 KiB erase blocks whose LEBs are 60 KiB, written out of order, with a stale
+//! copy of the first master LEB at a lower sequence number. In the UBIFS volume, the
+//! committed nodes are in LEB 8 and their index (fanout 8) in LEB 10; the journal bud in
+//! LEB 9, referenced by the log, deletes the deleted files and creates `empty.txt`, as if
+//! done since the last commit. Data blocks are compressed with LZO (literal runs, then a
+//! back reference for periodic content) but for the sparse file, deflated. `hello.txt`
+//! carries a `user.comment` extended attribute.
+use super::{Entry, Node};
+use flate2::Compression;
+use flate2::write::DeflateEncoder;
+use std::collections::BTreeMap;
+use std::io::Write;
+
+const PEB_SIZE: usize = 65536;
+const VID_OFFSET: usize = 2048;
+const DATA_OFFSET: usize = 4096;
+const LEB_SIZE: usize = PEB_SIZE - DATA_OFFSET;
+const MIN_IO: usize = 2048;
+const IMAGE_SEQ: u32 = 0x1234_5678;
+const LAYOUT_VOLUME: u32 = 0x7fff_efff;
+const KERNEL_VOLUME: u32 = 0;
+const ROOTFS_VOLUME: u32 = 1;
+const NODE_MAGIC: u32 = 0x0610_1831;
+const MAIN_LEB: u32 = 8;
+const BUD_LEB: u32 = 9;
+const INDEX_LEB: u32 = 10;
+const LOG_LEB: u32 = 3;
+const FANOUT: usize = 8;
+const BLOCK_SIZE: usize = 4096;
+const ROOT_INO: u32 = 1;
+const FIRST_INO: u32 = 64;
+const JOURNAL_ONLY: &str = "empty.txt";
+/// 2024-01-02 03:04:05 UTC.
+const TIMESTAMP: u64 = 1_704_164_645;
+
+fn put16(buffer: &mut [u8], at: usize, value: u16) {
+    buffer[at..at + 2].copy_from_slice(&value.to_le_bytes());
+}
+
+fn put32(buffer: &mut [u8], at: usize, value: u32) {
+    buffer[at..at + 4].copy_from_slice(&value.to_le_bytes());
+}
+
+fn put64(buffer: &mut [u8], at: usize, value: u64) {
+    buffer[at..at + 8].copy_from_slice(&value.to_le_bytes());
+}
+
+fn put32be(buffer: &mut [u8], at: usize, value: u32) {
+    buffer[at..at + 4].copy_from_slice(&value.to_be_bytes());
+}
+
+fn crc32(bytes: &[u8]) -> u32 {
+    let mut crc = !0u32;
+    for byte in bytes {
+        crc ^= *byte as u32;
+        for _ in 0..8 {
+            crc = (crc >> 1) ^ (0xedb8_8320 & (crc & 1).wrapping_neg());
+        }
+    }
+    crc
+}
+
+/// Name hash of the `r5` key format.
+fn r5(name: &[u8]) -> u32 {
+    let mut a = 0u32;
+    for byte in name {
+        let c = *byte as i8 as i32;
+        a = a.wrapping_add((c << 4) as u32);
+        a = a.wrapping_add((c >> 4) as u32);
+        a = a.wrapping_mul(11);
+    }
+    a &= 0x1fff_ffff;
+    if a <= 2 { a + 3 } else { a }
+}
+
+/// Key of `inum` of type `kind` (0 inode, 1 data, 2 entry, 3 extended attribute).
+fn key(inum: u32, kind: u32, value: u32) -> u64 {
+    (inum as u64) << 32 | (kind << 29 | value) as u64
+}
+
+fn put_key(buffer: &mut [u8], at: usize, key: u64) {
+    put32(buffer, at, (key >> 32) as u32);
+    put32(buffer, at + 4, key as u32);
+}
+
+/// LZO1X stream of `data`, whose bytes repeat every `period`: a literal run of the first
+/// period, then one back reference copying it over the rest.
+fn lzo(data: &[u8], period: usize) -> Vec<u8> {
+    // References copy at least 3 bytes.
+    let literals = if data.len() < period + 3 {
+        data.len()
+    } else {
+        period
+    };
+    let mut out = Vec::new();
+    if literals <= 238 {
+        out.push(17 + literals as u8);
+    } else {
+        out.push(0);
+        extend_length(&mut out, literals - 18);
+    }
+    out.extend_from_slice(&data[..literals]);
+    let length = data.len() - literals;
+    if length > 0 {
+        if length <= 33 {
+            out.push(32 | (length - 2) as u8);
+        } else {
+            out.push(32);
+            extend_length(&mut out, length - 33);
+        }
+        out.extend_from_slice(&(((period - 1) << 2) as u16).to_le_bytes());
+    }
+    out.extend_from_slice(&[0x11, 0, 0]);
+    out
+}
+
+/// Length past its instruction field: zero bytes worth 255 each, then the rest.
+fn extend_length(out: &mut Vec<u8>, length: usize) {
+    let zeros = (length - 1) / 255;
+    out.extend(std::iter::repeat_n(0, zeros));
+    out.push((length - 255 * zeros) as u8);
+}
+
+struct Inode {
+    inum: u32,
+    mode: u32,
+    size: u64,
+    nlink: u32,
+    flags: u32,
+    inline: Vec<u8>,
+    xattrs: u32,
+}
+
+struct Writer {
+    sqnum: u64,
+    lebs: BTreeMap<u32, Vec<u8>>,
+    /// Key and location of the committed leaf nodes.
+    leaves: Vec<(u64, u32, u32, u32)>,
+    next_inum: u32,
+    inums: BTreeMap<&'static str, u32>,
+}
+
+impl Writer {
+    /// Node of `kind` with `body` after the common header.
+    fn node(&mut self, kind: u8, body: &[u8]) -> Vec<u8> {
+        self.sqnum += 1;
+        let mut node = vec![0u8; 24];
+        node.extend_from_slice(body);
+        put32(&mut node, 0, NODE_MAGIC);
+        put64(&mut node, 8, self.sqnum);
+        let length = node.len() as u32;
+        put32(&mut node, 16, length);
+        node[20] = kind;
+        let crc = crc32(&node[8..]);
+        put32(&mut node, 4, crc);
+        node
+    }
+
+    /// Append `node` to LEB `lnum`, 8-byte aligned; returns its offset.
+    fn append(&mut self, lnum: u32, node: &[u8]) -> u32 {
+        let leb = self.lebs.entry(lnum).or_default();
+        leb.resize(leb.len().next_multiple_of(8), 0);
+        let offs = leb.len() as u32;
+        leb.extend_from_slice(node);
+        assert!(leb.len() <= LEB_SIZE);
+        offs
+    }
+
+    /// Pad LEB `lnum` to the next minimal I/O unit with a padding node, as the write
+    /// buffer does.
+    fn pad(&mut self, lnum: u32) {
+        let used = self.lebs[&lnum].len().next_multiple_of(8);
+        let mut body = vec![0u8; 4];
+        put32(
+            &mut body,
+            0,
+            (used.next_multiple_of(MIN_IO) - used - 28) as u32,
+        );
+        let node = self.node(5, &body);
+        self.append(lnum, &node);
+        let leb = self.lebs.get_mut(&lnum).unwrap();
+        leb.resize(used.next_multiple_of(MIN_IO), 0);
+    }
+
+    /// Committed leaf node, indexed under `key`.
+    fn leaf(&mut self, key: u64, kind: u8, body: &[u8]) {
+        let node = self.node(kind, body);
+        let offs = self.append(MAIN_LEB, &node);
+        self.leaves.push((key, MAIN_LEB, offs, node.len() as u32));
+    }
+
+    fn inode_body(inode: &Inode) -> Vec<u8> {
+        let mut body = vec![0u8; 160 - 24 + inode.inline.len()];
+        put_key(&mut body, 0, key(inode.inum, 0, 0));
+        put64(&mut body, 16, inode.inum as u64);
+        put64(&mut body, 24, inode.size);
+        for at in [32, 40, 48] {
+            put64(&mut body, at, TIMESTAMP);
+        }
+        put32(&mut body, 68, inode.nlink);
+        put32(&mut body, 80, inode.mode);
+        put32(&mut body, 84, inode.flags);
+        put32(&mut body, 88, inode.inline.len() as u32);
+        put32(&mut body, 92, inode.xattrs);
+        body[136..].copy_from_slice(&inode.inline);
+        body
+    }
+
+    fn entry_body(key: u64, name: &[u8], inum: u32, kind: u8) -> Vec<u8> {
+        let mut body = vec![0u8; 56 - 24 + name.len() + 1];
+        put_key(&mut body, 0, key);
+        put64(&mut body, 16, inum as u64);
+        body[25] = kind;
+        put16(&mut body, 26, name.len() as u16);
+        body[32..32 + name.len()].copy_from_slice(name);
+        body
+    }
+
+    fn data_body(inum: u32, block: usize, data: &[u8], compressor: u16) -> Vec<u8> {
+        let compressed = match compressor {
+            1 => lzo(data, 251),
+            2 => {
+                let mut encoder = DeflateEncoder::new(Vec::new(), Compression::default());
+                encoder.write_all(data).unwrap();
+                encoder.finish().unwrap()
+            }
+            _ => data.to_vec(),
+        };
+        let mut body = vec![0u8; 48 - 24];
+        put_key(&mut body, 0, key(inum, 1, block as u32));
+        put32(&mut body, 16, data.len() as u32);
+        put16(&mut body, 20, compressor);
+        body.extend_from_slice(&compressed);
+        body
+    }
+
+    /// Nodes of the file `data` of `size` bytes: its blocks, all-zero ones left out.
+    fn file(&mut self, inum: u32, data: &[u8], compressor: u16) {
+        for (block, chunk) in data.chunks(BLOCK_SIZE).enumerate() {
+            if chunk.iter().all(|b| *b == 0) {
+                continue;
+            }
+            let end = chunk.len() - chunk.iter().rev().take_while(|b| **b == 0).count();
+            let body = Self::data_body(inum, block, &chunk[..end], compressor);
+            self.leaf(key(inum, 1, block as u32), 1, &body);
+        }
+    }
+
+    /// Committed nodes of the directory `path` (inode `inum`) and everything under it.
+    fn directory(&mut self, path: &str, inum: u32, tree: &BTreeMap<String, Vec<&Entry>>) {
+        let mut size = 0;
+        let mut subdirectories = 0;
+        for entry in tree.get(path).into_iter().flatten() {
+            let name = entry.path.rsplit('/').next().unwrap().as_bytes();
+            if entry.path == JOURNAL_ONLY {
+                continue;
+            }
+            let child = self.next_inum;
+            self.next_inum += 1;
+            self.inums.insert(entry.path, child);
+            let (mode, kind) = match &entry.node {
+                Node::Dir => (0o040755, 1),
+                Node::Symlink(_) => (0o120777, 2),
+                _ => (0o100644, 0),
+            };
+            let mut inode = Inode {
+                inum: child,
+                mode,
+                size: 0,
+                nlink: 1,
+                flags: 0,
+                inline: Vec::new(),
+                xattrs: 0,
+            };
+            match &entry.node {
+                Node::Dir => {
+                    subdirectories += 1;
+                    self.directory(entry.path, child, tree);
+                }
+                Node::Symlink(target) => {
+                    inode.inline = target.as_bytes().to_vec();
+                    inode.size = target.len() as u64;
+                }
+                Node::File(data) | Node::Deleted(data) => {
+                    inode.size = data.len() as u64;
+                    self.file(child, data, 1);
+                }
+                Node::Sparse { size, offset, data } => {
+                    let mut content = vec![0u8; *size as usize];
+                    content[*offset as usize..*offset as usize + data.len()].copy_from_slice(data);
+                    inode.size = *size;
+                    self.file(child, &content, 2);
+                }
+                Node::Stream { .. } => unreachable!(),
+            }
+            if entry.path == "hello.txt" {
+                self.xattr(&mut inode, b"user.comment", b"fixture");
+            }
+            if !matches!(entry.node, Node::Dir) {
+                let body = Self::inode_body(&inode);
+                self.leaf(key(child, 0, 0), 0, &body);
+            }
+            let dent = key(inum, 2, r5(name));
+            let body = Self::entry_body(dent, name, child, kind);
+            self.leaf(dent, 2, &body);
+            size += (56 + name.len() + 1).next_multiple_of(8) as u64;
+        }
+        let inode = Inode {
+            inum,
+            mode: 0o040755,
+            size,
+            nlink: 2 + subdirectories,
+            flags: 0,
+            inline: Vec::new(),
+            xattrs: 0,
+        };
+        let body = Self::inode_body(&inode);
+        self.leaf(key(inum, 0, 0), 0, &body);
+    }
+
+    /// Extended attribute entry of `inode` and the inode holding its value.
+    fn xattr(&mut self, inode: &mut Inode, name: &[u8], value: &[u8]) {
+        let inum = self.next_inum;
+        self.next_inum += 1;
+        let holder = Inode {
+            inum,
+            mode: 0o100644,
+            size: value.len() as u64,
+            nlink: 1,
+            flags: 0x20,
+            inline: value.to_vec(),
+            xattrs: 0,
+        };
+        let body = Self::inode_body(&holder);
+        self.leaf(key(inum, 0, 0), 0, &body);
+        let xent = key(inode.inum, 3, r5(name));
+        let body = Self::entry_body(xent, name, inum, 0);
+        self.leaf(xent, 3, &body);
+        inode.xattrs += 1;
+        inode.flags |= 0x20;
+    }
+
+    /// Index nodes over the leaves, level by level; returns the root location.
+    fn index(&mut self) -> (u32, u32, u32) {
+        self.leaves.sort();
+        let mut level: Vec<(u64, u32, u32, u32)> = self.leaves.clone();
+        let mut height = 0u16;
+        loop {
+            let mut above = Vec::new();
+            for branches in level.chunks(FANOUT) {
+                let mut body = vec![0u8; 4];
+                put16(&mut body, 0, branches.len() as u16);
+                put16(&mut body, 2, height);
+                for (k, lnum, offs, len) in branches {
+                    let mut branch = [0u8; 20];
+                    put32(&mut branch, 0, *lnum);
+                    put32(&mut branch, 4, *offs);
+                    put32(&mut branch, 8, *len);
+                    put_key(&mut branch, 12, *k);
+                    body.extend_from_slice(&branch);
+                }
+                let node = self.node(9, &body);
+                let offs = self.append(INDEX_LEB, &node);
+                above.push((branches[0].0, INDEX_LEB, offs, node.len() as u32));
+            }
+            if above.len() == 1 {
+                let (_, lnum, offs, len) = above[0];
+                return (lnum, offs, len);
+            }
+            level = above;
+            height += 1;
+        }
+    }
+
+    fn master(&mut self, root: (u32, u32, u32), highest_inum: u32) -> Vec<u8> {
+        let mut body = vec![0u8; 512 - 24];
+        put64(&mut body, 0, highest_inum as u64);
+        put64(&mut body, 8, 1);
+        put32(&mut body, 16, 1);
+        put32(&mut body, 20, LOG_LEB);
+        put32(&mut body, 24, root.0);
+        put32(&mut body, 28, root.1);
+        put32(&mut body, 32, root.2);
+        put64(&mut body, 48, self.lebs[&INDEX_LEB].len() as u64);
+        self.node(7, &body)
+    }
+}
+
+/// UBIFS volume of `entries`, by LEB, and a master LEB for the stale erase block.
+fn ubifs(entries: &[Entry]) -> (BTreeMap<u32, Vec<u8>>, Vec<u8>) {
+    let mut tree: BTreeMap<String, Vec<&Entry>> = BTreeMap::new();
+    for entry in entries {
+        if matches!(entry.node, Node::Stream { .. }) {
+            continue;
+        }
+        let parent = entry.path.rsplit_once('/').map_or("", |(parent, _)| parent);
+        tree.entry(parent.to_string()).or_default().push(entry);
+    }
+    let mut writer = Writer {
+        sqnum: 0,
+        lebs: BTreeMap::new(),
+        leaves: Vec::new(),
+        next_inum: FIRST_INO,
+        inums: BTreeMap::new(),
+    };
+    writer.directory("", ROOT_INO, &tree);
+    let root = writer.index();
+    let highest_inum = writer.next_inum;
+
+    // Superblock: key hash r5 and simple keys (both 0), 4 KiB with the reserved space.
+    let mut sb = vec![0u8; 4096 - 24];
+    put32(&mut sb, 8, MIN_IO as u32);
+    put32(&mut sb, 12, LEB_SIZE as u32);
+    put32(&mut sb, 16, 16);
+    put32(&mut sb, 20, 64);
+    put32(&mut sb, 32, 2);
+    put32(&mut sb, 36, 2);
+    put32(&mut sb, 40, 1);
+    put32(&mut sb, 44, 1);
+    put32(&mut sb, 48, FANOUT as u32);
+    put32(&mut sb, 56, 4);
+    put16(&mut sb, 60, 1);
+    put32(&mut sb, 80, 1_000_000_000);
+    sb[84..100].copy_from_slice(&[0x42; 16]);
+    let sb = writer.node(6, &sb);
+    writer.append(0, &sb);
+
+    // Master LEBs: an older master node before the current one in the first.
+    let older = writer.master((INDEX_LEB, 0, 0), highest_inum);
+    let current = writer.master(root, highest_inum);
+    writer.append(1, &older);
+    writer.pad(1);
+    writer.append(1, &current);
+    writer.append(2, &current);
+
+    // Log: the commit start, then the reference to the bud.
+    let mut cs = vec![0u8; 8];
+    put64(&mut cs, 0, 1);
+    let cs = writer.node(10, &cs);
+    writer.append(LOG_LEB, &cs);
+    let mut reference = vec![0u8; 64 - 24];
+    put32(&mut reference, 0, BUD_LEB);
+    put32(&mut reference, 8, 1);
+    let reference = writer.node(8, &reference);
+    writer.append(LOG_LEB, &reference);
+
+    // The bud: deletions and the file created since the commit.
+    for entry in tree.get("").into_iter().flatten() {
+        let name = entry.path.as_bytes();
+        let dent = key(ROOT_INO, 2, r5(name));
+        let (inum, size, nlink) = match &entry.node {
+            Node::Deleted(data) => (writer.inums[entry.path], data.len() as u64, 0),
+            _ if entry.path == JOURNAL_ONLY => (highest_inum, 0, 1),
+            _ => continue,
+        };
+        let inode = Inode {
+            inum,
+            mode: 0o100644,
+            size,
+            nlink,
+            flags: 0,
+            inline: Vec::new(),
+            xattrs: 0,
+        };
+        let body = Writer::entry_body(dent, name, if nlink == 0 { 0 } else { inum }, 0);
+        let nodes = [
+            writer.node(0, &Writer::inode_body(&inode)),
+            writer.node(2, &body),
+        ];
+        for node in nodes {
+            writer.append(BUD_LEB, &node);
+        }
+    }
+    writer.pad(BUD_LEB);
+
+    // Newest master node of all, in the erase block UBI has since replaced.
+    let stale = writer.master((INDEX_LEB, 0, 0), highest_inum);
+    (writer.lebs, stale)
+}
+
+/// Erase counter and volume identifier headers of the erase block holding LEB `lnum` of
+/// `volume`, then its data.
+fn erase_block(volume: u32, lnum: u32, sqnum: u64, data: &[u8], static_volume: bool) -> Vec<u8> {
+    let mut peb = vec![0xffu8; PEB_SIZE];
+    let ec = &mut peb[..64];
+    ec.fill(0);
+    ec[..4].copy_from_slice(b"UBI#");
+    ec[4] = 1;
+    ec[15] = 3;
+    put32be(ec, 16, VID_OFFSET as u32);
+    put32be(ec, 20, DATA_OFFSET as u32);
+    put32be(ec, 24, IMAGE_SEQ);
+    let crc = crc32(&ec[..60]);
+    put32be(ec, 60, crc);
+
+    let vid = &mut peb[VID_OFFSET..VID_OFFSET + 64];
+    vid.fill(0);
+    vid[..4].copy_from_slice(b"UBI!");
+    vid[4] = 1;
+    vid[5] = if static_volume { 2 } else { 1 };
+    put32be(vid, 8, volume);
+    put32be(vid, 12, lnum);
+    if static_volume {
+        put32be(vid, 20, data.len() as u32);
+        put32be(vid, 24, 1);
+        put32be(vid, 32, crc32(data) ^ !0);
+    }
+    vid[40..48].copy_from_slice(&sqnum.to_be_bytes());
+    let crc = crc32(&vid[..60]);
+    put32be(vid, 60, crc);
+    peb[DATA_OFFSET..DATA_OFFSET + data.len()].copy_from_slice(data);
+    peb
+}
+
+/// Volume table record of a volume of `reserved` erase blocks.
+fn volume_record(name: &str, reserved: u32, static_volume: bool) -> Vec<u8> {
+    let mut record = vec![0u8; 172];
+    put32be(&mut record, 0, reserved);
+    put32be(&mut record, 4, 1);
+    record[12] = if static_volume { 2 } else { 1 };
+    record[14..16].copy_from_slice(&(name.len() as u16).to_be_bytes());
+    record[16..16 + name.len()].copy_from_slice(name.as_bytes());
+    let crc = crc32(&record[..168]);
+    put32be(&mut record, 168, crc);
+    record
+}
+
+pub fn build(entries: &[Entry]) -> Vec<u8> {
+    let (volume, stale) = ubifs(entries);
+    let kernel: Vec<u8> = (0..5000u32).map(|i| (i % 13) as u8).collect();
+
+    let mut table = Vec::new();
+    table.extend(volume_record("kernel", 1, true));
+    table.extend(volume_record("rootfs", 16, false));
+    for _ in 2..128 {
+        let mut record = vec![0u8; 172];
+        let crc = crc32(&record[..168]);
+        put32be(&mut record, 168, crc);
+        table.extend(record);
+    }
+
+    let mut sqnum = 0;
+    let mut next = || {
+        sqnum += 1;
+        sqnum
+    };
+    let mut pebs = vec![
+        erase_block(LAYOUT_VOLUME, 0, next(), &table, false),
+        erase_block(LAYOUT_VOLUME, 1, next(), &table, false),
+    ];
+    let mut others = vec![
+        erase_block(ROOTFS_VOLUME, 1, next(), &stale, false),
+        erase_block(KERNEL_VOLUME, 0, next(), &kernel, true),
+    ];
+    for (lnum, data) in &volume {
+        others.push(erase_block(ROOTFS_VOLUME, *lnum, next(), data, false));
+    }
+    others.push(vec![0xff; PEB_SIZE]);
+    // Out of order, as wear levelling leaves them.
+    others.reverse();
+    others.rotate_left(3);
+    pebs.append(&mut others);
+    pebs.concat()
+}
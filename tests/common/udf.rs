@@ -0,0 +1,317 @@
+//! Minimal UDF 2.01 writer: 2048-byte sectors, one type 1 partition from sector 260
+//! holding the file set descriptor, then the file entries and their content. Files of up
+//! to 1 KiB are embedded in their file entry, others are mapped by short allocation
+//! descriptors leaving all-zero blocks as holes. Directories use extended file entries;
+//! deleted files keep their file entry behind a file identifier marked deleted.
+use super::{Entry, Node};
+use std::collections::BTreeMap;
+
+const SECTOR: usize = 2048;
+const PARTITION_START: usize = 260;
+const EMBEDDED_MAX: usize = 1024;
+
+fn put16(buffer: &mut [u8], at: usize, value: u16) {
+    buffer[at..at + 2].copy_from_slice(&value.to_le_bytes());
+}
+
+fn put32(buffer: &mut [u8], at: usize, value: u32) {
+    buffer[at..at + 4].copy_from_slice(&value.to_le_bytes());
+}
+
+fn put64(buffer: &mut [u8], at: usize, value: u64) {
+    buffer[at..at + 8].copy_from_slice(&value.to_le_bytes());
+}
+
+/// CRC-ITU-T of the descriptor tags.
+fn crc(bytes: &[u8]) -> u16 {
+    let mut crc = 0u16;
+    for byte in bytes {
+        crc ^= (*byte as u16) << 8;
+        for _ in 0..8 {
+            crc = if crc & 0x8000 != 0 {
+                crc << 1 ^ 0x1021
+            } else {
+                crc << 1
+            };
+        }
+    }
+    crc
+}
+
+/// Fill the tag of the descriptor `bytes` recorded at `location`.
+fn tag(bytes: &mut [u8], id: u16, location: u32) {
+    put16(bytes, 0, id);
+    put16(bytes, 2, 3);
+    put16(bytes, 8, crc(&bytes[16..]));
+    put16(bytes, 10, (bytes.len() - 16) as u16);
+    put32(bytes, 12, location);
+    bytes[4] = 0;
+    bytes[4] = bytes[..16].iter().fold(0u8, |sum, b| sum.wrapping_add(*b));
+}
+
+/// 8-bit compressed Unicode.
+fn chars(name: &str) -> Vec<u8> {
+    let mut out = vec![8];
+    out.extend_from_slice(name.as_bytes());
+    out
+}
+
+fn dstring(field: &mut [u8], value: &str) {
+    let bytes = chars(value);
+    field[..bytes.len()].copy_from_slice(&bytes);
+    *field.last_mut().unwrap() = bytes.len() as u8;
+}
+
+/// 2024-01-02 03:04:05, local time at UTC+0.
+fn timestamp(field: &mut [u8]) {
+    put16(field, 0, 0x1000);
+    put16(field, 2, 2024);
+    field[4..9].copy_from_slice(&[1, 2, 3, 4, 5]);
+}
+
+/// ECMA-167 permissions of a Unix mode, owners may also change attributes and delete.
+fn permissions(mode: u32) -> u32 {
+    let mut out = 0x18 << 10;
+    for (class, shift) in [(0, 0), (1, 3), (2, 6)] {
+        out |= (mode >> shift & 7) << (class * 5);
+    }
+    out
+}
+
+struct Writer {
+    /// Blocks of the partition.
+    blocks: Vec<[u8; SECTOR]>,
+    unique_id: u64,
+}
+
+impl Writer {
+    fn allocate(&mut self) -> u32 {
+        self.blocks.push([0; SECTOR]);
+        (self.blocks.len() - 1) as u32
+    }
+
+    /// Write `content` to new blocks, returning its short allocation descriptors.
+    fn content(&mut self, content: &[u8]) -> Vec<u8> {
+        // Runs of blocks, `None` for holes.
+        let mut runs: Vec<(Option<u32>, usize)> = Vec::new();
+        for chunk in content.chunks(SECTOR) {
+            let block = match chunk.iter().all(|b| *b == 0) {
+                true => None,
+                false => {
+                    let block = self.allocate();
+                    self.blocks[block as usize][..chunk.len()].copy_from_slice(chunk);
+                    Some(block)
+                }
+            };
+            match runs.last_mut() {
+                Some((Some(first), length))
+                    if block == Some(*first + (*length / SECTOR) as u32) =>
+                {
+                    *length += chunk.len()
+                }
+                Some((None, length)) if block.is_none() => *length += chunk.len(),
+                _ => runs.push((block, chunk.len())),
+            }
+        }
+        let mut descriptors = Vec::new();
+        for (block, length) in runs {
+            let (kind, position) = block.map_or((2, 0), |b| (0, b));
+            descriptors.extend_from_slice(&((kind << 30) | length as u32).to_le_bytes());
+            descriptors.extend_from_slice(&position.to_le_bytes());
+        }
+        descriptors
+    }
+
+    /// Fill the file entry at `block`, extended for directories.
+    fn file_entry(&mut self, block: u32, file_type: u8, mode: u32, content: &[u8]) {
+        let embedded = content.len() <= EMBEDDED_MAX;
+        let descriptors = match embedded {
+            true => content.to_vec(),
+            false => self.content(content),
+        };
+        let recorded = match embedded {
+            true => 0,
+            false => content.len().div_ceil(SECTOR) as u64,
+        };
+        let extended = file_type == 4;
+        let start = if extended { 216 } else { 176 };
+        let mut entry = vec![0u8; start + descriptors.len()];
+        put16(&mut entry, 20, 4);
+        put16(&mut entry, 24, 1);
+        entry[27] = file_type;
+        put16(&mut entry, 34, if embedded { 3 } else { 0 });
+        put32(&mut entry, 44, permissions(mode));
+        put16(&mut entry, 48, 1);
+        put64(&mut entry, 56, content.len() as u64);
+        self.unique_id += 1;
+        if extended {
+            put64(&mut entry, 64, content.len() as u64);
+            put64(&mut entry, 72, recorded);
+            for at in [80, 92, 104, 116] {
+                timestamp(&mut entry[at..at + 12]);
+            }
+            put32(&mut entry, 128, 1);
+            put64(&mut entry, 200, self.unique_id);
+            put32(&mut entry, 212, descriptors.len() as u32);
+        } else {
+            put64(&mut entry, 64, recorded);
+            for at in [72, 84, 96] {
+                timestamp(&mut entry[at..at + 12]);
+            }
+            put32(&mut entry, 108, 1);
+            put64(&mut entry, 160, self.unique_id);
+            put32(&mut entry, 172, descriptors.len() as u32);
+        }
+        entry[start..].copy_from_slice(&descriptors);
+        tag(&mut entry, if extended { 266 } else { 261 }, block);
+        self.blocks[block as usize][..entry.len()].copy_from_slice(&entry);
+    }
+
+    /// Write directory `path` to the file entry at `block`, its children first.
+    fn directory(
+        &mut self,
+        path: &str,
+        block: u32,
+        parent: u32,
+        tree: &BTreeMap<String, Vec<&Entry>>,
+    ) {
+        let mut identifiers = vec![(String::new(), parent, 0x0a)];
+        for entry in tree.get(path).into_iter().flatten() {
+            let child = self.allocate();
+            let name = entry.path.rsplit('/').next().unwrap().to_string();
+            let characteristics = match &entry.node {
+                Node::Dir => {
+                    self.directory(entry.path, child, block, tree);
+                    0x02
+                }
+                Node::File(data) => {
+                    self.file_entry(child, 5, 0o644, data);
+                    0
+                }
+                Node::Deleted(data) => {
+                    self.file_entry(child, 5, 0o644, data);
+                    0x04
+                }
+                Node::Sparse { size, offset, data } => {
+                    self.file_entry(
+                        child,
+                        5,
+                        0o644,
+                        &super::sparse_content(*size, *offset, data),
+                    );
+                    0
+                }
+                Node::Symlink(target) => {
+                    // One named path component per part.
+                    let mut components = Vec::new();
+                    for part in target.split('/') {
+                        let name = chars(part);
+                        components.extend_from_slice(&[5, name.len() as u8, 0, 0]);
+                        components.extend_from_slice(&name);
+                    }
+                    self.file_entry(child, 12, 0o777, &components);
+                    0
+                }
+                other => panic!("UDF fixtures cannot hold {:?}", other),
+            };
+            identifiers.push((name, child, characteristics));
+        }
+        let mut data = Vec::new();
+        for (name, location, characteristics) in identifiers {
+            let name = match name.is_empty() {
+                true => Vec::new(),
+                false => chars(&name),
+            };
+            let mut fid = vec![0u8; (38 + name.len()).next_multiple_of(4)];
+            put16(&mut fid, 16, 1);
+            fid[18] = characteristics;
+            fid[19] = name.len() as u8;
+            put32(&mut fid, 20, SECTOR as u32);
+            put32(&mut fid, 24, location);
+            fid[38..38 + name.len()].copy_from_slice(&name);
+            tag(&mut fid, 257, block);
+            data.extend_from_slice(&fid);
+        }
+        self.file_entry(block, 4, 0o755, &data);
+    }
+}
+
+/// Image of the entries.
+pub fn build(entries: &[Entry]) -> Vec<u8> {
+    let mut tree: BTreeMap<String, Vec<&Entry>> = BTreeMap::new();
+    for entry in entries {
+        let parent = entry.path.rsplit_once('/').map_or("", |(parent, _)| parent);
+        tree.entry(parent.to_string()).or_default().push(entry);
+    }
+    let mut writer = Writer {
+        blocks: Vec::new(),
+        unique_id: 15,
+    };
+    let fsd_block = writer.allocate();
+    let root = writer.allocate();
+    writer.directory("", root, root, &tree);
+
+    let mut fsd = vec![0u8; 512];
+    timestamp(&mut fsd[16..28]);
+    put16(&mut fsd, 28, 3);
+    put16(&mut fsd, 30, 3);
+    dstring(&mut fsd[112..240], "FIXTURE");
+    dstring(&mut fsd[304..336], "fixture set");
+    put32(&mut fsd, 400, SECTOR as u32);
+    put32(&mut fsd, 404, root);
+    fsd[417..436].copy_from_slice(b"*OSTA UDF Compliant");
+    tag(&mut fsd, 256, fsd_block);
+    writer.blocks[fsd_block as usize][..512].copy_from_slice(&fsd);
+
+    let partition_length = writer.blocks.len();
+    let mut image = vec![0u8; (PARTITION_START + partition_length) * SECTOR];
+    let sector = |n: usize| n * SECTOR..(n + 1) * SECTOR;
+    for (n, identifier) in [(16, b"BEA01"), (17, b"NSR02"), (18, b"TEA01")] {
+        image[sector(n)][1..6].copy_from_slice(identifier);
+        image[sector(n)][6] = 1;
+    }
+
+    // Main volume descriptor sequence: primary, partition, logical volume, terminator.
+    let mut pvd = vec![0u8; 512];
+    dstring(&mut pvd[24..56], "FIXTURE");
+    dstring(&mut pvd[72..200], "fixture volume set");
+    timestamp(&mut pvd[376..388]);
+    tag(&mut pvd, 1, 32);
+    let mut pd = vec![0u8; 356];
+    put32(&mut pd, 16, 1);
+    put16(&mut pd, 20, 1);
+    pd[25..31].copy_from_slice(b"+NSR02");
+    put32(&mut pd, 184, 1);
+    put32(&mut pd, 188, PARTITION_START as u32);
+    put32(&mut pd, 192, partition_length as u32);
+    tag(&mut pd, 5, 33);
+    let mut lvd = vec![0u8; 446];
+    put32(&mut lvd, 16, 2);
+    dstring(&mut lvd[84..212], "FIXTURE");
+    put32(&mut lvd, 212, SECTOR as u32);
+    lvd[217..236].copy_from_slice(b"*OSTA UDF Compliant");
+    put32(&mut lvd, 248, SECTOR as u32);
+    put32(&mut lvd, 252, fsd_block);
+    put32(&mut lvd, 264, 6);
+    put32(&mut lvd, 268, 1);
+    lvd[440..446].copy_from_slice(&[1, 6, 1, 0, 0, 0]);
+    tag(&mut lvd, 6, 34);
+    let mut td = vec![0u8; 512];
+    tag(&mut td, 8, 35);
+    for (n, descriptor) in [(32, pvd), (33, pd), (34, lvd), (35, td)] {
+        image[sector(n)][..descriptor.len()].copy_from_slice(&descriptor);
+    }
+
+    let mut anchor = vec![0u8; 512];
+    put32(&mut anchor, 16, 4 * SECTOR as u32);
+    put32(&mut anchor, 20, 32);
+    put32(&mut anchor, 24, 4 * SECTOR as u32);
+    put32(&mut anchor, 28, 32);
+    tag(&mut anchor, 2, 256);
+    image[sector(256)][..512].copy_from_slice(&anchor);
+
+    for (n, block) in writer.blocks.iter().enumerate() {
+        image[sector(PARTITION_START + n)].copy_from_slice(block);
+    }
+    image
+}
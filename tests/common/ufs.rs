@@ -0,0 +1,287 @@
+//! Minimal UFS2 writer: 8 KiB blocks of eight 1 KiB fragments, one cylinder group with
+//! its block and 64 inodes after the superblock, then whole blocks of data. Files are
+//! mapped by their direct pointers and a single indirect block, leaving all-zero blocks
+//! as holes; symbolic links are kept in the inode. Deleted files keep their inode (mode
+//! cleared) and their entry, swallowed by the record of the previous one as the kernel
+//! does. `hello.txt` carries a `user.comment` extended attribute.
+use super::{Entry, Node};
+use std::collections::BTreeMap;
+
+const FSIZE: usize = 1024;
+const FRAG: usize = 8;
+const BSIZE: usize = FSIZE * FRAG;
+const SBLOCK: usize = 65536;
+const CBLKNO: usize = 72;
+const IBLKNO: usize = 80;
+const IPG: usize = 64;
+const DBLKNO: usize = IBLKNO + IPG * 256 / FSIZE;
+const NINDIR: usize = BSIZE / 8;
+const ROOT_INO: usize = 2;
+const MAXSYMLINKLEN: usize = 120;
+const DIRBLKSIZ: usize = 512;
+/// 2024-01-02 03:04:05 UTC.
+const TIMESTAMP: i64 = 1_704_164_645;
+
+fn put16(buffer: &mut [u8], at: usize, value: u16) {
+    buffer[at..at + 2].copy_from_slice(&value.to_le_bytes());
+}
+
+fn put32(buffer: &mut [u8], at: usize, value: u32) {
+    buffer[at..at + 4].copy_from_slice(&value.to_le_bytes());
+}
+
+fn put64(buffer: &mut [u8], at: usize, value: u64) {
+    buffer[at..at + 8].copy_from_slice(&value.to_le_bytes());
+}
+
+/// Directory blocks of `entries` (name, inode, type, deleted), `.` and `..` first.
+fn directory_data(entries: &[(String, usize, u8, bool)]) -> Vec<u8> {
+    let mut data = Vec::new();
+    let mut chunk = vec![0u8; DIRBLKSIZ];
+    let (mut used, mut last_live) = (0, 0);
+    for (name, ino, d_type, deleted) in entries {
+        let size = (8 + name.len() + 1).next_multiple_of(4);
+        if used + size > DIRBLKSIZ {
+            put16(&mut chunk, last_live + 4, (DIRBLKSIZ - last_live) as u16);
+            data.append(&mut chunk);
+            chunk = vec![0u8; DIRBLKSIZ];
+            used = 0;
+        }
+        put32(&mut chunk, used, *ino as u32);
+        put16(&mut chunk, used + 4, size as u16);
+        chunk[used + 6] = *d_type;
+        chunk[used + 7] = name.len() as u8;
+        chunk[used + 8..used + 8 + name.len()].copy_from_slice(name.as_bytes());
+        if *deleted {
+            let reclen = u16::from_le_bytes([chunk[last_live + 4], chunk[last_live + 5]]);
+            put16(&mut chunk, last_live + 4, reclen + size as u16);
+        } else {
+            last_live = used;
+        }
+        used += size;
+    }
+    put16(&mut chunk, last_live + 4, (DIRBLKSIZ - last_live) as u16);
+    data.append(&mut chunk);
+    data
+}
+
+/// Extended attribute record: length, namespace, content padding, name length, then the
+/// name and the content, each padded to 8 bytes.
+fn extattr(namespace: u8, name: &str, content: &[u8]) -> Vec<u8> {
+    let base = (7 + name.len()).next_multiple_of(8);
+    let padded = content.len().next_multiple_of(8);
+    let mut record = vec![0u8; base + padded];
+    put32(&mut record, 0, (base + padded) as u32);
+    record[4] = namespace;
+    record[5] = (padded - content.len()) as u8;
+    record[6] = name.len() as u8;
+    record[7..7 + name.len()].copy_from_slice(name.as_bytes());
+    record[base..base + content.len()].copy_from_slice(content);
+    record
+}
+
+struct Writer {
+    /// Fragments from the start of the data area.
+    data: Vec<u8>,
+    inodes: Vec<[u8; 256]>,
+}
+
+impl Writer {
+    /// First fragment of a new block holding `bytes`.
+    fn block(&mut self, bytes: &[u8]) -> u64 {
+        let fragment = DBLKNO + self.data.len() / FSIZE;
+        let start = self.data.len();
+        self.data.resize(start + BSIZE, 0);
+        self.data[start..start + bytes.len()].copy_from_slice(bytes);
+        fragment as u64
+    }
+
+    fn new_inode(&mut self) -> usize {
+        self.inodes.push([0; 256]);
+        self.inodes.len() - 1
+    }
+
+    /// Write the inode `ino` holding `content`.
+    fn inode(&mut self, ino: usize, mode: u16, nlink: u16, content: &[u8], xattrs: &[u8]) {
+        let mut inode = [0u8; 256];
+        let mut fragments = 0;
+        if mode >> 12 == 0o12 && content.len() < MAXSYMLINKLEN {
+            inode[112..112 + content.len()].copy_from_slice(content);
+        } else {
+            let mut indirect = vec![0u8; BSIZE];
+            for (lbn, chunk) in content.chunks(BSIZE).enumerate() {
+                if chunk.iter().all(|b| *b == 0) {
+                    continue;
+                }
+                let fragment = self.block(chunk);
+                fragments += FRAG;
+                match lbn {
+                    0..12 => put64(&mut inode, 112 + 8 * lbn, fragment),
+                    _ => put64(&mut indirect, 8 * (lbn - 12), fragment),
+                }
+            }
+            assert!(content.len() <= (12 + NINDIR) * BSIZE);
+            if indirect.iter().any(|b| *b != 0) {
+                put64(&mut inode, 208, self.block(&indirect));
+                fragments += FRAG;
+            }
+        }
+        if !xattrs.is_empty() {
+            put32(&mut inode, 92, xattrs.len() as u32);
+            put64(&mut inode, 96, self.block(xattrs));
+            fragments += FRAG;
+        }
+        put16(&mut inode, 0, mode);
+        put16(&mut inode, 2, nlink);
+        put32(&mut inode, 12, BSIZE as u32);
+        put64(&mut inode, 16, content.len() as u64);
+        put64(&mut inode, 24, (fragments * FSIZE / 512) as u64);
+        for at in [32, 40, 48, 56] {
+            put64(&mut inode, at, TIMESTAMP as u64);
+        }
+        put32(&mut inode, 80, 1);
+        self.inodes[ino] = inode;
+    }
+
+    /// Write directory `path` as inode `ino`, its children first.
+    fn directory(
+        &mut self,
+        path: &str,
+        ino: usize,
+        parent: usize,
+        tree: &BTreeMap<String, Vec<&Entry>>,
+    ) {
+        let mut entries = vec![
+            (".".to_string(), ino, 4, false),
+            ("..".to_string(), parent, 4, false),
+        ];
+        let mut subdirectories = 0;
+        for entry in tree.get(path).into_iter().flatten() {
+            let child = self.new_inode();
+            let name = entry.path.rsplit('/').next().unwrap().to_string();
+            let (d_type, deleted) = match &entry.node {
+                Node::Dir => {
+                    subdirectories += 1;
+                    self.directory(entry.path, child, ino, tree);
+                    (4, false)
+                }
+                Node::File(data) => {
+                    let xattrs = match entry.path {
+                        "hello.txt" => extattr(1, "comment", b"fixture"),
+                        _ => Vec::new(),
+                    };
+                    self.inode(child, 0o100644, 1, data, &xattrs);
+                    (8, false)
+                }
+                Node::Sparse { size, offset, data } => {
+                    let content = super::sparse_content(*size, *offset, data);
+                    self.inode(child, 0o100644, 1, &content, &[]);
+                    (8, false)
+                }
+                Node::Symlink(target) => {
+                    self.inode(child, 0o120777, 1, target.as_bytes(), &[]);
+                    (10, false)
+                }
+                Node::Deleted(data) => {
+                    self.inode(child, 0, 0, data, &[]);
+                    (8, true)
+                }
+                other => panic!("UFS fixtures cannot hold {:?}", other),
+            };
+            entries.push((name, child, d_type, deleted));
+        }
+        let data = directory_data(&entries);
+        self.inode(ino, 0o40755, 2 + subdirectories, &data, &[]);
+    }
+}
+
+/// Image of the entries.
+pub fn build(entries: &[Entry]) -> Vec<u8> {
+    let mut tree: BTreeMap<String, Vec<&Entry>> = BTreeMap::new();
+    for entry in entries {
+        let parent = entry.path.rsplit_once('/').map_or("", |(parent, _)| parent);
+        tree.entry(parent.to_string()).or_default().push(entry);
+    }
+    let mut writer = Writer {
+        data: Vec::new(),
+        inodes: vec![[0; 256]; ROOT_INO + 1],
+    };
+    writer.directory("", ROOT_INO, ROOT_INO, &tree);
+    assert!(writer.inodes.len() <= IPG);
+
+    let used = DBLKNO + writer.data.len() / FSIZE;
+    let fpg = (used + 8 * FRAG).next_multiple_of(FRAG);
+    let mut image = vec![0u8; fpg * FSIZE];
+    let inodes_at = IBLKNO * FSIZE;
+    for (ino, inode) in writer.inodes.iter().enumerate() {
+        image[inodes_at + ino * 256..inodes_at + (ino + 1) * 256].copy_from_slice(inode);
+    }
+    image[DBLKNO * FSIZE..DBLKNO * FSIZE + writer.data.len()].copy_from_slice(&writer.data);
+
+    // Cylinder group: header, used inode map, free fragment map.
+    let free_fragments = (fpg - used) as u64;
+    let cg = &mut image[CBLKNO * FSIZE..CBLKNO * FSIZE + BSIZE];
+    put32(cg, 4, 0x0009_0255);
+    put32(cg, 20, fpg as u32);
+    put32(cg, 24, 2);
+    put32(cg, 28, (free_fragments / FRAG as u64) as u32);
+    put32(cg, 32, (IPG - writer.inodes.len()) as u32);
+    put32(cg, 92, 168);
+    put32(cg, 96, 168 + IPG as u32 / 8);
+    put32(cg, 100, 168 + IPG as u32 / 8 + fpg as u32 / 8);
+    put32(cg, 116, IPG as u32);
+    put32(cg, 120, IPG as u32);
+    for ino in 0..writer.inodes.len() {
+        cg[168 + ino / 8] |= 1 << (ino % 8);
+    }
+    for fragment in used..fpg {
+        cg[176 + fragment / 8] |= 1 << (fragment % 8);
+    }
+
+    let superblock = &mut image[SBLOCK..SBLOCK + 2048];
+    for (at, value) in [
+        (8, 64),
+        (12, CBLKNO as u32),
+        (16, IBLKNO as u32),
+        (20, DBLKNO as u32),
+        (44, 1),
+        (48, BSIZE as u32),
+        (52, FSIZE as u32),
+        (56, FRAG as u32),
+        (60, 8),
+        (72, !(BSIZE as u32 - 1)),
+        (76, !(FSIZE as u32 - 1)),
+        (80, 13),
+        (84, 10),
+        (96, 3),
+        (100, 1),
+        (104, 2048),
+        (116, NINDIR as u32),
+        (120, (BSIZE / 256) as u32),
+        (156, FSIZE as u32),
+        (160, BSIZE as u32),
+        (184, IPG as u32),
+        (188, fpg as u32),
+        (860, BSIZE as u32),
+        (1320, MAXSYMLINKLEN as u32),
+        (1372, 0x1954_0119),
+    ] {
+        put32(superblock, at, value);
+    }
+    superblock[209] = 1;
+    superblock[212..224].copy_from_slice(b"/mnt/fixture");
+    superblock[680..687].copy_from_slice(b"fixture");
+    for (at, value) in [
+        (992, SBLOCK as u64),
+        (1000, SBLOCK as u64),
+        (1008, 2),
+        (1016, free_fragments / FRAG as u64),
+        (1024, (IPG - writer.inodes.len()) as u64),
+        (1072, TIMESTAMP as u64),
+        (1080, fpg as u64),
+        (1088, (fpg - DBLKNO) as u64),
+    ] {
+        put64(superblock, at, value);
+    }
+    image
+}
@@ -0,0 +1,168 @@
+//! Minimal YAFFS2 NAND dump: pages of 2 KiB chunks and 64 spare bytes holding the packed
+//! tags after the bad block marker, 64 pages a block. The second block, written first,
+//! holds every object with `hello.txt` as an older draft; the first, of a higher sequence
+//! number, its final content and header (with a `user.comment` extended attribute) and
+//! the deletion of the deleted files, moved under the deleted directory as YAFFS2 does.
+//! The last block is erased. Files get a chunk per 2 KiB but for all-zero ones, left as
+//! holes.
+use super::{Entry, Node};
+
+const CHUNK: usize = 2048;
+const SPARE: usize = 64;
+const PAGE: usize = CHUNK + SPARE;
+const PAGES_PER_BLOCK: usize = 64;
+const TAGS_OFFSET: usize = 2;
+const FIRST_OBJECT: u32 = 257;
+const ROOT_ID: u32 = 1;
+const DELETED_ID: u32 = 4;
+const FIRST_SEQUENCE: u32 = 0x1001;
+const DRAFT: &[u8] = b"hello, draft\n";
+/// 2024-01-02 03:04:05 UTC.
+const TIMESTAMP: u32 = 1_704_164_645;
+
+fn put32(buffer: &mut [u8], at: usize, value: u32) {
+    buffer[at..at + 4].copy_from_slice(&value.to_le_bytes());
+}
+
+/// An object header: what the header chunk and its tags say.
+struct Header<'a> {
+    id: u32,
+    object_type: u32,
+    parent: u32,
+    name: &'a str,
+    mode: u32,
+    size: u32,
+    alias: &'a str,
+    xattrs: &'a [(&'a str, &'a [u8])],
+}
+
+/// Pages of a block, in the order they are written.
+struct Block {
+    sequence: u32,
+    pages: Vec<Vec<u8>>,
+}
+
+impl Block {
+    fn page(&mut self, data: &[u8], object: u32, chunk: u32, bytes: u32) {
+        let mut page = vec![0xffu8; PAGE];
+        page[..data.len()].copy_from_slice(data);
+        let tags = &mut page[CHUNK + TAGS_OFFSET..CHUNK + TAGS_OFFSET + 16];
+        put32(tags, 0, self.sequence);
+        put32(tags, 4, object);
+        put32(tags, 8, chunk);
+        put32(tags, 12, bytes);
+        self.pages.push(page);
+        assert!(self.pages.len() <= PAGES_PER_BLOCK);
+    }
+
+    fn header(&mut self, header: &Header) {
+        let mut chunk = vec![0xffu8; CHUNK];
+        chunk[..512].fill(0);
+        put32(&mut chunk, 0, header.object_type);
+        put32(&mut chunk, 4, header.parent);
+        chunk[10..10 + header.name.len()].copy_from_slice(header.name.as_bytes());
+        put32(&mut chunk, 268, header.mode);
+        for at in [280, 284, 288] {
+            put32(&mut chunk, at, TIMESTAMP);
+        }
+        put32(&mut chunk, 292, header.size);
+        chunk[300..300 + header.alias.len()].copy_from_slice(header.alias.as_bytes());
+        put32(&mut chunk, 496, 0xffff_ffff);
+        // Extended attributes: length with itself, name and NUL, then the value.
+        let mut at = 512;
+        for (name, value) in header.xattrs {
+            let size = 4 + name.len() + 1 + value.len();
+            put32(&mut chunk, at, size as u32);
+            chunk[at + 4..at + 4 + name.len()].copy_from_slice(name.as_bytes());
+            chunk[at + 4 + name.len()] = 0;
+            chunk[at + 5 + name.len()..at + size].copy_from_slice(value);
+            at += size;
+        }
+        // Headers keep their type, parent and size in the tags as well.
+        self.page(
+            &chunk,
+            header.id | header.object_type << 28,
+            0x8000_0000 | header.parent,
+            header.size,
+        );
+    }
+
+    /// Chunks of `data`, all-zero ones left out.
+    fn data(&mut self, object: u32, data: &[u8]) {
+        for (n, chunk) in data.chunks(CHUNK).enumerate() {
+            if chunk.iter().any(|b| *b != 0) {
+                let end = chunk.len() - chunk.iter().rev().take_while(|b| **b == 0).count();
+                self.page(&chunk[..end], object, n as u32 + 1, end as u32);
+            }
+        }
+    }
+}
+
+pub fn build(entries: &[Entry]) -> Vec<u8> {
+    let mut older = Block {
+        sequence: FIRST_SEQUENCE,
+        pages: Vec::new(),
+    };
+    let mut newer = Block {
+        sequence: FIRST_SEQUENCE + 1,
+        pages: Vec::new(),
+    };
+    let mut ids: Vec<(&str, u32)> = vec![("", ROOT_ID)];
+    for (n, entry) in entries.iter().enumerate() {
+        let id = FIRST_OBJECT + n as u32;
+        let (parent_path, name) = entry.path.rsplit_once('/').unwrap_or(("", entry.path));
+        let parent = ids.iter().find(|(p, _)| *p == parent_path).unwrap().1;
+        ids.push((entry.path, id));
+        let (object_type, mode, data, alias) = match &entry.node {
+            Node::Dir => (3, 0o040755, Vec::new(), ""),
+            Node::Symlink(target) => (2, 0o120777, Vec::new(), *target),
+            Node::File(data) | Node::Deleted(data) => (1, 0o100644, data.clone(), ""),
+            Node::Sparse { size, offset, data } => {
+                let mut content = vec![0u8; *size as usize];
+                content[*offset as usize..*offset as usize + data.len()].copy_from_slice(data);
+                (1, 0o100644, content, "")
+            }
+            Node::Stream { .. } => unreachable!(),
+        };
+        let mut header = Header {
+            id,
+            object_type,
+            parent,
+            name,
+            mode,
+            size: data.len() as u32,
+            alias,
+            xattrs: &[],
+        };
+        if entry.path == "hello.txt" {
+            older.header(&Header {
+                size: DRAFT.len() as u32,
+                ..header
+            });
+            older.data(id, DRAFT);
+            newer.data(id, &data);
+            header.xattrs = &[("user.comment", b"fixture")];
+            newer.header(&header);
+            continue;
+        }
+        older.header(&header);
+        older.data(id, &data);
+        if let Node::Deleted(_) = entry.node {
+            newer.header(&Header {
+                parent: DELETED_ID,
+                name: "deleted",
+                ..header
+            });
+        }
+    }
+
+    let mut image = Vec::new();
+    for block in [newer, older] {
+        for page in &block.pages {
+            image.extend_from_slice(page);
+        }
+        image.resize(image.len().next_multiple_of(PAGES_PER_BLOCK * PAGE), 0xff);
+    }
+    image.resize(image.len() + PAGES_PER_BLOCK * PAGE, 0xff);
+    image
+}
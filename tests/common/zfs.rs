@@ -0,0 +1,536 @@
+//! Minimal ZFS writer: one 8 MiB disk vdev holding pool `tank`, whose root dataset holds
+//! the entries, with a snapshot of it and a child dataset `tank/data` holding
+//! `other.txt`. Blocks are uncompressed, files of a few bytes are embedded in their block
+//! pointer, objects of several blocks are mapped through one indirect block and all-zero
+//! blocks are left as holes. File attributes are system attributes.
+use super::{Entry, Node};
+
+const IMAGE_SIZE: usize = 8 << 20;
+const LABEL_SIZE: usize = 256 << 10;
+const DATA_START: usize = 4 << 20;
+const TXG: u64 = 10;
+/// 2024-01-02 03:04:06 UTC.
+const TIMESTAMP: u64 = 1_704_164_646;
+const INDIRECT_SHIFT: u8 = 14;
+const ZAP_BLOCK: usize = 4096;
+const DNODE_BLOCK: usize = 16 << 10;
+const OT_OBJECT_DIRECTORY: u8 = 1;
+const OT_DNODE: u8 = 10;
+const OT_OBJSET: u8 = 11;
+const OT_DSL_DIR: u8 = 12;
+const OT_DSL_DIR_CHILD_MAP: u8 = 13;
+const OT_DSL_DS_SNAP_MAP: u8 = 14;
+const OT_DSL_DATASET: u8 = 16;
+const OT_PLAIN_FILE: u8 = 19;
+const OT_DIRECTORY: u8 = 20;
+const OT_MASTER_NODE: u8 = 21;
+const OT_SA: u8 = 44;
+const OT_SA_MASTER_NODE: u8 = 45;
+const OT_SA_ATTR_REGISTRATION: u8 = 46;
+const OT_SA_ATTR_LAYOUTS: u8 = 47;
+const ZAP_MICRO: u64 = (1 << 63) + 3;
+const ZAP_HEADER: u64 = (1 << 63) + 1;
+const ZAP_LEAF: u64 = 1 << 63;
+/// System attributes registered, numbered by position: name, length (0 when variable)
+/// and byte-swap function.
+const SA_REGISTRY: [(&str, u64, u64); 18] = [
+    ("ZPL_ATIME", 16, 0),
+    ("ZPL_MTIME", 16, 0),
+    ("ZPL_CTIME", 16, 0),
+    ("ZPL_CRTIME", 16, 0),
+    ("ZPL_GEN", 8, 0),
+    ("ZPL_MODE", 8, 0),
+    ("ZPL_SIZE", 8, 0),
+    ("ZPL_PARENT", 8, 0),
+    ("ZPL_LINKS", 8, 0),
+    ("ZPL_XATTR", 8, 0),
+    ("ZPL_RDEV", 8, 0),
+    ("ZPL_FLAGS", 8, 0),
+    ("ZPL_UID", 8, 0),
+    ("ZPL_GID", 8, 0),
+    ("ZPL_PAD", 32, 0),
+    ("ZPL_ZNODE_ACL", 88, 0),
+    ("ZPL_DACL_COUNT", 8, 0),
+    ("ZPL_SYMLINK", 0, 1),
+];
+/// Layout 2 (every file), and layout 3 adding the symbolic link target.
+const SA_LAYOUT: [u16; 12] = [5, 6, 4, 12, 13, 7, 11, 0, 1, 2, 3, 8];
+const SA_SYMLINK: u16 = 17;
+
+/// Where the data of an object is: its block pointer, levels and last block id.
+struct Mapping {
+    bp: [u8; 128],
+    levels: u8,
+    max_block_id: u64,
+    used: u64,
+}
+
+struct Writer {
+    image: Vec<u8>,
+    next: usize,
+}
+
+fn put(buffer: &mut [u8], at: usize, value: u64) {
+    buffer[at..at + 8].copy_from_slice(&value.to_le_bytes());
+}
+
+/// Block pointer to `size` bytes at `offset` of the vdev, uncompressed, little-endian.
+fn block_pointer(offset: usize, size: usize, object_type: u8, level: u8) -> [u8; 128] {
+    let mut bp = [0u8; 128];
+    let sectors = (size >> 9) as u64;
+    put(&mut bp, 0, sectors);
+    put(&mut bp, 8, ((offset - DATA_START) >> 9) as u64);
+    let properties = (sectors - 1)
+        | (sectors - 1) << 16
+        | 2 << 32
+        | 7 << 40
+        | (object_type as u64) << 48
+        | (level as u64) << 56
+        | 1 << 63;
+    put(&mut bp, 48, properties);
+    put(&mut bp, 80, TXG);
+    put(&mut bp, 88, 1);
+    bp
+}
+
+/// Block pointer holding `data` (up to 112 bytes) itself.
+fn embedded_block_pointer(data: &[u8], object_type: u8) -> [u8; 128] {
+    let mut payload = data.to_vec();
+    payload.resize(112, 0);
+    let mut bp = [0u8; 128];
+    let words = [0..48, 56..80, 88..128];
+    let mut taken = 0;
+    for range in words {
+        let length = range.len();
+        bp[range].copy_from_slice(&payload[taken..taken + length]);
+        taken += length;
+    }
+    let length = data.len() as u64 - 1;
+    let properties =
+        length | length << 25 | 2 << 32 | 1 << 39 | (object_type as u64) << 48 | 1 << 63;
+    put(&mut bp, 48, properties);
+    put(&mut bp, 80, TXG);
+    bp
+}
+
+fn micro_zap(entries: &[(&str, u64)]) -> Vec<u8> {
+    let mut block = vec![0u8; ZAP_BLOCK];
+    put(&mut block, 0, ZAP_MICRO);
+    for (i, (name, value)) in entries.iter().enumerate() {
+        let at = 64 + 64 * i;
+        put(&mut block, at, *value);
+        block[at + 14..at + 14 + name.len()].copy_from_slice(name.as_bytes());
+    }
+    block
+}
+
+/// Chunks of a 4 KiB fat ZAP leaf, after its header and hash table.
+const CHUNKS_AT: usize = 48 + 2 * (1 << 7);
+
+/// Write `bytes` as a chain of array chunks of a fat ZAP leaf from chunk `next`.
+fn zap_array(leaf: &mut [u8], next: &mut usize, bytes: &[u8]) -> u16 {
+    let first = *next;
+    let pieces: Vec<&[u8]> = bytes.chunks(21).collect();
+    for (i, piece) in pieces.iter().enumerate() {
+        let at = CHUNKS_AT + 24 * *next;
+        leaf[at] = 251;
+        leaf[at + 1..at + 1 + piece.len()].copy_from_slice(piece);
+        let link = if i + 1 == pieces.len() {
+            0xffff
+        } else {
+            *next + 1
+        };
+        leaf[at + 22..at + 24].copy_from_slice(&(link as u16).to_le_bytes());
+        *next += 1;
+    }
+    first as u16
+}
+
+/// Fat ZAP of one leaf whose entries are arrays of 16-bit integers (as SA layouts are).
+fn fat_zap(entries: &[(String, Vec<u16>)]) -> Vec<u8> {
+    let mut blocks = vec![0u8; 2 * ZAP_BLOCK];
+    put(&mut blocks, 0, ZAP_HEADER);
+    put(&mut blocks, 8, 0x0002_f52a_b2ab);
+    let leaf = &mut blocks[ZAP_BLOCK..];
+    put(leaf, 0, ZAP_LEAF);
+    leaf[24..28].copy_from_slice(&0x02ab_1eafu32.to_le_bytes());
+    let mut next = 0;
+    for (name, values) in entries {
+        let entry = CHUNKS_AT + 24 * next;
+        next += 1;
+        let mut name_bytes = name.as_bytes().to_vec();
+        name_bytes.push(0);
+        let name_chunk = zap_array(leaf, &mut next, &name_bytes);
+        let value_bytes: Vec<u8> = values.iter().flat_map(|v| v.to_be_bytes()).collect();
+        let value_chunk = zap_array(leaf, &mut next, &value_bytes);
+        leaf[entry] = 252;
+        leaf[entry + 1] = 2;
+        for (at, value) in [
+            (4, name_chunk),
+            (6, name_bytes.len() as u16),
+            (8, value_chunk),
+            (10, values.len() as u16),
+        ] {
+            leaf[entry + at..entry + at + 2].copy_from_slice(&value.to_le_bytes());
+        }
+    }
+    blocks
+}
+
+/// A dnode of one block pointer, the bonus buffer after it.
+fn dnode(
+    object_type: u8,
+    bonus_type: u8,
+    bonus: &[u8],
+    block_size: usize,
+    data: &Mapping,
+) -> [u8; 512] {
+    let mut dnode = [0u8; 512];
+    dnode[0] = object_type;
+    dnode[1] = INDIRECT_SHIFT;
+    dnode[2] = data.levels;
+    dnode[3] = 1;
+    dnode[4] = bonus_type;
+    dnode[5] = 7;
+    dnode[6] = 2;
+    dnode[7] = 1;
+    dnode[8..10].copy_from_slice(&((block_size >> 9) as u16).to_le_bytes());
+    dnode[10..12].copy_from_slice(&(bonus.len() as u16).to_le_bytes());
+    put(&mut dnode, 16, data.max_block_id);
+    put(&mut dnode, 24, data.used);
+    dnode[64..192].copy_from_slice(&data.bp);
+    dnode[192..192 + bonus.len()].copy_from_slice(bonus);
+    dnode
+}
+
+/// System attributes of a file: layout 2, or 3 with `symlink`.
+fn sa_bonus(mode: u64, size: u64, parent: u64, symlink: Option<&[u8]>) -> Vec<u8> {
+    let mut bonus = vec![0u8; 8];
+    bonus[..4].copy_from_slice(&0x2f_505au32.to_le_bytes());
+    let layout = if symlink.is_some() { 3u16 } else { 2 };
+    bonus[4..6].copy_from_slice(&(layout | 1 << 10).to_le_bytes());
+    if let Some(target) = symlink {
+        bonus[6..8].copy_from_slice(&(target.len() as u16).to_le_bytes());
+    }
+    for number in SA_LAYOUT {
+        let value: &[u64] = match SA_REGISTRY[number as usize].0 {
+            "ZPL_MODE" => &[mode],
+            "ZPL_SIZE" => &[size],
+            "ZPL_GEN" => &[TXG],
+            "ZPL_UID" | "ZPL_GID" => &[1000],
+            "ZPL_PARENT" => &[parent],
+            "ZPL_FLAGS" => &[0x0800_0000_0000],
+            "ZPL_LINKS" => &[if mode >> 12 == 4 { 2 } else { 1 }],
+            _ => &[TIMESTAMP, 0],
+        };
+        bonus.extend(value.iter().flat_map(|v| v.to_le_bytes()));
+    }
+    if let Some(target) = symlink {
+        bonus.extend_from_slice(target);
+        bonus.resize(bonus.len().div_ceil(8) * 8, 0);
+    }
+    bonus
+}
+
+impl Writer {
+    fn block(&mut self, data: &[u8], object_type: u8, level: u8) -> [u8; 128] {
+        let size = data.len().div_ceil(512) * 512;
+        let offset = self.next;
+        self.image[offset..offset + data.len()].copy_from_slice(data);
+        self.next += size;
+        block_pointer(offset, size, object_type, level)
+    }
+
+    /// `data` cut in blocks of `block_size`, through an indirect block when there are
+    /// several.
+    fn object(&mut self, data: &[u8], block_size: usize, object_type: u8) -> Mapping {
+        if !data.is_empty() && data.len() <= 112 && object_type == OT_PLAIN_FILE {
+            return Mapping {
+                bp: embedded_block_pointer(data, object_type),
+                levels: 1,
+                max_block_id: 0,
+                used: 0,
+            };
+        }
+        let mut used = 0;
+        let bps: Vec<[u8; 128]> = data
+            .chunks(block_size)
+            .map(|chunk| {
+                if chunk.iter().all(|&b| b == 0) {
+                    return [0u8; 128];
+                }
+                let mut block = chunk.to_vec();
+                block.resize(block_size, 0);
+                used += block_size as u64;
+                self.block(&block, object_type, 0)
+            })
+            .collect();
+        let max_block_id = bps.len().saturating_sub(1) as u64;
+        match bps.len() {
+            0 => Mapping {
+                bp: [0u8; 128],
+                levels: 1,
+                max_block_id,
+                used,
+            },
+            1 => Mapping {
+                bp: bps[0],
+                levels: 1,
+                max_block_id,
+                used,
+            },
+            _ => {
+                let mut indirect = vec![0u8; 1 << INDIRECT_SHIFT];
+                for (i, bp) in bps.iter().enumerate() {
+                    indirect[128 * i..128 * (i + 1)].copy_from_slice(bp);
+                }
+                Mapping {
+                    bp: self.block(&indirect, object_type, 1),
+                    levels: 2,
+                    max_block_id,
+                    used: used + indirect.len() as u64,
+                }
+            }
+        }
+    }
+
+    /// The dnodes of an object set, as the data of its meta dnode, and the object set.
+    fn objset(&mut self, objects: &[[u8; 512]], os_type: u64) -> [u8; 128] {
+        let mut dnodes: Vec<u8> = objects.concat();
+        dnodes.resize(dnodes.len().div_ceil(DNODE_BLOCK) * DNODE_BLOCK, 0);
+        let mapping = self.object(&dnodes, DNODE_BLOCK, OT_DNODE);
+        let mut objset = vec![0u8; 1024];
+        objset[..512].copy_from_slice(&dnode(OT_DNODE, 0, &[], DNODE_BLOCK, &mapping));
+        put(&mut objset, 704, os_type);
+        self.block(&objset, OT_OBJSET, 0)
+    }
+
+    fn file(&mut self, objects: &mut Vec<[u8; 512]>, parent: u64, node: &Node) -> (u64, u64) {
+        let (mode, content, symlink, kind) = match node {
+            Node::File(data) => (0o100644, data.clone(), None, 8),
+            Node::Sparse { size, offset, data } => {
+                let mut content = vec![0u8; *size as usize];
+                content[*offset as usize..*offset as usize + data.len()].copy_from_slice(data);
+                (0o100644, content, None, 8)
+            }
+            Node::Symlink(target) => (0o120777, Vec::new(), Some(target.as_bytes()), 10),
+            other => panic!("ZFS fixtures cannot hold {:?}", other),
+        };
+        let block_size = match content.len() {
+            0..=112 => 512,
+            113..=65536 => 4096,
+            _ => 128 << 10,
+        };
+        let mapping = self.object(&content, block_size, OT_PLAIN_FILE);
+        let size = symlink.map_or(content.len(), <[u8]>::len) as u64;
+        let bonus = sa_bonus(mode, size, parent, symlink);
+        objects.push(dnode(OT_PLAIN_FILE, OT_SA, &bonus, block_size, &mapping));
+        (objects.len() as u64 - 1, kind)
+    }
+
+    /// Directory `dir` ("" for the root) and everything below it.
+    fn directory(
+        &mut self,
+        objects: &mut Vec<[u8; 512]>,
+        entries: &[Entry],
+        dir: &str,
+        parent: u64,
+    ) -> u64 {
+        let id = objects.len() as u64;
+        objects.push([0u8; 512]);
+        let parent = if dir.is_empty() { id } else { parent };
+        let mut children = Vec::new();
+        for entry in entries {
+            let (entry_dir, name) = entry.path.rsplit_once('/').unwrap_or(("", entry.path));
+            if entry_dir != dir || children.iter().any(|(n, _)| *n == name) {
+                continue;
+            }
+            let (object, kind) = match &entry.node {
+                Node::Dir => (self.directory(objects, entries, entry.path, id), 4),
+                Node::Deleted(_) | Node::Stream { .. } => continue,
+                node => self.file(objects, id, node),
+            };
+            children.push((name, object | kind << 60));
+        }
+        let mapping = self.object(&micro_zap(&children), ZAP_BLOCK, OT_DIRECTORY);
+        let bonus = sa_bonus(0o40755, children.len() as u64, parent, None);
+        objects[id as usize] = dnode(OT_DIRECTORY, OT_SA, &bonus, ZAP_BLOCK, &mapping);
+        id
+    }
+
+    /// ZPL object set: master node, system attribute tables, then the tree.
+    fn filesystem(&mut self, entries: &[Entry]) -> [u8; 128] {
+        let mut objects = vec![[0u8; 512]; 5];
+        let zap = |writer: &mut Self, object_type, entries: &[(&str, u64)]| {
+            let mapping = writer.object(&micro_zap(entries), ZAP_BLOCK, object_type);
+            dnode(object_type, 0, &[], ZAP_BLOCK, &mapping)
+        };
+        objects[2] = zap(self, OT_SA_MASTER_NODE, &[("REGISTRY", 3), ("LAYOUTS", 4)]);
+        let registry: Vec<(&str, u64)> = SA_REGISTRY
+            .iter()
+            .enumerate()
+            .map(|(i, (name, length, bswap))| (*name, i as u64 | bswap << 16 | length << 24))
+            .collect();
+        objects[3] = zap(self, OT_SA_ATTR_REGISTRATION, &registry);
+        let mut symlink_layout = SA_LAYOUT.to_vec();
+        symlink_layout.push(SA_SYMLINK);
+        let layouts = fat_zap(&[
+            ("2".to_string(), SA_LAYOUT.to_vec()),
+            ("3".to_string(), symlink_layout),
+        ]);
+        let mapping = self.object(&layouts, ZAP_BLOCK, OT_SA_ATTR_LAYOUTS);
+        objects[4] = dnode(OT_SA_ATTR_LAYOUTS, 0, &[], ZAP_BLOCK, &mapping);
+        let root = self.directory(&mut objects, entries, "", 0);
+        objects[1] = zap(
+            self,
+            OT_MASTER_NODE,
+            &[("ROOT", root), ("VERSION", 5), ("SA_ATTRS", 2)],
+        );
+        self.objset(&objects, 2)
+    }
+}
+
+fn dsl_dir(head: u64, parent: u64, children: u64) -> [u8; 512] {
+    let mut bonus = [0u8; 256];
+    put(&mut bonus, 0, TIMESTAMP);
+    put(&mut bonus, 8, head);
+    put(&mut bonus, 16, parent);
+    put(&mut bonus, 32, children);
+    let empty = Mapping {
+        bp: [0u8; 128],
+        levels: 1,
+        max_block_id: 0,
+        used: 0,
+    };
+    dnode(OT_DSL_DIR, OT_DSL_DIR, &bonus, 512, &empty)
+}
+
+fn dsl_dataset(dir: u64, snapshots: u64, guid: u64, objset: &[u8; 128]) -> [u8; 512] {
+    let mut bonus = [0u8; 320];
+    put(&mut bonus, 0, dir);
+    put(&mut bonus, 32, snapshots);
+    put(&mut bonus, 48, TIMESTAMP);
+    put(&mut bonus, 56, TXG);
+    put(&mut bonus, 72, 64 << 10);
+    put(&mut bonus, 112, guid);
+    bonus[128..256].copy_from_slice(objset);
+    let empty = Mapping {
+        bp: [0u8; 128],
+        levels: 1,
+        max_block_id: 0,
+        used: 0,
+    };
+    dnode(OT_DSL_DATASET, OT_DSL_DATASET, &bonus, 512, &empty)
+}
+
+/// Pair of an XDR-encoded nvlist: a string or a 64-bit integer, or a nested list.
+enum Nv {
+    Uint(u64),
+    Str(&'static str),
+    List(Vec<(&'static str, Nv)>),
+}
+
+fn xdr_string(out: &mut Vec<u8>, value: &str) {
+    out.extend((value.len() as u32).to_be_bytes());
+    out.extend(value.as_bytes());
+    out.resize(out.len().div_ceil(4) * 4, 0);
+}
+
+fn xdr_list(out: &mut Vec<u8>, pairs: &[(&str, Nv)]) {
+    out.extend([0u8, 0, 0, 0, 0, 0, 0, 1]);
+    for (name, value) in pairs {
+        let mut pair = Vec::new();
+        xdr_string(&mut pair, name);
+        match value {
+            Nv::Uint(v) => {
+                pair.extend([0, 0, 0, 8, 0, 0, 0, 1]);
+                pair.extend(v.to_be_bytes());
+            }
+            Nv::Str(s) => {
+                pair.extend([0, 0, 0, 9, 0, 0, 0, 1]);
+                xdr_string(&mut pair, s);
+            }
+            Nv::List(list) => {
+                pair.extend([0, 0, 0, 19, 0, 0, 0, 1]);
+                xdr_list(&mut pair, list);
+            }
+        }
+        let size = (pair.len() as u32 + 8).to_be_bytes();
+        out.extend(size);
+        out.extend(size);
+        out.extend(pair);
+    }
+    out.extend([0u8; 8]);
+}
+
+/// Pool image holding the entries in its root dataset.
+pub fn build(entries: &[Entry]) -> Vec<u8> {
+    let mut writer = Writer {
+        image: vec![0u8; IMAGE_SIZE],
+        next: DATA_START,
+    };
+    let root_fs = writer.filesystem(entries);
+    let other = [Entry {
+        path: "other.txt",
+        node: Node::File(b"in the second dataset\n".to_vec()),
+    }];
+    let data_fs = writer.filesystem(&other);
+
+    // Meta object set: 1 object directory, 2-3 root dataset, 4 its children, 5 its
+    // snapshots, 6 the snapshot, 7-8 tank/data.
+    let mut mos = vec![[0u8; 512]; 9];
+    let zap = |writer: &mut Writer, object_type, entries: &[(&str, u64)]| {
+        let mapping = writer.object(&micro_zap(entries), ZAP_BLOCK, object_type);
+        dnode(object_type, 0, &[], ZAP_BLOCK, &mapping)
+    };
+    mos[1] = zap(&mut writer, OT_OBJECT_DIRECTORY, &[("root_dataset", 2)]);
+    mos[2] = dsl_dir(3, 0, 4);
+    mos[3] = dsl_dataset(2, 5, 0x1111, &root_fs);
+    mos[4] = zap(
+        &mut writer,
+        OT_DSL_DIR_CHILD_MAP,
+        &[("$MOS", 0), ("data", 7)],
+    );
+    mos[5] = zap(&mut writer, OT_DSL_DS_SNAP_MAP, &[("snap1", 6)]);
+    mos[6] = dsl_dataset(2, 0, 0x2222, &root_fs);
+    mos[7] = dsl_dir(8, 2, 0);
+    mos[8] = dsl_dataset(7, 0, 0x3333, &data_fs);
+    let mos = writer.objset(&mos, 1);
+
+    let mut config = Vec::from([1u8, 1, 0, 0]);
+    xdr_list(
+        &mut config,
+        &[
+            ("version", Nv::Uint(5000)),
+            ("name", Nv::Str("tank")),
+            ("pool_guid", Nv::Uint(0xabcd)),
+            ("txg", Nv::Uint(TXG)),
+            (
+                "vdev_tree",
+                Nv::List(vec![("type", Nv::Str("disk")), ("ashift", Nv::Uint(9))]),
+            ),
+        ],
+    );
+    let mut label = vec![0u8; LABEL_SIZE];
+    label[16 << 10..(16 << 10) + config.len()].copy_from_slice(&config);
+    // A stale uberblock before the current one, which must win on its higher txg.
+    for (slot, txg) in [(0, TXG - 1), (1, TXG)] {
+        let at = (128 << 10) + slot * 1024;
+        put(&mut label, at, 0x00ba_b10c);
+        put(&mut label, at + 8, 5000);
+        put(&mut label, at + 16, txg);
+        put(&mut label, at + 32, TIMESTAMP);
+        if txg == TXG {
+            label[at + 40..at + 168].copy_from_slice(&mos);
+        }
+    }
+    for start in [
+        0,
+        LABEL_SIZE,
+        IMAGE_SIZE - 2 * LABEL_SIZE,
+        IMAGE_SIZE - LABEL_SIZE,
+    ] {
+        writer.image[start..start + LABEL_SIZE].copy_from_slice(&label);
+    }
+    writer.image
+}